@@ -0,0 +1,221 @@
+//! This crate's channels against `std::sync::mpsc`, with one consumer and a varying number of
+//! producers.
+//!
+//! Criterion measures throughput. A second pass then stamps every message as it's sent and
+//! prints percentiles of how long each one took to come out the other end.
+//!
+//! Run with `cargo bench --bench channels`, or `cargo bench --bench channels -- bounded` for a
+//! subset; the filter applies to the latency table too.
+
+use atomics::{affinity, mpmc, mpsc};
+use criterion::{BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use std::sync::{mpsc as std_mpsc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Unbounded channels ignore it.
+const CAPACITIES: [usize; 2] = [16, 1024];
+const LATENCY_MESSAGES_PER_PRODUCER: usize = 50_000;
+
+trait Channel {
+    const NAME: &'static str;
+    type Sender<T: Send>: Clone + Send;
+    type Receiver<T: Send>: Send;
+    fn channel<T: Send>(capacity: usize) -> (Self::Sender<T>, Self::Receiver<T>);
+    fn send<T: Send>(tx: &Self::Sender<T>, value: T);
+    fn recv<T: Send>(rx: &Self::Receiver<T>) -> T;
+}
+
+struct Bounded;
+
+impl Channel for Bounded {
+    const NAME: &'static str = "atomics";
+    type Sender<T: Send> = mpmc::Sender<T>;
+    type Receiver<T: Send> = mpmc::Receiver<T>;
+    fn channel<T: Send>(capacity: usize) -> (Self::Sender<T>, Self::Receiver<T>) {
+        mpmc::bounded(capacity)
+    }
+    fn send<T: Send>(tx: &Self::Sender<T>, value: T) {
+        tx.send(value).ok().unwrap()
+    }
+    fn recv<T: Send>(rx: &Self::Receiver<T>) -> T {
+        rx.recv().unwrap()
+    }
+}
+
+struct StdBounded;
+
+impl Channel for StdBounded {
+    const NAME: &'static str = "std";
+    type Sender<T: Send> = std_mpsc::SyncSender<T>;
+    type Receiver<T: Send> = std_mpsc::Receiver<T>;
+    fn channel<T: Send>(capacity: usize) -> (Self::Sender<T>, Self::Receiver<T>) {
+        std_mpsc::sync_channel(capacity)
+    }
+    fn send<T: Send>(tx: &Self::Sender<T>, value: T) {
+        tx.send(value).ok().unwrap()
+    }
+    fn recv<T: Send>(rx: &Self::Receiver<T>) -> T {
+        rx.recv().unwrap()
+    }
+}
+
+struct Unbounded;
+
+impl Channel for Unbounded {
+    const NAME: &'static str = "atomics";
+    type Sender<T: Send> = mpsc::Sender<T>;
+    type Receiver<T: Send> = mpsc::Receiver<T>;
+    fn channel<T: Send>(_: usize) -> (Self::Sender<T>, Self::Receiver<T>) {
+        mpsc::channel()
+    }
+    fn send<T: Send>(tx: &Self::Sender<T>, value: T) {
+        tx.send(value).ok().unwrap()
+    }
+    fn recv<T: Send>(rx: &Self::Receiver<T>) -> T {
+        rx.recv().unwrap()
+    }
+}
+
+struct StdUnbounded;
+
+impl Channel for StdUnbounded {
+    const NAME: &'static str = "std";
+    type Sender<T: Send> = std_mpsc::Sender<T>;
+    type Receiver<T: Send> = std_mpsc::Receiver<T>;
+    fn channel<T: Send>(_: usize) -> (Self::Sender<T>, Self::Receiver<T>) {
+        std_mpsc::channel()
+    }
+    fn send<T: Send>(tx: &Self::Sender<T>, value: T) {
+        tx.send(value).ok().unwrap()
+    }
+    fn recv<T: Send>(rx: &Self::Receiver<T>) -> T {
+        rx.recv().unwrap()
+    }
+}
+
+fn producer_counts() -> Vec<usize> {
+    let max = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+    std::iter::successors(Some(1), |p| Some(p * 2))
+        .take_while(|&p| p <= max)
+        .collect()
+}
+
+// Has each of `producers` pinned threads send `messages` values made by `make`, and hands
+// each one received to `got` on this thread. Returns how long that took from the moment the
+// producers were all ready.
+fn run<C: Channel, T: Send>(
+    producers: usize,
+    capacity: usize,
+    messages: usize,
+    make: impl Fn() -> T + Sync,
+    mut got: impl FnMut(T),
+) -> Duration {
+    let cores = affinity::cores();
+    let (tx, rx) = C::channel(capacity);
+    let ready = Barrier::new(producers + 1);
+    let _ = affinity::pin_current(cores[0]);
+    thread::scope(|s| {
+        for p in 0..producers {
+            let core = cores[(p + 1) % cores.len()];
+            let (tx, make, ready) = (tx.clone(), &make, &ready);
+            s.spawn(move || {
+                let _ = affinity::pin_current(core);
+                ready.wait();
+                for _ in 0..messages {
+                    C::send(&tx, make());
+                }
+            });
+        }
+        ready.wait();
+        let start = Instant::now();
+        for _ in 0..producers * messages {
+            got(C::recv(&rx));
+        }
+        start.elapsed()
+    })
+}
+
+fn throughput<C: Channel>(c: &mut Criterion, group: &str, capacities: &[usize]) {
+    for &producers in &producer_counts() {
+        let mut g = c.benchmark_group(format!("{}/{}p", group, producers));
+        g.throughput(Throughput::Elements(producers as u64));
+        for &capacity in capacities {
+            g.bench_with_input(BenchmarkId::new(C::NAME, capacity), &capacity, |b, &cap| {
+                b.iter_custom(|iters| {
+                    run::<C, u64>(
+                        producers,
+                        cap,
+                        iters as usize,
+                        || 1,
+                        |v| {
+                            black_box(v);
+                        },
+                    )
+                })
+            });
+        }
+        g.finish();
+    }
+}
+
+fn latency_table<C: Channel>(group: &str, capacities: &[usize], filter: Option<&str>) {
+    for &producers in &producer_counts() {
+        for &capacity in capacities {
+            let id = format!("{}/{}p/{}/{}", group, producers, C::NAME, capacity);
+            if filter.is_some_and(|f| !id.contains(f)) {
+                continue;
+            }
+            let mut l = Vec::with_capacity(producers * LATENCY_MESSAGES_PER_PRODUCER);
+            run::<C, Instant>(
+                producers,
+                capacity,
+                LATENCY_MESSAGES_PER_PRODUCER,
+                Instant::now,
+                |sent| l.push(sent.elapsed().as_nanos() as u64),
+            );
+            l.sort_unstable();
+            let percentile = |p: f64| l[((l.len() - 1) as f64 * p) as usize];
+            println!(
+                "{:<28} {:>8} {:>8} {:>8} {:>8} {:>10}",
+                id,
+                percentile(0.5),
+                percentile(0.9),
+                percentile(0.99),
+                percentile(0.999),
+                l[l.len() - 1]
+            );
+        }
+    }
+}
+
+fn main() {
+    let mut c = Criterion::default().configure_from_args();
+    throughput::<Bounded>(&mut c, "bounded", &CAPACITIES);
+    throughput::<StdBounded>(&mut c, "bounded", &CAPACITIES);
+    throughput::<Unbounded>(&mut c, "unbounded", &[0]);
+    throughput::<StdUnbounded>(&mut c, "unbounded", &[0]);
+    c.final_summary();
+
+    // `cargo test --benches` runs each benchmark once to see that it works; that's enough.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--test" || a == "--list") {
+        return;
+    }
+    let filter = args
+        .iter()
+        .find(|a| !a.starts_with('-'))
+        .map(String::as_str);
+    println!();
+    println!(
+        "{:<28} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        "latency (ns)", "p50", "p90", "p99", "p99.9", "max"
+    );
+    latency_table::<Bounded>("bounded", &CAPACITIES, filter);
+    latency_table::<StdBounded>("bounded", &CAPACITIES, filter);
+    latency_table::<Unbounded>("unbounded", &[0], filter);
+    latency_table::<StdUnbounded>("unbounded", &[0], filter);
+}
@@ -0,0 +1,73 @@
+//! False sharing: each thread increments a counter of its own, once with the counters packed
+//! next to each other and once with each in a [`CachePadded`].
+//!
+//! Nothing is shared in either case as far as the program can tell, but packed counters sit
+//! on the same cache line, so every increment takes the line away from every other thread.
+//! The slowdown column is how much that costs here. With one thread, or one core, it should
+//! be about 1; if it stays about 1 with several threads on several cores, `CachePadded` is
+//! no longer buying anything.
+//!
+//! Run with `cargo bench --bench false_sharing`.
+
+use atomics::affinity;
+use atomics::cache_padded::{CachePadded, CACHE_LINE};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OPS_PER_THREAD: usize = 5_000_000;
+
+fn run<C: Sync>(counters: &[C], counter: fn(&C) -> &AtomicU64, threads: usize) -> Duration {
+    let cores = affinity::cores();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let core = cores[t % cores.len()];
+            let c = counter(&counters[t]);
+            s.spawn(move || {
+                let _ = affinity::pin_current(core);
+                for _ in 0..OPS_PER_THREAD {
+                    c.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let max = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+    let packed: Vec<AtomicU64> = (0..max).map(|_| AtomicU64::new(0)).collect();
+    let padded: Vec<CachePadded<AtomicU64>> = (0..max).map(|_| Default::default()).collect();
+    // What the timings rely on: without padding the counters share lines, with it no two do.
+    assert!(CACHE_LINE / std::mem::size_of::<AtomicU64>() >= 2);
+    for pair in padded.windows(2) {
+        let (a, b) = (
+            &*pair[0] as *const _ as usize,
+            &*pair[1] as *const _ as usize,
+        );
+        assert_eq!(a % CACHE_LINE, 0);
+        assert!(b - a >= CACHE_LINE);
+    }
+
+    println!(
+        "{:>8} {:>12} {:>12} {:>9}",
+        "threads", "packed", "padded", "slowdown"
+    );
+    let mut threads = 1;
+    while threads <= max {
+        let p = run(&packed, |c| c, threads);
+        let c = run(&padded, |c| c, threads);
+        let per_op = |d: Duration| d.as_nanos() as f64 / (threads * OPS_PER_THREAD) as f64;
+        println!(
+            "{:>8} {:>9.2} ns {:>9.2} ns {:>8.1}x",
+            threads,
+            per_op(p),
+            per_op(c),
+            p.as_secs_f64() / c.as_secs_f64()
+        );
+        threads *= 2;
+    }
+}
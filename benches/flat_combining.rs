@@ -0,0 +1,64 @@
+//! Flat combining against a plain mutex, both guarding a `VecDeque` used as a queue.
+//!
+//! Run with `cargo bench --bench flat_combining`.
+
+use atomics::affinity;
+use atomics::flat_combining::FcLock;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OPS_PER_THREAD: usize = 200_000;
+
+fn run(threads: usize, op: impl Fn(usize) + Sync) -> Duration {
+    let cores = affinity::cores();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let core = cores[t % cores.len()];
+            let op = &op;
+            s.spawn(move || {
+                // One thread per core where possible, so the numbers don't depend on where
+                // the scheduler happens to put them.
+                let _ = affinity::pin_current(core);
+                for i in 0..OPS_PER_THREAD {
+                    op(i);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let max = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+    println!("{:>8} {:>12} {:>12}", "threads", "mutex", "fc");
+    let mut threads = 1;
+    while threads <= max {
+        let mutex = Mutex::new(VecDeque::new());
+        let m = run(threads, |i| {
+            let mut q = mutex.lock().unwrap();
+            if i % 2 == 0 {
+                q.push_back(i);
+            } else {
+                q.pop_front();
+            }
+        });
+        let fc = FcLock::new(VecDeque::new());
+        let f = run(threads, |i| {
+            fc.with_lock(|q| {
+                if i % 2 == 0 {
+                    q.push_back(i);
+                } else {
+                    q.pop_front();
+                }
+            })
+        });
+        let per_op = |d: Duration| d.as_nanos() as f64 / (threads * OPS_PER_THREAD) as f64;
+        println!("{:>8} {:>9.1} ns {:>9.1} ns", threads, per_op(m), per_op(f));
+        threads *= 2;
+    }
+}
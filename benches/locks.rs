@@ -0,0 +1,337 @@
+//! This crate's locks against `std::sync` and `parking_lot`, across thread counts and
+//! critical-section lengths.
+//!
+//! Criterion measures throughput. A second pass then times every acquisition and prints
+//! latency percentiles, because a lock that's quick on average can still leave one thread
+//! waiting far longer than the rest, and the mean hides that. Last, on Linux with a PMU to
+//! count with, a third pass counts cycles, instructions, cache misses and branch misses per
+//! operation, which is where two locks of the same throughput differ.
+//!
+//! Run with `cargo bench --bench locks`, or `cargo bench --bench locks -- rwlock` for a
+//! subset; the filter applies to the latency table too.
+
+use atomics::affinity;
+use atomics::flat_combining::FcLock;
+use atomics::perf::Counters;
+use atomics::raw_mutex::RawMutex;
+use criterion::{BenchmarkId, Criterion, Throughput};
+use std::cell::UnsafeCell;
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How many increments each critical section does.
+const CRITICAL_SECTIONS: [u32; 3] = [1, 10, 100];
+// One read-write lock acquisition in this many is a write.
+const WRITE_EVERY: usize = 10;
+const LATENCY_OPS_PER_THREAD: usize = 50_000;
+const COUNTED_OPS_PER_THREAD: usize = 200_000;
+
+trait Lock: Sync {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn write(&self, cs: u32);
+    // Mutexes have no shared mode, so by default a read is a write.
+    fn read(&self, cs: u32) {
+        self.write(cs)
+    }
+}
+
+fn work(v: &mut u64, cs: u32) {
+    for _ in 0..cs {
+        *v = black_box(*v).wrapping_add(1);
+    }
+}
+
+fn look(v: &u64, cs: u32) {
+    for _ in 0..cs {
+        black_box(*v);
+    }
+}
+
+impl Lock for std::sync::Mutex<u64> {
+    const NAME: &'static str = "std";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        work(&mut self.lock().unwrap(), cs)
+    }
+}
+
+impl Lock for parking_lot::Mutex<u64> {
+    const NAME: &'static str = "parking_lot";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        work(&mut self.lock(), cs)
+    }
+}
+
+// RawMutex has no data of its own.
+struct Raw {
+    lock: RawMutex,
+    v: UnsafeCell<u64>,
+}
+
+unsafe impl Sync for Raw {}
+
+impl Lock for Raw {
+    const NAME: &'static str = "raw";
+    fn new() -> Self {
+        Self {
+            lock: RawMutex::new(),
+            v: UnsafeCell::new(0),
+        }
+    }
+    fn write(&self, cs: u32) {
+        self.lock.lock();
+        // Safety: we hold the lock, and unlock it on this thread.
+        unsafe {
+            work(&mut *self.v.get(), cs);
+            self.lock.unlock();
+        }
+    }
+}
+
+impl Lock for FcLock<u64> {
+    const NAME: &'static str = "fc";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        self.with_lock(|v| work(v, cs))
+    }
+}
+
+impl Lock for std::sync::RwLock<u64> {
+    const NAME: &'static str = "std";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        work(&mut self.write().unwrap(), cs)
+    }
+    fn read(&self, cs: u32) {
+        look(&self.read().unwrap(), cs)
+    }
+}
+
+impl Lock for parking_lot::RwLock<u64> {
+    const NAME: &'static str = "parking_lot";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        work(&mut self.write(), cs)
+    }
+    fn read(&self, cs: u32) {
+        look(&self.read(), cs)
+    }
+}
+
+impl Lock for atomics::rwlock::RwLock<u64> {
+    const NAME: &'static str = "atomics";
+    fn new() -> Self {
+        Self::new(0)
+    }
+    fn write(&self, cs: u32) {
+        work(&mut self.write(), cs)
+    }
+    fn read(&self, cs: u32) {
+        look(&self.read(), cs)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Mix {
+    Exclusive,
+    ReadMostly,
+}
+
+impl Mix {
+    fn op<L: Lock>(self, lock: &L, i: usize, cs: u32) {
+        match self {
+            Mix::ReadMostly if !i.is_multiple_of(WRITE_EVERY) => lock.read(cs),
+            _ => lock.write(cs),
+        }
+    }
+}
+
+fn thread_counts() -> Vec<usize> {
+    let max = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+    std::iter::successors(Some(1), |t| Some(t * 2))
+        .take_while(|&t| t <= max)
+        .collect()
+}
+
+// Runs `op` `ops` times on each of `threads` pinned threads and returns how long they took
+// from the moment they were all ready.
+fn run(threads: usize, ops: usize, op: impl Fn(usize) + Sync) -> Duration {
+    let cores = affinity::cores();
+    let ready = Barrier::new(threads + 1);
+    thread::scope(|s| {
+        for t in 0..threads {
+            let core = cores[t % cores.len()];
+            let (op, ready) = (&op, &ready);
+            s.spawn(move || {
+                let _ = affinity::pin_current(core);
+                ready.wait();
+                for i in 0..ops {
+                    op(i);
+                }
+            });
+        }
+        ready.wait();
+        let start = Instant::now();
+        // Leaving the scope joins the threads.
+        start
+    })
+    .elapsed()
+}
+
+fn throughput<L: Lock>(c: &mut Criterion, group: &str, mix: Mix) {
+    for &threads in &thread_counts() {
+        let mut g = c.benchmark_group(format!("{}/{}t", group, threads));
+        g.throughput(Throughput::Elements(threads as u64));
+        for &cs in &CRITICAL_SECTIONS {
+            g.bench_with_input(BenchmarkId::new(L::NAME, cs), &cs, |b, &cs| {
+                b.iter_custom(|iters| {
+                    let lock = L::new();
+                    run(threads, iters as usize, |i| mix.op(&lock, i, cs))
+                })
+            });
+        }
+        g.finish();
+    }
+}
+
+// Acquisition-to-release times of every operation, in nanoseconds, sorted. The timer's own
+// cost is in there too, so only the differences between rows mean much.
+fn latencies<L: Lock>(threads: usize, mix: Mix, cs: u32) -> Vec<u64> {
+    let lock = L::new();
+    let cores = affinity::cores();
+    let mut all: Vec<u64> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let (core, lock) = (cores[t % cores.len()], &lock);
+                s.spawn(move || {
+                    let _ = affinity::pin_current(core);
+                    (0..LATENCY_OPS_PER_THREAD)
+                        .map(|i| {
+                            let start = Instant::now();
+                            mix.op(lock, i, cs);
+                            start.elapsed().as_nanos() as u64
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    all.sort_unstable();
+    all
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
+
+fn latency_table<L: Lock>(group: &str, mix: Mix, filter: Option<&str>) {
+    for &threads in &thread_counts() {
+        for &cs in &CRITICAL_SECTIONS {
+            let id = format!("{}/{}t/{}/{}", group, threads, L::NAME, cs);
+            if filter.is_some_and(|f| !id.contains(f)) {
+                continue;
+            }
+            let l = latencies::<L>(threads, mix, cs);
+            println!(
+                "{:<28} {:>8} {:>8} {:>8} {:>8} {:>10}",
+                id,
+                percentile(&l, 0.5),
+                percentile(&l, 0.9),
+                percentile(&l, 0.99),
+                percentile(&l, 0.999),
+                l[l.len() - 1]
+            );
+        }
+    }
+}
+
+fn counts_table<L: Lock>(group: &str, mix: Mix, filter: Option<&str>, counters: &Counters) {
+    for &threads in &thread_counts() {
+        for &cs in &CRITICAL_SECTIONS {
+            let id = format!("{}/{}t/{}/{}", group, threads, L::NAME, cs);
+            if filter.is_some_and(|f| !id.contains(f)) {
+                continue;
+            }
+            let lock = L::new();
+            let (_, counts) =
+                counters.measure(|| run(threads, COUNTED_OPS_PER_THREAD, |i| mix.op(&lock, i, cs)));
+            let ops = (threads * COUNTED_OPS_PER_THREAD) as f64;
+            print!("{:<28}", id);
+            for (_, n) in counts {
+                print!(" {:>12.2}", n as f64 / ops);
+            }
+            println!();
+        }
+    }
+}
+
+fn main() {
+    let mut c = Criterion::default().configure_from_args();
+    throughput::<std::sync::Mutex<u64>>(&mut c, "mutex", Mix::Exclusive);
+    throughput::<parking_lot::Mutex<u64>>(&mut c, "mutex", Mix::Exclusive);
+    throughput::<Raw>(&mut c, "mutex", Mix::Exclusive);
+    throughput::<FcLock<u64>>(&mut c, "mutex", Mix::Exclusive);
+    throughput::<std::sync::RwLock<u64>>(&mut c, "rwlock", Mix::ReadMostly);
+    throughput::<parking_lot::RwLock<u64>>(&mut c, "rwlock", Mix::ReadMostly);
+    throughput::<atomics::rwlock::RwLock<u64>>(&mut c, "rwlock", Mix::ReadMostly);
+    c.final_summary();
+
+    // `cargo test --benches` runs each benchmark once to see that it works; that's enough.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--test" || a == "--list") {
+        return;
+    }
+    let filter = args
+        .iter()
+        .find(|a| !a.starts_with('-'))
+        .map(String::as_str);
+    println!();
+    println!(
+        "{:<28} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        "latency (ns)", "p50", "p90", "p99", "p99.9", "max"
+    );
+    latency_table::<std::sync::Mutex<u64>>("mutex", Mix::Exclusive, filter);
+    latency_table::<parking_lot::Mutex<u64>>("mutex", Mix::Exclusive, filter);
+    latency_table::<Raw>("mutex", Mix::Exclusive, filter);
+    latency_table::<FcLock<u64>>("mutex", Mix::Exclusive, filter);
+    latency_table::<std::sync::RwLock<u64>>("rwlock", Mix::ReadMostly, filter);
+    latency_table::<parking_lot::RwLock<u64>>("rwlock", Mix::ReadMostly, filter);
+    latency_table::<atomics::rwlock::RwLock<u64>>("rwlock", Mix::ReadMostly, filter);
+
+    println!();
+    let counters = match Counters::standard() {
+        Ok(c) => c,
+        Err(e) => return println!("no hardware counters to count with: {}", e),
+    };
+    println!(
+        "{:<28} {:>12} {:>12} {:>12} {:>12}",
+        "per op", "cycles", "instructions", "cache misses", "branch misses"
+    );
+    counts_table::<std::sync::Mutex<u64>>("mutex", Mix::Exclusive, filter, &counters);
+    counts_table::<parking_lot::Mutex<u64>>("mutex", Mix::Exclusive, filter, &counters);
+    counts_table::<Raw>("mutex", Mix::Exclusive, filter, &counters);
+    counts_table::<FcLock<u64>>("mutex", Mix::Exclusive, filter, &counters);
+    counts_table::<std::sync::RwLock<u64>>("rwlock", Mix::ReadMostly, filter, &counters);
+    counts_table::<parking_lot::RwLock<u64>>("rwlock", Mix::ReadMostly, filter, &counters);
+    counts_table::<atomics::rwlock::RwLock<u64>>("rwlock", Mix::ReadMostly, filter, &counters);
+}
@@ -0,0 +1,60 @@
+//! A bag laid out per NUMA node against one with the same number of shards laid out flat.
+//!
+//! Half the threads only add and half only take, so nearly every take steals. The flat bag
+//! steals from whichever shard comes next; the per-node bag looks on its own node first, so
+//! on a machine with more than one node less of the stealing crosses sockets. On one node the
+//! two should come out the same.
+//!
+//! Run with `cargo bench --bench numa`.
+
+use atomics::affinity;
+use atomics::bag::Bag;
+use atomics::topology::Topology;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OPS_PER_THREAD: usize = 200_000;
+const SHARDS_PER_NODE: usize = 4;
+
+fn run(bag: &Bag<usize>, cores: &[usize]) -> Duration {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for (t, &core) in cores.iter().enumerate() {
+            s.spawn(move || {
+                let _ = affinity::pin_current(core);
+                for i in 0..OPS_PER_THREAD {
+                    if t % 2 == 0 {
+                        bag.add(i);
+                    } else {
+                        bag.take();
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let topology = Topology::detect();
+    let allowed = affinity::cores();
+    // Threads in node order, so each adder's neighbouring taker is usually on its node.
+    let mut cores: Vec<usize> = topology
+        .nodes()
+        .iter()
+        .flat_map(|n| n.cores())
+        .filter(|c| allowed.contains(c))
+        .copied()
+        .collect();
+    if cores.len() < 2 {
+        cores = vec![allowed[0]; 2];
+    }
+    println!("{} nodes, {} threads", topology.nodes().len(), cores.len());
+
+    let shards = topology.nodes().len() * SHARDS_PER_NODE;
+    let flat = run(&Bag::with_shards(shards), &cores);
+    let numa = run(&Bag::with_topology(&topology, SHARDS_PER_NODE), &cores);
+    let per_op = |d: Duration| d.as_nanos() as f64 / (cores.len() * OPS_PER_THREAD) as f64;
+    println!("{:>12} {:>12}", "flat", "per node");
+    println!("{:>9.1} ns {:>9.1} ns", per_op(flat), per_op(numa));
+}
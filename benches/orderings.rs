@@ -0,0 +1,109 @@
+//! What each memory ordering costs on this machine, uncontended, one thread.
+//!
+//! The numbers are per operation and include the loop around it; the `baseline` row is that
+//! loop on its own. On x86 expect every load and every read-modify-write to cost the same
+//! whatever the ordering, because the hardware gives acquire and release for free and a
+//! locked instruction is already sequentially consistent; only the SeqCst store and the
+//! SeqCst fence stand out. On ARM and POWER the orderings compile to different instructions
+//! and the columns come apart.
+//!
+//! Run with `cargo bench --bench orderings`.
+
+use std::hint::black_box;
+use std::sync::atomic::{fence, AtomicU64, Ordering::*};
+use std::time::Instant;
+
+const OPS: u64 = 20_000_000;
+// Each cell is the best of this many runs, which is the one least disturbed by everything
+// else the machine was doing.
+const RUNS: usize = 5;
+
+fn time<R>(op: impl Fn(&AtomicU64, u64) -> R) -> f64 {
+    (0..RUNS)
+        .map(|_| {
+            let a = AtomicU64::new(0);
+            // Hidden from the optimizer, which would otherwise see that nothing else can reach
+            // it and treat it as an ordinary local.
+            let a = black_box(&a);
+            let start = Instant::now();
+            for i in 0..OPS {
+                black_box(op(a, black_box(i)));
+            }
+            start.elapsed().as_nanos() as f64 / OPS as f64
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+fn row(op: &str, cells: [Option<f64>; 3]) {
+    print!("{:<18}", op);
+    for cell in cells {
+        match cell {
+            Some(ns) => print!(" {:>9.2} ns", ns),
+            None => print!(" {:>12}", "-"),
+        }
+    }
+    println!();
+}
+
+fn main() {
+    println!(
+        "{} {}, ns per operation",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    println!(
+        "{:<18} {:>12} {:>12} {:>12}",
+        "", "Relaxed", "Acq/Rel", "SeqCst"
+    );
+    row("baseline", [Some(time(|_, i| i)), None, None]);
+    row(
+        "load",
+        [
+            Some(time(|a, _| a.load(Relaxed))),
+            Some(time(|a, _| a.load(Acquire))),
+            Some(time(|a, _| a.load(SeqCst))),
+        ],
+    );
+    row(
+        "store",
+        [
+            Some(time(|a, i| a.store(i, Relaxed))),
+            Some(time(|a, i| a.store(i, Release))),
+            Some(time(|a, i| a.store(i, SeqCst))),
+        ],
+    );
+    row(
+        "swap",
+        [
+            Some(time(|a, i| a.swap(i, Relaxed))),
+            Some(time(|a, i| a.swap(i, AcqRel))),
+            Some(time(|a, i| a.swap(i, SeqCst))),
+        ],
+    );
+    row(
+        "fetch_add",
+        [
+            Some(time(|a, _| a.fetch_add(1, Relaxed))),
+            Some(time(|a, _| a.fetch_add(1, AcqRel))),
+            Some(time(|a, _| a.fetch_add(1, SeqCst))),
+        ],
+    );
+    // The counter starts at 0 and goes up by one per iteration, so every exchange succeeds.
+    row(
+        "compare_exchange",
+        [
+            Some(time(|a, i| a.compare_exchange(i, i + 1, Relaxed, Relaxed))),
+            Some(time(|a, i| a.compare_exchange(i, i + 1, AcqRel, Relaxed))),
+            Some(time(|a, i| a.compare_exchange(i, i + 1, SeqCst, Relaxed))),
+        ],
+    );
+    // A Relaxed fence isn't a thing: `fence` panics on it.
+    row(
+        "fence",
+        [
+            None,
+            Some(time(|_, _| fence(AcqRel))),
+            Some(time(|_, _| fence(SeqCst))),
+        ],
+    );
+}
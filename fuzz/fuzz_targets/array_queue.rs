@@ -0,0 +1,60 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use atomics::array_queue::ArrayQueue;
+use libfuzzer_sys::fuzz_target;
+use std::collections::VecDeque;
+
+// ArrayQueue against a VecDeque, one op at a time on one thread, with its indices starting
+// `before_wrap` short of usize::MAX, so they wrap to 0 within a few laps. After every op its
+// length, emptiness and fullness are the model's, and every pop gets the oldest item.
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    before_wrap: u16,
+    ops: Vec<Op>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Push,
+    ForcePush,
+    Pop,
+}
+
+fuzz_target!(|input: Input| {
+    let capacity = input.capacity as usize % 16 + 1;
+    let q = ArrayQueue::starting_at(capacity, usize::MAX - input.before_wrap as usize);
+    let mut model = VecDeque::new();
+    let mut next = 0u64;
+
+    for op in input.ops {
+        match op {
+            Op::Push => match q.push(next) {
+                Ok(()) => {
+                    assert!(model.len() < capacity, "pushed into a full queue");
+                    model.push_back(next);
+                }
+                Err(v) => assert_eq!((v, model.len()), (next, capacity)),
+            },
+            Op::ForcePush => {
+                let evicted = if model.len() == capacity {
+                    model.pop_front()
+                } else {
+                    None
+                };
+                assert_eq!(q.force_push(next), evicted);
+                model.push_back(next);
+            }
+            Op::Pop => assert_eq!(q.pop(), model.pop_front()),
+        }
+        next += 1;
+        assert_eq!(q.len(), model.len());
+        assert_eq!(q.is_empty(), model.is_empty());
+        assert_eq!(q.is_full(), model.len() == capacity);
+    }
+
+    assert!(q.drain().eq(model));
+    assert!(q.is_empty());
+});
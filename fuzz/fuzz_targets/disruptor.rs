@@ -0,0 +1,75 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use atomics::disruptor::Builder;
+use libfuzzer_sys::fuzz_target;
+
+// A two-stage disruptor driven one op at a time on one thread: publishes of any batch the
+// ring has room for, and polls of either stage. Each poll has to hand out the next run of
+// sequences, as far as the stage may read and no further: everything claimed for the first
+// stage, and everything the first has finished with for the second, each entry holding the
+// value published for it and not a lap-old one. Sequences start at `start`, so the masking
+// of them to slots runs with their high bits set.
+//
+// A publish the ring hasn't room for would wait for the consumers, which on one thread is
+// forever, so the target skips it; that the ring has the room it should is checked by the
+// publishes it does make never waiting.
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    start: u64,
+    ops: Vec<Op>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Publish(u8),
+    PollFirst,
+    PollSecond,
+}
+
+fuzz_target!(|input: Input| {
+    let capacity = (input.capacity as u64 % 64 + 1).next_power_of_two();
+    // Room for every entry the ops could publish, since sequences never wrap.
+    let start = input.start.min(u64::MAX - (1 << 32));
+    let mut builder = Builder::new(capacity as usize).start_sequence(start);
+    let first = builder.consumer(&[]);
+    builder.consumer(&[first]);
+    let (producer, mut consumers) = builder.build::<u64>();
+
+    // The next sequence to claim, and the next each stage reads.
+    let mut claimed = start;
+    let mut next = [start, start];
+
+    for op in input.ops {
+        match op {
+            Op::Publish(n) => {
+                let n = n as u64 % (capacity + 1);
+                if claimed + n - next[1] > capacity {
+                    continue;
+                }
+                let mut seq = claimed;
+                producer.publish_batch(n as usize, |s, entry| {
+                    assert_eq!(s, seq);
+                    *entry = s;
+                    seq += 1;
+                });
+                claimed += n;
+            }
+            Op::PollFirst | Op::PollSecond => {
+                let stage = matches!(op, Op::PollSecond) as usize;
+                let limit = if stage == 0 { claimed } else { next[0] };
+                let mut seq = next[stage];
+                let n = consumers[stage].poll(|s, &v, last| {
+                    assert_eq!((s, v), (seq, seq));
+                    seq += 1;
+                    assert_eq!(last, seq == limit);
+                });
+                assert_eq!(next[stage] + n as u64, limit);
+                next[stage] = limit;
+            }
+        }
+        assert!(next[1] <= next[0] && next[0] <= claimed && claimed - next[1] <= capacity);
+    }
+});
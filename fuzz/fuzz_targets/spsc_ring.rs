@@ -0,0 +1,96 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use atomics::spsc;
+use libfuzzer_sys::fuzz_target;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+
+// The SPSC ring against a VecDeque, one op at a time on one thread. Pops and chunk reads get
+// the oldest items first, pushes and chunk writes get exactly the room the model has left,
+// and whatever's left at the end comes out in order. The indices start wherever the input
+// says, so their wrap at twice the capacity, and the slots' wrap at the capacity, fall
+// anywhere in the run.
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    capacity: u8,
+    start: usize,
+    ops: Vec<Op>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Push,
+    Pop,
+    PushSlice(u8),
+    PopSlice(u8),
+    WriteChunk { wanted: u8, commit: u8 },
+    ReadChunk { wanted: u8, commit: u8 },
+}
+
+fuzz_target!(|input: Input| {
+    let capacity = input.capacity as usize % 16 + 1;
+    let (mut producer, mut consumer) = spsc::ring_starting_at::<u64>(capacity, input.start);
+    let mut model = VecDeque::new();
+    let mut next = 0u64;
+    let room = |model: &VecDeque<u64>| capacity - model.len();
+
+    for op in input.ops {
+        match op {
+            Op::Push => match producer.push(next) {
+                Ok(()) => {
+                    assert!(model.len() < capacity, "pushed into a full ring");
+                    model.push_back(next);
+                    next += 1;
+                }
+                Err(v) => assert_eq!((v, model.len()), (next, capacity)),
+            },
+            Op::Pop => assert_eq!(consumer.pop(), model.pop_front()),
+            Op::PushSlice(n) => {
+                let items: Vec<u64> = (next..next + n as u64).collect();
+                let pushed = producer.push_slice(&items);
+                assert_eq!(pushed, items.len().min(room(&model)));
+                model.extend(&items[..pushed]);
+                next += pushed as u64;
+            }
+            Op::PopSlice(n) => {
+                let mut out = vec![0; n as usize];
+                let popped = consumer.pop_slice(&mut out);
+                assert_eq!(popped, out.len().min(model.len()));
+                let expected: Vec<u64> = model.drain(..popped).collect();
+                assert_eq!(out[..popped], expected[..]);
+            }
+            Op::WriteChunk { wanted, commit } => {
+                let mut chunk = producer.write_chunk(wanted as usize);
+                assert_eq!(chunk.len(), (wanted as usize).min(room(&model)));
+                let len = chunk.len();
+                let (first, second) = chunk.as_mut_slices();
+                assert_eq!(first.len() + second.len(), len);
+                let n = commit as usize % (len + 1);
+                for (i, slot) in first.iter_mut().chain(second).take(n).enumerate() {
+                    *slot = MaybeUninit::new(next + i as u64);
+                }
+                // Safety: the first n slots were just written.
+                unsafe { chunk.commit(n) };
+                model.extend(next..next + n as u64);
+                next += n as u64;
+            }
+            Op::ReadChunk { wanted, commit } => {
+                let chunk = consumer.read_chunk(wanted as usize);
+                assert_eq!(chunk.len(), (wanted as usize).min(model.len()));
+                let (first, second) = chunk.as_slices();
+                let read: Vec<u64> = first.iter().chain(second).copied().collect();
+                assert!(read.iter().eq(model.iter().take(chunk.len())));
+                let n = commit as usize % (chunk.len() + 1);
+                chunk.commit(n);
+                model.drain(..n);
+            }
+        }
+    }
+
+    while let Some(v) = consumer.pop() {
+        assert_eq!(Some(v), model.pop_front());
+    }
+    assert!(model.is_empty());
+});
@@ -0,0 +1,95 @@
+// Generated from src/ffi.rs by its ffi_header_is_current test; don't edit.
+
+#ifndef ATOMICS_H
+#define ATOMICS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct atomics_mutex atomics_mutex_t;
+typedef struct atomics_rwlock atomics_rwlock_t;
+typedef struct atomics_channel atomics_channel_t;
+
+#define ATOMICS_OK 0
+#define ATOMICS_WOULD_BLOCK 1
+#define ATOMICS_DISCONNECTED 2
+
+// An unlocked mutex.
+atomics_mutex_t *atomics_mutex_create(void);
+
+// Waits for the mutex and takes it.
+void atomics_mutex_lock(atomics_mutex_t *mutex);
+
+// Takes the mutex if it's free.
+bool atomics_mutex_try_lock(atomics_mutex_t *mutex);
+
+// Gives the mutex back, on the thread that took it.
+void atomics_mutex_unlock(atomics_mutex_t *mutex);
+
+// Frees an unlocked mutex.
+void atomics_mutex_destroy(atomics_mutex_t *mutex);
+
+// An unlocked readers-writer lock.
+atomics_rwlock_t *atomics_rwlock_create(void);
+
+// Waits for the lock and takes it for reading, alongside any other readers.
+void atomics_rwlock_read_lock(atomics_rwlock_t *lock);
+
+// Takes the lock for reading if no writer has it or is waiting for it.
+bool atomics_rwlock_try_read_lock(atomics_rwlock_t *lock);
+
+// Gives back a read lock, on the thread that took it.
+void atomics_rwlock_read_unlock(atomics_rwlock_t *lock);
+
+// Waits for the lock and takes it for writing.
+void atomics_rwlock_write_lock(atomics_rwlock_t *lock);
+
+// Takes the lock for writing if no one has it.
+bool atomics_rwlock_try_write_lock(atomics_rwlock_t *lock);
+
+// Gives back a write lock, on the thread that took it.
+void atomics_rwlock_write_unlock(atomics_rwlock_t *lock);
+
+// Frees an unlocked readers-writer lock.
+void atomics_rwlock_destroy(atomics_rwlock_t *lock);
+
+// A channel holding up to `capacity` messages; with a capacity of zero, a send waits
+// for a receiver to take it.
+atomics_channel_t *atomics_channel_create(size_t capacity);
+
+// Sends `message`, waiting while the channel is full. ATOMICS_OK, or
+// ATOMICS_DISCONNECTED if it's closed.
+int atomics_channel_send(atomics_channel_t *channel, void *message);
+
+// Sends `message` if there's room. ATOMICS_OK, ATOMICS_WOULD_BLOCK if the channel is
+// full, or ATOMICS_DISCONNECTED if it's closed.
+int atomics_channel_try_send(atomics_channel_t *channel, void *message);
+
+// Receives a message into `*message`, waiting while the channel is empty. ATOMICS_OK, or
+// ATOMICS_DISCONNECTED once it's closed and drained.
+int atomics_channel_recv(atomics_channel_t *channel, void **message);
+
+// Receives a message into `*message` if there's one. ATOMICS_OK, ATOMICS_WOULD_BLOCK if
+// the channel is empty, or ATOMICS_DISCONNECTED once it's closed and drained.
+int atomics_channel_try_recv(atomics_channel_t *channel, void **message);
+
+// How many messages are queued.
+size_t atomics_channel_len(atomics_channel_t *channel);
+
+// Closes the channel: sends fail from now on, and receives once it's drained; blocked
+// senders and receivers wake to fail.
+void atomics_channel_close(atomics_channel_t *channel);
+
+// Frees a channel no one's using. Messages still queued are dropped without being
+// freed, so drain it first if they own anything.
+void atomics_channel_destroy(atomics_channel_t *channel);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,186 @@
+use std::io;
+
+/// Pins the current thread to `core`, so the scheduler won't move it to another one.
+///
+/// Cores are numbered as the OS numbers them, from zero. On macOS there's no hard pinning:
+/// this sets an affinity tag, which hints that threads with the same tag share a cache, and
+/// Apple silicon ignores it.
+pub fn pin_current(core: usize) -> io::Result<()> {
+    sys::set_current(&[core])
+}
+
+/// Lets the current thread run only on the given cores. Not supported on macOS, which has
+/// no notion of a set of cores to run on.
+pub fn set_current(cores: &[usize]) -> io::Result<()> {
+    if cores.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty set of cores",
+        ));
+    }
+    sys::set_current(cores)
+}
+
+/// The cores the current thread may run on, in order. Where the OS can't say, that's every
+/// core up to [`std::thread::available_parallelism`].
+pub fn cores() -> Vec<usize> {
+    sys::current().unwrap_or_else(|_| {
+        let n = std::thread::available_parallelism().map_or(1, |n| n.get());
+        (0..n).collect()
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn invalid_core(core: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("core {} is out of range", core),
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::os::raw::c_int;
+
+    // glibc's and musl's cpu_set_t: a bitmask of 1024 cores.
+    const SET_BITS: usize = 1024;
+
+    #[repr(C)]
+    struct CpuSet {
+        bits: [u64; SET_BITS / 64],
+    }
+
+    extern "C" {
+        fn sched_setaffinity(pid: c_int, size: usize, mask: *const CpuSet) -> c_int;
+        fn sched_getaffinity(pid: c_int, size: usize, mask: *mut CpuSet) -> c_int;
+    }
+
+    pub fn set_current(cores: &[usize]) -> io::Result<()> {
+        let mut set = CpuSet {
+            bits: [0; SET_BITS / 64],
+        };
+        for &core in cores {
+            if core >= SET_BITS {
+                return Err(super::invalid_core(core));
+            }
+            set.bits[core / 64] |= 1 << (core % 64);
+        }
+        // A pid of 0 means the calling thread.
+        if unsafe { sched_setaffinity(0, std::mem::size_of::<CpuSet>(), &set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn current() -> io::Result<Vec<usize>> {
+        let mut set = CpuSet {
+            bits: [0; SET_BITS / 64],
+        };
+        if unsafe { sched_getaffinity(0, std::mem::size_of::<CpuSet>(), &mut set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..SET_BITS)
+            .filter(|&core| set.bits[core / 64] & (1 << (core % 64)) != 0)
+            .collect())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::io;
+
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+
+    // Only the cores of the thread's processor group, which is all of them up to 64.
+    pub fn set_current(cores: &[usize]) -> io::Result<()> {
+        let mut mask = 0usize;
+        for &core in cores {
+            if core >= usize::BITS as usize {
+                return Err(super::invalid_core(core));
+            }
+            mask |= 1 << core;
+        }
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn current() -> io::Result<Vec<usize>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::convert::TryFrom;
+    use std::io;
+    use std::os::raw::{c_int, c_uint};
+
+    const THREAD_AFFINITY_POLICY: c_uint = 4;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_mach_thread_np(thread: usize) -> c_uint;
+        fn thread_policy_set(
+            thread: c_uint,
+            flavor: c_uint,
+            policy: *const c_int,
+            count: c_uint,
+        ) -> c_int;
+    }
+
+    pub fn set_current(cores: &[usize]) -> io::Result<()> {
+        let &[core] = cores else {
+            return Err(io::ErrorKind::Unsupported.into());
+        };
+        // Tag 0 means no affinity, so core n gets tag n + 1.
+        let tag = c_int::try_from(core + 1).map_err(|_| super::invalid_core(core))?;
+        let thread = unsafe { pthread_mach_thread_np(pthread_self()) };
+        if unsafe { thread_policy_set(thread, THREAD_AFFINITY_POLICY, &tag, 1) } != 0 {
+            return Err(io::Error::other("thread_policy_set failed"));
+        }
+        Ok(())
+    }
+
+    pub fn current() -> io::Result<Vec<usize>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod sys {
+    use std::io;
+
+    pub fn set_current(_cores: &[usize]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn current() -> io::Result<Vec<usize>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[test]
+fn affinity_pins_the_current_thread() {
+    let cores = cores();
+    assert!(!cores.is_empty());
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    std::thread::spawn(move || {
+        pin_current(cores[0]).unwrap();
+        assert_eq!(sys::current().unwrap(), [cores[0]]);
+        set_current(&cores).unwrap();
+        assert_eq!(sys::current().unwrap(), cores);
+        assert!(set_current(&[]).is_err());
+        assert!(pin_current(1 << 20).is_err());
+    })
+    .join()
+    .unwrap();
+}
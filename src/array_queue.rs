@@ -0,0 +1,581 @@
+use crate::cache_padded::CachePadded;
+use crate::sync_shim::atomic::{self, AtomicUsize, Ordering};
+use crate::sync_shim::{yield_now, UnsafeCell};
+use std::mem::MaybeUninit;
+
+struct Slot<T> {
+    // The index whose turn it is at this slot: `i` when free for the push at index i, `i + 1`
+    // when holding that push's value for the pop at index i.
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Everything of the queue but its slots, which ArrayQueue keeps on the heap and
+// StaticMpmcQueue inline, so both run the same code: each method here takes the slots.
+struct Indices {
+    // Padded so producers and consumers don't false-share.
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    one_lap: usize,
+}
+
+impl Indices {
+    // The index after `index`, wrapping to the start of the next lap past the last slot.
+    fn advance(&self, index: usize, capacity: usize) -> usize {
+        if (index & (self.one_lap - 1)) + 1 < capacity {
+            index + 1
+        } else {
+            (index & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    fn push<T>(&self, slots: &[Slot<T>], value: T) -> Result<(), T> {
+        self.push_or_else(slots, value, |value, tail, _, _| {
+            let head = self.head.load(Ordering::Relaxed);
+            if head.wrapping_add(self.one_lap) == tail {
+                Err(value)
+            } else {
+                Ok(value)
+            }
+        })
+    }
+
+    fn force_push<T>(&self, slots: &[Slot<T>], value: T) -> Option<T> {
+        self.push_or_else(slots, value, |value, tail, next, slot| {
+            // The slot at tail is the oldest item's; take it over by moving head and tail on
+            // together.
+            let head = tail.wrapping_sub(self.one_lap);
+            let next_head = next.wrapping_sub(self.one_lap);
+            if self
+                .head
+                .compare_exchange_weak(head, next_head, Ordering::SeqCst, Ordering::Relaxed)
+                .is_err()
+            {
+                return Ok(value);
+            }
+            self.tail.store(next, Ordering::SeqCst);
+            let old = slot.value.with_mut(|v| unsafe { (*v).assume_init_read() });
+            slot.value.with_mut(|v| unsafe { (*v).write(value) });
+            slot.stamp.store(tail + 1, Ordering::Release);
+            Err(old)
+        })
+        .err()
+    }
+
+    // The push loop, calling `full` when the slot at tail still holds last lap's item. `full`
+    // either hands the value back to retry with, or ends the push with an error holding
+    // whatever should be returned.
+    fn push_or_else<T, F>(&self, slots: &[Slot<T>], mut value: T, full: F) -> Result<(), T>
+    where
+        F: Fn(T, usize, usize, &Slot<T>) -> Result<T, T>,
+    {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &slots[tail & (self.one_lap - 1)];
+            let next = self.advance(tail, slots.len());
+            // Acquire: pairs with the pop that freed the slot, so we don't overwrite a value
+            // it's still reading.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == tail {
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    next,
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        slot.value.with_mut(|v| unsafe { (*v).write(value) });
+                        // Release: publishes the value to the pop at this index.
+                        slot.stamp.store(tail + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(t) => tail = t,
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // The slot still holds last lap's value. The queue is full unless a pop has
+                // moved head on and just hasn't finished with the slot yet.
+                atomic::fence(Ordering::SeqCst);
+                value = full(value, tail, next, slot)?;
+                yield_now();
+                tail = self.tail.load(Ordering::Relaxed);
+            } else {
+                // Another push got this index first.
+                yield_now();
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop<T>(&self, slots: &[Slot<T>]) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &slots[head & (self.one_lap - 1)];
+            // Acquire: pairs with the push that filled the slot.
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == head + 1 {
+                let next = self.advance(head, slots.len());
+                match self.head.compare_exchange_weak(
+                    head,
+                    next,
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = slot.value.with(|v| unsafe { (*v).assume_init_read() });
+                        // Release: frees the slot for the push one lap later.
+                        slot.stamp
+                            .store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(h) => head = h,
+                }
+            } else if stamp == head {
+                // Empty unless a push has moved tail on and just hasn't written yet.
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == head {
+                    return None;
+                }
+                yield_now();
+                head = self.head.load(Ordering::Relaxed);
+            } else {
+                // Another pop got this index first.
+                yield_now();
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn len(&self, capacity: usize) -> usize {
+        loop {
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head = self.head.load(Ordering::SeqCst);
+            // Only trust a tail that didn't move while we read head.
+            if self.tail.load(Ordering::SeqCst) == tail {
+                let lap_of = |i: usize| i & !(self.one_lap - 1);
+                let offset_of = |i: usize| i & (self.one_lap - 1);
+                return if offset_of(tail) > offset_of(head) {
+                    offset_of(tail) - offset_of(head)
+                } else if offset_of(tail) < offset_of(head) {
+                    capacity - offset_of(head) + offset_of(tail)
+                } else if lap_of(tail) == lap_of(head) {
+                    0
+                } else {
+                    capacity
+                };
+            }
+        }
+    }
+}
+
+/// Vyukov's bounded multi-producer multi-consumer queue.
+///
+/// Head and tail indices are `lap * one_lap + offset`, with `one_lap` the capacity rounded up
+/// to a power of two so the lap can live in the upper bits. Each slot's stamp says which index
+/// may use it next, so a push and a pop only contend when they're on the same slot.
+pub struct ArrayQueue<T> {
+    indices: Indices,
+    slots: Box<[Slot<T>]>,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self::at(capacity, 0)
+    }
+
+    /// A queue whose indices start at the lap `index` is in rather than 0, so a test or fuzz
+    /// target can have them wrap past `usize::MAX` after a few laps.
+    #[cfg(any(test, fuzzing))]
+    #[doc(hidden)]
+    pub fn starting_at(capacity: usize, index: usize) -> Self {
+        Self::at(capacity, index)
+    }
+
+    fn at(capacity: usize, index: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        let one_lap = (capacity + 1).next_power_of_two();
+        let start = index & !(one_lap - 1);
+        Self {
+            indices: Indices {
+                head: CachePadded::new(AtomicUsize::new(start)),
+                tail: CachePadded::new(AtomicUsize::new(start)),
+                one_lap,
+            },
+            slots: (0..capacity)
+                .map(|i| Slot {
+                    stamp: AtomicUsize::new(start + i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Pushes `value`, or hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.indices.push(&self.slots, value)
+    }
+
+    /// Pushes `value`, evicting and returning the oldest item if the queue is full.
+    pub fn force_push(&self, value: T) -> Option<T> {
+        self.indices.force_push(&self.slots, value)
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.indices.pop(&self.slots)
+    }
+
+    /// The number of items, which may be out of date by the time it's returned.
+    pub fn len(&self) -> usize {
+        self.indices.len(self.slots.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Pops items until it finds the queue empty, for flushing what's left at shutdown.
+    /// Pushes made meanwhile are drained too, so it ends once producers stop or fall behind.
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// [`ArrayQueue`] with its `N` slots inline rather than on the heap, so it can be made in a
+/// const and kept in a `static`. Not under loom, whose atomics can't be made in a const.
+#[cfg(not(loom))]
+pub struct StaticMpmcQueue<T, const N: usize> {
+    indices: Indices,
+    slots: [Slot<T>; N],
+}
+
+#[cfg(not(loom))]
+unsafe impl<T: Send, const N: usize> Send for StaticMpmcQueue<T, N> {}
+#[cfg(not(loom))]
+unsafe impl<T: Send, const N: usize> Sync for StaticMpmcQueue<T, N> {}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> StaticMpmcQueue<T, N> {
+    /// # Panics
+    ///
+    /// If `N` is zero; at compile time, in a const or a static.
+    pub const fn new() -> Self {
+        assert!(N > 0, "capacity must be non-zero");
+        let mut slots: [MaybeUninit<Slot<T>>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        let mut i = 0;
+        while i < N {
+            slots[i] = MaybeUninit::new(Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            });
+            i += 1;
+        }
+        Self {
+            indices: Indices {
+                head: CachePadded::new(AtomicUsize::new(0)),
+                tail: CachePadded::new(AtomicUsize::new(0)),
+                one_lap: (N + 1).next_power_of_two(),
+            },
+            // Safety: every slot was just initialized, and MaybeUninit<X> is laid out as X.
+            slots: unsafe {
+                (&slots as *const [MaybeUninit<Slot<T>>; N])
+                    .cast::<[Slot<T>; N]>()
+                    .read()
+            },
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Pushes `value`, or hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.indices.push(&self.slots, value)
+    }
+
+    /// Pushes `value`, evicting and returning the oldest item if the queue is full.
+    pub fn force_push(&self, value: T) -> Option<T> {
+        self.indices.force_push(&self.slots, value)
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.indices.pop(&self.slots)
+    }
+
+    /// The number of items, which may be out of date by the time it's returned.
+    pub fn len(&self) -> usize {
+        self.indices.len(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Pops items until it finds the queue empty, like [`ArrayQueue::drain`].
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> Default for StaticMpmcQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> Drop for StaticMpmcQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn array_queue_bounded() {
+    let q = ArrayQueue::new(3);
+    assert_eq!(q.push(1), Ok(()));
+    assert_eq!(q.push(2), Ok(()));
+    assert_eq!(q.push(3), Ok(()));
+    assert_eq!(q.push(4), Err(4));
+    assert!(q.is_full());
+    assert_eq!(q.pop(), Some(1));
+    assert_eq!(q.push(4), Ok(()));
+    assert_eq!(q.len(), 3);
+    assert_eq!(
+        (q.pop(), q.pop(), q.pop(), q.pop()),
+        (Some(2), Some(3), Some(4), None)
+    );
+
+    let q = ArrayQueue::new(2);
+    q.push(String::from("dropped with the queue")).unwrap();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn array_queue_force_push() {
+    let q = ArrayQueue::new(2);
+    assert_eq!(q.force_push(1), None);
+    assert_eq!(q.force_push(2), None);
+    assert_eq!(q.force_push(3), Some(1));
+    assert_eq!(q.force_push(4), Some(2));
+    assert_eq!(q.len(), 2);
+    assert_eq!((q.pop(), q.pop(), q.pop()), (Some(3), Some(4), None));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn array_queue_wraps_past_usize_max() {
+    // Two laps before the wrap: the indices go past usize::MAX halfway through.
+    let q = ArrayQueue::starting_at(3, usize::MAX - 7);
+    let mut next = 0;
+    for i in 0..20 {
+        assert_eq!(q.push(i), Ok(()));
+        if i % 3 == 2 {
+            assert!(q.is_full() && q.push(99).is_err());
+            while let Some(v) = q.pop() {
+                assert_eq!(v, next);
+                next += 1;
+            }
+        }
+        assert_eq!(q.len(), i + 1 - next);
+    }
+    assert_eq!(q.force_push(20), None);
+    assert_eq!(q.force_push(21), Some(18));
+    assert_eq!(q.drain().collect::<Vec<_>>(), [19, 20, 21]);
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn static_mpmc_queue_in_a_static() {
+    static Q: StaticMpmcQueue<u32, 3> = StaticMpmcQueue::new();
+    let n = if cfg!(miri) { 50 } else { 2000 };
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..n {
+                while Q.push(i).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        for i in 0..n {
+            loop {
+                match Q.pop() {
+                    Some(v) => break assert_eq!(v, i),
+                    None => std::thread::yield_now(),
+                }
+            }
+        }
+    });
+    assert!(Q.is_empty());
+    assert_eq!(
+        (Q.push(1), Q.push(2), Q.push(3), Q.push(4)),
+        (Ok(()), Ok(()), Ok(()), Err(4))
+    );
+    assert_eq!(Q.force_push(5), Some(1));
+    assert!(Q.is_full() && Q.capacity() == 3);
+
+    let q = StaticMpmcQueue::<String, 2>::new();
+    q.push(String::from("dropped with the queue")).unwrap();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn array_queue_mpmc() {
+    use std::thread;
+    let n = if cfg!(miri) { 100 } else { 2000 };
+    let q = &ArrayQueue::new(16);
+    let mut all: Vec<_> = thread::scope(|s| {
+        for t in 0..2 {
+            s.spawn(move || {
+                for i in 0..n {
+                    let mut v = t * n + i;
+                    while let Err(back) = q.push(v) {
+                        v = back;
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                s.spawn(move || {
+                    let mut got = Vec::new();
+                    while got.len() < n {
+                        match q.pop() {
+                            Some(v) => got.push(v),
+                            None => thread::yield_now(),
+                        }
+                    }
+                    got
+                })
+            })
+            .collect();
+        consumers
+            .into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect()
+    });
+    all.sort_unstable();
+    assert!(all.into_iter().eq(0..2 * n));
+}
+
+#[cfg(loom)]
+#[test]
+fn array_queue_loom() {
+    use loom::sync::Arc;
+    use loom::thread;
+    loom::model(|| {
+        let q = Arc::new(ArrayQueue::new(1));
+        let producer = {
+            let q = Arc::clone(&q);
+            thread::spawn(move || {
+                for i in 0..2 {
+                    while q.push(i).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+        for i in 0..2 {
+            loop {
+                match q.pop() {
+                    Some(v) => break assert_eq!(v, i),
+                    None => thread::yield_now(),
+                }
+            }
+        }
+        producer.join().unwrap();
+    });
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+proptest::proptest! {
+    // Each thread's pushes and pops on a queue of a few slots, interleaved under sched as the
+    // choices say, and then pops until it's empty. Every result, a push turned away or a
+    // value popped, has to be one that a bounded FIFO running the ops one at a time, in some
+    // order that keeps each thread's and respects which returned before which was called,
+    // gives. A failure shrinks to a short, simple interleaving, since the choices are the
+    // interleaving.
+    #[test]
+    #[cfg_attr(miri, ignore = "proptest persists failures in the working directory")]
+    fn array_queue_matches_vec_deque(
+        choices in proptest::collection::vec(0..4usize, 0..64),
+        capacity in 1..4usize,
+        threads in proptest::collection::vec(
+            proptest::collection::vec(proptest::bool::ANY, 0..8),
+            1..4,
+        ),
+    ) {
+        use crate::linearizability::{History, PushPop, Spec};
+        use crate::sched;
+        use std::collections::VecDeque;
+        use std::sync::Arc;
+
+        // A VecDeque of at most `capacity`, turning a push away when it's full as the queue
+        // does, by handing the value back.
+        #[derive(Clone, PartialEq, Eq, Hash)]
+        struct Bounded(VecDeque<u64>, usize);
+
+        impl Spec for Bounded {
+            type Op = PushPop<u64>;
+            type Ret = Option<u64>;
+
+            fn apply(&mut self, op: &PushPop<u64>) -> Option<u64> {
+                match *op {
+                    PushPop::Push(v) if self.0.len() == self.1 => Some(v),
+                    PushPop::Push(v) => {
+                        self.0.push_back(v);
+                        None
+                    }
+                    PushPop::Pop => self.0.pop_front(),
+                }
+            }
+        }
+
+        let q = Arc::new(ArrayQueue::new(capacity));
+        let history = Arc::new(History::new());
+        sched::run_guided(&choices, |s| {
+            for (t, ops) in threads.iter().cloned().enumerate() {
+                let (q, history) = (Arc::clone(&q), Arc::clone(&history));
+                s.spawn(move || {
+                    for (i, push) in ops.into_iter().enumerate() {
+                        // Every push's value its own, so a pop says which it got.
+                        let v = t as u64 * 100 + i as u64;
+                        if push {
+                            history.record(PushPop::Push(v), || q.push(v).err());
+                        } else {
+                            history.record(PushPop::Pop, || q.pop());
+                        }
+                    }
+                });
+            }
+        });
+        while history.record(PushPop::Pop, || q.pop()).is_some() {}
+        history
+            .check(Bounded(VecDeque::new(), capacity))
+            .map_err(|w| proptest::test_runner::TestCaseError::fail(w.to_string()))?;
+    }
+}
@@ -0,0 +1,32 @@
+mod barrier;
+mod cancellation;
+mod mutex;
+mod notify;
+pub mod oneshot;
+mod permits;
+mod rwlock;
+mod semaphore;
+mod waiters;
+
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use cancellation::{CancellationToken, Cancelled};
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+// Runs a test's future on the test's thread, as `executor::block_on` does; that one's only
+// there under the `executor` feature.
+#[cfg(test)]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let parker = crate::parker::Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        parker.park();
+    }
+}
@@ -0,0 +1,103 @@
+use super::notify::Notify;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Lets a fixed number of tasks wait until all of them have reached the same point.
+///
+/// The barrier is reusable: once `n` tasks have arrived they're all released and the next
+/// `n` start a new round. A `wait` future that is dropped before the round completes still
+/// counts as having arrived.
+///
+/// For threads there's [`barrier::Barrier`](crate::barrier::Barrier), whose waits can time
+/// out.
+pub struct Barrier {
+    n: usize,
+    arrived: Mutex<usize>,
+    // Bumped by each round's leader.
+    generation: AtomicU64,
+    released: Notify,
+}
+
+/// Returned by [`Barrier::wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+impl Barrier {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            arrived: Mutex::new(0),
+            generation: AtomicU64::new(0),
+            released: Notify::new(),
+        }
+    }
+
+    /// Waits until `n` tasks have arrived in this round. The last one to arrive is the
+    /// leader.
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let generation = match self.arrive() {
+            Some(generation) => generation,
+            None => return BarrierWaitResult { leader: true },
+        };
+        loop {
+            // Created before the check, so a release after it completes the future.
+            let released = self.released.notified();
+            if self.generation.load(Ordering::SeqCst) != generation {
+                return BarrierWaitResult { leader: false };
+            }
+            released.await;
+        }
+    }
+
+    // Counts us in, returning the round to wait for, or None if we completed it.
+    fn arrive(&self) -> Option<u64> {
+        let mut arrived = self.arrived.lock().unwrap();
+        let generation = self.generation.load(Ordering::SeqCst);
+        *arrived += 1;
+        if *arrived < self.n {
+            return Some(generation);
+        }
+        *arrived = 0;
+        self.generation.store(generation + 1, Ordering::SeqCst);
+        drop(arrived);
+        self.released.notify_waiters();
+        None
+    }
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+#[test]
+fn barrier_releases_rounds_with_one_leader() {
+    use crate::async_sync::block_on;
+
+    fn assert_send<T: Send>(_: &T) {}
+    let barrier = Barrier::new(3);
+    assert_send(&barrier.wait());
+    let leaders = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..3)
+            .map(|_| {
+                s.spawn(|| {
+                    block_on(async {
+                        let mut leader = 0;
+                        for _ in 0..5 {
+                            leader += barrier.wait().await.is_leader() as usize;
+                        }
+                        leader
+                    })
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .sum::<usize>()
+    });
+    assert_eq!(leaders, 5);
+}
@@ -0,0 +1,227 @@
+use super::waiters::{List, Waiter};
+use crate::parker::Parker;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+struct Node {
+    // Only ever goes from false to true, under the lock.
+    cancelled: AtomicBool,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    waiters: List<()>,
+    // Weak, so a dropped child doesn't stay alive until its parent is cancelled.
+    children: Vec<Weak<Node>>,
+}
+
+/// A flag that's raised once and seen by every clone, with a way for both threads and tasks
+/// to wait for it.
+///
+/// A [`child_token`](Self::child_token) is cancelled along with its parent, but can also be
+/// cancelled on its own without affecting the parent.
+#[derive(Clone)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Waiting,
+    Done,
+}
+
+/// Waits for a token to be cancelled, from [`CancellationToken::cancelled`].
+pub struct Cancelled<'a> {
+    node: &'a Node,
+    waiter: Waiter<()>,
+    state: State,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self {
+            node: Arc::new(Node {
+                cancelled: AtomicBool::new(false),
+                inner: Mutex::new(Inner {
+                    waiters: List::new(),
+                    children: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Returns a new token that's cancelled when this one is, or right away if this one
+    /// already has been.
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+        let mut inner = self.node.inner.lock().unwrap();
+        if self.node.cancelled.load(Ordering::Relaxed) {
+            drop(inner);
+            child.cancel();
+            return child;
+        }
+        // Prune before the vector grows, so children that come and go don't pile up.
+        if inner.children.len() == inner.children.capacity() {
+            inner.children.retain(|c| c.strong_count() > 0);
+        }
+        inner.children.push(Arc::downgrade(&child.node));
+        child
+    }
+
+    /// Cancels this token and all of its descendants, waking everything waiting on them.
+    /// Cancelling twice does nothing.
+    pub fn cancel(&self) {
+        // A stack rather than recursion, so a long chain of children can't overflow it.
+        let mut pending = vec![Arc::clone(&self.node)];
+        while let Some(node) = pending.pop() {
+            let mut inner = node.inner.lock().unwrap();
+            if node.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+            // Release: what came before the cancel is visible to whoever sees it.
+            node.cancelled.store(true, Ordering::Release);
+            let mut wakers = Vec::new();
+            while !inner.waiters.is_empty() {
+                wakers.extend(inner.waiters.wake_front());
+            }
+            let children = std::mem::take(&mut inner.children);
+            drop(inner);
+            wakers.into_iter().for_each(|w| w.wake());
+            pending.extend(children.iter().filter_map(Weak::upgrade));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Blocks the current thread until the token is cancelled.
+    pub fn wait(&self) {
+        let parker = Parker::new();
+        let waker = parker.unparker().waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut cancelled = std::pin::pin!(self.cancelled());
+        while cancelled.as_mut().poll(&mut cx).is_pending() {
+            parker.park();
+        }
+    }
+
+    /// Returns a future that completes once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            node: &self.node,
+            waiter: Waiter::new(()),
+            state: State::Idle,
+        }
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: the waiter is never moved out; the list points at it while it's waiting.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = this.node;
+        match this.state {
+            State::Idle => {
+                if node.cancelled.load(Ordering::Acquire) {
+                    this.state = State::Done;
+                    return Poll::Ready(());
+                }
+                let mut inner = node.inner.lock().unwrap();
+                if node.cancelled.load(Ordering::Relaxed) {
+                    this.state = State::Done;
+                    return Poll::Ready(());
+                }
+                this.waiter.register(cx.waker());
+                unsafe { inner.waiters.push_back(&this.waiter) };
+                this.state = State::Waiting;
+                Poll::Pending
+            }
+            State::Waiting => {
+                if !this.waiter.poll_woken(cx.waker()) {
+                    return Poll::Pending;
+                }
+                // The canceller may still be holding our waker; it's done with the node once
+                // it has let go of the lock.
+                drop(node.inner.lock().unwrap());
+                this.state = State::Done;
+                Poll::Ready(())
+            }
+            State::Done => panic!("Cancelled polled after completion"),
+        }
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if self.state != State::Waiting {
+            return;
+        }
+        let mut inner = self.node.inner.lock().unwrap();
+        if !self.waiter.is_woken() {
+            unsafe { inner.waiters.remove(&self.waiter) };
+        }
+    }
+}
+
+#[test]
+fn cancellation_reaches_children_not_parents() {
+    let root = CancellationToken::new();
+    let child = root.child_token();
+    let grandchild = child.child_token();
+    let sibling = root.child_token();
+
+    child.cancel();
+    assert!(child.is_cancelled() && grandchild.is_cancelled());
+    assert!(!root.is_cancelled() && !sibling.is_cancelled());
+
+    // Dropped children are pruned rather than kept for the parent's cancel.
+    for _ in 0..100 {
+        drop(root.child_token());
+    }
+    assert!(root.node.inner.lock().unwrap().children.len() < 100);
+
+    let clone = root.clone();
+    clone.cancel();
+    assert!(root.is_cancelled() && sibling.is_cancelled());
+    assert!(root.child_token().is_cancelled());
+}
+
+#[test]
+fn cancellation_wakes_threads_and_tasks() {
+    let token = CancellationToken::new();
+    let parker = Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let child = token.child_token();
+    let mut dropped = Box::pin(token.cancelled());
+    let mut task = Box::pin(child.cancelled());
+    assert!(dropped.as_mut().poll(&mut cx).is_pending());
+    assert!(task.as_mut().poll(&mut cx).is_pending());
+    drop(dropped);
+
+    std::thread::scope(|s| {
+        let waiters: Vec<_> = (0..3).map(|_| s.spawn(|| token.wait())).collect();
+        std::thread::yield_now();
+        token.cancel();
+        for w in waiters {
+            w.join().unwrap();
+        }
+    });
+    assert!(task.as_mut().poll(&mut cx).is_ready());
+    token.wait();
+}
@@ -0,0 +1,177 @@
+use super::permits::Permits;
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+
+/// A mutex for tasks: [`lock`](Self::lock) suspends the task rather than blocking its thread.
+///
+/// Waiting tasks get the lock in the order they asked for it, handed over directly by the
+/// guard being dropped. Dropping a `lock` future that's still waiting takes it out of line.
+pub struct Mutex<T> {
+    permits: Permits,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub const fn new(t: T) -> Self {
+        Self {
+            permits: Permits::new(1),
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        self.permits.acquire(1).await;
+        MutexGuard { lock: self }
+    }
+
+    /// Takes the lock if it's free and no task is waiting for it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.permits
+            .try_acquire(1)
+            .then(|| MutexGuard { lock: self })
+    }
+
+    /// Unlocks it without a guard, for a guard that was `mem::forget`-ten or
+    /// [`leak`](MutexGuard::leak)ed. The lock goes to the next task in line, as from a drop.
+    ///
+    /// # Safety
+    ///
+    /// It's held through a guard that won't be dropped, and nothing borrowed through that
+    /// guard, a leaked `&mut T` included, is used again.
+    pub unsafe fn force_unlock(&self) {
+        self.permits.release(1);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Holds a [`Mutex`] locked. It's `Send` for a `Send` value, so it can be held across an
+/// `.await` in a task that moves between threads.
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+}
+
+unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Keeps the mutex locked for good, and the data borrowed for as long as the mutex is.
+    /// Only [`Mutex::force_unlock`] unlocks it again.
+    pub fn leak(s: Self) -> &'a mut T {
+        let data = s.lock.v.get();
+        std::mem::forget(s);
+        // Safety: the permit is never given back, so this is the only borrow from now on.
+        unsafe { &mut *data }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we hold the only permit.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.permits.release(1);
+    }
+}
+
+#[test]
+fn async_mutex_is_fifo_and_cancellable() {
+    use crate::parker::Parker;
+    use std::future::Future;
+    use std::task::{Context, Poll};
+
+    let m = Mutex::new(Vec::new());
+    let parker = Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut lock = |f: std::pin::Pin<&mut _>| match Future::poll(f, &mut cx) {
+        Poll::Ready(guard) => Some(guard),
+        Poll::Pending => None,
+    };
+
+    let mut guard = m.try_lock().unwrap();
+    let mut a = Box::pin(m.lock());
+    let mut b = Box::pin(m.lock());
+    let mut c = Box::pin(m.lock());
+    assert!(lock(a.as_mut()).is_none());
+    assert!(lock(b.as_mut()).is_none());
+    assert!(lock(c.as_mut()).is_none());
+    // Queued tasks keep latecomers out, even between holders.
+    assert!(m.try_lock().is_none());
+
+    guard.push('x');
+    drop(guard);
+    let mut guard = lock(a.as_mut()).unwrap();
+    assert!(lock(c.as_mut()).is_none());
+    guard.push('a');
+    // Dropping b takes it out of line, so the lock goes straight to c.
+    drop(b);
+    drop(guard);
+    let mut guard = lock(c.as_mut()).unwrap();
+    guard.push('c');
+    drop(guard);
+    assert_eq!(*m.try_lock().unwrap(), ['x', 'a', 'c']);
+
+    // A leaked guard holds it until it's forced open, for the next in line.
+    let mut d = Box::pin(m.lock());
+    MutexGuard::leak(m.try_lock().unwrap()).push('l');
+    assert!(lock(d.as_mut()).is_none());
+    // Safety: the leaked borrow above is gone.
+    unsafe { m.force_unlock() };
+    assert_eq!(*lock(d.as_mut()).unwrap(), ['x', 'a', 'c', 'l']);
+}
+
+#[test]
+fn async_mutex_across_threads() {
+    use crate::async_sync::block_on;
+
+    fn assert_send<T: Send>(_: &T) {}
+    let m = Mutex::new(0);
+    // A task holding the lock, or waiting for it, can move between threads.
+    assert_send(&async {
+        let guard = m.lock().await;
+        std::future::ready(()).await;
+        drop(guard);
+    });
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                block_on(async {
+                    for _ in 0..100 {
+                        let mut guard = m.lock().await;
+                        let v = *guard;
+                        std::thread::yield_now();
+                        *guard = v + 1;
+                    }
+                })
+            });
+        }
+    });
+    assert_eq!(m.into_inner(), 400);
+}
@@ -0,0 +1,315 @@
+use super::waiters::{List, Waiter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+// The low bits of the state. WAITING means the list has waiters, which rules out a stored
+// permit; while it's set the state only changes under the list's lock.
+const EMPTY: usize = 0;
+const WAITING: usize = 1;
+const NOTIFIED: usize = 2;
+const MASK: usize = 3;
+// The rest of the state counts notify_waiters calls.
+const CALL: usize = 4;
+
+// Everything on the state is SeqCst: notified's load has to be ordered against a change the
+// caller makes to its own state before notifying, and what came before a notify has to be
+// visible to the task it wakes.
+
+/// Wakes tasks waiting on it, like a condition variable without the mutex.
+///
+/// [`notify_one`](Self::notify_one) wakes the longest-waiting task, or stores a permit when
+/// none is waiting, so the next [`notified`](Self::notified) completes right away; several
+/// notifies with nobody waiting still store only one. [`notify_waiters`](Self::notify_waiters)
+/// wakes every task waiting now and stores nothing.
+pub struct Notify {
+    state: AtomicUsize,
+    // Each waiter notes whether it was picked by notify_one, so it can pass that on if it's
+    // dropped before it gets to use it.
+    waiters: Mutex<List<AtomicBool>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Waiting,
+    Done,
+}
+
+/// Waits for a notification from [`Notify::notified`].
+///
+/// It counts as waiting from when it's created for `notify_waiters`, and from its first poll
+/// for `notify_one`. Dropping it gives up its place, passing on a `notify_one` it had already
+/// been picked for.
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    // The number of notify_waiters calls when the future was created.
+    calls: usize,
+    waiter: Waiter<AtomicBool>,
+    state: State,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(EMPTY),
+            waiters: Mutex::new(List::new()),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        while state & MASK != WAITING {
+            if state & MASK == NOTIFIED {
+                return;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state | NOTIFIED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return,
+                Err(s) => state = s,
+            }
+        }
+        let mut waiters = self.waiters.lock().unwrap();
+        let waker = self.notify_one_locked(&mut waiters);
+        drop(waiters);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn notify_one_locked(&self, waiters: &mut MutexGuard<'_, List<AtomicBool>>) -> Option<Waker> {
+        let mut state = self.state.load(Ordering::SeqCst);
+        while state & MASK != WAITING {
+            // The waiters left before we got the lock; store a permit instead.
+            match self.state.compare_exchange_weak(
+                state,
+                state & !MASK | NOTIFIED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return None,
+                Err(s) => state = s,
+            }
+        }
+        waiters
+            .front()
+            .expect("WAITING with no waiters")
+            .data
+            .store(true, Ordering::Relaxed);
+        let waker = waiters.wake_front();
+        if waiters.is_empty() {
+            self.state.store(state & !MASK, Ordering::SeqCst);
+        }
+        waker
+    }
+
+    /// Wakes every task that's waiting, including `notified` futures that haven't been polled
+    /// yet, without storing a permit.
+    pub fn notify_waiters(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        // While WAITING is set nothing else touches the state, so it can be stored outright.
+        let state = self.state.load(Ordering::SeqCst);
+        if state & MASK == WAITING {
+            self.state.store((state + CALL) & !MASK, Ordering::SeqCst);
+        } else {
+            self.state.fetch_add(CALL, Ordering::SeqCst);
+        }
+        let mut wakers = Vec::new();
+        while !waiters.is_empty() {
+            wakers.extend(waiters.wake_front());
+        }
+        drop(waiters);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            calls: self.state.load(Ordering::SeqCst) / CALL,
+            waiter: Waiter::new(AtomicBool::new(false)),
+            state: State::Idle,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notified<'_> {
+    // Completes if notify_waiters was called since we were created, or there's a permit to
+    // take. Otherwise returns the state to move on from.
+    fn try_complete(&self, mut state: usize) -> Result<(), usize> {
+        loop {
+            if state / CALL != self.calls {
+                return Ok(());
+            }
+            if state & MASK != NOTIFIED {
+                return Err(state);
+            }
+            match self.notify.state.compare_exchange_weak(
+                state,
+                state & !MASK,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(s) => state = s,
+            }
+        }
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: the waiter is never moved out; the list points at it while it's waiting.
+        let this = unsafe { self.get_unchecked_mut() };
+        let notify = this.notify;
+        match this.state {
+            State::Idle => {
+                if this
+                    .try_complete(notify.state.load(Ordering::SeqCst))
+                    .is_ok()
+                {
+                    this.state = State::Done;
+                    return Poll::Ready(());
+                }
+                let mut waiters = notify.waiters.lock().unwrap();
+                this.waiter.register(cx.waker());
+                let mut state = notify.state.load(Ordering::SeqCst);
+                loop {
+                    state = match this.try_complete(state) {
+                        Ok(()) => {
+                            this.state = State::Done;
+                            return Poll::Ready(());
+                        }
+                        Err(state) => state,
+                    };
+                    if state & MASK == WAITING {
+                        break;
+                    }
+                    match notify.state.compare_exchange_weak(
+                        state,
+                        state | WAITING,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    ) {
+                        Ok(_) => break,
+                        Err(s) => state = s,
+                    }
+                }
+                unsafe { waiters.push_back(&this.waiter) };
+                this.state = State::Waiting;
+                Poll::Pending
+            }
+            State::Waiting => {
+                if !this.waiter.poll_woken(cx.waker()) {
+                    return Poll::Pending;
+                }
+                // The notifier may still be holding our waker; it's done with the node once
+                // it has let go of the lock.
+                drop(notify.waiters.lock().unwrap());
+                this.state = State::Done;
+                Poll::Ready(())
+            }
+            State::Done => panic!("Notified polled after completion"),
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if self.state != State::Waiting {
+            return;
+        }
+        let notify = self.notify;
+        let mut waiters = notify.waiters.lock().unwrap();
+        let mut waker = None;
+        if !self.waiter.is_woken() {
+            unsafe { waiters.remove(&self.waiter) };
+            if waiters.is_empty() {
+                let state = notify.state.load(Ordering::SeqCst);
+                notify.state.store(state & !MASK, Ordering::SeqCst);
+            }
+        } else if self.waiter.data.load(Ordering::Relaxed) {
+            // Picked by notify_one but never got to use it.
+            waker = notify.notify_one_locked(&mut waiters);
+        }
+        drop(waiters);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[test]
+fn notify_stores_one_permit() {
+    use crate::parker::Parker;
+
+    let notify = Notify::new();
+    let parker = Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = Context::from_waker(&waker);
+
+    notify.notify_one();
+    notify.notify_one();
+    assert!(Box::pin(notify.notified())
+        .as_mut()
+        .poll(&mut cx)
+        .is_ready());
+    let mut a = Box::pin(notify.notified());
+    let mut b = Box::pin(notify.notified());
+    assert!(a.as_mut().poll(&mut cx).is_pending());
+    assert!(b.as_mut().poll(&mut cx).is_pending());
+
+    // a is picked, but passes it on to b by being dropped.
+    notify.notify_one();
+    drop(a);
+    assert!(b.as_mut().poll(&mut cx).is_ready());
+
+    // notify_waiters reaches futures that were created but not polled, and stores nothing.
+    let mut c = Box::pin(notify.notified());
+    let mut d = Box::pin(notify.notified());
+    assert!(c.as_mut().poll(&mut cx).is_pending());
+    notify.notify_waiters();
+    assert!(c.as_mut().poll(&mut cx).is_ready());
+    assert!(d.as_mut().poll(&mut cx).is_ready());
+    assert!(Box::pin(notify.notified())
+        .as_mut()
+        .poll(&mut cx)
+        .is_pending());
+}
+
+#[test]
+fn notify_wakes_across_threads() {
+    use crate::async_sync::block_on;
+
+    let notify = Notify::new();
+    let ready = AtomicBool::new(false);
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            block_on(async {
+                loop {
+                    let notified = notify.notified();
+                    if ready.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    notified.await;
+                }
+            })
+        });
+        std::thread::yield_now();
+        ready.store(true, Ordering::SeqCst);
+        notify.notify_waiters();
+    });
+}
@@ -0,0 +1,149 @@
+use crate::atomic_option::AtomicOption;
+use crate::atomic_waker::AtomicWaker;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+pub use crate::oneshot::{RecvError, TryRecvError};
+
+const SENT: u8 = 1;
+const SENDER_GONE: u8 = 2;
+const RECEIVER_GONE: u8 = 4;
+
+struct Shared<T> {
+    value: AtomicOption<T>,
+    state: AtomicU8,
+    receiver: AtomicWaker,
+}
+
+/// The sending half of a [`channel`]; `send` consumes it, so at most one value goes through.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a [`channel`], and a future of the value.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The async counterpart of [`oneshot::channel`](crate::oneshot::channel): the same
+/// [`AtomicOption`] and state flags, with the receiving task registered in an [`AtomicWaker`]
+/// instead of a thread on a parker.
+///
+/// Sending never blocks or waits, so it can be done from synchronous code.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: AtomicOption::none(),
+        state: AtomicU8::new(0),
+        receiver: AtomicWaker::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared })
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, or hands it back if the receiver is already gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.shared.state.load(Ordering::Relaxed) & RECEIVER_GONE != 0 {
+            return Err(value);
+        }
+        self.shared
+            .value
+            .try_put(value)
+            .unwrap_or_else(|_| unreachable!("only one send per channel"));
+        // AcqRel: the RMW orders us against the receiver's drop; whichever comes second
+        // knows the other happened.
+        let state = self.shared.state.fetch_or(SENT, Ordering::AcqRel);
+        if state & RECEIVER_GONE != 0 {
+            // The receiver left after our first check. If it didn't take the value on its
+            // way out, it never will.
+            if let Some(value) = self.shared.value.take() {
+                return Err(value);
+            }
+        }
+        Ok(())
+        // Drop wakes the receiver.
+    }
+
+    /// Whether the receiver has been dropped, so a send would fail.
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.load(Ordering::Relaxed) & RECEIVER_GONE != 0
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release: a sent value is visible to a receiver that sees the flag.
+        self.shared.state.fetch_or(SENDER_GONE, Ordering::Release);
+        self.shared.receiver.wake();
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.shared.value.take() {
+            return Ok(value);
+        }
+        if self.shared.state.load(Ordering::Acquire) & SENDER_GONE == 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // The sender may have sent just before it dropped.
+        self.shared.value.take().ok_or(TryRecvError::Disconnected)
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.try_recv() {
+            Ok(value) => return Poll::Ready(Ok(value)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {}
+        }
+        // A sender that drops after this sees the registration; one that dropped before it
+        // is caught by checking again.
+        this.shared.receiver.register(cx.waker());
+        match this.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let state = self.shared.state.fetch_or(RECEIVER_GONE, Ordering::AcqRel);
+        if state & SENT != 0 {
+            // Drop an unreceived value now rather than whenever the sender's Arc goes.
+            drop(self.shared.value.take());
+        }
+    }
+}
+
+#[test]
+fn async_oneshot_wakes_receiver() {
+    use crate::async_sync::block_on;
+
+    let (tx, rx) = channel();
+    let t = std::thread::spawn(move || tx.send(String::from("hi")).unwrap());
+    assert_eq!(block_on(rx).as_deref(), Ok("hi"));
+    t.join().unwrap();
+
+    let (tx, rx) = channel::<u32>();
+    let t = std::thread::spawn(move || drop(tx));
+    assert_eq!(block_on(rx), Err(RecvError));
+    t.join().unwrap();
+
+    let (tx, mut rx) = channel::<u32>();
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    drop(rx);
+    assert!(tx.is_closed());
+    assert_eq!(tx.send(1), Err(1));
+}
@@ -0,0 +1,205 @@
+use super::waiters::{List, Waiter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+// Set in the state while the queue has waiters; the rest of the state is the number of
+// available permits, shifted up by one.
+const QUEUED: usize = 1;
+
+pub const MAX_PERMITS: usize = usize::MAX >> 1;
+
+/// A fair counting semaphore for tasks, the core of the async locks.
+///
+/// Acquiring takes any number of permits at once. Waiters queue in FIFO order, each in a
+/// [`Waiter`] inside its own [`Acquire`] future, and are handed their permits directly by
+/// [`release`](Self::release), so a waiter can't be overtaken by later arrivals. While anyone
+/// is queued, the state can only change under the queue lock.
+pub struct Permits {
+    state: AtomicUsize,
+    // Waiters with the number of permits they want.
+    queue: Mutex<List<usize>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Queued,
+    Done,
+}
+
+/// Waits for permits from [`Permits::acquire`]. Dropping it gives up its place in the queue,
+/// or hands back the permits if they had already been granted.
+pub struct Acquire<'a> {
+    permits: &'a Permits,
+    waiter: Waiter<usize>,
+    state: State,
+}
+
+impl Permits {
+    pub const fn new(permits: usize) -> Self {
+        assert!(permits <= MAX_PERMITS, "too many permits");
+        Self {
+            state: AtomicUsize::new(permits << 1),
+            queue: Mutex::new(List::new()),
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.state.load(Ordering::Relaxed) >> 1
+    }
+
+    /// Takes `n` permits if they're available and nobody is queued for them.
+    pub fn try_acquire(&self, n: usize) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & QUEUED != 0 || state >> 1 < n {
+                return false;
+            }
+            // Acquire: pairs with the Release of whoever gave the permits back.
+            match self.state.compare_exchange_weak(
+                state,
+                state - (n << 1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(s) => state = s,
+            }
+        }
+    }
+
+    pub fn acquire(&self, n: usize) -> Acquire<'_> {
+        assert!(n <= MAX_PERMITS, "too many permits");
+        Acquire {
+            permits: self,
+            waiter: Waiter::new(n),
+            state: State::Idle,
+        }
+    }
+
+    /// Gives back `n` permits, handing them to queued waiters in order.
+    pub fn release(&self, n: usize) {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & QUEUED == 0 {
+            // Release: what the holder did happens before the next acquire.
+            match self.state.compare_exchange_weak(
+                state,
+                state + (n << 1),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(s) => state = s,
+            }
+        }
+        let mut queue = self.queue.lock().unwrap();
+        let wakers = self.grant(&mut queue, n);
+        drop(queue);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    // Hands the available permits plus `extra` to waiters from the front of the queue for as
+    // long as the front one can be satisfied, and returns the wakers to call once unlocked.
+    fn grant(&self, queue: &mut MutexGuard<'_, List<usize>>, extra: usize) -> Vec<Waker> {
+        let mut available = (self.state.load(Ordering::Relaxed) >> 1) + extra;
+        let mut wakers = Vec::new();
+        while let Some(needed) = queue.front().map(|head| head.data) {
+            if needed > available {
+                break;
+            }
+            available -= needed;
+            wakers.extend(queue.wake_front());
+        }
+        let queued = if queue.is_empty() { 0 } else { QUEUED };
+        // Nobody else changes the state while QUEUED is set, and we hold the queue lock.
+        self.state.store(available << 1 | queued, Ordering::Release);
+        wakers
+    }
+}
+
+impl Future for Acquire<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: the waiter is never moved out; the queue points at it while it's queued.
+        let this = unsafe { self.get_unchecked_mut() };
+        let permits = this.permits;
+        let waiter = &this.waiter;
+        match this.state {
+            State::Idle => {
+                if permits.try_acquire(waiter.data) {
+                    this.state = State::Done;
+                    return Poll::Ready(());
+                }
+                let mut queue = permits.queue.lock().unwrap();
+                waiter.register(cx.waker());
+                let mut state = permits.state.load(Ordering::Relaxed);
+                loop {
+                    if state & QUEUED == 0 && state >> 1 >= waiter.data {
+                        match permits.state.compare_exchange_weak(
+                            state,
+                            state - (waiter.data << 1),
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        ) {
+                            Ok(_) => {
+                                this.state = State::Done;
+                                return Poll::Ready(());
+                            }
+                            Err(s) => state = s,
+                        }
+                        continue;
+                    }
+                    // Setting QUEUED sends every later release through the queue lock.
+                    match permits.state.compare_exchange_weak(
+                        state,
+                        state | QUEUED,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => break,
+                        Err(s) => state = s,
+                    }
+                }
+                unsafe { queue.push_back(waiter) };
+                this.state = State::Queued;
+                Poll::Pending
+            }
+            State::Queued => {
+                if !waiter.poll_woken(cx.waker()) {
+                    return Poll::Pending;
+                }
+                // The granter may still be holding our waker; it's done with the node once
+                // it has let go of the queue lock.
+                drop(permits.queue.lock().unwrap());
+                this.state = State::Done;
+                Poll::Ready(())
+            }
+            State::Done => panic!("Acquire polled after completion"),
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if self.state != State::Queued {
+            return;
+        }
+        let permits = self.permits;
+        let mut queue = permits.queue.lock().unwrap();
+        if self.waiter.is_woken() {
+            // Granted, but we never got to use them.
+            drop(queue);
+            permits.release(self.waiter.data);
+            return;
+        }
+        unsafe { queue.remove(&self.waiter) };
+        // We may have been what held up the waiters behind us.
+        let wakers = permits.grant(&mut queue, 0);
+        drop(queue);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
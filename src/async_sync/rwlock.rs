@@ -0,0 +1,145 @@
+use super::permits::{Permits, MAX_PERMITS};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+
+// A reader takes one permit and a writer takes all of them.
+const MAX_READERS: usize = MAX_PERMITS;
+
+/// A readers-writer lock for tasks.
+///
+/// Readers and writers wait in one FIFO line, so a writer only waits for the readers ahead
+/// of it: readers arriving after it queue behind it instead of starving it. Both guards are
+/// `Send` for a `Send + Sync` value and can be held across an `.await`.
+pub struct RwLock<T> {
+    permits: Permits,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for RwLock<T> where T: Send {}
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    pub const fn new(t: T) -> Self {
+        Self {
+            permits: Permits::new(MAX_READERS),
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.permits.acquire(1).await;
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.permits
+            .try_acquire(1)
+            .then(|| RwLockReadGuard { lock: self })
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.permits.acquire(MAX_READERS).await;
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.permits
+            .try_acquire(MAX_READERS)
+            .then(|| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: readers exclude writers, so no one holds a mutable reference.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.permits.release(1);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we hold every permit.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.permits.release(MAX_READERS);
+    }
+}
+
+#[test]
+fn async_rwlock_queued_writer_holds_off_readers() {
+    use crate::parker::Parker;
+    use std::future::Future;
+    use std::task::{Context, Poll};
+
+    let lock = RwLock::new(0);
+    let parker = Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let first = lock.try_read().unwrap();
+    let second = lock.try_read().unwrap();
+    let mut write = Box::pin(lock.write());
+    assert!(write.as_mut().poll(&mut cx).is_pending());
+    // The writer is next in line, so a new reader waits behind it.
+    assert!(lock.try_read().is_none());
+    let mut read = Box::pin(lock.read());
+    assert!(read.as_mut().poll(&mut cx).is_pending());
+
+    drop(first);
+    assert!(write.as_mut().poll(&mut cx).is_pending());
+    drop(second);
+    let mut guard = match write.as_mut().poll(&mut cx) {
+        Poll::Ready(guard) => guard,
+        Poll::Pending => panic!("writer not woken"),
+    };
+    *guard = 1;
+    assert!(read.as_mut().poll(&mut cx).is_pending());
+    drop(guard);
+    match read.as_mut().poll(&mut cx) {
+        Poll::Ready(guard) => assert_eq!(*guard, 1),
+        Poll::Pending => panic!("reader not woken"),
+    }
+    assert!(lock.try_write().is_some());
+}
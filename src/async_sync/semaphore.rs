@@ -0,0 +1,231 @@
+use super::permits::{Permits, MAX_PERMITS};
+use std::sync::Arc;
+
+/// A counting semaphore for tasks, for capping how many operations run at once.
+///
+/// Waiters are served in FIFO order, including ones asking for several permits at a time,
+/// which hold up everyone behind them until they're satisfied. Put it in an [`Arc`] to get
+/// [`OwnedSemaphorePermit`]s that can be moved into spawned tasks.
+pub struct Semaphore {
+    permits: Permits,
+}
+
+/// Permits taken from a borrowed [`Semaphore`], given back on drop.
+#[must_use = "the permits are given back as soon as this is dropped"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    n: usize,
+}
+
+/// Permits taken from a [`Semaphore`] in an `Arc`, given back on drop. It keeps the semaphore
+/// alive.
+#[must_use = "the permits are given back as soon as this is dropped"]
+pub struct OwnedSemaphorePermit {
+    semaphore: Arc<Semaphore>,
+    n: usize,
+}
+
+impl Semaphore {
+    pub const MAX_PERMITS: usize = MAX_PERMITS;
+
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: Permits::new(permits),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.available()
+    }
+
+    /// Adds `n` new permits, waking waiters they satisfy.
+    pub fn add_permits(&self, n: usize) {
+        assert!(
+            n <= MAX_PERMITS - self.available_permits(),
+            "too many permits"
+        );
+        self.permits.release(n);
+    }
+
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1).await
+    }
+
+    pub async fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        self.permits.acquire(n).await;
+        SemaphorePermit { semaphore: self, n }
+    }
+
+    /// Takes a permit if one is free and no task is waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        self.permits
+            .try_acquire(n)
+            .then(|| SemaphorePermit { semaphore: self, n })
+    }
+
+    pub async fn acquire_owned(self: Arc<Self>) -> OwnedSemaphorePermit {
+        self.acquire_many_owned(1).await
+    }
+
+    pub async fn acquire_many_owned(self: Arc<Self>, n: usize) -> OwnedSemaphorePermit {
+        self.permits.acquire(n).await;
+        OwnedSemaphorePermit { semaphore: self, n }
+    }
+
+    pub fn try_acquire_owned(self: Arc<Self>) -> Option<OwnedSemaphorePermit> {
+        self.try_acquire_many_owned(1)
+    }
+
+    pub fn try_acquire_many_owned(self: Arc<Self>, n: usize) -> Option<OwnedSemaphorePermit> {
+        self.permits
+            .try_acquire(n)
+            .then(|| OwnedSemaphorePermit { semaphore: self, n })
+    }
+}
+
+impl SemaphorePermit<'_> {
+    pub fn num_permits(&self) -> usize {
+        self.n
+    }
+
+    /// Takes `n` of the permits off into a permit of their own, or `None` if there aren't
+    /// that many, so a batch can hand pieces of what it acquired to subtasks that give them
+    /// back as they finish.
+    pub fn split(&mut self, n: usize) -> Option<Self> {
+        self.n = self.n.checked_sub(n)?;
+        Some(Self {
+            semaphore: self.semaphore,
+            n,
+        })
+    }
+
+    /// Takes `other`'s permits into this one.
+    ///
+    /// # Panics
+    ///
+    /// If they're from different semaphores.
+    pub fn merge(&mut self, mut other: Self) {
+        assert!(
+            std::ptr::eq(self.semaphore, other.semaphore),
+            "merging permits from different semaphores"
+        );
+        self.n += std::mem::take(&mut other.n);
+    }
+
+    /// Keeps the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.n = 0;
+    }
+}
+
+impl OwnedSemaphorePermit {
+    pub fn num_permits(&self) -> usize {
+        self.n
+    }
+
+    pub fn semaphore(&self) -> &Arc<Semaphore> {
+        &self.semaphore
+    }
+
+    /// Takes `n` of the permits off into a permit of their own, or `None` if there aren't
+    /// that many.
+    pub fn split(&mut self, n: usize) -> Option<Self> {
+        self.n = self.n.checked_sub(n)?;
+        Some(Self {
+            semaphore: Arc::clone(&self.semaphore),
+            n,
+        })
+    }
+
+    /// Takes `other`'s permits into this one.
+    ///
+    /// # Panics
+    ///
+    /// If they're from different semaphores.
+    pub fn merge(&mut self, mut other: Self) {
+        assert!(
+            Arc::ptr_eq(&self.semaphore, &other.semaphore),
+            "merging permits from different semaphores"
+        );
+        self.n += std::mem::take(&mut other.n);
+    }
+
+    /// Keeps the permits out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.n = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.n != 0 {
+            self.semaphore.permits.release(self.n);
+        }
+    }
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        if self.n != 0 {
+            self.semaphore.permits.release(self.n);
+        }
+    }
+}
+
+#[test]
+fn semaphore_caps_concurrency() {
+    use crate::async_sync::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let semaphore = Arc::new(Semaphore::new(2));
+    let running = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            // Owned permits are 'static, so the whole task can move to another thread.
+            let task = {
+                let (semaphore, running) = (Arc::clone(&semaphore), Arc::clone(&running));
+                async move {
+                    for _ in 0..50 {
+                        let permit = Arc::clone(&semaphore).acquire_owned().await;
+                        assert!(running.fetch_add(1, Ordering::SeqCst) < 2);
+                        std::thread::yield_now();
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        drop(permit);
+                    }
+                }
+            };
+            std::thread::spawn(move || block_on(task))
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    assert_eq!(semaphore.available_permits(), 2);
+
+    let permit = semaphore.try_acquire_many(2).unwrap();
+    assert!(semaphore.try_acquire().is_none());
+    permit.forget();
+    semaphore.add_permits(1);
+    assert_eq!(semaphore.try_acquire().map(|p| p.num_permits()), Some(1));
+
+    // A batch's permits given back piece by piece.
+    semaphore.add_permits(3);
+    let mut batch = Arc::clone(&semaphore).try_acquire_many_owned(4).unwrap();
+    let pieces: Vec<_> = (0..3).map(|_| batch.split(1).unwrap()).collect();
+    assert!(batch.split(2).is_none());
+    assert_eq!(batch.num_permits(), 1);
+    for (i, piece) in pieces.into_iter().enumerate() {
+        drop(piece);
+        assert_eq!(semaphore.available_permits(), i + 1);
+    }
+    let mut rest = semaphore.try_acquire_many(3).unwrap();
+    let piece = rest.split(2).unwrap();
+    rest.merge(piece);
+    assert_eq!(rest.num_permits(), 3);
+    drop((rest, batch));
+    assert_eq!(semaphore.available_permits(), 4);
+}
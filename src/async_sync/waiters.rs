@@ -0,0 +1,131 @@
+use crate::atomic_waker::AtomicWaker;
+use std::cell::UnsafeCell;
+use std::marker::PhantomPinned;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Waker;
+
+/// A task's node in a [`List`], embedded in the future that waits and pinned with it.
+///
+/// Every waiter goes through the same steps: link itself while holding the list's lock,
+/// [`poll_woken`](Self::poll_woken) until it's been woken, and then take the lock once more
+/// before the node can go away, since the waker may still be finishing with it. A future
+/// dropped while linked removes itself under the lock instead.
+pub struct Waiter<D> {
+    pub data: D,
+    waker: AtomicWaker,
+    // Set under the lock once the waiter has been taken off the list and woken.
+    woken: AtomicBool,
+    // Only touched under the lock.
+    links: UnsafeCell<Links<D>>,
+    _pin: PhantomPinned,
+}
+
+struct Links<D> {
+    prev: *const Waiter<D>,
+    next: *const Waiter<D>,
+}
+
+unsafe impl<D: Send> Send for Waiter<D> {}
+unsafe impl<D: Sync> Sync for Waiter<D> {}
+
+/// An intrusive FIFO of [`Waiter`]s, to be kept behind a lock.
+pub struct List<D> {
+    head: *const Waiter<D>,
+    tail: *const Waiter<D>,
+}
+
+// The nodes are only reached through the list while it's locked.
+unsafe impl<D: Sync> Send for List<D> {}
+
+impl<D> Waiter<D> {
+    pub const fn new(data: D) -> Self {
+        Self {
+            data,
+            waker: AtomicWaker::new(),
+            woken: AtomicBool::new(false),
+            links: UnsafeCell::new(Links {
+                prev: ptr::null(),
+                next: ptr::null(),
+            }),
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Whether the waiter has been woken, as of the lock being held.
+    pub fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Relaxed)
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        self.waker.register(waker);
+    }
+
+    /// Registers `waker` unless the waiter has been woken, returning whether it has.
+    pub fn poll_woken(&self, waker: &Waker) -> bool {
+        // Acquire: pairs with the Release in List::wake_front.
+        if self.woken.load(Ordering::Acquire) {
+            return true;
+        }
+        self.waker.register(waker);
+        self.woken.load(Ordering::Acquire)
+    }
+}
+
+impl<D> List<D> {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null(),
+            tail: ptr::null(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn front(&self) -> Option<&Waiter<D>> {
+        unsafe { self.head.as_ref() }
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must be pinned, and stay put until it's woken or removed.
+    pub unsafe fn push_back(&mut self, waiter: &Waiter<D>) {
+        *waiter.links.get() = Links {
+            prev: self.tail,
+            next: ptr::null(),
+        };
+        match self.tail.as_ref() {
+            Some(tail) => (*tail.links.get()).next = waiter,
+            None => self.head = waiter,
+        }
+        self.tail = waiter;
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must be on this list.
+    pub unsafe fn remove(&mut self, waiter: &Waiter<D>) {
+        let Links { prev, next } = *waiter.links.get();
+        match prev.as_ref() {
+            Some(prev) => (*prev.links.get()).next = next,
+            None => self.head = next,
+        }
+        match next.as_ref() {
+            Some(next) => (*next.links.get()).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Takes the front waiter off the list and marks it woken, returning its waker to call
+    /// once the lock is released.
+    pub fn wake_front(&mut self) -> Option<Waker> {
+        let waiter = self.front().expect("waking an empty list") as *const Waiter<D>;
+        // Safety: push_back's caller keeps listed waiters alive.
+        let waiter = unsafe { &*waiter };
+        unsafe { self.remove(waiter) };
+        waiter.woken.store(true, Ordering::Release);
+        waiter.waker.take()
+    }
+}
@@ -0,0 +1,300 @@
+// A 64-bit atomic for every target: std's AtomicU64 where the target has one, and otherwise
+// `emulated::AtomicU64`, with the same methods, so code written against `AtomicU64` from here
+// builds unchanged on the 32-bit targets without 64-bit atomics (32-bit PowerPC and MIPS,
+// ARMv5 and the Cortex-M cores among them).
+//
+// The emulation is a seqlock: a sequence word that's odd while a writer is in, and the value
+// as two 32-bit halves. A load reads the halves between two reads of the sequence and tries
+// again if it changed, so loads never wait on each other and never write; stores and
+// read-modify-writes take the sequence word as a spin lock. The emulation is always compiled,
+// so its tests run on the targets that don't need it too.
+
+/// Whether this target has a native 64-bit atomic: if not, [`AtomicU64`] is the emulated one.
+pub const fn has_atomic_u64() -> bool {
+    cfg!(target_has_atomic = "64")
+}
+
+#[cfg(target_has_atomic = "64")]
+pub use std::sync::atomic::AtomicU64;
+
+#[cfg(not(target_has_atomic = "64"))]
+pub use emulated::AtomicU64;
+
+/// A `u64` made atomic out of 32-bit atomics.
+pub mod emulated {
+    use crate::backoff::Backoff;
+    use std::cell::UnsafeCell;
+    use std::fmt;
+    use std::sync::atomic::{self, AtomicU32, Ordering};
+
+    /// std's `AtomicU64`, less `as_ptr` and the conversions from pointers, built on 32-bit
+    /// atomics.
+    ///
+    /// Every operation is at least as strong as acquire-release, whatever ordering it's
+    /// given: a load synchronizes with the store it reads from. A SeqCst one is fenced on
+    /// both sides, so it takes its place in the single total order too.
+    pub struct AtomicU64 {
+        seq: AtomicU32,
+        value: UnsafeCell<u64>,
+    }
+
+    // Safety: the value is only read and written through `halves`, as atomics, while it's
+    // shared.
+    unsafe impl Send for AtomicU64 {}
+    unsafe impl Sync for AtomicU64 {}
+
+    fn split(v: u64) -> [u32; 2] {
+        let b = v.to_ne_bytes();
+        [
+            u32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+            u32::from_ne_bytes([b[4], b[5], b[6], b[7]]),
+        ]
+    }
+
+    fn join([a, b]: [u32; 2]) -> u64 {
+        let (a, b) = (a.to_ne_bytes(), b.to_ne_bytes());
+        u64::from_ne_bytes([a[0], a[1], a[2], a[3], b[0], b[1], b[2], b[3]])
+    }
+
+    fn fence_if_seqcst(order: Ordering) {
+        if order == Ordering::SeqCst {
+            atomic::fence(Ordering::SeqCst);
+        }
+    }
+
+    impl AtomicU64 {
+        pub const fn new(v: u64) -> Self {
+            Self {
+                seq: AtomicU32::new(0),
+                value: UnsafeCell::new(v),
+            }
+        }
+
+        fn halves(&self) -> &[AtomicU32; 2] {
+            // Safety: a u64 is at least as aligned as a u32, and two of them cover it.
+            unsafe { &*(self.value.get() as *const [AtomicU32; 2]) }
+        }
+
+        // Runs `f` on the value as the only writer, stores what it returns and returns what
+        // was there.
+        fn write(&self, order: Ordering, f: impl FnOnce(u64) -> Option<u64>) -> u64 {
+            fence_if_seqcst(order);
+            let backoff = Backoff::new();
+            let seq = loop {
+                let seq = self.seq.load(Ordering::Relaxed);
+                // Acquire: pairs with the Release of the last writer's exit.
+                if seq.is_multiple_of(2)
+                    && self
+                        .seq
+                        .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    break seq;
+                }
+                backoff.snooze();
+            };
+            // Release: a reader that sees any half we write sees the odd sequence too.
+            atomic::fence(Ordering::Release);
+            let [lo, hi] = self.halves();
+            let old = join([lo.load(Ordering::Relaxed), hi.load(Ordering::Relaxed)]);
+            match f(old) {
+                Some(new) => {
+                    let [l, h] = split(new);
+                    lo.store(l, Ordering::Relaxed);
+                    hi.store(h, Ordering::Relaxed);
+                    self.seq.store(seq.wrapping_add(2), Ordering::Release);
+                }
+                // Nothing changed, so readers that saw `seq` read the same value as before.
+                None => self.seq.store(seq, Ordering::Release),
+            }
+            fence_if_seqcst(order);
+            old
+        }
+
+        pub fn load(&self, order: Ordering) -> u64 {
+            fence_if_seqcst(order);
+            let backoff = Backoff::new();
+            let [lo, hi] = self.halves();
+            loop {
+                // Acquire: pairs with the Release of the writer's exit, so we see its halves.
+                let seq = self.seq.load(Ordering::Acquire);
+                if seq.is_multiple_of(2) {
+                    let v = join([lo.load(Ordering::Relaxed), hi.load(Ordering::Relaxed)]);
+                    // Acquire: the halves are read before the sequence is read again.
+                    atomic::fence(Ordering::Acquire);
+                    if self.seq.load(Ordering::Relaxed) == seq {
+                        fence_if_seqcst(order);
+                        return v;
+                    }
+                }
+                backoff.snooze();
+            }
+        }
+
+        pub fn store(&self, v: u64, order: Ordering) {
+            self.write(order, |_| Some(v));
+        }
+
+        pub fn swap(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |_| Some(v))
+        }
+
+        pub fn compare_exchange(
+            &self,
+            current: u64,
+            new: u64,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<u64, u64> {
+            let order = if failure == Ordering::SeqCst {
+                failure
+            } else {
+                success
+            };
+            let old = self.write(order, |v| (v == current).then_some(new));
+            if old == current {
+                Ok(old)
+            } else {
+                Err(old)
+            }
+        }
+
+        /// Never fails spuriously; it's [`compare_exchange`](Self::compare_exchange).
+        pub fn compare_exchange_weak(
+            &self,
+            current: u64,
+            new: u64,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<u64, u64> {
+            self.compare_exchange(current, new, success, failure)
+        }
+
+        pub fn fetch_add(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old.wrapping_add(v)))
+        }
+
+        pub fn fetch_sub(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old.wrapping_sub(v)))
+        }
+
+        pub fn fetch_and(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old & v))
+        }
+
+        pub fn fetch_nand(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(!(old & v)))
+        }
+
+        pub fn fetch_or(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old | v))
+        }
+
+        pub fn fetch_xor(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old ^ v))
+        }
+
+        pub fn fetch_max(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old.max(v)))
+        }
+
+        pub fn fetch_min(&self, v: u64, order: Ordering) -> u64 {
+            self.write(order, |old| Some(old.min(v)))
+        }
+
+        /// A CAS loop around `f`, like std's; `f` isn't run with the write lock held, so it
+        /// may be called more than once.
+        pub fn fetch_update(
+            &self,
+            set_order: Ordering,
+            fetch_order: Ordering,
+            mut f: impl FnMut(u64) -> Option<u64>,
+        ) -> Result<u64, u64> {
+            let mut prev = self.load(fetch_order);
+            while let Some(next) = f(prev) {
+                match self.compare_exchange(prev, next, set_order, fetch_order) {
+                    Ok(v) => return Ok(v),
+                    Err(v) => prev = v,
+                }
+            }
+            Err(prev)
+        }
+
+        pub fn get_mut(&mut self) -> &mut u64 {
+            self.value.get_mut()
+        }
+
+        pub fn into_inner(self) -> u64 {
+            self.value.into_inner()
+        }
+    }
+
+    impl Default for AtomicU64 {
+        fn default() -> Self {
+            Self::new(0)
+        }
+    }
+
+    impl From<u64> for AtomicU64 {
+        fn from(v: u64) -> Self {
+            Self::new(v)
+        }
+    }
+
+    impl fmt::Debug for AtomicU64 {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(&self.load(Ordering::Relaxed), f)
+        }
+    }
+}
+
+// Writers store values whose halves match, so a torn read would show as halves that don't.
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn emulated_atomic_u64_never_tears() {
+    use std::sync::atomic::Ordering;
+
+    let n = if cfg!(miri) { 20 } else { 20_000 };
+    let both = |x: u32| u64::from(x) << 32 | u64::from(x);
+    let a = emulated::AtomicU64::new(0);
+    std::thread::scope(|s| {
+        for t in 0..2u32 {
+            let a = &a;
+            s.spawn(move || {
+                for i in 0..n {
+                    a.store(both(t * n + i), Ordering::Release);
+                }
+            });
+        }
+        for _ in 0..n {
+            let v = a.load(Ordering::Acquire);
+            assert_eq!(v >> 32, v & u64::from(u32::MAX), "torn read {:#x}", v);
+        }
+    });
+
+    // Counting across the carry from the low half into the high one.
+    let start = u64::from(u32::MAX) - 100;
+    let a = emulated::AtomicU64::new(start);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    a.fetch_add(1, Ordering::AcqRel);
+                }
+            });
+        }
+    });
+    assert_eq!(a.load(Ordering::Relaxed), start + 4 * u64::from(n));
+    assert_eq!(
+        a.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst),
+        Err(start + 4 * u64::from(n))
+    );
+    assert_eq!(a.swap(u64::MAX, Ordering::SeqCst), start + 4 * u64::from(n));
+    assert_eq!(
+        a.fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| v.checked_add(1)),
+        Err(u64::MAX)
+    );
+    assert_eq!(a.fetch_and(1 << 40, Ordering::Relaxed), u64::MAX);
+    assert_eq!(format!("{:?}", a), (1u64 << 40).to_string());
+    assert_eq!(has_atomic_u64(), cfg!(target_has_atomic = "64"));
+}
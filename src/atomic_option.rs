@@ -0,0 +1,97 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// An `Option<T>` that can be filled and emptied atomically, holding its value boxed.
+///
+/// There's no way to look at the value in place, only to move it in or out, so a value never
+/// has to outlive the pointer that held it and no reclamation scheme is needed.
+pub struct AtomicOption<T> {
+    ptr: AtomicPtr<T>,
+}
+
+unsafe impl<T: Send> Send for AtomicOption<T> {}
+unsafe impl<T: Send> Sync for AtomicOption<T> {}
+
+fn into_ptr<T>(value: Option<T>) -> *mut T {
+    value.map_or(ptr::null_mut(), |v| Box::into_raw(Box::new(v)))
+}
+
+// Safety: `ptr` must be null or a box that nobody else owns.
+unsafe fn from_ptr<T>(ptr: *mut T) -> Option<T> {
+    (!ptr.is_null()).then(|| *Box::from_raw(ptr))
+}
+
+impl<T> AtomicOption<T> {
+    pub const fn none() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn new(value: Option<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(into_ptr(value)),
+        }
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn swap(&self, value: Option<T>) -> Option<T> {
+        // AcqRel: releases the new value to whoever takes it, acquires the old one.
+        unsafe { from_ptr(self.ptr.swap(into_ptr(value), Ordering::AcqRel)) }
+    }
+
+    pub fn take(&self) -> Option<T> {
+        if self.ptr.load(Ordering::Relaxed).is_null() {
+            return None;
+        }
+        self.swap(None)
+    }
+
+    /// Stores `value` if the option is empty, or hands it back if it isn't.
+    pub fn try_put(&self, value: T) -> Result<(), T> {
+        let new = Box::into_raw(Box::new(value));
+        match self
+            .ptr
+            .compare_exchange(ptr::null_mut(), new, Ordering::Release, Ordering::Relaxed)
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(*unsafe { Box::from_raw(new) }),
+        }
+    }
+
+    /// Whether there's a value right now; another thread may change that at any time.
+    pub fn is_some(&self) -> bool {
+        !self.ptr.load(Ordering::Relaxed).is_null()
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        let ptr = std::mem::replace(self.ptr.get_mut(), ptr::null_mut());
+        unsafe { from_ptr(ptr) }
+    }
+}
+
+impl<T> Default for AtomicOption<T> {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl<T> Drop for AtomicOption<T> {
+    fn drop(&mut self) {
+        drop(unsafe { from_ptr(*self.ptr.get_mut()) });
+    }
+}
+
+#[test]
+fn atomic_option_moves_values() {
+    let o = AtomicOption::none();
+    assert_eq!(o.try_put(1), Ok(()));
+    assert_eq!(o.try_put(2), Err(2));
+    assert!(o.is_some());
+    assert_eq!(o.swap(Some(3)), Some(1));
+    assert_eq!(o.take(), Some(3));
+    assert_eq!(o.take(), None);
+    let o = AtomicOption::new(Some(String::from("dropped with the option")));
+    assert!(o.is_some());
+    assert_eq!(AtomicOption::new(Some(4)).into_inner(), Some(4));
+}
@@ -0,0 +1,116 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Waker;
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 1;
+const WAKING: usize = 2;
+
+/// Holds the waker of the one task waiting on something, so that whoever makes it ready can
+/// wake it.
+///
+/// [`register`](Self::register) is meant to be called by that one task each time it's about
+/// to return `Pending`, and replaces the waker it registered last time; [`wake`](Self::wake)
+/// can come from any number of threads. A wake that races with a register is never lost:
+/// the registering task wakes itself instead.
+pub struct AtomicWaker {
+    // REGISTERING while a register is writing the waker, WAKING while a wake is taking it or
+    // has found a register in progress.
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// Only whoever moves the state out of WAITING touches the waker.
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        // Acquire: a waker written by an earlier register is ours to read or replace.
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|state| state)
+        {
+            WAITING => {
+                let slot = unsafe { &mut *self.waker.get() };
+                if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+                    *slot = Some(waker.clone());
+                }
+                // Release: publishes the waker to the next wake.
+                if let Err(state) = self.state.compare_exchange(
+                    REGISTERING,
+                    WAITING,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    // A wake came in while we held the slot and couldn't take the waker, so
+                    // the wake is ours to deliver.
+                    debug_assert_eq!(state, REGISTERING | WAKING);
+                    let waker = slot.take();
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            // A wake is taking the old waker right now; it can't see the new one, so wake it
+            // here instead.
+            WAKING => waker.wake_by_ref(),
+            // Another register is in progress, which only a caller breaking the one-task rule
+            // can cause.
+            state => debug_assert!(state == REGISTERING || state == REGISTERING | WAKING),
+        }
+    }
+
+    /// Wakes the registered task, if any, and clears the registration.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    /// Takes the registered waker without waking it.
+    pub fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                // Release: a register that comes after us sees the slot emptied.
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            // Either a register will see our WAKING bit and wake its task itself, or another
+            // wake already has the waker.
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn atomic_waker_wakes_last_registered() {
+    use crate::parker::Parker;
+    let first = Parker::new();
+    let second = Parker::new();
+    let w = AtomicWaker::new();
+    w.register(&first.unparker().waker());
+    w.register(&second.unparker().waker());
+    std::thread::scope(|s| {
+        s.spawn(|| w.wake());
+        second.park();
+    });
+    // The registration was used up.
+    assert!(w.take().is_none());
+}
@@ -0,0 +1,195 @@
+use crate::cache_padded::CachePadded;
+use crate::sync_shim::yield_now;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Steps up to here spin 2^step times; snooze yields past it.
+const SPIN_LIMIT: u32 = 6;
+// After this many steps, is_completed says it's time to block instead.
+const YIELD_LIMIT: u32 = 10;
+
+// Under loom each spin is a yield to the model checker, and one per step is plenty.
+fn spins(step: u32) -> u32 {
+    if cfg!(loom) {
+        1
+    } else {
+        1 << step
+    }
+}
+
+/// Exponential backoff for the retry loop of a CAS or a spin-wait.
+///
+/// Call [`spin`](Self::spin) after losing a CAS to another thread that's making progress, and
+/// [`snooze`](Self::snooze) while waiting for another thread to do something, which yields
+/// the processor once spinning has gone on long enough. Once
+/// [`is_completed`](Self::is_completed), the wait has been long enough that blocking on a
+/// parker or an [`EventCount`](crate::event_count::EventCount) beats backing off further.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    step: Cell<u32>,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self { step: Cell::new(0) }
+    }
+
+    /// Starts over, as after the contended operation finally went through.
+    pub fn reset(&self) {
+        self.step.set(0);
+    }
+
+    /// Spins for a while, twice as long as last time up to a limit. Never yields.
+    pub fn spin(&self) {
+        let step = self.step.get();
+        crate::cpu::pause(spins(step.min(SPIN_LIMIT)));
+        if step <= SPIN_LIMIT {
+            self.step.set(step + 1);
+        }
+    }
+
+    /// Spins like [`spin`](Self::spin) at first, then yields the thread.
+    pub fn snooze(&self) {
+        let step = self.step.get();
+        if step <= SPIN_LIMIT {
+            crate::cpu::pause(spins(step));
+        } else {
+            yield_now();
+        }
+        if step <= YIELD_LIMIT {
+            self.step.set(step + 1);
+        }
+    }
+
+    /// [`snooze`](Self::snooze) for a wait on `word` to stop holding `value`: the spinning
+    /// steps relax the way [`cpu::relax_strategy`](crate::cpu::relax_strategy) picks for this
+    /// processor, which on aarch64 sleeps in WFE until the word's written.
+    pub fn snooze_while_eq(&self, word: &AtomicU32, value: u32) {
+        let step = self.step.get();
+        if step <= SPIN_LIMIT {
+            crate::cpu::wait_while_eq(word, value, spins(step));
+        } else {
+            yield_now();
+        }
+        if step <= YIELD_LIMIT {
+            self.step.set(step + 1);
+        }
+    }
+
+    /// Whether snoozing has gone on long enough that the caller should block instead.
+    pub fn is_completed(&self) -> bool {
+        self.step.get() > YIELD_LIMIT
+    }
+
+    /// How many steps it has backed off since it was made or reset, counting up to where
+    /// [`is_completed`](Self::is_completed) says to block.
+    pub fn steps(&self) -> u32 {
+        self.step.get()
+    }
+}
+
+// How long to spin before blocking, learned per lock the way glibc's adaptive mutexes learn
+// it: each wait that got the lock by spinning pulls a running average toward the steps it
+// took, each that gave up and blocked decays it, and the next wait is allowed twice the
+// average and a little more. A lock held for short stretches keeps spinning; one held across
+// system calls soon goes straight to sleep, without a spin of a full Backoff every time.
+//
+// The averages live in a table of padded words hashed by the lock's address, so the lock
+// itself stays one word, and hot locks don't false-share their tuning. Locks that hash to
+// the same slot share an average, which costs some accuracy and nothing else.
+
+const TUNING_SLOTS: usize = 64;
+// Averages are kept in sixteenths of a step, so a decay by an eighth still moves them.
+const ONE_STEP: u32 = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNTUNED: CachePadded<AtomicU32> =
+    CachePadded::new(AtomicU32::new(YIELD_LIMIT / 2 * ONE_STEP));
+static TUNING: [CachePadded<AtomicU32>; TUNING_SLOTS] = [UNTUNED; TUNING_SLOTS];
+
+/// What a lock has learned about how long waiting for it by spinning pays off, for the locks
+/// that spin with a [`Backoff`] before they block.
+#[derive(Debug, Clone, Copy)]
+pub struct SpinTuning {
+    average: &'static AtomicU32,
+}
+
+impl SpinTuning {
+    /// The tuning of the lock at `lock`'s address.
+    pub fn of<T: ?Sized>(lock: &T) -> Self {
+        let addr = lock as *const T as *const u8 as usize;
+        // Fibonacci hashing: the top bits of the product depend on all of the address.
+        let slot = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            >> (64 - TUNING_SLOTS.trailing_zeros());
+        Self {
+            average: &TUNING[slot as usize % TUNING_SLOTS],
+        }
+    }
+
+    /// How many [`Backoff`] steps to try before blocking: never fewer than two, so a lock
+    /// that's gone to sleeping can find out spinning pays again, and never more than it
+    /// takes the backoff to complete.
+    pub fn budget(&self) -> u32 {
+        // Rounded, since the eighths the average moves by stop short of where it's heading.
+        let average = (self.average.load(Ordering::Relaxed) + ONE_STEP / 2) / ONE_STEP;
+        (average * 2 + 2).min(YIELD_LIMIT + 1)
+    }
+
+    /// Feeds back how a wait went: `steps` taken, and whether spinning got the lock.
+    pub fn record(&self, steps: u32, acquired: bool) {
+        // A lost update only loses one sample, so there's no need for a CAS.
+        let average = self.average.load(Ordering::Relaxed);
+        let average = if acquired {
+            let target = steps.min(YIELD_LIMIT + 1) * ONE_STEP;
+            (average as i32 + (target as i32 - average as i32) / 8) as u32
+        } else {
+            average - average / 8
+        };
+        self.average.store(average, Ordering::Relaxed);
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn backoff_completes_only_by_snoozing() {
+    let backoff = Backoff::new();
+    for _ in 0..100 {
+        backoff.spin();
+    }
+    // Spinning alone never says to block; it's meant for contention that clears itself.
+    assert!(!backoff.is_completed());
+    while !backoff.is_completed() {
+        backoff.snooze();
+    }
+    assert_eq!(backoff.step.get(), YIELD_LIMIT + 1);
+    backoff.reset();
+    assert!(!backoff.is_completed());
+
+    let word = AtomicU32::new(0);
+    while !backoff.is_completed() {
+        backoff.snooze_while_eq(&word, 0);
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn spin_tuning_follows_what_waits_took() {
+    // Its own slot, so the tests' locks don't move it meanwhile.
+    static AVERAGE: AtomicU32 = AtomicU32::new(YIELD_LIMIT / 2 * ONE_STEP);
+    let tuning = SpinTuning { average: &AVERAGE };
+    assert_eq!(tuning.budget(), YIELD_LIMIT + 1);
+
+    // Waits that keep giving up teach it to barely spin at all.
+    for _ in 0..50 {
+        tuning.record(tuning.budget(), false);
+    }
+    assert_eq!(tuning.budget(), 2);
+    // And a run of waits that got the lock after a few steps teaches it to spin again.
+    for _ in 0..50 {
+        tuning.record(4, true);
+    }
+    assert_eq!(tuning.budget(), 4 * 2 + 2);
+
+    let a = SpinTuning::of(&0u64);
+    assert!((2..=YIELD_LIMIT + 1).contains(&a.budget()));
+}
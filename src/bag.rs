@@ -0,0 +1,161 @@
+use crate::cache_padded::CachePadded;
+use crate::stack::TreiberStack;
+use crate::thread_id;
+use crate::topology::{self, Topology};
+
+// Kept in a CachePadded so threads pushing to neighbouring stacks don't false-share their
+// heads.
+struct Shard<T> {
+    stack: TreiberStack<T>,
+}
+
+// Live threads have dense IDs, so the first few threads land on distinct shards.
+fn thread_index() -> usize {
+    thread_id::current().unwrap_or(0)
+}
+
+/// An unordered concurrent collection.
+///
+/// Each thread adds to and takes from its own lock-free stack, and only goes looking in
+/// other threads' stacks once its own is empty. With no order to keep, producers and consumers
+/// on different threads rarely touch the same cache line.
+///
+/// A bag built [`with_topology`](Self::with_topology) gives each NUMA node its own shards and
+/// steals from the thread's own node before going to another.
+pub struct Bag<T> {
+    shards: Box<[CachePadded<Shard<T>>]>,
+    // The node of each core, by core number; empty unless the bag is laid out per node.
+    node_of_core: Box<[usize]>,
+    // How many shards each node has, laid out node by node.
+    per_node: usize,
+}
+
+impl<T: Send + 'static> Bag<T> {
+    pub fn new() -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, |n| n.get()) * 2;
+        Self::with_shards(shards)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        let shards = shards.max(1);
+        Self {
+            shards: Self::make_shards(shards),
+            node_of_core: Box::new([]),
+            per_node: shards,
+        }
+    }
+
+    /// A bag with `shards_per_node` shards on each of `topology`'s nodes. A thread's home
+    /// shard is on the node it's running on when it adds or takes, so a thread that isn't
+    /// pinned may find its items on another node after it moves.
+    pub fn with_topology(topology: &Topology, shards_per_node: usize) -> Self {
+        let per_node = shards_per_node.max(1);
+        let nodes = topology.nodes();
+        let cores = nodes.iter().flat_map(|n| n.cores()).copied();
+        let mut node_of_core = vec![0; cores.max().map_or(0, |c| c + 1)];
+        for (i, node) in nodes.iter().enumerate() {
+            for &core in node.cores() {
+                node_of_core[core] = i;
+            }
+        }
+        Self {
+            shards: Self::make_shards(nodes.len() * per_node),
+            node_of_core: node_of_core.into_boxed_slice(),
+            per_node,
+        }
+    }
+
+    fn make_shards(n: usize) -> Box<[CachePadded<Shard<T>>]> {
+        (0..n)
+            .map(|_| Shard {
+                stack: TreiberStack::new(),
+            })
+            .map(CachePadded::new)
+            .collect()
+    }
+
+    // The first of the current node's shards, and this thread's shard among them.
+    fn home(&self) -> (usize, usize) {
+        let node = if self.node_of_core.is_empty() {
+            0
+        } else {
+            topology::current_cpu()
+                .and_then(|core| self.node_of_core.get(core).copied())
+                .unwrap_or(0)
+        };
+        (node * self.per_node, thread_index() % self.per_node)
+    }
+
+    pub fn add(&self, item: T) {
+        let (node, shard) = self.home();
+        self.shards[node + shard].stack.push(item);
+    }
+
+    /// Takes some item, preferring this thread's own, then its node's, or returns None if
+    /// the bag looked empty everywhere.
+    pub fn take(&self) -> Option<T> {
+        let (node, shard) = self.home();
+        let per_node = self.per_node;
+        let n = self.shards.len();
+        let local = (0..per_node).map(|i| node + (shard + i) % per_node);
+        let remote = (per_node..n).map(|i| (node + i) % n);
+        local.chain(remote).find_map(|i| self.shards[i].stack.pop())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.stack.is_empty())
+    }
+}
+
+impl<T: Send + 'static> Default for Bag<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn bag_every_item_once() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    let n = if cfg!(miri) { 100 } else { 2000 };
+    let bag = &Bag::with_shards(4);
+    let seen = &(0..2 * n)
+        .map(|_| AtomicBool::new(false))
+        .collect::<Vec<_>>();
+    // Two threads only add and two only take, so nearly everything taken is stolen.
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                if t < 2 {
+                    for i in 0..n {
+                        bag.add(t * n + i);
+                    }
+                } else {
+                    for _ in 0..n / 2 {
+                        if let Some(v) = bag.take() {
+                            assert!(!seen[v].swap(true, Ordering::Relaxed));
+                        }
+                    }
+                }
+            });
+        }
+    });
+    while let Some(v) = bag.take() {
+        assert!(!seen[v].swap(true, Ordering::Relaxed));
+    }
+    assert!(bag.is_empty());
+    assert!(seen.iter().all(|s| s.load(Ordering::Relaxed)));
+}
+
+#[test]
+fn bag_with_topology_prefers_its_node() {
+    let topology = Topology::from_nodes(vec![vec![0], vec![1]]);
+    let bag = Bag::with_topology(&topology, 2);
+    assert_eq!(bag.shards.len(), 4);
+    let (node, shard) = bag.home();
+    bag.shards[(node + 2) % 4].stack.push(1);
+    bag.shards[node + (shard + 1) % 2].stack.push(2);
+    // The other shard on this node comes before the other node's.
+    assert_eq!(bag.take(), Some(2));
+    assert_eq!(bag.take(), Some(1));
+    assert!(bag.take().is_none());
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// std's Barrier, plus timed waits. A participant that's stuck, or gone, would otherwise hang
+// everyone at the barrier, this round and every round after; here a wait that times out
+// breaks the barrier instead, the way Java's CyclicBarrier does, and every wait on it, the
+// ones already waiting and the ones yet to come, fails with BrokenBarrier, so each thread
+// finds out and can give up on the phase.
+//
+// Broken stays broken: which of the waiters' work went through that round can't be known
+// from here, so starting over is a new barrier's job.
+
+struct State {
+    arrived: usize,
+    // Bumped by each round's leader.
+    generation: u64,
+    broken: bool,
+}
+
+/// Lets a fixed number of threads wait until all of them have reached the same point, like
+/// `std::sync::Barrier`, with timed waits that break it.
+pub struct Barrier {
+    n: usize,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+/// Returned by [`Barrier::wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+/// A wait on the barrier timed out, this one or another thread's, so the round can't
+/// complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrokenBarrier;
+
+impl fmt::Display for BrokenBarrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("waiting on a broken barrier")
+    }
+}
+
+impl Error for BrokenBarrier {}
+
+impl Barrier {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: Mutex::new(State {
+                arrived: 0,
+                generation: 0,
+                broken: false,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until `n` threads have arrived in this round. The last one to arrive is the
+    /// leader. Fails if the barrier is, or gets, broken.
+    pub fn wait(&self) -> Result<BarrierWaitResult, BrokenBarrier> {
+        self.wait_until(None)
+    }
+
+    /// Like [`wait`](Self::wait), but breaks the barrier if the round hasn't completed
+    /// after `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<BarrierWaitResult, BrokenBarrier> {
+        self.wait_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`wait`](Self::wait), but breaks the barrier if the round hasn't completed by
+    /// `deadline`.
+    pub fn wait_deadline(&self, deadline: Instant) -> Result<BarrierWaitResult, BrokenBarrier> {
+        self.wait_until(Some(deadline))
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.lock().broken
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Nothing panics with it held.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // No deadline means waiting forever.
+    fn wait_until(&self, deadline: Option<Instant>) -> Result<BarrierWaitResult, BrokenBarrier> {
+        let mut state = self.lock();
+        if state.broken {
+            return Err(BrokenBarrier);
+        }
+        state.arrived += 1;
+        if state.arrived >= self.n {
+            state.arrived = 0;
+            state.generation += 1;
+            drop(state);
+            self.released.notify_all();
+            return Ok(BarrierWaitResult { leader: true });
+        }
+        let generation = state.generation;
+        while state.generation == generation {
+            if state.broken {
+                return Err(BrokenBarrier);
+            }
+            state = match deadline {
+                None => self.released.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.broken = true;
+                        drop(state);
+                        self.released.notify_all();
+                        return Err(BrokenBarrier);
+                    }
+                    self.released
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        Ok(BarrierWaitResult { leader: false })
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("n", &self.n)
+            .field("broken", &self.is_broken())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn barrier_breaks_when_a_wait_times_out() {
+    let barrier = Barrier::new(3);
+    let leaders = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..3)
+            .map(|_| {
+                s.spawn(|| {
+                    (0..5)
+                        .map(|_| barrier.wait().unwrap().is_leader() as usize)
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .sum::<usize>()
+    });
+    assert_eq!(leaders, 5);
+
+    // The third participant never shows up: the timed wait breaks the barrier, and the
+    // untimed one already waiting gets out too.
+    std::thread::scope(|s| {
+        let untimed = s.spawn(|| barrier.wait());
+        let timed = barrier.wait_timeout(Duration::from_millis(20));
+        assert_eq!(timed, Err(BrokenBarrier));
+        assert_eq!(untimed.join().unwrap(), Err(BrokenBarrier));
+    });
+    assert!(barrier.is_broken());
+    assert_eq!(barrier.wait(), Err(BrokenBarrier));
+}
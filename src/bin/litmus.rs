@@ -0,0 +1,125 @@
+//! Runs the crate's litmus tests and prints how often each outcome came up.
+//!
+//! `cargo run --release --bin litmus -- [TEST...] [--iters N] [--threads N]`
+//!
+//! A TEST picks the test of that name, or a family of them: `sb` runs every `sb-*` test. With
+//! none, every test runs. `--threads` is how many threads to keep busy; a two-thread test with
+//! `--threads 4` runs as two copies at once, splitting the iterations between them. `--list`
+//! lists the tests. Exits with 1 if any test shows an outcome its model forbids.
+
+use atomics::litmus::{self, Litmus, Report};
+use std::process;
+use std::thread;
+
+const USAGE: &str = "usage: litmus [--list] [TEST...] [--iters N] [--threads N]";
+
+struct Args {
+    list: bool,
+    tests: Vec<String>,
+    iterations: usize,
+    threads: usize,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("litmus: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+// Takes counts like 100000, 1e6 or 2.5e5.
+fn parse_count(flag: &str, value: Option<String>) -> usize {
+    let value = value.unwrap_or_else(|| usage_error(&format!("{} needs a value", flag)));
+    match value.parse::<f64>() {
+        Ok(n) if n >= 1.0 && n.fract() == 0.0 && n <= usize::MAX as f64 => n as usize,
+        _ => usage_error(&format!("{} takes a positive count, not {:?}", flag, value)),
+    }
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        list: false,
+        tests: Vec::new(),
+        iterations: 100_000,
+        threads: 0,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--list" => args.list = true,
+            "--iters" => args.iterations = parse_count("--iters", argv.next()),
+            "--threads" => args.threads = parse_count("--threads", argv.next()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            flag if flag.starts_with('-') => usage_error(&format!("unknown flag {}", flag)),
+            _ => args.tests.push(arg),
+        }
+    }
+    args
+}
+
+fn selected(test: &Litmus, names: &[String]) -> bool {
+    names.is_empty()
+        || names.iter().any(|n| {
+            test.name() == n
+                || test
+                    .name()
+                    .strip_prefix(n.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+        })
+}
+
+// Runs enough copies of `test` at once to keep `threads` threads busy.
+fn run(test: &Litmus, iterations: usize, threads: usize) -> Report {
+    let copies = (threads / test.num_threads().max(1)).clamp(1, iterations);
+    let mut reports = thread::scope(|s| {
+        let handles: Vec<_> = (0..copies)
+            .map(|c| {
+                // The first copies take the remainder.
+                let share = iterations / copies + usize::from(c < iterations % copies);
+                s.spawn(move || test.run(share))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    })
+    .into_iter();
+    let mut total = reports.next().unwrap();
+    for report in reports {
+        total.merge(report);
+    }
+    total
+}
+
+fn main() {
+    let args = parse_args();
+    let tests: Vec<Litmus> = litmus::suite()
+        .into_iter()
+        .filter(|t| selected(t, &args.tests))
+        .collect();
+    if tests.is_empty() {
+        usage_error(&format!("no test matches {}", args.tests.join(" ")));
+    }
+    if args.list {
+        for test in &tests {
+            println!(
+                "{:<16} {} threads  {}",
+                test.name(),
+                test.num_threads(),
+                test.description()
+            );
+        }
+        return;
+    }
+    let mut failed = false;
+    for test in &tests {
+        let report = run(test, args.iterations, args.threads);
+        println!("{}", report);
+        failed |= !report.passed();
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,815 @@
+//! Hammers one of the crate's primitives from many threads and checks it held together.
+//!
+//! `cargo run --release --bin stress -- --primitive NAME [--threads N] [--ops N]
+//! [--duration D] [--mix P] [--perf]`
+//!
+//! Every thread runs a random mix of the primitive's operations, P percent of them writes
+//! (locking to write, pushing, inserting) and the rest reads (reading under the lock, popping,
+//! looking up), until `--ops` operations have run between them or `--duration` (like `500ms`,
+//! `60s`, `10m` or `2h`) is up, whichever comes first. Then it prints the throughput and checks
+//! the invariants: nothing lost or duplicated, FIFO per producer where there's an order to
+//! keep, no torn state under a lock. `--list` lists the primitives. Exits with 1 if an
+//! invariant doesn't hold, which for `aba-naive` it's meant not to: run it and `aba-tagged`
+//! or `aba-hazard` with the same arguments to see ABA lose or duplicate values in the one and
+//! not the others. It takes more threads than cores to show up on a single core.
+//!
+//! `--soak` runs for all of `--duration`, which can be hours, stopping every thread at each
+//! `--check-every` (10s by default) to check the invariants so far: queues and stacks are
+//! drained to see every value is still reachable, maps looked through for every key. It also
+//! tracks the resident set, and fails once it has grown by more than `--max-rss-growth` MiB
+//! (256 by default) since the first check, which is how a slow leak in the epoch reclamation
+//! shows up. The resident set is only known on Linux.
+//!
+//! `--perf` also counts cycles, instructions, cache misses and branch misses across the
+//! threads, on Linux where there's a PMU to count them, and prints them per operation, so two
+//! primitives with the same throughput can be told apart by their cache traffic. Where there's
+//! no PMU, as in most VMs, it counts CPU time alone. A soak's counts take in its checks.
+
+use atomics::aba::{self, Guard};
+use atomics::array_queue::ArrayQueue;
+use atomics::flat_combining::FcLock;
+use atomics::hashmap::ConcurrentHashMap;
+use atomics::mpmc;
+use atomics::perf::{self, Counters, Event};
+use atomics::rwlock::RwLock;
+use atomics::seg_queue::SegQueue;
+use atomics::skiplist::SkipMap;
+use atomics::stack::TreiberStack;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: stress [--list] --primitive NAME [--threads N] [--ops N] \
+                     [--duration D] [--mix P] [--perf] \
+                     [--soak [--check-every D] [--max-rss-growth MIB]]";
+
+// Operations a thread claims at a time, and runs between looks at the clock.
+const BATCH: u64 = 1024;
+// Capacity of the bounded queue and channel.
+const CAPACITY: usize = 1024;
+// Nodes in the ABA stacks' pools: few, so a popped node comes back soon enough that a
+// stalled pop can still be holding it.
+const ABA_CAPACITY: usize = 16;
+// Keys each thread has of its own in the hash map.
+const KEYS_PER_THREAD: u64 = 1024;
+
+struct Args {
+    list: bool,
+    primitive: String,
+    threads: usize,
+    ops: Option<u64>,
+    duration: Option<Duration>,
+    mix: u64,
+    perf: bool,
+    soak: bool,
+    check_every: Duration,
+    max_rss_growth: u64,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("stress: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn value(flag: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| usage_error(&format!("{} needs a value", flag)))
+}
+
+// Takes counts like 100000, 10_000_000 or 1e6.
+fn parse_count(flag: &str, value: String) -> u64 {
+    match value.replace('_', "").parse::<f64>() {
+        Ok(n) if n >= 1.0 && n.fract() == 0.0 && n <= u64::MAX as f64 => n as u64,
+        _ => usage_error(&format!("{} takes a positive count, not {:?}", flag, value)),
+    }
+}
+
+// Takes durations like 500ms, 60s, 10m or 2h; a bare number is seconds.
+fn parse_duration(flag: &str, value: String) -> Duration {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (n, unit) = value.split_at(split);
+    let scale = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => usage_error(&format!(
+            "{} takes a duration like 60s, not {:?}",
+            flag, value
+        )),
+    };
+    match n.parse::<f64>() {
+        Ok(n) if n > 0.0 => Duration::from_secs_f64(n * scale),
+        _ => usage_error(&format!(
+            "{} takes a duration like 60s, not {:?}",
+            flag, value
+        )),
+    }
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        list: false,
+        primitive: String::new(),
+        threads: thread::available_parallelism().map_or(4, |n| n.get()),
+        ops: None,
+        duration: None,
+        mix: 50,
+        perf: false,
+        soak: false,
+        check_every: Duration::from_secs(10),
+        max_rss_growth: 256,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--list" => args.list = true,
+            "--primitive" => args.primitive = value(&arg, argv.next()),
+            "--threads" => args.threads = parse_count(&arg, value(&arg, argv.next())) as usize,
+            "--ops" => args.ops = Some(parse_count(&arg, value(&arg, argv.next()))),
+            "--duration" => args.duration = Some(parse_duration(&arg, value(&arg, argv.next()))),
+            "--mix" => {
+                args.mix = match value(&arg, argv.next()).parse() {
+                    Ok(p) if p <= 100 => p,
+                    _ => usage_error("--mix takes a percentage from 0 to 100"),
+                }
+            }
+            "--perf" => args.perf = true,
+            "--soak" => args.soak = true,
+            "--check-every" => args.check_every = parse_duration(&arg, value(&arg, argv.next())),
+            "--max-rss-growth" => args.max_rss_growth = parse_count(&arg, value(&arg, argv.next())),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => usage_error(&format!("unexpected argument {}", arg)),
+        }
+    }
+    if args.soak && (args.ops.is_some() || args.duration.is_none()) {
+        usage_error("--soak runs for a --duration, not a number of --ops");
+    }
+    if args.ops.is_none() && args.duration.is_none() {
+        args.ops = Some(1_000_000);
+    }
+    args
+}
+
+// xorshift64*: cheap, and good enough to pick operations and keys.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// A primitive under stress: the operations, and the invariants to check once they're done.
+trait Stress: Sync {
+    /// What one thread keeps track of to check the invariants with.
+    type Local: Send;
+
+    fn local(&self, thread: usize, threads: usize) -> Self::Local;
+
+    fn op(&self, local: &mut Self::Local, write: bool, rng: &mut Rng);
+
+    /// Checks the invariants with every thread's state, while they're all stopped. `checker`
+    /// is the checking thread's own, kept from one check to the next, for whatever it takes
+    /// out of the primitive to look at. Describes what was checked, or what went wrong.
+    fn check(&self, locals: &[&Self::Local], checker: &mut Self::Local) -> Result<String, String>;
+}
+
+// A pair only ever changed together under the lock, so a reader seeing them differ saw a
+// write half done.
+struct Locked<L> {
+    lock: L,
+    write: fn(&L),
+    read: fn(&L) -> (u64, u64),
+}
+
+#[derive(Default)]
+struct LockedLocal {
+    writes: u64,
+    torn: u64,
+}
+
+impl<L: Sync> Stress for Locked<L> {
+    type Local = LockedLocal;
+
+    fn local(&self, _: usize, _: usize) -> LockedLocal {
+        LockedLocal::default()
+    }
+
+    fn op(&self, local: &mut LockedLocal, write: bool, _: &mut Rng) {
+        if write {
+            (self.write)(&self.lock);
+            local.writes += 1;
+        } else {
+            let (a, b) = (self.read)(&self.lock);
+            if a != b {
+                local.torn += 1;
+            }
+        }
+    }
+
+    fn check(&self, locals: &[&LockedLocal], _: &mut LockedLocal) -> Result<String, String> {
+        let writes: u64 = locals.iter().map(|l| l.writes).sum();
+        let torn: u64 = locals.iter().map(|l| l.torn).sum();
+        if torn > 0 {
+            return Err(format!("{} reads saw a write half done", torn));
+        }
+        let (a, b) = (self.read)(&self.lock);
+        if (a, b) != (writes, writes) {
+            return Err(format!(
+                "{} writes, but the lock holds ({}, {})",
+                writes, a, b
+            ));
+        }
+        Ok(format!("{} writes, no torn reads", writes))
+    }
+}
+
+// Values are the producing thread in the top bits and its count of pushes below, so the
+// consumer can tell who pushed what in which order.
+const SEQ_BITS: u32 = 40;
+
+struct Queue<Q> {
+    queue: Q,
+    fifo: bool,
+    push: fn(&Q, u64) -> bool,
+    pop: fn(&Q) -> Option<u64>,
+}
+
+struct QueueLocal {
+    thread: u64,
+    pushed: u64,
+    rejected: u64,
+    popped: u64,
+    pushed_sum: u128,
+    popped_sum: u128,
+    // The last seq popped from each producer, plus one.
+    last: Vec<u64>,
+    out_of_order: u64,
+}
+
+impl<Q> Queue<Q> {
+    fn popped(&self, local: &mut QueueLocal, v: u64) {
+        local.popped += 1;
+        local.popped_sum += u128::from(v);
+        let (producer, seq) = ((v >> SEQ_BITS) as usize, v & ((1 << SEQ_BITS) - 1));
+        if local.last[producer] > seq {
+            local.out_of_order += 1;
+        }
+        local.last[producer] = seq + 1;
+    }
+}
+
+impl<Q: Sync> Stress for Queue<Q> {
+    type Local = QueueLocal;
+
+    fn local(&self, thread: usize, threads: usize) -> QueueLocal {
+        QueueLocal {
+            thread: thread as u64,
+            pushed: 0,
+            rejected: 0,
+            popped: 0,
+            pushed_sum: 0,
+            popped_sum: 0,
+            last: vec![0; threads],
+            out_of_order: 0,
+        }
+    }
+
+    fn op(&self, local: &mut QueueLocal, write: bool, _: &mut Rng) {
+        if write {
+            let v = local.thread << SEQ_BITS | local.pushed;
+            if (self.push)(&self.queue, v) {
+                local.pushed += 1;
+                local.pushed_sum += u128::from(v);
+            } else {
+                local.rejected += 1;
+            }
+        } else if let Some(v) = (self.pop)(&self.queue) {
+            self.popped(local, v);
+        }
+    }
+
+    fn check(&self, locals: &[&QueueLocal], checker: &mut QueueLocal) -> Result<String, String> {
+        // Whatever's left counts as popped, in order, by the checker. Popping more than was
+        // pushed is already a failure, and stopping there keeps a queue that has come to
+        // loop back on itself from draining forever.
+        let outstanding = locals
+            .iter()
+            .chain([&&*checker])
+            .map(|l| l.pushed as i64 - l.popped as i64)
+            .sum::<i64>()
+            .max(0);
+        let mut left = 0;
+        while left <= outstanding {
+            let Some(v) = (self.pop)(&self.queue) else {
+                break;
+            };
+            self.popped(checker, v);
+            left += 1;
+        }
+        let locals: Vec<&QueueLocal> = locals.iter().copied().chain([&*checker]).collect();
+        let sum = |f: fn(&QueueLocal) -> u64| locals.iter().map(|l| f(l)).sum::<u64>();
+        let (pushed, popped) = (sum(|l| l.pushed), sum(|l| l.popped));
+        let (rejected, out_of_order) = (sum(|l| l.rejected), sum(|l| l.out_of_order));
+        let pushed_sum: u128 = locals.iter().map(|l| l.pushed_sum).sum();
+        let popped_sum: u128 = locals.iter().map(|l| l.popped_sum).sum();
+        let mut errors = String::new();
+        if pushed != popped {
+            let _ = write!(errors, "{} pushed but {} popped; ", pushed, popped);
+        } else if pushed_sum != popped_sum {
+            errors.push_str("the values popped aren't the ones pushed; ");
+        }
+        if self.fifo && out_of_order > 0 {
+            let _ = write!(errors, "{} values popped out of order; ", out_of_order);
+        }
+        if !errors.is_empty() {
+            return Err(errors.trim_end_matches("; ").to_string());
+        }
+        Ok(format!(
+            "{} pushed, {} rejected full, {} left to drain; every one popped once{}",
+            pushed,
+            rejected,
+            left,
+            if self.fifo { ", in order" } else { "" }
+        ))
+    }
+}
+
+// Every thread has keys of its own, so it knows exactly what a lookup of one should find.
+// A key's value is its complement.
+struct Map<M> {
+    map: M,
+    threads: u64,
+    // Each returns whether the key was there before.
+    insert: fn(&M, u64) -> bool,
+    remove: fn(&M, u64) -> bool,
+    get: fn(&M, u64) -> Option<u64>,
+    len: fn(&M) -> usize,
+    // The keys in order, for a map that can be walked.
+    keys: Option<fn(&M) -> Vec<u64>>,
+}
+
+struct MapLocal {
+    thread: u64,
+    present: HashSet<u64>,
+    wrong: u64,
+}
+
+impl<M: Sync> Stress for Map<M> {
+    type Local = MapLocal;
+
+    fn local(&self, thread: usize, _: usize) -> MapLocal {
+        MapLocal {
+            thread: thread as u64,
+            present: HashSet::new(),
+            wrong: 0,
+        }
+    }
+
+    fn op(&self, local: &mut MapLocal, write: bool, rng: &mut Rng) {
+        let r = rng.next();
+        let key = (r >> 1) % KEYS_PER_THREAD * self.threads + local.thread;
+        let right = if !write {
+            (self.get)(&self.map, key) == local.present.contains(&key).then_some(!key)
+        } else if r & 1 == 0 {
+            (self.insert)(&self.map, key) != local.present.insert(key)
+        } else {
+            (self.remove)(&self.map, key) == local.present.remove(&key)
+        };
+        if !right {
+            local.wrong += 1;
+        }
+    }
+
+    fn check(&self, locals: &[&MapLocal], _: &mut MapLocal) -> Result<String, String> {
+        let wrong: u64 = locals.iter().map(|l| l.wrong).sum();
+        let present: usize = locals.iter().map(|l| l.present.len()).sum();
+        let len = (self.len)(&self.map);
+        let missing = locals
+            .iter()
+            .flat_map(|l| &l.present)
+            .filter(|&&k| (self.get)(&self.map, k) != Some(!k))
+            .count();
+        // Walking the map has to reach exactly the keys that are in it, in order.
+        let unreachable = self.keys.is_some_and(|keys| {
+            let mut expected: Vec<u64> = locals.iter().flat_map(|l| &l.present).copied().collect();
+            expected.sort_unstable();
+            keys(&self.map) != expected
+        });
+        if wrong > 0 || missing > 0 || len != present || unreachable {
+            return Err(format!(
+                "{} operations found the wrong thing, {} keys are missing, the map has {} \
+                 entries for {} keys{}",
+                wrong,
+                missing,
+                len,
+                present,
+                if unreachable {
+                    ", and walking it finds others"
+                } else {
+                    ""
+                }
+            ));
+        }
+        Ok(format!(
+            "{} keys, every lookup right{}",
+            present,
+            if self.keys.is_some() {
+                ", every key reachable"
+            } else {
+                ""
+            }
+        ))
+    }
+}
+
+// Runs `op` in a loop, off a seeded Rng of the thread's own.
+fn ops<S: Stress>(stress: &S, local: &mut S::Local, rng: &mut Rng, n: u64, mix: u64) {
+    for _ in 0..n {
+        let write = rng.next() % 100 < mix;
+        stress.op(local, write, rng);
+    }
+}
+
+fn rng(thread: usize) -> Rng {
+    Rng(0x9e37_79b9_7f4a_7c15 ^ (thread as u64 + 1))
+}
+
+fn throughput(args: &Args, ops: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    format!(
+        "{} ops in {:.2?}, {:.2} Mops/s, {:.1} ns of thread time per op",
+        ops,
+        elapsed,
+        ops as f64 / secs / 1e6,
+        secs * 1e9 * args.threads as f64 / ops.max(1) as f64
+    )
+}
+
+// The counters `--perf` asks for, or just CPU time where the hardware ones can't be had.
+fn counters(args: &Args) -> Option<Counters> {
+    if !args.perf {
+        return None;
+    }
+    let mut events = perf::STANDARD.to_vec();
+    events.push(Event::CpuTime);
+    match Counters::new(&events) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            println!("perf: no hardware counters ({}), counting CPU time only", e);
+            Counters::new(&[Event::CpuTime])
+                .map_err(|e| println!("perf: can't count CPU time either ({})", e))
+                .ok()
+        }
+    }
+}
+
+fn print_counts(counters: Option<&Counters>, ops: u64) {
+    if let Some(c) = counters {
+        println!("perf: {}", perf::per_op(&c.stop(), ops));
+    }
+}
+
+// Runs until the ops or the duration run out, then checks once.
+fn hammer<S: Stress>(stress: &S, args: &Args) -> bool {
+    let budget = AtomicU64::new(args.ops.unwrap_or(u64::MAX));
+    let counters = counters(args);
+    if let Some(c) = &counters {
+        c.start();
+    }
+    let start = Instant::now();
+    let deadline = args.duration.map(|d| start + d);
+    let (locals, ops): (Vec<_>, Vec<_>) = thread::scope(|s| {
+        let handles: Vec<_> = (0..args.threads)
+            .map(|t| {
+                let budget = &budget;
+                s.spawn(move || {
+                    let mut local = stress.local(t, args.threads);
+                    let mut rng = rng(t);
+                    let mut done = 0;
+                    while deadline.is_none_or(|d| Instant::now() < d) {
+                        let Ok(left) =
+                            budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                                n.checked_sub(n.min(BATCH)).filter(|_| n > 0)
+                            })
+                        else {
+                            break;
+                        };
+                        ops(stress, &mut local, &mut rng, left.min(BATCH), args.mix);
+                        done += left.min(BATCH);
+                    }
+                    (local, done)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).unzip()
+    });
+    let elapsed = start.elapsed();
+    let ops = ops.into_iter().sum();
+    println!(
+        "{}: {} threads, {}",
+        args.primitive,
+        args.threads,
+        throughput(args, ops, elapsed)
+    );
+    print_counts(counters.as_ref(), ops);
+    let mut checker = stress.local(args.threads, args.threads);
+    report(stress.check(&locals.iter().collect::<Vec<_>>(), &mut checker))
+}
+
+fn report(check: Result<String, String>) -> bool {
+    match &check {
+        Ok(summary) => println!("ok: {}", summary),
+        Err(error) => println!("FAILED: {}", error),
+    }
+    check.is_ok()
+}
+
+// The resident set in bytes, from /proc/self/status. None off Linux.
+fn rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+// Runs for the whole duration, stopping every thread at each checkpoint to check the
+// invariants so far and the resident set. The threads stop between batches: the main thread
+// bumps `checkpoint`, and each one that sees it meets the main thread at the barrier, waits
+// out the check, and meets it there again.
+fn soak<S: Stress>(stress: &S, args: &Args) -> bool {
+    let duration = args.duration.unwrap();
+    let stop = AtomicBool::new(false);
+    let checkpoint = AtomicU64::new(0);
+    let barrier = Barrier::new(args.threads + 1);
+    let done = AtomicU64::new(0);
+    // Each thread only has its own locked while running a batch, so a check can look at them
+    // all once the threads are stopped.
+    let locals: Vec<_> = (0..args.threads)
+        .map(|t| Mutex::new(stress.local(t, args.threads)))
+        .collect();
+    let mut checker = stress.local(args.threads, args.threads);
+    let counters = counters(args);
+    if let Some(c) = &counters {
+        c.start();
+    }
+    let start = Instant::now();
+    let mut passed = true;
+    thread::scope(|s| {
+        for (t, local) in locals.iter().enumerate() {
+            let (stop, checkpoint, barrier, done) = (&stop, &checkpoint, &barrier, &done);
+            s.spawn(move || {
+                let mut rng = rng(t);
+                let mut seen = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let c = checkpoint.load(Ordering::Relaxed);
+                    if c != seen {
+                        seen = c;
+                        barrier.wait();
+                        barrier.wait();
+                        continue;
+                    }
+                    ops(
+                        stress,
+                        &mut local.lock().unwrap(),
+                        &mut rng,
+                        BATCH,
+                        args.mix,
+                    );
+                    done.fetch_add(BATCH, Ordering::Relaxed);
+                }
+            });
+        }
+
+        let mut baseline = None;
+        let (mut last_ops, mut last_time) = (0, start);
+        let mut next = start + args.check_every;
+        while passed && next < start + duration {
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+            checkpoint.fetch_add(1, Ordering::Relaxed);
+            barrier.wait();
+            let now = Instant::now();
+            let ops = done.load(Ordering::Relaxed);
+            let guards: Vec<_> = locals.iter().map(|l| l.lock().unwrap()).collect();
+            let check = stress.check(
+                &guards.iter().map(|g| &**g).collect::<Vec<_>>(),
+                &mut checker,
+            );
+            drop(guards);
+            let rss = rss();
+            let baseline = *baseline.get_or_insert(rss);
+            print!(
+                "[{:>7.1}s] {}",
+                (now - start).as_secs_f64(),
+                throughput(args, ops - last_ops, now - last_time)
+            );
+            match (rss, baseline) {
+                (Some(rss), Some(baseline)) => {
+                    let growth = rss.saturating_sub(baseline);
+                    println!(
+                        ", rss {:.1} MiB ({:+.1})",
+                        mib(rss),
+                        mib(rss) - mib(baseline)
+                    );
+                    if growth > args.max_rss_growth * 1024 * 1024 {
+                        println!(
+                            "FAILED: the resident set grew {:.1} MiB since the first check",
+                            mib(growth)
+                        );
+                        passed = false;
+                    }
+                }
+                _ => println!(),
+            }
+            passed &= report(check);
+            if !passed {
+                stop.store(true, Ordering::Relaxed);
+            }
+            (last_ops, last_time) = (ops, Instant::now());
+            barrier.wait();
+            next += args.check_every;
+        }
+        if passed {
+            thread::sleep((start + duration).saturating_duration_since(Instant::now()));
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+    if !passed {
+        return false;
+    }
+    let elapsed = start.elapsed();
+    let ops = done.into_inner();
+    println!(
+        "{}: {} threads, {}",
+        args.primitive,
+        args.threads,
+        throughput(args, ops, elapsed)
+    );
+    print_counts(counters.as_ref(), ops);
+    let locals: Vec<_> = locals
+        .into_iter()
+        .map(|l| l.into_inner().unwrap())
+        .collect();
+    report(stress.check(&locals.iter().collect::<Vec<_>>(), &mut checker))
+}
+
+fn run<S: Stress>(stress: &S, args: &Args) -> bool {
+    if args.soak {
+        soak(stress, args)
+    } else {
+        hammer(stress, args)
+    }
+}
+
+const PRIMITIVES: &[(&str, &str)] = &[
+    ("mutex", "FcLock, the flat-combining lock"),
+    ("rwlock", "RwLock, reads shared and writes exclusive"),
+    ("stack", "TreiberStack, reclaiming through epoch"),
+    ("aba-naive", "aba::Stack with no guard, which ABA breaks"),
+    ("aba-tagged", "aba::Stack with a tagged head"),
+    ("aba-hazard", "aba::Stack with hazard pointers"),
+    ("seg-queue", "SegQueue, unbounded"),
+    ("array-queue", "ArrayQueue, bounded"),
+    ("channel", "mpmc::bounded, with try_send and try_recv"),
+    ("hashmap", "ConcurrentHashMap"),
+    ("skiplist", "SkipMap, reclaiming through epoch"),
+];
+
+fn aba(guard: Guard) -> Queue<aba::Stack> {
+    Queue {
+        queue: aba::Stack::new(guard, ABA_CAPACITY),
+        fifo: false,
+        push: |q, v| q.push(v).is_ok(),
+        pop: aba::Stack::pop,
+    }
+}
+
+fn stress(args: &Args) -> bool {
+    match args.primitive.as_str() {
+        "mutex" => run(
+            &Locked {
+                lock: FcLock::new((0u64, 0u64)),
+                write: |l| {
+                    l.with_lock(|p| {
+                        p.0 += 1;
+                        p.1 += 1;
+                    })
+                },
+                read: |l| l.with_lock(|p| *p),
+            },
+            args,
+        ),
+        "rwlock" => run(
+            &Locked {
+                lock: RwLock::new((0u64, 0u64)),
+                write: |l| {
+                    let mut p = l.write();
+                    p.0 += 1;
+                    p.1 += 1;
+                },
+                read: |l| *l.read(),
+            },
+            args,
+        ),
+        "stack" => run(
+            &Queue {
+                queue: TreiberStack::new(),
+                fifo: false,
+                push: |q, v| {
+                    q.push(v);
+                    true
+                },
+                pop: TreiberStack::pop,
+            },
+            args,
+        ),
+        "aba-naive" => run(&aba(Guard::None), args),
+        "aba-tagged" => run(&aba(Guard::Tagged), args),
+        "aba-hazard" => run(&aba(Guard::Hazard), args),
+        "seg-queue" => run(
+            &Queue {
+                queue: SegQueue::new(),
+                fifo: true,
+                push: |q, v| {
+                    q.push(v);
+                    true
+                },
+                pop: SegQueue::pop,
+            },
+            args,
+        ),
+        "array-queue" => run(
+            &Queue {
+                queue: ArrayQueue::new(CAPACITY),
+                fifo: true,
+                push: |q, v| q.push(v).is_ok(),
+                pop: ArrayQueue::pop,
+            },
+            args,
+        ),
+        "channel" => run(
+            &Queue {
+                queue: mpmc::bounded(CAPACITY),
+                fifo: true,
+                push: |(tx, _), v| tx.try_send(v).is_ok(),
+                pop: |(_, rx)| rx.try_recv().ok(),
+            },
+            args,
+        ),
+        "hashmap" => run(
+            &Map {
+                map: ConcurrentHashMap::new(),
+                threads: args.threads as u64,
+                insert: |m, k| m.insert(k, !k).is_some(),
+                remove: |m, k| m.remove(&k).is_some(),
+                get: |m, k| m.get(&k, |&v| v),
+                len: ConcurrentHashMap::len,
+                keys: None,
+            },
+            args,
+        ),
+        "skiplist" => run(
+            &Map {
+                map: SkipMap::new(),
+                threads: args.threads as u64,
+                insert: |m, k| !m.insert(k, !k),
+                remove: |m, k| m.remove(&k),
+                get: |m, k| m.get(&k),
+                len: SkipMap::len,
+                keys: Some(|m| m.iter().map(|(k, _)| k).collect()),
+            },
+            args,
+        ),
+        "" => usage_error("--primitive is required"),
+        name => usage_error(&format!("no primitive {}; --list lists them", name)),
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    if args.list {
+        for (name, description) in PRIMITIVES {
+            println!("{:<12} {}", name, description);
+        }
+        return;
+    }
+    if !stress(&args) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const BITS: usize = usize::BITS as usize;
+
+/// A fixed-size set of small integers, one atomic bit each.
+pub struct AtomicBitSet {
+    words: Box<[AtomicUsize]>,
+    len: usize,
+}
+
+impl AtomicBitSet {
+    /// A set that can hold `0..len`, initially empty.
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: (0..len.div_ceil(BITS))
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            len,
+        }
+    }
+
+    /// How many integers the set can hold.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|w| w.load(Ordering::Relaxed) == 0)
+    }
+
+    fn locate(&self, i: usize) -> (&AtomicUsize, usize) {
+        assert!(i < self.len, "{} out of range for a set of {}", i, self.len);
+        (&self.words[i / BITS], 1 << (i % BITS))
+    }
+
+    /// Adds `i`, returning true if it wasn't already there.
+    pub fn insert(&self, i: usize) -> bool {
+        let (word, bit) = self.locate(i);
+        // AcqRel, like a lock: whoever inserts sees what the last remover did before removing.
+        word.fetch_or(bit, Ordering::AcqRel) & bit == 0
+    }
+
+    /// Removes `i`, returning true if it was there.
+    pub fn remove(&self, i: usize) -> bool {
+        let (word, bit) = self.locate(i);
+        word.fetch_and(!bit, Ordering::AcqRel) & bit != 0
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        let (word, bit) = self.locate(i);
+        word.load(Ordering::Acquire) & bit != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|w| w.load(Ordering::Relaxed).count_ones() as usize)
+            .sum()
+    }
+
+    /// Inserts the first absent integer, searching word by word from the one holding `from`
+    /// and wrapping around. Returns None if the set is full.
+    pub fn insert_first_absent(&self, from: usize) -> Option<usize> {
+        let n = self.words.len();
+        let start = if n == 0 { 0 } else { from / BITS % n };
+        for w in (start..n).chain(0..start) {
+            let word = &self.words[w];
+            let mut current = word.load(Ordering::Relaxed);
+            loop {
+                let bit = (!current).trailing_zeros() as usize;
+                if bit == BITS || w * BITS + bit >= self.len {
+                    break;
+                }
+                match word.compare_exchange_weak(
+                    current,
+                    current | 1 << bit,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Some(w * BITS + bit),
+                    Err(c) => current = c,
+                }
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn bitset_basic() {
+    let set = AtomicBitSet::new(70);
+    assert!(set.insert(3));
+    assert!(!set.insert(3));
+    assert!(set.insert(69));
+    assert!(set.contains(69) && !set.contains(68));
+    assert_eq!(set.count(), 2);
+    assert!(set.remove(3));
+    assert!(!set.remove(3));
+    assert_eq!(set.insert_first_absent(64), Some(64));
+    assert_eq!(set.insert_first_absent(0), Some(0));
+}
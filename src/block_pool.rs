@@ -0,0 +1,151 @@
+use std::alloc::{self, Layout};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+// Free list links and the head's index are stored as block + 1, so 0 means "none".
+const NONE: u32 = 0;
+
+/// A preallocated region carved into fixed-size blocks, handed out through a lock-free free
+/// list. Allocation and deallocation never touch the global allocator.
+pub struct BlockPool {
+    region: NonNull<u8>,
+    layout: Layout,
+    block_size: usize,
+    // The next free block after each free block. Kept out of the blocks themselves, so a
+    // popper reading a stale link never races with the new owner's writes.
+    next: Box<[AtomicU32]>,
+    // The top 32 bits count pops, so a stale head can't be CASed back in (ABA).
+    free: AtomicU64,
+}
+
+unsafe impl Send for BlockPool {}
+unsafe impl Sync for BlockPool {}
+
+impl BlockPool {
+    /// A pool of `count` blocks, each fitting `layout`.
+    pub fn new(layout: Layout, count: usize) -> Self {
+        assert!(count > 0 && count < u32::MAX as usize, "bad block count");
+        let layout = layout.pad_to_align();
+        let block_size = layout.size().max(1);
+        let region = Layout::from_size_align(block_size * count, layout.align()).unwrap();
+        let ptr = NonNull::new(unsafe { alloc::alloc(region) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(region));
+        Self {
+            region: ptr,
+            layout: region,
+            block_size,
+            next: (0..count as u32)
+                .map(|i| AtomicU32::new(if i + 1 < count as u32 { i + 2 } else { NONE }))
+                .collect(),
+            free: AtomicU64::new(1),
+        }
+    }
+
+    /// A pool of `count` blocks, each big enough for a `T`.
+    pub fn for_type<T>(count: usize) -> Self {
+        Self::new(Layout::new::<T>(), count)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.next.len()
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Takes a block, or returns None if they're all in use. Its contents are uninitialized.
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        let mut head = self.free.load(Ordering::Acquire);
+        loop {
+            let block = (head as u32).checked_sub(1)? as usize;
+            // May be stale if someone pops `block` first, but then the tag has moved on and
+            // our CAS fails.
+            let next = self.next[block].load(Ordering::Relaxed);
+            let new = (head >> 32).wrapping_add(1) << 32 | u64::from(next);
+            match self
+                .free
+                .compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    let ptr = unsafe { self.region.as_ptr().add(block * self.block_size) };
+                    return Some(unsafe { NonNull::new_unchecked(ptr) });
+                }
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    /// Returns a block to the pool.
+    ///
+    /// # Safety
+    ///
+    /// `block` must have come from this pool's `alloc` and not been returned since.
+    pub unsafe fn dealloc(&self, block: NonNull<u8>) {
+        let offset = block.as_ptr().offset_from(self.region.as_ptr()) as usize;
+        debug_assert!(
+            offset.is_multiple_of(self.block_size) && offset / self.block_size < self.capacity()
+        );
+        let index = offset / self.block_size;
+        let mut head = self.free.load(Ordering::Relaxed);
+        loop {
+            self.next[index].store(head as u32, Ordering::Relaxed);
+            let new = head & !u64::from(u32::MAX) | (index as u64 + 1);
+            // Release: the next owner sees everything the last one wrote before returning it.
+            match self
+                .free
+                .compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+impl Drop for BlockPool {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.region.as_ptr(), self.layout) };
+    }
+}
+
+#[test]
+fn block_pool_exhausts_and_recycles() {
+    let pool = BlockPool::for_type::<[u64; 3]>(4);
+    assert_eq!(pool.block_size(), 24);
+    let blocks: Vec<_> = (0..4).map(|_| pool.alloc().unwrap()).collect();
+    assert!(pool.alloc().is_none());
+    for (i, b) in blocks.iter().enumerate() {
+        assert_eq!(b.as_ptr() as usize % 8, 0);
+        unsafe { b.cast::<[u64; 3]>().as_ptr().write([i as u64; 3]) };
+    }
+    unsafe { pool.dealloc(blocks[2]) };
+    assert_eq!(pool.alloc(), Some(blocks[2]));
+}
+
+#[test]
+fn block_pool_concurrent() {
+    let pool = &BlockPool::for_type::<u64>(8);
+    std::thread::scope(|s| {
+        for t in 0..4u64 {
+            s.spawn(move || {
+                for i in 0..1000 {
+                    let Some(b) = pool.alloc() else {
+                        std::thread::yield_now();
+                        continue;
+                    };
+                    let p = b.cast::<u64>().as_ptr();
+                    unsafe {
+                        p.write(t * 1000 + i);
+                        std::thread::yield_now();
+                        // Nobody else got the same block meanwhile.
+                        assert_eq!(p.read(), t * 1000 + i);
+                        pool.dealloc(b);
+                    }
+                }
+            });
+        }
+    });
+    let all: Vec<_> = (0..8).map(|_| pool.alloc()).collect();
+    assert!(all.iter().all(Option::is_some));
+}
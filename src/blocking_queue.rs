@@ -0,0 +1,192 @@
+use crate::array_queue::ArrayQueue;
+use crate::close_gate::CloseGate;
+use crate::event_count::EventCount;
+use std::fmt;
+
+/// Returned by the waiting operations once the queue, or the pool, has been closed: a push's
+/// value is handed back, and a pop only gets it once everything left has been popped.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Closed<T = ()>(pub T);
+
+impl<T> fmt::Debug for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Closed(..)")
+    }
+}
+
+impl<T> fmt::Display for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("closed")
+    }
+}
+
+impl<T> std::error::Error for Closed<T> {}
+
+/// An [`ArrayQueue`] whose `push` sleeps while the queue is full and whose `pop` sleeps while
+/// it's empty, on a pair of [`EventCount`]s.
+///
+/// [`close`](Self::close) is for shutdown: it wakes everyone waiting, later pushes fail, and
+/// pops go on until the queue is drained and only then fail, without the producers having to
+/// be tracked down and stopped first.
+pub struct BlockingQueue<T> {
+    queue: ArrayQueue<T>,
+    // Pushes go through it, so none lands after a pop has found the queue closed and empty.
+    closed: CloseGate,
+    not_empty: EventCount,
+    not_full: EventCount,
+}
+
+impl<T> BlockingQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: ArrayQueue::new(capacity),
+            closed: CloseGate::new(),
+            not_empty: EventCount::new(),
+            not_full: EventCount::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Pushes `value`, or hands it back if the queue is full or closed.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        // Handed back by the gate if the queue's closed, or by the queue if it's full.
+        self.closed
+            .push(value, &self.not_empty, |value| self.queue.push(value))??;
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    pub fn try_pop(&self) -> Option<T> {
+        let value = self.queue.pop()?;
+        self.not_full.notify_one();
+        Some(value)
+    }
+
+    /// Closes the queue, waking every push and pop that's waiting.
+    pub fn close(&self) {
+        self.closed.close();
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_closed()
+    }
+
+    /// Pops items until it finds the queue empty, without waiting, and makes room for
+    /// producers as it goes, like [`ArrayQueue::drain`].
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.try_pop())
+    }
+
+    /// Pushes `value`, waiting for room if the queue is full, or hands it back once the queue
+    /// is closed.
+    pub fn push(&self, mut value: T) -> Result<(), Closed<T>> {
+        loop {
+            match self.try_push(value) {
+                Ok(()) => return Ok(()),
+                Err(v) if self.is_closed() => return Err(Closed(v)),
+                Err(v) => value = v,
+            }
+            let key = self.not_full.prepare_wait();
+            match self.try_push(value) {
+                Ok(()) => {
+                    self.not_full.cancel_wait(key);
+                    return Ok(());
+                }
+                Err(v) if self.is_closed() => {
+                    self.not_full.cancel_wait(key);
+                    return Err(Closed(v));
+                }
+                Err(v) => value = v,
+            }
+            self.not_full.wait(key);
+        }
+    }
+
+    /// Pops a value, waiting for one if the queue is empty, or fails once the queue is closed
+    /// and drained.
+    pub fn pop(&self) -> Result<T, Closed> {
+        loop {
+            if let Some(value) = self.try_pop() {
+                return Ok(value);
+            }
+            let key = self.not_empty.prepare_wait();
+            if let Some(value) = self.try_pop() {
+                self.not_empty.cancel_wait(key);
+                return Ok(value);
+            }
+            // Closed, but a push let in before the close may still be landing, and wakes us
+            // when it has.
+            if self.closed.is_drained() {
+                self.not_empty.cancel_wait(key);
+                // It may have landed since the last look.
+                return self.try_pop().ok_or(Closed(()));
+            }
+            self.not_empty.wait(key);
+        }
+    }
+}
+
+#[test]
+fn blocking_queue_pipeline() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let q = &BlockingQueue::new(4);
+    let got = std::thread::scope(|s| {
+        for t in 0..3 {
+            s.spawn(move || {
+                for i in 0..n {
+                    q.push(t * n + i).unwrap();
+                }
+            });
+        }
+        let mut got: Vec<_> = (0..3 * n).map(|_| q.pop().unwrap()).collect();
+        got.sort_unstable();
+        got
+    });
+    assert!(got.into_iter().eq(0..3 * n));
+    assert_eq!(q.try_pop(), None);
+}
+
+#[test]
+fn blocking_queue_close_wakes_everyone() {
+    let q = &BlockingQueue::new(2);
+    std::thread::scope(|s| {
+        let consumers: Vec<_> = (0..2)
+            .map(|_| s.spawn(move || std::iter::from_fn(|| q.pop().ok()).count()))
+            .collect();
+        q.push(1).unwrap();
+        q.push(2).unwrap();
+        q.push(3).unwrap();
+        let producer = s.spawn(move || {
+            let mut accepted = 0;
+            loop {
+                match q.push(4) {
+                    Ok(()) => accepted += 1,
+                    Err(Closed(v)) => return (v, accepted),
+                }
+            }
+        });
+        std::thread::yield_now();
+        q.close();
+        let (refused, accepted) = producer.join().unwrap();
+        assert_eq!(refused, 4);
+        let popped: usize = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        // Every push that was let in was popped, however it raced with the close.
+        assert_eq!(popped, 3 + accepted);
+    });
+    assert!(q.is_closed() && q.is_empty());
+    assert_eq!(q.try_push(5), Err(5));
+    assert_eq!(q.pop(), Err(Closed(())));
+}
@@ -0,0 +1,349 @@
+use crate::event_count::EventCount;
+use crate::rwlock::RwLock;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::mpsc::SendError;
+
+struct Slot<T> {
+    // The position of the message in `value`, meaningless while it's None.
+    pos: u64,
+    value: Option<T>,
+}
+
+struct Shared<T> {
+    slots: Box<[RwLock<Slot<T>>]>,
+    // The next position to hand out to a sender.
+    tail: AtomicU64,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    sent: EventCount,
+}
+
+/// The sending half of a broadcast [`channel`]; clone it for more producers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// One subscriber to a broadcast [`channel`], with its own cursor into the ring.
+///
+/// Cloning it gives a subscriber at the same position.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    next: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The receiver fell behind and this many messages were overwritten before it read them.
+    /// It has skipped ahead to the oldest message still in the ring.
+    Lagged(u64),
+    /// Every sender is gone and the receiver has read everything left.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The receiver is caught up.
+    Empty,
+    /// See [`RecvError::Lagged`].
+    Lagged(u64),
+    /// Every sender is gone and the receiver has read everything left.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// Nothing arrived before the deadline.
+    Timeout,
+    /// See [`RecvError::Lagged`].
+    Lagged(u64),
+    /// Every sender is gone and the receiver has read everything left.
+    Disconnected,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lagged(n) => write!(f, "receiver lagged behind by {} messages", n),
+            Self::Disconnected => f.write_str("receiving on a channel whose senders are all gone"),
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("receiving on an empty channel"),
+            Self::Lagged(n) => RecvError::Lagged(*n).fmt(f),
+            Self::Disconnected => RecvError::Disconnected.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timed out waiting on a channel"),
+            Self::Lagged(n) => RecvError::Lagged(*n).fmt(f),
+            Self::Disconnected => RecvError::Disconnected.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
+/// A broadcast channel keeping the last `capacity` messages in a ring, where every receiver
+/// sees every message.
+///
+/// Senders never wait for receivers: a new message overwrites the oldest one, and a receiver
+/// that hadn't read it yet gets [`Lagged`](RecvError::Lagged) and skips ahead. Each slot has
+/// its own [`RwLock`], so readers cloning a message only hold off the sender that's about to
+/// overwrite that one slot.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be non-zero");
+    let shared = Arc::new(Shared {
+        slots: (0..capacity)
+            .map(|_| {
+                RwLock::new(Slot {
+                    pos: 0,
+                    value: None,
+                })
+            })
+            .collect(),
+        tail: AtomicU64::new(0),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        sent: EventCount::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared, next: 0 })
+}
+
+impl<T> Shared<T> {
+    fn slot(&self, pos: u64) -> &RwLock<Slot<T>> {
+        &self.slots[(pos % self.slots.len() as u64) as usize]
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every current receiver, or hands it back if there are none.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        if shared.receivers.load(Ordering::Relaxed) == 0 {
+            return Err(SendError(value));
+        }
+        let pos = shared.tail.fetch_add(1, Ordering::Relaxed);
+        let mut slot = shared.slot(pos).write();
+        // A sender a whole lap ahead may have got here first, in which case our message
+        // has already been overwritten as far as anyone can tell.
+        if slot.value.is_none() || slot.pos < pos {
+            slot.pos = pos;
+            slot.value = Some(value);
+        }
+        drop(slot);
+        shared.sent.notify_all();
+        Ok(())
+    }
+
+    /// A new receiver that sees messages sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            shared: Arc::clone(&self.shared),
+            next: self.shared.tail.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    fn read(&mut self) -> Result<T, TryRecvError> {
+        let shared = &*self.shared;
+        let slot = shared.slot(self.next).read();
+        match &slot.value {
+            Some(value) if slot.pos == self.next => {
+                self.next += 1;
+                Ok(value.clone())
+            }
+            Some(_) if slot.pos > self.next => {
+                drop(slot);
+                let oldest = shared
+                    .tail
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(shared.slots.len() as u64);
+                let missed = oldest - self.next;
+                self.next = oldest;
+                Err(TryRecvError::Lagged(missed))
+            }
+            // Last lap's message, or nothing yet: the next one hasn't been written.
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self.read() {
+            Err(TryRecvError::Empty) => {}
+            result => return result,
+        }
+        if self.shared.senders.load(Ordering::SeqCst) != 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // The last sender may have sent just before it dropped.
+        match self.read() {
+            Err(TryRecvError::Empty) => Err(TryRecvError::Disconnected),
+            result => result,
+        }
+    }
+
+    /// Waits for the next message.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|e| match e {
+            RecvTimeoutError::Lagged(n) => RecvError::Lagged(n),
+            _ => RecvError::Disconnected,
+        })
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`.
+    pub fn recv_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let into_timeout = |e| match e {
+            TryRecvError::Lagged(n) => RecvTimeoutError::Lagged(n),
+            _ => RecvTimeoutError::Disconnected,
+        };
+        loop {
+            match self.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                result => return result.map_err(into_timeout),
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let key = self.shared.sent.prepare_wait();
+            match self.try_recv() {
+                Err(TryRecvError::Empty) => match deadline {
+                    None => self.shared.sent.wait(key),
+                    Some(deadline) => {
+                        self.shared.sent.wait_deadline(key, deadline);
+                    }
+                },
+                result => {
+                    self.shared.sent.cancel_wait(key);
+                    return result.map_err(into_timeout);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // SeqCst: our sends happen before a receiver that sees the count hit zero, and the
+        // count is re-checked after registering to wait.
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.sent.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn broadcast_lagged_receiver_skips_ahead() {
+    let (tx, mut rx) = channel(4);
+    tx.send(0).unwrap();
+    let mut fresh = tx.subscribe();
+    for i in 1..6 {
+        tx.send(i).unwrap();
+    }
+    // 0 and 1 have been overwritten by 4 and 5.
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Lagged(2)));
+    assert_eq!(fresh.try_recv(), Err(TryRecvError::Lagged(1)));
+    for i in 2..6 {
+        assert_eq!(rx.try_recv(), Ok(i));
+        assert_eq!(fresh.recv(), Ok(i));
+    }
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    drop(tx);
+    assert_eq!(rx.recv(), Err(RecvError::Disconnected));
+}
+
+#[test]
+fn broadcast_fans_out() {
+    let (tx, rx) = channel(256);
+    let receivers: Vec<_> = (0..3)
+        .map(|_| {
+            let mut rx = rx.clone();
+            std::thread::spawn(move || std::iter::from_fn(|| rx.recv().ok()).collect::<Vec<_>>())
+        })
+        .collect();
+    drop(rx);
+    for i in 0..200 {
+        tx.send(i).unwrap();
+    }
+    drop(tx);
+    for r in receivers {
+        assert!(r.join().unwrap().into_iter().eq(0..200));
+    }
+}
+
+#[test]
+fn broadcast_recv_timeout() {
+    let (tx, mut rx) = channel(2);
+    let timeout = Duration::from_millis(10);
+    assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+    for i in 0..3 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Lagged(1)));
+    assert_eq!(rx.recv_timeout(timeout), Ok(1));
+    drop(tx);
+    assert_eq!(rx.recv_timeout(timeout), Ok(2));
+    assert_eq!(
+        rx.recv_timeout(timeout),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
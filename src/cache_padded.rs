@@ -0,0 +1,127 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// The alignment [`CachePadded`] uses on this architecture: the cache line size, or the pair
+/// of lines the prefetcher pulls in together where that's what it takes to stop false sharing.
+//
+// x86_64 prefetches lines in adjacent pairs and recent aarch64 and powerpc64 cores have
+// 128-byte lines; s390x has 256-byte lines; the 32-bit embedded targets listed have 32-byte
+// ones. Everything else gets the common 64.
+pub const CACHE_LINE: usize = if cfg!(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "powerpc64"
+)) {
+    128
+} else if cfg!(any(
+    target_arch = "arm",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "riscv32",
+    target_arch = "sparc",
+    target_arch = "hexagon"
+)) {
+    32
+} else if cfg!(target_arch = "s390x") {
+    256
+} else {
+    64
+};
+
+/// Aligns and pads a value to [`CACHE_LINE`] bytes, so it never shares a cache line with
+/// another value.
+///
+/// Two atomics written by different threads, such as a queue's head and tail, bounce the
+/// line between cores on every write if they share it, even though neither thread reads the
+/// other's. Wrapping each in `CachePadded` gives it a line of its own.
+// The alignments here must match CACHE_LINE above.
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    any(
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "riscv32",
+        target_arch = "sparc",
+        target_arch = "hexagon"
+    ),
+    repr(align(32))
+)]
+#[cfg_attr(target_arch = "s390x", repr(align(256)))]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "arm",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "riscv32",
+        target_arch = "sparc",
+        target_arch = "hexagon",
+        target_arch = "s390x"
+    )),
+    repr(align(64))
+)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachePadded")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[test]
+fn cache_padded_fills_its_line() {
+    use std::mem;
+    use std::sync::atomic::AtomicUsize;
+
+    assert_eq!(mem::align_of::<CachePadded<u8>>(), CACHE_LINE);
+    assert_eq!(mem::size_of::<CachePadded<AtomicUsize>>(), CACHE_LINE);
+    assert_eq!(mem::size_of::<CachePadded<[u8; 129]>>() % CACHE_LINE, 0);
+    let pair = [CachePadded::new(1u8), CachePadded::new(2u8)];
+    let gap = &*pair[1] as *const u8 as usize - &*pair[0] as *const u8 as usize;
+    assert_eq!(gap, CACHE_LINE);
+}
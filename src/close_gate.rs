@@ -0,0 +1,103 @@
+use crate::event_count::EventCount;
+use crate::sync_shim::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// A closed flag that pushes check before pushing can't close a queue by itself: a push can
+// find it clear, and then land after the close, after a consumer that found the queue closed
+// and empty has given up on it, and nothing ever pops it. So pushes count themselves in
+// before they look at the flag, and out once they've pushed, and a consumer only takes the
+// queue for finished once it's closed with nobody in: every push that got past the flag has
+// landed by then, and the queue's empty for good if it's empty after that.
+//
+// All SeqCst. A push's count in and load of the flag, and close's store and a consumer's
+// loads of the flag and the count, need one order for either the push to see the close or
+// the consumer to see the push counted in.
+
+/// Lets a queue be closed while pushes race with the close, so that a consumer that finds it
+/// [`drained`](Self::is_drained) and empty knows every push that was let in has been popped.
+pub struct CloseGate {
+    closed: AtomicBool,
+    // Pushes that have counted themselves in and not yet out.
+    pushing: AtomicUsize,
+}
+
+impl CloseGate {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            closed: AtomicBool::new(false),
+            pushing: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            closed: AtomicBool::new(false),
+            pushing: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `push` on `value` unless the gate is closed, and hands `value` back if it is. A
+    /// push still in when the gate closes wakes `consumers` on the way out, for those waiting
+    /// to see it drained.
+    pub fn push<T, R>(
+        &self,
+        value: T,
+        consumers: &EventCount,
+        push: impl FnOnce(T) -> R,
+    ) -> Result<R, T> {
+        // Counts the push out however it leaves, even if `push` panics.
+        struct Out<'a>(&'a CloseGate, &'a EventCount);
+        impl Drop for Out<'_> {
+            fn drop(&mut self) {
+                if self.0.pushing.fetch_sub(1, Ordering::SeqCst) == 1 && self.0.is_closed() {
+                    self.1.notify_all();
+                }
+            }
+        }
+        self.pushing.fetch_add(1, Ordering::SeqCst);
+        let _out = Out(self, consumers);
+        if self.is_closed() {
+            return Err(value);
+        }
+        Ok(push(value))
+    }
+
+    /// Closes the gate to pushes that haven't got past it yet.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Whether the gate is closed and every push it let in has finished, so that a queue
+    /// behind it that's empty now stays empty.
+    pub fn is_drained(&self) -> bool {
+        self.is_closed() && self.pushing.load(Ordering::SeqCst) == 0
+    }
+}
+
+impl Default for CloseGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn close_gate_waits_for_the_pushes_it_let_in() {
+    let gate = CloseGate::new();
+    let consumers = EventCount::new();
+    let pushed = gate.push(1, &consumers, |v| {
+        // Closed with this push in: closed, but not drained until it's out.
+        gate.close();
+        assert!(gate.is_closed() && !gate.is_drained());
+        v
+    });
+    assert_eq!(pushed, Ok(1));
+    assert!(gate.is_drained());
+    assert_eq!(gate.push(2, &consumers, |_| unreachable!()), Err(2));
+    assert!(gate.is_drained());
+}
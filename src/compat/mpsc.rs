@@ -0,0 +1,284 @@
+use crate::{mpmc, mpsc};
+use std::fmt;
+use std::time::Duration;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+/// A drop-in for `std::sync::mpsc::Sender`, on the unbounded [`mpsc`](crate::mpsc) channel.
+pub struct Sender<T> {
+    inner: mpsc::Sender<T>,
+}
+
+/// A drop-in for `std::sync::mpsc::SyncSender`, on the bounded [`mpmc`](crate::mpmc)
+/// channel.
+pub struct SyncSender<T> {
+    inner: mpmc::Sender<T>,
+}
+
+/// A drop-in for `std::sync::mpsc::Receiver`, for either kind of channel.
+pub struct Receiver<T> {
+    inner: Flavor<T>,
+}
+
+enum Flavor<T> {
+    Unbounded(mpsc::Receiver<T>),
+    Bounded(mpmc::Receiver<T>),
+}
+
+/// Same as `std::sync::mpsc::channel`, except that `T` must be `Send` up front.
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::channel();
+    (
+        Sender { inner: tx },
+        Receiver {
+            inner: Flavor::Unbounded(rx),
+        },
+    )
+}
+
+/// Same as `std::sync::mpsc::sync_channel`, including the rendezvous behaviour of a zero
+/// bound.
+pub fn sync_channel<T: Send>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let (tx, rx) = mpmc::bounded(bound);
+    (
+        SyncSender { inner: tx },
+        Receiver {
+            inner: Flavor::Bounded(rx),
+        },
+    )
+}
+
+impl<T: Send> Sender<T> {
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner
+            .send(t)
+            .map_err(|mpsc::SendError(t)| SendError(t))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> SyncSender<T> {
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner
+            .send(t)
+            .map_err(|mpsc::SendError(t)| SendError(t))
+    }
+
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(t).map_err(|e| match e {
+            mpmc::TrySendError::Full(t) => TrySendError::Full(t),
+            mpmc::TrySendError::Disconnected(t) => TrySendError::Disconnected(t),
+        })
+    }
+
+    /// Not in std: like [`send`](Self::send), but gives up after `timeout`, handing the value
+    /// back as `Full` if there was still no room, or `Disconnected`.
+    pub fn send_timeout(&self, t: T, timeout: Duration) -> Result<(), TrySendError<T>> {
+        self.inner.send_timeout(t, timeout).map_err(|e| match e {
+            mpmc::SendTimeoutError::Timeout(t) => TrySendError::Full(t),
+            mpmc::SendTimeoutError::Disconnected(t) => TrySendError::Disconnected(t),
+        })
+    }
+
+    /// Not in std: the bound, for comparing with [`len`](Self::len) to see how far behind
+    /// the receivers are.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Not in std: how many messages are queued, which may be out of date by the time it's
+    /// returned.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Not in std.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+fn try_recv_error(e: mpsc::TryRecvError) -> TryRecvError {
+    match e {
+        mpsc::TryRecvError::Empty => TryRecvError::Empty,
+        mpsc::TryRecvError::Disconnected => TryRecvError::Disconnected,
+    }
+}
+
+fn recv_timeout_error(e: mpsc::RecvTimeoutError) -> RecvTimeoutError {
+    match e {
+        mpsc::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+        mpsc::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match &self.inner {
+            Flavor::Unbounded(rx) => rx.try_recv(),
+            Flavor::Bounded(rx) => rx.try_recv(),
+        }
+        .map_err(try_recv_error)
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match &self.inner {
+            Flavor::Unbounded(rx) => rx.recv(),
+            Flavor::Bounded(rx) => rx.recv(),
+        }
+        .map_err(|mpsc::RecvError| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match &self.inner {
+            Flavor::Unbounded(rx) => rx.recv_timeout(timeout),
+            Flavor::Bounded(rx) => rx.recv_timeout(timeout),
+        }
+        .map_err(recv_timeout_error)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T: Send> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T: Send> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T: Send> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T: Send> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Send> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn compat_mpsc_matches_std() {
+    // A type holding the ends with no bound of its own on T, as std allows.
+    #[allow(dead_code)]
+    struct Ends<T> {
+        tx: Sender<T>,
+        sync_tx: SyncSender<T>,
+        rx: Receiver<T>,
+        iter: Option<IntoIter<T>>,
+    }
+
+    // Written exactly as it would be against std::sync::mpsc.
+    let (tx, rx) = channel();
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let tx = tx.clone();
+            std::thread::spawn(move || tx.send(i).unwrap())
+        })
+        .collect();
+    drop(tx);
+    let mut got: Vec<i32> = rx.iter().collect();
+    got.sort_unstable();
+    assert_eq!(got, [0, 1, 2]);
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    let (tx, rx) = sync_channel(1);
+    tx.try_send(1).unwrap();
+    assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+    assert_eq!((tx.len(), tx.capacity()), (1, 1));
+    assert_eq!(
+        tx.send_timeout(2, Duration::from_millis(10)),
+        Err(TrySendError::Full(2))
+    );
+    assert_eq!(rx.recv(), Ok(1));
+    assert!(tx.is_empty());
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    drop(rx);
+    assert_eq!(tx.send(3), Err(SendError(3)));
+    assert_eq!(
+        tx.send_timeout(4, Duration::from_millis(10)),
+        Err(TrySendError::Disconnected(4))
+    );
+}
@@ -0,0 +1,734 @@
+use crate::raw_mutex::RawMutex;
+use crate::rwlock;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// parking_lot's Mutex, RwLock and Condvar, by the same names and with the same signatures, so
+// a project moves over by changing `use parking_lot::...` to `use
+// atomics::compat::parking_lot::...`. No poisoning, `const fn new`, guards that `map` to part
+// of what they guard. Mutex is a `RawMutex`, RwLock is the crate's `RwLock` (with everything
+// its features report), and Condvar waits on a futex word.
+//
+// Not here: fair unlocking, timed locking, upgradable reads and downgrading, and the
+// `lock_api` raw-lock traits parking_lot's types are generic over.
+
+/// A drop-in for `parking_lot::Mutex`, on a [`RawMutex`].
+pub struct Mutex<T: ?Sized> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// Same as `parking_lot::const_mutex`.
+pub const fn const_mutex<T>(val: T) -> Mutex<T> {
+    Mutex::new(val)
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            raw: RawMutex::new(),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.lock();
+        MutexGuard::new(self)
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.raw.try_lock() {
+            Some(MutexGuard::new(self))
+        } else {
+            None
+        }
+    }
+
+    /// Whether someone holds it, which may have changed by the time it returns.
+    pub fn is_locked(&self) -> bool {
+        if self.raw.try_lock() {
+            // Safety: we just took it, on this thread.
+            unsafe { self.raw.unlock() };
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Unlocks it without a guard, for a guard that was `mem::forget`-ten or
+    /// [`leak`](MutexGuard::leak)ed, as when the lock is taken in one FFI callback and given
+    /// back in another.
+    ///
+    /// # Safety
+    ///
+    /// The current thread holds it, through a guard that won't be dropped, and nothing
+    /// borrowed through that guard, a leaked `&mut T` included, is used again.
+    pub unsafe fn force_unlock(&self) {
+        self.raw.unlock();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(t: T) -> Self {
+        Self::new(t)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+// Guards are !Send: on macOS a RawMutex has to be unlocked on the thread that locked it.
+type NotSend = PhantomData<*const ()>;
+
+/// A drop-in for `parking_lot::MutexGuard`.
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _not_send: NotSend,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The mutex this guard holds.
+    pub fn mutex(s: &Self) -> &'a Mutex<T> {
+        s.mutex
+    }
+
+    /// A guard for part of the locked data, which keeps the mutex locked until it's dropped.
+    pub fn map<U: ?Sized>(s: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedMutexGuard<'a, U> {
+        let raw = &s.mutex.raw;
+        // Safety: we hold the lock, and give it over to the mapped guard.
+        let data = f(unsafe { &mut *s.mutex.data.get() }) as *mut U;
+        std::mem::forget(s);
+        MappedMutexGuard {
+            raw,
+            data,
+            _marker: PhantomData,
+        }
+    }
+
+    /// [`map`](Self::map), where `f` may decline, in which case the guard comes back.
+    pub fn try_map<U: ?Sized>(
+        s: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedMutexGuard<'a, U>, Self> {
+        let raw = &s.mutex.raw;
+        // Safety: as for `map`; on `None`, nothing borrowed from the data outlives the call.
+        let data = match f(unsafe { &mut *s.mutex.data.get() }) {
+            Some(data) => data as *mut U,
+            None => return Err(s),
+        };
+        std::mem::forget(s);
+        Ok(MappedMutexGuard {
+            raw,
+            data,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Keeps the mutex locked for good, and the data borrowed for as long as the mutex is.
+    /// Only [`Mutex::force_unlock`] unlocks it again.
+    pub fn leak(s: Self) -> &'a mut T {
+        let data = s.mutex.data.get();
+        std::mem::forget(s);
+        // Safety: the lock is never released, so this is the only borrow from now on.
+        unsafe { &mut *data }
+    }
+
+    /// Unlocks the mutex while `f` runs, and locks it again afterwards.
+    pub fn unlocked<R>(s: &mut Self, f: impl FnOnce() -> R) -> R {
+        // Safety: the guard holds it, on this thread.
+        unsafe { s.mutex.raw.unlock() };
+        let relock = Relock(&s.mutex.raw);
+        let r = f();
+        drop(relock);
+        r
+    }
+}
+
+// Locks again when dropped, so a guard that's been `unlocked` holds the lock even if `f`
+// panics.
+struct Relock<'a>(&'a RawMutex);
+
+impl Drop for Relock<'_> {
+    fn drop(&mut self) {
+        self.0.lock();
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: the guard holds it, on this thread.
+        unsafe { self.mutex.raw.unlock() };
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A drop-in for `parking_lot::MappedMutexGuard`: a [`MutexGuard`] narrowed with `map`.
+pub struct MappedMutexGuard<'a, T: ?Sized> {
+    raw: &'a RawMutex,
+    data: *mut T,
+    _marker: PhantomData<&'a mut T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedMutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MappedMutexGuard<'a, T> {
+    pub fn map<U: ?Sized>(s: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedMutexGuard<'a, U> {
+        let raw = s.raw;
+        // Safety: we hold the lock, and give it over to the new guard.
+        let data = f(unsafe { &mut *s.data }) as *mut U;
+        std::mem::forget(s);
+        MappedMutexGuard {
+            raw,
+            data,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MappedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MappedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: the guard holds it, on this thread.
+        unsafe { self.raw.unlock() };
+    }
+}
+
+/// A drop-in for `parking_lot::RwLock`, on the crate's [`RwLock`](crate::rwlock::RwLock).
+pub struct RwLock<T: ?Sized> {
+    raw: rwlock::RwLock<()>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// Same as `parking_lot::const_rwlock`.
+#[cfg(not(loom))]
+pub const fn const_rwlock<T>(val: T) -> RwLock<T> {
+    RwLock::new(val)
+}
+
+impl<T> RwLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(val: T) -> Self {
+        Self {
+            raw: rwlock::RwLock::new(()),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(val: T) -> Self {
+        Self {
+            raw: rwlock::RwLock::new(()),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        RwLockReadGuard {
+            guard: self.raw.read(),
+            lock: self,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let guard = self.raw.try_read()?;
+        Some(RwLockReadGuard { guard, lock: self })
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        RwLockWriteGuard {
+            guard: self.raw.write(),
+            lock: self,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let guard = self.raw.try_write()?;
+        Some(RwLockWriteGuard { guard, lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(t: T) -> Self {
+        Self::new(t)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("RwLock").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+/// A drop-in for `parking_lot::RwLockReadGuard`.
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    guard: rwlock::RwLockReadGuard<'a, ()>,
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> RwLockReadGuard<'a, T> {
+    /// Unlocks the lock while `f` runs, and reads it again afterwards.
+    #[track_caller]
+    pub fn unlocked<R>(s: &mut Self, f: impl FnOnce() -> R) -> R {
+        rwlock::RwLockReadGuard::unlocked(&mut s.guard, f)
+    }
+
+    pub fn rwlock(s: &Self) -> &'a RwLock<T> {
+        s.lock
+    }
+
+    /// A guard for part of the data, which keeps the read lock until it's dropped.
+    pub fn map<U: ?Sized>(s: Self, f: impl FnOnce(&T) -> &U) -> MappedRwLockReadGuard<'a, U> {
+        // Safety: readers exclude writers, and the read lock goes over to the mapped guard.
+        let data = f(unsafe { &*s.lock.data.get() }) as *const U;
+        MappedRwLockReadGuard {
+            guard: s.guard,
+            data,
+        }
+    }
+
+    pub fn try_map<U: ?Sized>(
+        s: Self,
+        f: impl FnOnce(&T) -> Option<&U>,
+    ) -> Result<MappedRwLockReadGuard<'a, U>, Self> {
+        let data = match f(unsafe { &*s.lock.data.get() }) {
+            Some(data) => data as *const U,
+            None => return Err(s),
+        };
+        Ok(MappedRwLockReadGuard {
+            guard: s.guard,
+            data,
+        })
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A drop-in for `parking_lot::RwLockWriteGuard`.
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    guard: rwlock::RwLockWriteGuard<'a, ()>,
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    /// Unlocks the lock while `f` runs, and writes it again afterwards.
+    #[track_caller]
+    pub fn unlocked<R>(s: &mut Self, f: impl FnOnce() -> R) -> R {
+        rwlock::RwLockWriteGuard::unlocked(&mut s.guard, f)
+    }
+
+    pub fn rwlock(s: &Self) -> &'a RwLock<T> {
+        s.lock
+    }
+
+    /// A guard for part of the data, which keeps the write lock until it's dropped.
+    pub fn map<U: ?Sized>(
+        s: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, U> {
+        // Safety: we hold the lock exclusively, and give it over to the mapped guard.
+        let data = f(unsafe { &mut *s.lock.data.get() }) as *mut U;
+        MappedRwLockWriteGuard {
+            guard: s.guard,
+            data,
+        }
+    }
+
+    pub fn try_map<U: ?Sized>(
+        s: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedRwLockWriteGuard<'a, U>, Self> {
+        let data = match f(unsafe { &mut *s.lock.data.get() }) {
+            Some(data) => data as *mut U,
+            None => return Err(s),
+        };
+        Ok(MappedRwLockWriteGuard {
+            guard: s.guard,
+            data,
+        })
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A drop-in for `parking_lot::MappedRwLockReadGuard`.
+pub struct MappedRwLockReadGuard<'a, T: ?Sized> {
+    guard: rwlock::RwLockReadGuard<'a, ()>,
+    data: *const T,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedRwLockReadGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MappedRwLockReadGuard<'a, T> {
+    pub fn map<U: ?Sized>(s: Self, f: impl FnOnce(&T) -> &U) -> MappedRwLockReadGuard<'a, U> {
+        let data = f(unsafe { &*s.data }) as *const U;
+        MappedRwLockReadGuard {
+            guard: s.guard,
+            data,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MappedRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+/// A drop-in for `parking_lot::MappedRwLockWriteGuard`.
+pub struct MappedRwLockWriteGuard<'a, T: ?Sized> {
+    guard: rwlock::RwLockWriteGuard<'a, ()>,
+    data: *mut T,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MappedRwLockWriteGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MappedRwLockWriteGuard<'a, T> {
+    pub fn map<U: ?Sized>(
+        s: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, U> {
+        let data = f(unsafe { &mut *s.data }) as *mut U;
+        MappedRwLockWriteGuard {
+            guard: s.guard,
+            data,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MappedRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MappedRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+/// Same as `parking_lot::WaitTimeoutResult`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(self) -> bool {
+        self.0
+    }
+}
+
+/// A drop-in for `parking_lot::Condvar`, waiting on a [`futex`](crate::futex) word.
+pub struct Condvar {
+    // Bumped by every notify, so a waiter that unlocked before it doesn't sleep through it.
+    seq: AtomicU32,
+    waiters: AtomicUsize,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wakes a waiter, and says whether there was one. Waits can return spuriously, so more
+    /// than one may wake.
+    pub fn notify_one(&self) -> bool {
+        self.seq.fetch_add(1, Ordering::Release);
+        // SeqCst, with the waiter's: either we see it counted or it sees the new sequence.
+        if self.waiters.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        crate::futex::wake_one(&self.seq);
+        true
+    }
+
+    /// Wakes every waiter, and returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        self.seq.fetch_add(1, Ordering::Release);
+        let waiters = self.waiters.load(Ordering::SeqCst);
+        if waiters > 0 {
+            crate::futex::wake_all(&self.seq);
+        }
+        waiters
+    }
+
+    pub fn wait<T: ?Sized>(&self, guard: &mut MutexGuard<'_, T>) {
+        self.wait_deadline(guard, None);
+    }
+
+    pub fn wait_until<T: ?Sized>(
+        &self,
+        guard: &mut MutexGuard<'_, T>,
+        timeout: Instant,
+    ) -> WaitTimeoutResult {
+        self.wait_deadline(guard, Some(timeout));
+        WaitTimeoutResult(Instant::now() >= timeout)
+    }
+
+    pub fn wait_for<T: ?Sized>(
+        &self,
+        guard: &mut MutexGuard<'_, T>,
+        timeout: Duration,
+    ) -> WaitTimeoutResult {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.wait_until(guard, deadline),
+            None => {
+                self.wait(guard);
+                WaitTimeoutResult(false)
+            }
+        }
+    }
+
+    /// Waits for as long as `condition` holds, checking it first.
+    pub fn wait_while<T: ?Sized>(
+        &self,
+        guard: &mut MutexGuard<'_, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) {
+        while condition(guard) {
+            self.wait(guard);
+        }
+    }
+
+    pub fn wait_while_until<T: ?Sized>(
+        &self,
+        guard: &mut MutexGuard<'_, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+        timeout: Instant,
+    ) -> WaitTimeoutResult {
+        while condition(guard) {
+            if self.wait_until(guard, timeout).timed_out() {
+                return WaitTimeoutResult(condition(guard));
+            }
+        }
+        WaitTimeoutResult(false)
+    }
+
+    pub fn wait_while_for<T: ?Sized>(
+        &self,
+        guard: &mut MutexGuard<'_, T>,
+        condition: impl FnMut(&mut T) -> bool,
+        timeout: Duration,
+    ) -> WaitTimeoutResult {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.wait_while_until(guard, condition, deadline),
+            None => {
+                self.wait_while(guard, condition);
+                WaitTimeoutResult(false)
+            }
+        }
+    }
+
+    fn wait_deadline<T: ?Sized>(&self, guard: &mut MutexGuard<'_, T>, deadline: Option<Instant>) {
+        // Read before unlocking: a notify made after we unlock bumps it, and the wait sees that.
+        let seq = self.seq.load(Ordering::Acquire);
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        MutexGuard::unlocked(guard, || crate::futex::wait(&self.seq, seq, deadline));
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Condvar { .. }")
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn parking_lot_facade() {
+    static COUNT: Mutex<u64> = const_mutex(0);
+
+    let n = if cfg!(miri) { 50 } else { 10_000 };
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    *COUNT.lock() += 1;
+                }
+            });
+        }
+    });
+    assert_eq!(*COUNT.lock(), 4 * n);
+
+    let pair = Mutex::new((0u32, String::new()));
+    let mut second = MutexGuard::map(pair.lock(), |p| &mut p.1);
+    second.push_str("mapped");
+    assert!(pair.is_locked() && pair.try_lock().is_none());
+    drop(second);
+    assert!(!pair.is_locked());
+    assert!(MutexGuard::try_map(pair.lock(), |_| None::<&mut u32>).is_err());
+    let leaked = MutexGuard::leak(pair.lock());
+    leaked.0 = 7;
+    assert!(pair.try_lock().is_none());
+    // Safety: this thread locked it, and `leaked` isn't used again.
+    unsafe { pair.force_unlock() };
+    assert_eq!(pair.lock().0, 7);
+
+    let lock = RwLock::new(vec![1, 2, 3]);
+    {
+        let first = RwLockReadGuard::map(lock.read(), |v| &v[0]);
+        assert_eq!(*first, 1);
+        assert!(lock.try_read().is_some() && lock.try_write().is_none());
+    }
+    *RwLockWriteGuard::map(lock.write(), |v| &mut v[2]) = 30;
+    let mut w = lock.write();
+    RwLockWriteGuard::unlocked(&mut w, || assert!(lock.try_read().is_some()));
+    drop(w);
+    assert_eq!(*lock.read(), [1, 2, 30]);
+
+    let ready = Mutex::new(false);
+    let cv = Condvar::new();
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            *ready.lock() = true;
+            cv.notify_all();
+        });
+        let mut guard = ready.lock();
+        cv.wait_while(&mut guard, |ready| !*ready);
+        assert!(*guard);
+    });
+    let mut guard = ready.lock();
+    assert!(cv
+        .wait_for(&mut guard, Duration::from_millis(1))
+        .timed_out());
+    assert!(!cv.notify_one());
+}
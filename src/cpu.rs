@@ -0,0 +1,214 @@
+use crate::sync_shim::spin_loop;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+// What the processor we're running on can do beyond what the target guarantees, found out
+// once, the first time anyone asks. A build for plain x86_64 still gets to use what the
+// machine has, without a `-C target-cpu` that would make the binary fail on the machines
+// that don't.
+//
+// Backoff spins through `pause`: with WAITPKG that's one `tpause`, which lets the core drop
+// into a light sleep and gives its hyperthread sibling the pipeline until the deadline, rather
+// than a run of `pause` instructions.
+//
+// A spin-wait that knows the word it's waiting on can do better on aarch64, through
+// `wait_while_eq`: it loads the word with LDXR, which arms the exclusive monitor on its cache
+// line, and if it's unchanged sleeps in WFE until another core's store to the line clears the
+// monitor and wakes it. The core draws next to nothing meanwhile and stops hammering the line
+// with loads, which on big.LITTLE parts and many-core servers is most of what spinning costs.
+// It's only safe with the kernel's event stream on: a core may lose the monitor without an
+// event, when the line is evicted say, and then nothing but an interrupt would wake it, so
+// `relax_strategy` picks WFE only when the stream will wake it within about 100µs regardless.
+// Under loom, shuttle or Miri nothing is detected.
+
+/// The optional instructions this processor has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    /// x86_64's 16-byte compare-and-swap, for a 128-bit CAS.
+    pub cmpxchg16b: bool,
+    /// x86's `tpause`, `umonitor` and `umwait`: a pause that sleeps until a deadline.
+    pub waitpkg: bool,
+    /// On aarch64, whether the kernel's event stream is on, so a WFE wakes by itself within a
+    /// bounded time even if no event is sent.
+    pub wfe: bool,
+}
+
+static FEATURES: OnceLock<Features> = OnceLock::new();
+
+/// This processor's [`Features`].
+pub fn features() -> Features {
+    *FEATURES.get_or_init(|| {
+        if cfg!(any(loom, shuttle, miri)) {
+            Features::default()
+        } else {
+            sys::detect()
+        }
+    })
+}
+
+// Reference cycles, which the TSC counts, for one `pause`: somewhere between the 10 or so of
+// older x86 cores and the 140 of Skylake and later.
+#[cfg(all(target_arch = "x86_64", not(any(loom, shuttle, miri))))]
+const PAUSE_CYCLES: u64 = 64;
+
+/// Waits about as long as `spins` spin-loop hints.
+pub fn pause(spins: u32) {
+    #[cfg(all(target_arch = "x86_64", not(any(loom, shuttle, miri))))]
+    if spins > 1 && features().waitpkg {
+        // Safety: the processor has WAITPKG.
+        unsafe { sys::tpause(u64::from(spins) * PAUSE_CYCLES) };
+        return;
+    }
+    for _ in 0..spins {
+        spin_loop();
+    }
+}
+
+/// How a spin-wait on a word passes the time between looks at it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Relax {
+    /// Spin-loop hints, or `tpause` where there's WAITPKG.
+    Spin,
+    /// aarch64's WFE, woken by a store to the word or by the event stream.
+    Wfe,
+}
+
+/// The [`Relax`] this processor gets: WFE where the event stream is on, spinning elsewhere.
+pub fn relax_strategy() -> Relax {
+    if features().wfe {
+        Relax::Wfe
+    } else {
+        Relax::Spin
+    }
+}
+
+/// Waits a while for `word` to stop holding `value`: where the strategy is WFE, until a store
+/// to it or the next event-stream tick; elsewhere, as long as [`pause`] with `spins`. Returns
+/// early, or late, whenever; callers load the word again, with the ordering they need.
+pub fn wait_while_eq(word: &AtomicU32, value: u32, spins: u32) {
+    #[cfg(all(
+        target_arch = "aarch64",
+        target_os = "linux",
+        not(any(loom, shuttle, miri))
+    ))]
+    if relax_strategy() == Relax::Wfe {
+        // Safety: the event stream is on, so the wait ends within a tick.
+        unsafe { sys::wfe_while_eq(word, value) };
+        return;
+    }
+    if word.load(Ordering::Relaxed) == value {
+        pause(spins);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sys {
+    use super::Features;
+
+    pub fn detect() -> Features {
+        use std::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+
+        // Structured extended features, ECX bit 5.
+        let waitpkg = __get_cpuid_max(0).0 >= 7 && __cpuid_count(7, 0).ecx & (1 << 5) != 0;
+        Features {
+            cmpxchg16b: std::is_x86_feature_detected!("cmpxchg16b"),
+            waitpkg,
+            wfe: false,
+        }
+    }
+
+    /// Sleeps in the lighter, quicker-to-wake C0.1 state until `cycles` from now, or until
+    /// the OS's limit on a user-mode wait, whichever comes first.
+    ///
+    /// # Safety
+    ///
+    /// The processor has WAITPKG.
+    #[cfg(not(any(loom, shuttle, miri)))]
+    pub unsafe fn tpause(cycles: u64) {
+        let deadline = std::arch::x86_64::_rdtsc().wrapping_add(cycles);
+        std::arch::asm!(
+            "tpause {control:e}",
+            control = in(reg) 1u32,
+            in("eax") deadline as u32,
+            in("edx") (deadline >> 32) as u32,
+            options(nomem, nostack),
+        );
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+mod sys {
+    use super::Features;
+    use std::os::raw::c_ulong;
+
+    const AT_HWCAP: c_ulong = 16;
+    const HWCAP_EVTSTRM: c_ulong = 1 << 2;
+
+    extern "C" {
+        fn getauxval(kind: c_ulong) -> c_ulong;
+    }
+
+    pub fn detect() -> Features {
+        Features {
+            wfe: unsafe { getauxval(AT_HWCAP) } & HWCAP_EVTSTRM != 0,
+            ..Features::default()
+        }
+    }
+
+    /// Sleeps in WFE while `word` holds `value`, until a store to its cache line or an event.
+    ///
+    /// # Safety
+    ///
+    /// The kernel's event stream is on; without it, this can sleep until the next interrupt.
+    #[cfg(not(any(loom, shuttle, miri)))]
+    pub unsafe fn wfe_while_eq(word: &std::sync::atomic::AtomicU32, value: u32) {
+        // SEVL then WFE empties the event register, so the second WFE waits for a new event
+        // rather than returning on a stale one. LDXR arms the monitor on the word.
+        std::arch::asm!(
+            "sevl",
+            "wfe",
+            "ldxr {seen:w}, [{word}]",
+            "cmp {seen:w}, {value:w}",
+            "b.ne 2f",
+            "wfe",
+            "2:",
+            word = in(reg) word.as_ptr(),
+            value = in(reg) value,
+            seen = out(reg) _,
+            options(nostack, readonly),
+        );
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "aarch64", target_os = "linux")
+)))]
+mod sys {
+    use super::Features;
+
+    pub fn detect() -> Features {
+        Features::default()
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn cpu_features_are_detected_once() {
+    let f = features();
+    assert_eq!(features(), f);
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    assert_eq!(f.cmpxchg16b, std::is_x86_feature_detected!("cmpxchg16b"));
+    if cfg!(miri) {
+        assert_eq!(f, Features::default());
+    }
+    for spins in [0, 1, 64] {
+        pause(spins);
+    }
+
+    assert_eq!(relax_strategy() == Relax::Wfe, f.wfe);
+    // A word that's already changed doesn't wait, and one that hasn't comes back regardless.
+    let word = AtomicU32::new(1);
+    wait_while_eq(&word, 0, u32::MAX);
+    wait_while_eq(&word, 1, 64);
+}
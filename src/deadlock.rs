@@ -0,0 +1,203 @@
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::panic::Location;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+// A waits-for graph of every thread blocked on one of the crate's locks. A thread that's about
+// to block adds an edge to the lock it wants, and the lock has edges to whoever holds it; a
+// deadlock is a cycle, and the thread whose wait would close it panics with a report of every
+// thread on it instead of blocking. Checking then is enough: a cycle can only be closed by a
+// new wait, since a thread that takes a lock isn't waiting for anything.
+//
+// Locks are told apart by address. `rwlock::RwLock` reports to this module under the
+// `deadlock-detect` feature; other locks can do the same through `wait`, `acquired` and
+// `released`.
+
+struct Holder {
+    thread: ThreadId,
+    site: &'static Location<'static>,
+}
+
+struct Waiter {
+    thread: ThreadId,
+    lock: usize,
+    name: String,
+    site: &'static Location<'static>,
+    backtrace: Backtrace,
+}
+
+struct Graph {
+    holders: BTreeMap<usize, Vec<Holder>>,
+    // Few enough, only the threads blocked right now, to look through.
+    waiting: Vec<Waiter>,
+}
+
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    holders: BTreeMap::new(),
+    waiting: Vec::new(),
+});
+
+fn graph() -> std::sync::MutexGuard<'static, Graph> {
+    GRAPH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Graph {
+    fn waiter(&self, thread: ThreadId) -> Option<&Waiter> {
+        self.waiting.iter().find(|w| w.thread == thread)
+    }
+
+    fn stop_waiting(&mut self, thread: ThreadId) {
+        self.waiting.retain(|w| w.thread != thread);
+    }
+
+    // Follows the waits from `from`, keeping the threads on the way in `path`, and says
+    // whether they lead to `to`.
+    fn cycle(&self, from: ThreadId, to: ThreadId, path: &mut Vec<ThreadId>) -> bool {
+        if path.contains(&from) {
+            return false;
+        }
+        path.push(from);
+        let Some(waiter) = self.waiter(from) else {
+            path.pop();
+            return false;
+        };
+        for holder in self.holders.get(&waiter.lock).into_iter().flatten() {
+            if holder.thread == to || self.cycle(holder.thread, to, path) {
+                return true;
+            }
+        }
+        path.pop();
+        false
+    }
+
+    fn report(&self, cycle: &[ThreadId]) -> String {
+        let mut report = match cycle.len() {
+            1 => "deadlock: a thread waiting on itself".to_string(),
+            n => format!("deadlock: {} threads waiting on each other", n),
+        };
+        for (i, t) in cycle.iter().enumerate() {
+            let waiter = self.waiter(*t).unwrap();
+            let next = cycle[(i + 1) % cycle.len()];
+            let held = self.holders[&waiter.lock]
+                .iter()
+                .find(|h| h.thread == next)
+                .map(|h| h.site);
+            let _ = write!(
+                report,
+                "\n\nthread {} waits at {} for lock {:#x}, which thread {} took at {}\n{}",
+                waiter.name,
+                waiter.site,
+                waiter.lock,
+                self.waiter(next).unwrap().name,
+                held.unwrap(),
+                waiter.backtrace
+            );
+        }
+        report
+    }
+}
+
+fn name() -> String {
+    let t = thread::current();
+    match t.name() {
+        Some(name) => format!("'{}'", name),
+        None => format!("{:?}", t.id()),
+    }
+}
+
+/// Says the current thread is about to block on `lock`, wanting it at `site`. Panics, with
+/// every thread involved and where, if that would deadlock.
+pub fn wait(lock: usize, site: &'static Location<'static>) {
+    let me = thread::current().id();
+    let mut graph = graph();
+    graph.waiting.push(Waiter {
+        thread: me,
+        lock,
+        name: name(),
+        site,
+        backtrace: Backtrace::force_capture(),
+    });
+    let mut cycle = Vec::new();
+    if graph.cycle(me, me, &mut cycle) {
+        let report = graph.report(&cycle);
+        graph.stop_waiting(me);
+        drop(graph);
+        panic!("{}", report);
+    }
+}
+
+/// Says the current thread took `lock` at `site`, and so has stopped waiting for it if it
+/// was.
+pub fn acquired(lock: usize, site: &'static Location<'static>) {
+    let thread = thread::current().id();
+    let mut graph = graph();
+    graph.stop_waiting(thread);
+    graph
+        .holders
+        .entry(lock)
+        .or_default()
+        .push(Holder { thread, site });
+}
+
+/// Says a hold on `lock` has ended: the current thread's, or if it has none, someone's whose
+/// guard it was sent.
+pub fn released(lock: usize) {
+    let thread = thread::current().id();
+    let mut graph = graph();
+    if let Some(holders) = graph.holders.get_mut(&lock) {
+        let i = holders.iter().position(|h| h.thread == thread).unwrap_or(0);
+        holders.swap_remove(i);
+        if holders.is_empty() {
+            graph.holders.remove(&lock);
+        }
+    }
+}
+
+// Under lock-order, the second thread's out-of-order acquisition panics before it waits.
+#[cfg(not(feature = "lock-order"))]
+#[test]
+fn deadlock_reports_both_threads() {
+    use crate::rwlock::RwLock;
+    use std::sync::Barrier;
+
+    let (a, b) = (RwLock::new(0), RwLock::new(0));
+    let barrier = Barrier::new(2);
+    let results: Vec<_> = thread::scope(|s| {
+        let lock_both = |name: &str, first: &'static str| {
+            let (a, b, barrier) = (&a, &b, &barrier);
+            thread::Builder::new()
+                .name(name.to_string())
+                .spawn_scoped(s, move || {
+                    let (x, y) = if first == "a" { (a, b) } else { (b, a) };
+                    let _x = x.write();
+                    barrier.wait();
+                    *y.write() += 1;
+                })
+                .unwrap()
+        };
+        let threads = vec![lock_both("ab", "a"), lock_both("ba", "b")];
+        threads.into_iter().map(|t| t.join()).collect()
+    });
+    // Whichever waited second panicked, which let the other through.
+    let panics: Vec<String> = results
+        .into_iter()
+        .filter_map(|r| r.err())
+        .map(|e| *e.downcast::<String>().unwrap())
+        .collect();
+    assert_eq!(panics.len(), 1, "{:?}", panics);
+    let report = &panics[0];
+    assert!(report.starts_with("deadlock: 2 threads waiting on each other"));
+    assert!(report.contains("thread 'ab' waits at src/deadlock.rs"));
+    assert!(report.contains("thread 'ba' waits at src/deadlock.rs"));
+    assert_eq!(*a.read() + *b.read(), 1);
+}
+
+#[test]
+#[should_panic(expected = "deadlock: a thread waiting on itself")]
+fn deadlock_reports_upgrading_a_read() {
+    let l = crate::rwlock::RwLock::new(0);
+    let _r = l.read();
+    *l.write() += 1;
+}
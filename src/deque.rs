@@ -0,0 +1,367 @@
+use crate::cache_padded::CachePadded;
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicIsize, AtomicPtr, Ordering};
+use std::sync::Arc;
+
+const MIN_CAP: usize = 32;
+// Upper bound on how many items one steal_batch moves.
+const MAX_BATCH: usize = 32;
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn alloc(cap: usize) -> *mut Self {
+        debug_assert!(cap.is_power_of_two());
+        Box::into_raw(Box::new(Self {
+            slots: (0..cap)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+        }))
+    }
+
+    fn cap(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn at(&self, i: isize) -> *mut MaybeUninit<T> {
+        self.slots[i as usize & (self.cap() - 1)].get()
+    }
+
+    unsafe fn write(&self, i: isize, t: T) {
+        self.at(i).write(MaybeUninit::new(t));
+    }
+
+    // Stealers read a slot before claiming it, so the result may be a stale (even torn) copy
+    // of an item that a racing pop/steal already took and the worker has since overwritten.
+    // That's the well-known benign race of Chase–Lev; callers only assume_init the copy after
+    // winning the CAS on front and otherwise just let it go (a MaybeUninit never drops T).
+    // Rust has no atomic memcpy to make it race-free, so Miri still reports it.
+    unsafe fn read(&self, i: isize) -> MaybeUninit<T> {
+        self.at(i).read()
+    }
+}
+
+struct Inner<T> {
+    // Stealers take from front, the worker pushes and pops at back. Padded so stealers don't
+    // false-share with the worker.
+    front: CachePadded<AtomicIsize>,
+    back: CachePadded<AtomicIsize>,
+    buffer: AtomicPtr<Buffer<T>>,
+    // Buffers replaced by a resize, kept alive until the deque is dropped because a stealer
+    // may still be reading from them. Only the worker touches this.
+    retired: UnsafeCell<Vec<*mut Buffer<T>>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let back = *self.back.get_mut();
+        let buffer = *self.buffer.get_mut();
+        unsafe {
+            for i in *self.front.get_mut()..back {
+                ptr::drop_in_place((*(*buffer).at(i)).as_mut_ptr());
+            }
+            drop(Box::from_raw(buffer));
+            for old in self.retired.get_mut().drain(..) {
+                drop(Box::from_raw(old));
+            }
+        }
+    }
+}
+
+/// The result of a steal attempt.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    Empty,
+    Success(T),
+    // Lost a race with another stealer or the worker; the deque may still have items.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    pub fn success(self) -> Option<T> {
+        match self {
+            Steal::Success(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn is_retry(&self) -> bool {
+        matches!(self, Steal::Retry)
+    }
+}
+
+/// The owner side of a Chase–Lev work-stealing deque: pushes and pops LIFO at the back.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    // Send but not Sync: only one thread may act as the worker.
+    _marker: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: Send> Send for Worker<T> {}
+
+impl<T> Worker<T> {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                front: CachePadded::new(AtomicIsize::new(0)),
+                back: CachePadded::new(AtomicIsize::new(0)),
+                buffer: AtomicPtr::new(Buffer::alloc(MIN_CAP)),
+                retired: UnsafeCell::new(Vec::new()),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let b = self.inner.back.load(Ordering::Relaxed);
+        let f = self.inner.front.load(Ordering::SeqCst);
+        cmp::max(b - f, 0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Makes sure there's room for `extra` more items past back.
+    fn reserve(&self, extra: usize) -> *mut Buffer<T> {
+        let inner = &*self.inner;
+        let b = inner.back.load(Ordering::Relaxed);
+        let f = inner.front.load(Ordering::Acquire);
+        let buffer = inner.buffer.load(Ordering::Relaxed);
+        let cap = unsafe { (*buffer).cap() };
+        let len = (b - f) as usize;
+        if len + extra <= cap {
+            return buffer;
+        }
+        let new = Buffer::alloc((len + extra).next_power_of_two());
+        unsafe {
+            for i in f..b {
+                ptr::copy_nonoverlapping((*buffer).at(i), (*new).at(i), 1);
+            }
+            (*inner.retired.get()).push(buffer);
+        }
+        // Release: stealers that load the new buffer must see the copied items.
+        inner.buffer.store(new, Ordering::Release);
+        new
+    }
+
+    pub fn push(&self, t: T) {
+        let buffer = self.reserve(1);
+        let b = self.inner.back.load(Ordering::Relaxed);
+        unsafe { (*buffer).write(b, t) };
+        // Release: a stealer that sees the new back also sees the item.
+        self.inner.back.store(b + 1, Ordering::Release);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let b = inner.back.load(Ordering::Relaxed) - 1;
+        let buffer = inner.buffer.load(Ordering::Relaxed);
+        inner.back.store(b, Ordering::Relaxed);
+        // SeqCst: the decrement of back must be visible before we read front, otherwise a
+        // stealer and this pop can both take the last item (the classic Chase–Lev race).
+        atomic::fence(Ordering::SeqCst);
+        let f = inner.front.load(Ordering::Relaxed);
+        let len = b - f;
+        if len < 0 {
+            inner.back.store(b + 1, Ordering::Relaxed);
+            return None;
+        }
+        let value = unsafe { (*buffer).read(b) };
+        if len == 0 {
+            // Last item: race the stealers for it through front.
+            let won = inner
+                .front
+                .compare_exchange(f, f + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            inner.back.store(b + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        Some(unsafe { value.assume_init() })
+    }
+}
+
+impl<T> Default for Worker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A handle that steals FIFO from the front of a [`Worker`]'s deque.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T> Stealer<T> {
+    pub fn is_empty(&self) -> bool {
+        let f = self.inner.front.load(Ordering::Acquire);
+        atomic::fence(Ordering::SeqCst);
+        let b = self.inner.back.load(Ordering::Acquire);
+        b - f <= 0
+    }
+
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let f = inner.front.load(Ordering::Acquire);
+        // SeqCst: pairs with the fence in pop. Without it we could read a back from before the
+        // worker's decrement and take the item the worker is popping.
+        atomic::fence(Ordering::SeqCst);
+        let b = inner.back.load(Ordering::Acquire);
+        if b - f <= 0 {
+            return Steal::Empty;
+        }
+        let buffer = inner.buffer.load(Ordering::Acquire);
+        let value = unsafe { (*buffer).read(f) };
+        if inner
+            .front
+            .compare_exchange(f, f + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+        Steal::Success(unsafe { value.assume_init() })
+    }
+
+    /// Moves about half of the items (at most `MAX_BATCH`) into `dest`, publishing them to
+    /// `dest` all at once, and returns how many were moved.
+    pub fn steal_batch(&self, dest: &Worker<T>) -> Steal<usize> {
+        if Arc::ptr_eq(&self.inner, &dest.inner) {
+            return if dest.is_empty() {
+                Steal::Empty
+            } else {
+                Steal::Success(0)
+            };
+        }
+        let inner = &*self.inner;
+        let mut f = inner.front.load(Ordering::Acquire);
+        atomic::fence(Ordering::SeqCst);
+        let mut b = inner.back.load(Ordering::Acquire);
+        let len = b - f;
+        if len <= 0 {
+            return Steal::Empty;
+        }
+        let n = cmp::min((len as usize).div_ceil(2), MAX_BATCH);
+        let dest_buffer = dest.reserve(n);
+        let dest_b = dest.inner.back.load(Ordering::Relaxed);
+        let mut moved = 0;
+        // The worker pops from the back without touching front unless it's taking the last
+        // item, so one CAS can't claim a range. Claim items one at a time instead, re-checking
+        // back (behind the same fence as steal) before each.
+        while moved < n {
+            if moved > 0 {
+                atomic::fence(Ordering::SeqCst);
+                b = inner.back.load(Ordering::Acquire);
+                if b - f <= 0 {
+                    break;
+                }
+            }
+            let buffer = inner.buffer.load(Ordering::Acquire);
+            let value = unsafe { (*buffer).read(f) };
+            if inner
+                .front
+                .compare_exchange(f, f + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_err()
+            {
+                break;
+            }
+            unsafe { (*dest_buffer).at(dest_b + moved as isize).write(value) };
+            f += 1;
+            moved += 1;
+        }
+        if moved == 0 {
+            return Steal::Retry;
+        }
+        // Release: dest's own stealers must see the items once they see the new back.
+        dest.inner
+            .back
+            .store(dest_b + moved as isize, Ordering::Release);
+        Steal::Success(moved)
+    }
+}
+
+#[test]
+#[cfg_attr(
+    miri,
+    ignore = "a stealer's speculative read of a slot races with the worker"
+)]
+fn deque_every_item_once() {
+    use std::sync::atomic::AtomicUsize;
+    let worker = Worker::new();
+    let taken = &AtomicUsize::new(0);
+    let sum = &AtomicUsize::new(0);
+    const N: usize = 20_000;
+    std::thread::scope(|scope| {
+        for _ in 0..3 {
+            let s = worker.stealer();
+            scope.spawn(move || {
+                while taken.load(Ordering::Relaxed) < N {
+                    match s.steal() {
+                        Steal::Success(v) => {
+                            sum.fetch_add(v, Ordering::Relaxed);
+                            taken.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Steal::Retry => {}
+                        Steal::Empty => std::thread::yield_now(),
+                    }
+                }
+            });
+        }
+        for i in 0..N {
+            worker.push(i);
+            if i % 3 == 0 {
+                if let Some(v) = worker.pop() {
+                    sum.fetch_add(v, Ordering::Relaxed);
+                    taken.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        while let Some(v) = worker.pop() {
+            sum.fetch_add(v, Ordering::Relaxed);
+            taken.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    assert_eq!(taken.load(Ordering::Relaxed), N);
+    assert_eq!(sum.load(Ordering::Relaxed), (0..N).sum::<usize>());
+}
+
+#[test]
+fn deque_steal_batch() {
+    let victim = Worker::new();
+    for i in 0..100 {
+        victim.push(i);
+    }
+    let thief = Worker::new();
+    assert_eq!(victim.stealer().steal_batch(&thief), Steal::Success(32));
+    assert_eq!(victim.len(), 68);
+    assert_eq!(thief.len(), 32);
+    // The thief got the oldest items, and pops them newest-first.
+    assert_eq!(thief.pop(), Some(31));
+    assert_eq!(thief.stealer().steal(), Steal::Success(0));
+    assert_eq!(victim.pop(), Some(99));
+    assert_eq!(victim.stealer().steal(), Steal::Success(32));
+}
@@ -0,0 +1,319 @@
+use crate::cache_padded::CachePadded;
+use std::cell::UnsafeCell;
+use std::hint;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// How consumers wait for new entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// Spin on the sequences: the lowest latency, at the cost of a core per consumer.
+    BusySpin,
+    /// Sleep on a condition variable that every publish signals.
+    Blocking,
+}
+
+// Padded: every sequence is written by one thread and polled by others.
+type Sequence = CachePadded<AtomicU64>;
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    // published[s % capacity] == s + 1 once sequence s is readable.
+    published: Box<[AtomicU64]>,
+    // The next sequence a producer will claim.
+    claim: Sequence,
+    // How many entries each consumer has finished with.
+    consumed: Box<[Sequence]>,
+    // The consumers each one has to stay behind; empty means it reads straight off the ring.
+    after: Box<[Box<[usize]>]>,
+    wait: WaitStrategy,
+    lock: Mutex<()>,
+    signal: Condvar,
+}
+
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    fn slot(&self, seq: u64) -> usize {
+        (seq & (self.capacity() - 1)) as usize
+    }
+
+    fn wake(&self) {
+        if self.wait == WaitStrategy::Blocking {
+            // Taking the lock orders this with a consumer that checked for entries and is about
+            // to sleep, so it can't miss the notification.
+            drop(self.lock.lock().unwrap());
+            self.signal.notify_all();
+        }
+    }
+
+    // The sequence `consumer` may read up to (exclusive).
+    fn limit(&self, consumer: usize, next: u64) -> u64 {
+        let after = &self.after[consumer];
+        if !after.is_empty() {
+            // Acquire: pairs with the upstream consumer's Release, which in turn happened after
+            // it saw the entries published.
+            return after
+                .iter()
+                .map(|&c| self.consumed[c].load(Ordering::Acquire))
+                .min()
+                .unwrap();
+        }
+        // Producers publish out of order, so only the contiguous run from `next` counts.
+        let claimed = self.claim.load(Ordering::Relaxed);
+        let mut seq = next;
+        while seq < claimed && self.published[self.slot(seq)].load(Ordering::Acquire) == seq + 1 {
+            seq += 1;
+        }
+        seq
+    }
+}
+
+/// Describes the consumer graph of a [`Disruptor`] before it's built.
+pub struct Builder {
+    capacity: usize,
+    wait: WaitStrategy,
+    after: Vec<Box<[usize]>>,
+    start: u64,
+}
+
+/// Names a consumer while the graph is being built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsumerId(usize);
+
+impl Builder {
+    /// A ring with `capacity` (rounded up to a power of two) preallocated entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1).next_power_of_two(),
+            wait: WaitStrategy::BusySpin,
+            after: Vec::new(),
+            start: 0,
+        }
+    }
+
+    pub fn wait_strategy(mut self, wait: WaitStrategy) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Starts the sequences at `seq` rather than 0, so a test or fuzz target can run the ring
+    /// with the high bits of its sequences set. They're never meant to wrap, in 2^64
+    /// entries, so `seq` leaves room for every entry the run publishes.
+    #[cfg(any(test, fuzzing))]
+    #[doc(hidden)]
+    pub fn start_sequence(mut self, seq: u64) -> Self {
+        self.start = seq;
+        self
+    }
+
+    /// Adds a consumer that sees each entry only after every consumer in `after` is done with
+    /// it.
+    pub fn consumer(&mut self, after: &[ConsumerId]) -> ConsumerId {
+        self.after.push(after.iter().map(|c| c.0).collect());
+        ConsumerId(self.after.len() - 1)
+    }
+
+    /// Returns a producer handle and the consumers, in the order they were added.
+    pub fn build<T: Default>(self) -> (Producer<T>, Vec<Consumer<T>>) {
+        assert!(!self.after.is_empty(), "a disruptor needs a consumer");
+        let (n, start) = (self.after.len(), self.start);
+        let shared = Arc::new(Shared {
+            slots: (0..self.capacity)
+                .map(|_| UnsafeCell::new(T::default()))
+                .collect(),
+            published: (0..self.capacity).map(|_| AtomicU64::new(0)).collect(),
+            claim: CachePadded::new(AtomicU64::new(start)),
+            consumed: (0..n)
+                .map(|_| CachePadded::new(AtomicU64::new(start)))
+                .collect(),
+            after: self.after.into_boxed_slice(),
+            wait: self.wait,
+            lock: Mutex::new(()),
+            signal: Condvar::new(),
+        });
+        let consumers = (0..n)
+            .map(|id| Consumer {
+                shared: Arc::clone(&shared),
+                id,
+                next: start,
+            })
+            .collect();
+        (Producer { shared }, consumers)
+    }
+}
+
+/// A multi-producer handle to a disruptor ring; clone it for every producing thread.
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Producer<T> {
+    /// Claims the next entry, fills it in with `f` and publishes it.
+    pub fn publish(&self, f: impl FnOnce(&mut T)) {
+        let mut f = Some(f);
+        self.publish_batch(1, |_, entry| f.take().unwrap()(entry));
+    }
+
+    /// Claims `n` consecutive entries with a single atomic add, fills each one in with `f`
+    /// (given its sequence number), and publishes them. Waits while the slowest consumer is a
+    /// full ring behind.
+    pub fn publish_batch(&self, n: usize, mut f: impl FnMut(u64, &mut T)) {
+        let shared = &*self.shared;
+        assert!(n as u64 <= shared.capacity(), "batch larger than the ring");
+        let start = shared.claim.fetch_add(n as u64, Ordering::Relaxed);
+        let end = start + n as u64;
+        // Consumers only ever fall further behind the ones they follow, so the minimum over all
+        // of them is the minimum over the last stage.
+        // Acquire: every consumer is done reading the entries we're about to overwrite.
+        while shared
+            .consumed
+            .iter()
+            .map(|c| c.load(Ordering::Acquire))
+            .min()
+            .unwrap()
+            + shared.capacity()
+            < end
+        {
+            thread::yield_now();
+        }
+        for seq in start..end {
+            let slot = shared.slot(seq);
+            // Safety: we claimed seq, and no consumer will look at it until it's published.
+            f(seq, unsafe { &mut *shared.slots[slot].get() });
+            // Release: publishes the entry.
+            shared.published[slot].store(seq + 1, Ordering::Release);
+        }
+        shared.wake();
+    }
+}
+
+/// One stage of the pipeline. Each consumer runs on a single thread.
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+    next: u64,
+}
+
+impl<T> Consumer<T> {
+    /// Runs `f` on every entry that's ready (with its sequence number and whether it's the
+    /// last of the batch), then marks them done in one store. Returns how many there were.
+    pub fn poll(&mut self, mut f: impl FnMut(u64, &T, bool)) -> usize {
+        let shared = &*self.shared;
+        let limit = shared.limit(self.id, self.next);
+        for seq in self.next..limit {
+            // Safety: published, and producers won't reuse the slot until we move past it.
+            let entry = unsafe { &*shared.slots[shared.slot(seq)].get() };
+            f(seq, entry, seq + 1 == limit);
+        }
+        let n = (limit - self.next) as usize;
+        if n > 0 {
+            self.next = limit;
+            // Release: our reads are done before producers or downstream consumers move on.
+            shared.consumed[self.id].store(limit, Ordering::Release);
+            if !shared.after.iter().all(|a| a.is_empty()) {
+                shared.wake();
+            }
+        }
+        n
+    }
+
+    /// Like [`poll`](Self::poll), but waits according to the ring's [`WaitStrategy`] until
+    /// at least one entry is ready.
+    pub fn process(&mut self, f: impl FnMut(u64, &T, bool)) -> usize {
+        let shared = &*self.shared;
+        match shared.wait {
+            WaitStrategy::BusySpin => {
+                while shared.limit(self.id, self.next) == self.next {
+                    hint::spin_loop();
+                }
+            }
+            WaitStrategy::Blocking => {
+                let mut lock = shared.lock.lock().unwrap();
+                while shared.limit(self.id, self.next) == self.next {
+                    lock = shared.signal.wait(lock).unwrap();
+                }
+            }
+        }
+        self.poll(f)
+    }
+}
+
+#[cfg(test)]
+fn run_pipeline(wait: WaitStrategy, start: u64) {
+    use std::sync::atomic::AtomicBool;
+
+    let mut builder = Builder::new(64).wait_strategy(wait).start_sequence(start);
+    let first = builder.consumer(&[]);
+    builder.consumer(&[first]);
+    let (producer, mut consumers) = builder.build::<u64>();
+    let mut second = consumers.pop().unwrap();
+    let mut first = consumers.pop().unwrap();
+
+    let per_producer: u64 = if cfg!(miri) { 200 } else { 2000 };
+    let total = 3 * per_producer;
+    let seen = &(0..total)
+        .map(|_| AtomicBool::new(false))
+        .collect::<Vec<_>>();
+    let sum = thread::scope(|s| {
+        for _ in 0..3 {
+            let producer = producer.clone();
+            s.spawn(move || {
+                for _ in 0..per_producer / 4 {
+                    producer.publish_batch(4, |seq, entry| *entry = (seq - start) * 10);
+                }
+            });
+        }
+        drop(producer);
+        s.spawn(move || {
+            let mut done = 0;
+            while done < total {
+                done += first.process(|seq, &v, _| {
+                    assert_eq!(v, (seq - start) * 10);
+                    seen[(seq - start) as usize].store(true, Ordering::Relaxed);
+                }) as u64;
+            }
+        });
+        let mut done = 0;
+        let mut sum = 0;
+        while done < total {
+            done += second.process(|seq, &v, _| {
+                // The first stage has always finished with an entry before we get it.
+                assert!(seen[(seq - start) as usize].load(Ordering::Relaxed));
+                sum += v;
+            }) as u64;
+        }
+        sum
+    });
+    assert_eq!(sum, (0..total).map(|s| s * 10).sum::<u64>());
+}
+
+#[test]
+fn disruptor_pipeline_busy_spin() {
+    run_pipeline(WaitStrategy::BusySpin, 0);
+}
+
+#[test]
+fn disruptor_pipeline_blocking() {
+    run_pipeline(WaitStrategy::Blocking, 0);
+}
+
+#[test]
+fn disruptor_pipeline_high_sequences() {
+    // Every bit above the slot's set, with a million entries to spare.
+    run_pipeline(WaitStrategy::BusySpin, u64::MAX - (1 << 20));
+}
@@ -0,0 +1,274 @@
+use crate::tsan;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+// Epochs advance in steps of 2 so the low bit of a participant's epoch can mean "pinned".
+const PINNED: usize = 1;
+// Garbage retired in epoch e may still be reachable by threads pinned in e or e + 2, so it's
+// freed once the global epoch has reached e + 4.
+const GRACE: usize = 4;
+// How often a thread tries to advance the epoch and collect, counted in pins.
+const PINS_BETWEEN_COLLECT: usize = 64;
+// Retiring this many objects triggers a collection right away.
+const MAX_LOCAL_GARBAGE: usize = 128;
+
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+// Prepend-only list of participants. Records are never freed; a thread that exits marks its
+// record inactive so a later thread can adopt it (and any garbage it left behind).
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+struct Deferred {
+    ptr: *mut u8,
+    drop_fn: unsafe fn(*mut u8),
+    epoch: usize,
+}
+
+unsafe fn drop_box<T>(ptr: *mut u8) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+struct Participant {
+    next: *const Participant,
+    active: AtomicBool,
+    epoch: AtomicUsize,
+    // Only touched by the thread that currently owns the record (active == true).
+    guards: Cell<usize>,
+    pins: Cell<usize>,
+    garbage: UnsafeCell<Vec<Deferred>>,
+}
+
+unsafe impl Sync for Participant {}
+
+impl Participant {
+    fn acquire() -> &'static Participant {
+        let mut p = PARTICIPANTS.load(Ordering::Acquire);
+        while !p.is_null() {
+            let participant = unsafe { &*p };
+            if !participant.active.load(Ordering::Relaxed)
+                && participant
+                    .active
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return participant;
+            }
+            p = participant.next as *mut Participant;
+        }
+        let new = Box::leak(Box::new(Participant {
+            next: ptr::null(),
+            active: AtomicBool::new(true),
+            epoch: AtomicUsize::new(0),
+            guards: Cell::new(0),
+            pins: Cell::new(0),
+            garbage: UnsafeCell::new(Vec::new()),
+        }));
+        let mut head = PARTICIPANTS.load(Ordering::Relaxed);
+        loop {
+            new.next = head;
+            match PARTICIPANTS.compare_exchange_weak(
+                head,
+                new,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return new,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    fn release(&'static self) {
+        self.collect();
+        // Release: whoever adopts the record next must see our garbage list.
+        self.active.store(false, Ordering::Release);
+    }
+
+    fn pin(&'static self) -> Guard {
+        let guards = self.guards.get();
+        self.guards.set(guards + 1);
+        if guards == 0 {
+            let epoch = EPOCH.load(Ordering::Relaxed);
+            self.epoch.store(epoch | PINNED, Ordering::Relaxed);
+            // SeqCst: our pin must be visible to any thread that tries to advance the epoch
+            // before we go on to read shared pointers. Otherwise it could advance twice and
+            // free something we're about to dereference.
+            atomic::fence(Ordering::SeqCst);
+            let pins = self.pins.get() + 1;
+            self.pins.set(pins);
+            if pins.is_multiple_of(PINS_BETWEEN_COLLECT) {
+                self.collect();
+            }
+        }
+        Guard {
+            participant: self,
+            release_on_drop: false,
+            _not_send: PhantomData,
+        }
+    }
+
+    fn unpin(&self) {
+        let guards = self.guards.get() - 1;
+        self.guards.set(guards);
+        if guards == 0 {
+            // Release: all our reads of shared nodes happen before the epoch can move on.
+            self.epoch.store(0, Ordering::Release);
+        }
+    }
+
+    fn collect(&self) {
+        let global = try_advance();
+        // Split off the expired garbage before running any destructors, which may themselves
+        // pin and retire more objects.
+        let expired: Vec<Deferred> = {
+            let garbage = unsafe { &mut *self.garbage.get() };
+            let (expired, keep) = garbage
+                .drain(..)
+                .partition(|d| global.wrapping_sub(d.epoch) >= GRACE);
+            *garbage = keep;
+            expired
+        };
+        for d in expired {
+            unsafe { (d.drop_fn)(d.ptr) };
+        }
+    }
+}
+
+// Advances the global epoch if every pinned participant has caught up with it, and returns
+// the (possibly new) global epoch.
+fn try_advance() -> usize {
+    let global = EPOCH.load(Ordering::Relaxed);
+    atomic::fence(Ordering::SeqCst);
+    let mut p = PARTICIPANTS.load(Ordering::Acquire);
+    while !p.is_null() {
+        let participant = unsafe { &*p };
+        let epoch = participant.epoch.load(Ordering::Relaxed);
+        if epoch & PINNED != 0 && epoch & !PINNED != global {
+            return global;
+        }
+        // For the fence below.
+        tsan::acquire(&participant.epoch);
+        p = participant.next as *mut Participant;
+    }
+    // Acquire: pairs with unpin's Release so everything those threads read is done before
+    // garbage from two epochs ago gets freed.
+    atomic::fence(Ordering::Acquire);
+    let next = global.wrapping_add(2);
+    match EPOCH.compare_exchange(global, next, Ordering::Release, Ordering::Relaxed) {
+        Ok(_) => next,
+        Err(current) => current,
+    }
+}
+
+struct Handle {
+    participant: &'static Participant,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.participant.release();
+    }
+}
+
+thread_local! {
+    static HANDLE: Handle = Handle {
+        participant: Participant::acquire(),
+    };
+}
+
+/// Pins the current thread, keeping every object reachable through shared pointers alive
+/// until the guard is dropped.
+pub fn pin() -> Guard {
+    HANDLE
+        .try_with(|h| h.participant.pin())
+        .unwrap_or_else(|_| {
+            // The thread-local is already gone (we're in a TLS destructor), so borrow a
+            // participant just for this guard.
+            let mut guard = Participant::acquire().pin();
+            guard.release_on_drop = true;
+            guard
+        })
+}
+
+/// Proof that the current thread is pinned; see [`pin`].
+pub struct Guard {
+    participant: &'static Participant,
+    release_on_drop: bool,
+    // Pins are per thread.
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Guard {
+    /// Frees the `Box`-allocated `ptr` once no pinned thread can still be looking at it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, already be unreachable for threads that pin after
+    /// this call, and not be retired twice.
+    pub unsafe fn defer_destroy<T: Send + 'static>(&self, ptr: *mut T) {
+        let garbage = &mut *self.participant.garbage.get();
+        garbage.push(Deferred {
+            ptr: ptr as *mut u8,
+            drop_fn: drop_box::<T>,
+            epoch: EPOCH.load(Ordering::Relaxed),
+        });
+        if garbage.len() >= MAX_LOCAL_GARBAGE {
+            self.participant.collect();
+        }
+    }
+
+    /// Tries to advance the epoch and free this thread's expired garbage.
+    pub fn flush(&self) {
+        self.participant.collect();
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.participant.unpin();
+        if self.release_on_drop {
+            self.participant.release();
+        }
+    }
+}
+
+#[test]
+fn epoch_waits_for_pinned_threads() {
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    let tracker = Arc::new(());
+    let (pinned_tx, pinned_rx) = mpsc::channel();
+    let (unpin_tx, unpin_rx) = mpsc::channel::<()>();
+    let reader = std::thread::spawn(move || {
+        let _guard = pin();
+        pinned_tx.send(()).unwrap();
+        unpin_rx.recv().unwrap();
+    });
+    pinned_rx.recv().unwrap();
+
+    let guard = pin();
+    unsafe { guard.defer_destroy(Box::into_raw(Box::new(Arc::clone(&tracker)))) };
+    drop(guard);
+    for _ in 0..10 {
+        pin().flush();
+    }
+    assert_eq!(
+        Arc::strong_count(&tracker),
+        2,
+        "freed while a reader was pinned"
+    );
+
+    unpin_tx.send(()).unwrap();
+    reader.join().unwrap();
+    // Other tests may be pinned concurrently and hold the epoch back for a while.
+    for _ in 0..10_000 {
+        if Arc::strong_count(&tracker) == 1 {
+            break;
+        }
+        pin().flush();
+        std::thread::yield_now();
+    }
+    assert_eq!(Arc::strong_count(&tracker), 1);
+}
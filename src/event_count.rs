@@ -0,0 +1,242 @@
+use crate::sync_shim::atomic::{self, AtomicU64, Ordering};
+#[cfg(any(loom, shuttle))]
+use crate::sync_shim::Condvar;
+use crate::sync_shim::Mutex;
+use std::task::Waker;
+use std::time::Instant;
+
+// The low half of the state counts threads between prepare_wait and the end of their wait;
+// the high half is an epoch every notify bumps. Waiters sleep on `wake`, a word every notify
+// bumps after the epoch, through `futex`; under loom and shuttle, which can't see a futex
+// wait, they sleep on a condvar instead.
+const WAITER: u64 = 1;
+const EPOCH: u64 = 1 << 32;
+
+/// Lets threads sleep until a condition on lock-free state may have changed, without that
+/// state knowing about sleepers.
+///
+/// A waiter calls [`prepare_wait`](Self::prepare_wait), checks its condition again, and then
+/// either cancels or [`wait`](Self::wait)s. A notifier changes the state and then calls
+/// [`notify_one`](Self::notify_one) or [`notify_all`](Self::notify_all), which cost a single
+/// fence and load while nobody is waiting. A notify that lands between prepare_wait and wait
+/// is never lost.
+///
+/// A thread that needs to wait on several event counts at once can instead
+/// [`watch`](Self::watch) each of them with the [`waker`](crate::parker::Unparker::waker) of
+/// its own [`Parker`](crate::parker::Parker), and a task can watch one with its own waker.
+pub struct EventCount {
+    state: AtomicU64,
+    lock: Mutex<Watchers>,
+    #[cfg(not(any(loom, shuttle)))]
+    wake: std::sync::atomic::AtomicU32,
+    #[cfg(any(loom, shuttle))]
+    condvar: Condvar,
+}
+
+struct Watchers {
+    next_id: u64,
+    list: Vec<(u64, Waker)>,
+}
+
+/// A snapshot of the epoch taken by [`EventCount::prepare_wait`].
+#[must_use = "pass it to wait, or call cancel_wait"]
+pub struct WaitKey {
+    epoch: u64,
+}
+
+/// A registration made by [`EventCount::watch`].
+#[must_use = "pass it to unwatch"]
+pub struct WatchKey {
+    id: u64,
+}
+
+impl EventCount {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            lock: Mutex::new(Watchers {
+                next_id: 0,
+                list: Vec::new(),
+            }),
+            #[cfg(not(shuttle))]
+            wake: std::sync::atomic::AtomicU32::new(0),
+            #[cfg(shuttle)]
+            condvar: Condvar::new(),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            lock: Mutex::new(Watchers {
+                next_id: 0,
+                list: Vec::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn prepare_wait(&self) -> WaitKey {
+        // SeqCst: orders our registration before the condition re-check that follows, against
+        // the notifier's state change and its fence. The fence is what keeps a re-check made
+        // with weaker loads from moving above the registration.
+        let state = self.state.fetch_add(WAITER, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        WaitKey {
+            epoch: state & !(EPOCH - 1),
+        }
+    }
+
+    /// Backs out of a prepare_wait whose condition turned out to hold already.
+    pub fn cancel_wait(&self, _key: WaitKey) {
+        self.state.fetch_sub(WAITER, Ordering::Relaxed);
+    }
+
+    /// Sleeps until a notify that came after `key` was taken.
+    pub fn wait(&self, key: WaitKey) {
+        self.wait_until(key, None);
+    }
+
+    /// Like [`wait`](Self::wait), but gives up at `deadline`. Returns whether it was notified.
+    pub fn wait_deadline(&self, key: WaitKey, deadline: Instant) -> bool {
+        self.wait_until(key, Some(deadline))
+    }
+
+    #[cfg(not(any(loom, shuttle)))]
+    fn wait_until(&self, key: WaitKey, deadline: Option<Instant>) -> bool {
+        let mut notified = true;
+        loop {
+            // Read before the epoch: a notify that bumps the epoch after that bumps this
+            // after, and the futex sees it's changed.
+            let wake = self.wake.load(std::sync::atomic::Ordering::SeqCst);
+            if self.state.load(Ordering::SeqCst) & !(EPOCH - 1) != key.epoch {
+                break;
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                notified = false;
+                break;
+            }
+            crate::futex::wait(&self.wake, wake, deadline);
+        }
+        self.state.fetch_sub(WAITER, Ordering::Relaxed);
+        notified
+    }
+
+    #[cfg(any(loom, shuttle))]
+    fn wait_until(&self, key: WaitKey, deadline: Option<Instant>) -> bool {
+        let mut lock = self.lock.lock().unwrap();
+        let mut notified = true;
+        while self.state.load(Ordering::SeqCst) & !(EPOCH - 1) == key.epoch {
+            lock = match deadline {
+                None => self.condvar.wait(lock).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        notified = false;
+                        break;
+                    }
+                    self.condvar.wait_timeout(lock, deadline - now).unwrap().0
+                }
+            };
+        }
+        drop(lock);
+        self.state.fetch_sub(WAITER, Ordering::Relaxed);
+        notified
+    }
+
+    /// Has every notify wake `waker` until [`unwatch`](Self::unwatch)ed.
+    ///
+    /// Like prepare_wait, the caller should check its condition again after watching; a
+    /// notify that comes after that wakes it.
+    pub fn watch(&self, waker: &Waker) -> WatchKey {
+        let mut watchers = self.lock.lock().unwrap();
+        let id = watchers.next_id;
+        watchers.next_id += 1;
+        watchers.list.push((id, waker.clone()));
+        drop(watchers);
+        // Counted as a waiter so notify doesn't skip us. Listed before counting, so a
+        // notifier that sees the count finds us in the list.
+        self.state.fetch_add(WAITER, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        WatchKey { id }
+    }
+
+    pub fn unwatch(&self, key: WatchKey) {
+        let mut watchers = self.lock.lock().unwrap();
+        watchers.list.retain(|(id, _)| *id != key.id);
+        drop(watchers);
+        self.state.fetch_sub(WAITER, Ordering::Relaxed);
+    }
+
+    pub fn notify_one(&self) {
+        self.notify(false);
+    }
+
+    pub fn notify_all(&self) {
+        self.notify(true);
+    }
+
+    fn notify(&self, all: bool) {
+        // SeqCst: pairs with prepare_wait. Either the waiter's registration is visible here,
+        // or its re-check sees the change we made before calling notify.
+        atomic::fence(Ordering::SeqCst);
+        if self.state.load(Ordering::SeqCst) & (EPOCH - 1) == 0 {
+            return;
+        }
+        self.state.fetch_add(EPOCH, Ordering::SeqCst);
+        let watchers = self.lock.lock().unwrap();
+        for (_, waker) in &watchers.list {
+            waker.wake_by_ref();
+        }
+        drop(watchers);
+        #[cfg(not(any(loom, shuttle)))]
+        {
+            self.wake.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if all {
+                crate::futex::wake_all(&self.wake);
+            } else {
+                crate::futex::wake_one(&self.wake);
+            }
+        }
+        // Having taken the lock means a waiter that saw the old epoch is already in the
+        // condvar.
+        #[cfg(any(loom, shuttle))]
+        if all {
+            self.condvar.notify_all();
+        } else {
+            self.condvar.notify_one();
+        }
+    }
+}
+
+impl Default for EventCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn event_count_wakes_waiter() {
+    use std::sync::atomic::AtomicBool;
+    let ready = &AtomicBool::new(false);
+    let ec = &EventCount::new();
+    std::thread::scope(|s| {
+        s.spawn(move || loop {
+            if ready.load(Ordering::Acquire) {
+                return;
+            }
+            let key = ec.prepare_wait();
+            if ready.load(Ordering::Acquire) {
+                ec.cancel_wait(key);
+                return;
+            }
+            ec.wait(key);
+        });
+        std::thread::yield_now();
+        ready.store(true, Ordering::Release);
+        ec.notify_all();
+    });
+}
@@ -0,0 +1,247 @@
+use std::borrow::Borrow;
+use std::cell::{Cell, UnsafeCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+enum Op<K, V> {
+    Insert(K, V),
+    Remove(K),
+}
+
+impl<K: Hash + Eq, V> Op<K, V> {
+    fn apply(self, map: &mut HashMap<K, V>) {
+        match self {
+            Op::Insert(k, v) => {
+                map.insert(k, v);
+            }
+            Op::Remove(k) => {
+                map.remove(&k);
+            }
+        }
+    }
+}
+
+struct Shared<K, V> {
+    maps: [UnsafeCell<HashMap<K, V>>; 2],
+    // Which map readers use; the writer has the other one.
+    active: AtomicUsize,
+    // One counter per read handle, odd while that handle is inside a read.
+    readers: Mutex<Vec<Arc<AtomicUsize>>>,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for Shared<K, V> {}
+unsafe impl<K: Send, V: Send> Send for Shared<K, V> {}
+
+/// Creates an eventually consistent map with one writer and any number of readers.
+///
+/// The map is kept twice. Readers look at one copy without locks or retries, while the writer
+/// changes the other and logs what it did. [`WriteHandle::refresh`] swaps the copies, waits for
+/// readers still in the old one to leave, and replays the log onto it. Readers see none of the
+/// writes made since the last refresh, and all of them after it.
+pub fn new<K, V>() -> (WriteHandle<K, V>, ReadHandle<K, V>)
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    let shared = Arc::new(Shared {
+        maps: [
+            UnsafeCell::new(HashMap::new()),
+            UnsafeCell::new(HashMap::new()),
+        ],
+        active: AtomicUsize::new(0),
+        readers: Mutex::new(Vec::new()),
+    });
+    let reader = ReadHandle::register(Arc::clone(&shared));
+    (
+        WriteHandle {
+            shared,
+            log: Vec::new(),
+        },
+        reader,
+    )
+}
+
+pub struct WriteHandle<K, V> {
+    shared: Arc<Shared<K, V>>,
+    log: Vec<Op<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> WriteHandle<K, V> {
+    fn map(&mut self) -> &mut HashMap<K, V> {
+        let inactive = 1 - self.shared.active.load(Ordering::Relaxed);
+        // Safety: refresh waited for every reader to leave this copy before giving it to us.
+        unsafe { &mut *self.shared.maps[inactive].get() }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.map().insert(key.clone(), value.clone());
+        self.log.push(Op::Insert(key, value));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.map().remove(&key);
+        self.log.push(Op::Remove(key));
+    }
+
+    /// Whether there are writes readers can't see yet.
+    pub fn pending(&self) -> bool {
+        !self.log.is_empty()
+    }
+
+    /// Makes every write so far visible to readers.
+    pub fn refresh(&mut self) {
+        if self.log.is_empty() {
+            return;
+        }
+        let shared = &*self.shared;
+        // SeqCst, with the SeqCst read side below: either a reader's counter bump is visible
+        // to the checks that follow, or the reader sees the new active map.
+        let old = shared.active.fetch_xor(1, Ordering::SeqCst);
+        let mut readers = shared.readers.lock().unwrap();
+        // Handles that were dropped only have our reference left.
+        readers.retain(|r| Arc::strong_count(r) > 1);
+        for reader in readers.iter() {
+            let seen = reader.load(Ordering::SeqCst);
+            if seen % 2 == 1 {
+                // In a read that may have started on the old map; wait for it to end.
+                while reader.load(Ordering::Acquire) == seen {
+                    thread::yield_now();
+                }
+            }
+        }
+        drop(readers);
+        // Safety: no reader can be in `old` any more, and new ones go to the other map.
+        let map = unsafe { &mut *shared.maps[old].get() };
+        for op in self.log.drain(..) {
+            op.apply(map);
+        }
+    }
+}
+
+/// A reader of the map; clone it for each reading thread.
+///
+/// It can be sent to another thread, but not shared with one:
+///
+/// ```compile_fail
+/// fn shared<T: Sync>(_: &T) {}
+/// let (_w, r) = atomics::evmap::new::<u32, u32>();
+/// shared(&r);
+/// ```
+pub struct ReadHandle<K, V> {
+    shared: Arc<Shared<K, V>>,
+    epoch: Arc<AtomicUsize>,
+    // Not Sync: the counter only tracks one read at a time, so two threads reading through
+    // one handle could both take their read for a nested one and leave it uncounted.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for ReadHandle<K, V> {}
+
+impl<K, V> ReadHandle<K, V> {
+    fn register(shared: Arc<Shared<K, V>>) -> Self {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        shared.readers.lock().unwrap().push(Arc::clone(&epoch));
+        Self {
+            shared,
+            epoch,
+            _not_sync: PhantomData,
+        }
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&HashMap<K, V>) -> R) -> R {
+        // A read nested inside another on the same handle is already covered.
+        let nested = self.epoch.load(Ordering::Relaxed) % 2 == 1;
+        if !nested {
+            self.epoch.fetch_add(1, Ordering::SeqCst);
+        }
+        let active = self.shared.active.load(Ordering::SeqCst);
+        // Safety: the writer won't touch this map until our counter moves on.
+        let r = f(unsafe { &*self.shared.maps[active].get() });
+        if !nested {
+            // Release: our reads of the map happen before the writer reuses it.
+            self.epoch.fetch_add(1, Ordering::Release);
+        }
+        r
+    }
+
+    /// Runs `f` on the value for `key` as of the last refresh.
+    pub fn get<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q> + Hash + Eq,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(|map| map.get(key).map(f))
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Hash + Eq,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read(|map| map.contains_key(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.read(|map| map.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Clone for ReadHandle<K, V> {
+    fn clone(&self) -> Self {
+        Self::register(Arc::clone(&self.shared))
+    }
+}
+
+#[test]
+fn evmap_refresh_publishes() {
+    let (mut w, r) = new();
+    w.insert("a", 1);
+    assert!(w.pending());
+    assert_eq!(r.get("a", |v| *v), None);
+    w.refresh();
+    assert_eq!(r.get("a", |v| *v), Some(1));
+    w.insert("b", 2);
+    w.remove("a");
+    w.refresh();
+    // Both copies have caught up now.
+    w.insert("c", 3);
+    w.refresh();
+    assert!(!r.contains_key("a"));
+    assert_eq!(r.len(), 2);
+}
+
+#[test]
+fn evmap_concurrent_readers() {
+    let (mut w, r) = new();
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let r = r.clone();
+            thread::spawn(move || loop {
+                // Keys are published in order, so a reader must see a prefix.
+                let n = r.len();
+                for k in 0..n {
+                    assert_eq!(r.get(&k, |v| *v), Some(k * 2));
+                }
+                if n == 100 {
+                    return;
+                }
+                thread::yield_now();
+            })
+        })
+        .collect();
+    for k in 0..100 {
+        w.insert(k, k * 2);
+        w.refresh();
+    }
+    for h in readers {
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::async_sync::oneshot;
+use crate::event_count::EventCount;
+use crate::parker::Parker;
+use crate::seg_queue::SegQueue;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle as ThreadHandle};
+
+/// Runs `future` to completion on the current thread, parking it while the future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let parker = Parker::new();
+    let waker = parker.unparker().waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        parker.park();
+    }
+}
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Shared {
+    queue: SegQueue<Arc<Task>>,
+    ready: EventCount,
+    shutdown: AtomicBool,
+    // Every task not yet dropped, by id, queued or waiting, so the pool's drop can reach the
+    // ones that are only kept alive by wakers: their future holds a waker for the task that
+    // holds the future, and nothing else would ever drop it.
+    tasks: Mutex<HashMap<usize, Weak<Task>>>,
+    next_id: AtomicUsize,
+}
+
+struct Task {
+    id: usize,
+    // None once the task has finished.
+    future: Mutex<Option<BoxFuture>>,
+    // Set while the task is in the queue, so a burst of wakes queues it once.
+    scheduled: AtomicBool,
+    shared: Arc<Shared>,
+}
+
+/// A fixed set of worker threads polling spawned tasks from a shared [`SegQueue`].
+///
+/// It's meant for exercising the crate's async primitives across threads, not for serving
+/// anything: there's no work stealing, timers or I/O. Dropping it stops the workers and
+/// drops every task that hasn't finished, queued or waiting to be woken.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<ThreadHandle<()>>,
+}
+
+/// Waits for a task spawned on a [`ThreadPool`] and returns its output.
+///
+/// Awaiting it panics if the task panicked, or if the pool was dropped before the task
+/// finished.
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<T>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "a pool needs at least one thread");
+        let shared = Arc::new(Shared {
+            queue: SegQueue::new(),
+            ready: EventCount::new(),
+            shutdown: AtomicBool::new(false),
+            tasks: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+        });
+        let workers = (0..threads)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || shared.work())
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let task = Arc::new(Task {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            future: Mutex::new(Some(Box::pin(async move {
+                // The handle may have been dropped; the task ran either way.
+                let _ = tx.send(future.await);
+            }))),
+            scheduled: AtomicBool::new(true),
+            shared: Arc::clone(&self.shared),
+        });
+        self.shared
+            .tasks
+            .lock()
+            .unwrap()
+            .insert(task.id, Arc::downgrade(&task));
+        self.shared.schedule(task);
+        JoinHandle { rx }
+    }
+}
+
+impl Shared {
+    fn schedule(&self, task: Arc<Task>) {
+        self.queue.push(task);
+        self.ready.notify_one();
+    }
+
+    fn work(&self) {
+        loop {
+            if let Some(task) = self.queue.pop() {
+                task.run();
+                continue;
+            }
+            if self.shutdown.load(Ordering::SeqCst) {
+                return;
+            }
+            let key = self.ready.prepare_wait();
+            if let Some(task) = self.queue.pop() {
+                self.ready.cancel_wait(key);
+                task.run();
+            } else if self.shutdown.load(Ordering::SeqCst) {
+                self.ready.cancel_wait(key);
+                return;
+            } else {
+                self.ready.wait(key);
+            }
+        }
+    }
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        // Cleared first, so a wake during the poll queues the task again.
+        self.scheduled.store(false, Ordering::SeqCst);
+        let mut slot = self.future.lock().unwrap();
+        let Some(future) = slot.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+        // A panicking task is dropped, which its JoinHandle sees as the sender going away.
+        match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => {}
+            Ok(Poll::Ready(())) | Err(_) => *slot = None,
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.shared.tasks.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if self.shared.shutdown.load(Ordering::Relaxed) {
+            return;
+        }
+        if !self.scheduled.swap(true, Ordering::SeqCst) {
+            let shared = Arc::clone(&self.shared);
+            shared.schedule(self);
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.ready.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        // Tasks point back at the pool, and waiting ones at themselves, through the wakers
+        // their futures hold; dropping the futures breaks both cycles, and with them go the
+        // JoinHandles' senders. Out of the registry first: a future's drop may drop tasks,
+        // which take its lock.
+        let tasks: Vec<_> = self
+            .shared
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for task in tasks {
+            drop(task.future.lock().unwrap().take());
+        }
+        while let Some(task) = self.shared.queue.pop() {
+            drop(task.future.lock().unwrap().take());
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map(|result| result.expect("task panicked or its pool was dropped"))
+    }
+}
+
+#[test]
+fn thread_pool_runs_async_primitives() {
+    use crate::async_sync::{Mutex, Semaphore};
+    let pool = ThreadPool::new(3);
+    let counter = Arc::new(Mutex::new(0));
+    let semaphore = Arc::new(Semaphore::new(2));
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let counter = Arc::clone(&counter);
+            let semaphore = Arc::clone(&semaphore);
+            pool.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let mut count = counter.lock().await;
+                *count += 1;
+                i * 2
+            })
+        })
+        .collect();
+    let results = block_on(async {
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(handle.await);
+        }
+        results
+    });
+    assert!(results.into_iter().eq((0..8).map(|i| i * 2)));
+    assert_eq!(*block_on(counter.lock()), 8);
+
+    // A panicking task doesn't take its worker down with it.
+    let failed = pool.spawn(async { panic!("task failure") });
+    let again = pool.spawn(async { 1 });
+    assert_eq!(block_on(again), 1);
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| block_on(failed))).is_err());
+}
+
+#[test]
+fn thread_pool_drop_drops_waiting_tasks() {
+    let pool = ThreadPool::new(1);
+    let (tx, rx) = oneshot::channel::<u32>();
+    let (started_tx, started_rx) = oneshot::channel();
+    let waiting = pool.spawn(async move {
+        started_tx.send(()).unwrap();
+        rx.await
+    });
+    block_on(started_rx).unwrap();
+    drop(pool);
+    // The task's future went with the pool, and the receiver it held with it.
+    assert!(tx.send(1).is_err());
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| block_on(waiting))).is_err());
+}
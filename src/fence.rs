@@ -0,0 +1,390 @@
+use crate::backoff::Backoff;
+use crate::sync_shim::atomic::{self, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use crate::sync_shim::UnsafeCell;
+use crate::tsan;
+
+// Synchronizing through fences rather than through the accesses themselves. A Release fence
+// followed by a Relaxed store synchronizes with a Relaxed load that reads the store followed
+// by an Acquire fence, just as a Release store read by an Acquire load does. The difference
+// is that a fence orders every access around it, not just one: a single fence can publish
+// several stores, and a reader can spin on Relaxed loads and pay for the Acquire once, after
+// the load that found what it was waiting for.
+
+/// An atomic the helpers here can load and store.
+pub trait Atomic {
+    type Value: Copy;
+
+    fn load(&self, order: Ordering) -> Self::Value;
+    fn store(&self, v: Self::Value, order: Ordering);
+}
+
+macro_rules! atomic {
+    ($($name:ident: $t:ty),*) => {
+        $(
+            impl Atomic for $name {
+                type Value = $t;
+
+                fn load(&self, order: Ordering) -> $t {
+                    $name::load(self, order)
+                }
+
+                fn store(&self, v: $t, order: Ordering) {
+                    $name::store(self, v, order)
+                }
+            }
+        )*
+    };
+}
+
+atomic!(AtomicBool: bool, AtomicUsize: usize, AtomicU64: u64);
+
+impl<T> Atomic for AtomicPtr<T> {
+    type Value = *mut T;
+
+    fn load(&self, order: Ordering) -> *mut T {
+        AtomicPtr::load(self, order)
+    }
+
+    fn store(&self, v: *mut T, order: Ordering) {
+        AtomicPtr::store(self, v, order)
+    }
+}
+
+/// A Release fence, then a Relaxed store of `v`: everything before the call happens before
+/// whatever follows a [`load_then_acquire_fence`], or an Acquire load, that reads `v`.
+pub fn release_fence_then_store<A: Atomic>(a: &A, v: A::Value) {
+    tsan::release(a);
+    atomic::fence(Ordering::Release);
+    a.store(v, Ordering::Relaxed);
+}
+
+/// A Relaxed load, then an Acquire fence: if the load read a value stored by
+/// [`release_fence_then_store`], or by a Release store, everything before that store happens
+/// before everything after the call.
+pub fn load_then_acquire_fence<A: Atomic>(a: &A) -> A::Value {
+    let v = a.load(Ordering::Relaxed);
+    atomic::fence(Ordering::Acquire);
+    tsan::acquire(a);
+    v
+}
+
+/// Spins on Relaxed loads until `done` accepts the value, then fences once, so the loads that
+/// found nothing don't each pay for an Acquire.
+pub fn wait_then_acquire_fence<A: Atomic>(
+    a: &A,
+    mut done: impl FnMut(A::Value) -> bool,
+) -> A::Value {
+    let backoff = Backoff::new();
+    loop {
+        let v = a.load(Ordering::Relaxed);
+        if done(v) {
+            atomic::fence(Ordering::Acquire);
+            tsan::acquire(a);
+            return v;
+        }
+        backoff.snooze();
+    }
+}
+
+// How light_barrier and heavy_barrier work here, found out on first use.
+const UNKNOWN: u8 = 0;
+const ASYMMETRIC: u8 = 1;
+const SYMMETRIC: u8 = 2;
+static BARRIERS: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(UNKNOWN);
+
+fn asymmetric() -> bool {
+    match BARRIERS.load(Ordering::Relaxed) {
+        ASYMMETRIC => true,
+        SYMMETRIC => false,
+        _ => {
+            // Threads racing to find out all find the same.
+            let asymmetric = !cfg!(any(loom, shuttle, miri)) && sys::register();
+            let state = if asymmetric { ASYMMETRIC } else { SYMMETRIC };
+            BARRIERS.store(state, Ordering::Relaxed);
+            asymmetric
+        }
+    }
+}
+
+/// The cheap half of an asymmetric pair of fences, for the path that runs all the time: a
+/// [`light_barrier`] and a [`heavy_barrier`] on different threads order the accesses around
+/// them as two SeqCst fences would. Where the OS can force a fence onto every running thread
+/// of the process, membarrier on Linux and FlushProcessWriteBuffers on Windows, this is only
+/// a compiler fence; elsewhere, and under loom, shuttle and Miri, it's a SeqCst fence.
+///
+/// So a reader that has to be ordered against a rare writer, as in RCU or a BRAVO lock's
+/// reader fast path, can leave the cost of the fence to the writer.
+pub fn light_barrier() {
+    if asymmetric() {
+        std::sync::atomic::compiler_fence(Ordering::SeqCst);
+    } else {
+        atomic::fence(Ordering::SeqCst);
+    }
+}
+
+/// The expensive half of the pair [`light_barrier`] describes: a SeqCst fence on this thread
+/// and, in effect, on every other thread of the process that is running. It takes a system
+/// call that interrupts them, so it's for the path that runs rarely.
+pub fn heavy_barrier() {
+    if asymmetric() {
+        sys::heavy_barrier();
+    } else {
+        atomic::fence(Ordering::SeqCst);
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+mod sys {
+    use std::os::raw::{c_int, c_long, c_uint};
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_MEMBARRIER: c_long = 324;
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const SYS_MEMBARRIER: c_long = 283;
+
+    const MEMBARRIER_CMD_QUERY: c_int = 0;
+    const MEMBARRIER_CMD_PRIVATE_EXPEDITED: c_int = 1 << 3;
+    const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: c_int = 1 << 4;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    fn membarrier(cmd: c_int) -> c_long {
+        unsafe { syscall(SYS_MEMBARRIER, cmd, 0 as c_uint, 0 as c_int) }
+    }
+
+    // Whether the kernel has the expedited private command, and has taken the process's
+    // registration for it, which it wants before the first use.
+    pub fn register() -> bool {
+        let commands = membarrier(MEMBARRIER_CMD_QUERY);
+        commands >= 0
+            && commands & MEMBARRIER_CMD_PRIVATE_EXPEDITED as c_long != 0
+            && membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) == 0
+    }
+
+    pub fn heavy_barrier() {
+        // Can't fail once registered.
+        let r = membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED);
+        assert!(r == 0, "membarrier: {}", std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    extern "system" {
+        fn FlushProcessWriteBuffers();
+    }
+
+    pub fn register() -> bool {
+        true
+    }
+
+    pub fn heavy_barrier() {
+        unsafe { FlushProcessWriteBuffers() }
+    }
+}
+
+#[cfg(not(any(
+    windows,
+    all(
+        target_os = "linux",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64"
+        )
+    )
+)))]
+mod sys {
+    pub fn register() -> bool {
+        false
+    }
+
+    pub fn heavy_barrier() {
+        unreachable!()
+    }
+}
+
+/// The spin lock of the `with_lock` example with its orderings moved into fences: the CAS
+/// that takes the lock is Relaxed and followed by an Acquire fence, and the unlock is a
+/// Release fence then a Relaxed store. It's as correct as the original, and on most hardware
+/// costs the same, since the fences compile to what the orderings did.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let backoff = Backoff::new();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                backoff.snooze();
+            }
+            backoff.spin();
+        }
+        // Pairs with the unlock's fence: the CAS read the store after it.
+        atomic::fence(Ordering::Acquire);
+        tsan::acquire(&self.locked);
+        // Safety: we hold the lock.
+        let ret = self.v.with_mut(|v| f(unsafe { &mut *v }));
+        release_fence_then_store(&self.locked, false);
+        ret
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn fence_mutex_counts() {
+    let n = if cfg!(miri) { 50 } else { 1000 };
+    let l = Mutex::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    l.with_lock(|v| *v += 1);
+                }
+            });
+        }
+    });
+    assert_eq!(l.with_lock(|v| *v), 4 * n);
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn fence_one_fence_publishes_every_store() {
+    use crate::litmus::Litmus;
+
+    let n = if cfg!(miri) { 20 } else { 1000 };
+    // Locations: three data words, the flag, and the registers r1 (flag) and r2..r4 (data).
+    let mut test = Litmus::new("fence-mp", 8)
+        .thread(|m| {
+            for d in 0..3 {
+                m[d].store(42, Ordering::Relaxed);
+            }
+            atomic::fence(Ordering::Release);
+            m[3].store(1, Ordering::Relaxed);
+        })
+        .thread(|m| {
+            let r1 = m[3].load(Ordering::Relaxed);
+            atomic::fence(Ordering::Acquire);
+            m[4].store(r1, Ordering::Relaxed);
+            for d in 0..3 {
+                m[5 + d].store(m[d].load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        })
+        .outcome(&[4, 5, 6, 7])
+        // Once the flag's seen, all three are; before that any of them may be.
+        .allow(&[1, 42, 42, 42]);
+    for seen in 0..8 {
+        let r = |d: u64| if seen >> d & 1 == 1 { 42 } else { 0 };
+        test = test.allow(&[0, r(0), r(1), r(2)]);
+    }
+    let report = test.run(n);
+    assert!(report.passed(), "{}", report);
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn fence_light_and_heavy_barriers_forbid_store_buffering() {
+    use crate::litmus::Litmus;
+
+    let n = if cfg!(miri) { 20 } else { 1000 };
+    // Locations: x, y, and the registers r1 and r2.
+    let report = Litmus::new("sb-asymmetric", 4)
+        .thread(|m| {
+            m[0].store(1, Ordering::Relaxed);
+            light_barrier();
+            m[2].store(m[1].load(Ordering::Relaxed), Ordering::Relaxed);
+        })
+        .thread(|m| {
+            m[1].store(1, Ordering::Relaxed);
+            heavy_barrier();
+            m[3].store(m[0].load(Ordering::Relaxed), Ordering::Relaxed);
+        })
+        .outcome(&[2, 3])
+        .allow(&[0, 1])
+        .allow(&[1, 0])
+        .allow(&[1, 1])
+        .run(n);
+    assert!(report.passed(), "{}", report);
+}
+
+#[cfg(loom)]
+#[test]
+fn fence_mutex_loom() {
+    use loom::sync::Arc;
+
+    loom::model(|| {
+        let l = Arc::new(Mutex::new(0));
+        let t = {
+            let l = Arc::clone(&l);
+            loom::thread::spawn(move || l.with_lock(|v| *v += 1))
+        };
+        l.with_lock(|v| *v += 1);
+        t.join().unwrap();
+        assert_eq!(l.with_lock(|v| *v), 2);
+    });
+}
+
+#[cfg(loom)]
+#[test]
+fn fence_message_passing_loom() {
+    use loom::sync::Arc;
+
+    loom::model(|| {
+        let data = Arc::new(UnsafeCell::new(0));
+        let ready = Arc::new(AtomicBool::new(false));
+        let t = {
+            let (data, ready) = (Arc::clone(&data), Arc::clone(&ready));
+            loom::thread::spawn(move || {
+                data.with_mut(|d| unsafe { *d = 42 });
+                release_fence_then_store(&*ready, true);
+            })
+        };
+        if load_then_acquire_fence(&*ready) {
+            assert_eq!(data.with(|d| unsafe { *d }), 42);
+        }
+        t.join().unwrap();
+    });
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn fence_wait_then_acquire_fence_sees_the_data() {
+    // Under the test scheduler, so each seed waits through a different number of loads.
+    crate::sched::check(|s| {
+        let data = std::sync::Arc::new(AtomicU64::new(0));
+        let ready = std::sync::Arc::new(AtomicUsize::new(0));
+        s.spawn({
+            let (data, ready) = (data.clone(), ready.clone());
+            move || {
+                data.store(42, Ordering::Relaxed);
+                release_fence_then_store(&*ready, 1);
+            }
+        });
+        assert_eq!(wait_then_acquire_fence(&*ready, |r| r == 1), 1);
+        assert_eq!(data.load(Ordering::Relaxed), 42);
+    });
+}
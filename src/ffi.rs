@@ -0,0 +1,414 @@
+use crate::mpmc;
+use crate::raw_mutex::RawMutex;
+use crate::rwlock::{RwLockReadGuard, RwLockWriteGuard};
+use std::cell::RefCell;
+use std::os::raw::c_void;
+
+// The mutex, readers-writer lock and bounded channel for C and C++, behind opaque handles:
+// each has a `create` that allocates one and a `destroy` that frees it, and lock and unlock
+// calls in place of guards. The C declarations are in include/atomics.h, which is generated
+// from the signatures here: `header` renders it, and the ffi_header_is_current test fails
+// when the file's out of date and rewrites it under `ATOMICS_WRITE_HEADER=1`. A library
+// for C to link with comes out of
+//
+//     cargo rustc --lib --release --features ffi --crate-type staticlib
+//
+// Nothing here checks its handles: passing one that's null, destroyed, or still in use by
+// another thread when it's destroyed is undefined behaviour, as it is for pthreads. A misuse
+// that can be caught, like unlocking a lock the thread doesn't hold, panics, which aborts the
+// process at the C boundary.
+
+/// What the channel calls return.
+pub const ATOMICS_OK: i32 = 0;
+/// A `try_` call would have had to wait.
+pub const ATOMICS_WOULD_BLOCK: i32 = 1;
+/// The channel was closed, and for a receive, drained.
+pub const ATOMICS_DISCONNECTED: i32 = 2;
+
+/// A [`RawMutex`](crate::raw_mutex::RawMutex), so os_unfair_lock on macOS.
+pub struct Mutex(RawMutex);
+
+/// A [`rwlock::RwLock`](crate::rwlock::RwLock) guarding nothing; C keeps its data beside it.
+pub struct RwLock(crate::rwlock::RwLock<()>);
+
+/// A bounded [`mpmc`] channel of pointers, with both ends in the one handle, so that it only
+/// disconnects when it's closed.
+pub struct Channel {
+    tx: mpmc::Sender<Message>,
+    rx: mpmc::Receiver<Message>,
+}
+
+// What goes through a channel belongs to whoever sent or received it; the channel only moves
+// the pointer.
+struct Message(*mut c_void);
+
+unsafe impl Send for Message {}
+
+// Only ever dropped, which is the unlock.
+#[allow(dead_code)]
+enum Guard {
+    Read(RwLockReadGuard<'static, ()>),
+    Write(RwLockWriteGuard<'static, ()>),
+}
+
+thread_local! {
+    // The RwLock guards this thread holds for C, by the lock's address, most recent last. A
+    // guard borrows its lock, which C keeps alive until it's unlocked and destroyed.
+    static HELD: RefCell<Vec<(usize, Guard)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn hold(lock: &RwLock, guard: Guard) {
+    HELD.with(|held| {
+        held.borrow_mut()
+            .push((lock as *const RwLock as usize, guard))
+    });
+}
+
+fn unhold(lock: &RwLock, write: bool) {
+    let addr = lock as *const RwLock as usize;
+    let guard = HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        let i = held
+            .iter()
+            .rposition(|(a, g)| *a == addr && matches!(g, Guard::Write(_)) == write)
+            .unwrap_or_else(|| {
+                panic!(
+                    "ffi: unlocking a rwlock at {:#x} this thread doesn't hold for {}",
+                    addr,
+                    if write { "writing" } else { "reading" }
+                )
+            });
+        held.remove(i).1
+    });
+    // Unlocks outside the borrow of HELD.
+    drop(guard);
+}
+
+// Extends a guard's borrow of a lock to what C says it is.
+unsafe fn forever<T: ?Sized>(t: &T) -> &'static T {
+    &*(t as *const T)
+}
+
+/// The C spelling of a type that crosses the boundary.
+trait CType {
+    const C: &'static str;
+}
+
+macro_rules! c_types {
+    ($($t:ty => $c:literal,)*) => {
+        $(
+            impl CType for $t {
+                const C: &'static str = $c;
+            }
+        )*
+    };
+}
+
+c_types! {
+    () => "void",
+    bool => "bool",
+    i32 => "int",
+    usize => "size_t",
+    *mut c_void => "void *",
+    *mut *mut c_void => "void **",
+    *mut Mutex => "atomics_mutex_t *",
+    *mut RwLock => "atomics_rwlock_t *",
+    *mut Channel => "atomics_channel_t *",
+}
+
+// Defines the exported functions and DECLARATIONS, their C declarations with their doc
+// comments, from the same signatures.
+macro_rules! exports {
+    ($(
+        $(#[doc = $doc:literal])*
+        fn $name:ident($($arg:ident: $t:ty),*) $(-> $r:ty)? $body:block
+    )*) => {
+        $(
+            $(#[doc = $doc])*
+            #[no_mangle]
+            #[allow(clippy::missing_safety_doc)]
+            pub unsafe extern "C" fn $name($($arg: $t),*) $(-> $r)? $body
+        )*
+
+        const DECLARATIONS: &[fn() -> String] = &[$(
+            || {
+                let args: &[String] = &[$(c_arg(<$t as CType>::C, stringify!($arg))),*];
+                let docs: &[&str] = &[$($doc),*];
+                format!(
+                    "{}{}({});\n",
+                    docs.iter().map(|d| format!("//{}\n", d)).collect::<String>(),
+                    c_arg(<exports!(@ret $($r)?) as CType>::C, stringify!($name)),
+                    if args.is_empty() { "void".to_string() } else { args.join(", ") }
+                )
+            }
+        ),*];
+    };
+    (@ret) => { () };
+    (@ret $r:ty) => { $r };
+}
+
+// "int" and "x" to "int x", "void *" and "x" to "void *x".
+fn c_arg(t: &str, name: &str) -> String {
+    if t.ends_with('*') {
+        format!("{}{}", t, name)
+    } else {
+        format!("{} {}", t, name)
+    }
+}
+
+exports! {
+    /// An unlocked mutex.
+    fn atomics_mutex_create() -> *mut Mutex {
+        Box::into_raw(Box::new(Mutex(RawMutex::new())))
+    }
+
+    /// Waits for the mutex and takes it.
+    fn atomics_mutex_lock(mutex: *mut Mutex) {
+        (*mutex).0.lock();
+    }
+
+    /// Takes the mutex if it's free.
+    fn atomics_mutex_try_lock(mutex: *mut Mutex) -> bool {
+        (*mutex).0.try_lock()
+    }
+
+    /// Gives the mutex back, on the thread that took it.
+    fn atomics_mutex_unlock(mutex: *mut Mutex) {
+        (*mutex).0.unlock();
+    }
+
+    /// Frees an unlocked mutex.
+    fn atomics_mutex_destroy(mutex: *mut Mutex) {
+        drop(Box::from_raw(mutex));
+    }
+
+    /// An unlocked readers-writer lock.
+    fn atomics_rwlock_create() -> *mut RwLock {
+        Box::into_raw(Box::new(RwLock(crate::rwlock::RwLock::new(()))))
+    }
+
+    /// Waits for the lock and takes it for reading, alongside any other readers.
+    fn atomics_rwlock_read_lock(lock: *mut RwLock) {
+        let l = forever(&*lock);
+        hold(l, Guard::Read(l.0.read()));
+    }
+
+    /// Takes the lock for reading if no writer has it or is waiting for it.
+    fn atomics_rwlock_try_read_lock(lock: *mut RwLock) -> bool {
+        let l = forever(&*lock);
+        l.0.try_read().map(|g| hold(l, Guard::Read(g))).is_some()
+    }
+
+    /// Gives back a read lock, on the thread that took it.
+    fn atomics_rwlock_read_unlock(lock: *mut RwLock) {
+        unhold(&*lock, false);
+    }
+
+    /// Waits for the lock and takes it for writing.
+    fn atomics_rwlock_write_lock(lock: *mut RwLock) {
+        let l = forever(&*lock);
+        hold(l, Guard::Write(l.0.write()));
+    }
+
+    /// Takes the lock for writing if no one has it.
+    fn atomics_rwlock_try_write_lock(lock: *mut RwLock) -> bool {
+        let l = forever(&*lock);
+        l.0.try_write().map(|g| hold(l, Guard::Write(g))).is_some()
+    }
+
+    /// Gives back a write lock, on the thread that took it.
+    fn atomics_rwlock_write_unlock(lock: *mut RwLock) {
+        unhold(&*lock, true);
+    }
+
+    /// Frees an unlocked readers-writer lock.
+    fn atomics_rwlock_destroy(lock: *mut RwLock) {
+        drop(Box::from_raw(lock));
+    }
+
+    /// A channel holding up to `capacity` messages; with a capacity of zero, a send waits
+    /// for a receiver to take it.
+    fn atomics_channel_create(capacity: usize) -> *mut Channel {
+        let (tx, rx) = mpmc::bounded(capacity);
+        Box::into_raw(Box::new(Channel { tx, rx }))
+    }
+
+    /// Sends `message`, waiting while the channel is full. ATOMICS_OK, or
+    /// ATOMICS_DISCONNECTED if it's closed.
+    fn atomics_channel_send(channel: *mut Channel, message: *mut c_void) -> i32 {
+        match (*channel).tx.send(Message(message)) {
+            Ok(()) => ATOMICS_OK,
+            Err(_) => ATOMICS_DISCONNECTED,
+        }
+    }
+
+    /// Sends `message` if there's room. ATOMICS_OK, ATOMICS_WOULD_BLOCK if the channel is
+    /// full, or ATOMICS_DISCONNECTED if it's closed.
+    fn atomics_channel_try_send(channel: *mut Channel, message: *mut c_void) -> i32 {
+        match (*channel).tx.try_send(Message(message)) {
+            Ok(()) => ATOMICS_OK,
+            Err(mpmc::TrySendError::Full(_)) => ATOMICS_WOULD_BLOCK,
+            Err(mpmc::TrySendError::Disconnected(_)) => ATOMICS_DISCONNECTED,
+        }
+    }
+
+    /// Receives a message into `*message`, waiting while the channel is empty. ATOMICS_OK, or
+    /// ATOMICS_DISCONNECTED once it's closed and drained.
+    fn atomics_channel_recv(channel: *mut Channel, message: *mut *mut c_void) -> i32 {
+        match (*channel).rx.recv() {
+            Ok(m) => {
+                *message = m.0;
+                ATOMICS_OK
+            }
+            Err(_) => ATOMICS_DISCONNECTED,
+        }
+    }
+
+    /// Receives a message into `*message` if there's one. ATOMICS_OK, ATOMICS_WOULD_BLOCK if
+    /// the channel is empty, or ATOMICS_DISCONNECTED once it's closed and drained.
+    fn atomics_channel_try_recv(channel: *mut Channel, message: *mut *mut c_void) -> i32 {
+        match (*channel).rx.try_recv() {
+            Ok(m) => {
+                *message = m.0;
+                ATOMICS_OK
+            }
+            Err(mpmc::TryRecvError::Empty) => ATOMICS_WOULD_BLOCK,
+            Err(mpmc::TryRecvError::Disconnected) => ATOMICS_DISCONNECTED,
+        }
+    }
+
+    /// How many messages are queued.
+    fn atomics_channel_len(channel: *mut Channel) -> usize {
+        (*channel).tx.len()
+    }
+
+    /// Closes the channel: sends fail from now on, and receives once it's drained; blocked
+    /// senders and receivers wake to fail.
+    fn atomics_channel_close(channel: *mut Channel) {
+        (*channel).tx.close();
+    }
+
+    /// Frees a channel no one's using. Messages still queued are dropped without being
+    /// freed, so drain it first if they own anything.
+    fn atomics_channel_destroy(channel: *mut Channel) {
+        drop(Box::from_raw(channel));
+    }
+}
+
+/// include/atomics.h, as the signatures here make it.
+pub fn header() -> String {
+    let mut h = String::from(
+        "// Generated from src/ffi.rs by its ffi_header_is_current test; don't edit.\n\
+         \n\
+         #ifndef ATOMICS_H\n\
+         #define ATOMICS_H\n\
+         \n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {\n\
+         #endif\n\
+         \n\
+         typedef struct atomics_mutex atomics_mutex_t;\n\
+         typedef struct atomics_rwlock atomics_rwlock_t;\n\
+         typedef struct atomics_channel atomics_channel_t;\n\
+         \n",
+    );
+    h += &format!(
+        "#define ATOMICS_OK {}\n#define ATOMICS_WOULD_BLOCK {}\n#define ATOMICS_DISCONNECTED {}\n",
+        ATOMICS_OK, ATOMICS_WOULD_BLOCK, ATOMICS_DISCONNECTED
+    );
+    for declaration in DECLARATIONS {
+        h += "\n";
+        h += &declaration();
+    }
+    h += "\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n";
+    h
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "reads and compiles files")]
+fn ffi_header_is_current() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/atomics.h");
+    let header = header();
+    if std::env::var_os("ATOMICS_WRITE_HEADER").is_some() {
+        std::fs::write(path, &header).unwrap();
+    }
+    let on_disk = std::fs::read_to_string(path).unwrap_or_default();
+    assert!(
+        on_disk == header,
+        "include/atomics.h is out of date; run this test with ATOMICS_WRITE_HEADER=1"
+    );
+    // And that it's C, where there's a compiler to say so.
+    if let Ok(status) = std::process::Command::new("cc")
+        .args(["-fsyntax-only", "-Wall", "-Werror", "-x", "c", path])
+        .status()
+    {
+        assert!(status.success(), "include/atomics.h doesn't compile");
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn ffi_locks_and_channel_work_through_the_c_calls() {
+    use std::ptr;
+
+    unsafe {
+        let m = atomics_mutex_create();
+        atomics_mutex_lock(m);
+        assert!(!atomics_mutex_try_lock(m));
+        atomics_mutex_unlock(m);
+        assert!(atomics_mutex_try_lock(m));
+        atomics_mutex_unlock(m);
+        atomics_mutex_destroy(m);
+
+        let l = atomics_rwlock_create();
+        atomics_rwlock_read_lock(l);
+        assert!(atomics_rwlock_try_read_lock(l));
+        assert!(!atomics_rwlock_try_write_lock(l));
+        atomics_rwlock_read_unlock(l);
+        atomics_rwlock_read_unlock(l);
+        atomics_rwlock_write_lock(l);
+        assert!(!atomics_rwlock_try_read_lock(l));
+        atomics_rwlock_write_unlock(l);
+        assert!(atomics_rwlock_try_write_lock(l));
+        atomics_rwlock_write_unlock(l);
+        atomics_rwlock_destroy(l);
+
+        let c = atomics_channel_create(1);
+        let mut values = [1, 2];
+        let mut out = ptr::null_mut();
+        assert_eq!(atomics_channel_try_recv(c, &mut out), ATOMICS_WOULD_BLOCK);
+        assert_eq!(
+            atomics_channel_send(c, &mut values[0] as *mut i32 as *mut c_void),
+            ATOMICS_OK
+        );
+        assert_eq!(
+            atomics_channel_try_send(c, &mut values[1] as *mut i32 as *mut c_void),
+            ATOMICS_WOULD_BLOCK
+        );
+        assert_eq!(atomics_channel_len(c), 1);
+        atomics_channel_close(c);
+        assert_eq!(
+            atomics_channel_send(c, ptr::null_mut()),
+            ATOMICS_DISCONNECTED
+        );
+        assert_eq!(atomics_channel_recv(c, &mut out), ATOMICS_OK);
+        assert_eq!(*(out as *mut i32), 1);
+        assert_eq!(atomics_channel_recv(c, &mut out), ATOMICS_DISCONNECTED);
+        atomics_channel_destroy(c);
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[should_panic(expected = "this thread doesn't hold for writing")]
+fn ffi_rwlock_unlock_without_the_lock_panics() {
+    // Through unhold, since the panic would abort at the C boundary.
+    // Leaked, since the read guard outlives the test in its thread's HELD.
+    let l = Box::leak(Box::new(RwLock(crate::rwlock::RwLock::new(()))));
+    unsafe { atomics_rwlock_read_lock(l) };
+    unhold(l, true);
+}
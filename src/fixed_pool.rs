@@ -0,0 +1,524 @@
+use crate::affinity;
+use crate::mpmc::{self, Receiver, Sender};
+use crate::parker::{Parker, Unparker};
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+type PanicHandler = Box<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+/// What a [`FixedPool`] does when a task panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Count the panic and keep going; the worker moves on to the next task.
+    CatchAndContinue,
+    /// Shut the pool down: tasks still queued are dropped without running, later `execute`s
+    /// fail, and [`FixedPool::join`] returns the first panic.
+    Abort,
+}
+
+/// Returned by `execute` once the pool has been shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownError;
+
+impl fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("executing on a pool that has been shut down")
+    }
+}
+
+impl Error for ShutdownError {}
+
+/// Configures a [`FixedPool`] before its threads start.
+pub struct Builder {
+    threads: usize,
+    queue_capacity: usize,
+    panic_policy: PanicPolicy,
+    panic_handler: Option<PanicHandler>,
+    pin_workers: bool,
+}
+
+struct Shared {
+    policy: PanicPolicy,
+    handler: Option<PanicHandler>,
+    panics: AtomicUsize,
+    // Set by an abort or shutdown_now: what's still queued is dropped unrun.
+    aborted: AtomicBool,
+    // The first panic under PanicPolicy::Abort, for join to return.
+    payload: Mutex<Option<Box<dyn Any + Send>>>,
+    // Workers that haven't exited, for join_timeout to wait on.
+    running: Mutex<usize>,
+    exited: Condvar,
+}
+
+/// `threads` workers taking boxed closures from a bounded [`mpmc`] channel.
+///
+/// [`execute`](Self::execute) waits while the queue is full, which keeps a fast producer from
+/// running ahead of the workers. [`shutdown`](Self::shutdown) closes the queue; the workers
+/// finish what was already queued and exit, while [`shutdown_now`](Self::shutdown_now) has
+/// them drop it instead. Dropping the pool shuts it down and waits for them, like
+/// [`join`](Self::join) but without reporting a panic.
+pub struct FixedPool {
+    tx: Sender<Task>,
+    workers: Vec<JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+struct ScopeState {
+    // Tasks executed on the scope that haven't finished or been dropped.
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    owner: Unparker,
+}
+
+/// Executes tasks that may borrow from the caller of [`FixedPool::scope_and_block`].
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope FixedPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+// Counts a scoped task as done however it goes: run, panicked, or dropped unrun.
+struct ScopedTask {
+    state: Arc<ScopeState>,
+}
+
+impl Builder {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            queue_capacity: 64,
+            panic_policy: PanicPolicy::CatchAndContinue,
+            panic_handler: None,
+            pin_workers: false,
+        }
+    }
+
+    /// How many tasks can be queued before `execute` waits. Zero makes every `execute` wait
+    /// for a worker to take the task.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Calls `handler` on the worker with the payload of each task that panics, to log it or
+    /// count it somewhere, before the [`PanicPolicy`] takes over. Scoped tasks' panics go to
+    /// their scope instead.
+    pub fn panic_handler(
+        mut self,
+        handler: impl Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    ) -> Self {
+        self.panic_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Pins worker `i` to the `i`th of the [`affinity::cores`] the builder's thread may run on,
+    /// wrapping around when there are more workers than cores. Pinning is best effort; a
+    /// worker that can't be pinned runs anyway.
+    pub fn pin_workers(mut self, pin: bool) -> Self {
+        self.pin_workers = pin;
+        self
+    }
+
+    pub fn build(self) -> FixedPool {
+        assert!(self.threads > 0, "a pool needs at least one thread");
+        let (tx, rx) = mpmc::bounded(self.queue_capacity);
+        let shared = Arc::new(Shared {
+            policy: self.panic_policy,
+            handler: self.panic_handler,
+            panics: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+            payload: Mutex::new(None),
+            running: Mutex::new(self.threads),
+            exited: Condvar::new(),
+        });
+        let cores = if self.pin_workers {
+            affinity::cores()
+        } else {
+            Vec::new()
+        };
+        let workers = (0..self.threads)
+            .map(|i| {
+                let rx = rx.clone();
+                let shared = Arc::clone(&shared);
+                let core = cores.get(i % cores.len().max(1)).copied();
+                thread::Builder::new()
+                    .name(format!("fixed-pool-{}", i))
+                    .spawn(move || {
+                        if let Some(core) = core {
+                            let _ = affinity::pin_current(core);
+                        }
+                        shared.work(rx)
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        FixedPool {
+            tx,
+            workers,
+            shared,
+        }
+    }
+}
+
+impl Shared {
+    fn work(&self, rx: Receiver<Task>) {
+        // Counts the worker out however it leaves, even if the panic handler panics.
+        struct Exit<'a>(&'a Shared);
+        impl Drop for Exit<'_> {
+            fn drop(&mut self) {
+                *self.0.running.lock().unwrap() -= 1;
+                self.0.exited.notify_all();
+            }
+        }
+        let _exit = Exit(self);
+        for task in rx.iter() {
+            // SeqCst, like the stores: once an abort or shutdown_now has set it, every task
+            // taken after is dropped, not run.
+            if self.aborted.load(Ordering::SeqCst) {
+                // Dropped unrun; a scoped task counts itself done on the way out.
+                continue;
+            }
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(task)) {
+                self.panics.fetch_add(1, Ordering::Relaxed);
+                if let Some(handler) = &self.handler {
+                    handler(&*payload);
+                }
+                if self.policy == PanicPolicy::Abort {
+                    self.payload.lock().unwrap().get_or_insert(payload);
+                    self.aborted.store(true, Ordering::SeqCst);
+                    rx.close();
+                }
+            }
+        }
+    }
+}
+
+impl FixedPool {
+    /// A pool of `threads` workers with the [`Builder`] defaults.
+    pub fn new(threads: usize) -> Self {
+        Builder::new(threads).build()
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queues `f` to run on a worker, waiting for room if the queue is full.
+    pub fn execute<F>(&self, f: F) -> Result<(), ShutdownError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_boxed(Box::new(f))
+    }
+
+    fn execute_boxed(&self, task: Task) -> Result<(), ShutdownError> {
+        self.tx.send(task).map_err(|_| ShutdownError)
+    }
+
+    /// Runs `f` with a [`Scope`] whose tasks may borrow local data, and blocks until every
+    /// task executed on it has finished.
+    ///
+    /// A panic in a scoped task is resumed here, not handled by the pool's panic policy. A
+    /// scoped task that's dropped because the pool aborted just never runs. Calling this from
+    /// one of the pool's own tasks can deadlock, since the task it waits on may need the
+    /// worker it's blocking.
+    pub fn scope_and_block<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let parker = Parker::new();
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+                owner: parker.unparker(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // Acquire: pairs with the Release in ScopedTask::drop.
+        while scope.state.pending.load(Ordering::Acquire) != 0 {
+            parker.park();
+        }
+        let value = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        value
+    }
+
+    /// Closes the queue. Already queued tasks still run; later `execute`s fail.
+    pub fn shutdown(&self) {
+        self.tx.close();
+    }
+
+    /// Closes the queue and drops the tasks still in it unrun. Tasks already running finish.
+    pub fn shutdown_now(&self) {
+        // SeqCst, with the close's: ahead of it in the one order, so a worker that takes a
+        // task once this has returned sees it, and drops the task.
+        self.shared.aborted.store(true, Ordering::SeqCst);
+        self.tx.close();
+    }
+
+    /// Whether the pool was shut down, or aborted after a panic.
+    pub fn is_shutdown(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// How many tasks have panicked so far, not counting scoped ones.
+    pub fn panicked_tasks(&self) -> usize {
+        self.shared.panics.load(Ordering::Relaxed)
+    }
+
+    /// Shuts the pool down and waits for the workers to finish what's queued. Under
+    /// [`PanicPolicy::Abort`], returns the panic that aborted the pool, if one did.
+    pub fn join(mut self) -> thread::Result<()> {
+        self.finish();
+        match self.shared.payload.lock().unwrap().take() {
+            Some(payload) => Err(payload),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`join`](Self::join), but if the workers are still busy after `timeout`, gives
+    /// up and hands the pool back, shut down, to wait on again or to
+    /// [`shutdown_now`](Self::shutdown_now).
+    pub fn join_timeout(self, timeout: Duration) -> Result<thread::Result<()>, Self> {
+        self.shutdown();
+        let deadline = Instant::now().checked_add(timeout);
+        let mut running = self.shared.running.lock().unwrap();
+        while *running > 0 {
+            let left = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::MAX,
+            };
+            if left.is_zero() {
+                drop(running);
+                return Err(self);
+            }
+            running = self.shared.exited.wait_timeout(running, left).unwrap().0;
+        }
+        drop(running);
+        Ok(self.join())
+    }
+
+    fn finish(&mut self) {
+        self.shutdown();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for FixedPool {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl fmt::Debug for FixedPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FixedPool")
+            .field("threads", &self.num_threads())
+            .field("shutdown", &self.is_shutdown())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'scope, 'env: 'scope> Scope<'scope, 'env> {
+    /// Queues `f`, which may borrow anything that outlives the scope.
+    pub fn execute<F>(&'scope self, f: F) -> Result<(), ShutdownError>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.state.pending.fetch_add(1, Ordering::Relaxed);
+        let done = ScopedTask {
+            state: Arc::clone(&self.state),
+        };
+        let task: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                done.state.panic.lock().unwrap().get_or_insert(payload);
+            }
+            drop(done);
+        });
+        // Safety: scope_and_block doesn't return until every ScopedTask is gone, and each
+        // one goes only after its closure has run or been dropped.
+        let task: Task = unsafe { mem::transmute(task) };
+        // A task that can't be queued is dropped here, counting itself done.
+        self.pool.execute_boxed(task)
+    }
+}
+
+impl Drop for ScopedTask {
+    fn drop(&mut self) {
+        // Release: pairs with the Acquire in scope_and_block.
+        if self.state.pending.fetch_sub(1, Ordering::Release) == 1 {
+            self.state.owner.unpark();
+        }
+    }
+}
+
+#[test]
+fn fixed_pool_scope_borrows_and_blocks() {
+    let pool = Builder::new(3).queue_capacity(2).build();
+    let mut totals = [0u64; 8];
+    pool.scope_and_block(|s| {
+        for (i, total) in totals.iter_mut().enumerate() {
+            s.execute(move || *total = (0..=i as u64).sum()).unwrap();
+        }
+    });
+    assert_eq!(totals, [0, 1, 3, 6, 10, 15, 21, 28]);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.scope_and_block(|s| s.execute(|| panic!("scoped")).unwrap())
+    }));
+    assert!(result.is_err());
+    // Scoped panics go to the scope, not to the pool.
+    assert_eq!(pool.panicked_tasks(), 0);
+    assert!(pool.join().is_ok());
+}
+
+#[test]
+fn fixed_pool_panic_policies() {
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    let pool = FixedPool::new(2);
+    for i in 0..10 {
+        let ran = Arc::clone(&ran);
+        pool.execute(move || {
+            assert!(i != 3, "task failure");
+            ran.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+    }
+    pool.shutdown();
+    assert_eq!(pool.execute(|| {}), Err(ShutdownError));
+    while pool.panicked_tasks() == 0 {
+        thread::yield_now();
+    }
+    assert!(pool.join().is_ok());
+    // A graceful shutdown still runs everything that was queued.
+    assert_eq!(ran.load(Ordering::Relaxed), 9);
+
+    let pool = Builder::new(1).panic_policy(PanicPolicy::Abort).build();
+    pool.execute(|| panic!("task failure")).unwrap();
+    while !pool.is_shutdown() {
+        thread::yield_now();
+    }
+    assert_eq!(pool.execute(|| {}), Err(ShutdownError));
+    let payload = pool.join().unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"task failure"));
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let pool = Builder::new(2)
+        .panic_handler({
+            let seen = Arc::clone(&seen);
+            move |payload| {
+                let message = payload.downcast_ref::<String>().cloned();
+                seen.lock().unwrap().extend(message);
+            }
+        })
+        .build();
+    for i in 0..3 {
+        pool.execute(move || panic!("task {}", i)).unwrap();
+    }
+    assert!(pool.join().is_ok());
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort_unstable();
+    assert_eq!(seen, ["task 0", "task 1", "task 2"]);
+}
+
+#[test]
+fn fixed_pool_shutdown_races_execute() {
+    // Every task that execute let in runs, however it raced with a shutdown on another thread:
+    // the plain ones counted, and the scoped ones by scope_and_block returning at all.
+    let rounds = if cfg!(miri) { 5 } else { 200 };
+    for _ in 0..rounds {
+        let pool = Builder::new(2).queue_capacity(4).build();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let accepted = thread::scope(|s| {
+            s.spawn(|| {
+                thread::yield_now();
+                pool.shutdown();
+            });
+            (0..)
+                .take_while(|_| {
+                    let ran = Arc::clone(&ran);
+                    pool.execute(move || {
+                        ran.fetch_add(1, Ordering::Relaxed);
+                    })
+                    .is_ok()
+                })
+                .count()
+        });
+        assert!(pool.join().is_ok());
+        assert_eq!(ran.load(Ordering::Relaxed), accepted);
+
+        let pool = Builder::new(2).queue_capacity(4).build();
+        let ran = AtomicUsize::new(0);
+        let accepted = thread::scope(|s| {
+            s.spawn(|| {
+                thread::yield_now();
+                pool.shutdown();
+            });
+            pool.scope_and_block(|scope| {
+                (0..)
+                    .take_while(|_| {
+                        scope
+                            .execute(|| {
+                                ran.fetch_add(1, Ordering::Relaxed);
+                            })
+                            .is_ok()
+                    })
+                    .count()
+            })
+        });
+        assert_eq!(ran.load(Ordering::Relaxed), accepted);
+    }
+}
+
+#[test]
+fn fixed_pool_shutdown_now_and_join_timeout() {
+    let (started_tx, started_rx) = mpmc::bounded(0);
+    let (release_tx, release_rx) = mpmc::bounded::<()>(0);
+    let ran = Arc::new(AtomicUsize::new(0));
+    let pool = Builder::new(1).queue_capacity(8).build();
+    pool.execute(move || {
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    })
+    .unwrap();
+    started_rx.recv().unwrap();
+    for _ in 0..5 {
+        let ran = Arc::clone(&ran);
+        pool.execute(move || {
+            ran.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+    }
+    pool.shutdown_now();
+    assert_eq!(pool.execute(|| {}), Err(ShutdownError));
+    // The running task holds the only worker.
+    let pool = pool.join_timeout(Duration::from_millis(10)).unwrap_err();
+    drop(release_tx);
+    assert!(pool.join_timeout(Duration::from_secs(10)).unwrap().is_ok());
+    // The queued tasks were dropped unrun.
+    assert_eq!(ran.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,160 @@
+use std::cell::UnsafeCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::thread;
+
+// An operation published by a waiting thread. It lives on that thread's stack, which is fine
+// because the thread doesn't return until the combiner has set `done`, and the combiner never
+// touches the record after that.
+struct Record<D> {
+    next: *mut Record<D>,
+    call: unsafe fn(*mut u8, &mut D),
+    op: *mut u8,
+    done: AtomicBool,
+}
+
+/// Wraps a sequential data structure `D` with flat combining.
+///
+/// Threads publish their operation as a closure instead of taking a lock. Whoever gets the lock
+/// becomes the combiner and runs everyone's published operations in one go, so `D` stays hot
+/// in one core's cache and the lock word isn't passed from thread to thread for every call.
+pub struct FcLock<D> {
+    locked: AtomicBool,
+    // Prepend-only stack of operations waiting for a combiner.
+    pending: AtomicPtr<Record<D>>,
+    data: UnsafeCell<D>,
+}
+
+unsafe impl<D: Send> Send for FcLock<D> {}
+unsafe impl<D: Send> Sync for FcLock<D> {}
+
+impl<D> FcLock<D> {
+    pub const fn new(data: D) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            pending: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Runs `f` on the data, possibly on another thread that's combining at the time. A panic
+    /// in `f` is caught there and resumed here.
+    pub fn with_lock<R, F>(&self, f: F) -> R
+    where
+        R: Send,
+        F: FnOnce(&mut D) -> R + Send,
+    {
+        struct Op<F, R> {
+            f: Option<F>,
+            out: Option<thread::Result<R>>,
+        }
+
+        unsafe fn call<D, F: FnOnce(&mut D) -> R, R>(op: *mut u8, data: &mut D) {
+            let op = &mut *(op as *mut Op<F, R>);
+            let f = op.f.take().unwrap();
+            op.out = Some(panic::catch_unwind(AssertUnwindSafe(|| f(data))));
+        }
+
+        let mut op = Op {
+            f: Some(f),
+            out: None,
+        };
+        let mut record = Record {
+            next: ptr::null_mut(),
+            call: call::<D, F, R>,
+            op: &mut op as *mut Op<F, R> as *mut u8,
+            done: AtomicBool::new(false),
+        };
+        let record_ptr: *mut Record<D> = &mut record;
+        let mut head = self.pending.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*record_ptr).next = head };
+            // Release: publishes the record to the combiner.
+            match self.pending.compare_exchange_weak(
+                head,
+                record_ptr,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(h) => head = h,
+            }
+        }
+
+        // Acquire: pairs with the Release store of `done`, so we see what `f` did.
+        while !unsafe { &*record_ptr }.done.load(Ordering::Acquire) {
+            if !self.locked.load(Ordering::Relaxed)
+                && self
+                    .locked
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                self.combine();
+                self.locked.store(false, Ordering::Release);
+            } else {
+                thread::yield_now();
+            }
+        }
+        match op.out.take().unwrap() {
+            Ok(r) => r,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    // Runs everything that's pending, with the lock held.
+    fn combine(&self) {
+        // Safety: we hold the lock.
+        let data = unsafe { &mut *self.data.get() };
+        // A few passes pick up operations published while the first batch ran, without
+        // letting one thread combine forever.
+        for _ in 0..4 {
+            let mut record = self.pending.swap(ptr::null_mut(), Ordering::Acquire);
+            if record.is_null() {
+                return;
+            }
+            while !record.is_null() {
+                let r = unsafe { &*record };
+                // Read everything we need first: once done is set the record may be gone.
+                let next = r.next;
+                unsafe { (r.call)(r.op, data) };
+                r.done.store(true, Ordering::Release);
+                record = next;
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut D {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> D {
+        self.data.into_inner()
+    }
+}
+
+#[test]
+fn fc_lock_counts() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let l = &FcLock::new(Vec::new());
+    thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    l.with_lock(|v| v.push(t * n + i));
+                }
+            });
+        }
+    });
+    let mut v = l.with_lock(std::mem::take);
+    v.sort_unstable();
+    assert!(v.into_iter().eq(0..4 * n));
+}
+
+#[test]
+fn fc_lock_propagates_panics() {
+    let l = FcLock::new(0);
+    let r = panic::catch_unwind(AssertUnwindSafe(|| l.with_lock(|_| panic!("boom"))));
+    assert!(r.is_err());
+    assert_eq!(l.with_lock(|n| *n + 1), 1);
+}
@@ -0,0 +1,415 @@
+use std::sync::atomic::AtomicU32;
+use std::time::Instant;
+
+// Waiting on an address: a thread sleeps while a 32-bit word holds the value it expects, and
+// another wakes it after changing the word. It's what Parker and EventCount sleep on, so a
+// park or a blocked channel is a single system call each way, with no lock taken to wake
+// anyone.
+//
+// The backends are Linux's futex, Windows' WaitOnAddress and WakeByAddress*, macOS's
+// __ulock_wait and __ulock_wake, WebAssembly's memory.atomic.wait32 and memory.atomic.notify
+// where it's built with the atomics feature, and elsewhere (and under Miri) `fallback`, a
+// table of std mutexes and condvars keyed by address. The fallback is always compiled, so its
+// tests run on every platform CI has, not only the ones that need it.
+//
+// A browser won't let its main thread wait, and traps if it tries: on the web, whatever may
+// block, a lock that's contended or a channel that's empty, belongs in a Web Worker.
+//
+// A wait can return spuriously, whenever the word changes or for no reason at all; callers
+// check their condition again in a loop. Words are process-private: for waits across
+// processes see `shm`.
+
+/// Which backend this platform waits with: "futex", "WaitOnAddress", "ulock",
+/// "memory.atomic.wait32" or "fallback".
+pub const BACKEND: &str = sys::BACKEND;
+
+/// Sleeps while `word` holds `expected`, until woken or `deadline`. May return spuriously.
+pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+    sys::wait(word, expected, deadline)
+}
+
+/// Wakes one thread waiting on `word`, if there is one.
+pub fn wake_one(word: &AtomicU32) {
+    sys::wake_one(word)
+}
+
+/// Wakes every thread waiting on `word`.
+pub fn wake_all(word: &AtomicU32) {
+    sys::wake_all(word)
+}
+
+/// Waiting on an address with std's mutexes and condvars, for the platforms with nothing
+/// better. Addresses hash to one of a fixed number of condvars, so a wake can wake waiters on
+/// other words too, which a spurious return covers.
+pub mod fallback {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Condvar, Mutex};
+    use std::time::Instant;
+
+    const BUCKETS: usize = 64;
+
+    struct Bucket {
+        lock: Mutex<()>,
+        condvar: Condvar,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const BUCKET: Bucket = Bucket {
+        lock: Mutex::new(()),
+        condvar: Condvar::new(),
+    };
+
+    static TABLE: [Bucket; BUCKETS] = [BUCKET; BUCKETS];
+
+    fn bucket(word: &AtomicU32) -> &'static Bucket {
+        // Fibonacci hashing, so neighbouring words land apart.
+        let h = (word as *const AtomicU32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        &TABLE[(h >> 58) as usize % BUCKETS]
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        let b = bucket(word);
+        let lock = b.lock.lock().unwrap_or_else(|e| e.into_inner());
+        // Under the bucket's lock, which a waker takes after changing the word: either we see
+        // the change here or we're in the condvar by the time it notifies.
+        if word.load(Ordering::SeqCst) != expected {
+            return;
+        }
+        match deadline {
+            None => drop(b.condvar.wait(lock)),
+            Some(deadline) => {
+                let now = Instant::now();
+                if deadline > now {
+                    drop(b.condvar.wait_timeout(lock, deadline - now));
+                }
+            }
+        }
+    }
+
+    pub fn wake_one(word: &AtomicU32) {
+        // All of them: the one this wakes might be waiting on another word.
+        wake_all(word);
+    }
+
+    pub fn wake_all(word: &AtomicU32) {
+        let b = bucket(word);
+        drop(b.lock.lock().unwrap_or_else(|e| e.into_inner()));
+        b.condvar.notify_all();
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    ),
+    not(miri)
+))]
+mod sys {
+    use std::os::raw::{c_int, c_long};
+    use std::sync::atomic::AtomicU32;
+    use std::time::Instant;
+
+    pub const BACKEND: &str = "futex";
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_FUTEX: c_long = 202;
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const SYS_FUTEX: c_long = 98;
+
+    const FUTEX_WAIT_PRIVATE: c_int = 128;
+    const FUTEX_WAKE_PRIVATE: c_int = 128 | 1;
+
+    #[repr(C)]
+    struct Timespec {
+        sec: i64,
+        nsec: c_long,
+    }
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        // FUTEX_WAIT's timeout is relative.
+        let timeout = match deadline {
+            None => None,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return;
+                }
+                Some(Timespec {
+                    sec: left.as_secs().min(i64::MAX as u64) as i64,
+                    nsec: left.subsec_nanos() as c_long,
+                })
+            }
+        };
+        let timeout = timeout
+            .as_ref()
+            .map_or(std::ptr::null(), |t| t as *const Timespec);
+        // Fails with EAGAIN if the word's changed, EINTR on a signal and ETIMEDOUT at the
+        // deadline, all of which are a return to the caller's loop.
+        unsafe {
+            syscall(
+                SYS_FUTEX,
+                word as *const AtomicU32,
+                FUTEX_WAIT_PRIVATE,
+                expected,
+                timeout,
+            )
+        };
+    }
+
+    pub fn wake_one(word: &AtomicU32) {
+        unsafe {
+            syscall(
+                SYS_FUTEX,
+                word as *const AtomicU32,
+                FUTEX_WAKE_PRIVATE,
+                1 as c_int,
+            )
+        };
+    }
+
+    pub fn wake_all(word: &AtomicU32) {
+        unsafe {
+            syscall(
+                SYS_FUTEX,
+                word as *const AtomicU32,
+                FUTEX_WAKE_PRIVATE,
+                c_int::MAX,
+            )
+        };
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod sys {
+    use std::os::raw::c_void;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Instant;
+
+    pub const BACKEND: &str = "WaitOnAddress";
+
+    const INFINITE: u32 = u32::MAX;
+
+    #[link(name = "synchronization")]
+    extern "system" {
+        fn WaitOnAddress(
+            address: *const c_void,
+            compare: *const c_void,
+            size: usize,
+            milliseconds: u32,
+        ) -> i32;
+        fn WakeByAddressSingle(address: *const c_void);
+        fn WakeByAddressAll(address: *const c_void);
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        let milliseconds = match deadline {
+            None => INFINITE,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return;
+                }
+                // Rounded up, so a wait doesn't come back just short of its deadline, and kept
+                // short of INFINITE.
+                let ms = left.as_nanos().div_ceil(1_000_000);
+                ms.min(u128::from(INFINITE - 1)) as u32
+            }
+        };
+        // Returns false on a timeout, which is a return to the caller's loop like any other.
+        unsafe {
+            WaitOnAddress(
+                word as *const AtomicU32 as *const c_void,
+                &expected as *const u32 as *const c_void,
+                4,
+                milliseconds,
+            )
+        };
+    }
+
+    pub fn wake_one(word: &AtomicU32) {
+        unsafe { WakeByAddressSingle(word as *const AtomicU32 as *const c_void) };
+    }
+
+    pub fn wake_all(word: &AtomicU32) {
+        unsafe { WakeByAddressAll(word as *const AtomicU32 as *const c_void) };
+    }
+}
+
+// What libc++ and std's own parking use on macOS. They're private to libSystem, but have been
+// there, unchanged, since 10.12.
+#[cfg(all(target_os = "macos", not(miri)))]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+    use std::sync::atomic::AtomicU32;
+    use std::time::Instant;
+
+    pub const BACKEND: &str = "ulock";
+
+    const UL_COMPARE_AND_WAIT: u32 = 1;
+    const ULF_WAKE_ALL: u32 = 0x0000_0100;
+    const ULF_NO_ERRNO: u32 = 0x0100_0000;
+
+    extern "C" {
+        fn __ulock_wait(operation: u32, addr: *mut c_void, value: u64, timeout_us: u32) -> c_int;
+        fn __ulock_wake(operation: u32, addr: *mut c_void, wake_value: u64) -> c_int;
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        // In microseconds, where 0 is forever; a longer wait comes back early, to the caller's
+        // loop.
+        let timeout = match deadline {
+            None => 0,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return;
+                }
+                left.as_micros().clamp(1, u128::from(u32::MAX)) as u32
+            }
+        };
+        unsafe {
+            __ulock_wait(
+                UL_COMPARE_AND_WAIT | ULF_NO_ERRNO,
+                word as *const AtomicU32 as *mut c_void,
+                u64::from(expected),
+                timeout,
+            )
+        };
+    }
+
+    pub fn wake_one(word: &AtomicU32) {
+        unsafe {
+            __ulock_wake(
+                UL_COMPARE_AND_WAIT | ULF_NO_ERRNO,
+                word as *const AtomicU32 as *mut c_void,
+                0,
+            )
+        };
+    }
+
+    pub fn wake_all(word: &AtomicU32) {
+        unsafe {
+            __ulock_wake(
+                UL_COMPARE_AND_WAIT | ULF_WAKE_ALL | ULF_NO_ERRNO,
+                word as *const AtomicU32 as *mut c_void,
+                0,
+            )
+        };
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics", not(miri)))]
+mod sys {
+    use core::arch::wasm32::{memory_atomic_notify, memory_atomic_wait32};
+    use std::sync::atomic::AtomicU32;
+    use std::time::Instant;
+
+    pub const BACKEND: &str = "memory.atomic.wait32";
+
+    fn addr(word: &AtomicU32) -> *mut i32 {
+        word.as_ptr() as *mut i32
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, deadline: Option<Instant>) {
+        // In nanoseconds, relative, where a negative timeout is forever.
+        let timeout = match deadline {
+            None => -1,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return;
+                }
+                left.as_nanos().min(i64::MAX as u128) as i64
+            }
+        };
+        // 0 when woken, 1 when the word had changed and 2 at the timeout, all of which are a
+        // return to the caller's loop.
+        unsafe { memory_atomic_wait32(addr(word), expected as i32, timeout) };
+    }
+
+    pub fn wake_one(word: &AtomicU32) {
+        unsafe { memory_atomic_notify(addr(word), 1) };
+    }
+
+    pub fn wake_all(word: &AtomicU32) {
+        unsafe { memory_atomic_notify(addr(word), u32::MAX) };
+    }
+}
+
+#[cfg(not(any(
+    all(
+        target_os = "linux",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64"
+        ),
+        not(miri)
+    ),
+    all(windows, not(miri)),
+    all(target_os = "macos", not(miri)),
+    all(target_arch = "wasm32", target_feature = "atomics", not(miri))
+)))]
+mod sys {
+    pub use super::fallback::{wait, wake_all, wake_one};
+
+    pub const BACKEND: &str = "fallback";
+}
+
+// The same handoff through this platform's backend and through the fallback.
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn futex_wakes_waiters_and_times_out() {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    type Wait = fn(&AtomicU32, u32, Option<Instant>);
+    type Wake = fn(&AtomicU32);
+    let backends: [(Wait, Wake, Wake); 2] = [
+        (wait, wake_one, wake_all),
+        (fallback::wait, fallback::wake_one, fallback::wake_all),
+    ];
+    for (wait, wake_one, wake_all) in backends {
+        // A changed word doesn't wait at all, and neither does a deadline that's passed.
+        let word = AtomicU32::new(1);
+        wait(&word, 0, None);
+        wait(&word, 1, Some(Instant::now()));
+        let start = Instant::now();
+        wait(&word, 1, Some(start + Duration::from_millis(20)));
+
+        let word = &AtomicU32::new(0);
+        std::thread::scope(|s| {
+            let waiters: Vec<_> = (0..3)
+                .map(|_| {
+                    s.spawn(move || {
+                        while word.load(Ordering::Acquire) == 0 {
+                            wait(word, 0, None);
+                        }
+                    })
+                })
+                .collect();
+            std::thread::sleep(Duration::from_millis(10));
+            word.store(1, Ordering::Release);
+            wake_one(word);
+            wake_all(word);
+            for w in waiters {
+                w.join().unwrap();
+            }
+        });
+    }
+    assert!([
+        "futex",
+        "WaitOnAddress",
+        "ulock",
+        "memory.atomic.wait32",
+        "fallback"
+    ]
+    .contains(&BACKEND));
+}
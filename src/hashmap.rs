@@ -0,0 +1,338 @@
+use crate::cache_padded::CachePadded;
+use crate::rwlock::{RwLock, RwLockWriteGuard};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+
+// Kept in a CachePadded so neighbouring shards' lock words don't false-share.
+struct Shard<K, V> {
+    map: RwLock<HashMap<K, V>>,
+}
+
+/// A concurrent hash map made of independently locked `HashMap` shards.
+pub struct ConcurrentHashMap<K, V, S = RandomState> {
+    shards: Box<[CachePadded<Shard<K, V>>]>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> ConcurrentHashMap<K, V> {
+    pub fn new() -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, |n| n.get()) * 4;
+        Self::with_shards(shards)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> ConcurrentHashMap<K, V, S> {
+    /// Uses `shards` (rounded up to a power of two) shards and `hasher` to pick between them.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        let n = shards.max(1).next_power_of_two();
+        Self {
+            shards: (0..n)
+                .map(|_| Shard {
+                    map: RwLock::new(HashMap::new()),
+                })
+                .map(CachePadded::new)
+                .collect(),
+            hasher,
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &RwLock<HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        // The shard maps hash with their own RandomState, so using the low bits here doesn't
+        // leave each shard with a skewed subset of its own buckets.
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash & (self.shards.len() - 1)].map
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().insert(key, value)
+    }
+
+    /// Runs `f` on the value for `key` while holding its shard's read lock.
+    pub fn get<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).read().get(key).map(f)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).read().contains_key(key)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).write().remove(key)
+    }
+
+    /// Locks `key`'s shard for writing and returns its entry. Anything done through the entry
+    /// is atomic with respect to other writers, and the shard stays locked until the entry (or
+    /// the reference it turns into) is dropped.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+        Entry {
+            map: self.shard(&key).write(),
+            key,
+        }
+    }
+
+    /// Runs `f` on the value for `key` under its shard's write lock.
+    pub fn update<Q, R>(&self, key: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).write().get_mut(key).map(f)
+    }
+
+    /// Applies `update` to the value for `key`, or inserts `insert()` if there is none.
+    pub fn upsert(&self, key: K, insert: impl FnOnce() -> V, update: impl FnOnce(&mut V)) {
+        let mut map = self.shard(&key).write();
+        match map.get_mut(&key) {
+            Some(v) => update(v),
+            None => {
+                map.insert(key, insert());
+            }
+        }
+    }
+
+    /// The number of entries. Shards are counted one at a time, so under concurrent
+    /// modification this is only a snapshot.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.map.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.map.read().is_empty())
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ConcurrentHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A key's slot in a [`ConcurrentHashMap`], holding its shard's write lock.
+pub struct Entry<'a, K, V> {
+    map: RwLockWriteGuard<'a, HashMap<K, V>>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Runs `f` on the value if there is one.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(v) = self.map.get_mut(&self.key) {
+            f(v);
+        }
+        self
+    }
+
+    pub fn or_insert(self, value: V) -> RefMut<'a, K, V> {
+        self.or_insert_with(|| value)
+    }
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> RefMut<'a, K, V> {
+        let Entry { mut map, key } = self;
+        // The value lives in the map's heap storage, not in the guard, so it stays put when the
+        // guard moves into the RefMut.
+        let value: *mut V = map.entry(key).or_insert_with(f);
+        RefMut { _map: map, value }
+    }
+
+    pub fn or_default(self) -> RefMut<'a, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+/// A mutable reference to a value in a [`ConcurrentHashMap`]; its shard stays write-locked
+/// until this is dropped.
+pub struct RefMut<'a, K, V> {
+    _map: RwLockWriteGuard<'a, HashMap<K, V>>,
+    value: *mut V,
+}
+
+impl<K, V> Deref for RefMut<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // Safety: the shard is locked and nothing can touch the map but through us.
+        unsafe { &*self.value }
+    }
+}
+
+impl<K, V> DerefMut for RefMut<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        unsafe { &mut *self.value }
+    }
+}
+
+#[test]
+fn hashmap_basic() {
+    let map = ConcurrentHashMap::with_shards(4);
+    assert_eq!(map.insert("a".to_string(), 1), None);
+    assert_eq!(map.insert("b".to_string(), 2), None);
+    assert_eq!(map.insert("a".to_string(), 3), Some(1));
+    assert_eq!(map.get("a", |v| *v), Some(3));
+    assert_eq!(map.get("c", |v| *v), None);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.remove("b"), Some(2));
+    assert!(!map.contains_key("b"));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn hashmap_concurrent() {
+    let n = if cfg!(miri) { 50 } else { 1000 };
+    let map = &ConcurrentHashMap::new();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    map.insert(i * 4 + t, t);
+                }
+                for i in (0..n).step_by(2) {
+                    assert_eq!(map.remove(&(i * 4 + t)), Some(t));
+                }
+            });
+        }
+    });
+    assert_eq!(map.len(), 2 * n);
+    for k in 0..4 * n {
+        assert_eq!(
+            map.get(&k, |v| *v),
+            if (k / 4) % 2 == 1 { Some(k % 4) } else { None }
+        );
+    }
+}
+
+#[test]
+fn hashmap_entry_is_atomic() {
+    let n = if cfg!(miri) { 50 } else { 1000 };
+    let map = &ConcurrentHashMap::with_shards(2);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    *map.entry(i % 10).or_insert(0) += 1;
+                    map.upsert(i % 10 + 10, || 1, |v| *v += 1);
+                    map.entry(i % 10 + 20).and_modify(|v| *v += 1).or_default();
+                }
+            });
+        }
+    });
+    let per_key = 4 * n / 10;
+    for k in 0..10 {
+        assert_eq!(map.get(&k, |v| *v), Some(per_key));
+        assert_eq!(map.get(&(k + 10), |v| *v), Some(per_key));
+        assert_eq!(map.get(&(k + 20), |v| *v), Some(per_key - 1));
+    }
+    assert_eq!(map.update(&0, |v| std::mem::replace(v, 7)), Some(per_key));
+    assert_eq!(map.update(&99, |v| *v), None);
+    assert_eq!(map.get(&0, |v| *v), Some(7));
+}
+
+#[cfg(shuttle)]
+#[test]
+fn hashmap_shuttle() {
+    use shuttle::sync::Arc;
+    use shuttle::thread;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    crate::sync_shim::shuttle_check(|| {
+        // A fixed hasher, so a failing schedule replays with the same shards.
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let map = Arc::new(ConcurrentHashMap::with_shards_and_hasher(2, hasher));
+        let writers: Vec<_> = (0..2)
+            .map(|t| {
+                let map = Arc::clone(&map);
+                thread::spawn(move || {
+                    map.insert(t, 0);
+                    map.upsert(2, || 1, |v| *v += 1);
+                })
+            })
+            .collect();
+        let seen = map.get(&2, |v| *v);
+        assert!(seen.is_none() || seen <= Some(2));
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(map.get(&2, |v| *v), Some(2));
+        assert_eq!(map.len(), 3);
+    });
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+proptest::proptest! {
+    // Each thread's inserts, gets and removes on a few keys they all share, interleaved under
+    // sched as the choices say, and then a get of every key. Every result has to be one that
+    // running the ops one at a time, in some order that keeps each thread's and respects
+    // which returned before which was called, gives. A failure shrinks to a short, simple
+    // interleaving, since the choices are the interleaving.
+    #[test]
+    #[cfg_attr(miri, ignore = "proptest persists failures in the working directory")]
+    fn hashmap_matches_hash_map(
+        choices in proptest::collection::vec(0..4usize, 0..64),
+        threads in proptest::collection::vec(
+            proptest::collection::vec((0..3u8, 0..3u64), 0..8),
+            1..4,
+        ),
+    ) {
+        use crate::linearizability::{History, Map, MapOp};
+        use crate::sched;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+        use std::sync::Arc;
+
+        // A fixed hasher, so the choices replay with the same shards.
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let map = Arc::new(ConcurrentHashMap::with_shards_and_hasher(2, hasher));
+        let history = Arc::new(History::new());
+        sched::run_guided(&choices, |s| {
+            for (t, ops) in threads.iter().cloned().enumerate() {
+                let (map, history) = (Arc::clone(&map), Arc::clone(&history));
+                s.spawn(move || {
+                    for (i, (op, k)) in ops.into_iter().enumerate() {
+                        // Every insert's value its own, so a get says which it saw.
+                        let v = t as u64 * 100 + i as u64;
+                        match op {
+                            0 => history.record(MapOp::Insert(k, v), || map.insert(k, v)),
+                            1 => history.record(MapOp::Get(k), || map.get(&k, |&v| v)),
+                            _ => history.record(MapOp::Remove(k), || map.remove(&k)),
+                        };
+                    }
+                });
+            }
+        });
+        for k in 0..3 {
+            history.record(MapOp::Get(k), || map.get(&k, |&v| v));
+        }
+        history
+            .check(Map::default())
+            .map_err(|w| proptest::test_runner::TestCaseError::fail(w.to_string()))?;
+    }
+}
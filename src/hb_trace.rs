@@ -0,0 +1,533 @@
+use crate::thread_id;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+const DEFAULT_THREADS: usize = 64;
+
+type Log = Mutex<Vec<(thread::ThreadId, Event)>>;
+
+/// What an [`Event`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A location's initial value, written when it was made.
+    Init,
+    Load,
+    Store,
+    Rmw,
+    Fence,
+}
+
+/// One recorded operation.
+#[derive(Debug, Clone)]
+pub struct Event {
+    // None for Init, which has no thread of its own in the graph.
+    thread: Option<usize>,
+    location: Option<usize>,
+    kind: Kind,
+    order: Ordering,
+    read: Option<u32>,
+    written: Option<u32>,
+    // The ID of the write read from, and of the write made.
+    source: Option<u64>,
+    write: Option<u64>,
+}
+
+impl Event {
+    pub fn thread(&self) -> Option<usize> {
+        self.thread
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn order(&self) -> Ordering {
+        self.order
+    }
+
+    /// The value a load or RMW read.
+    pub fn read(&self) -> Option<u32> {
+        self.read
+    }
+
+    /// The value a store or RMW wrote.
+    pub fn written(&self) -> Option<u32> {
+        self.written
+    }
+
+    fn is_acquire(&self) -> bool {
+        matches!(
+            self.order,
+            Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+        )
+    }
+
+    fn is_release(&self) -> bool {
+        matches!(
+            self.order,
+            Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+        )
+    }
+}
+
+/// Records the operations on its [`TracedAtomic`]s and fences, for working out afterwards
+/// which happened before which, and why.
+///
+/// Each thread appends to a log of its own, picked by its [`thread_id`], so recording adds
+/// no synchronization between threads that run at the same time. A thread's events are
+/// taken to be in program order. Happens-before only goes through the recorded operations:
+/// spawning and joining threads, or locks, don't show.
+pub struct Recorder {
+    locations: Mutex<Vec<String>>,
+    next_write: AtomicU64,
+    // A thread's log can pass to a later thread with its thread_id, so each entry says which
+    // thread made it.
+    logs: Box<[Log]>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::with_threads(DEFAULT_THREADS)
+    }
+
+    /// A recorder for threads with IDs below `threads`.
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            locations: Mutex::new(Vec::new()),
+            next_write: AtomicU64::new(0),
+            logs: (0..threads).map(|_| Mutex::new(Vec::new())).collect(),
+        }
+    }
+
+    /// A new location called `name`, holding `value`.
+    pub fn atomic(&self, name: impl Into<String>, value: u32) -> TracedAtomic<'_> {
+        let location = {
+            let mut locations = self.locations.lock().unwrap();
+            locations.push(name.into());
+            locations.len() - 1
+        };
+        let write = self.write_id();
+        self.log(Event {
+            thread: None,
+            location: Some(location),
+            kind: Kind::Init,
+            order: Ordering::Relaxed,
+            read: None,
+            written: Some(value),
+            source: None,
+            write: Some(write),
+        });
+        TracedAtomic {
+            recorder: self,
+            location,
+            cell: AtomicU64::new(pack(write, value)),
+        }
+    }
+
+    pub fn fence(&self, order: Ordering) {
+        std::sync::atomic::fence(order);
+        self.log(Event {
+            thread: None,
+            location: None,
+            kind: Kind::Fence,
+            order,
+            read: None,
+            written: None,
+            source: None,
+            write: None,
+        });
+    }
+
+    fn write_id(&self) -> u64 {
+        // Write IDs only have to be unique, and fit in 32 bits.
+        let id = self.next_write.fetch_add(1, Ordering::Relaxed);
+        assert!(id <= u32::MAX as u64, "too many writes to record");
+        id
+    }
+
+    fn log(&self, event: Event) {
+        let index = thread_id::current().expect("recording during thread teardown");
+        let log = self
+            .logs
+            .get(index)
+            .expect("thread ID past the recorder's threads");
+        log.lock().unwrap().push((thread::current().id(), event));
+    }
+
+    /// The events recorded so far, and the happens-before graph over them.
+    pub fn graph(&self) -> HbGraph {
+        let mut events = Vec::new();
+        let mut sb = Vec::new();
+        // Threads are numbered in the order their logs first show them.
+        let mut threads: HashMap<thread::ThreadId, (usize, usize)> = HashMap::new();
+        for log in self.logs.iter() {
+            for (id, e) in log.lock().unwrap().iter() {
+                let mut e = e.clone();
+                if e.kind != Kind::Init {
+                    let next = threads.len();
+                    let last = events.len();
+                    let (number, prev) = threads.entry(*id).or_insert((next, last));
+                    if *prev != last {
+                        sb.push((*prev, last));
+                    }
+                    *prev = last;
+                    e.thread = Some(*number);
+                }
+                events.push(e);
+            }
+        }
+        let writes: HashMap<u64, usize> = events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.write.map(|w| (w, i)))
+            .collect();
+        let rf: Vec<(usize, usize)> = events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.source.map(|w| (writes[&w], i)))
+            .collect();
+        let prev_in_thread: HashMap<usize, usize> = sb.iter().map(|&(a, b)| (b, a)).collect();
+        let next_in_thread: HashMap<usize, usize> = sb.iter().copied().collect();
+        let source_of: HashMap<usize, usize> = rf.iter().map(|&(w, r)| (r, w)).collect();
+
+        // sw: a release store, or a release fence before a store, heads a release sequence
+        // of RMWs each reading the one before; an acquire load, or an acquire fence after a
+        // load, reading from any write of it synchronizes with the head.
+        let mut sw = Vec::new();
+        for &(w, r) in &rf {
+            let acquire = std::iter::successors(Some(r), |e| next_in_thread.get(e).copied())
+                .find(|&e| (e == r || events[e].kind == Kind::Fence) && events[e].is_acquire());
+            let Some(acquire) = acquire else {
+                continue;
+            };
+            let mut head = w;
+            loop {
+                let release = if events[head].is_release() && events[head].kind != Kind::Init {
+                    Some(head)
+                } else {
+                    std::iter::successors(prev_in_thread.get(&head).copied(), |e| {
+                        prev_in_thread.get(e).copied()
+                    })
+                    .find(|&e| events[e].kind == Kind::Fence && events[e].is_release())
+                };
+                if let Some(release) = release {
+                    sw.push((release, acquire));
+                }
+                match source_of.get(&head) {
+                    Some(&prev) if events[head].kind == Kind::Rmw => head = prev,
+                    _ => break,
+                }
+            }
+        }
+        sw.sort_unstable();
+        sw.dedup();
+
+        let mut successors = vec![Vec::new(); events.len()];
+        for &(a, b) in sb.iter().chain(&sw) {
+            successors[a].push(b);
+        }
+        // hb is the transitive closure of sb and sw; fine for the few thousand events a
+        // teaching example records.
+        let hb = (0..events.len())
+            .map(|a| {
+                let mut seen = vec![false; events.len()];
+                let mut stack = successors[a].clone();
+                while let Some(e) = stack.pop() {
+                    if !std::mem::replace(&mut seen[e], true) {
+                        stack.extend(&successors[e]);
+                    }
+                }
+                seen
+            })
+            .collect();
+        HbGraph {
+            locations: self.locations.lock().unwrap().clone(),
+            events,
+            sb,
+            rf,
+            sw,
+            hb,
+        }
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A location's word: the ID of the write that stored it on top, the value below, so a load
+// knows which write it read from.
+fn pack(write: u64, value: u32) -> u64 {
+    (write << 32) | value as u64
+}
+
+fn unpack(word: u64) -> (u64, u32) {
+    (word >> 32, word as u32)
+}
+
+/// An `AtomicU32` whose operations a [`Recorder`] logs.
+pub struct TracedAtomic<'r> {
+    recorder: &'r Recorder,
+    location: usize,
+    cell: AtomicU64,
+}
+
+impl TracedAtomic<'_> {
+    fn event(&self, kind: Kind, order: Ordering) -> Event {
+        Event {
+            thread: None,
+            location: Some(self.location),
+            kind,
+            order,
+            read: None,
+            written: None,
+            source: None,
+            write: None,
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> u32 {
+        let (source, value) = unpack(self.cell.load(order));
+        self.recorder.log(Event {
+            read: Some(value),
+            source: Some(source),
+            ..self.event(Kind::Load, order)
+        });
+        value
+    }
+
+    pub fn store(&self, value: u32, order: Ordering) {
+        let write = self.recorder.write_id();
+        self.cell.store(pack(write, value), order);
+        self.recorder.log(Event {
+            written: Some(value),
+            write: Some(write),
+            ..self.event(Kind::Store, order)
+        });
+    }
+
+    /// Replaces the value with `f` of it, as one RMW, returning the old value.
+    fn rmw(&self, order: Ordering, f: impl Fn(u32) -> u32) -> u32 {
+        let write = self.recorder.write_id();
+        let failure = match order {
+            Ordering::Release => Ordering::Relaxed,
+            Ordering::AcqRel => Ordering::Acquire,
+            order => order,
+        };
+        let mut word = self.cell.load(failure);
+        loop {
+            let (source, value) = unpack(word);
+            let new = f(value);
+            match self
+                .cell
+                .compare_exchange_weak(word, pack(write, new), order, failure)
+            {
+                Ok(_) => {
+                    self.recorder.log(Event {
+                        read: Some(value),
+                        written: Some(new),
+                        source: Some(source),
+                        write: Some(write),
+                        ..self.event(Kind::Rmw, order)
+                    });
+                    return value;
+                }
+                Err(w) => word = w,
+            }
+        }
+    }
+
+    pub fn swap(&self, value: u32, order: Ordering) -> u32 {
+        self.rmw(order, |_| value)
+    }
+
+    pub fn fetch_add(&self, value: u32, order: Ordering) -> u32 {
+        self.rmw(order, |v| v.wrapping_add(value))
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u32, u32> {
+        let write = self.recorder.write_id();
+        let mut word = self.cell.load(failure);
+        loop {
+            let (source, value) = unpack(word);
+            if value != current {
+                self.recorder.log(Event {
+                    read: Some(value),
+                    source: Some(source),
+                    ..self.event(Kind::Load, failure)
+                });
+                return Err(value);
+            }
+            match self
+                .cell
+                .compare_exchange_weak(word, pack(write, new), success, failure)
+            {
+                Ok(_) => {
+                    self.recorder.log(Event {
+                        read: Some(value),
+                        written: Some(new),
+                        source: Some(source),
+                        write: Some(write),
+                        ..self.event(Kind::Rmw, success)
+                    });
+                    return Ok(value);
+                }
+                Err(w) => word = w,
+            }
+        }
+    }
+}
+
+/// The recorded events, with the sequenced-before, reads-from and synchronizes-with edges
+/// between them, and happens-before as their closure.
+pub struct HbGraph {
+    locations: Vec<String>,
+    events: Vec<Event>,
+    sb: Vec<(usize, usize)>,
+    rf: Vec<(usize, usize)>,
+    sw: Vec<(usize, usize)>,
+    // hb[a][b]: whether a happens before b.
+    hb: Vec<Vec<bool>>,
+}
+
+impl HbGraph {
+    /// Every event, each thread's in program order. Edges refer to events by index here.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// The name of the location an event accessed.
+    pub fn location(&self, event: usize) -> Option<&str> {
+        self.events[event].location.map(|l| &self.locations[l][..])
+    }
+
+    /// The (write, read) pairs of each read and the write it read from.
+    pub fn reads_from(&self) -> &[(usize, usize)] {
+        &self.rf
+    }
+
+    /// The (release, acquire) pairs that synchronize.
+    pub fn synchronizes_with(&self) -> &[(usize, usize)] {
+        &self.sw
+    }
+
+    pub fn happens_before(&self, a: usize, b: usize) -> bool {
+        self.hb[a][b]
+    }
+
+    fn label(&self, i: usize) -> String {
+        let e = &self.events[i];
+        let loc = self.location(i).unwrap_or("");
+        let read = e.read.unwrap_or(0);
+        let written = e.written.unwrap_or(0);
+        match e.kind {
+            Kind::Init => format!("{} = {}", loc, written),
+            Kind::Load => format!("load {} == {}\\n{:?}", loc, read, e.order),
+            Kind::Store => format!("store {} = {}\\n{:?}", loc, written, e.order),
+            Kind::Rmw => format!("rmw {} {} -> {}\\n{:?}", loc, read, written, e.order),
+            Kind::Fence => format!("fence\\n{:?}", e.order),
+        }
+    }
+
+    /// The graph in Graphviz's DOT language: a cluster per thread, with sb edges down it,
+    /// dashed red rf edges and bold green sw edges across. Render with `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        let mut threads: Vec<usize> = self.events.iter().filter_map(Event::thread).collect();
+        threads.sort_unstable();
+        threads.dedup();
+        let mut dot = String::from("digraph hb {\n  node [shape=box, fontname=monospace];\n");
+        for (i, e) in self.events.iter().enumerate() {
+            if e.thread.is_none() {
+                let _ = writeln!(dot, "  e{} [label=\"{}\", style=dashed];", i, self.label(i));
+            }
+        }
+        for t in threads {
+            let _ = writeln!(
+                dot,
+                "  subgraph cluster_t{} {{\n    label=\"thread {}\";",
+                t, t
+            );
+            for (i, e) in self.events.iter().enumerate() {
+                if e.thread == Some(t) {
+                    let _ = writeln!(dot, "    e{} [label=\"{}\"];", i, self.label(i));
+                }
+            }
+            dot.push_str("  }\n");
+        }
+        for &(a, b) in &self.sb {
+            let _ = writeln!(dot, "  e{} -> e{};", a, b);
+        }
+        for &(a, b) in &self.rf {
+            let _ = writeln!(
+                dot,
+                "  e{} -> e{} [label=rf, color=red, style=dashed, constraint=false];",
+                a, b
+            );
+        }
+        for &(a, b) in &self.sw {
+            let _ = writeln!(
+                dot,
+                "  e{} -> e{} [label=sw, color=darkgreen, penwidth=2];",
+                a, b
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+fn record_mp(store: Ordering, load: Ordering) -> HbGraph {
+    let recorder = Recorder::new();
+    let data = recorder.atomic("data", 0);
+    let flag = recorder.atomic("flag", 0);
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            data.store(42, Ordering::Relaxed);
+            flag.store(1, store);
+        });
+        s.spawn(|| {
+            while flag.load(load) == 0 {
+                std::thread::yield_now();
+            }
+            assert_eq!(data.load(Ordering::Relaxed), 42);
+        });
+    });
+    recorder.graph()
+}
+
+#[test]
+fn hb_trace_finds_sw_of_message_passing() {
+    let find = |g: &HbGraph, kind, loc: &str| {
+        (0..g.events().len())
+            .rev()
+            .find(|&i| g.events()[i].kind() == kind && g.location(i) == Some(loc))
+            .unwrap()
+    };
+    let g = record_mp(Ordering::Release, Ordering::Acquire);
+    let (data_store, flag_store) = (find(&g, Kind::Store, "data"), find(&g, Kind::Store, "flag"));
+    let (flag_load, data_load) = (find(&g, Kind::Load, "flag"), find(&g, Kind::Load, "data"));
+    assert_eq!(g.synchronizes_with(), [(flag_store, flag_load)]);
+    assert!(g.reads_from().contains(&(data_store, data_load)));
+    assert!(g.happens_before(data_store, data_load));
+    assert!(!g.happens_before(data_load, data_store));
+    let dot = g.to_dot();
+    assert!(dot.starts_with("digraph hb {"));
+    assert!(dot.contains(&format!("e{} -> e{} [label=sw", flag_store, flag_load)));
+
+    // Relaxed, the same run has no sw edge, so nothing orders the data accesses.
+    let g = record_mp(Ordering::Relaxed, Ordering::Relaxed);
+    assert!(g.synchronizes_with().is_empty());
+    assert!(!g.happens_before(find(&g, Kind::Store, "data"), find(&g, Kind::Load, "data")));
+}
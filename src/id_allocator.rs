@@ -0,0 +1,86 @@
+use crate::bitset::AtomicBitSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Hands out small integer IDs from `0..capacity` and takes them back for reuse.
+///
+/// Both operations are a few atomic RMWs on an [`AtomicBitSet`]. Searches start where the last
+/// allocation left off, so concurrent allocators mostly hit different words.
+pub struct IdAllocator {
+    used: AtomicBitSet,
+    hint: AtomicUsize,
+}
+
+impl IdAllocator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            used: AtomicBitSet::new(capacity),
+            hint: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.used.capacity()
+    }
+
+    /// Takes a free ID, or returns None if all of them are in use.
+    pub fn allocate(&self) -> Option<usize> {
+        let id = self
+            .used
+            .insert_first_absent(self.hint.load(Ordering::Relaxed))?;
+        self.hint.store(id + 1, Ordering::Relaxed);
+        Some(id)
+    }
+
+    /// Gives `id` back.
+    ///
+    /// # Panics
+    ///
+    /// If `id` isn't currently allocated.
+    pub fn release(&self, id: usize) {
+        assert!(self.used.remove(id), "released unallocated id {}", id);
+    }
+
+    /// How many IDs are in use. Only a snapshot under concurrent use.
+    pub fn in_use(&self) -> usize {
+        self.used.count()
+    }
+}
+
+#[test]
+fn id_allocator_unique_ids() {
+    use std::sync::atomic::AtomicBool;
+    let n = if cfg!(miri) { 200 } else { 2000 };
+    let ids = &IdAllocator::new(100);
+    let held = &(0..100).map(|_| AtomicBool::new(false)).collect::<Vec<_>>();
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(move || {
+                let mut mine = Vec::new();
+                for i in 0..n {
+                    if let Some(id) = ids.allocate() {
+                        assert!(
+                            !held[id].swap(true, Ordering::Relaxed),
+                            "{} handed out twice",
+                            id
+                        );
+                        mine.push(id);
+                    }
+                    if i % 3 == 0 || mine.len() > 20 {
+                        if let Some(id) = mine.pop() {
+                            held[id].store(false, Ordering::Relaxed);
+                            ids.release(id);
+                        }
+                    }
+                }
+                for id in mine {
+                    held[id].store(false, Ordering::Relaxed);
+                    ids.release(id);
+                }
+            });
+        }
+    });
+    assert_eq!(ids.in_use(), 0);
+    let all: Vec<_> = (0..100).map(|_| ids.allocate().unwrap()).collect();
+    assert_eq!(ids.allocate(), None);
+    assert_eq!(all.len(), 100);
+}
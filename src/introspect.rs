@@ -0,0 +1,220 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+// A registry of the named primitives alive right now and what they're doing, for a debug
+// endpoint or a dump on a signal: `dump` lists every lock with who holds it and how many are
+// waiting, and every channel with how much is queued. Under the `introspect` feature a
+// `rwlock::RwLock` registers when it's `named`, and an `mpmc` channel when it's `publish`ed;
+// each is dropped from the registry once it's gone.
+//
+// A snapshot is taken one primitive at a time, so it's only consistent within each one. It
+// takes a std mutex, so a signal handler shouldn't call `dump` itself but wake a thread that
+// does.
+
+/// What a registered primitive is doing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Lock {
+        readers: usize,
+        /// The thread holding it for writing, if one is.
+        writer: Option<String>,
+        waiters: usize,
+    },
+    Channel {
+        len: usize,
+        capacity: usize,
+        senders: usize,
+        receivers: usize,
+        /// Whether nothing more can go through: it was closed, or one end is gone.
+        closed: bool,
+    },
+}
+
+/// A registered primitive, as [`snapshot`] found it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub state: State,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.state {
+            State::Lock {
+                readers,
+                writer,
+                waiters,
+            } => {
+                write!(f, "lock {}: ", self.name)?;
+                match (writer, readers) {
+                    (Some(w), _) => write!(f, "write-locked by thread {}", w)?,
+                    (None, 0) => write!(f, "unlocked")?,
+                    (None, n) => write!(f, "read-locked by {}", n)?,
+                }
+                write!(f, ", {} waiting", waiters)
+            }
+            State::Channel {
+                len,
+                capacity,
+                senders,
+                receivers,
+                closed,
+            } => write!(
+                f,
+                "channel {}: {} of {} queued, {} senders, {} receivers{}",
+                self.name,
+                len,
+                capacity,
+                senders,
+                receivers,
+                if *closed { ", closed" } else { "" }
+            ),
+        }
+    }
+}
+
+type Probe = Box<dyn Fn() -> Option<State> + Send + Sync>;
+
+static REGISTRY: Mutex<Vec<(String, Probe)>> = Mutex::new(Vec::new());
+
+/// Registers a primitive by `name`; `probe` reads its state, or says it's gone with None,
+/// which drops it from the registry.
+pub fn register(name: &str, probe: impl Fn() -> Option<State> + Send + Sync + 'static) {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((name.to_string(), Box::new(probe)));
+}
+
+/// Every registered primitive still alive, in the order they registered.
+pub fn snapshot() -> Vec<Entry> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = Vec::new();
+    registry.retain(|(name, probe)| match probe() {
+        Some(state) => {
+            entries.push(Entry {
+                name: name.clone(),
+                state,
+            });
+            true
+        }
+        None => false,
+    });
+    entries
+}
+
+/// [`snapshot`], a line per primitive.
+pub fn dump() -> String {
+    snapshot().iter().map(|e| format!("{}\n", e)).collect()
+}
+
+/// What a lock keeps up to date for the registry: it tells this of every acquisition,
+/// release and wait, and the registry reads it back.
+#[derive(Default)]
+pub struct LockState {
+    readers: AtomicUsize,
+    writer: Mutex<Option<String>>,
+    waiters: AtomicUsize,
+}
+
+impl LockState {
+    /// Registers a lock as `name`, for as long as the returned state is alive.
+    pub fn register(name: &str) -> Arc<Self> {
+        let state = Arc::new(Self::default());
+        let weak: Weak<Self> = Arc::downgrade(&state);
+        register(name, move || {
+            let s = weak.upgrade()?;
+            let writer = s.writer.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            Some(State::Lock {
+                readers: s.readers.load(Ordering::Relaxed),
+                writer,
+                waiters: s.waiters.load(Ordering::Relaxed),
+            })
+        });
+        state
+    }
+
+    pub fn acquired(&self, write: bool) {
+        if write {
+            let t = thread::current();
+            let name = match t.name() {
+                Some(name) => format!("'{}'", name),
+                None => format!("{:?}", t.id()),
+            };
+            *self.writer.lock().unwrap_or_else(|e| e.into_inner()) = Some(name);
+        } else {
+            self.readers.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn released(&self, write: bool) {
+        if write {
+            *self.writer.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        } else {
+            self.readers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn waiting(&self) {
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn done_waiting(&self) {
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn introspect_dumps_named_locks_and_channels() {
+    use crate::{mpmc, rwlock::RwLock};
+    use std::time::{Duration, Instant};
+
+    let ours = |name: &str| snapshot().into_iter().find(|e| e.name == name);
+    let lock = RwLock::new(0).named("introspect-test-lock");
+    let guard = lock.write();
+    thread::scope(|s| {
+        let reader = s.spawn(|| *lock.read());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let entry = ours("introspect-test-lock").unwrap();
+            if let State::Lock { waiters: 1, .. } = entry.state {
+                let line = entry.to_string();
+                assert!(
+                    line.starts_with(
+                        "lock introspect-test-lock: write-locked by thread \
+                         'introspect::introspect_dumps"
+                    ),
+                    "{}",
+                    line
+                );
+                assert!(line.ends_with(", 1 waiting"), "{}", line);
+                break;
+            }
+            assert!(Instant::now() < deadline, "the reader never waited");
+            thread::yield_now();
+        }
+        drop(guard);
+        reader.join().unwrap();
+    });
+    let _r = lock.read();
+    assert!(dump().contains("lock introspect-test-lock: read-locked by 1, 0 waiting\n"));
+
+    let (tx, rx) = mpmc::bounded(4);
+    tx.publish("introspect-test-channel");
+    tx.send(1).unwrap();
+    assert!(
+        dump().contains("channel introspect-test-channel: 1 of 4 queued, 1 senders, 1 receivers\n")
+    );
+    drop(rx);
+    assert!(ours("introspect-test-channel")
+        .unwrap()
+        .to_string()
+        .ends_with(", closed"));
+    drop(tx);
+    assert_eq!(ours("introspect-test-channel"), None);
+    drop(_r);
+    drop(lock);
+    assert_eq!(ours("introspect-test-lock"), None);
+}
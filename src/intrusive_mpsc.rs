@@ -0,0 +1,245 @@
+use crate::sync_shim::atomic::{AtomicBool, AtomicPtr, Ordering};
+use crate::sync_shim::yield_now;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::Arc;
+
+/// The link a type embeds to be queued in an [`MpscQueue`] without allocating.
+pub struct Link {
+    next: AtomicPtr<Link>,
+    queued: AtomicBool,
+}
+
+impl Link {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the owning node is currently sitting in a queue.
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Types that embed a [`Link`].
+///
+/// The link must be the first field of a `#[repr(C)]` struct, so a pointer to the link is
+/// also a pointer to the node; `push` asserts this.
+pub trait Linked {
+    fn link(&self) -> &Link;
+}
+
+/// Vyukov's intrusive multi-producer single-consumer queue.
+///
+/// Producers push with a single `swap`, the consumer never allocates, and nodes are handed
+/// over as `Arc`s so a node can't be freed while queued.
+pub struct MpscQueue<T: Linked> {
+    // Producers swap themselves into head; the consumer walks from tail.
+    head: AtomicPtr<Link>,
+    tail: UnsafeCell<*mut Link>,
+    // Heap-allocated so its address survives moves of the queue. Kept as a raw pointer
+    // (rather than a Box) since it's aliased by head/tail.
+    stub: *mut Link,
+    _nodes: PhantomData<Arc<T>>,
+}
+
+unsafe impl<T> Send for MpscQueue<T> where T: Linked + Send + Sync {}
+unsafe impl<T> Sync for MpscQueue<T> where T: Linked + Send + Sync {}
+
+/// Result of a single [`MpscQueue::try_pop`] attempt.
+pub enum Pop<T> {
+    Data(Arc<T>),
+    Empty,
+    // A producer has swapped itself into head but not yet linked the previous node to it.
+    Inconsistent,
+}
+
+impl<T: Linked> MpscQueue<T> {
+    pub fn new() -> Self {
+        let stub = Box::into_raw(Box::new(Link::new()));
+        Self {
+            head: AtomicPtr::new(stub),
+            tail: UnsafeCell::new(stub),
+            stub,
+            _nodes: PhantomData,
+        }
+    }
+
+    /// Pushes `node`, or hands it back if it's already in a queue.
+    pub fn push(&self, node: Arc<T>) -> Result<(), Arc<T>> {
+        let link = node.link();
+        assert!(
+            ptr::eq(
+                link as *const Link as *const u8,
+                &*node as *const T as *const u8
+            ),
+            "Link must be the first field of a #[repr(C)] node"
+        );
+        if link.queued.swap(true, Ordering::Acquire) {
+            return Err(node);
+        }
+        let link = Arc::into_raw(node) as *mut Link;
+        unsafe { self.push_link(link) };
+        Ok(())
+    }
+
+    unsafe fn push_link(&self, link: *mut Link) {
+        (*link).next.store(ptr::null_mut(), Ordering::Relaxed);
+        // AcqRel: Release publishes the node's contents to the consumer, Acquire orders us
+        // after the producer whose node we're about to link to.
+        let prev = self.head.swap(link, Ordering::AcqRel);
+        // Between the swap and this store the queue is "inconsistent": the consumer can see
+        // prev but not reach us yet.
+        (*prev).next.store(link, Ordering::Release);
+    }
+
+    /// Makes one attempt at popping a node.
+    ///
+    /// # Safety
+    ///
+    /// Must not be called concurrently with another `try_pop` or `pop`.
+    pub unsafe fn try_pop(&self) -> Pop<T> {
+        let mut tail = *self.tail.get();
+        let mut next = (*tail).next.load(Ordering::Acquire);
+        if tail == self.stub {
+            if next.is_null() {
+                return Pop::Empty;
+            }
+            *self.tail.get() = next;
+            tail = next;
+            next = (*next).next.load(Ordering::Acquire);
+        }
+        if next.is_null() {
+            if tail != self.head.load(Ordering::Acquire) {
+                return Pop::Inconsistent;
+            }
+            // tail is the last node; put the stub behind it so tail can move past it.
+            self.push_link(self.stub);
+            next = (*tail).next.load(Ordering::Acquire);
+            if next.is_null() {
+                return Pop::Inconsistent;
+            }
+        }
+        *self.tail.get() = next;
+        let node = Arc::from_raw(tail as *const T);
+        node.link().queued.store(false, Ordering::Release);
+        Pop::Data(node)
+    }
+
+    /// Pops a node, spinning past the short window where a producer is mid-push.
+    ///
+    /// # Safety
+    ///
+    /// Must not be called concurrently with another `try_pop` or `pop`.
+    pub unsafe fn pop(&self) -> Option<Arc<T>> {
+        loop {
+            match self.try_pop() {
+                Pop::Data(node) => return Some(node),
+                Pop::Empty => return None,
+                Pop::Inconsistent => yield_now(),
+            }
+        }
+    }
+}
+
+impl<T: Linked> Default for MpscQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Linked> Drop for MpscQueue<T> {
+    fn drop(&mut self) {
+        // Safety: &mut self means no one else can be popping (or pushing).
+        while unsafe { self.pop() }.is_some() {}
+        drop(unsafe { Box::from_raw(self.stub) });
+    }
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+#[repr(C)]
+struct TestNode {
+    link: Link,
+    producer: usize,
+    seq: usize,
+}
+
+#[cfg(all(test, not(any(loom, shuttle))))]
+impl Linked for TestNode {
+    fn link(&self) -> &Link {
+        &self.link
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_fifo_per_producer() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let q = &MpscQueue::<TestNode>::new();
+    std::thread::scope(|s| {
+        for producer in 0..4 {
+            s.spawn(move || {
+                for seq in 0..n {
+                    let node = Arc::new(TestNode {
+                        link: Link::new(),
+                        producer,
+                        seq,
+                    });
+                    assert!(q.push(node).is_ok());
+                }
+            });
+        }
+        let mut next_seq = [0; 4];
+        let mut received = 0;
+        while received < 4 * n {
+            match unsafe { q.pop() } {
+                Some(node) => {
+                    assert_eq!(node.seq, next_seq[node.producer]);
+                    next_seq[node.producer] += 1;
+                    received += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+    });
+    assert!(unsafe { q.pop() }.is_none());
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_rejects_double_push() {
+    let q = MpscQueue::new();
+    let node = Arc::new(TestNode {
+        link: Link::new(),
+        producer: 0,
+        seq: 0,
+    });
+    assert!(q.push(Arc::clone(&node)).is_ok());
+    assert!(node.link.is_queued());
+    assert!(q.push(Arc::clone(&node)).is_err());
+    let popped = unsafe { q.pop() }.unwrap();
+    assert!(Arc::ptr_eq(&popped, &node));
+    assert!(!node.link.is_queued());
+    assert!(q.push(popped).is_ok());
+    drop(q);
+    assert_eq!(Arc::strong_count(&node), 1);
+}
@@ -0,0 +1,148 @@
+use crate::backoff::Backoff;
+use critical_section::CriticalSection;
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// A spinlock that's also a critical section, for data shared between thread context and
+// interrupt handlers on embedded targets. Interrupts are masked (or whatever the
+// `critical-section` implementation the binary links does) for as long as it's held, so a
+// handler can't preempt the holder and spin forever on a lock that can't be released; the
+// spinlock inside is for the other cores, which a critical section on one core doesn't stop.
+//
+// Locking takes the `CriticalSection` token the caller is already in, and the guard borrows
+// it, so a guard can't outlive its critical section, and critical sections stay nested as
+// `critical_section::with` requires. Spinning never yields: with interrupts masked there's no
+// scheduler to yield to.
+
+/// A spinlock whose guard holds a critical section. Built with the `critical-section` feature.
+pub struct IrqMutex<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqMutex<T> {}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Locks, spinning while another core holds it, for as long as the critical section `cs`.
+    pub fn lock<'cs>(&'cs self, cs: CriticalSection<'cs>) -> IrqMutexGuard<'cs, T> {
+        let backoff = Backoff::new();
+        while !self.try_acquire() {
+            while self.locked.load(Ordering::Relaxed) {
+                backoff.spin();
+            }
+        }
+        IrqMutexGuard { mutex: self, cs }
+    }
+
+    pub fn try_lock<'cs>(&'cs self, cs: CriticalSection<'cs>) -> Option<IrqMutexGuard<'cs, T>> {
+        if self.try_acquire() {
+            Some(IrqMutexGuard { mutex: self, cs })
+        } else {
+            None
+        }
+    }
+
+    /// Enters a critical section, locks, and runs `f` with the value and the critical section.
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T, CriticalSection<'_>) -> R) -> R {
+        critical_section::with(|cs| {
+            let mut guard = self.lock(cs);
+            f(&mut guard, cs)
+        })
+    }
+
+    fn try_acquire(&self) -> bool {
+        // Acquire: pairs with the Release of the unlock before.
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: Default> Default for IrqMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// An [`IrqMutex`] held, inside the critical section it was locked in.
+pub struct IrqMutexGuard<'cs, T> {
+    mutex: &'cs IrqMutex<T>,
+    cs: CriticalSection<'cs>,
+}
+
+impl<'cs, T> IrqMutexGuard<'cs, T> {
+    /// The critical section the lock is held in, for the other `critical_section` types it
+    /// also unlocks.
+    pub fn critical_section(&self) -> CriticalSection<'cs> {
+        self.cs
+    }
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release: what was done under the lock happens before the next holder's lock.
+        self.mutex.locked.store(false, Ordering::Release);
+    }
+}
+
+#[test]
+fn irq_mutex_holds_a_critical_section() {
+    use std::cell::Cell;
+
+    let n = if cfg!(miri) { 50 } else { 10_000 };
+    let m = IrqMutex::new(0u64);
+    let interrupts = critical_section::Mutex::new(Cell::new(0u64));
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    m.with_lock(|v, cs| {
+                        *v += 1;
+                        // The guard's critical section opens critical_section's own types.
+                        let c = interrupts.borrow(cs);
+                        c.set(c.get() + 1);
+                    });
+                }
+            });
+        }
+    });
+    assert_eq!(critical_section::with(|cs| *m.lock(cs)), 4 * n);
+
+    critical_section::with(|cs| {
+        let guard = m.lock(cs);
+        assert!(m.try_lock(cs).is_none());
+        assert_eq!(interrupts.borrow(guard.critical_section()).get(), 4 * n);
+        drop(guard);
+        assert!(m.try_lock(cs).is_some());
+    });
+}
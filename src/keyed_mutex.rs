@@ -0,0 +1,184 @@
+use crate::cache_padded::CachePadded;
+use crate::raw_mutex::RawMutex;
+use crate::rwlock::RwLock;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+// A lock per key, made when someone first locks the key and dropped when the last of the
+// threads using it, holding it or waiting for it, lets go, so the map only ever holds the
+// keys in use. Keys are spread over shards like LruCache's; a shard's lock is held only to
+// find or remove a key's mutex, never while waiting on one.
+//
+// A key's mutex is counted by its Arc: the map holds one, and every guard and waiter another,
+// and they're only cloned and dropped with the shard locked, so a count of one there means no
+// one else has it and it can go.
+
+// Each key's mutex, by key, for the keys in use.
+type Shard<K> = RwLock<HashMap<K, Arc<RawMutex>>>;
+
+/// Locks keys, not data: [`lock`](Self::lock) on a key waits only for other holders of the
+/// same key, for "one request in flight per user" or "one writer per file" without one lock
+/// for everyone.
+pub struct KeyedMutex<K, S = RandomState> {
+    shards: Box<[CachePadded<Shard<K>>]>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq + Clone> KeyedMutex<K> {
+    pub fn new() -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, |n| n.get()) * 4;
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone> Default for KeyedMutex<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, S: BuildHasher> KeyedMutex<K, S> {
+    /// Spreads the keys over `shards` shards, rounded up to a power of two.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        Self {
+            shards: (0..shards.max(1).next_power_of_two())
+                .map(|_| CachePadded::new(RwLock::new(HashMap::new())))
+                .collect(),
+            hasher,
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Shard<K>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash & (self.shards.len() - 1)]
+    }
+
+    // The key's mutex, made if no one's using the key.
+    fn mutex(&self, key: &K) -> Arc<RawMutex> {
+        let mut map = self.shard(key).write();
+        Arc::clone(map.entry(key.clone()).or_default())
+    }
+
+    /// Locks `key`, waiting while another thread holds it.
+    pub fn lock(&self, key: K) -> KeyedMutexGuard<'_, K, S> {
+        let mutex = self.mutex(&key);
+        mutex.lock();
+        KeyedMutexGuard::new(self, key, mutex)
+    }
+
+    /// Locks `key` if no other thread holds it.
+    pub fn try_lock(&self, key: K) -> Option<KeyedMutexGuard<'_, K, S>> {
+        let mutex = self.mutex(&key);
+        if mutex.try_lock() {
+            Some(KeyedMutexGuard::new(self, key, mutex))
+        } else {
+            self.done_with(&key, mutex);
+            None
+        }
+    }
+
+    /// Whether a thread holds `key` or is waiting for it.
+    pub fn is_locked<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).read().contains_key(key)
+    }
+
+    /// How many keys are held or waited for.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Drops our hold on the key's mutex, and the key with it if we were the last.
+    fn done_with(&self, key: &K, mutex: Arc<RawMutex>) {
+        let mut map = self.shard(key).write();
+        drop(mutex);
+        if map.get(key).is_some_and(|m| Arc::strong_count(m) == 1) {
+            map.remove(key);
+        }
+    }
+}
+
+/// A key of a [`KeyedMutex`] held, released on drop.
+#[must_use = "the key is unlocked as soon as this is dropped"]
+pub struct KeyedMutexGuard<'a, K: Hash + Eq + Clone, S: BuildHasher = RandomState> {
+    keyed: &'a KeyedMutex<K, S>,
+    // Both only taken in drop.
+    key: Option<K>,
+    mutex: Option<Arc<RawMutex>>,
+    // A RawMutex is unlocked on the thread that locked it.
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<K: Hash + Eq + Clone + Sync, S: BuildHasher + Sync> Sync for KeyedMutexGuard<'_, K, S> {}
+
+impl<'a, K: Hash + Eq + Clone, S: BuildHasher> KeyedMutexGuard<'a, K, S> {
+    fn new(keyed: &'a KeyedMutex<K, S>, key: K, mutex: Arc<RawMutex>) -> Self {
+        Self {
+            keyed,
+            key: Some(key),
+            mutex: Some(mutex),
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        self.key.as_ref().unwrap()
+    }
+}
+
+impl<K: Hash + Eq + Clone, S: BuildHasher> Drop for KeyedMutexGuard<'_, K, S> {
+    fn drop(&mut self) {
+        let (key, mutex) = (self.key.take().unwrap(), self.mutex.take().unwrap());
+        // Safety: this guard locked it, on this thread.
+        unsafe { mutex.unlock() };
+        self.keyed.done_with(&key, mutex);
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn keyed_mutex_serializes_each_key_alone() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let n = if cfg!(miri) { 20 } else { 2000 };
+    let keyed = KeyedMutex::new();
+    let in_flight: Vec<AtomicUsize> = (0..4).map(|_| AtomicUsize::new(0)).collect();
+    std::thread::scope(|s| {
+        for t in 0..8 {
+            let (keyed, in_flight) = (&keyed, &in_flight);
+            s.spawn(move || {
+                for i in 0..n {
+                    let user = (t + i) % 4;
+                    let _guard = keyed.lock(user);
+                    assert_eq!(in_flight[user].fetch_add(1, Ordering::Relaxed), 0);
+                    in_flight[user].fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    // Idle keys are gone.
+    assert!(keyed.is_empty());
+
+    let a = keyed.lock(1);
+    assert!(keyed.try_lock(1).is_none());
+    let b = keyed.try_lock(2).unwrap();
+    assert!(keyed.is_locked(&1) && keyed.is_locked(&2) && !keyed.is_locked(&3));
+    assert_eq!((a.key(), b.key(), keyed.len()), (&1, &2, 2));
+    drop((a, b));
+    assert!(keyed.is_empty());
+}
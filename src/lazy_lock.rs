@@ -0,0 +1,173 @@
+use crate::once_lock::OnceLock;
+use std::fmt;
+use std::ops::Deref;
+
+// std's LazyLock, on the crate's OnceLock, and `static_lazy!`, which declares statics of it
+// with lazy_static's syntax, so moving off lazy_static is a change of the macro's name.
+//
+// The initializer is a `Fn`, not an `FnOnce`: one that panics, or for TryLazyLock fails,
+// leaves the value unset, as OnceLock does, and the next access runs it again. In a static
+// it's a plain `fn` anyway. TryLazyLock is for the initializers that can fail, reading a
+// config or opening a device, where the error should reach the caller and a later caller
+// should get to try again, rather than the first error being kept for good.
+
+/// A value made on first access, by `init`, and shared from then on.
+pub struct LazyLock<T, F = fn() -> T> {
+    cell: OnceLock<T>,
+    init: F,
+}
+
+impl<T, F: Fn() -> T> LazyLock<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// The value, made now if this is the first access. Like `std::sync::LazyLock`, it's
+    /// called as `LazyLock::force(&lazy)`, so it doesn't shadow a method of `T`.
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(&this.init)
+    }
+
+    /// The value, if it's been made.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.cell.get()
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for LazyLock<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+impl<T: Default> Default for LazyLock<T> {
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("LazyLock").field(value).finish(),
+            None => f.write_str("LazyLock(<uninit>)"),
+        }
+    }
+}
+
+/// A [`LazyLock`] whose `init` can fail. A failure isn't kept: the error goes to the caller
+/// of [`force`](Self::force), and the next one runs `init` again.
+pub struct TryLazyLock<T, E, F = fn() -> Result<T, E>> {
+    cell: OnceLock<T>,
+    init: F,
+    _error: std::marker::PhantomData<fn() -> E>,
+}
+
+impl<T, E, F: Fn() -> Result<T, E>> TryLazyLock<T, E, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceLock::new(),
+            init,
+            _error: std::marker::PhantomData,
+        }
+    }
+
+    /// The value, made now if it hasn't been yet, or the error making it failed with.
+    pub fn force(this: &Self) -> Result<&T, E> {
+        this.cell.get_or_try_init(&this.init)
+    }
+
+    /// The value, if it's been made.
+    pub fn get(this: &Self) -> Option<&T> {
+        this.cell.get()
+    }
+}
+
+impl<T: fmt::Debug, E, F> fmt::Debug for TryLazyLock<T, E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("TryLazyLock").field(value).finish(),
+            None => f.write_str("TryLazyLock(<uninit>)"),
+        }
+    }
+}
+
+/// Declares statics made on first access, in `lazy_static!`'s syntax: each item's
+/// attributes, doc comments included, and visibility are kept, and it becomes a static
+/// [`LazyLock`].
+///
+/// An item declared as a `Result<T, E>` with its initializer marked `try` becomes a
+/// [`TryLazyLock<T, E>`] instead: the initializer returns a `Result`, and an `Err` isn't
+/// kept, so the next [`TryLazyLock::force`] runs it again: `static ref CONFIG:
+/// Result<Config, io::Error> = try Config::read(path);`.
+#[macro_export]
+macro_rules! static_lazy {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        $vis:vis static ref $name:ident : Result<$t:ty, $e:ty> = try $init:expr;
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        $vis static $name: $crate::lazy_lock::TryLazyLock<$t, $e> =
+            $crate::lazy_lock::TryLazyLock::new(|| $init);
+        $crate::static_lazy!($($rest)*);
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis static ref $name:ident : $t:ty = $init:expr;
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        $vis static $name: $crate::lazy_lock::LazyLock<$t> =
+            $crate::lazy_lock::LazyLock::new(|| $init);
+        $crate::static_lazy!($($rest)*);
+    };
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn static_lazy_makes_statics_on_first_access() {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static MADE: AtomicUsize = AtomicUsize::new(0);
+    static TRIES: AtomicUsize = AtomicUsize::new(0);
+
+    crate::static_lazy! {
+        /// Squares, by root.
+        static ref SQUARES: HashMap<u32, u32> = {
+            MADE.fetch_add(1, Ordering::Relaxed);
+            (0..10).map(|i| (i, i * i)).collect()
+        };
+        #[allow(non_upper_case_globals)]
+        static ref name: String = "atomics".to_string();
+        static ref FLAKY: Result<u32, String> = try {
+            match TRIES.fetch_add(1, Ordering::Relaxed) {
+                0 => Err("not yet".to_string()),
+                n => Ok(n as u32),
+            }
+        };
+    }
+
+    assert!(LazyLock::get(&SQUARES).is_none());
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| assert_eq!(SQUARES[&7], 49));
+        }
+    });
+    assert_eq!(SQUARES.len(), 10);
+    assert_eq!(MADE.load(Ordering::Relaxed), 1);
+    assert_eq!(name.as_str(), "atomics");
+
+    // The failure isn't kept; the second try's value is.
+    assert_eq!(TryLazyLock::force(&FLAKY), Err("not yet".to_string()));
+    assert_eq!(TryLazyLock::force(&FLAKY), Ok(&1));
+    assert_eq!(TryLazyLock::force(&FLAKY), Ok(&1));
+    assert_eq!(format!("{:?}", FLAKY), "TryLazyLock(1)");
+}
@@ -0,0 +1,106 @@
+// A wasm32 build with threads needs a nightly compiler for the atomics target feature, and
+// on it the futex backend's wait and notify are still behind a library feature.
+#![cfg_attr(
+    all(target_arch = "wasm32", target_feature = "atomics"),
+    feature(stdarch_wasm_atomic_wait)
+)]
+
+pub mod aba;
+pub mod affinity;
+pub mod array_queue;
+pub mod async_sync;
+pub mod atomic64;
+pub mod atomic_option;
+pub mod atomic_waker;
+pub mod backoff;
+pub mod bag;
+pub mod barrier;
+pub mod bitset;
+pub mod block_pool;
+pub mod blocking_queue;
+pub mod broadcast;
+pub mod cache_padded;
+pub mod close_gate;
+pub mod compat;
+pub mod cpu;
+#[cfg(feature = "deadlock-detect")]
+pub mod deadlock;
+pub mod deque;
+pub mod disruptor;
+pub mod epoch;
+pub mod event_count;
+pub mod evmap;
+#[cfg(feature = "executor")]
+pub mod executor;
+pub mod fence;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixed_pool;
+pub mod flat_combining;
+pub mod futex;
+pub mod hashmap;
+pub mod hb_trace;
+pub mod id_allocator;
+#[cfg(feature = "introspect")]
+pub mod introspect;
+pub mod intrusive_mpsc;
+#[cfg(feature = "critical-section")]
+pub mod irq_mutex;
+pub mod keyed_mutex;
+pub mod lazy_lock;
+pub mod linearizability;
+pub mod list_set;
+pub mod litmus;
+#[cfg(feature = "lock-order")]
+pub mod lock_order;
+pub mod locked_ptr;
+pub mod lru;
+pub mod meter;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod model;
+pub mod mpmc;
+pub mod mpsc;
+pub mod named_semaphore;
+pub mod once_lock;
+pub mod oneshot;
+pub mod parker;
+pub mod per_cpu;
+pub mod perf;
+pub mod pool;
+pub mod priority_channel;
+pub mod priority_queue;
+#[cfg(feature = "profile")]
+pub mod profile;
+#[cfg(feature = "race-detect")]
+pub mod race;
+pub mod rate_limiter;
+pub mod raw_mutex;
+pub mod rwlock;
+#[cfg(test)]
+pub mod sched;
+pub mod seg_queue;
+pub mod select;
+pub mod sequence;
+pub mod shm;
+pub mod skiplist;
+pub mod slab;
+pub mod split_ordered;
+pub mod spsc;
+pub mod stack;
+#[cfg(feature = "futures")]
+pub mod stream;
+#[cfg(feature = "strict-orderings")]
+pub mod strict_orderings;
+pub mod sync_shim;
+pub mod teaching;
+pub mod thread;
+pub mod thread_id;
+pub mod token;
+pub mod topology;
+pub mod triple_buffer;
+pub mod tsan;
+pub mod watch;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+pub mod work_stealing;
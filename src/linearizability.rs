@@ -0,0 +1,392 @@
+use crate::thread_id;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The sequential behaviour a concurrent object's history is checked against.
+pub trait Spec: Clone + Eq + Hash {
+    type Op: fmt::Debug;
+    type Ret: PartialEq + fmt::Debug;
+
+    fn apply(&mut self, op: &Self::Op) -> Self::Ret;
+}
+
+/// An operation on a [`Queue`] or [`Stack`]. A push returns `None`, a pop what it popped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PushPop<T> {
+    Push(T),
+    Pop,
+}
+
+/// A FIFO queue.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Queue<T>(pub VecDeque<T>);
+
+impl<T: Clone + Eq + Hash + fmt::Debug> Spec for Queue<T> {
+    type Op = PushPop<T>;
+    type Ret = Option<T>;
+
+    fn apply(&mut self, op: &PushPop<T>) -> Option<T> {
+        match op {
+            PushPop::Push(v) => {
+                self.0.push_back(v.clone());
+                None
+            }
+            PushPop::Pop => self.0.pop_front(),
+        }
+    }
+}
+
+/// A LIFO stack.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Stack<T>(pub Vec<T>);
+
+impl<T: Clone + Eq + Hash + fmt::Debug> Spec for Stack<T> {
+    type Op = PushPop<T>;
+    type Ret = Option<T>;
+
+    fn apply(&mut self, op: &PushPop<T>) -> Option<T> {
+        match op {
+            PushPop::Push(v) => {
+                self.0.push(v.clone());
+                None
+            }
+            PushPop::Pop => self.0.pop(),
+        }
+    }
+}
+
+/// An operation on a [`Map`], each returning the value that was under the key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapOp<K, V> {
+    Insert(K, V),
+    Get(K),
+    Remove(K),
+}
+
+/// A map.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Map<K, V>(pub BTreeMap<K, V>);
+
+impl<K, V> Spec for Map<K, V>
+where
+    K: Clone + Ord + Hash + fmt::Debug,
+    V: Clone + Eq + Hash + fmt::Debug,
+{
+    type Op = MapOp<K, V>;
+    type Ret = Option<V>;
+
+    fn apply(&mut self, op: &MapOp<K, V>) -> Option<V> {
+        match op {
+            MapOp::Insert(k, v) => self.0.insert(k.clone(), v.clone()),
+            MapOp::Get(k) => self.0.get(k).cloned(),
+            MapOp::Remove(k) => self.0.remove(k),
+        }
+    }
+}
+
+struct Entry<Op, Ret> {
+    thread: usize,
+    op: Op,
+    ret: Ret,
+    // When the call was made and when it returned, on the history's clock.
+    call: u64,
+    returned: u64,
+}
+
+/// The operations threads ran on an object, with when each was called and returned.
+///
+/// A history is linearizable if each operation can be taken to happen at some instant
+/// between its call and its return, such that the results match the [`Spec`] running them
+/// one at a time in that order. [`check`](Self::check) searches for such an order with the
+/// Wing-Gong algorithm, as improved by Lowe ("Testing for linearizability", 2017): try each
+/// operation that could go next, and back out when one's result doesn't match, skipping
+/// states already found to be dead ends.
+pub struct History<Op, Ret> {
+    clock: AtomicU64,
+    entries: Mutex<Vec<Entry<Op, Ret>>>,
+}
+
+impl<Op: fmt::Debug, Ret: PartialEq + fmt::Debug> History<Op, Ret> {
+    pub fn new() -> Self {
+        Self {
+            clock: AtomicU64::new(0),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Runs `f`, which does `op` on the object, and records it with what it returned.
+    pub fn record(&self, op: Op, f: impl FnOnce() -> Ret) -> Ret
+    where
+        Ret: Clone,
+    {
+        // SeqCst: the clock has to order calls and returns in the order they happened, across
+        // threads, which is what the single total order of SeqCst RMWs gives.
+        let call = self.clock.fetch_add(1, Ordering::SeqCst);
+        let ret = f();
+        let returned = self.clock.fetch_add(1, Ordering::SeqCst);
+        self.entries.lock().unwrap().push(Entry {
+            thread: thread_id::current().unwrap_or(0),
+            op,
+            ret: ret.clone(),
+            call,
+            returned,
+        });
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the history against `spec`, starting from its state as given, and returns
+    /// what went wrong if it isn't linearizable.
+    pub fn check<S: Spec<Op = Op, Ret = Ret>>(&self, spec: S) -> Result<(), Witness> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.sort_by_key(|e| e.call);
+        let n = entries.len();
+
+        // Calls and returns in time order, as a doubly linked list that operations are taken
+        // out of as they're linearized. Node 0 is the head, then a call node 1 + i and a
+        // return node 1 + n + i for each entry i.
+        let mut order: Vec<(u64, usize)> = Vec::with_capacity(2 * n);
+        for (i, e) in entries.iter().enumerate() {
+            order.push((e.call, 1 + i));
+            order.push((e.returned, 1 + n + i));
+        }
+        order.sort_unstable();
+        let nil = usize::MAX;
+        let mut next = vec![nil; 2 * n + 1];
+        let mut prev = vec![nil; 2 * n + 1];
+        let mut last = 0;
+        for &(_, node) in &order {
+            next[last] = node;
+            prev[node] = last;
+            last = node;
+        }
+        let unlink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, node: usize| {
+            let (p, q) = (prev[node], next[node]);
+            next[p] = q;
+            if q != nil {
+                prev[q] = p;
+            }
+        };
+        let relink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, node: usize| {
+            let (p, q) = (prev[node], next[node]);
+            next[p] = node;
+            if q != nil {
+                prev[q] = node;
+            }
+        };
+
+        let mut linearized = vec![0u64; n.div_ceil(64)];
+        let mut seen: HashSet<(Vec<u64>, S)> = HashSet::new();
+        let mut state = spec;
+        let mut stack: Vec<(usize, S)> = Vec::new();
+        let mut longest: Vec<usize> = Vec::new();
+        let mut node = next[0];
+        while next[0] != nil {
+            if node <= n {
+                let i = node - 1;
+                let mut after = state.clone();
+                let matches = after.apply(&entries[i].op) == entries[i].ret;
+                linearized[i / 64] |= 1 << (i % 64);
+                if matches && seen.insert((linearized.clone(), after.clone())) {
+                    stack.push((i, std::mem::replace(&mut state, after)));
+                    unlink(&mut next, &mut prev, 1 + i);
+                    unlink(&mut next, &mut prev, 1 + n + i);
+                    if stack.len() > longest.len() {
+                        longest = stack.iter().map(|&(i, _)| i).collect();
+                    }
+                    node = next[0];
+                } else {
+                    linearized[i / 64] &= !(1 << (i % 64));
+                    node = next[node];
+                }
+            } else {
+                // An operation returned without any order so far fitting it in; undo the
+                // last choice and try the one after it.
+                let Some((i, before)) = stack.pop() else {
+                    return Err(Witness::new(&entries, &longest));
+                };
+                state = before;
+                linearized[i / 64] &= !(1 << (i % 64));
+                relink(&mut next, &mut prev, 1 + n + i);
+                relink(&mut next, &mut prev, 1 + i);
+                node = next[1 + i];
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<Op: fmt::Debug, Ret: PartialEq + fmt::Debug> Default for History<Op, Ret> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why a history isn't linearizable: the history, and the longest order of its operations
+/// that the spec goes along with.
+#[derive(Debug, Clone)]
+pub struct Witness {
+    history: Vec<String>,
+    longest: Vec<usize>,
+    stuck: usize,
+}
+
+impl Witness {
+    fn new<Op: fmt::Debug, Ret: fmt::Debug>(entries: &[Entry<Op, Ret>], longest: &[usize]) -> Self {
+        // The first operation left to return can't go next: after the longest order, the
+        // spec gives it a different result.
+        let stuck = (0..entries.len())
+            .filter(|i| !longest.contains(i))
+            .min_by_key(|&i| entries[i].returned)
+            .unwrap_or(0);
+        Self {
+            history: entries
+                .iter()
+                .map(|e| {
+                    format!(
+                        "thread {:<3} [{:>6}, {:>6}]  {:?} -> {:?}",
+                        e.thread, e.call, e.returned, e.op, e.ret
+                    )
+                })
+                .collect(),
+            longest: longest.to_vec(),
+            stuck,
+        }
+    }
+}
+
+impl fmt::Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "history isn't linearizable:")?;
+        for (i, op) in self.history.iter().enumerate() {
+            writeln!(f, "  #{:<4} {}", i, op)?;
+        }
+        writeln!(
+            f,
+            "longest linearization, {} of {} operations:",
+            self.longest.len(),
+            self.history.len()
+        )?;
+        for &i in &self.longest {
+            writeln!(f, "  #{:<4} {}", i, self.history[i])?;
+        }
+        write!(
+            f,
+            "after which #{} can't go next:\n  #{:<4} {}",
+            self.stuck, self.stuck, self.history[self.stuck]
+        )
+    }
+}
+
+#[cfg(test)]
+fn entry<Op, Ret>(op: Op, ret: Ret, call: u64, returned: u64) -> Entry<Op, Ret> {
+    Entry {
+        thread: 0,
+        op,
+        ret,
+        call,
+        returned,
+    }
+}
+
+#[test]
+fn linearizability_rejects_a_stale_pop() {
+    // A pop that starts after a push has returned can't come back empty...
+    let h = History::new();
+    h.entries.lock().unwrap().extend([
+        entry(PushPop::Push(1), None, 0, 1),
+        entry(PushPop::Pop, None, 2, 3),
+    ]);
+    let witness = h.check(Queue::default()).unwrap_err();
+    assert_eq!(witness.longest, [0]);
+    assert_eq!(witness.stuck, 1);
+    assert!(witness.to_string().contains("Pop -> None"));
+
+    // ...but one that overlaps it can. Popping 1 then 2 after is a queue's order, not a
+    // stack's.
+    let h = History::new();
+    h.entries.lock().unwrap().extend([
+        entry(PushPop::Push(1), None, 0, 3),
+        entry(PushPop::Pop, None, 1, 2),
+        entry(PushPop::Push(2), None, 4, 5),
+        entry(PushPop::Pop, Some(1), 6, 7),
+        entry(PushPop::Pop, Some(2), 8, 9),
+    ]);
+    assert!(h.check(Queue::default()).is_ok());
+    assert!(h.check(Stack::default()).is_err());
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn linearizability_of_the_collections() {
+    use crate::hashmap::ConcurrentHashMap;
+    use crate::seg_queue::SegQueue;
+    use crate::stack::TreiberStack;
+
+    // Thread t's i-th op, picked by a hash of the two.
+    let pick = |t: u64, i: u64| (t * 31 + i).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 61;
+    let ops = if cfg!(miri) { 8 } else { 30 };
+    let run = |f: &(dyn Fn(u64, u64) + Sync)| {
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                s.spawn(move || (0..ops).for_each(|i| f(t, i)));
+            }
+        })
+    };
+
+    let queue = SegQueue::new();
+    let h = History::new();
+    run(&|t, i| {
+        if pick(t, i) < 4 {
+            let v = t * 100 + i;
+            h.record(PushPop::Push(v), || {
+                queue.push(v);
+                None
+            });
+        } else {
+            h.record(PushPop::Pop, || queue.pop());
+        }
+    });
+    h.check(Queue::default())
+        .unwrap_or_else(|w| panic!("{}", w));
+
+    let stack = TreiberStack::new();
+    let h = History::new();
+    run(&|t, i| {
+        if pick(t, i) < 4 {
+            let v = t * 100 + i;
+            h.record(PushPop::Push(v), || {
+                stack.push(v);
+                None
+            });
+        } else {
+            h.record(PushPop::Pop, || stack.pop());
+        }
+    });
+    h.check(Stack::default())
+        .unwrap_or_else(|w| panic!("{}", w));
+
+    let map = ConcurrentHashMap::new();
+    let h = History::new();
+    run(&|t, i| {
+        let key = pick(i, t) % 4;
+        match pick(t, i) % 3 {
+            0 => h.record(MapOp::Insert(key, t * 100 + i), || {
+                map.insert(key, t * 100 + i)
+            }),
+            1 => h.record(MapOp::Get(key), || map.get(&key, |&v| v)),
+            _ => h.record(MapOp::Remove(key), || map.remove(&key)),
+        };
+    });
+    h.check(Map::default()).unwrap_or_else(|w| panic!("{}", w));
+}
@@ -0,0 +1,218 @@
+use crate::epoch::{self, Guard};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+// A node is logically deleted once the low bit of its own `next` pointer is set. Marking next
+// (rather than keeping a separate flag) is what makes the algorithm work: an insert that
+// tries to link a new node after a deleted one fails its CAS.
+const MARK: usize = 1;
+
+fn is_marked<T>(p: *mut T) -> bool {
+    p.addr() & MARK != 0
+}
+
+fn marked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a | MARK)
+}
+
+fn unmarked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a & !MARK)
+}
+
+struct Node<T> {
+    key: T,
+    next: AtomicPtr<Node<T>>,
+}
+
+/// Harris's lock-free sorted linked list, used as a set.
+///
+/// `remove` marks a node before unlinking it, and unlinked nodes are retired through
+/// [`epoch`](crate::epoch) so concurrent traversals never touch freed memory.
+pub struct ListSet<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for ListSet<T> {}
+unsafe impl<T: Send + Sync> Sync for ListSet<T> {}
+
+impl<T> ListSet<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<T: Ord + Send + 'static> ListSet<T> {
+    // Returns the link that points at the first node >= key, that node (or null), and whether
+    // its key equals `key`. Marked nodes found on the way are unlinked and retired.
+    fn find<'g>(
+        &'g self,
+        key: &T,
+        guard: &'g Guard,
+    ) -> (&'g AtomicPtr<Node<T>>, *mut Node<T>, bool) {
+        'retry: loop {
+            let mut prev = &self.head;
+            let mut curr = prev.load(Ordering::Acquire);
+            loop {
+                if curr.is_null() {
+                    return (prev, curr, false);
+                }
+                let node = unsafe { &*curr };
+                let next = node.next.load(Ordering::Acquire);
+                if is_marked(next) {
+                    // If prev itself got marked meanwhile, its next now carries the mark bit
+                    // and this CAS fails, so we never unlink behind a deleted node.
+                    if prev
+                        .compare_exchange(curr, unmarked(next), Ordering::AcqRel, Ordering::Acquire)
+                        .is_err()
+                    {
+                        continue 'retry;
+                    }
+                    unsafe { guard.defer_destroy(curr) };
+                    curr = unmarked(next);
+                    continue;
+                }
+                if node.key >= *key {
+                    return (prev, curr, node.key == *key);
+                }
+                prev = &node.next;
+                curr = next;
+            }
+        }
+    }
+
+    /// Inserts `key`, returning false if it was already present.
+    pub fn insert(&self, key: T) -> bool {
+        let guard = epoch::pin();
+        let new = Box::into_raw(Box::new(Node {
+            key,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }));
+        loop {
+            let (prev, curr, found) = self.find(unsafe { &(*new).key }, &guard);
+            if found {
+                drop(unsafe { Box::from_raw(new) });
+                return false;
+            }
+            unsafe { (*new).next.store(curr, Ordering::Relaxed) };
+            // Release: publishes the new node's key and next pointer.
+            if prev
+                .compare_exchange(curr, new, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+
+    /// Removes `key`, returning false if it wasn't present.
+    pub fn remove(&self, key: &T) -> bool {
+        let guard = epoch::pin();
+        loop {
+            let (prev, curr, found) = self.find(key, &guard);
+            if !found {
+                return false;
+            }
+            let node = unsafe { &*curr };
+            let next = node.next.load(Ordering::Acquire);
+            if is_marked(next) {
+                continue;
+            }
+            // Logical deletion: the linearization point of remove.
+            if node
+                .next
+                .compare_exchange(next, marked(next), Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                continue;
+            }
+            // Physical deletion; if it fails someone else's find will do it.
+            if prev
+                .compare_exchange(curr, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                unsafe { guard.defer_destroy(curr) };
+            } else {
+                self.find(key, &guard);
+            }
+            return true;
+        }
+    }
+
+    /// Whether `key` is present. Never writes to the list.
+    pub fn contains(&self, key: &T) -> bool {
+        let _guard = epoch::pin();
+        let mut curr = self.head.load(Ordering::Acquire);
+        while !curr.is_null() {
+            let node = unsafe { &*curr };
+            let next = node.next.load(Ordering::Acquire);
+            if node.key >= *key {
+                return node.key == *key && !is_marked(next);
+            }
+            curr = unmarked(next);
+        }
+        false
+    }
+}
+
+impl<T> Default for ListSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for ListSet<T> {
+    fn drop(&mut self) {
+        // Nodes still linked in are ours alone; unlinked ones belong to the epoch collector.
+        let mut curr = *self.head.get_mut();
+        while !curr.is_null() {
+            let mut node = unsafe { Box::from_raw(curr) };
+            curr = unmarked(*node.next.get_mut());
+        }
+    }
+}
+
+#[test]
+fn list_set_basic() {
+    let set = ListSet::new();
+    assert!(set.insert(3));
+    assert!(set.insert(1));
+    assert!(set.insert(2));
+    assert!(!set.insert(2));
+    assert!(set.contains(&1) && set.contains(&2) && set.contains(&3));
+    assert!(set.remove(&2));
+    assert!(!set.remove(&2));
+    assert!(!set.contains(&2));
+    assert!(set.contains(&3));
+}
+
+#[test]
+fn list_set_concurrent() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Barrier;
+    let set = &ListSet::new();
+    let inserted = &AtomicUsize::new(0);
+    let barrier = &Barrier::new(4);
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                // Everyone races to insert every key, but each one goes in exactly once.
+                for k in 0..200 {
+                    if set.insert(k) {
+                        inserted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                barrier.wait();
+                // Thread t owns the keys that are t mod 4, and removes every third of those.
+                for k in (t..200).step_by(4).filter(|k| k % 3 == 0) {
+                    assert!(set.remove(&k));
+                }
+            });
+        }
+    });
+    assert_eq!(inserted.load(Ordering::Relaxed), 200);
+    for k in 0..200 {
+        assert_eq!(set.contains(&k), k % 3 != 0, "key {}", k);
+    }
+}
@@ -0,0 +1,245 @@
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Checks that locks are always taken in the same order, so two threads can't each hold one
+// the other is about to block on, even on runs where they happen not to meet.
+//
+// A lock with a level has to be taken after every lock of a lower level the thread holds and
+// before any of a higher one; two of the same level can't be held at once. Between locks
+// without one, the order is learned: every blocking acquisition made while holding other
+// locks adds an edge from each of them to the new one, and one that would close a cycle
+// panics with the stacks on both sides of it. Only blocking acquisitions count, since a try
+// that fails can't deadlock, though a lock taken by a try is held like any other.
+//
+// Every acquisition captures a backtrace, for the report, so this is slow. Edges are kept for
+// the life of the process, even for locks that are gone.
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+struct Held {
+    id: usize,
+    level: Option<u32>,
+    site: &'static Location<'static>,
+    backtrace: Backtrace,
+}
+
+thread_local! {
+    static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+}
+
+// A blocking acquisition of `to` made at `site` while holding `from`, taken at `from_site`.
+struct Edge {
+    from_site: &'static Location<'static>,
+    site: &'static Location<'static>,
+    backtrace: Backtrace,
+}
+
+static EDGES: Mutex<BTreeMap<usize, BTreeMap<usize, Edge>>> = Mutex::new(BTreeMap::new());
+
+fn edges() -> std::sync::MutexGuard<'static, BTreeMap<usize, BTreeMap<usize, Edge>>> {
+    EDGES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// A way along the learned edges from `from` to `to`, if there is one.
+fn path(
+    edges: &BTreeMap<usize, BTreeMap<usize, Edge>>,
+    from: usize,
+    to: usize,
+    seen: &mut Vec<usize>,
+) -> Option<Vec<(usize, usize)>> {
+    if seen.contains(&from) {
+        return None;
+    }
+    seen.push(from);
+    for &next in edges.get(&from)?.keys() {
+        if next == to {
+            return Some(vec![(from, to)]);
+        }
+        if let Some(mut rest) = path(edges, next, to, seen) {
+            rest.insert(0, (from, next));
+            return Some(rest);
+        }
+    }
+    None
+}
+
+/// Where a lock stands in the order: its level, if it was given one, and an ID for the
+/// learned edges. Locks hold one of these under the `lock-order` feature and tell it what
+/// they're doing.
+pub struct Key {
+    // 0 until the lock is first used.
+    id: AtomicUsize,
+    level: Option<u32>,
+}
+
+impl Key {
+    pub const fn new(level: Option<u32>) -> Self {
+        Self {
+            id: AtomicUsize::new(0),
+            level,
+        }
+    }
+
+    fn id(&self) -> usize {
+        let id = self.id.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        match self
+            .id
+            .compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => new,
+            Err(id) => id,
+        }
+    }
+
+    /// Says the current thread is about to block on the lock at `site`. Panics if that takes
+    /// it out of order with a lock the thread holds.
+    pub fn blocking(&self, site: &'static Location<'static>) {
+        let id = self.id();
+        // Panics once the thread-local is no longer borrowed, since unwinding releases the
+        // thread's locks.
+        let report = HELD.with(|held| {
+            for h in held.borrow().iter().filter(|h| h.id != id) {
+                if let (Some(mine), Some(theirs)) = (self.level, h.level) {
+                    if mine <= theirs {
+                        return Some(format!(
+                            "lock order: a level {} lock taken at {} while holding a level {} \
+                             lock, taken at {}\n\nhere:\n{}\n\nthe level {} lock taken:\n{}",
+                            mine,
+                            site,
+                            theirs,
+                            h.site,
+                            Backtrace::force_capture(),
+                            theirs,
+                            h.backtrace
+                        ));
+                    }
+                    continue;
+                }
+                let mut edges = edges();
+                if let Some(path) = path(&edges, id, h.id, &mut Vec::new()) {
+                    let mut report = format!(
+                        "lock order: lock {} taken at {} while holding lock {}, taken at {}, \
+                         but the other way round before\n\nhere:\n{}",
+                        id,
+                        site,
+                        h.id,
+                        h.site,
+                        Backtrace::force_capture()
+                    );
+                    for (from, to) in path {
+                        let edge = &edges[&from][&to];
+                        let _ = write!(
+                            report,
+                            "\n\nlock {} taken at {} while holding lock {}, taken at {}:\n{}",
+                            to, edge.site, from, edge.from_site, edge.backtrace
+                        );
+                    }
+                    return Some(report);
+                }
+                edges
+                    .entry(h.id)
+                    .or_default()
+                    .entry(id)
+                    .or_insert_with(|| Edge {
+                        from_site: h.site,
+                        site,
+                        backtrace: Backtrace::force_capture(),
+                    });
+            }
+            None
+        });
+        if let Some(report) = report {
+            panic!("{}", report);
+        }
+    }
+
+    /// Says the current thread took the lock at `site`.
+    pub fn acquired(&self, site: &'static Location<'static>) {
+        let held = Held {
+            id: self.id(),
+            level: self.level,
+            site,
+            backtrace: Backtrace::force_capture(),
+        };
+        HELD.with(|h| h.borrow_mut().push(held));
+    }
+
+    /// Says a hold on the lock has ended. If the current thread isn't the one holding it,
+    /// because the guard was sent, nothing changes.
+    pub fn released(&self) {
+        let id = self.id();
+        // Gone already if the thread's thread-locals are being torn down.
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|h| h.id == id) {
+                held.remove(i);
+            }
+        });
+    }
+}
+
+#[test]
+fn lock_order_allows_a_consistent_order() {
+    use crate::rwlock::RwLock;
+
+    let (a, b) = (RwLock::new(0), RwLock::new(0));
+    let (low, high) = (RwLock::with_level(0, 1), RwLock::with_level(0, 2));
+    for _ in 0..2 {
+        let _a = a.write();
+        let _b = b.read();
+        let _low = low.write();
+        let _high = high.write();
+    }
+    // Out of order, but without blocking.
+    let _b = b.write();
+    assert!(a.try_write().is_some());
+}
+
+// A guard handed to another thread isn't held by the thread that took it: b then a, after
+// handing a off, doesn't make an order with a before b.
+#[test]
+fn lock_order_forgets_handed_off_guards() {
+    use crate::rwlock::RwLock;
+    use std::sync::Arc;
+
+    let (a, b) = (Arc::new(RwLock::new(0)), RwLock::new(0));
+    let guard = a.write_arc();
+    std::thread::spawn(move || drop(guard)).join().unwrap();
+    let _b = b.write();
+    let _a = a.write();
+}
+
+#[test]
+#[should_panic(expected = "but the other way round before")]
+fn lock_order_catches_an_inversion_that_never_deadlocked() {
+    use crate::rwlock::RwLock;
+
+    let (a, b) = (RwLock::new(0), RwLock::new(0));
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let _a = a.write();
+            let _b = b.write();
+        });
+    });
+    let _b = b.write();
+    let _a = a.write();
+}
+
+#[test]
+#[should_panic(expected = "a level 1 lock taken at src/lock_order.rs")]
+fn lock_order_checks_levels() {
+    use crate::rwlock::RwLock;
+
+    let (low, high) = (RwLock::with_level(0, 1), RwLock::with_level(0, 2));
+    let _high = high.read();
+    let _low = low.read();
+}
@@ -0,0 +1,272 @@
+use crate::backoff::Backoff;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+// A spinlock that lives in the pointer it guards. A `T` aligned to 2 or more leaves the low
+// bits of a pointer to it always zero, so bit 0 can be the lock and the bits above it, up to
+// the alignment, a small tag, such as a node's color or a "deleted" mark. Intrusive lists and
+// trees get a lock per link this way, for the price of the link.
+//
+// Waiting spins with `Backoff` and yields; there's no word of 32 bits to sleep on, so it's
+// for locks held for a few instructions, like the ones on a node's links.
+//
+// The bits are set and cleared through `map_addr`, so the pointer's provenance survives them.
+
+const LOCKED: usize = 1;
+
+/// A `*mut T`, a lock, and a tag, in one [`AtomicPtr`].
+///
+/// The lock guards the pointer and the tag, and whatever else the structure says it guards,
+/// the pointee most of all: [`lock_and_deref`](Self::lock_and_deref) locks and hands out the
+/// pointee for as long as the lock's held.
+pub struct LockedPtr<T> {
+    ptr: AtomicPtr<T>,
+}
+
+impl<T> LockedPtr<T> {
+    /// How many tag bits a `T`'s alignment leaves above the lock bit.
+    pub const TAG_BITS: u32 = mem::align_of::<T>().trailing_zeros() - 1;
+    const TAG_MASK: usize = (mem::align_of::<T>() - 1) & !LOCKED;
+
+    /// Unlocked, null and untagged.
+    ///
+    /// # Panics
+    ///
+    /// If `T` is aligned to 1, leaving no bit for the lock; at compile time, in a const.
+    pub const fn null() -> Self {
+        assert!(mem::align_of::<T>() >= 2, "no spare bit in the pointer");
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Unlocked, holding `ptr` untagged.
+    ///
+    /// # Panics
+    ///
+    /// If `T` is aligned to 1, or `ptr` isn't aligned for `T`.
+    pub fn new(ptr: *mut T) -> Self {
+        assert!(
+            ptr.addr() & (mem::align_of::<T>() - 1) == 0,
+            "pointer not aligned for T"
+        );
+        let this = Self::null();
+        this.ptr.store(ptr, Ordering::Relaxed);
+        this
+    }
+
+    /// The pointer, without the lock and the tag. Unless the lock's held, it may be out of
+    /// date by the time it's returned.
+    pub fn load(&self, order: Ordering) -> *mut T {
+        self.ptr
+            .load(order)
+            .map_addr(|a| a & !(Self::TAG_MASK | LOCKED))
+    }
+
+    pub fn tag(&self) -> usize {
+        (self.ptr.load(Ordering::Relaxed).addr() & Self::TAG_MASK) >> 1
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.ptr.load(Ordering::Relaxed).addr() & LOCKED != 0
+    }
+
+    /// Locks, spinning while someone else holds it.
+    pub fn lock(&self) -> LockedPtrGuard<'_, T> {
+        let backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            while self.is_locked() {
+                backoff.snooze();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<LockedPtrGuard<'_, T>> {
+        let current = self.ptr.load(Ordering::Relaxed);
+        if current.addr() & LOCKED != 0 {
+            return None;
+        }
+        // Acquire: pairs with the Release of the unlock before.
+        self.ptr
+            .compare_exchange(
+                current,
+                current.map_addr(|a| a | LOCKED),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+        Some(LockedPtrGuard {
+            lock: self,
+            value: current,
+        })
+    }
+
+    /// Locks, and if the pointer isn't null, hands out the pointee until the guard's dropped;
+    /// if it is, unlocks again and returns `None`. `T` has to be `Send`, since the pointee
+    /// goes to whichever thread locks it, and a `LockedPtr` is shared by them all.
+    ///
+    /// # Safety
+    ///
+    /// For as long as the lock's held, a non-null pointer points to a live `T` that nothing
+    /// but the lock's holder reads or writes.
+    pub unsafe fn lock_and_deref(&self) -> Option<LockedDeref<'_, T>>
+    where
+        T: Send,
+    {
+        let guard = self.lock();
+        if guard.ptr().is_null() {
+            return None;
+        }
+        Some(LockedDeref {
+            guard,
+            _pointee: PhantomData,
+        })
+    }
+
+    /// The pointer, for the owner, who needs no lock.
+    pub fn into_inner(self) -> *mut T {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Default for LockedPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+/// A [`LockedPtr`] locked, released on drop.
+///
+/// Changes to the pointer and the tag made through it are stored with the unlock, so other
+/// threads see them as one.
+#[must_use = "the pointer is unlocked as soon as this is dropped"]
+pub struct LockedPtrGuard<'a, T> {
+    lock: &'a LockedPtr<T>,
+    // The pointer and tag to unlock with, the lock bit clear.
+    value: *mut T,
+}
+
+impl<T> LockedPtrGuard<'_, T> {
+    pub fn ptr(&self) -> *mut T {
+        self.value.map_addr(|a| a & !LockedPtr::<T>::TAG_MASK)
+    }
+
+    /// Points at `ptr` from the unlock on, keeping the tag.
+    ///
+    /// # Panics
+    ///
+    /// If `ptr` isn't aligned for `T`.
+    pub fn set_ptr(&mut self, ptr: *mut T) {
+        let mask = LockedPtr::<T>::TAG_MASK;
+        assert!(
+            ptr.addr() & (mem::align_of::<T>() - 1) == 0,
+            "pointer not aligned for T"
+        );
+        let tag = self.value.addr() & mask;
+        self.value = ptr.map_addr(|a| a | tag);
+    }
+
+    pub fn tag(&self) -> usize {
+        (self.value.addr() & LockedPtr::<T>::TAG_MASK) >> 1
+    }
+
+    /// Tags it with `tag` from the unlock on.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in [`LockedPtr::TAG_BITS`].
+    pub fn set_tag(&mut self, tag: usize) {
+        let mask = LockedPtr::<T>::TAG_MASK;
+        assert!((tag << 1) & !mask == 0, "tag too wide for T's alignment");
+        self.value = self.value.map_addr(|a| (a & !mask) | (tag << 1));
+    }
+}
+
+impl<T> Drop for LockedPtrGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release: what was done under the lock happens before the next holder's lock.
+        self.lock.ptr.store(self.value, Ordering::Release);
+    }
+}
+
+/// The pointee of a [`LockedPtr`], borrowed for as long as its lock's held. Made by
+/// [`LockedPtr::lock_and_deref`].
+pub struct LockedDeref<'a, T> {
+    guard: LockedPtrGuard<'a, T>,
+    _pointee: PhantomData<&'a mut T>,
+}
+
+impl<T> LockedDeref<'_, T> {
+    pub fn tag(&self) -> usize {
+        self.guard.tag()
+    }
+
+    /// See [`LockedPtrGuard::set_tag`].
+    pub fn set_tag(&mut self, tag: usize) {
+        self.guard.set_tag(tag);
+    }
+}
+
+impl<T> Deref for LockedDeref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: non-null, and live and ours while locked, as lock_and_deref's caller promised.
+        unsafe { &*self.guard.ptr() }
+    }
+}
+
+impl<T> DerefMut for LockedDeref<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.guard.ptr() }
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn locked_ptr_packs_lock_and_tag() {
+    // Aligned to 8: a lock bit and two tag bits.
+    struct Node {
+        hits: u64,
+    }
+    assert_eq!(LockedPtr::<Node>::TAG_BITS, 2);
+
+    let node = Box::into_raw(Box::new(Node { hits: 0 }));
+    let link = LockedPtr::new(node);
+    {
+        let mut guard = link.lock();
+        assert!(link.is_locked() && link.try_lock().is_none());
+        guard.set_tag(3);
+        assert_eq!((guard.ptr(), guard.tag()), (node, 3));
+    }
+    assert!(!link.is_locked());
+    assert_eq!((link.load(Ordering::Acquire), link.tag()), (node, 3));
+
+    let n = if cfg!(miri) { 50 } else { 5000 };
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    // Safety: the node lives until after the scope, and is only touched
+                    // under the lock.
+                    unsafe { link.lock_and_deref() }.unwrap().hits += 1;
+                }
+            });
+        }
+    });
+    // Safety: as above.
+    let found = unsafe { link.lock_and_deref() }.unwrap();
+    assert_eq!((found.hits, found.tag()), (4 * n, 3));
+    drop(found);
+
+    link.lock().set_ptr(ptr::null_mut());
+    assert!(unsafe { link.lock_and_deref() }.is_none());
+    assert_eq!((link.tag(), link.into_inner()), (3, ptr::null_mut()));
+    drop(unsafe { Box::from_raw(node) });
+}
@@ -0,0 +1,317 @@
+use crate::cache_padded::CachePadded;
+use crate::rwlock::RwLock;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    // Set by every hit, under the read lock; the clock hand clears it and evicts entries it
+    // finds already clear.
+    referenced: AtomicBool,
+    expires: Option<Instant>,
+}
+
+impl<K, V> Slot<K, V> {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|e| now >= e)
+    }
+}
+
+struct Clock<K, V> {
+    index: HashMap<K, usize>,
+    slots: Vec<Slot<K, V>>,
+    hand: usize,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq, V> Clock<K, V> {
+    // Picks the slot to reuse, giving every referenced entry a second chance. Expired entries
+    // don't get one.
+    fn victim(&mut self, now: Instant) -> usize {
+        loop {
+            let hand = self.hand;
+            self.hand = (hand + 1) % self.slots.len();
+            let slot = &self.slots[hand];
+            if slot.is_expired(now) || !slot.referenced.swap(false, Ordering::Relaxed) {
+                return hand;
+            }
+        }
+    }
+
+    fn remove_slot(&mut self, i: usize) -> Slot<K, V> {
+        let slot = self.slots.swap_remove(i);
+        if let Some(moved) = self.slots.get(i) {
+            *self.index.get_mut(&moved.key).unwrap() = i;
+        }
+        if self.hand >= self.slots.len() {
+            self.hand = 0;
+        }
+        slot
+    }
+}
+
+// Kept in a CachePadded so neighbouring shards' lock words and counters don't false-share.
+struct Shard<K, V> {
+    clock: RwLock<Clock<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// A fixed-capacity concurrent cache with approximate LRU eviction.
+///
+/// Entries are spread over independently locked shards, each evicting with the CLOCK
+/// algorithm: a hit only sets a flag under the shard's read lock, so lookups never contend
+/// with each other, and a full shard evicts the first entry that hasn't been hit since the
+/// clock hand last passed it.
+///
+/// Entries inserted with a time-to-live stop being returned once it runs out. They're
+/// removed lazily: evicted when the clock hand reaches them, whether or not they were hit,
+/// though an unhit live entry the hand reaches first goes before them; or by
+/// [`purge_expired`](Self::purge_expired), which takes one shard at a time so it can run on a
+/// background thread without stalling the whole cache.
+pub struct LruCache<K, V, S = RandomState> {
+    shards: Box<[CachePadded<Shard<K, V>>]>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, |n| n.get()) * 4;
+        Self::with_shards(capacity, shards)
+    }
+
+    pub fn with_shards(capacity: usize, shards: usize) -> Self {
+        Self::with_shards_and_hasher(capacity, shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, S: BuildHasher> LruCache<K, V, S> {
+    /// Holds at most `capacity` entries in total, split over `shards` shards (rounded to a
+    /// power of two, and never more than `capacity`).
+    pub fn with_shards_and_hasher(capacity: usize, shards: usize, hasher: S) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        let mut n = shards.max(1).next_power_of_two();
+        while n > capacity {
+            n /= 2;
+        }
+        Self {
+            shards: (0..n)
+                .map(|i| Shard {
+                    clock: RwLock::new(Clock {
+                        index: HashMap::new(),
+                        slots: Vec::new(),
+                        hand: 0,
+                        // Hand out the remainder one each, so the shards sum to `capacity`.
+                        capacity: capacity / n + usize::from(i < capacity % n),
+                    }),
+                    hits: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                })
+                .map(CachePadded::new)
+                .collect(),
+            hasher,
+        }
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Shard<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash & (self.shards.len() - 1)]
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let shard = self.shard(key);
+        let clock = shard.clock.read();
+        match clock.index.get(key) {
+            Some(&i) if !clock.slots[i].is_expired(Instant::now()) => {
+                let slot = &clock.slots[i];
+                slot.referenced.store(true, Ordering::Relaxed);
+                shard.hits.fetch_add(1, Ordering::Relaxed);
+                Some(slot.value.clone())
+            }
+            _ => {
+                shard.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Inserts or replaces the value for `key`. If that needed room in a full shard, returns
+    /// the entry that was evicted for it.
+    pub fn insert(&self, key: K, value: V) -> Option<(K, V)> {
+        self.insert_expiring(key, value, None)
+    }
+
+    /// Like [`insert`](Self::insert), but the entry expires after `ttl`.
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<(K, V)> {
+        self.insert_expiring(key, value, Some(Instant::now() + ttl))
+    }
+
+    fn insert_expiring(&self, key: K, value: V, expires: Option<Instant>) -> Option<(K, V)> {
+        let mut clock = self.shard(&key).clock.write();
+        if let Some(&i) = clock.index.get(&key) {
+            let slot = &mut clock.slots[i];
+            slot.value = value;
+            slot.expires = expires;
+            *slot.referenced.get_mut() = true;
+            return None;
+        }
+        let slot = Slot {
+            key: key.clone(),
+            value,
+            // New entries start cold, so a burst of one-off keys can't flush the hot ones.
+            referenced: AtomicBool::new(false),
+            expires,
+        };
+        if clock.slots.len() < clock.capacity {
+            let i = clock.slots.len();
+            clock.slots.push(slot);
+            clock.index.insert(key, i);
+            return None;
+        }
+        let i = clock.victim(Instant::now());
+        let old = std::mem::replace(&mut clock.slots[i], slot);
+        clock.index.remove(&old.key);
+        clock.index.insert(key, i);
+        Some((old.key, old.value))
+    }
+
+    /// Removes `key` from the cache.
+    pub fn evict<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut clock = self.shard(key).clock.write();
+        let i = clock.index.remove(key)?;
+        Some(clock.remove_slot(i).value)
+    }
+
+    /// Removes every expired entry, locking one shard at a time, and returns how many there
+    /// were.
+    pub fn purge_expired(&self) -> usize {
+        let mut purged = 0;
+        for shard in self.shards.iter() {
+            let mut clock = shard.clock.write();
+            let now = Instant::now();
+            // Backwards, so the entries remove_slot swaps down have already been checked.
+            for i in (0..clock.slots.len()).rev() {
+                if clock.slots[i].is_expired(now) {
+                    let slot = clock.remove_slot(i);
+                    clock.index.remove(&slot.key);
+                    purged += 1;
+                }
+            }
+        }
+        purged
+    }
+
+    /// The number of entries, including expired ones not yet removed. Shards are counted one
+    /// at a time, so under concurrent modification this is only a snapshot.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.clock.read().slots.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.clock.read().slots.is_empty())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|s| s.clock.read().capacity).sum()
+    }
+
+    /// How many `get`s found their key.
+    pub fn hits(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|s| s.hits.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// How many `get`s didn't.
+    pub fn misses(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|s| s.misses.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+#[test]
+fn lru_second_chance() {
+    let cache = LruCache::with_shards(3, 1);
+    for k in 0..3 {
+        assert_eq!(cache.insert(k, k * 10), None);
+    }
+    assert_eq!(cache.get(&0), Some(0));
+    assert_eq!(cache.get(&2), Some(20));
+    // 1 is the only entry that hasn't been hit since it went in.
+    assert_eq!(cache.insert(3, 30), Some((1, 10)));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.evict(&0), Some(0));
+    assert_eq!(cache.evict(&0), None);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&3), Some(30));
+    assert_eq!((cache.hits(), cache.misses()), (3, 1));
+}
+
+#[test]
+fn lru_stays_within_capacity() {
+    let n = if cfg!(miri) { 200 } else { 2000 };
+    let cache = &LruCache::with_shards(100, 8);
+    assert_eq!(cache.capacity(), 100);
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    let k = (i * 7919 + t) % 500;
+                    if cache.get(&k).is_none() {
+                        cache.insert(k, k);
+                    }
+                    if i % 13 == 0 {
+                        cache.evict(&((k + 1) % 500));
+                    }
+                    assert!(cache.len() <= 100);
+                }
+            });
+        }
+    });
+    assert!(cache.len() <= 100);
+    assert_eq!(cache.hits() + cache.misses(), 4 * n);
+    for k in 0..500 {
+        if let Some(v) = cache.get(&k) {
+            assert_eq!(v, k);
+        }
+    }
+}
+
+#[test]
+fn lru_ttl_expiry() {
+    let cache = LruCache::with_shards(3, 1);
+    cache.insert_with_ttl(0, 0, Duration::ZERO);
+    cache.insert_with_ttl(1, 10, Duration::from_secs(3600));
+    cache.insert(2, 20);
+    assert_eq!(cache.get(&0), None);
+    assert_eq!(cache.get(&1), Some(10));
+    // 2 is cold too, but the expired entry goes first.
+    assert_eq!(cache.insert(3, 30), Some((0, 0)));
+    cache.insert_with_ttl(2, 21, Duration::ZERO);
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.purge_expired(), 1);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&3), Some(30));
+}
@@ -0,0 +1,203 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Throughput as the 1, 5 and 15 minute load averages of Unix, and Dropwizard's Meter, have
+// it: every TICK the marks since the last tick become that tick's rate, and each average
+// moves towards it by its alpha, so a mark counts for less the older it gets, by e every
+// 1, 5 or 15 minutes.
+//
+// `mark` is a Relaxed fetch_add on the marks not yet ticked and nothing else, cheap enough
+// for every request on a hot path. Ticks are claimed with a CAS on the time of the last one,
+// counted in nanoseconds since the meter was made, by whoever finds one due: a read of a
+// rate, or `tick` called from a timer for a meter that's rarely read. A tick that finds
+// several due catches up on all of them at once, the marks going to the first and the rest
+// only decaying, so marks made while nothing ticked look as if they were all made in the
+// last interval.
+//
+// The averages are f64s kept as their bits in AtomicU64s. Only the claimer of a tick writes
+// them, so the only race is a tick that takes over an interval to apply against the next
+// one, and a CAS loop covers that.
+
+const TICK: Duration = Duration::from_secs(5);
+
+// Not a rate; the averages before the first tick, which sets them to its rate outright
+// rather than having them climb from zero for a quarter of an hour.
+const UNSET: u64 = u64::MAX;
+
+/// Marks' rate per second, averaged over the last 1, 5 and 15 minutes with exponentially
+/// decaying weights.
+pub struct EwmaMeter {
+    uncounted: AtomicU64,
+    counted: AtomicU64,
+    // Nanoseconds since `start`, at the last tick.
+    last_tick: AtomicU64,
+    // The 1, 5 and 15 minute averages.
+    rates: [AtomicU64; 3],
+    start: Instant,
+}
+
+impl EwmaMeter {
+    pub fn new() -> Self {
+        Self {
+            uncounted: AtomicU64::new(0),
+            counted: AtomicU64::new(0),
+            last_tick: AtomicU64::new(0),
+            rates: std::array::from_fn(|_| AtomicU64::new(UNSET)),
+            start: Instant::now(),
+        }
+    }
+
+    /// Counts `n` events.
+    pub fn mark(&self, n: u64) {
+        self.uncounted.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Every event marked so far.
+    pub fn count(&self) -> u64 {
+        // Counted first: a tick moves marks from uncounted to it, so they're seen once or,
+        // if a tick comes in between, not at all, but never twice.
+        let counted = self.counted.load(Ordering::Relaxed);
+        counted + self.uncounted.load(Ordering::Relaxed)
+    }
+
+    /// Events per second over the meter's whole life.
+    pub fn mean_rate(&self) -> f64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.count() as f64 / elapsed
+    }
+
+    pub fn one_minute_rate(&self) -> f64 {
+        self.rates()[0]
+    }
+
+    pub fn five_minute_rate(&self) -> f64 {
+        self.rates()[1]
+    }
+
+    pub fn fifteen_minute_rate(&self) -> f64 {
+        self.rates()[2]
+    }
+
+    /// The 1, 5 and 15 minute rates, from the same tick.
+    pub fn rates(&self) -> [f64; 3] {
+        self.tick();
+        self.rates
+            .each_ref()
+            .map(|r| match r.load(Ordering::Relaxed) {
+                UNSET => 0.0,
+                bits => f64::from_bits(bits),
+            })
+    }
+
+    /// Applies the ticks that are due. Reads of the rates do this themselves; a meter that's
+    /// marked for a long while between reads can be ticked from a timer, every few seconds,
+    /// to keep its marks in the intervals they were made in.
+    pub fn tick(&self) {
+        self.tick_at(self.start.elapsed());
+    }
+
+    fn tick_at(&self, now: Duration) {
+        let now = u64::try_from(now.as_nanos()).unwrap_or(u64::MAX);
+        let interval = TICK.as_nanos() as u64;
+        let last = self.last_tick.load(Ordering::Relaxed);
+        let due = now.saturating_sub(last) / interval;
+        if due == 0 {
+            return;
+        }
+        if self
+            .last_tick
+            .compare_exchange(
+                last,
+                last + due * interval,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            // Someone else claimed them.
+            return;
+        }
+        let marks = self.uncounted.swap(0, Ordering::Relaxed);
+        self.counted.fetch_add(marks, Ordering::Relaxed);
+        let instant = marks as f64 / TICK.as_secs_f64();
+        for (rate, minutes) in self.rates.iter().zip([1.0, 5.0, 15.0]) {
+            let alpha = 1.0 - (-TICK.as_secs_f64() / 60.0 / minutes).exp();
+            // The idle ticks after the first decay it towards zero.
+            let idle = (1.0 - alpha).powf((due - 1) as f64);
+            let _ = rate.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let next = match bits {
+                    UNSET => instant,
+                    bits => {
+                        let old = f64::from_bits(bits);
+                        old + alpha * (instant - old)
+                    }
+                };
+                Some((next * idle).to_bits())
+            });
+        }
+    }
+}
+
+impl Default for EwmaMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for EwmaMeter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [m1, m5, m15] = self.rates();
+        f.debug_struct("EwmaMeter")
+            .field("count", &self.count())
+            .field("m1_rate", &m1)
+            .field("m5_rate", &m5)
+            .field("m15_rate", &m15)
+            .finish()
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn ewma_meter_decays_its_rates() {
+    let meter = EwmaMeter::new();
+    let n = if cfg!(miri) { 100 } else { 10_000 };
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| (0..n).for_each(|_| meter.mark(1)));
+        }
+    });
+    assert_eq!(meter.count(), 4 * n);
+    // Nothing's ticked yet.
+    assert_eq!(
+        meter.rates.each_ref().map(|r| r.load(Ordering::Relaxed)),
+        [UNSET; 3]
+    );
+
+    // A steady 100 a second holds every average at 100, the first tick setting them.
+    let rates = |meter: &EwmaMeter| {
+        meter
+            .rates
+            .each_ref()
+            .map(|r| f64::from_bits(r.load(Ordering::Relaxed)))
+    };
+    let meter = EwmaMeter::new();
+    for tick in 1..=12 {
+        meter.mark(500);
+        meter.tick_at(TICK * tick);
+    }
+    assert!(rates(&meter).iter().all(|r| (r - 100.0).abs() < 1e-9));
+
+    // Then nothing for a minute, caught up in one go: the one minute average falls to 1/e
+    // of it, and the longer ones by less.
+    meter.tick_at(TICK * 24 + Duration::from_millis(1));
+    meter.tick_at(TICK * 24 + Duration::from_millis(2));
+    let [m1, m5, m15] = rates(&meter);
+    assert!((m1 - 100.0 / std::f64::consts::E).abs() < 1e-9);
+    assert!(m1 < m5 && m5 < m15 && m15 < 100.0);
+    assert_eq!(meter.count(), 6000);
+}
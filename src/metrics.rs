@@ -0,0 +1,560 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Counters, gauges and histograms for the sync primitives, gathered in Prometheus's text
+// exposition format. Under the `metrics` feature a named `rwlock::RwLock` publishes to the
+// global registry, and so does an `mpmc` channel once its sender is `publish`ed:
+//
+//     atomics_rwlock_acquisitions_total{lock,mode}   counter
+//     atomics_rwlock_contended_total{lock,mode}      counter, acquisitions that had to wait
+//     atomics_rwlock_contention_ratio{lock}          gauge, contended over acquisitions
+//     atomics_rwlock_wait_seconds{lock}              histogram of the contended waits
+//     atomics_channel_sent_total{channel}            counter
+//     atomics_channel_received_total{channel}        counter
+//     atomics_channel_depth{channel}                 gauge, messages queued
+//
+// Percentiles come from the histograms, with `histogram_quantile` on the Prometheus side or
+// `Histogram::percentile` here. A lock's metrics outlive it, as counters should; a channel's
+// depth goes once the channel does.
+//
+// Under the `serde` feature a counter serializes as its current count and a histogram as its
+// buckets and sum, to checkpoint them or ship them as JSON; deserializing makes new ones that
+// carry on from there. A histogram's buckets are read one at a time while others may be
+// observing, so its snapshot is only as consistent as a gather's.
+
+/// A count that only goes up.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Bucket i holds durations below 2^(i + FIRST_BUCKET) ns: the first is 1µs, the last about
+// 8.6s, and beyond it is +Inf.
+const FIRST_BUCKET: u32 = 10;
+const BUCKETS: usize = 24;
+
+/// Durations counted in power-of-two buckets from 1µs up, so a percentile is known to within
+/// a factor of two.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn bound(i: usize) -> Option<u64> {
+        (i < BUCKETS).then(|| 1 << (i as u32 + FIRST_BUCKET))
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let nanos = d.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let i = (0..BUCKETS)
+            .find(|&i| nanos < Self::bound(i).unwrap())
+            .unwrap_or(BUCKETS);
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+    }
+
+    /// The upper bound of the bucket the `p`th quantile, 0 to 1, falls in, or None if nothing
+    /// has been observed or it's beyond the last bucket.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &n) in counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Self::bound(i).map(Duration::from_nanos);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Counter {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.get())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Counter {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        u64::deserialize(d).map(|n| Self(AtomicU64::new(n)))
+    }
+}
+
+// A histogram as it's serialized: the count in each bucket, the last being +Inf, and the sum.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HistogramSnapshot {
+    buckets: Vec<u64>,
+    sum_nanos: u64,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Histogram {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+            sum_nanos: self.sum_nanos.load(Ordering::Relaxed),
+        }
+        .serialize(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Histogram {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let snapshot = HistogramSnapshot::deserialize(d)?;
+        if snapshot.buckets.len() != BUCKETS + 1 {
+            return Err(serde::de::Error::invalid_length(
+                snapshot.buckets.len(),
+                &"one count for each bucket",
+            ));
+        }
+        let h = Self::default();
+        for (b, n) in h.buckets.iter().zip(snapshot.buckets) {
+            b.store(n, Ordering::Relaxed);
+        }
+        h.sum_nanos.store(snapshot.sum_nanos, Ordering::Relaxed);
+        Ok(h)
+    }
+}
+
+enum Value {
+    Counter(Arc<Counter>),
+    // None once whatever it measures is gone, which drops the gauge.
+    Gauge(Box<dyn Fn() -> Option<f64> + Send + Sync>),
+    Histogram(Arc<Histogram>),
+}
+
+struct Metric {
+    name: String,
+    help: &'static str,
+    labels: String,
+    value: Value,
+}
+
+/// A set of metrics to [`gather`](Self::gather) together. Metrics with the same name are one
+/// family, told apart by their labels, and should all be of the same kind.
+pub struct Registry {
+    metrics: Mutex<Vec<Metric>>,
+}
+
+static GLOBAL: Registry = Registry::new();
+
+/// The registry the crate's primitives publish to.
+pub fn global() -> &'static Registry {
+    &GLOBAL
+}
+
+/// [`global`]'s metrics, for a scrape.
+pub fn gather() -> String {
+    GLOBAL.gather()
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    for (i, (k, v)) in labels.iter().enumerate() {
+        let v = v
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = write!(out, "{}{}=\"{}\"", if i == 0 { "" } else { "," }, k, v);
+    }
+    out
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            metrics: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn add(&self, name: &str, help: &'static str, l: &[(&str, &str)], value: Value) {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Metric {
+                name: name.to_string(),
+                help,
+                labels: labels(l),
+                value,
+            });
+    }
+
+    pub fn counter(&self, name: &str, help: &'static str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        let c = Arc::new(Counter::default());
+        self.add(name, help, labels, Value::Counter(Arc::clone(&c)));
+        c
+    }
+
+    /// A gauge read at every gather; it's dropped the first time `f` returns None.
+    pub fn gauge(
+        &self,
+        name: &str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        f: impl Fn() -> Option<f64> + Send + Sync + 'static,
+    ) {
+        self.add(name, help, labels, Value::Gauge(Box::new(f)));
+    }
+
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Histogram> {
+        let h = Arc::new(Histogram::default());
+        self.add(name, help, labels, Value::Histogram(Arc::clone(&h)));
+        h
+    }
+
+    /// Every metric in the text exposition format, families in the order they first came.
+    pub fn gather(&self) -> String {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        let mut samples = Vec::new();
+        metrics.retain(|m| {
+            let mut out = String::new();
+            let braces = |extra: &str| match (m.labels.is_empty(), extra.is_empty()) {
+                (true, true) => String::new(),
+                (true, false) => format!("{{{}}}", extra),
+                (false, true) => format!("{{{}}}", m.labels),
+                (false, false) => format!("{{{},{}}}", m.labels, extra),
+            };
+            match &m.value {
+                Value::Counter(c) => {
+                    let _ = writeln!(out, "{}{} {}", m.name, braces(""), c.get());
+                }
+                Value::Gauge(f) => match f() {
+                    Some(v) => {
+                        let _ = writeln!(out, "{}{} {}", m.name, braces(""), v);
+                    }
+                    None => return false,
+                },
+                Value::Histogram(h) => {
+                    let mut cumulative = 0;
+                    for (i, b) in h.buckets.iter().enumerate() {
+                        cumulative += b.load(Ordering::Relaxed);
+                        let le = match Histogram::bound(i) {
+                            Some(nanos) => format!("{:e}", nanos as f64 / 1e9),
+                            None => "+Inf".to_string(),
+                        };
+                        let le = format!("le=\"{}\"", le);
+                        let _ = writeln!(out, "{}_bucket{} {}", m.name, braces(&le), cumulative);
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{}_sum{} {}",
+                        m.name,
+                        braces(""),
+                        h.sum().as_secs_f64()
+                    );
+                    let _ = writeln!(out, "{}_count{} {}", m.name, braces(""), cumulative);
+                }
+            }
+            samples.push(out);
+            true
+        });
+        let mut out = String::new();
+        let mut done = vec![false; metrics.len()];
+        for i in 0..metrics.len() {
+            if done[i] {
+                continue;
+            }
+            let m = &metrics[i];
+            let kind = match m.value {
+                Value::Counter(_) => "counter",
+                Value::Gauge(_) => "gauge",
+                Value::Histogram(_) => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", m.name, m.help);
+            let _ = writeln!(out, "# TYPE {} {}", m.name, kind);
+            for j in i..metrics.len() {
+                if metrics[j].name == m.name {
+                    done[j] = true;
+                    out += &samples[j];
+                }
+            }
+        }
+        out
+    }
+}
+
+/// What a named lock publishes.
+pub struct LockMetrics {
+    read: Arc<Counter>,
+    write: Arc<Counter>,
+    read_contended: Arc<Counter>,
+    write_contended: Arc<Counter>,
+    wait: Arc<Histogram>,
+}
+
+impl LockMetrics {
+    pub fn new(registry: &Registry, lock: &str) -> Self {
+        let acquisitions = |mode| {
+            registry.counter(
+                "atomics_rwlock_acquisitions_total",
+                "Acquisitions of the lock.",
+                &[("lock", lock), ("mode", mode)],
+            )
+        };
+        let contended = |mode| {
+            registry.counter(
+                "atomics_rwlock_contended_total",
+                "Acquisitions of the lock that had to wait.",
+                &[("lock", lock), ("mode", mode)],
+            )
+        };
+        let m = Self {
+            read: acquisitions("read"),
+            write: acquisitions("write"),
+            read_contended: contended("read"),
+            write_contended: contended("write"),
+            wait: registry.histogram(
+                "atomics_rwlock_wait_seconds",
+                "How long contended acquisitions of the lock waited.",
+                &[("lock", lock)],
+            ),
+        };
+        let [read, write, read_contended, write_contended] =
+            [&m.read, &m.write, &m.read_contended, &m.write_contended].map(Arc::clone);
+        registry.gauge(
+            "atomics_rwlock_contention_ratio",
+            "The share of acquisitions of the lock that had to wait.",
+            &[("lock", lock)],
+            move || {
+                let all = read.get() + write.get();
+                let contended = read_contended.get() + write_contended.get();
+                Some(if all == 0 {
+                    0.0
+                } else {
+                    contended as f64 / all as f64
+                })
+            },
+        );
+        m
+    }
+
+    /// Counts an acquisition; `write` is which mode.
+    pub fn acquired(&self, write: bool) {
+        if write { &self.write } else { &self.read }.inc();
+    }
+
+    /// Counts an acquisition as contended, after it waited for `d`.
+    pub fn waited(&self, write: bool, d: Duration) {
+        if write {
+            &self.write_contended
+        } else {
+            &self.read_contended
+        }
+        .inc();
+        self.wait.observe(d);
+    }
+}
+
+/// What a published channel publishes, but for its depth, which the channel reads out itself.
+pub struct ChannelMetrics {
+    sent: Arc<Counter>,
+    received: Arc<Counter>,
+}
+
+impl ChannelMetrics {
+    /// `depth` is how many messages are queued, or None once the channel is gone.
+    pub fn new(
+        registry: &Registry,
+        channel: &str,
+        depth: impl Fn() -> Option<f64> + Send + Sync + 'static,
+    ) -> Self {
+        let labels = [("channel", channel)];
+        registry.gauge(
+            "atomics_channel_depth",
+            "Messages queued in the channel.",
+            &labels,
+            depth,
+        );
+        Self {
+            sent: registry.counter(
+                "atomics_channel_sent_total",
+                "Messages sent on the channel.",
+                &labels,
+            ),
+            received: registry.counter(
+                "atomics_channel_received_total",
+                "Messages received from the channel.",
+                &labels,
+            ),
+        }
+    }
+
+    pub fn sent(&self) {
+        self.sent.inc();
+    }
+
+    pub fn received(&self) {
+        self.received.inc();
+    }
+}
+
+#[test]
+fn metrics_gather_the_text_format() {
+    let registry = Registry::new();
+    let c = registry.counter("c_total", "A counter.", &[("name", "a \"b\"\n")]);
+    c.add(3);
+    let gone = Arc::new(());
+    let weak = Arc::downgrade(&gone);
+    registry.gauge("g", "A gauge.", &[], move || weak.upgrade().map(|_| 1.5));
+    let h = registry.histogram("h_seconds", "A histogram.", &[("x", "1")]);
+    h.observe(Duration::from_nanos(500));
+    h.observe(Duration::from_micros(3));
+    h.observe(Duration::from_secs(60));
+    registry
+        .counter("c_total", "A counter.", &[("name", "d")])
+        .inc();
+
+    let text = registry.gather();
+    assert!(text.starts_with(
+        "# HELP c_total A counter.\n\
+         # TYPE c_total counter\n\
+         c_total{name=\"a \\\"b\\\"\\n\"} 3\n\
+         c_total{name=\"d\"} 1\n\
+         # HELP g A gauge.\n\
+         # TYPE g gauge\n\
+         g 1.5\n\
+         # HELP h_seconds A histogram.\n\
+         # TYPE h_seconds histogram\n\
+         h_seconds_bucket{x=\"1\",le=\"1.024e-6\"} 1\n\
+         h_seconds_bucket{x=\"1\",le=\"2.048e-6\"} 1\n\
+         h_seconds_bucket{x=\"1\",le=\"4.096e-6\"} 2\n"
+    ));
+    assert!(text.ends_with(
+        "h_seconds_bucket{x=\"1\",le=\"+Inf\"} 3\n\
+         h_seconds_sum{x=\"1\"} 60.0000035\n\
+         h_seconds_count{x=\"1\"} 3\n"
+    ));
+    assert_eq!(h.percentile(0.5), Some(Duration::from_nanos(4096)));
+    assert_eq!(h.percentile(0.99), None);
+
+    drop(gone);
+    assert!(!registry.gather().contains("# TYPE g"));
+}
+
+#[test]
+fn metrics_publish_named_locks_and_channels() {
+    use crate::{mpmc, rwlock::RwLock};
+
+    let lock = RwLock::new(0).named("metrics-test-lock");
+    let held = lock.write();
+    let started = std::sync::Barrier::new(2);
+    std::thread::scope(|s| {
+        let reader = s.spawn(|| {
+            started.wait();
+            *lock.read()
+        });
+        // Long enough for the reader to be waiting by the time the lock's free.
+        started.wait();
+        std::thread::sleep(Duration::from_millis(20));
+        drop(held);
+        reader.join().unwrap();
+    });
+    *lock.write() += 1;
+    let text = gather();
+    for line in [
+        "atomics_rwlock_acquisitions_total{lock=\"metrics-test-lock\",mode=\"read\"} 1\n",
+        "atomics_rwlock_acquisitions_total{lock=\"metrics-test-lock\",mode=\"write\"} 2\n",
+        "atomics_rwlock_contended_total{lock=\"metrics-test-lock\",mode=\"read\"} 1\n",
+        "atomics_rwlock_contention_ratio{lock=\"metrics-test-lock\"} 0.3333333333333333\n",
+        "atomics_rwlock_wait_seconds_count{lock=\"metrics-test-lock\"} 1\n",
+    ] {
+        assert!(text.contains(line), "{} not in\n{}", line, text);
+    }
+
+    let (tx, rx) = mpmc::bounded(4);
+    tx.publish("metrics-test-channel");
+    tx.send(1).unwrap();
+    tx.try_send(2).unwrap();
+    rx.recv().unwrap();
+    let text = gather();
+    for line in [
+        "atomics_channel_depth{channel=\"metrics-test-channel\"} 1\n",
+        "atomics_channel_sent_total{channel=\"metrics-test-channel\"} 2\n",
+        "atomics_channel_received_total{channel=\"metrics-test-channel\"} 1\n",
+    ] {
+        assert!(text.contains(line), "{} not in\n{}", line, text);
+    }
+    drop((tx, rx));
+    assert!(!gather().contains("atomics_channel_depth{channel=\"metrics-test-channel\"}"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn metrics_serialize_their_current_values() {
+    let c = Counter::default();
+    c.add(7);
+    assert_eq!(serde_json::to_string(&c).unwrap(), "7");
+    let c: Counter = serde_json::from_str("8").unwrap();
+    assert_eq!(c.get(), 8);
+
+    let h = Histogram::default();
+    h.observe(Duration::from_nanos(500));
+    h.observe(Duration::from_micros(3));
+    let json = serde_json::to_string(&h).unwrap();
+    assert!(json.starts_with("{\"buckets\":[1,0,1,0,"), "{}", json);
+    assert!(json.ends_with("],\"sum_nanos\":3500}"), "{}", json);
+    let back: Histogram = serde_json::from_str(&json).unwrap();
+    assert_eq!((back.count(), back.sum()), (2, Duration::from_nanos(3500)));
+    assert_eq!(back.percentile(1.0), h.percentile(1.0));
+    assert!(serde_json::from_str::<Histogram>("{\"buckets\":[1],\"sum_nanos\":0}").is_err());
+}
@@ -0,0 +1,1397 @@
+use crate::array_queue::ArrayQueue;
+#[cfg(not(loom))]
+use crate::array_queue::StaticMpmcQueue;
+use crate::close_gate::CloseGate;
+use crate::event_count::EventCount;
+use crate::select::{SelectRecv, SelectSend};
+use crate::sync_shim::atomic::{AtomicUsize, Ordering};
+use crate::sync_shim::{Condvar, Mutex, MutexGuard};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+// Lives once per channel behind the Arc, so the padded ArrayQueue's size doesn't matter.
+#[allow(clippy::large_enum_variant)]
+enum Flavor<T> {
+    Array(ArrayQueue<T>),
+    Zero(Rendezvous<T>),
+}
+
+struct Shared<T> {
+    flavor: Flavor<T>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // Closed by close on either end; nothing more can be sent, but what's queued can be
+    // received. The array flavor's sends go through it.
+    closed: CloseGate,
+    not_empty: EventCount,
+    not_full: EventCount,
+    // Set once the channel is published under the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    metrics: std::sync::OnceLock<crate::metrics::ChannelMetrics>,
+    // Run once the channel is published under the `introspect` feature.
+    #[cfg(feature = "introspect")]
+    registered: std::sync::Once,
+}
+
+/// The sending half of a [`bounded`] channel; clone it for more producers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a [`bounded`] channel; clone it for more consumers.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity (or, for a zero-capacity channel, no receiver is waiting).
+    Full(T),
+    /// The channel was closed or every receiver is gone.
+    Disconnected(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(value) | Self::Disconnected(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("sending on a full channel"),
+            Self::Disconnected(_) => f.write_str("sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    /// There was still no room at the deadline.
+    Timeout(T),
+    /// The channel was closed or every receiver is gone.
+    Disconnected(T),
+}
+
+impl<T> SendTimeoutError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Timeout(value) | Self::Disconnected(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(_) => f.write_str("Timeout(..)"),
+            Self::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(_) => f.write_str("timed out sending on a full channel"),
+            Self::Disconnected(_) => f.write_str("sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> std::error::Error for SendTimeoutError<T> {}
+
+/// A multi-producer multi-consumer channel holding at most `capacity` messages, on an
+/// [`ArrayQueue`] with an [`EventCount`] for each direction to sleep on.
+///
+/// `send` waits while the channel is full and `recv` while it's empty, which is the
+/// backpressure an unbounded [`mpsc`](crate::mpsc) channel doesn't give.
+///
+/// With a capacity of zero the channel is a rendezvous: `send` waits until a receiver has
+/// taken the value, and `try_send` only succeeds while a receiver is already waiting.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let flavor = if capacity == 0 {
+        Flavor::Zero(Rendezvous::new())
+    } else {
+        Flavor::Array(ArrayQueue::new(capacity))
+    };
+    let shared = Arc::new(Shared {
+        flavor,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        closed: CloseGate::new(),
+        not_empty: EventCount::new(),
+        not_full: EventCount::new(),
+        #[cfg(feature = "metrics")]
+        metrics: std::sync::OnceLock::new(),
+        #[cfg(feature = "introspect")]
+        registered: std::sync::Once::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared })
+}
+
+// Which end `Ends::traced` was called for.
+#[derive(Clone, Copy)]
+enum Op {
+    Send,
+    Recv,
+}
+
+// What a channel on a queue, Shared with the array flavor or StaticChannel, brings to the
+// loops of `Queued`: when its ends are gone, and what it does at each send, receive and wait.
+trait Ends {
+    // Whether nothing more can arrive: the senders are gone, or the channel's closed and the
+    // sends it let in have landed. One may have landed since the caller last looked, so
+    // callers check the queue again after this.
+    fn senders_gone(&self) -> bool;
+
+    // Whether nothing more may be sent.
+    fn receivers_gone(&self) -> bool;
+
+    fn traced(&self, _op: Op) {}
+
+    fn waits(&self, _what: &'static str) {}
+}
+
+// The queues a channel can be on.
+trait Slots<T> {
+    fn push(&self, value: T) -> Result<(), T>;
+    fn force_push(&self, value: T) -> Option<T>;
+    fn pop(&self) -> Option<T>;
+}
+
+impl<T> Slots<T> for ArrayQueue<T> {
+    fn push(&self, value: T) -> Result<(), T> {
+        ArrayQueue::push(self, value)
+    }
+
+    fn force_push(&self, value: T) -> Option<T> {
+        ArrayQueue::force_push(self, value)
+    }
+
+    fn pop(&self) -> Option<T> {
+        ArrayQueue::pop(self)
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> Slots<T> for StaticMpmcQueue<T, N> {
+    fn push(&self, value: T) -> Result<(), T> {
+        StaticMpmcQueue::push(self, value)
+    }
+
+    fn force_push(&self, value: T) -> Option<T> {
+        StaticMpmcQueue::force_push(self, value)
+    }
+
+    fn pop(&self) -> Option<T> {
+        StaticMpmcQueue::pop(self)
+    }
+}
+
+// A channel's queue, the gate its close shuts, and the event counts its receivers and
+// senders wait on, with the sends and receives on them, waiting or not, that Shared's array
+// flavor and StaticChannel share.
+struct Queued<'a, Q, E> {
+    queue: &'a Q,
+    gate: &'a CloseGate,
+    not_empty: &'a EventCount,
+    not_full: &'a EventCount,
+    ends: &'a E,
+}
+
+impl<Q, E: Ends> Queued<'_, Q, E> {
+    fn try_send<T>(&self, value: T) -> Result<(), TrySendError<T>>
+    where
+        Q: Slots<T>,
+    {
+        if self.ends.receivers_gone() {
+            return Err(TrySendError::Disconnected(value));
+        }
+        self.gate
+            .push(value, self.not_empty, |value| self.queue.push(value))
+            .map_err(TrySendError::Disconnected)?
+            .map_err(TrySendError::Full)?;
+        self.not_empty.notify_one();
+        self.ends.traced(Op::Send);
+        Ok(())
+    }
+
+    fn force_send<T>(&self, value: T) -> Result<Option<T>, SendError<T>>
+    where
+        Q: Slots<T>,
+    {
+        if self.ends.receivers_gone() {
+            return Err(SendError(value));
+        }
+        let evicted = self
+            .gate
+            .push(value, self.not_empty, |value| self.queue.force_push(value))
+            .map_err(SendError)?;
+        self.not_empty.notify_one();
+        self.ends.traced(Op::Send);
+        Ok(evicted)
+    }
+
+    fn try_recv<T>(&self) -> Result<T, TryRecvError>
+    where
+        Q: Slots<T>,
+    {
+        let pop = || {
+            let value = self.queue.pop()?;
+            self.not_full.notify_one();
+            self.ends.traced(Op::Recv);
+            Some(value)
+        };
+        if let Some(value) = pop() {
+            return Ok(value);
+        }
+        if !self.ends.senders_gone() {
+            return Err(TryRecvError::Empty);
+        }
+        // The last sender may have sent just before it dropped, or a send let in before the
+        // close landed.
+        pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    // No deadline means waiting forever.
+    fn send_until<T>(
+        &self,
+        mut value: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>>
+    where
+        Q: Slots<T>,
+    {
+        loop {
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(v)) => {
+                    return Err(SendTimeoutError::Disconnected(v))
+                }
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(SendTimeoutError::Timeout(value));
+            }
+            let key = self.not_full.prepare_wait();
+            match self.try_send(value) {
+                Ok(()) => {
+                    self.not_full.cancel_wait(key);
+                    return Ok(());
+                }
+                Err(TrySendError::Disconnected(v)) => {
+                    self.not_full.cancel_wait(key);
+                    return Err(SendTimeoutError::Disconnected(v));
+                }
+                Err(TrySendError::Full(v)) => {
+                    value = v;
+                    self.ends.waits("mpmc send waiting for room");
+                    match deadline {
+                        None => self.not_full.wait(key),
+                        Some(deadline) => {
+                            self.not_full.wait_deadline(key, deadline);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until<T>(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError>
+    where
+        Q: Slots<T>,
+    {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let key = self.not_empty.prepare_wait();
+            match self.try_recv() {
+                Ok(value) => {
+                    self.not_empty.cancel_wait(key);
+                    return Ok(value);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.not_empty.cancel_wait(key);
+                    return Err(RecvTimeoutError::Disconnected);
+                }
+                Err(TryRecvError::Empty) => {
+                    self.ends.waits("mpmc recv waiting for a message");
+                    match deadline {
+                        None => self.not_empty.wait(key),
+                        Some(deadline) => {
+                            self.not_empty.wait_deadline(key, deadline);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The zero-capacity flavor.
+///
+/// Both sides of a handoff wait for each other, so there's no lock-free fast path worth
+/// having; it's a single slot under a mutex, with a count of takes so a sender can tell its
+/// own value was the one taken.
+struct Rendezvous<T> {
+    handoff: Mutex<Handoff<T>>,
+    condvar: Condvar,
+}
+
+struct Handoff<T> {
+    value: Option<T>,
+    receivers_waiting: usize,
+    taken: u64,
+}
+
+impl<T> Rendezvous<T> {
+    fn new() -> Self {
+        Self {
+            handoff: Mutex::new(Handoff {
+                value: None,
+                receivers_waiting: 0,
+                taken: 0,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Handoff<T>> {
+        self.handoff.lock().unwrap()
+    }
+
+    // Waits for a change, or until `deadline` has passed, in which case it returns None.
+    fn wait<'a>(
+        &self,
+        handoff: MutexGuard<'a, Handoff<T>>,
+        deadline: Option<Instant>,
+    ) -> Option<MutexGuard<'a, Handoff<T>>> {
+        match deadline {
+            None => Some(self.condvar.wait(handoff).unwrap()),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                Some(
+                    self.condvar
+                        .wait_timeout(handoff, deadline - now)
+                        .unwrap()
+                        .0,
+                )
+            }
+        }
+    }
+
+    fn take(&self, handoff: &mut Handoff<T>) -> Option<T> {
+        let value = handoff.value.take()?;
+        handoff.taken += 1;
+        self.condvar.notify_all();
+        Some(value)
+    }
+
+    fn wake_all(&self) {
+        drop(self.lock());
+        self.condvar.notify_all();
+    }
+}
+
+impl<T> Shared<T> {
+    fn is_closed(&self) -> bool {
+        self.closed.is_closed()
+    }
+
+    fn close(&self) {
+        self.closed.close();
+        self.disconnect(&self.not_empty);
+        self.not_full.notify_all();
+    }
+
+    // The array flavor's queue, with what the loops on it need.
+    fn queued<'a>(&'a self, queue: &'a ArrayQueue<T>) -> Queued<'a, ArrayQueue<T>, Self> {
+        Queued {
+            queue,
+            gate: &self.closed,
+            not_empty: &self.not_empty,
+            not_full: &self.not_full,
+            ends: self,
+        }
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let zero = match &self.flavor {
+            Flavor::Array(queue) => return self.queued(queue).try_send(value),
+            Flavor::Zero(zero) => zero,
+        };
+        if self.receivers_gone() {
+            return Err(TrySendError::Disconnected(value));
+        }
+        let mut handoff = zero.lock();
+        if handoff.value.is_some() || handoff.receivers_waiting == 0 {
+            return Err(TrySendError::Full(value));
+        }
+        handoff.value = Some(value);
+        zero.condvar.notify_all();
+        self.traced(Op::Send);
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let zero = match &self.flavor {
+            Flavor::Array(queue) => return self.queued(queue).try_recv(),
+            Flavor::Zero(zero) => zero,
+        };
+        let pop = || {
+            let value = zero.take(&mut zero.lock())?;
+            // The slot is free for a select to send into, if receivers are still waiting.
+            self.not_full.notify_all();
+            self.traced(Op::Recv);
+            Some(value)
+        };
+        if let Some(value) = pop() {
+            return Ok(value);
+        }
+        if !self.senders_gone() {
+            return Err(TryRecvError::Empty);
+        }
+        // The last sender may have sent just before it dropped.
+        pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    // No deadline means waiting forever.
+    fn send_until(&self, value: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let zero = match &self.flavor {
+            Flavor::Array(queue) => return self.queued(queue).send_until(value, deadline),
+            Flavor::Zero(zero) => zero,
+        };
+        let mut handoff = zero.lock();
+        // Wait for the slot, which another sender's value may be sitting in.
+        while handoff.value.is_some() {
+            if self.receivers_gone() {
+                return Err(SendTimeoutError::Disconnected(value));
+            }
+            handoff = match zero.wait(handoff, deadline) {
+                Some(handoff) => handoff,
+                None => return Err(SendTimeoutError::Timeout(value)),
+            };
+        }
+        if self.receivers_gone() {
+            return Err(SendTimeoutError::Disconnected(value));
+        }
+        handoff.value = Some(value);
+        self.traced(Op::Send);
+        let ours = handoff.taken + 1;
+        zero.condvar.notify_all();
+        // For selects, which wait on the event counts rather than the condvar.
+        self.not_empty.notify_all();
+        while handoff.taken < ours {
+            let take_back = |handoff: &mut Handoff<T>| {
+                handoff
+                    .value
+                    .take()
+                    .expect("our value is still in the slot")
+            };
+            if self.receivers_gone() {
+                return Err(SendTimeoutError::Disconnected(take_back(&mut handoff)));
+            }
+            handoff = match zero.wait(handoff, deadline) {
+                Some(handoff) => handoff,
+                None => {
+                    // This lock was given up in the wait, so a receiver may have taken the
+                    // value after all.
+                    let mut handoff = zero.lock();
+                    if handoff.taken >= ours {
+                        return Ok(());
+                    }
+                    return Err(SendTimeoutError::Timeout(take_back(&mut handoff)));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let zero = match &self.flavor {
+            Flavor::Array(queue) => return self.queued(queue).recv_until(deadline),
+            Flavor::Zero(zero) => zero,
+        };
+        let mut handoff = zero.lock();
+        handoff.receivers_waiting += 1;
+        // A select can now send to us.
+        self.not_full.notify_all();
+        let result = loop {
+            if let Some(value) = zero.take(&mut handoff) {
+                self.not_full.notify_all();
+                self.traced(Op::Recv);
+                break Ok(value);
+            }
+            if self.senders_gone() {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            handoff = match zero.wait(handoff, deadline) {
+                Some(handoff) => handoff,
+                None => {
+                    let mut handoff = zero.lock();
+                    handoff.receivers_waiting -= 1;
+                    let value = zero.take(&mut handoff).ok_or(RecvTimeoutError::Timeout)?;
+                    self.traced(Op::Recv);
+                    return Ok(value);
+                }
+            };
+        };
+        handoff.receivers_waiting -= 1;
+        result
+    }
+
+    fn capacity(&self) -> usize {
+        match &self.flavor {
+            Flavor::Array(queue) => queue.capacity(),
+            Flavor::Zero(_) => 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match &self.flavor {
+            Flavor::Array(queue) => queue.len(),
+            Flavor::Zero(_) => 0,
+        }
+    }
+
+    #[cfg(any(feature = "introspect", feature = "metrics"))]
+    fn publish(self: &Arc<Self>, name: &str)
+    where
+        T: Send + 'static,
+    {
+        #[cfg(feature = "metrics")]
+        {
+            let shared = Arc::downgrade(self);
+            let depth = move || shared.upgrade().map(|s| s.len() as f64);
+            self.metrics.get_or_init(|| {
+                crate::metrics::ChannelMetrics::new(crate::metrics::global(), name, depth)
+            });
+        }
+        #[cfg(feature = "introspect")]
+        self.registered.call_once(|| {
+            let shared = Arc::downgrade(self);
+            crate::introspect::register(name, move || {
+                let s = shared.upgrade()?;
+                Some(crate::introspect::State::Channel {
+                    len: s.len(),
+                    capacity: s.capacity(),
+                    senders: s.senders.load(Ordering::SeqCst),
+                    receivers: s.receivers.load(Ordering::SeqCst),
+                    closed: s.senders_gone() || s.receivers_gone(),
+                })
+            });
+        });
+    }
+
+    // Wakes everyone blocked on the other side of a disconnect.
+    fn disconnect(&self, events: &EventCount) {
+        if let Flavor::Zero(zero) = &self.flavor {
+            zero.wake_all();
+        }
+        events.notify_all();
+    }
+}
+
+impl<T> Ends for Shared<T> {
+    fn senders_gone(&self) -> bool {
+        self.senders.load(Ordering::SeqCst) == 0 || self.closed.is_drained()
+    }
+
+    fn receivers_gone(&self) -> bool {
+        self.receivers.load(Ordering::SeqCst) == 0 || self.is_closed()
+    }
+
+    // What the `tracing` feature emits at each send and receive, and each time one waits;
+    // without the feature, nothing. The channel is told apart by its address. A published
+    // channel counts its sends and receives here too.
+    #[allow(unused_variables)]
+    fn traced(&self, op: Op) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.get() {
+            match op {
+                Op::Send => metrics.sent(),
+                Op::Recv => metrics.received(),
+            }
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            channel = self as *const Self as usize,
+            len = self.len(),
+            "{}",
+            match op {
+                Op::Send => "mpmc send",
+                Op::Recv => "mpmc recv",
+            }
+        );
+    }
+
+    #[allow(unused_variables)]
+    fn waits(&self, what: &'static str) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            channel = self as *const Self as usize,
+            len = self.len(),
+            "{}",
+            what
+        );
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.shared.try_send(value)
+    }
+
+    /// Sends `value` without ever waiting, evicting and returning the oldest queued message
+    /// if the channel is full.
+    ///
+    /// A zero-capacity channel has nothing to evict: the value goes straight to a waiting
+    /// receiver if there is one, and is handed back as the eviction otherwise.
+    pub fn force_send(&self, value: T) -> Result<Option<T>, SendError<T>> {
+        let shared = &*self.shared;
+        match &shared.flavor {
+            Flavor::Array(queue) => shared.queued(queue).force_send(value),
+            Flavor::Zero(_) => match shared.try_send(value) {
+                Ok(()) => Ok(None),
+                Err(TrySendError::Full(value)) => Ok(Some(value)),
+                Err(TrySendError::Disconnected(value)) => Err(SendError(value)),
+            },
+        }
+    }
+
+    /// Sends `value`, waiting for room while the channel is full.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.shared
+            .send_until(value, None)
+            .map_err(|e| SendError(e.into_inner()))
+    }
+
+    /// Like [`send`](Self::send), but gives up after `timeout`.
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.shared
+            .send_until(value, Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`send`](Self::send), but gives up at `deadline`.
+    pub fn send_deadline(&self, value: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.shared.send_until(value, Some(deadline))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Sender<T> {
+    /// Closes the channel for everyone: later sends fail, blocked senders get their values
+    /// back, and receivers get `Disconnected` once they've drained what was already sent.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Whether the channel was closed or every receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.shared.receivers_gone()
+    }
+
+    /// Publishes the channel's depth and how much goes through it to
+    /// [`metrics::global`](crate::metrics::global) as `name`, and under `introspect` registers
+    /// it by that name for [`introspect::dump`](crate::introspect::dump). Only the first name
+    /// given, from either end, counts.
+    #[cfg(any(feature = "introspect", feature = "metrics"))]
+    pub fn publish(&self, name: &str)
+    where
+        T: Send + 'static,
+    {
+        self.shared.publish(name);
+    }
+}
+
+impl<T> Receiver<T> {
+    /// See [`Sender::close`].
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Whether the channel was closed or every sender is gone. There may still be messages to
+    /// receive.
+    pub fn is_closed(&self) -> bool {
+        self.shared.senders.load(Ordering::SeqCst) == 0 || self.shared.is_closed()
+    }
+
+    /// See [`Sender::publish`].
+    #[cfg(any(feature = "introspect", feature = "metrics"))]
+    pub fn publish(&self, name: &str)
+    where
+        T: Send + 'static,
+    {
+        self.shared.publish(name);
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.shared.try_recv()
+    }
+
+    /// Waits for a message, failing once every sender is gone and the channel is drained.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.shared.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.shared.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.shared.recv_until(Some(deadline))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> SelectRecv for Receiver<T> {
+    type Msg = T;
+
+    fn try_recv_select(&self) -> Result<T, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv_events(&self) -> &EventCount {
+        &self.shared.not_empty
+    }
+}
+
+impl<T> SelectSend for Sender<T> {
+    type Msg = T;
+
+    fn try_send_select(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.try_send(msg)
+    }
+
+    fn send_events(&self) -> &EventCount {
+        &self.shared.not_full
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // SeqCst: our sends happen before a receiver that sees the count hit zero, and the
+        // count is re-checked after registering to wait.
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.disconnect(&self.shared.not_empty);
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.disconnect(&self.shared.not_full);
+        }
+    }
+}
+
+/// Blocks for each message in turn, ending once every sender is gone and the channel is
+/// drained. Returned by `Receiver::iter`.
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+/// Yields whatever is queued right now. Returned by `Receiver::try_iter`.
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+/// The owning version of [`Iter`].
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Receiver<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+/// A [`bounded`] channel with its `N` slots inline, on a [`StaticMpmcQueue`], so it needs no
+/// heap and can be made in a const and kept in a `static`. Not under loom, whose atomics can't
+/// be made in a const.
+///
+/// Both ends are the channel itself, shared by reference, so there are no handles to count:
+/// it's only disconnected by [`close`](Self::close), after which sends fail and receives get
+/// `Disconnected` once what was already sent is drained. `N` can't be zero.
+#[cfg(not(loom))]
+pub struct StaticChannel<T, const N: usize> {
+    queue: StaticMpmcQueue<T, N>,
+    closed: CloseGate,
+    not_empty: EventCount,
+    not_full: EventCount,
+}
+
+/// A channel holding at most `N` messages in a [`StaticChannel`], for a `static`:
+/// `static EVENTS: StaticChannel<Event, 16> = bounded_static();`.
+#[cfg(not(loom))]
+pub const fn bounded_static<T, const N: usize>() -> StaticChannel<T, N> {
+    StaticChannel::new()
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> StaticChannel<T, N> {
+    /// # Panics
+    ///
+    /// If `N` is zero; at compile time, in a const or a static.
+    pub const fn new() -> Self {
+        Self {
+            queue: StaticMpmcQueue::new(),
+            closed: CloseGate::new(),
+            not_empty: EventCount::new(),
+            not_full: EventCount::new(),
+        }
+    }
+
+    // The queue, with what the loops on it need.
+    fn queued(&self) -> Queued<'_, StaticMpmcQueue<T, N>, Self> {
+        Queued {
+            queue: &self.queue,
+            gate: &self.closed,
+            not_empty: &self.not_empty,
+            not_full: &self.not_full,
+            ends: self,
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.queued().try_send(value)
+    }
+
+    /// Sends `value` without ever waiting, evicting and returning the oldest queued message
+    /// if the channel is full.
+    pub fn force_send(&self, value: T) -> Result<Option<T>, SendError<T>> {
+        self.queued().force_send(value)
+    }
+
+    /// Sends `value`, waiting for room while the channel is full.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send_until(value, None)
+            .map_err(|e| SendError(e.into_inner()))
+    }
+
+    /// Like [`send`](Self::send), but gives up after `timeout`.
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(value, Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`send`](Self::send), but gives up at `deadline`.
+    pub fn send_deadline(&self, value: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(value, Some(deadline))
+    }
+
+    fn send_until(&self, value: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        self.queued().send_until(value, deadline)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.queued().try_recv()
+    }
+
+    /// Waits for a message, failing once the channel is closed and drained.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.queued().recv_until(deadline)
+    }
+
+    /// Yields whatever is queued right now.
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.try_recv().ok())
+    }
+
+    /// Closes the channel: later sends fail, blocked senders get their values back, and
+    /// receivers get `Disconnected` once they've drained what was already sent.
+    pub fn close(&self) {
+        self.closed.close();
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_closed()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> Default for StaticChannel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Closing is the only way to disconnect it, and it's neither traced nor counted.
+#[cfg(not(loom))]
+impl<T, const N: usize> Ends for StaticChannel<T, N> {
+    fn senders_gone(&self) -> bool {
+        self.closed.is_drained()
+    }
+
+    fn receivers_gone(&self) -> bool {
+        self.is_closed()
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> SelectRecv for StaticChannel<T, N> {
+    type Msg = T;
+
+    fn try_recv_select(&self) -> Result<T, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv_events(&self) -> &EventCount {
+        &self.not_empty
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> SelectSend for StaticChannel<T, N> {
+    type Msg = T;
+
+    fn try_send_select(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.try_send(msg)
+    }
+
+    fn send_events(&self) -> &EventCount {
+        &self.not_full
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_channel_backpressure() {
+    let (tx, rx) = bounded(2);
+    assert_eq!(tx.try_send(1), Ok(()));
+    assert_eq!(tx.try_send(2), Ok(()));
+    assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(tx.len(), 2);
+    assert_eq!(rx.try_recv(), Ok(1));
+    assert_eq!(rx.try_recv(), Ok(2));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let producers: Vec<_> = (0..2)
+        .map(|t| {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for i in 0..n {
+                    tx.send(t * n + i).unwrap();
+                    assert!(tx.len() <= 2);
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let consumers: Vec<_> = (0..2)
+        .map(|_| {
+            let rx = rx.clone();
+            std::thread::spawn(move || std::iter::from_fn(|| rx.recv().ok()).collect::<Vec<_>>())
+        })
+        .collect();
+    drop(rx);
+    for p in producers {
+        p.join().unwrap();
+    }
+    let mut all: Vec<_> = consumers
+        .into_iter()
+        .flat_map(|c| c.join().unwrap())
+        .collect();
+    all.sort_unstable();
+    assert!(all.into_iter().eq(0..2 * n));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_channel_wakes_blocked_sender_on_disconnect() {
+    let (tx, rx) = bounded(1);
+    tx.send(1).unwrap();
+    let t = std::thread::spawn(move || tx.send(2));
+    std::thread::yield_now();
+    drop(rx);
+    assert_eq!(t.join().unwrap(), Err(SendError(2)));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn zero_capacity_rendezvous() {
+    let (tx, rx) = bounded(0);
+    assert_eq!(tx.capacity(), 0);
+    // Nobody is waiting to receive.
+    assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+    let sent = &AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..100 {
+                tx.send(i).unwrap();
+                sent.store(i + 1, Ordering::SeqCst);
+            }
+        });
+        for i in 0..100 {
+            assert_eq!(rx.recv(), Ok(i));
+            std::thread::yield_now();
+            // With nowhere to buffer, the producer can't get further than the value just taken.
+            assert!(sent.load(Ordering::SeqCst) <= i + 1);
+        }
+        assert_eq!(rx.recv(), Err(RecvError));
+    });
+
+    let (tx, rx) = bounded::<u32>(0);
+    let t = std::thread::spawn(move || tx.send(1));
+    std::thread::yield_now();
+    drop(rx);
+    assert_eq!(t.join().unwrap(), Err(SendError(1)));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_channel_timeouts() {
+    let timeout = Duration::from_millis(10);
+    for capacity in [0, 1] {
+        let (tx, rx) = bounded(capacity);
+        assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+        if capacity == 1 {
+            tx.send(0).unwrap();
+        }
+        // Full, or with nobody there to take it.
+        assert_eq!(
+            tx.send_timeout(1, timeout),
+            Err(SendTimeoutError::Timeout(1))
+        );
+        let t = std::thread::spawn(move || tx.send_timeout(2, Duration::from_secs(10)));
+        if capacity == 1 {
+            assert_eq!(rx.recv(), Ok(0));
+        }
+        assert_eq!(
+            rx.recv_deadline(Instant::now() + Duration::from_secs(10)),
+            Ok(2)
+        );
+        assert_eq!(t.join().unwrap(), Ok(()));
+        assert_eq!(
+            rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_receiver_iterators() {
+    let (tx, rx) = bounded(2);
+    let t = std::thread::spawn(move || {
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+    });
+    let mut sum = 0;
+    for i in &rx {
+        sum += i;
+    }
+    assert_eq!(sum, 45);
+    assert_eq!(rx.try_iter().count(), 0);
+    t.join().unwrap();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_channel_force_send() {
+    let (tx, rx) = bounded(2);
+    assert_eq!(tx.force_send(1), Ok(None));
+    assert_eq!(tx.force_send(2), Ok(None));
+    assert_eq!(tx.force_send(3), Ok(Some(1)));
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [2, 3]);
+
+    let (tx, rx) = bounded(0);
+    assert_eq!(tx.force_send(1), Ok(Some(1)));
+    drop(rx);
+    assert_eq!(tx.force_send(2), Err(SendError(2)));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn bounded_channel_close() {
+    for capacity in [0, 2] {
+        let (tx, rx) = bounded(capacity);
+        for i in 0..capacity {
+            tx.send(i).unwrap();
+        }
+        // Waits for room, or for a receiver that never comes.
+        let blocked = {
+            let tx = tx.clone();
+            std::thread::spawn(move || tx.send(9))
+        };
+        std::thread::yield_now();
+        rx.close();
+        assert_eq!(blocked.join().unwrap(), Err(SendError(9)));
+        assert!(tx.is_closed() && rx.is_closed());
+        assert_eq!(tx.try_send(3), Err(TrySendError::Disconnected(3)));
+        for i in 0..capacity {
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn channel_close_loses_no_sends() {
+    use crate::sched;
+
+    // Under the test scheduler, so each seed lands the close somewhere else among the sends:
+    // every send that got in has to be received before the receiver's told it's closed.
+    sched::check(|s| {
+        let (tx, rx) = bounded(4);
+        let sender = s.spawn(move || (0..3).filter(|&i| tx.try_send(i).is_ok()).count());
+        rx.close();
+        let received = std::iter::from_fn(|| loop {
+            match rx.try_recv() {
+                Ok(v) => return Some(v),
+                Err(TryRecvError::Empty) => sched::yield_now(),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        })
+        .count();
+        assert_eq!(received, sender.join().unwrap());
+
+        let channel = Arc::new(StaticChannel::<usize, 4>::new());
+        let sender = s.spawn({
+            let channel = Arc::clone(&channel);
+            move || (0..3).filter(|&i| channel.try_send(i).is_ok()).count()
+        });
+        channel.close();
+        let received = std::iter::from_fn(|| loop {
+            match channel.try_recv() {
+                Ok(v) => return Some(v),
+                Err(TryRecvError::Empty) => sched::yield_now(),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        })
+        .count();
+        assert_eq!(received, sender.join().unwrap());
+    });
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn static_channel_needs_no_heap() {
+    static CHANNEL: StaticChannel<usize, 4> = bounded_static();
+
+    assert_eq!(CHANNEL.capacity(), 4);
+    for i in 0..4 {
+        assert_eq!(CHANNEL.try_send(i), Ok(()));
+    }
+    assert_eq!(CHANNEL.try_send(4), Err(TrySendError::Full(4)));
+    assert_eq!(CHANNEL.force_send(4), Ok(Some(0)));
+    assert_eq!(CHANNEL.try_iter().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    assert_eq!(
+        CHANNEL.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let sum = &AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for t in 0..2 {
+            s.spawn(move || {
+                for i in 0..n {
+                    CHANNEL.send(t * n + i).unwrap();
+                    assert!(CHANNEL.len() <= 4);
+                }
+            });
+        }
+        for _ in 0..2 {
+            s.spawn(move || {
+                while let Ok(v) = CHANNEL.recv() {
+                    sum.fetch_add(v, Ordering::Relaxed);
+                }
+            });
+        }
+        let mut received = 0;
+        while received < 2 * n * (2 * n - 1) / 2 {
+            received = sum.load(Ordering::Relaxed);
+            std::thread::yield_now();
+        }
+        CHANNEL.close();
+    });
+    assert_eq!(sum.load(Ordering::Relaxed), (0..2 * n).sum::<usize>());
+    assert_eq!(CHANNEL.try_send(0), Err(TrySendError::Disconnected(0)));
+    assert_eq!(CHANNEL.recv(), Err(RecvError));
+}
+
+#[cfg(shuttle)]
+#[test]
+fn mpmc_shuttle() {
+    use shuttle::thread;
+    crate::sync_shim::shuttle_check(|| {
+        let (tx, rx) = bounded(1);
+        let producers: Vec<_> = (0..2)
+            .map(|t| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..2 {
+                        tx.send(t * 2 + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        let consumer = {
+            let rx = rx.clone();
+            thread::spawn(move || rx.recv().ok())
+        };
+        let mut got: Vec<_> = rx.iter().collect();
+        got.extend(consumer.join().unwrap());
+        for p in producers {
+            p.join().unwrap();
+        }
+        got.sort_unstable();
+        assert_eq!(got, [0, 1, 2, 3]);
+    });
+}
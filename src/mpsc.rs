@@ -0,0 +1,618 @@
+use crate::close_gate::CloseGate;
+use crate::event_count::EventCount;
+use crate::intrusive_mpsc::{Link, Linked, MpscQueue};
+use crate::mpmc::TrySendError;
+use crate::select::{SelectRecv, SelectSend};
+use crate::sync_shim::atomic::{AtomicUsize, Ordering};
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[repr(C)]
+struct Message<T> {
+    link: Link,
+    value: T,
+}
+
+impl<T> Linked for Message<T> {
+    fn link(&self) -> &Link {
+        &self.link
+    }
+}
+
+// The queue wants its nodes Sync, but nobody ever looks at a message's value while it's
+// shared: the sender moves it in, and the receiver only reads it after unwrapping the last Arc.
+unsafe impl<T: Send> Sync for Message<T> {}
+
+struct Shared<T> {
+    queue: MpscQueue<Message<T>>,
+    senders: AtomicUsize,
+    // Closed by close, or when the receiver drops; nothing more can be sent. Sends go
+    // through it.
+    closed: CloseGate,
+    ready: EventCount,
+}
+
+/// The sending half of a [`channel`]; clone it for more producers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a [`channel`]. There's only ever one, so it's `Send` but not `Sync`.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// The channel was closed or the receiver is gone; the unsent value is handed back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The channel was closed or every sender is gone, and it's drained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing is queued right now.
+    Empty,
+    /// The channel was closed or every sender is gone, and it's drained.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// Nothing arrived before the deadline.
+    Timeout,
+    /// The channel was closed or every sender is gone, and it's drained.
+    Disconnected,
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on a closed and empty channel")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("receiving on an empty channel"),
+            Self::Disconnected => RecvError.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timed out waiting on a channel"),
+            Self::Disconnected => RecvError.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
+/// An unbounded multi-producer single-consumer channel on the intrusive [`MpscQueue`].
+///
+/// Each message is its own queue node, so sending is an allocation and a swap; the receiver
+/// sleeps on an [`EventCount`] that senders only touch when it's actually waiting.
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: MpscQueue::new(),
+        senders: AtomicUsize::new(1),
+        closed: CloseGate::new(),
+        ready: EventCount::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    let receiver = Receiver {
+        shared,
+        _not_sync: PhantomData,
+    };
+    (sender, receiver)
+}
+
+impl<T: Send> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // Before allocating a message that can't be sent; send_message checks properly.
+        if self.shared.closed.is_closed() {
+            return Err(SendError(value));
+        }
+        self.send_message(Arc::new(Message {
+            link: Link::new(),
+            value,
+        }))
+    }
+
+    fn send_message(&self, message: Arc<Message<T>>) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        match shared
+            .closed
+            .push(message, &shared.ready, |message| shared.queue.push(message))
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => unreachable!("a fresh message can't be queued already"),
+            Err(message) => {
+                let message =
+                    Arc::into_inner(message).expect("an unsent message has no other owners");
+                return Err(SendError(message.value));
+            }
+        }
+        self.shared.ready.notify_one();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(channel = Arc::as_ptr(&self.shared) as usize, "mpsc send");
+        Ok(())
+    }
+
+    /// Allocates the queue node for a message up front, so the message can be built in place
+    /// and then sent without being moved.
+    pub fn reserve(&self) -> SendSlot<'_, T> {
+        SendSlot {
+            sender: self,
+            message: Arc::new(Message {
+                link: Link::new(),
+                value: MaybeUninit::uninit(),
+            }),
+        }
+    }
+}
+
+/// A message that has its queue node but isn't sent yet. Returned by [`Sender::reserve`].
+///
+/// Dropping it without committing frees the node without sending anything.
+pub struct SendSlot<'a, T: Send> {
+    sender: &'a Sender<T>,
+    message: Arc<Message<MaybeUninit<T>>>,
+}
+
+impl<T: Send> SendSlot<'_, T> {
+    /// The message's storage inside its queue node.
+    pub fn as_uninit(&mut self) -> &mut MaybeUninit<T> {
+        &mut Arc::get_mut(&mut self.message)
+            .expect("a reserved message has no other owners")
+            .value
+    }
+
+    /// Sends the message written through [`as_uninit`](Self::as_uninit).
+    ///
+    /// # Safety
+    ///
+    /// The message must have been initialized.
+    pub unsafe fn commit(self) -> Result<(), SendError<T>> {
+        // MaybeUninit<T> has T's layout and Message is repr(C), so this is the same node
+        // with its value now known to be there.
+        let message = Arc::from_raw(Arc::into_raw(self.message) as *const Message<T>);
+        self.sender.send_message(message)
+    }
+
+    /// Writes `value` into the node and sends it.
+    pub fn write(mut self, value: T) -> Result<(), SendError<T>> {
+        self.as_uninit().write(value);
+        unsafe { self.commit() }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Closes the channel for every sender: later sends fail, and the receiver gets
+    /// `Disconnected` once it has drained what was already sent.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Whether the channel was closed or the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.is_closed()
+    }
+}
+
+impl<T> Shared<T> {
+    fn close(&self) {
+        self.closed.close();
+        self.ready.notify_all();
+    }
+
+    // Whether nothing more can arrive: the senders are gone, or the channel's closed and the
+    // sends it let in have landed. One may have landed since the caller last looked, so
+    // callers check the queue again after this.
+    fn disconnected(&self) -> bool {
+        self.senders.load(Ordering::SeqCst) == 0 || self.closed.is_drained()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release: our sends happen before a receiver that sees the count hit zero.
+        if self.shared.senders.fetch_sub(1, Ordering::Release) == 1 {
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    fn pop(&self) -> Option<T> {
+        // Safety: this is the only receiver, and it isn't Sync.
+        let message = unsafe { self.shared.queue.pop() }?;
+        let message = Arc::into_inner(message).expect("a popped message has no other owners");
+        #[cfg(feature = "tracing")]
+        tracing::trace!(channel = Arc::as_ptr(&self.shared) as usize, "mpsc recv");
+        Some(message.value)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.pop() {
+            return Ok(value);
+        }
+        if !self.shared.disconnected() {
+            return Err(TryRecvError::Empty);
+        }
+        // The last sender may have sent just before it dropped, or a send let in before the
+        // close landed.
+        self.pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    /// Waits for a message, failing once every sender is gone and the channel is drained.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let ready = &self.shared.ready;
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let key = ready.prepare_wait();
+            match self.try_recv() {
+                Ok(value) => {
+                    ready.cancel_wait(key);
+                    return Ok(value);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    ready.cancel_wait(key);
+                    return Err(RecvTimeoutError::Disconnected);
+                }
+                Err(TryRecvError::Empty) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        channel = Arc::as_ptr(&self.shared) as usize,
+                        "mpsc recv waiting for a message"
+                    );
+                    match deadline {
+                        None => ready.wait(key),
+                        Some(deadline) => {
+                            ready.wait_deadline(key, deadline);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Closes the channel: later sends fail, but what was already sent can still be received.
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.is_closed()
+    }
+}
+
+impl<T: Send> SelectRecv for Receiver<T> {
+    type Msg = T;
+
+    fn try_recv_select(&self) -> Result<T, TryRecvError> {
+        self.try_recv()
+    }
+
+    fn recv_events(&self) -> &EventCount {
+        &self.shared.ready
+    }
+}
+
+impl<T: Send> SelectSend for Sender<T> {
+    type Msg = T;
+
+    // Unbounded, so never Full.
+    fn try_send_select(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.send(msg)
+            .map_err(|SendError(msg)| TrySendError::Disconnected(msg))
+    }
+
+    fn send_events(&self) -> &EventCount {
+        &self.shared.ready
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // Messages already queued, or still landing, are dropped along with the queue.
+        self.shared.closed.close();
+    }
+}
+
+/// Blocks for each message in turn, ending once every sender is gone and the channel is
+/// drained. Returned by `Receiver::iter`.
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+/// Yields whatever is queued right now. Returned by `Receiver::try_iter`.
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+/// The owning version of [`Iter`].
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T: Send> Receiver<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T: Send> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T: Send> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T: Send> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T: Send> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Send> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_channel_disconnects() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let (tx, rx) = channel();
+    let producers: Vec<_> = (0..3)
+        .map(|t| {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for i in 0..n {
+                    tx.send(t * n + i).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let mut got = Vec::new();
+    while let Ok(v) = rx.recv() {
+        got.push(v);
+    }
+    for p in producers {
+        p.join().unwrap();
+    }
+    got.sort_unstable();
+    assert!(got.into_iter().eq(0..3 * n));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (tx, rx) = channel::<String>();
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    tx.send(String::from("dropped with the channel")).unwrap();
+    drop(rx);
+    assert!(tx.send(String::new()).is_err());
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_recv_timeout() {
+    let (tx, rx) = channel();
+    let timeout = Duration::from_millis(10);
+    assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+    tx.send(1).unwrap();
+    assert_eq!(rx.recv_timeout(timeout), Ok(1));
+    let t = std::thread::spawn(move || tx.send(2).unwrap());
+    assert_eq!(
+        rx.recv_deadline(Instant::now() + Duration::from_secs(10)),
+        Ok(2)
+    );
+    t.join().unwrap();
+    assert_eq!(
+        rx.recv_timeout(timeout),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_receiver_iterators() {
+    let (tx, rx) = channel();
+    for i in 0..3 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(rx.try_iter().next(), None);
+    let t = std::thread::spawn(move || {
+        for i in 3..6 {
+            tx.send(i).unwrap();
+        }
+    });
+    // Ends once the sender is dropped.
+    assert_eq!(rx.into_iter().collect::<Vec<_>>(), [3, 4, 5]);
+    t.join().unwrap();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_close() {
+    let (tx, rx) = channel();
+    let tx2 = tx.clone();
+    tx.send(1).unwrap();
+    tx2.close();
+    assert!(tx.is_closed());
+    assert_eq!(tx.send(2), Err(SendError(2)));
+    // Already-sent messages drain before the channel reports itself disconnected, even
+    // though a sender is still alive.
+    assert_eq!(rx.recv(), Ok(1));
+    assert_eq!(rx.recv(), Err(RecvError));
+
+    let (tx, rx) = channel();
+    tx.send(1).unwrap();
+    rx.close();
+    assert_eq!(tx.send(2), Err(SendError(2)));
+    assert_eq!(rx.try_recv(), Ok(1));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_close_loses_no_sends() {
+    use crate::sched;
+
+    // As with the mpmc channel: each seed lands the close somewhere else among the sends,
+    // and every send that got in is received before the receiver's told it's closed.
+    sched::check(|s| {
+        let (tx, rx) = channel();
+        let sender = s.spawn(move || (0..3).filter(|&i| tx.send(i).is_ok()).count());
+        rx.close();
+        let received = std::iter::from_fn(|| loop {
+            match rx.try_recv() {
+                Ok(v) => return Some(v),
+                Err(TryRecvError::Empty) => sched::yield_now(),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        })
+        .count();
+        assert_eq!(received, sender.join().unwrap());
+    });
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn mpsc_reserve_in_place() {
+    let (tx, rx) = channel::<[u64; 64]>();
+    let mut slot = tx.reserve();
+    let buf = slot.as_uninit().as_mut_ptr() as *mut u64;
+    for i in 0..64 {
+        unsafe { buf.add(i).write(i as u64) };
+    }
+    unsafe { slot.commit() }.unwrap();
+    assert!(rx.recv().unwrap().iter().copied().eq(0..64));
+
+    // Abandoned reservations send nothing.
+    drop(tx.reserve());
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    rx.close();
+    assert!(tx.reserve().write([0; 64]).is_err());
+}
+
+#[cfg(shuttle)]
+#[test]
+fn mpsc_shuttle() {
+    use shuttle::thread;
+    crate::sync_shim::shuttle_check(|| {
+        let (tx, rx) = channel();
+        let producers: Vec<_> = (0..2)
+            .map(|t| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..2 {
+                        tx.send((t, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        // Each producer's values arrive in the order it sent them.
+        let mut next = [0, 0];
+        for (t, i) in rx.iter() {
+            assert_eq!(i, next[t]);
+            next[t] += 1;
+        }
+        assert_eq!(next, [2, 2]);
+        for p in producers {
+            p.join().unwrap();
+        }
+    });
+}
@@ -0,0 +1,479 @@
+use std::io;
+use std::time::Duration;
+
+// A counting semaphore the OS keeps by name, so separate processes of one service, started
+// separately and sharing no memory, can cap between them how many of something run at once.
+// It's POSIX's sem_open on Unix and a named semaphore object on Windows; `shm::Semaphore` is
+// the one to use for processes that already share an arena.
+//
+// It hands out the same RAII permits as `async_sync::Semaphore`, one at a time: neither OS
+// takes several permits atomically, and taking them one by one could leave two processes each
+// holding half of what they need. A permit given back by a process that dies holding it isn't
+// given back at all, on either OS; a service that can crash holding permits needs to be able
+// to recreate the semaphore.
+//
+// Names are one path component, with or without the leading slash POSIX wants. On Linux the
+// semaphore is a file under /dev/shm, there until it's unlinked; on Windows it goes with the
+// last handle to it, and `unlink` does nothing. macOS limits names to 31 bytes and has no
+// timed wait, so `try_acquire_for` polls there.
+
+/// A semaphore shared by every process that opens it by name.
+pub struct NamedSemaphore {
+    sem: sys::Sem,
+    name: String,
+}
+
+/// A permit taken from a [`NamedSemaphore`], given back on drop.
+#[must_use = "the permit is given back as soon as this is dropped"]
+pub struct NamedSemaphorePermit<'a> {
+    semaphore: &'a NamedSemaphore,
+    forgotten: bool,
+}
+
+fn name(name: &str) -> io::Result<&str> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    if name.is_empty() || name.contains(['/', '\\', '\0']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "named semaphore: a name is one path component",
+        ));
+    }
+    Ok(name)
+}
+
+impl NamedSemaphore {
+    /// Creates the semaphore `name` with `permits` free. Fails if it already exists.
+    pub fn create(name: &str, permits: u32) -> io::Result<Self> {
+        let name = self::name(name)?;
+        Ok(Self {
+            sem: sys::Sem::open(name, Some(permits))?,
+            name: name.to_string(),
+        })
+    }
+
+    /// Opens the semaphore `name` another process created.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let name = self::name(name)?;
+        Ok(Self {
+            sem: sys::Sem::open(name, None)?,
+            name: name.to_string(),
+        })
+    }
+
+    /// Opens `name`, or creates it with `permits` free if no one has yet, for processes that
+    /// can start in any order.
+    pub fn open_or_create(name: &str, permits: u32) -> io::Result<Self> {
+        match Self::create(name, permits) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Self::open(name),
+            r => r,
+        }
+    }
+
+    /// Removes the name `name`, so the next `create` makes a new semaphore; the old one goes
+    /// once every process has it closed. Does nothing on Windows.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        sys::unlink(self::name(name)?)
+    }
+
+    /// The name, without the leading slash.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Takes a permit, waiting while there are none.
+    pub fn acquire(&self) -> NamedSemaphorePermit<'_> {
+        self.sem.wait(None);
+        self.permit()
+    }
+
+    /// Takes a permit if one is free.
+    pub fn try_acquire(&self) -> Option<NamedSemaphorePermit<'_>> {
+        self.sem.wait(Some(Duration::ZERO)).then(|| self.permit())
+    }
+
+    /// Takes a permit, waiting up to `timeout` for one.
+    pub fn try_acquire_for(&self, timeout: Duration) -> Option<NamedSemaphorePermit<'_>> {
+        self.sem.wait(Some(timeout)).then(|| self.permit())
+    }
+
+    /// Adds `n` new permits, for every process.
+    ///
+    /// # Panics
+    ///
+    /// If that takes the count past what the OS allows, which is at least `i32::MAX`.
+    pub fn add_permits(&self, n: u32) {
+        self.sem.post(n);
+    }
+
+    fn permit(&self) -> NamedSemaphorePermit<'_> {
+        NamedSemaphorePermit {
+            semaphore: self,
+            forgotten: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for NamedSemaphore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NamedSemaphore")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl NamedSemaphorePermit<'_> {
+    /// Keeps the permit out of the semaphore for good.
+    pub fn forget(mut self) {
+        self.forgotten = true;
+    }
+}
+
+impl Drop for NamedSemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if !self.forgotten {
+            self.semaphore.sem.post(1);
+        }
+    }
+}
+
+#[cfg(all(any(target_os = "linux", target_os = "macos"), not(miri)))]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+    #[cfg(target_os = "linux")]
+    use std::os::raw::c_long;
+    use std::os::raw::{c_char, c_int, c_uint, c_void};
+    use std::time::Duration;
+    #[cfg(target_os = "macos")]
+    use std::time::Instant;
+    #[cfg(target_os = "linux")]
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[cfg(target_os = "linux")]
+    const O_CREAT: c_int = 0o100;
+    #[cfg(target_os = "linux")]
+    const O_EXCL: c_int = 0o200;
+    #[cfg(target_os = "macos")]
+    const O_CREAT: c_int = 0x200;
+    #[cfg(target_os = "macos")]
+    const O_EXCL: c_int = 0x800;
+
+    #[cfg(target_os = "linux")]
+    const SEM_FAILED: *mut c_void = std::ptr::null_mut();
+    #[cfg(target_os = "macos")]
+    const SEM_FAILED: *mut c_void = !0 as *mut c_void;
+
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn sem_open(name: *const c_char, oflag: c_int, ...) -> *mut c_void;
+        fn sem_close(sem: *mut c_void) -> c_int;
+        fn sem_unlink(name: *const c_char) -> c_int;
+        fn sem_wait(sem: *mut c_void) -> c_int;
+        fn sem_trywait(sem: *mut c_void) -> c_int;
+        #[cfg(target_os = "linux")]
+        fn sem_timedwait(sem: *mut c_void, abstime: *const Timespec) -> c_int;
+        fn sem_post(sem: *mut c_void) -> c_int;
+    }
+
+    fn path(name: &str) -> io::Result<CString> {
+        CString::new(format!("/{}", name))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    pub struct Sem(*mut c_void);
+
+    // Safety: a sem_t is made for sharing, between threads and processes alike.
+    unsafe impl Send for Sem {}
+    unsafe impl Sync for Sem {}
+
+    impl Sem {
+        pub fn open(name: &str, create: Option<u32>) -> io::Result<Self> {
+            let path = path(name)?;
+            let sem = match create {
+                // Variadic, so the mode and count go as promoted ints.
+                Some(permits) => unsafe {
+                    sem_open(
+                        path.as_ptr(),
+                        O_CREAT | O_EXCL,
+                        0o600 as c_uint,
+                        permits as c_uint,
+                    )
+                },
+                None => unsafe { sem_open(path.as_ptr(), 0) },
+            };
+            if sem == SEM_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(sem))
+        }
+
+        // Returns whether it took a permit, which with no timeout it always does.
+        pub fn wait(&self, timeout: Option<Duration>) -> bool {
+            match timeout {
+                None => check(retry(|| unsafe { sem_wait(self.0) }), None),
+                Some(Duration::ZERO) => self.try_wait(),
+                Some(timeout) => self.wait_for(timeout),
+            }
+        }
+
+        fn try_wait(&self) -> bool {
+            check(
+                retry(|| unsafe { sem_trywait(self.0) }),
+                Some(io::ErrorKind::WouldBlock),
+            )
+        }
+
+        #[cfg(target_os = "linux")]
+        fn wait_for(&self, timeout: Duration) -> bool {
+            // sem_timedwait's deadline is on the realtime clock.
+            let deadline = match SystemTime::now().checked_add(timeout) {
+                Some(deadline) => deadline.duration_since(UNIX_EPOCH).unwrap_or_default(),
+                None => return self.wait(None),
+            };
+            let abstime = Timespec {
+                tv_sec: deadline.as_secs().min(c_long::MAX as u64) as c_long,
+                tv_nsec: deadline.subsec_nanos() as c_long,
+            };
+            check(
+                retry(|| unsafe { sem_timedwait(self.0, &abstime) }),
+                Some(io::ErrorKind::TimedOut),
+            )
+        }
+
+        #[cfg(target_os = "macos")]
+        fn wait_for(&self, timeout: Duration) -> bool {
+            let deadline = Instant::now().checked_add(timeout);
+            loop {
+                if self.try_wait() {
+                    return true;
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return false;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        pub fn post(&self, n: u32) {
+            for _ in 0..n {
+                if unsafe { sem_post(self.0) } != 0 {
+                    panic!(
+                        "named semaphore: can't post: {}",
+                        io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+    }
+
+    // Whether a wait took a permit: it did if it succeeded, and didn't if it failed with
+    // `none`, the error for there not being one. Any other error means the handle's bad.
+    fn check(r: io::Result<()>, none: Option<io::ErrorKind>) -> bool {
+        match r {
+            Ok(()) => true,
+            Err(e) if Some(e.kind()) == none => false,
+            Err(e) => panic!("named semaphore: can't wait: {}", e),
+        }
+    }
+
+    impl Drop for Sem {
+        fn drop(&mut self) {
+            unsafe { sem_close(self.0) };
+        }
+    }
+
+    // Runs `f` again for as long as a signal interrupts it.
+    fn retry(mut f: impl FnMut() -> c_int) -> io::Result<()> {
+        loop {
+            if f() == 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    pub fn unlink(name: &str) -> io::Result<()> {
+        if unsafe { sem_unlink(path(name)?.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod sys {
+    use std::convert::TryFrom;
+    use std::io;
+    use std::os::raw::c_void;
+    use std::time::Duration;
+
+    const ERROR_ALREADY_EXISTS: i32 = 183;
+    const SEMAPHORE_ALL_ACCESS: u32 = 0x1F_0003;
+    const WAIT_OBJECT_0: u32 = 0;
+    const WAIT_TIMEOUT: u32 = 0x102;
+    const INFINITE: u32 = u32::MAX;
+
+    extern "system" {
+        fn CreateSemaphoreW(
+            attributes: *mut c_void,
+            initial: i32,
+            maximum: i32,
+            name: *const u16,
+        ) -> *mut c_void;
+        fn OpenSemaphoreW(access: u32, inherit: i32, name: *const u16) -> *mut c_void;
+        fn WaitForSingleObject(handle: *mut c_void, milliseconds: u32) -> u32;
+        fn ReleaseSemaphore(handle: *mut c_void, n: i32, previous: *mut i32) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    pub struct Sem(*mut c_void);
+
+    // Safety: a semaphore handle is made for sharing, between threads and processes alike.
+    unsafe impl Send for Sem {}
+    unsafe impl Sync for Sem {}
+
+    impl Sem {
+        pub fn open(name: &str, create: Option<u32>) -> io::Result<Self> {
+            let name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+            let handle = match create {
+                Some(permits) => {
+                    let initial = i32::try_from(permits).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "named semaphore: too many permits",
+                        )
+                    })?;
+                    unsafe {
+                        CreateSemaphoreW(std::ptr::null_mut(), initial, i32::MAX, name.as_ptr())
+                    }
+                }
+                None => unsafe { OpenSemaphoreW(SEMAPHORE_ALL_ACCESS, 0, name.as_ptr()) },
+            };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // Creating one that exists opens it instead, and says so afterwards.
+            let e = io::Error::last_os_error();
+            let sem = Self(handle);
+            if create.is_some() && e.raw_os_error() == Some(ERROR_ALREADY_EXISTS) {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            Ok(sem)
+        }
+
+        pub fn wait(&self, timeout: Option<Duration>) -> bool {
+            let milliseconds = match timeout {
+                None => INFINITE,
+                Some(t) => t.as_millis().min(INFINITE as u128 - 1) as u32,
+            };
+            match unsafe { WaitForSingleObject(self.0, milliseconds) } {
+                WAIT_OBJECT_0 => true,
+                WAIT_TIMEOUT => false,
+                _ => panic!(
+                    "named semaphore: can't wait: {}",
+                    io::Error::last_os_error()
+                ),
+            }
+        }
+
+        pub fn post(&self, n: u32) {
+            let n = i32::try_from(n).expect("named semaphore: too many permits");
+            if n > 0 && unsafe { ReleaseSemaphore(self.0, n, std::ptr::null_mut()) } == 0 {
+                panic!(
+                    "named semaphore: can't post: {}",
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+
+    impl Drop for Sem {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    pub fn unlink(_: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(
+    all(any(target_os = "linux", target_os = "macos"), not(miri)),
+    all(windows, not(miri))
+)))]
+mod sys {
+    use std::convert::Infallible;
+    use std::io;
+    use std::time::Duration;
+
+    pub struct Sem(Infallible);
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "named semaphores are only on Linux, macOS and Windows",
+        )
+    }
+
+    impl Sem {
+        pub fn open(_: &str, _: Option<u32>) -> io::Result<Self> {
+            Err(unsupported())
+        }
+
+        pub fn wait(&self, _: Option<Duration>) -> bool {
+            match self.0 {}
+        }
+
+        pub fn post(&self, _: u32) {
+            match self.0 {}
+        }
+    }
+
+    pub fn unlink(_: &str) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "opens OS semaphores")]
+fn named_semaphore_is_shared_by_name() {
+    if !cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        assert!(NamedSemaphore::create("atomics-test", 1).is_err());
+        return;
+    }
+    let name = format!("atomics-sem-{}", std::process::id());
+    let a = NamedSemaphore::create(&name, 2).unwrap();
+    assert_eq!(
+        NamedSemaphore::create(&name, 2).unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    // A second handle, as another process would open it, shares the count.
+    let b = NamedSemaphore::open_or_create(&format!("/{}", name), 9).unwrap();
+    let p1 = a.acquire();
+    let p2 = b.try_acquire().unwrap();
+    assert!(a.try_acquire().is_none());
+    assert!(b.try_acquire_for(Duration::from_millis(10)).is_none());
+    drop(p1);
+    let p3 = b.try_acquire_for(Duration::from_secs(5)).unwrap();
+    p2.forget();
+    drop(p3);
+    // One permit back from p3, and one added for the forgotten p2.
+    b.add_permits(1);
+    let held: Vec<_> = (0..2).map(|_| a.try_acquire().unwrap()).collect();
+    assert!(b.try_acquire().is_none());
+    drop(held);
+    assert_eq!(b.name(), name);
+    assert!(NamedSemaphore::open("bad/name").is_err());
+    NamedSemaphore::unlink(&name).unwrap();
+}
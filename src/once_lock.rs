@@ -0,0 +1,262 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+// std's OnceLock, plus waiting for someone else to fill it: a thread that needs the value but
+// has no way to make it, say a config a loader thread reads, sleeps in `wait` until it's set
+// rather than polling `get` or pairing the cell with a condvar.
+//
+// The state word goes from INCOMPLETE to RUNNING while an initializer runs, and on to
+// COMPLETE once the value's written, or back to INCOMPLETE if the initializer panicked or
+// failed. Waiters sleep on the word through `futex`, and every change of it out of RUNNING
+// wakes them all; there are only ever a couple, so there's no waiter count to save the system
+// call.
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const COMPLETE: u32 = 2;
+
+/// A cell written once, by [`set`](Self::set) or [`get_or_init`](Self::get_or_init), and
+/// read through shared references after that.
+pub struct OnceLock<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Safety: a value written once, by whichever thread, and then shared: Send to be written from
+// another thread, and Sync to be shared.
+unsafe impl<T: Send> Send for OnceLock<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+
+impl<T: UnwindSafe> UnwindSafe for OnceLock<T> {}
+impl<T: UnwindSafe + RefUnwindSafe> RefUnwindSafe for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        // Acquire: pairs with the Release that published the value.
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            // Safety: complete, so written, and never written again.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Sets the value, or hands `value` back if it was already set. Of threads racing to set
+    /// it, one wins and the rest each get their own value back, once the winner's is in.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// The value, made by `f` if it isn't set yet. If another thread is already making it,
+    /// waits for that one instead of running `f`. If `f` panics, the cell stays unset and the
+    /// next caller gets to try.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        match self.get_or_try_init(|| Ok::<T, std::convert::Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`get_or_init`](Self::get_or_init), but `f` may fail, and if it does, the cell
+    /// stays unset, as for a panic, and the error is returned.
+    pub fn get_or_try_init<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+        let mut f = Some(f);
+        loop {
+            if let Some(value) = self.get() {
+                return Ok(value);
+            }
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // Puts the state back if `f` unwinds, or fails.
+                    let reset = Reset(&self.state);
+                    let value = f.take().unwrap()()?;
+                    std::mem::forget(reset);
+                    // Safety: RUNNING is ours, so no one else writes or reads the value.
+                    unsafe { (*self.value.get()).write(value) };
+                    self.finish(COMPLETE);
+                }
+                Err(COMPLETE) => {}
+                Err(_) => crate::futex::wait(&self.state, RUNNING, None),
+            }
+        }
+    }
+
+    /// Waits for the value to be set, by whichever thread sets it.
+    pub fn wait(&self) -> &T {
+        self.wait_until(None).unwrap()
+    }
+
+    /// Like [`wait`](Self::wait), but gives up after `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<&T> {
+        self.wait_until(Instant::now().checked_add(timeout))
+    }
+
+    // No deadline means waiting forever.
+    fn wait_until(&self, deadline: Option<Instant>) -> Option<&T> {
+        loop {
+            let state = self.state.load(Ordering::Acquire);
+            if state == COMPLETE {
+                return self.get();
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return None;
+            }
+            crate::futex::wait(&self.state, state, deadline);
+        }
+    }
+
+    fn finish(&self, state: u32) {
+        // Release: publishes the value to whoever sees COMPLETE.
+        self.state.store(state, Ordering::Release);
+        crate::futex::wake_all(&self.state);
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    /// Takes the value out, leaving the cell unset.
+    pub fn take(&mut self) -> Option<T> {
+        if *self.state.get_mut() != COMPLETE {
+            return None;
+        }
+        *self.state.get_mut() = INCOMPLETE;
+        // Safety: it was complete, and now isn't, so it won't be read or dropped again.
+        Some(unsafe { self.value.get_mut().assume_init_read() })
+    }
+
+    /// Takes the value out through a shared reference, for test fixtures that set up a
+    /// global `OnceLock` afresh for each case. Returns `None` if it isn't set, or is being
+    /// set right now.
+    ///
+    /// # Safety
+    ///
+    /// Nothing borrowed from the cell, by [`get`](Self::get), [`wait`](Self::wait) or
+    /// anything else, is still alive, nor will be used again.
+    pub unsafe fn reset(&self) -> Option<T> {
+        // Acquire: the value we take is the one that was published. Through RUNNING, so a
+        // get meanwhile finds it unset.
+        self.state
+            .compare_exchange(COMPLETE, RUNNING, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        let value = (*self.value.get()).assume_init_read();
+        self.finish(INCOMPLETE);
+        Some(value)
+    }
+}
+
+// Makes the cell unset again, for an initializer that panicked or failed.
+struct Reset<'a>(&'a AtomicU32);
+
+impl Drop for Reset<'_> {
+    fn drop(&mut self) {
+        self.0.store(INCOMPLETE, Ordering::Release);
+        crate::futex::wake_all(self.0);
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for OnceLock<T> {
+    fn from(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(COMPLETE),
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceLock").field(value).finish(),
+            None => f.write_str("OnceLock(<unset>)"),
+        }
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn once_lock_waits_for_its_value() {
+    static CONFIG: OnceLock<String> = OnceLock::new();
+
+    assert_eq!(CONFIG.wait_timeout(Duration::from_millis(10)), None);
+    let readers = if cfg!(miri) { 2 } else { 8 };
+    std::thread::scope(|s| {
+        for _ in 0..readers {
+            s.spawn(|| assert_eq!(CONFIG.wait(), "loaded"));
+        }
+        std::thread::yield_now();
+        assert_eq!(CONFIG.set("loaded".to_string()), Ok(()));
+    });
+    assert_eq!(CONFIG.set("again".to_string()), Err("again".to_string()));
+    assert_eq!(CONFIG.get_or_init(|| unreachable!()), "loaded");
+    // Safety: no borrow of CONFIG outlives this point.
+    assert_eq!(unsafe { CONFIG.reset() }, Some("loaded".to_string()));
+    assert_eq!(CONFIG.get(), None);
+
+    // Racing sets: one value goes in, and every other comes back to the thread that set it.
+    let rejected = std::thread::scope(|s| {
+        let sets: Vec<_> = (0..4)
+            .map(|i| s.spawn(move || CONFIG.set(i.to_string()).err()))
+            .collect();
+        sets.into_iter()
+            .filter_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(rejected.len(), 3);
+    assert!(!rejected.iter().any(|v| v == CONFIG.get().unwrap()));
+    let mut cell = OnceLock::from(5);
+    assert_eq!((cell.take(), cell.take()), (Some(5), None));
+
+    // A panicking initializer leaves it for the next one.
+    let cell = OnceLock::new();
+    let panicked = std::panic::catch_unwind(|| cell.get_or_init(|| panic!("no")));
+    assert!(panicked.is_err() && cell.get().is_none());
+    assert_eq!(*cell.get_or_init(|| 1), 1);
+    assert_eq!(cell.into_inner(), Some(1));
+
+    // So does a failing one, and its error comes back.
+    let cell = OnceLock::new();
+    assert_eq!(cell.get_or_try_init(|| Err("no")), Err("no"));
+    assert_eq!(cell.get_or_try_init(|| Ok::<_, &str>(2)), Ok(&2));
+}
@@ -0,0 +1,229 @@
+use crate::atomic_option::AtomicOption;
+use crate::parker::{Parker, Unparker};
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SENT: u8 = 1;
+const SENDER_GONE: u8 = 2;
+const RECEIVER_GONE: u8 = 4;
+
+struct Shared<T> {
+    value: AtomicOption<T>,
+    state: AtomicU8,
+}
+
+/// The sending half of a [`channel`]; `send` consumes it, so at most one value goes through.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    unparker: Unparker,
+}
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    parker: Parker,
+}
+
+/// The sender was dropped without sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing has been sent yet.
+    Empty,
+    /// The sender was dropped without sending.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// Nothing was sent before the deadline.
+    Timeout,
+    /// The sender was dropped without sending.
+    Disconnected,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("oneshot sender dropped without sending")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("oneshot channel is empty"),
+            Self::Disconnected => RecvError.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timed out waiting on a oneshot channel"),
+            Self::Disconnected => RecvError.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
+/// A channel for a single value: the value sits in an [`AtomicOption`] and the receiver
+/// sleeps on a [`Parker`] until the sender fills it or goes away.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: AtomicOption::none(),
+        state: AtomicU8::new(0),
+    });
+    let parker = Parker::new();
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+        unparker: parker.unparker(),
+    };
+    (sender, Receiver { shared, parker })
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, or hands it back if the receiver is already gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.shared.state.load(Ordering::Relaxed) & RECEIVER_GONE != 0 {
+            return Err(value);
+        }
+        self.shared
+            .value
+            .try_put(value)
+            .unwrap_or_else(|_| unreachable!("only one send per channel"));
+        // AcqRel: the RMW orders us against the receiver's drop; whichever comes second
+        // knows the other happened.
+        let state = self.shared.state.fetch_or(SENT, Ordering::AcqRel);
+        if state & RECEIVER_GONE != 0 {
+            // The receiver left after our first check. If it didn't take the value on its
+            // way out, it never will.
+            if let Some(value) = self.shared.value.take() {
+                return Err(value);
+            }
+        }
+        Ok(())
+        // Drop wakes the receiver.
+    }
+
+    /// Whether the receiver has been dropped, so a send would fail.
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.load(Ordering::Relaxed) & RECEIVER_GONE != 0
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release: a sent value is visible to a receiver that sees the flag.
+        self.shared.state.fetch_or(SENDER_GONE, Ordering::Release);
+        self.unparker.unpark();
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.shared.value.take() {
+            return Ok(value);
+        }
+        if self.shared.state.load(Ordering::Acquire) & SENDER_GONE == 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // The sender may have sent just before it dropped.
+        self.shared.value.take().ok_or(TryRecvError::Disconnected)
+    }
+
+    /// Waits for the value, failing once the sender is dropped without sending.
+    pub fn recv(mut self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`, leaving the receiver usable.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`, leaving the receiver usable.
+    pub fn recv_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            // The sender unparks on drop, which comes after its send.
+            match deadline {
+                None => self.parker.park(),
+                Some(deadline) if Instant::now() < deadline => self.parker.park_deadline(deadline),
+                Some(_) => return Err(RecvTimeoutError::Timeout),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let state = self.shared.state.fetch_or(RECEIVER_GONE, Ordering::AcqRel);
+        if state & SENT != 0 {
+            // Drop an unreceived value now rather than whenever the sender's Arc goes.
+            drop(self.shared.value.take());
+        }
+    }
+}
+
+#[test]
+fn oneshot_delivers_once() {
+    let (tx, rx) = channel();
+    let t = std::thread::spawn(move || tx.send(String::from("hi")).unwrap());
+    assert_eq!(rx.recv().as_deref(), Ok("hi"));
+    t.join().unwrap();
+
+    let (tx, mut rx) = channel::<u32>();
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    tx.send(7).unwrap();
+    assert_eq!(rx.try_recv(), Ok(7));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (tx, rx) = channel::<u32>();
+    drop(rx);
+    assert!(tx.is_closed());
+    assert_eq!(tx.send(1), Err(1));
+}
+
+#[test]
+fn oneshot_sender_dropped() {
+    let (tx, rx) = channel::<u32>();
+    let t = std::thread::spawn(move || drop(tx));
+    assert_eq!(rx.recv(), Err(RecvError));
+    t.join().unwrap();
+}
+
+#[test]
+fn oneshot_recv_timeout() {
+    let (tx, mut rx) = channel::<u32>();
+    let timeout = Duration::from_millis(10);
+    assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+    let t = std::thread::spawn(move || tx.send(3).unwrap());
+    assert_eq!(
+        rx.recv_deadline(Instant::now() + Duration::from_secs(10)),
+        Ok(3)
+    );
+    t.join().unwrap();
+    assert_eq!(
+        rx.recv_timeout(timeout),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
@@ -0,0 +1,205 @@
+#[cfg(any(loom, shuttle))]
+use crate::sync_shim::atomic::{AtomicU32, Ordering};
+#[cfg(any(loom, shuttle))]
+use crate::sync_shim::{Condvar, Mutex};
+use std::cell::Cell;
+use std::marker::PhantomData;
+#[cfg(not(any(loom, shuttle)))]
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use std::time::Instant;
+
+// A parked thread waits on the state word through `futex`. Loom and shuttle can't see a
+// futex wait, so under them it sleeps on a Mutex and Condvar of theirs instead.
+
+const EMPTY: u32 = 0;
+const PARKED: u32 = 1;
+const NOTIFIED: u32 = 2;
+
+struct Inner {
+    state: AtomicU32,
+    #[cfg(any(loom, shuttle))]
+    lock: Mutex<()>,
+    #[cfg(any(loom, shuttle))]
+    condvar: Condvar,
+}
+
+/// Blocks a thread until its [`Unparker`] is called, like `thread::park` but for a handle
+/// that isn't tied to one particular thread.
+///
+/// An unpark that comes before the park isn't lost: it leaves a token that the next `park`
+/// consumes right away.
+pub struct Parker {
+    inner: Arc<Inner>,
+    // Only one thread may park on it at a time.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// Wakes the thread parked on the matching [`Parker`].
+#[derive(Clone)]
+pub struct Unparker {
+    inner: Arc<Inner>,
+}
+
+impl Parker {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: AtomicU32::new(EMPTY),
+                #[cfg(any(loom, shuttle))]
+                lock: Mutex::new(()),
+                #[cfg(any(loom, shuttle))]
+                condvar: Condvar::new(),
+            }),
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn unparker(&self) -> Unparker {
+        Unparker {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Blocks until unparked, or returns right away if a token is waiting.
+    pub fn park(&self) {
+        self.park_until(None);
+    }
+
+    /// Like [`park`](Self::park), but gives up at `deadline`.
+    pub fn park_deadline(&self, deadline: Instant) {
+        self.park_until(Some(deadline));
+    }
+
+    fn park_until(&self, deadline: Option<Instant>) {
+        let inner = &*self.inner;
+        // Acquire: whatever the unparker did before unparking is visible once we return.
+        if inner
+            .state
+            .compare_exchange(NOTIFIED, EMPTY, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        if let Err(state) =
+            inner
+                .state
+                .compare_exchange(EMPTY, PARKED, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            debug_assert_eq!(state, NOTIFIED);
+            inner.state.swap(EMPTY, Ordering::Acquire);
+            return;
+        }
+        // Under the `tracing` feature, the span lasts as long as the thread sleeps.
+        #[cfg(feature = "tracing")]
+        let _parked = tracing::trace_span!("park", deadline = ?deadline).entered();
+        loop {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                // An unpark may have just landed; take its token either way.
+                inner.state.swap(EMPTY, Ordering::Acquire);
+                return;
+            }
+            inner.sleep(deadline);
+            // Waits return spuriously; only a NOTIFIED state counts.
+            if inner
+                .state
+                .compare_exchange(NOTIFIED, EMPTY, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Unparker {
+    pub fn unpark(&self) {
+        self.inner.unpark();
+    }
+
+    /// A [`Waker`] that unparks, for blocking on a future or handing to anything that wakes tasks.
+    pub fn waker(&self) -> Waker {
+        Waker::from(Arc::clone(&self.inner))
+    }
+}
+
+impl Inner {
+    fn unpark(&self) {
+        // Release: pairs with park's Acquire.
+        if self.state.swap(NOTIFIED, Ordering::Release) == PARKED {
+            #[cfg(feature = "tracing")]
+            tracing::trace!("unpark");
+            self.wake();
+        }
+    }
+
+    // Sleeps while the state is PARKED, as far as `deadline`; it may return early.
+    #[cfg(not(any(loom, shuttle)))]
+    fn sleep(&self, deadline: Option<Instant>) {
+        crate::futex::wait(&self.state, PARKED, deadline);
+    }
+
+    #[cfg(not(any(loom, shuttle)))]
+    fn wake(&self) {
+        crate::futex::wake_one(&self.state);
+    }
+
+    #[cfg(any(loom, shuttle))]
+    fn sleep(&self, deadline: Option<Instant>) {
+        let lock = self.lock.lock().unwrap();
+        // Under the lock, which wake takes after the state changes.
+        if self.state.load(Ordering::Relaxed) != PARKED {
+            return;
+        }
+        match deadline {
+            None => drop(self.condvar.wait(lock).unwrap()),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now < deadline {
+                    drop(self.condvar.wait_timeout(lock, deadline - now).unwrap());
+                }
+            }
+        }
+    }
+
+    #[cfg(any(loom, shuttle))]
+    fn wake(&self) {
+        // Taking the lock means the parker is inside the condvar wait by now, or will see
+        // the state change before it gets there.
+        drop(self.lock.lock().unwrap());
+        self.condvar.notify_one();
+    }
+}
+
+impl Wake for Inner {
+    fn wake(self: Arc<Self>) {
+        self.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.unpark();
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn parker_token_is_not_lost() {
+    let p = Parker::new();
+    let u = p.unparker();
+    // A token left by an earlier unpark makes park return immediately.
+    u.unpark();
+    p.park();
+    let t = std::thread::spawn(move || {
+        std::thread::yield_now();
+        u.unpark();
+    });
+    p.park();
+    t.join().unwrap();
+}
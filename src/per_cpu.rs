@@ -0,0 +1,93 @@
+use crate::affinity;
+use crate::cache_padded::CachePadded;
+use crate::thread_id;
+use crate::topology;
+
+/// One cache-padded `T` per CPU, with [`get`](Self::get) picking the slot of the CPU the
+/// calling thread is running on.
+///
+/// Threads on different CPUs touch different slots, so a counter or freelist split this way
+/// scales with the number of cores however many threads share them. The thread can be moved
+/// to another CPU right after `get` returns, so two threads can still end up in the same
+/// slot: `T` has to be safe to share, and a slot is only a hint about who will use it.
+///
+/// On Linux the CPU comes from `sched_getcpu`, which recent glibc answers with a load from
+/// the thread's restartable-sequence area instead of a system call. Where the OS can't say
+/// which CPU a thread is on, threads are spread by [`thread_id`] instead.
+pub struct PerCpu<T> {
+    slots: Box<[CachePadded<T>]>,
+}
+
+impl<T> PerCpu<T> {
+    /// One slot for each CPU the process may run on, each made by `init`.
+    pub fn new(init: impl FnMut() -> T) -> Self {
+        let cpus = affinity::cores().iter().max().map_or(0, |&c| c + 1);
+        let parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_slots(cpus.max(parallelism), init)
+    }
+
+    /// `slots` slots, each made by `init`. CPUs past the last slot wrap around.
+    pub fn with_slots(slots: usize, mut init: impl FnMut() -> T) -> Self {
+        Self {
+            slots: (0..slots.max(1))
+                .map(|_| CachePadded::new(init()))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The current CPU's slot.
+    pub fn get(&self) -> &T {
+        let index = topology::current_cpu()
+            .or_else(thread_id::current)
+            .unwrap_or(0);
+        &self.slots[index % self.slots.len()]
+    }
+
+    /// Every slot, as for summing a counter.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().map(|s| &**s)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().map(|s| &mut **s)
+    }
+}
+
+impl<T: Default> Default for PerCpu<T> {
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+#[test]
+fn per_cpu_counter_sums() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let counter = PerCpu::<AtomicUsize>::default();
+    assert!(counter.len() >= affinity::cores().len());
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    counter.get().fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    let total: usize = counter.iter().map(|c| c.load(Ordering::Relaxed)).sum();
+    assert_eq!(total, 4000);
+
+    let mut small = PerCpu::with_slots(1, || 0);
+    for slot in small.iter_mut() {
+        *slot += 1;
+    }
+    assert_eq!(*small.get(), 1);
+}
@@ -0,0 +1,297 @@
+use std::io;
+
+// Hardware performance counters, for comparing designs by what they cost the caches and the
+// branch predictor rather than by wall time alone: two locks with the same throughput on an
+// idle machine can differ by several times in cache misses, and that's what decides which
+// slows down first when the rest of the program competes for the caches.
+//
+// On Linux this is perf_event_open, counting for the process's threads, including those
+// started after the counters are, in user space only so that it works at the default
+// perf_event_paranoid. Elsewhere, and in VMs and containers without a PMU to count with,
+// `Counters::new` fails and the callers go without.
+
+/// What a [`Counters`] can count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Cycles,
+    Instructions,
+    CacheReferences,
+    CacheMisses,
+    BranchMisses,
+    /// Nanoseconds on a CPU, which the kernel counts itself, so it's there even without a
+    /// PMU. Against wall time, it tells spinning from sleeping.
+    CpuTime,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Cycles => "cycles",
+            Event::Instructions => "instructions",
+            Event::CacheReferences => "cache references",
+            Event::CacheMisses => "cache misses",
+            Event::BranchMisses => "branch misses",
+            Event::CpuTime => "ns of CPU",
+        }
+    }
+}
+
+/// The counters [`Counters::standard`] opens.
+pub const STANDARD: &[Event] = &[
+    Event::Cycles,
+    Event::Instructions,
+    Event::CacheMisses,
+    Event::BranchMisses,
+];
+
+/// A set of counters, each counting one [`Event`] across the process. They start stopped.
+pub struct Counters {
+    counters: Vec<(Event, sys::Counter)>,
+}
+
+impl Counters {
+    /// Fails if any of `events` can't be counted here.
+    pub fn new(events: &[Event]) -> io::Result<Self> {
+        let counters = events
+            .iter()
+            .map(|&e| Ok((e, sys::Counter::open(e)?)))
+            .collect::<io::Result<_>>()?;
+        Ok(Self { counters })
+    }
+
+    /// Cycles, instructions, cache misses and branch misses.
+    pub fn standard() -> io::Result<Self> {
+        Self::new(STANDARD)
+    }
+
+    /// Zeroes the counts and starts counting.
+    pub fn start(&self) {
+        for (_, c) in &self.counters {
+            c.reset();
+            c.enable();
+        }
+    }
+
+    /// Stops counting and gives the counts since [`start`](Self::start).
+    pub fn stop(&self) -> Vec<(Event, u64)> {
+        for (_, c) in &self.counters {
+            c.disable();
+        }
+        self.counters.iter().map(|(e, c)| (*e, c.read())).collect()
+    }
+
+    /// Counts what `f` does, on this thread and any it starts.
+    pub fn measure<R>(&self, f: impl FnOnce() -> R) -> (R, Vec<(Event, u64)>) {
+        self.start();
+        let r = f();
+        (r, self.stop())
+    }
+}
+
+/// Counts shared out over `ops` operations, as "name N per op" joined by commas.
+pub fn per_op(counts: &[(Event, u64)], ops: u64) -> String {
+    counts
+        .iter()
+        .map(|(e, n)| format!("{:.2} {} per op", *n as f64 / ops.max(1) as f64, e.name()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+mod sys {
+    use super::Event;
+    use std::io;
+    use std::os::raw::{c_int, c_long, c_ulong, c_void};
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 298;
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const SYS_PERF_EVENT_OPEN: c_long = 241;
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_TYPE_SOFTWARE: u32 = 1;
+    const PERF_FLAG_FD_CLOEXEC: c_ulong = 1 << 3;
+    const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: c_ulong = 0x2401;
+    const PERF_EVENT_IOC_RESET: c_ulong = 0x2403;
+
+    // The bits of perf_event_attr's flags word this uses.
+    const DISABLED: u64 = 1 << 0;
+    const INHERIT: u64 = 1 << 1;
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+
+    // The first version of perf_event_attr; the kernel takes the size and zeroes the rest.
+    #[repr(C)]
+    struct Attr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        bp_addr: u64,
+    }
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+        fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize;
+        fn close(fd: c_int) -> c_int;
+    }
+
+    pub struct Counter(c_int);
+
+    impl Counter {
+        pub fn open(event: Event) -> io::Result<Self> {
+            let (kind, config) = match event {
+                Event::Cycles => (PERF_TYPE_HARDWARE, 0),
+                Event::Instructions => (PERF_TYPE_HARDWARE, 1),
+                Event::CacheReferences => (PERF_TYPE_HARDWARE, 2),
+                Event::CacheMisses => (PERF_TYPE_HARDWARE, 3),
+                Event::BranchMisses => (PERF_TYPE_HARDWARE, 5),
+                // The task clock.
+                Event::CpuTime => (PERF_TYPE_SOFTWARE, 1),
+            };
+            let attr = Attr {
+                kind,
+                size: std::mem::size_of::<Attr>() as u32,
+                config,
+                sample_period: 0,
+                sample_type: 0,
+                read_format: 0,
+                flags: DISABLED | INHERIT | EXCLUDE_KERNEL | EXCLUDE_HV,
+                wakeup_events: 0,
+                bp_type: 0,
+                bp_addr: 0,
+            };
+            // This process, on any CPU, in no group.
+            let fd = unsafe {
+                syscall(
+                    SYS_PERF_EVENT_OPEN,
+                    &attr as *const Attr,
+                    0 as c_int,
+                    -1 as c_int,
+                    -1 as c_int,
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(fd as c_int))
+        }
+
+        fn ioctl(&self, request: c_ulong) {
+            // Can't fail on a counter that opened.
+            unsafe { ioctl(self.0, request, 0 as c_ulong) };
+        }
+
+        pub fn enable(&self) {
+            self.ioctl(PERF_EVENT_IOC_ENABLE);
+        }
+
+        pub fn disable(&self) {
+            self.ioctl(PERF_EVENT_IOC_DISABLE);
+        }
+
+        pub fn reset(&self) {
+            self.ioctl(PERF_EVENT_IOC_RESET);
+        }
+
+        pub fn read(&self) -> u64 {
+            let mut n = 0u64;
+            let r = unsafe { read(self.0, &mut n as *mut u64 as *mut c_void, 8) };
+            assert!(r == 8, "reading a counter: {}", io::Error::last_os_error());
+            n
+        }
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            unsafe { close(self.0) };
+        }
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+)))]
+mod sys {
+    use super::Event;
+    use std::io;
+
+    pub enum Counter {}
+
+    impl Counter {
+        pub fn open(_: Event) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "performance counters are only counted on Linux",
+            ))
+        }
+
+        pub fn enable(&self) {
+            match *self {}
+        }
+
+        pub fn disable(&self) {
+            match *self {}
+        }
+
+        pub fn reset(&self) {
+            match *self {}
+        }
+
+        pub fn read(&self) -> u64 {
+            match *self {}
+        }
+    }
+}
+
+// The PMU may not be there to count instructions, but the kernel always counts CPU time.
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "makes system calls Miri doesn't support")]
+fn perf_counts_a_spawned_threads_work() {
+    if !cfg!(target_os = "linux") {
+        assert!(Counters::new(&[Event::CpuTime]).is_err());
+        return;
+    }
+    let events = match Counters::new(&[Event::Instructions]) {
+        Ok(_) => vec![Event::CpuTime, Event::Instructions],
+        Err(_) => vec![Event::CpuTime],
+    };
+    let counters = Counters::new(&events).unwrap();
+    let ((), counts) = counters.measure(|| {
+        std::thread::spawn(|| {
+            let mut x = 0u64;
+            for i in 0..1_000_000 {
+                x = std::hint::black_box(x.wrapping_add(i));
+            }
+        })
+        .join()
+        .unwrap()
+    });
+    assert_eq!(counts.iter().map(|c| c.0).collect::<Vec<_>>(), events);
+    assert!(counts[0].1 > 0, "{:?}", counts);
+    if let Some(&(_, instructions)) = counts.get(1) {
+        assert!(instructions >= 1_000_000, "{:?}", counts);
+    }
+    assert!(per_op(&counts, 1_000_000).contains(" ns of CPU per op"));
+}
@@ -0,0 +1,147 @@
+use crate::blocking_queue::Closed;
+use crate::stack::TreiberStack;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A lock-free pool of reusable objects, such as buffers on a hot path.
+///
+/// [`get`](Pool::get) hands out an idle object, or makes a new one if there is none, and the
+/// returned [`Pooled`] puts it back when it's dropped. Objects go back as they are; clearing
+/// them is up to the caller.
+///
+/// [`close`](Pool::close) is for shutdown: the idle objects are dropped, so is each one
+/// handed out as it comes back, and nothing more is handed out.
+pub struct Pool<T> {
+    idle: TreiberStack<T>,
+    create: Box<dyn Fn() -> T + Send + Sync>,
+    closed: AtomicBool,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    pub fn new(create: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Self {
+            idle: TreiberStack::new(),
+            create: Box::new(create),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If the pool was closed; see [`try_get`](Self::try_get).
+    pub fn get(&self) -> Pooled<'_, T> {
+        self.try_get().expect("get on a closed pool")
+    }
+
+    /// Like [`get`](Self::get), but fails once the pool is closed.
+    pub fn try_get(&self) -> Result<Pooled<'_, T>, Closed> {
+        if self.is_closed() {
+            return Err(Closed(()));
+        }
+        let value = self.idle.pop().unwrap_or_else(|| (self.create)());
+        Ok(Pooled {
+            pool: self,
+            value: ManuallyDrop::new(value),
+        })
+    }
+
+    /// Closes the pool, dropping the idle objects now and the others as they're returned.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.idle.drain().for_each(drop);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// An object borrowed from a [`Pool`], returned to it on drop.
+pub struct Pooled<'a, T: Send + 'static> {
+    pool: &'a Pool<T>,
+    value: ManuallyDrop<T>,
+}
+
+impl<T: Send + 'static> Pooled<'_, T> {
+    /// Takes the object out of the pool for good.
+    pub fn detach(mut self) -> T {
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        std::mem::forget(self);
+        value
+    }
+}
+
+impl<T: Send + 'static> Deref for Pooled<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Send + 'static> DerefMut for Pooled<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Send + 'static> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        if self.pool.is_closed() {
+            return;
+        }
+        self.pool.idle.push(value);
+        // SeqCst, with close's: either close's drain finds it, or we see the close here.
+        if self.pool.is_closed() {
+            self.pool.idle.drain().for_each(drop);
+        }
+    }
+}
+
+#[test]
+fn pool_reuses_objects() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let pool = &Pool::new(|| {
+        CREATED.fetch_add(1, Ordering::Relaxed);
+        Vec::<u8>::with_capacity(64)
+    });
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for _ in 0..n {
+                    let mut buf = pool.get();
+                    buf.clear();
+                    buf.push(t);
+                    assert_eq!(*buf, [t]);
+                }
+            });
+        }
+    });
+    // Never more objects than threads using them at once.
+    assert!(CREATED.load(Ordering::Relaxed) <= 4);
+    let buf = pool.get().detach();
+    assert!(buf.capacity() >= 64);
+}
+
+#[test]
+fn pool_close_drops_objects() {
+    use std::sync::Arc;
+    let item = Arc::new(());
+    let pool = Pool::new({
+        let item = Arc::clone(&item);
+        move || Arc::clone(&item)
+    });
+    let (a, b) = (pool.get(), pool.get());
+    drop(a);
+    assert_eq!(Arc::strong_count(&item), 4);
+    pool.close();
+    assert!(pool.is_closed() && pool.try_get().is_err());
+    // The idle one went at the close, and the one out goes as it comes back.
+    assert_eq!(Arc::strong_count(&item), 3);
+    drop(b);
+    assert_eq!(Arc::strong_count(&item), 2);
+}
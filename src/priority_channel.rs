@@ -0,0 +1,230 @@
+use crate::event_count::EventCount;
+use crate::priority_queue::PriorityQueue;
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+struct Shared<T, P> {
+    // Reversed so that `pop_min` takes the highest priority, oldest first among equals.
+    queue: PriorityQueue<T, Reverse<P>>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    ready: EventCount,
+}
+
+/// The sending half of a priority [`channel`]; clone it for more producers.
+pub struct Sender<T, P> {
+    shared: Arc<Shared<T, P>>,
+}
+
+/// The receiving half of a priority [`channel`]; clone it for more consumers.
+pub struct Receiver<T, P> {
+    shared: Arc<Shared<T, P>>,
+}
+
+/// An unbounded channel on the lock-free [`PriorityQueue`] that hands out the
+/// highest-priority pending message instead of the oldest one.
+///
+/// Messages of equal priority come out in the order they were sent.
+pub fn channel<T, P>() -> (Sender<T, P>, Receiver<T, P>)
+where
+    T: Send + Sync + 'static,
+    P: Ord + Clone + Send + Sync + 'static,
+{
+    let shared = Arc::new(Shared {
+        queue: PriorityQueue::new(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        ready: EventCount::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared })
+}
+
+impl<T, P> Sender<T, P>
+where
+    T: Send + Sync + 'static,
+    P: Ord + Clone + Send + Sync + 'static,
+{
+    /// Queues `value` at `priority`, or hands it back if every receiver is gone.
+    pub fn send(&self, value: T, priority: P) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::Relaxed) == 0 {
+            return Err(SendError(value));
+        }
+        self.shared.queue.push(value, Reverse(priority));
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T, P> Sender<T, P> {
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+impl<T, P> Receiver<T, P>
+where
+    T: Send + Sync + 'static,
+    P: Ord + Clone + Send + Sync + 'static,
+{
+    fn pop(&self) -> Option<(P, T)> {
+        let (Reverse(priority), value) = self.shared.queue.pop_min()?;
+        Some((priority, value))
+    }
+
+    /// Takes the highest-priority message queued right now, along with its priority.
+    pub fn try_recv(&self) -> Result<(P, T), TryRecvError> {
+        if let Some(message) = self.pop() {
+            return Ok(message);
+        }
+        if self.shared.senders.load(Ordering::SeqCst) != 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // The last sender may have sent just before it dropped.
+        self.pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    /// Waits for a message, failing once every sender is gone and the channel is drained.
+    pub fn recv(&self) -> Result<(P, T), RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(P, T), RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up at `deadline`.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<(P, T), RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    // No deadline means waiting forever.
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<(P, T), RecvTimeoutError> {
+        let ready = &self.shared.ready;
+        loop {
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let key = ready.prepare_wait();
+            match self.try_recv() {
+                Ok(message) => {
+                    ready.cancel_wait(key);
+                    return Ok(message);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    ready.cancel_wait(key);
+                    return Err(RecvTimeoutError::Disconnected);
+                }
+                Err(TryRecvError::Empty) => match deadline {
+                    None => ready.wait(key),
+                    Some(deadline) => {
+                        ready.wait_deadline(key, deadline);
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<T, P> Receiver<T, P> {
+    pub fn len(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+}
+
+impl<T, P> Clone for Sender<T, P> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T, P> Clone for Receiver<T, P> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T, P> Drop for Sender<T, P> {
+    fn drop(&mut self) {
+        // SeqCst: our sends happen before a receiver that sees the count hit zero, and the
+        // count is re-checked after registering to wait.
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+impl<T, P> Drop for Receiver<T, P> {
+    fn drop(&mut self) {
+        // Messages still queued are dropped along with the queue.
+        self.shared.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn priority_channel_jumps_the_queue() {
+    let (tx, rx) = channel();
+    tx.send("low", 1).unwrap();
+    tx.send("urgent", 9).unwrap();
+    tx.send("normal a", 5).unwrap();
+    tx.send("normal b", 5).unwrap();
+    assert_eq!(rx.len(), 4);
+    assert_eq!(rx.try_recv(), Ok((9, "urgent")));
+    assert_eq!(rx.recv(), Ok((5, "normal a")));
+    assert_eq!(rx.recv(), Ok((5, "normal b")));
+    drop(tx);
+    assert_eq!(rx.recv(), Ok((1, "low")));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (tx, rx) = channel::<u32, u8>();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    drop(rx);
+    assert_eq!(tx.send(0, 0), Err(SendError(0)));
+}
+
+#[test]
+fn priority_channel_wakes_receivers() {
+    let (tx, rx) = channel();
+    let receivers: Vec<_> = (0..2)
+        .map(|_| {
+            let rx = rx.clone();
+            std::thread::spawn(move || std::iter::from_fn(|| rx.recv().ok()).count())
+        })
+        .collect();
+    drop(rx);
+    for i in 0..100u32 {
+        tx.send(i, i % 7).unwrap();
+    }
+    drop(tx);
+    let total: usize = receivers.into_iter().map(|r| r.join().unwrap()).sum();
+    assert_eq!(total, 100);
+}
@@ -0,0 +1,133 @@
+use crate::skiplist::{Entry, SkipMap};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+// Lets the popper that wins an entry move the item out while the node itself stays alive for
+// other threads' cursors.
+struct Item<T>(AtomicPtr<T>);
+
+impl<T> Drop for Item<T> {
+    fn drop(&mut self) {
+        let item = *self.0.get_mut();
+        if !item.is_null() {
+            drop(unsafe { Box::from_raw(item) });
+        }
+    }
+}
+
+/// A lock-free priority queue built on [`SkipMap`].
+///
+/// Items are keyed by their priority and an insertion counter, so equal priorities pop in
+/// FIFO order from `pop_min` (and LIFO from `pop_max`).
+pub struct PriorityQueue<T, P> {
+    map: SkipMap<(P, u64), Item<T>>,
+    seq: AtomicU64,
+}
+
+impl<T, P> PriorityQueue<T, P> {
+    pub fn new() -> Self {
+        Self {
+            map: SkipMap::new(),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<T, P> PriorityQueue<T, P>
+where
+    T: Send + Sync + 'static,
+    P: Ord + Clone + Send + Sync + 'static,
+{
+    pub fn push(&self, item: T, priority: P) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let item = Item(AtomicPtr::new(Box::into_raw(Box::new(item))));
+        self.map.insert((priority, seq), item);
+    }
+
+    // Claims the entry `end` points at by removing it, retrying from the new end if another
+    // popper got there first.
+    fn pop_end<'a>(
+        &'a self,
+        end: impl Fn(&'a SkipMap<(P, u64), Item<T>>) -> Option<Entry<'a, (P, u64), Item<T>>>,
+    ) -> Option<(P, T)> {
+        loop {
+            let entry = end(&self.map)?;
+            if self.map.remove(entry.key()) {
+                // Removing it made us the only one who'll ever take the item.
+                let item = entry.value().0.swap(ptr::null_mut(), Ordering::Relaxed);
+                return Some((entry.key().0.clone(), *unsafe { Box::from_raw(item) }));
+            }
+        }
+    }
+
+    /// Removes the item with the lowest priority.
+    pub fn pop_min(&self) -> Option<(P, T)> {
+        self.pop_end(SkipMap::front)
+    }
+
+    /// Removes the item with the highest priority.
+    pub fn pop_max(&self) -> Option<(P, T)> {
+        self.pop_end(SkipMap::back)
+    }
+}
+
+impl<T, P> Default for PriorityQueue<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn priority_queue_order() {
+    let q = PriorityQueue::new();
+    q.push("b1", 2);
+    q.push("a", 1);
+    q.push("c", 3);
+    q.push("b2", 2);
+    assert_eq!(q.pop_max(), Some((3, "c")));
+    assert_eq!(q.pop_min(), Some((1, "a")));
+    assert_eq!(q.pop_min(), Some((2, "b1")));
+    assert_eq!(q.len(), 1);
+    assert_eq!(q.pop_max(), Some((2, "b2")));
+    assert_eq!(q.pop_min(), None);
+}
+
+#[test]
+fn priority_queue_concurrent() {
+    use std::sync::atomic::AtomicBool;
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let q = &PriorityQueue::new();
+    let seen = &(0..4 * n)
+        .map(|_| AtomicBool::new(false))
+        .collect::<Vec<_>>();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    let v = i * 4 + t;
+                    q.push(v, v % 97);
+                    if i % 2 == 0 {
+                        let (p, v) = if t % 2 == 0 { q.pop_min() } else { q.pop_max() }.unwrap();
+                        assert_eq!(p, v % 97);
+                        assert!(!seen[v].swap(true, Ordering::Relaxed));
+                    }
+                }
+            });
+        }
+    });
+    let mut last = 0;
+    while let Some((p, v)) = q.pop_min() {
+        assert!(p >= last);
+        last = p;
+        assert!(!seen[v].swap(true, Ordering::Relaxed));
+    }
+    assert!(seen.iter().all(|s| s.load(Ordering::Relaxed)));
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::panic::Location;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Where the time waiting for locks goes, by the line that took each one. `rwlock::RwLock`
+// records every acquisition that had to wait under the `profile` feature, with its caller
+// from `#[track_caller]`, into a histogram per call site; `report` ranks the sites by their
+// total wait.
+//
+// The histograms are HDR histograms: buckets of equal width within each power of two, so a
+// recorded wait is known to within a sixteenth of itself from nanoseconds to centuries in under
+// a thousand counters. Recording takes a global mutex, which is fine for finding which lock
+// hurts but adds contention of its own.
+
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BITS;
+
+/// Counts of nanosecond values, each kept to within 1/16 of itself. Serializable under the
+/// `serde` feature.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    // Grown as far as the largest value needs.
+    counts: Vec<u64>,
+    count: u64,
+    total: u128,
+    max: u64,
+}
+
+// Values below SUB_BUCKETS get a bucket each; above that, each power of two is split into
+// SUB_BUCKETS / 2 buckets of its width over SUB_BUCKETS / 2.
+fn bucket(v: u64) -> usize {
+    if v < SUB_BUCKETS {
+        return v as usize;
+    }
+    let msb = 63 - v.leading_zeros();
+    let shift = msb + 1 - SUB_BITS;
+    ((u64::from(shift) + 1) * SUB_BUCKETS / 2 + ((v >> shift) - SUB_BUCKETS / 2)) as usize
+}
+
+// The highest value that falls in bucket `i`.
+fn highest(i: usize) -> u64 {
+    let i = i as u64;
+    if i < SUB_BUCKETS {
+        return i;
+    }
+    let shift = i / (SUB_BUCKETS / 2) - 1;
+    let sub = i % (SUB_BUCKETS / 2) + SUB_BUCKETS / 2;
+    ((sub + 1) << shift).wrapping_sub(1)
+}
+
+impl Histogram {
+    pub fn record(&mut self, nanos: u64) {
+        let i = bucket(nanos);
+        if self.counts.len() <= i {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.count += 1;
+        self.total += u128::from(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.total.min(u128::from(u64::MAX)) as u64)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// The `p`th quantile, 0 to 1: the highest value in its bucket, but no more than the
+    /// largest recorded. Zero if nothing has been.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = ((self.count as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_nanos(highest(i).min(self.max));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// A call site's waits, as [`sites`] gives them.
+#[derive(Clone)]
+pub struct Site {
+    pub location: &'static Location<'static>,
+    pub waits: Histogram,
+}
+
+// By file, line and column, since a Location isn't Ord.
+type Key = (&'static str, u32, u32);
+
+static SITES: Mutex<BTreeMap<Key, Site>> = Mutex::new(BTreeMap::new());
+
+fn sites_lock() -> std::sync::MutexGuard<'static, BTreeMap<Key, Site>> {
+    SITES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Puts `waited` down to the acquisition at `site`.
+pub fn record(site: &'static Location<'static>, waited: Duration) {
+    let nanos = waited.as_nanos().min(u128::from(u64::MAX)) as u64;
+    sites_lock()
+        .entry((site.file(), site.line(), site.column()))
+        .or_insert_with(|| Site {
+            location: site,
+            waits: Histogram::default(),
+        })
+        .waits
+        .record(nanos);
+}
+
+/// Every site that has waited, the longest total wait first.
+pub fn sites() -> Vec<Site> {
+    let mut sites: Vec<Site> = sites_lock().values().cloned().collect();
+    sites.sort_by_key(|s| std::cmp::Reverse(s.waits.total));
+    sites
+}
+
+/// Forgets everything recorded so far.
+pub fn reset() {
+    sites_lock().clear();
+}
+
+/// A table, for printing, of the `top` sites with the longest total wait: how often and how
+/// long each waited, and its median, 99th percentile and longest wait.
+pub fn report(top: usize) -> String {
+    let mut out = format!(
+        "{:>12} {:>8} {:>12} {:>12} {:>12}  site\n",
+        "total", "waits", "p50", "p99", "max"
+    );
+    for site in sites().into_iter().take(top) {
+        let w = &site.waits;
+        let _ = writeln!(
+            out,
+            "{:>12} {:>8} {:>12} {:>12} {:>12}  {}",
+            format!("{:.1?}", w.total()),
+            w.count(),
+            format!("{:.1?}", w.percentile(0.5)),
+            format!("{:.1?}", w.percentile(0.99)),
+            format!("{:.1?}", w.max()),
+            site.location
+        );
+    }
+    out
+}
+
+#[test]
+fn profile_histogram_keeps_values_to_a_sixteenth() {
+    for v in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+        let i = bucket(v);
+        assert!(highest(i) >= v && (i == 0 || highest(i - 1) < v), "{}", v);
+        assert!(highest(i) - v <= v / (SUB_BUCKETS / 2), "{}", v);
+    }
+    let mut h = Histogram::default();
+    for v in 1..=1000 {
+        h.record(v * 1000);
+    }
+    let p50 = h.percentile(0.5).as_nanos() as u64;
+    assert!((500_000..=500_000 + 500_000 / 16).contains(&p50), "{}", p50);
+    assert_eq!(h.percentile(1.0), Duration::from_micros(1000));
+    assert_eq!(h.count(), 1000);
+
+    #[cfg(feature = "serde")]
+    {
+        let back: Histogram = serde_json::from_str(&serde_json::to_string(&h).unwrap()).unwrap();
+        assert_eq!((back.count(), back.total()), (h.count(), h.total()));
+        assert_eq!(back.percentile(0.5), h.percentile(0.5));
+    }
+}
+
+#[test]
+fn profile_reports_the_contended_site() {
+    use crate::rwlock::RwLock;
+
+    let lock = RwLock::new(0);
+    let held = lock.write();
+    let started = std::sync::Barrier::new(2);
+    let line = line!() + 4;
+    std::thread::scope(|s| {
+        let writer = s.spawn(|| {
+            started.wait();
+            *lock.write() += 1;
+        });
+        // Long enough for the writer to be waiting by the time the lock's free.
+        started.wait();
+        std::thread::sleep(Duration::from_millis(20));
+        drop(held);
+        writer.join().unwrap();
+    });
+    let site = sites()
+        .into_iter()
+        .find(|s| s.location.file() == file!() && s.location.line() == line)
+        .expect("the blocked write wasn't recorded");
+    assert_eq!(site.waits.count(), 1);
+    assert!(site.waits.max() >= Duration::from_millis(10));
+    assert!(report(usize::MAX).contains(&site.location.to_string()));
+}
@@ -0,0 +1,393 @@
+use std::backtrace::Backtrace;
+use std::cell::{RefCell, UnsafeCell};
+use std::fmt;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Vector clocks: the i-th entry is how far thread i had got, as seen by the clock's owner.
+#[derive(Debug, Clone, Default)]
+struct VClock(Vec<u64>);
+
+impl VClock {
+    fn get(&self, thread: usize) -> u64 {
+        self.0.get(thread).copied().unwrap_or(0)
+    }
+
+    fn join(&mut self, other: &VClock) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (a, &b) in self.0.iter_mut().zip(&other.0) {
+            *a = (*a).max(b);
+        }
+    }
+}
+
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+struct ThreadState {
+    index: usize,
+    clock: VClock,
+    // The clock at the last release fence, which later Relaxed stores publish.
+    released: VClock,
+    // The clocks Relaxed loads have read since, which the next acquire fence takes.
+    pending: VClock,
+}
+
+impl ThreadState {
+    fn new() -> Self {
+        let index = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+        let mut clock = VClock::default();
+        clock.0.resize(index + 1, 0);
+        clock.0[index] = 1;
+        Self {
+            index,
+            clock,
+            released: VClock::default(),
+            pending: VClock::default(),
+        }
+    }
+
+    // Moves on past a release, so the thread's later accesses aren't covered by it.
+    fn tick(&mut self) {
+        self.clock.0[self.index] += 1;
+    }
+
+    fn epoch(&self) -> Epoch {
+        Epoch {
+            thread: self.index,
+            time: self.clock.get(self.index),
+        }
+    }
+}
+
+thread_local! {
+    static THREAD: RefCell<ThreadState> = RefCell::new(ThreadState::new());
+}
+
+fn with_thread<R>(f: impl FnOnce(&mut ThreadState) -> R) -> R {
+    THREAD.with(|t| f(&mut t.borrow_mut()))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Epoch {
+    thread: usize,
+    time: u64,
+}
+
+impl Epoch {
+    fn happens_before(&self, clock: &VClock) -> bool {
+        self.time <= clock.get(self.thread)
+    }
+}
+
+struct Access {
+    epoch: Epoch,
+    write: bool,
+    backtrace: Backtrace,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = if self.write { "write" } else { "read" };
+        write!(
+            f,
+            "{} by thread {} at:\n{}",
+            what, self.epoch.thread, self.backtrace
+        )
+    }
+}
+
+/// Spawns a thread that happens after everything the current thread has done so far, as
+/// `std::thread::spawn` does; the detector only knows about it when it goes through here.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let parent = with_thread(|t| {
+        let clock = t.clock.clone();
+        t.tick();
+        clock
+    });
+    JoinHandle {
+        handle: thread::spawn(move || {
+            with_thread(|t| t.clock.join(&parent));
+            let r = f();
+            (r, with_thread(|t| t.clock.clone()))
+        }),
+    }
+}
+
+/// A thread started with [`spawn`]. Joining it makes everything it did happen before the
+/// joiner's next access.
+pub struct JoinHandle<T> {
+    handle: thread::JoinHandle<(T, VClock)>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn join(self) -> thread::Result<T> {
+        let (r, clock) = self.handle.join()?;
+        with_thread(|t| t.clock.join(&clock));
+        Ok(r)
+    }
+}
+
+/// A fence, as `std::sync::atomic::fence`.
+pub fn fence(order: Ordering) {
+    atomic::fence(order);
+    with_thread(|t| {
+        if matches!(
+            order,
+            Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            let pending = std::mem::take(&mut t.pending);
+            t.clock.join(&pending);
+        }
+        if matches!(
+            order,
+            Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            t.released = t.clock.clone();
+            t.tick();
+        }
+    });
+}
+
+/// An `AtomicU64` that carries the clocks that make its Release/Acquire pairs synchronize.
+///
+/// Each operation takes a lock, so in fact they all run one at a time, but the detector only
+/// counts the synchronization the orderings promise: SeqCst counts as AcqRel, and a Relaxed
+/// load synchronizes with nothing unless an acquire fence follows it.
+pub struct RaceAtomicU64 {
+    // The value, and the clock of the release sequence it's in.
+    state: Mutex<(u64, VClock)>,
+}
+
+impl RaceAtomicU64 {
+    pub fn new(v: u64) -> Self {
+        Self {
+            state: Mutex::new((v, VClock::default())),
+        }
+    }
+
+    fn acquire(t: &mut ThreadState, clock: &VClock, order: Ordering) {
+        if matches!(
+            order,
+            Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            t.clock.join(clock);
+        } else {
+            t.pending.join(clock);
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> u64 {
+        assert!(
+            !matches!(order, Ordering::Release | Ordering::AcqRel),
+            "there is no such thing as a release load"
+        );
+        let state = self.state.lock().unwrap();
+        with_thread(|t| Self::acquire(t, &state.1, order));
+        state.0
+    }
+
+    pub fn store(&self, v: u64, order: Ordering) {
+        assert!(
+            !matches!(order, Ordering::Acquire | Ordering::AcqRel),
+            "there is no such thing as an acquire store"
+        );
+        let mut state = self.state.lock().unwrap();
+        with_thread(|t| {
+            // A store starts a new release sequence: its own clock if it's a release, or
+            // that of the last release fence.
+            state.1 = if matches!(order, Ordering::Release | Ordering::SeqCst) {
+                let clock = t.clock.clone();
+                t.tick();
+                clock
+            } else {
+                t.released.clone()
+            };
+        });
+        state.0 = v;
+    }
+
+    // An RMW continues the release sequence it reads from, adding its own clock if it's a
+    // release. If `f` turns the value down, it's a load with the `failure` ordering.
+    fn rmw(
+        &self,
+        order: Ordering,
+        failure: Ordering,
+        f: impl FnOnce(u64) -> Option<u64>,
+    ) -> Result<u64, u64> {
+        let mut state = self.state.lock().unwrap();
+        let old = state.0;
+        let Some(new) = f(old) else {
+            with_thread(|t| Self::acquire(t, &state.1, failure));
+            return Err(old);
+        };
+        with_thread(|t| {
+            Self::acquire(t, &state.1, order);
+            if matches!(
+                order,
+                Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+            ) {
+                let clock = t.clock.clone();
+                state.1.join(&clock);
+                t.tick();
+            } else {
+                let released = t.released.clone();
+                state.1.join(&released);
+            }
+        });
+        state.0 = new;
+        Ok(old)
+    }
+
+    pub fn swap(&self, v: u64, order: Ordering) -> u64 {
+        self.rmw(order, order, |_| Some(v)).unwrap()
+    }
+
+    pub fn fetch_add(&self, v: u64, order: Ordering) -> u64 {
+        self.rmw(order, order, |old| Some(old.wrapping_add(v)))
+            .unwrap()
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: u64,
+        new: u64,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u64, u64> {
+        self.rmw(success, failure, |old| (old == current).then_some(new))
+    }
+}
+
+struct Shadow {
+    last_write: Option<Access>,
+    // The reads since the last write, each thread's latest.
+    reads: Vec<Access>,
+}
+
+/// A cell for plain, non-atomic data, that panics on a data race: two accesses, at least
+/// one a write, that don't happen one before the other.
+///
+/// Races are found from the happens-before the threads' [`RaceAtomicU64`]s, [`fence`]s and
+/// [`spawn`]s and joins establish, whatever order the accesses happened to run in. The
+/// report has both accesses' backtraces. Accesses take a lock, so even a racy one is
+/// memory-safe, and the data can be used freely from the closures.
+pub struct RaceCell<T> {
+    value: UnsafeCell<T>,
+    shadow: Mutex<Shadow>,
+}
+
+// Safety: every access to the value is under the shadow lock.
+unsafe impl<T: Send> Sync for RaceCell<T> {}
+
+impl<T> RaceCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            shadow: Mutex::new(Shadow {
+                last_write: None,
+                reads: Vec::new(),
+            }),
+        }
+    }
+
+    fn check(&self, shadow: &Shadow, access: &Access, clock: &VClock) {
+        let earlier =
+            shadow
+                .last_write
+                .iter()
+                .chain(if access.write { &shadow.reads[..] } else { &[] });
+        for other in earlier {
+            if other.epoch.thread != access.epoch.thread && !other.epoch.happens_before(clock) {
+                panic!(
+                    "data race on a RaceCell<{}>\n{}\nconflicts with earlier {}",
+                    std::any::type_name::<T>(),
+                    access,
+                    other
+                );
+            }
+        }
+    }
+
+    fn access(&self, write: bool) -> std::sync::MutexGuard<'_, Shadow> {
+        let mut shadow = self.shadow.lock().unwrap_or_else(|e| e.into_inner());
+        let (epoch, clock) = with_thread(|t| (t.epoch(), t.clock.clone()));
+        let access = Access {
+            epoch,
+            write,
+            backtrace: Backtrace::force_capture(),
+        };
+        self.check(&shadow, &access, &clock);
+        if write {
+            shadow.reads.clear();
+            shadow.last_write = Some(access);
+        } else {
+            shadow.reads.retain(|r| r.epoch.thread != epoch.thread);
+            shadow.reads.push(access);
+        }
+        shadow
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let _shadow = self.access(false);
+        // Safety: the shadow lock is held.
+        f(unsafe { &*self.value.get() })
+    }
+
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _shadow = self.access(true);
+        // Safety: the shadow lock is held.
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+#[cfg(test)]
+fn message_passing(store: Ordering, load: Ordering, fences: bool) {
+    use std::sync::Arc;
+
+    let data = Arc::new(RaceCell::new(0));
+    let flag = Arc::new(RaceAtomicU64::new(0));
+    let writer = {
+        let (data, flag) = (Arc::clone(&data), Arc::clone(&flag));
+        spawn(move || {
+            data.with_mut(|d| *d = 42);
+            if fences {
+                fence(Ordering::Release);
+            }
+            flag.store(1, store);
+        })
+    };
+    while flag.load(load) == 0 {
+        thread::yield_now();
+    }
+    if fences {
+        fence(Ordering::Acquire);
+    }
+    assert_eq!(data.with(|d| *d), 42);
+    writer.join().unwrap();
+    // Joined, so this write is ordered after the writer's whatever the orderings were.
+    data.with_mut(|d| *d += 1);
+}
+
+#[test]
+fn race_release_acquire_is_race_free() {
+    message_passing(Ordering::Release, Ordering::Acquire, false);
+    message_passing(Ordering::Relaxed, Ordering::Relaxed, true);
+    message_passing(Ordering::SeqCst, Ordering::SeqCst, false);
+}
+
+#[test]
+#[should_panic(expected = "data race on a RaceCell<i32>")]
+fn race_relaxed_message_passing_races() {
+    message_passing(Ordering::Relaxed, Ordering::Relaxed, false);
+}
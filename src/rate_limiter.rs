@@ -0,0 +1,157 @@
+use crate::parker::Parker;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// The state word: tokens in the top 24 bits, the tick of the last refill in the low 40.
+const TICK_BITS: u32 = 40;
+const TICK_MASK: u64 = (1 << TICK_BITS) - 1;
+
+/// The most tokens a [`RateLimiter`] can hold.
+pub const MAX_CAPACITY: u32 = (1 << (64 - TICK_BITS)) - 1;
+
+fn pack(tokens: u32, tick: u64) -> u64 {
+    (tokens as u64) << TICK_BITS | tick & TICK_MASK
+}
+
+fn unpack(state: u64) -> (u32, u64) {
+    ((state >> TICK_BITS) as u32, state & TICK_MASK)
+}
+
+/// A token bucket: holds up to `capacity` tokens and gains one every `interval`.
+///
+/// The token count and the time it was last brought up to date share one atomic word, so
+/// [`try_acquire`](Self::try_acquire) is a load and a CAS, retried only if another thread
+/// got in first. Time is counted in whole intervals since the limiter was made, so no
+/// fraction of a token is ever lost to rounding. The count of intervals wraps, so a limiter
+/// left alone for 2^39 of them or more may come back with fewer tokens than it should.
+pub struct RateLimiter {
+    state: AtomicU64,
+    capacity: u32,
+    interval: Duration,
+    start: Instant,
+}
+
+impl RateLimiter {
+    /// A full bucket of `capacity` tokens, refilled at one per `interval`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero or over [`MAX_CAPACITY`], or `interval` is zero.
+    pub fn new(capacity: u32, interval: Duration) -> Self {
+        assert!(
+            capacity > 0 && capacity <= MAX_CAPACITY,
+            "capacity out of range"
+        );
+        assert!(!interval.is_zero(), "zero refill interval");
+        Self {
+            state: AtomicU64::new(pack(capacity, 0)),
+            capacity,
+            interval,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    // Whole intervals since the limiter was made.
+    fn ticks(&self) -> u64 {
+        (self.start.elapsed().as_nanos() / self.interval.as_nanos()) as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.ticks() & TICK_MASK
+    }
+
+    // The tokens there would be at tick `now`, and the tick to store with them.
+    fn refilled(&self, state: u64, now: u64) -> (u32, u64) {
+        let (tokens, tick) = unpack(state);
+        let gained = now.wrapping_sub(tick) & TICK_MASK;
+        if gained > TICK_MASK / 2 {
+            // Another thread read the clock after us and stored a later tick.
+            return (tokens, tick);
+        }
+        let tokens = (tokens as u64 + gained).min(self.capacity as u64) as u32;
+        (tokens, now)
+    }
+
+    /// Takes `n` tokens if there are that many, or returns false and takes none.
+    pub fn try_acquire(&self, n: u32) -> bool {
+        self.try_acquire_at(n, self.now()).is_ok()
+    }
+
+    // On failure, returns how many tokens there were.
+    fn try_acquire_at(&self, n: u32, now: u64) -> Result<(), u32> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let (tokens, tick) = self.refilled(state, now);
+            if tokens < n {
+                return Err(tokens);
+            }
+            // Relaxed: the tokens are just a count; they don't publish anything.
+            match self.state.compare_exchange_weak(
+                state,
+                pack(tokens - n, tick),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(s) => state = s,
+            }
+        }
+    }
+
+    /// Takes `n` tokens, parking until enough have come in.
+    ///
+    /// Waiters don't queue; each sleeps until its own tokens should be there and then tries
+    /// again, so under contention a large request can keep losing to small ones.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is more than the capacity, since the bucket could never hold that many.
+    pub fn acquire(&self, n: u32) {
+        assert!(n <= self.capacity, "acquiring more than the capacity");
+        let parker = Parker::new();
+        loop {
+            let ticks = self.ticks();
+            let tokens = match self.try_acquire_at(n, ticks & TICK_MASK) {
+                Ok(()) => return,
+                Err(tokens) => tokens,
+            };
+            // The tick at which the last missing token comes in.
+            let ready = (ticks + (n - tokens) as u64) as u128 * self.interval.as_nanos();
+            parker.park_deadline(self.start + Duration::from_nanos(ready as u64));
+        }
+    }
+
+    /// How many tokens there are right now. Only a snapshot under concurrent use.
+    pub fn available(&self) -> u32 {
+        self.refilled(self.state.load(Ordering::Relaxed), self.now())
+            .0
+    }
+}
+
+#[test]
+fn rate_limiter_refills_over_time() {
+    use std::thread;
+
+    // Starts full, and never hands out more than it holds.
+    let limiter = RateLimiter::new(4, Duration::from_secs(1));
+    thread::scope(|s| {
+        let taken: Vec<_> = (0..4)
+            .map(|_| s.spawn(|| (0..10).filter(|_| limiter.try_acquire(1)).count()))
+            .collect();
+        let taken: usize = taken.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(taken, 4);
+    });
+
+    let limiter = RateLimiter::new(4, Duration::from_millis(20));
+    assert!(limiter.try_acquire(4));
+    let start = Instant::now();
+    limiter.acquire(4);
+    // Four more ticks have to start, at least three whole intervals past the one we drained in.
+    let waited = start.elapsed();
+    assert!(waited >= Duration::from_millis(60), "waited {:?}", waited);
+    assert!(limiter.available() < 4);
+}
@@ -0,0 +1,207 @@
+// A plain blocking mutex with no data of its own, for building locks on and for code that
+// locks and unlocks in separate calls. Uncontended, locking and unlocking are one atomic each;
+// contended, a waiter spins for a while and then sleeps through `futex`, and an unlock only
+// makes a system call if someone's asleep. How long "a while" is, is learned per lock by a
+// `backoff::SpinTuning`, from how the waits before went.
+//
+// On macOS it's os_unfair_lock instead: the kernel knows which thread holds it, so a
+// high-priority waiter lends the holder its priority rather than waiting behind threads the
+// holder can't get ahead of, which a futex-style word can't do. That's why it has to be
+// unlocked on the thread that locked it, everywhere, so code that works on one platform
+// works on the others.
+
+/// A mutex taken and given back by separate calls, like a pthread mutex.
+pub struct RawMutex {
+    inner: imp::Lock,
+}
+
+impl RawMutex {
+    pub const fn new() -> Self {
+        Self {
+            inner: imp::Lock::new(),
+        }
+    }
+
+    /// Which implementation this platform gets: "os_unfair_lock", or "futex" for the one
+    /// built on [`futex`](crate::futex).
+    pub const IMPLEMENTATION: &'static str = imp::IMPLEMENTATION;
+
+    pub fn lock(&self) {
+        self.inner.lock();
+    }
+
+    pub fn try_lock(&self) -> bool {
+        self.inner.try_lock()
+    }
+
+    /// Gives the mutex back.
+    ///
+    /// # Safety
+    ///
+    /// The current thread holds it. On macOS, os_unfair_lock aborts the process otherwise.
+    pub unsafe fn unlock(&self) {
+        self.inner.unlock();
+    }
+}
+
+impl Default for RawMutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(all(target_os = "macos", not(miri))))]
+mod imp {
+    use crate::backoff::{Backoff, SpinTuning};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    pub const IMPLEMENTATION: &str = "futex";
+
+    const UNLOCKED: u32 = 0;
+    const LOCKED: u32 = 1;
+    // Locked, and someone may be asleep waiting for it.
+    const CONTENDED: u32 = 2;
+
+    pub struct Lock {
+        state: AtomicU32,
+    }
+
+    impl Lock {
+        pub const fn new() -> Self {
+            Self {
+                state: AtomicU32::new(UNLOCKED),
+            }
+        }
+
+        pub fn lock(&self) {
+            // Acquire: pairs with the Release of the unlock before.
+            if self
+                .state
+                .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                self.lock_contended();
+            }
+        }
+
+        #[cold]
+        fn lock_contended(&self) {
+            let tuning = SpinTuning::of(self);
+            let budget = tuning.budget();
+            let backoff = Backoff::new();
+            while backoff.steps() < budget {
+                let state = self.state.load(Ordering::Relaxed);
+                match state {
+                    UNLOCKED
+                        if self
+                            .state
+                            .compare_exchange_weak(
+                                UNLOCKED,
+                                LOCKED,
+                                Ordering::Acquire,
+                                Ordering::Relaxed,
+                            )
+                            .is_ok() =>
+                    {
+                        tuning.record(backoff.steps(), true);
+                        return;
+                    }
+                    // Others are asleep already; spinning won't get ahead of them.
+                    CONTENDED => break,
+                    _ => {}
+                }
+                backoff.snooze_while_eq(&self.state, state);
+            }
+            tuning.record(backoff.steps(), false);
+            // From here on we take it as CONTENDED, since we can't know we're the only waiter.
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                crate::futex::wait(&self.state, CONTENDED, None);
+            }
+        }
+
+        pub fn try_lock(&self) -> bool {
+            self.state
+                .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        pub fn unlock(&self) {
+            // Release: what was done under the lock happens before the next holder's lock.
+            if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+                crate::futex::wake_one(&self.state);
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", not(miri)))]
+mod imp {
+    use std::cell::UnsafeCell;
+
+    pub const IMPLEMENTATION: &str = "os_unfair_lock";
+
+    // From <os/lock.h>: a word that's zero while it's unlocked, and the owner's thread, for
+    // the kernel, while it's locked.
+    #[repr(C)]
+    struct OsUnfairLock {
+        _opaque: u32,
+    }
+
+    extern "C" {
+        fn os_unfair_lock_lock(lock: *mut OsUnfairLock);
+        fn os_unfair_lock_trylock(lock: *mut OsUnfairLock) -> bool;
+        fn os_unfair_lock_unlock(lock: *mut OsUnfairLock);
+    }
+
+    pub struct Lock(UnsafeCell<OsUnfairLock>);
+
+    unsafe impl Send for Lock {}
+    unsafe impl Sync for Lock {}
+
+    impl Lock {
+        pub const fn new() -> Self {
+            Self(UnsafeCell::new(OsUnfairLock { _opaque: 0 }))
+        }
+
+        pub fn lock(&self) {
+            unsafe { os_unfair_lock_lock(self.0.get()) }
+        }
+
+        pub fn try_lock(&self) -> bool {
+            unsafe { os_unfair_lock_trylock(self.0.get()) }
+        }
+
+        pub fn unlock(&self) {
+            unsafe { os_unfair_lock_unlock(self.0.get()) }
+        }
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn raw_mutex_excludes() {
+    use std::cell::UnsafeCell;
+
+    struct Counter(UnsafeCell<u64>);
+    unsafe impl Sync for Counter {}
+
+    let n = if cfg!(miri) { 50 } else { 10_000 };
+    let m = RawMutex::new();
+    let count = Counter(UnsafeCell::new(0));
+    assert!(m.try_lock());
+    assert!(!m.try_lock());
+    unsafe { m.unlock() };
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..n {
+                    m.lock();
+                    unsafe { *count.0.get() += 1 };
+                    unsafe { m.unlock() };
+                }
+            });
+        }
+    });
+    assert_eq!(unsafe { *count.0.get() }, 4 * n);
+    assert!(["os_unfair_lock", "futex"].contains(&RawMutex::IMPLEMENTATION));
+}
@@ -0,0 +1,784 @@
+use crate::sync_shim::atomic::{AtomicUsize, Ordering};
+use crate::sync_shim::{yield_now, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+#[cfg(feature = "tracing")]
+use std::time::Duration;
+#[cfg(any(feature = "metrics", feature = "profile", feature = "tracing"))]
+use std::time::Instant;
+
+const WRITER: usize = 1;
+// Set by a writer that's waiting, so new readers hold off and the writer can't starve.
+const WRITER_WAITING: usize = 2;
+const READER: usize = 4;
+
+// Holds at least this long, in nanoseconds, get a warning under the `tracing` feature.
+#[cfg(feature = "tracing")]
+static LONG_HOLD: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(10_000_000);
+
+/// Sets how long a hold on an [`RwLock`] has to last for the `tracing` feature to warn about
+/// it; 10ms by default.
+#[cfg(feature = "tracing")]
+pub fn set_long_hold(threshold: Duration) {
+    LONG_HOLD.store(
+        threshold.as_nanos().min(u64::MAX as u128) as u64,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+}
+
+/// A spinning readers-writer lock.
+///
+/// Under the `deadlock-detect` feature it reports every acquisition and wait to
+/// [`deadlock`](crate::deadlock), and a thread whose wait would deadlock panics. Under
+/// `lock-order` it does the same for [`lock_order`](crate::lock_order), and a thread taking
+/// locks out of order panics. Under `tracing` it emits a span for every acquisition that has
+/// to wait and an event when it gets the lock, and warns of holds longer than
+/// [`set_long_hold`]'s threshold, all with the lock's [`named`](Self::named) name. Under
+/// `metrics` a named lock publishes its acquisitions, contention and wait times to
+/// [`metrics::global`](crate::metrics::global), and under `profile` every wait is put down to
+/// the line that took the lock, for [`profile::report`](crate::profile::report). Under
+/// `watchdog` a [`watchdog`](crate::watchdog) thread warns of any hold that goes on too long.
+/// Under `introspect` a named lock is listed, with its holders and waiters, in
+/// [`introspect::dump`](crate::introspect::dump).
+///
+/// The guards of a lock in an [`Arc`], from [`read_arc`](Self::read_arc) and
+/// [`write_arc`](Self::write_arc), own their reference to it, so a stage of a pipeline can lock
+/// it and pass the guard on to the next stage's thread, which unlocks it. Deadlock detection
+/// and the lock-order check know them as handed off: from the moment it's taken, such a hold
+/// belongs to no thread, so it doesn't count toward the order of locks the taking thread goes
+/// on to take, and a cycle through it isn't found.
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    #[cfg(feature = "lock-order")]
+    order: crate::lock_order::Key,
+    #[cfg(feature = "tracing")]
+    name: &'static str,
+    #[cfg(feature = "metrics")]
+    metrics: Option<std::sync::Arc<crate::metrics::LockMetrics>>,
+    #[cfg(feature = "introspect")]
+    live: Option<std::sync::Arc<crate::introspect::LockState>>,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for RwLock<T> where T: Send {}
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(t: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            #[cfg(feature = "lock-order")]
+            order: crate::lock_order::Key::new(None),
+            #[cfg(feature = "tracing")]
+            name: "unnamed",
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "introspect")]
+            live: None,
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(t: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            #[cfg(feature = "lock-order")]
+            order: crate::lock_order::Key::new(None),
+            #[cfg(feature = "tracing")]
+            name: "unnamed",
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "introspect")]
+            live: None,
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    /// A lock with a place in the order `lock-order` checks: it has to be taken after any
+    /// lock of a lower level and before any of a higher one. Without the feature, the same as
+    /// `new`.
+    #[allow(unused_mut, unused_variables)]
+    pub fn with_level(t: T, level: u32) -> Self {
+        let mut lock = Self::new(t);
+        #[cfg(feature = "lock-order")]
+        {
+            lock.order = crate::lock_order::Key::new(Some(level));
+        }
+        lock
+    }
+
+    /// Names the lock in what the `tracing` feature emits, under `metrics` publishes it by
+    /// that name, and under `introspect` registers it by that name. Without any of them, does
+    /// nothing.
+    #[allow(unused_mut, unused_variables)]
+    pub fn named(mut self, name: &'static str) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            self.name = name;
+        }
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics = Some(std::sync::Arc::new(crate::metrics::LockMetrics::new(
+                crate::metrics::global(),
+                name,
+            )));
+        }
+        #[cfg(feature = "introspect")]
+        {
+            self.live = Some(crate::introspect::LockState::register(name));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.blocking();
+        if let Some(guard) = self.try_read() {
+            return guard;
+        }
+        let _waiting = self.waiting("read");
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            // Stay in the shared cache state until it looks like we could get in.
+            while self.state.load(Ordering::Relaxed) & (WRITER | WRITER_WAITING) != 0 {
+                yield_now();
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        // Only a writer keeps us out: a weak CAS failing spuriously, or because another
+        // reader came or went, is worth another go.
+        while state & (WRITER | WRITER_WAITING) == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(RwLockReadGuard {
+                        lock: self,
+                        hold: self.acquired("read"),
+                        _not_send: PhantomData,
+                    })
+                }
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.blocking();
+        if let Some(guard) = self.try_write() {
+            return guard;
+        }
+        let _waiting = self.waiting("write");
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                // Acquire: pairs with the Release of the previous holder's unlock. Taking the
+                // lock also clears WRITER_WAITING; other waiting writers set it again.
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return RwLockWriteGuard {
+                        lock: self,
+                        hold: self.acquired("write"),
+                        _not_send: PhantomData,
+                    };
+                }
+                continue;
+            }
+            if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            yield_now();
+        }
+    }
+
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & !WRITER_WAITING != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(RwLockWriteGuard {
+            lock: self,
+            hold: self.acquired("write"),
+            _not_send: PhantomData,
+        })
+    }
+
+    // What the debugging, tracing and metrics features are told; without them, nothing. "Blocking" is
+    // before a blocking acquisition and "waiting" once it has to wait.
+    #[track_caller]
+    fn blocking(&self) {
+        #[cfg(feature = "lock-order")]
+        self.order.blocking(std::panic::Location::caller());
+    }
+
+    #[track_caller]
+    #[allow(unused_variables)]
+    fn waiting(&self, mode: &'static str) -> Waiting {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::wait(self.id(), std::panic::Location::caller());
+        #[cfg(feature = "introspect")]
+        if let Some(live) = &self.live {
+            live.waiting();
+        }
+        Waiting {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("rwlock wait", lock = self.name, mode).entered(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone().map(|m| (m, mode == "write")),
+            #[cfg(feature = "profile")]
+            site: std::panic::Location::caller(),
+            #[cfg(feature = "introspect")]
+            live: self.live.clone(),
+            #[cfg(any(feature = "metrics", feature = "profile", feature = "tracing"))]
+            since: Instant::now(),
+        }
+    }
+
+    #[track_caller]
+    #[allow(unused_variables)]
+    fn acquired(&self, mode: &'static str) -> Hold {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::acquired(self.id(), std::panic::Location::caller());
+        #[cfg(feature = "lock-order")]
+        self.order.acquired(std::panic::Location::caller());
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.acquired(mode == "write");
+        }
+        #[cfg(feature = "introspect")]
+        if let Some(live) = &self.live {
+            live.acquired(mode == "write");
+        }
+        Hold {
+            #[cfg(any(feature = "deadlock-detect", feature = "lock-order"))]
+            handed_off: false,
+            #[cfg(feature = "watchdog")]
+            watch: crate::watchdog::acquired(self.id(), std::panic::Location::caller()),
+            #[cfg(feature = "tracing")]
+            since: Instant::now(),
+            #[cfg(any(feature = "introspect", feature = "tracing"))]
+            mode,
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn released(&self, hold: &Hold) {
+        #[cfg(feature = "deadlock-detect")]
+        if !hold.handed_off {
+            crate::deadlock::released(self.id());
+        }
+        #[cfg(feature = "lock-order")]
+        if !hold.handed_off {
+            self.order.released();
+        }
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::released(&hold.watch);
+        #[cfg(feature = "introspect")]
+        if let Some(live) = &self.live {
+            live.released(hold.mode == "write");
+        }
+        #[cfg(feature = "tracing")]
+        {
+            let held = hold.since.elapsed();
+            if held.as_nanos() >= u128::from(LONG_HOLD.load(std::sync::atomic::Ordering::Relaxed)) {
+                tracing::warn!(
+                    lock = self.name,
+                    mode = hold.mode,
+                    ?held,
+                    "rwlock held long"
+                );
+            }
+        }
+    }
+
+    // Makes a hold one that can end on any thread, by handing it over now to no thread at all.
+    #[allow(unused_mut)]
+    fn hand_off(&self, mut hold: Hold) -> Hold {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::released(self.id());
+        #[cfg(feature = "lock-order")]
+        self.order.released();
+        #[cfg(any(feature = "deadlock-detect", feature = "lock-order"))]
+        {
+            hold.handed_off = true;
+        }
+        hold
+    }
+
+    #[cfg(any(feature = "deadlock-detect", feature = "watchdog"))]
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+// Alive while a blocking acquisition waits: the tracing span's lifetime, the wait the
+// metrics time, and the waiter introspection counts.
+struct Waiting {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    // The lock's metrics, if it's named, and whether this is a write.
+    #[cfg(feature = "metrics")]
+    metrics: Option<(std::sync::Arc<crate::metrics::LockMetrics>, bool)>,
+    #[cfg(feature = "profile")]
+    site: &'static std::panic::Location<'static>,
+    #[cfg(feature = "introspect")]
+    live: Option<std::sync::Arc<crate::introspect::LockState>>,
+    #[cfg(any(feature = "metrics", feature = "profile", feature = "tracing"))]
+    since: Instant,
+}
+
+#[cfg(any(
+    feature = "introspect",
+    feature = "metrics",
+    feature = "profile",
+    feature = "tracing"
+))]
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        #[cfg(feature = "introspect")]
+        if let Some(live) = &self.live {
+            live.done_waiting();
+        }
+        #[cfg(any(feature = "metrics", feature = "profile", feature = "tracing"))]
+        {
+            let waited = self.since.elapsed();
+            #[cfg(feature = "tracing")]
+            tracing::debug!(parent: &*self.span, ?waited, "rwlock acquired");
+            #[cfg(feature = "metrics")]
+            if let Some((metrics, write)) = &self.metrics {
+                metrics.waited(*write, waited);
+            }
+            #[cfg(feature = "profile")]
+            crate::profile::record(self.site, waited);
+        }
+    }
+}
+
+// What a guard keeps of its acquisition for the debugging features; nothing, without them.
+struct Hold {
+    // Taken for an owned guard, and so already off the books of the thread that took it.
+    #[cfg(any(feature = "deadlock-detect", feature = "lock-order"))]
+    handed_off: bool,
+    #[cfg(feature = "watchdog")]
+    watch: crate::watchdog::Watch,
+    #[cfg(feature = "tracing")]
+    since: Instant,
+    #[cfg(any(feature = "introspect", feature = "tracing"))]
+    mode: &'static str,
+}
+
+impl<T> RwLock<T> {
+    /// [`read`](Self::read), for a guard that keeps the lock alive and can be released on
+    /// another thread.
+    #[track_caller]
+    pub fn read_arc(self: &Arc<Self>) -> ArcRwLockReadGuard<T> {
+        let hold = self.read().into_hold();
+        ArcRwLockReadGuard {
+            lock: Arc::clone(self),
+            hold: self.hand_off(hold),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_read_arc(self: &Arc<Self>) -> Option<ArcRwLockReadGuard<T>> {
+        let hold = self.try_read()?.into_hold();
+        Some(ArcRwLockReadGuard {
+            lock: Arc::clone(self),
+            hold: self.hand_off(hold),
+        })
+    }
+
+    /// [`write`](Self::write), for a guard that keeps the lock alive and can be released on
+    /// another thread.
+    #[track_caller]
+    pub fn write_arc(self: &Arc<Self>) -> ArcRwLockWriteGuard<T> {
+        let hold = self.write().into_hold();
+        ArcRwLockWriteGuard {
+            lock: Arc::clone(self),
+            hold: self.hand_off(hold),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_write_arc(self: &Arc<Self>) -> Option<ArcRwLockWriteGuard<T>> {
+        let hold = self.try_write()?.into_hold();
+        Some(ArcRwLockWriteGuard {
+            lock: Arc::clone(self),
+            hold: self.hand_off(hold),
+        })
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+// Guards are !Send: deadlock-detect and lock-order strike a release off the books of the
+// thread doing it, which have to be the books the acquisition went on. The owned guards come
+// off the books as they're made, so they can go to other threads.
+type NotSend = PhantomData<*const ()>;
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    hold: Hold,
+    _not_send: NotSend,
+}
+
+unsafe impl<T: Sync> Sync for RwLockReadGuard<'_, T> {}
+
+impl<T> RwLockReadGuard<'_, T> {
+    /// Unlocks for as long as `f` runs, and then reads the lock again, behind any writer
+    /// that started waiting meanwhile, so a long computation doesn't have to keep writers out
+    /// or have its caller restructured. If `f` panics, the lock is read again before the
+    /// panic goes on, for the guard to release.
+    #[track_caller]
+    pub fn unlocked<R>(s: &mut Self, f: impl FnOnce() -> R) -> R {
+        s.lock.released(&s.hold);
+        s.lock.state.fetch_sub(READER, Ordering::Release);
+        let r = panic::catch_unwind(AssertUnwindSafe(f));
+        s.hold = s.lock.read().into_hold();
+        r.unwrap_or_else(|p| panic::resume_unwind(p))
+    }
+
+    // Ends the guard without releasing the lock, and returns what it kept of the acquisition.
+    fn into_hold(self) -> Hold {
+        let guard = ManuallyDrop::new(self);
+        // Safety: the guard is never dropped, so its hold is moved out only once.
+        unsafe { std::ptr::read(&guard.hold) }
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: readers exclude writers, so no one holds a mutable reference.
+        self.lock.v.with(|v| unsafe { &*v })
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.released(&self.hold);
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    hold: Hold,
+    _not_send: NotSend,
+}
+
+unsafe impl<T: Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLockWriteGuard<'_, T> {
+    /// Unlocks for as long as `f` runs, and then takes the lock again, as a new writer that
+    /// gets in line with the others, so readers and writers held up by a long computation
+    /// get their turn. If `f` panics, the lock is taken again before the panic goes on, for
+    /// the guard to release.
+    #[track_caller]
+    pub fn unlocked<R>(s: &mut Self, f: impl FnOnce() -> R) -> R {
+        s.lock.released(&s.hold);
+        s.lock.state.fetch_and(!WRITER, Ordering::Release);
+        let r = panic::catch_unwind(AssertUnwindSafe(f));
+        s.hold = s.lock.write().into_hold();
+        r.unwrap_or_else(|p| panic::resume_unwind(p))
+    }
+
+    fn into_hold(self) -> Hold {
+        let guard = ManuallyDrop::new(self);
+        // Safety: as for a read guard's.
+        unsafe { std::ptr::read(&guard.hold) }
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.v.with(|v| unsafe { &*v })
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we hold the lock exclusively.
+        self.lock.v.with_mut(|v| unsafe { &mut *v })
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.released(&self.hold);
+        // Leaves WRITER_WAITING alone so a queued writer keeps its priority over readers.
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+/// A read lock on an `Arc<RwLock>`, which can be sent to another thread and released there.
+pub struct ArcRwLockReadGuard<T> {
+    lock: Arc<RwLock<T>>,
+    hold: Hold,
+}
+
+impl<T> ArcRwLockReadGuard<T> {
+    pub fn rwlock(&self) -> &Arc<RwLock<T>> {
+        &self.lock
+    }
+}
+
+impl<T> Deref for ArcRwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.v.with(|v| unsafe { &*v })
+    }
+}
+
+impl<T> Drop for ArcRwLockReadGuard<T> {
+    fn drop(&mut self) {
+        self.lock.released(&self.hold);
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+/// A write lock on an `Arc<RwLock>`, which can be sent to another thread and released there.
+pub struct ArcRwLockWriteGuard<T> {
+    lock: Arc<RwLock<T>>,
+    hold: Hold,
+}
+
+impl<T> ArcRwLockWriteGuard<T> {
+    pub fn rwlock(&self) -> &Arc<RwLock<T>> {
+        &self.lock
+    }
+}
+
+impl<T> Deref for ArcRwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.lock.v.with(|v| unsafe { &*v })
+    }
+}
+
+impl<T> DerefMut for ArcRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.lock.v.with_mut(|v| unsafe { &mut *v })
+    }
+}
+
+impl<T> Drop for ArcRwLockWriteGuard<T> {
+    fn drop(&mut self) {
+        self.lock.released(&self.hold);
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn rwlock_test() {
+    use std::thread;
+    let n = if cfg!(miri) { 50 } else { 1000 };
+    let l = &RwLock::new(0);
+    thread::scope(|s| {
+        for i in 0..8 {
+            s.spawn(move || {
+                for _ in 0..n {
+                    if i % 2 == 0 {
+                        *l.write() += 1;
+                    } else {
+                        let v = *l.read();
+                        assert!(v <= 4 * n);
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(*l.read(), 4 * n);
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn rwlock_readers_share() {
+    let l = RwLock::new(());
+    let r1 = l.read();
+    let r2 = l.try_read();
+    assert!(r2.is_some());
+    assert!(l.try_write().is_none());
+    drop((r1, r2));
+    let w = l.try_write();
+    assert!(w.is_some());
+    assert!(l.try_read().is_none());
+}
+
+// Each stage locks the next one's input before unlocking its own, and hands its guard on.
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn rwlock_arc_guards_are_released_where_they_end_up() {
+    use std::sync::mpsc;
+
+    let l = Arc::new(RwLock::new(Vec::new()));
+    let (tx, rx) = mpsc::channel::<ArcRwLockWriteGuard<Vec<u32>>>();
+    let mut guard = l.write_arc();
+    guard.push(1);
+    let stage = std::thread::spawn(move || {
+        let mut guard = rx.recv().unwrap();
+        guard.push(2);
+        drop(guard);
+    });
+    tx.send(guard).unwrap();
+    stage.join().unwrap();
+    assert_eq!(*l.read(), [1, 2]);
+
+    let r = l.read_arc();
+    assert!(l.try_write_arc().is_none());
+    assert!(l.try_read_arc().is_some());
+    std::thread::spawn(move || assert_eq!(r.len(), 2))
+        .join()
+        .unwrap();
+    assert_eq!(Arc::strong_count(&l), 1);
+    assert!(l.try_write_arc().is_some());
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn rwlock_guards_unlock_for_a_while() {
+    let l = RwLock::new(1);
+    let mut w = l.write();
+    let seen = RwLockWriteGuard::unlocked(&mut w, || *l.try_read().unwrap());
+    assert_eq!(seen, 1);
+    *w += 1;
+    assert!(l.try_read().is_none());
+
+    // A panic in the closure leaves the lock held by the guard, which releases it.
+    let r = panic::catch_unwind(AssertUnwindSafe(|| {
+        RwLockWriteGuard::unlocked(&mut w, || panic!("in f"));
+    }));
+    assert!(r.is_err() && l.try_read().is_none());
+    drop(w);
+
+    let mut r = l.read();
+    RwLockReadGuard::unlocked(&mut r, || *l.try_write().unwrap() += 1);
+    assert_eq!(*r, 3);
+    assert!(l.try_write().is_none());
+}
+
+#[cfg(loom)]
+#[test]
+fn rwlock_loom() {
+    use loom::sync::Arc;
+    use loom::thread;
+    loom::model(|| {
+        let l = Arc::new(RwLock::new(0));
+        let writer = {
+            let l = Arc::clone(&l);
+            thread::spawn(move || *l.write() += 1)
+        };
+        let v = *l.read();
+        assert!(v <= 1);
+        *l.write() += 1;
+        writer.join().unwrap();
+        assert_eq!(*l.read(), 2);
+    });
+}
+
+#[cfg(all(feature = "tracing", not(any(loom, shuttle))))]
+#[test]
+fn rwlock_traces_waits_and_long_holds() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Dispatch, Event, Metadata};
+
+    // Every span's name and every event's fields, as text.
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!("{}={:?} ", field, value);
+        }
+    }
+
+    impl tracing::Subscriber for Collect {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.0
+                .lock()
+                .unwrap()
+                .push(span.metadata().name().to_string());
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let collect = Collect::default();
+    let dispatch = Dispatch::new(collect.clone());
+    let seen = |what: &str| collect.0.lock().unwrap().iter().any(|e| e.contains(what));
+    let lock = RwLock::new(0).named("cache");
+    set_long_hold(Duration::from_millis(1));
+    tracing::dispatcher::with_default(&dispatch, || {
+        thread::scope(|s| {
+            let guard = lock.write();
+            let reader = s.spawn(|| tracing::dispatcher::with_default(&dispatch, || *lock.read()));
+            while !seen("rwlock wait") {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(2));
+            drop(guard);
+            reader.join().unwrap();
+        })
+    });
+    assert!(seen(
+        r#"message=rwlock held long lock="cache" mode="write""#
+    ));
+    assert!(seen("message=rwlock acquired waited="));
+}
@@ -0,0 +1,999 @@
+// A cooperative scheduler for the unit tests: under `run`, only one thread runs at a time,
+// and at every access to a sync_shim atomic, lock or condvar, and every spin_loop or
+// yield_now, it hands over to a thread picked by a seeded random number generator. So a
+// test's interleaving is fixed by its seed, and a failure found under one seed happens
+// again, every time, under the same seed, at no more cost than a thread switch per access.
+//
+// Unlike loom it explores one interleaving per seed and only sequentially consistent ones,
+// since the threads really do run one at a time; use `check` to try many seeds. In the
+// unit tests, sync_shim's types are this module's, which act as std's outside `run`.
+//
+// `record` also keeps the last atomic accesses of a run, and `replay` forces the interleaving
+// a run took, which still holds if a change to the test or the scheduler means its seed no
+// longer leads there. `check` prints both on a failure.
+//
+// `run_guided` takes the interleaving from a list of choices rather than a seed, for property
+// tests: a seed is all or nothing to proptest, while a list shrinks, by dropping choices and
+// making them smaller, to an interleaving with fewer, earlier switches.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{self as std_sync, Arc, LockResult, PoisonError, TryLockError};
+use std::thread;
+use std::time::Duration;
+
+// A run that takes this many switches is taken to be stuck: every thread waiting on the
+// others, say, or spinning with nothing left to wait for.
+const MAX_STEPS: usize = 1_000_000;
+
+struct State {
+    current: usize,
+    live: Vec<bool>,
+    rng: u64,
+    steps: usize,
+    // The thread each switch went to.
+    schedule: Vec<usize>,
+    // Where a replay still has to go, and the switch where the run left it, if it has.
+    replay: Option<VecDeque<usize>>,
+    diverged: Option<usize>,
+    // The choices a guided run has left: each picks that many live threads on from the first.
+    guide: Option<VecDeque<usize>>,
+    // The last `trace_len` atomic accesses, and the number each location was given.
+    trace: VecDeque<Event>,
+    trace_len: usize,
+    locations: HashMap<usize, usize>,
+}
+
+impl State {
+    fn new(seed: u64) -> Self {
+        Self {
+            current: 0,
+            live: vec![true],
+            rng: seed,
+            steps: 0,
+            schedule: Vec::new(),
+            replay: None,
+            diverged: None,
+            guide: None,
+            trace: VecDeque::new(),
+            trace_len: 0,
+            locations: HashMap::new(),
+        }
+    }
+
+    fn pick(&mut self) -> Option<usize> {
+        if let Some(replay) = &mut self.replay {
+            match replay.pop_front() {
+                Some(next) if self.live.get(next) == Some(&true) => {
+                    self.schedule.push(next);
+                    return Some(next);
+                }
+                // Whatever the replay's lost, the run has to finish; the seed picks from
+                // here on and `replay` fails it at the end.
+                _ => {
+                    self.diverged.get_or_insert(self.schedule.len());
+                    self.replay = None;
+                }
+            }
+        }
+        let live: Vec<usize> = (0..self.live.len()).filter(|&t| self.live[t]).collect();
+        if let Some(guide) = &mut self.guide {
+            let next = match guide.pop_front() {
+                Some(choice) => *live.get(choice % live.len().max(1))?,
+                // Out of choices: round robin, which can't starve a thread another waits for.
+                None => *live.iter().find(|&&t| t > self.current).or(live.first())?,
+            };
+            self.schedule.push(next);
+            return Some(next);
+        }
+        // splitmix64
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let next = *live.get(z as usize % live.len().max(1))?;
+        self.schedule.push(next);
+        Some(next)
+    }
+}
+
+struct Scheduler {
+    state: std_sync::Mutex<State>,
+    turn: std_sync::Condvar,
+}
+
+impl Scheduler {
+    fn lock(&self) -> std_sync::MutexGuard<'_, State> {
+        // A thread that panics mid-switch still has to let the others finish.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_turn<'a>(
+        &'a self,
+        mut state: std_sync::MutexGuard<'a, State>,
+        id: usize,
+    ) -> std_sync::MutexGuard<'a, State> {
+        while state.current != id {
+            state = self
+                .turn
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state
+    }
+
+    fn switch(&self, id: usize) {
+        let mut state = self.lock();
+        state.steps += 1;
+        if state.steps > MAX_STEPS {
+            drop(state);
+            panic!("sched: no end after {} switches", MAX_STEPS);
+        }
+        state.current = state.pick().unwrap_or(id);
+        self.turn.notify_all();
+        drop(self.wait_turn(state, id));
+    }
+
+    fn exit(&self, id: usize) {
+        let mut state = self.lock();
+        state.live[id] = false;
+        if let Some(next) = state.pick() {
+            state.current = next;
+        }
+        self.turn.notify_all();
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Scheduler>, usize)>> = const { RefCell::new(None) };
+}
+
+// Lets another thread run, if this one is under a scheduler.
+fn switch() {
+    let current = CURRENT.with(|c| c.borrow().clone());
+    if let Some((scheduler, id)) = current {
+        scheduler.switch(id);
+    }
+}
+
+fn is_scheduled() -> bool {
+    CURRENT.with(|c| c.borrow().is_some())
+}
+
+// Takes the thread out of the schedule once it's done, panicking or not.
+struct Exit(Arc<Scheduler>, usize);
+
+impl Drop for Exit {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = None);
+        self.0.exit(self.1);
+    }
+}
+
+/// One atomic access in a [`record`]ed run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// As numbered in the schedule.
+    pub thread: usize,
+    /// The method called: `load`, `fetch_add`, `fence` and so on.
+    pub op: &'static str,
+    /// Which atomic, numbered in the order the run first touched them. None for a fence.
+    pub location: Option<usize>,
+    /// For a compare-exchange, the success or failure ordering, whichever applied.
+    pub ordering: Ordering,
+    /// The value read, if the access read one.
+    pub read: Option<u64>,
+    /// The value written, if the access wrote one; a failed compare-exchange wrote none.
+    pub written: Option<u64>,
+}
+
+impl fmt::Display for Event {
+    /// `t1 compare_exchange a0 AcqRel: 0 -> 1`, say.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{} {}", self.thread, self.op)?;
+        if let Some(location) = self.location {
+            write!(f, " a{}", location)?;
+        }
+        write!(f, " {:?}", self.ordering)?;
+        match (self.read, self.written) {
+            (Some(r), Some(w)) => write!(f, ": {} -> {}", r, w),
+            (Some(r), None) => write!(f, ": {}", r),
+            (None, Some(w)) => write!(f, ": -> {}", w),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+// Adds an access to the current run's trace, if it's keeping one.
+fn trace(
+    op: &'static str,
+    location: Option<usize>,
+    ordering: Ordering,
+    read: Option<u64>,
+    written: Option<u64>,
+) {
+    let current = CURRENT.with(|c| c.borrow().clone());
+    let Some((scheduler, thread)) = current else {
+        return;
+    };
+    let mut state = scheduler.lock();
+    if state.trace_len == 0 {
+        return;
+    }
+    let next = state.locations.len();
+    let location = location.map(|l| *state.locations.entry(l).or_insert(next));
+    if state.trace.len() == state.trace_len {
+        state.trace.pop_front();
+    }
+    state.trace.push_back(Event {
+        thread,
+        op,
+        location,
+        ordering,
+        read,
+        written,
+    });
+}
+
+/// What a [`record`]ed run did.
+pub struct Recording {
+    /// Which thread each switch went to, for [`replay`].
+    pub schedule: Vec<usize>,
+    /// Its last atomic accesses, oldest first.
+    pub trace: Vec<Event>,
+    /// What it panicked with, if it did.
+    pub panic: Option<Box<dyn Any + Send>>,
+}
+
+impl fmt::Display for Recording {
+    /// The trace, one access per line, then the schedule in the form `SCHED_REPLAY` takes.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.trace {
+            writeln!(f, "  {}", event)?;
+        }
+        write!(f, "schedule: {}", encode(&self.schedule))
+    }
+}
+
+/// A schedule as `0*3,1,0*12`: each switch's thread, with runs of the same one counted.
+pub fn encode(schedule: &[usize]) -> String {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &t in schedule {
+        match runs.last_mut() {
+            Some((last, n)) if *last == t => *n += 1,
+            _ => runs.push((t, 1)),
+        }
+    }
+    let runs: Vec<String> = runs
+        .into_iter()
+        .map(|(t, n)| match n {
+            1 => t.to_string(),
+            n => format!("{}*{}", t, n),
+        })
+        .collect();
+    runs.join(",")
+}
+
+/// The schedule [`encode`] made `s` from, or None if it didn't.
+pub fn decode(s: &str) -> Option<Vec<usize>> {
+    let mut schedule = Vec::new();
+    for run in s.split(',').filter(|r| !r.is_empty()) {
+        let (t, n): (usize, usize) = match run.split_once('*') {
+            Some((t, n)) => (t.parse().ok()?, n.parse().ok()?),
+            None => (run.parse().ok()?, 1),
+        };
+        schedule.extend(std::iter::repeat_n(t, n));
+    }
+    Some(schedule)
+}
+
+/// Spawns threads into a [`run`].
+pub struct Scope<'scope, 'env: 'scope> {
+    scope: &'scope thread::Scope<'scope, 'env>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl<'scope> Scope<'scope, '_> {
+    pub fn spawn<T: Send + 'scope>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'scope,
+    ) -> JoinHandle<'scope, T> {
+        let id = {
+            let mut state = self.scheduler.lock();
+            state.live.push(true);
+            state.live.len() - 1
+        };
+        let scheduler = Arc::clone(&self.scheduler);
+        let handle = self.scope.spawn(move || {
+            drop(scheduler.wait_turn(scheduler.lock(), id));
+            CURRENT.with(|c| *c.borrow_mut() = Some((Arc::clone(&scheduler), id)));
+            let _exit = Exit(scheduler, id);
+            f()
+        });
+        JoinHandle {
+            handle,
+            scheduler: Arc::clone(&self.scheduler),
+            id,
+        }
+    }
+}
+
+/// A thread of a [`run`].
+pub struct JoinHandle<'scope, T> {
+    handle: thread::ScopedJoinHandle<'scope, T>,
+    scheduler: Arc<Scheduler>,
+    id: usize,
+}
+
+impl<T> JoinHandle<'_, T> {
+    pub fn join(self) -> thread::Result<T> {
+        while self.scheduler.lock().live[self.id] {
+            switch();
+        }
+        self.handle.join()
+    }
+}
+
+// Runs `f` under a scheduler starting from `state`, and returns the state it ended in along
+// with how `f` and its threads ended.
+fn execute<'env, F>(state: State, f: F) -> (State, thread::Result<()>)
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>),
+{
+    let scheduler = Arc::new(Scheduler {
+        state: std_sync::Mutex::new(state),
+        turn: std_sync::Condvar::new(),
+    });
+    assert!(!is_scheduled(), "sched::run inside sched::run");
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        thread::scope(|s| {
+            CURRENT.with(|c| *c.borrow_mut() = Some((Arc::clone(&scheduler), 0)));
+            let _exit = Exit(Arc::clone(&scheduler), 0);
+            f(&Scope {
+                scope: s,
+                scheduler: Arc::clone(&scheduler),
+            });
+        })
+    }));
+    let state = std::mem::replace(&mut *scheduler.lock(), State::new(0));
+    (state, result)
+}
+
+/// Runs `f` and the threads it spawns one at a time, switching between them at every
+/// sync_shim access in an order fixed by `seed`, and returns the order: which thread each
+/// switch went to, `f` being thread 0 and the rest numbered as spawned. A panic in any of
+/// them ends the run with that panic once the others are done.
+pub fn run<'env, F>(seed: u64, f: F) -> Vec<usize>
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>),
+{
+    let (state, result) = execute(State::new(seed), f);
+    if let Err(e) = result {
+        panic::resume_unwind(e);
+    }
+    state.schedule
+}
+
+/// [`run`], but switching as `choices` say rather than as a seed does: at each switch the
+/// next choice picks the live thread that many on from the first, wrapping, and once they run
+/// out, the threads take turns. So every list of choices is a schedule, and a shorter or
+/// smaller one a simpler schedule, for proptest to shrink a failure to.
+pub fn run_guided<'env, F>(choices: &[usize], f: F) -> Vec<usize>
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>),
+{
+    let mut state = State::new(0);
+    state.guide = Some(choices.iter().copied().collect());
+    let (state, result) = execute(state, f);
+    if let Err(e) = result {
+        panic::resume_unwind(e);
+    }
+    state.schedule
+}
+
+/// [`run`], keeping the last `trace_len` atomic accesses, and returning them and the
+/// schedule whether or not the run panicked.
+pub fn record<'env, F>(seed: u64, trace_len: usize, f: F) -> Recording
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>),
+{
+    let mut state = State::new(seed);
+    state.trace_len = trace_len;
+    let (state, result) = execute(state, f);
+    Recording {
+        schedule: state.schedule,
+        trace: state.trace.into(),
+        panic: result.err(),
+    }
+}
+
+/// Runs `f` as [`run`] does, but switching as `schedule`, which a run or [`record`] returned,
+/// says to. So as long as `f` does the same as it did then, it takes the same interleaving.
+/// Panics if `f` stopped following the schedule: asked for a switch the schedule doesn't
+/// have, or one to a thread that has finished or doesn't exist.
+pub fn replay<'env, F>(schedule: &[usize], f: F)
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>),
+{
+    let mut state = State::new(0);
+    state.replay = Some(schedule.iter().copied().collect());
+    let (state, result) = execute(state, f);
+    if let Err(e) = result {
+        panic::resume_unwind(e);
+    }
+    if let Some(at) = state.diverged {
+        panic!("sched: the run left the replayed schedule at switch {}", at);
+    }
+}
+
+/// Runs `f` under [`run`] with `SCHED_ITERATIONS` seeds (100 by default, 10 under Miri) from
+/// `SCHED_SEED` on (0 by default). On a panic it says which seed to rerun it with, and prints
+/// the last `SCHED_TRACE` atomic accesses (20 by default) and the schedule; set
+/// `SCHED_REPLAY` to that schedule to [`replay`] just that run.
+pub fn check<'env, F>(f: F)
+where
+    F: for<'scope> Fn(&Scope<'scope, 'env>) + panic::RefUnwindSafe,
+{
+    let var = |name, default| {
+        std::env::var(name).map_or(default, |v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("{} isn't a number", name))
+        })
+    };
+    if let Ok(schedule) = std::env::var("SCHED_REPLAY") {
+        let schedule = decode(&schedule).expect("SCHED_REPLAY isn't a schedule");
+        return replay(&schedule, &f);
+    }
+    let first = var("SCHED_SEED", 0);
+    let iterations = var("SCHED_ITERATIONS", if cfg!(miri) { 10 } else { 100 });
+    let trace_len = var("SCHED_TRACE", 20) as usize;
+    for seed in first..first + iterations {
+        let recording = record(seed, trace_len, &f);
+        if let Some(e) = recording.panic {
+            eprintln!(
+                "sched: failed with seed {}; rerun with SCHED_SEED={}",
+                seed, seed
+            );
+            eprintln!(
+                "sched: the last atomic accesses, then the schedule for SCHED_REPLAY:\n{}",
+                Recording {
+                    panic: None,
+                    ..recording
+                }
+            );
+            panic::resume_unwind(e);
+        }
+    }
+}
+
+pub fn spin_loop() {
+    switch();
+    std::hint::spin_loop();
+}
+
+pub fn yield_now() {
+    if is_scheduled() {
+        switch();
+    } else {
+        thread::yield_now();
+    }
+}
+
+/// std's atomics, with a switch before every access, and every access traced when the run
+/// is being [`record`](super::record)ed.
+pub mod atomic {
+    use super::{switch, trace};
+    use std::sync::atomic as std_atomic;
+    pub use std::sync::atomic::Ordering;
+
+    pub fn fence(order: Ordering) {
+        switch();
+        #[cfg(feature = "strict-orderings")]
+        crate::strict_orderings::fence(order);
+        std_atomic::fence(order);
+        trace("fence", None, order, None, None);
+    }
+
+    macro_rules! atomic {
+        ($name:ident, $t:ty $(, $rmw:ident)*) => {
+            #[derive(Debug, Default)]
+            pub struct $name(std_atomic::$name);
+
+            impl $name {
+                pub const fn new(v: $t) -> Self {
+                    Self(std_atomic::$name::new(v))
+                }
+
+                fn location(&self) -> Option<usize> {
+                    Some(self as *const Self as usize)
+                }
+
+                pub fn load(&self, order: Ordering) -> $t {
+                    switch();
+                    let v = self.0.load(order);
+                    trace("load", self.location(), order, Some(v as u64), None);
+                    v
+                }
+
+                pub fn store(&self, v: $t, order: Ordering) {
+                    switch();
+                    #[cfg(feature = "strict-orderings")]
+                    crate::strict_orderings::store(self as *const Self as usize, order);
+                    self.0.store(v, order);
+                    trace("store", self.location(), order, None, Some(v as u64));
+                }
+
+                pub fn swap(&self, v: $t, order: Ordering) -> $t {
+                    switch();
+                    #[cfg(feature = "strict-orderings")]
+                    crate::strict_orderings::rmw(self as *const Self as usize, order);
+                    let old = self.0.swap(v, order);
+                    trace("swap", self.location(), order, Some(old as u64), Some(v as u64));
+                    old
+                }
+
+                pub fn compare_exchange(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    switch();
+                    let r = self.0.compare_exchange(current, new, success, failure);
+                    self.trace_cas("compare_exchange", r, new, success, failure);
+                    r
+                }
+
+                pub fn compare_exchange_weak(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    switch();
+                    let r = self.0.compare_exchange_weak(current, new, success, failure);
+                    self.trace_cas("compare_exchange_weak", r, new, success, failure);
+                    r
+                }
+
+                fn trace_cas(
+                    &self,
+                    op: &'static str,
+                    r: Result<$t, $t>,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) {
+                    #[cfg(feature = "strict-orderings")]
+                    {
+                        crate::strict_orderings::compare_exchange(success, failure);
+                        if r.is_ok() {
+                            crate::strict_orderings::rmw(self as *const Self as usize, success);
+                        }
+                    }
+                    match r {
+                        Ok(old) => trace(op, self.location(), success, Some(old as u64), Some(new as u64)),
+                        Err(old) => trace(op, self.location(), failure, Some(old as u64), None),
+                    }
+                }
+
+                pub fn get_mut(&mut self) -> &mut $t {
+                    self.0.get_mut()
+                }
+
+                pub fn into_inner(self) -> $t {
+                    self.0.into_inner()
+                }
+
+                $(
+                    pub fn $rmw(&self, v: $t, order: Ordering) -> $t {
+                        switch();
+                        #[cfg(feature = "strict-orderings")]
+                        crate::strict_orderings::rmw(self as *const Self as usize, order);
+                        let old = self.0.$rmw(v, order);
+                        // Nothing else runs until the next switch, so this is what it wrote.
+                        let new = self.0.load(Ordering::Relaxed);
+                        trace(stringify!($rmw), self.location(), order, Some(old as u64), Some(new as u64));
+                        old
+                    }
+                )*
+            }
+        };
+    }
+
+    atomic!(AtomicBool, bool, fetch_and, fetch_or, fetch_xor);
+    atomic!(
+        AtomicUsize,
+        usize,
+        fetch_add,
+        fetch_sub,
+        fetch_and,
+        fetch_or,
+        fetch_xor,
+        fetch_max,
+        fetch_min
+    );
+    atomic!(
+        AtomicU64, u64, fetch_add, fetch_sub, fetch_and, fetch_or, fetch_xor, fetch_max, fetch_min
+    );
+
+    #[derive(Debug, Default)]
+    pub struct AtomicPtr<T>(std_atomic::AtomicPtr<T>);
+
+    impl<T> AtomicPtr<T> {
+        pub const fn new(p: *mut T) -> Self {
+            Self(std_atomic::AtomicPtr::new(p))
+        }
+
+        fn location(&self) -> Option<usize> {
+            Some(self as *const Self as usize)
+        }
+
+        pub fn load(&self, order: Ordering) -> *mut T {
+            switch();
+            let p = self.0.load(order);
+            trace("load", self.location(), order, Some(p as u64), None);
+            p
+        }
+
+        pub fn store(&self, p: *mut T, order: Ordering) {
+            switch();
+            #[cfg(feature = "strict-orderings")]
+            crate::strict_orderings::store(self as *const Self as usize, order);
+            self.0.store(p, order);
+            trace("store", self.location(), order, None, Some(p as u64));
+        }
+
+        pub fn swap(&self, p: *mut T, order: Ordering) -> *mut T {
+            switch();
+            #[cfg(feature = "strict-orderings")]
+            crate::strict_orderings::rmw(self as *const Self as usize, order);
+            let old = self.0.swap(p, order);
+            trace(
+                "swap",
+                self.location(),
+                order,
+                Some(old as u64),
+                Some(p as u64),
+            );
+            old
+        }
+
+        pub fn compare_exchange(
+            &self,
+            current: *mut T,
+            new: *mut T,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<*mut T, *mut T> {
+            switch();
+            let r = self.0.compare_exchange(current, new, success, failure);
+            self.trace_cas("compare_exchange", r, new, success, failure);
+            r
+        }
+
+        pub fn compare_exchange_weak(
+            &self,
+            current: *mut T,
+            new: *mut T,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<*mut T, *mut T> {
+            switch();
+            let r = self.0.compare_exchange_weak(current, new, success, failure);
+            self.trace_cas("compare_exchange_weak", r, new, success, failure);
+            r
+        }
+
+        fn trace_cas(
+            &self,
+            op: &'static str,
+            r: Result<*mut T, *mut T>,
+            new: *mut T,
+            success: Ordering,
+            failure: Ordering,
+        ) {
+            #[cfg(feature = "strict-orderings")]
+            {
+                crate::strict_orderings::compare_exchange(success, failure);
+                if r.is_ok() {
+                    crate::strict_orderings::rmw(self as *const Self as usize, success);
+                }
+            }
+            match r {
+                Ok(old) => trace(
+                    op,
+                    self.location(),
+                    success,
+                    Some(old as u64),
+                    Some(new as u64),
+                ),
+                Err(old) => trace(op, self.location(), failure, Some(old as u64), None),
+            }
+        }
+
+        pub fn get_mut(&mut self) -> &mut *mut T {
+            self.0.get_mut()
+        }
+
+        pub fn into_inner(self) -> *mut T {
+            self.0.into_inner()
+        }
+    }
+}
+
+/// std's Mutex, which under [`run`] tries the lock and switches until it gets it, rather
+/// than block a thread that holds the schedule.
+#[derive(Debug, Default)]
+pub struct Mutex<T: ?Sized>(std_sync::Mutex<T>);
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    guard: std_sync::MutexGuard<'a, T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(v: T) -> Self {
+        Self(std_sync::Mutex::new(v))
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    fn wrap<'a>(
+        &'a self,
+        r: LockResult<std_sync::MutexGuard<'a, T>>,
+    ) -> LockResult<MutexGuard<'a, T>> {
+        match r {
+            Ok(guard) => Ok(MutexGuard { mutex: self, guard }),
+            Err(e) => Err(PoisonError::new(MutexGuard {
+                mutex: self,
+                guard: e.into_inner(),
+            })),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        if !is_scheduled() {
+            return self.wrap(self.0.lock());
+        }
+        loop {
+            switch();
+            match self.0.try_lock() {
+                Ok(guard) => return self.wrap(Ok(guard)),
+                Err(TryLockError::Poisoned(e)) => return self.wrap(Err(e)),
+                Err(TryLockError::WouldBlock) => {}
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.0.get_mut()
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Whether a [`Condvar::wait_timeout`] timed out.
+#[derive(Debug, Clone, Copy)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// std's Condvar, whose waits under [`run`] release the lock, switch and take it back, which
+/// is a spurious wakeup as far as the waiter can tell.
+#[derive(Debug, Default)]
+pub struct Condvar(std_sync::Condvar);
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self(std_sync::Condvar::new())
+    }
+
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex;
+        if is_scheduled() {
+            drop(guard);
+            return mutex.lock();
+        }
+        mutex.wrap(self.0.wait(guard.guard))
+    }
+
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let mutex = guard.mutex;
+        if is_scheduled() {
+            drop(guard);
+            return match mutex.lock() {
+                Ok(guard) => Ok((guard, WaitTimeoutResult(false))),
+                Err(e) => Err(PoisonError::new((e.into_inner(), WaitTimeoutResult(false)))),
+            };
+        }
+        match self.0.wait_timeout(guard.guard, timeout) {
+            Ok((guard, r)) => Ok((
+                MutexGuard { mutex, guard },
+                WaitTimeoutResult(r.timed_out()),
+            )),
+            Err(e) => {
+                let (guard, r) = e.into_inner();
+                Err(PoisonError::new((
+                    MutexGuard { mutex, guard },
+                    WaitTimeoutResult(r.timed_out()),
+                )))
+            }
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.0.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.0.notify_all();
+    }
+}
+
+#[test]
+fn sched_same_seed_same_interleaving() {
+    use atomic::{AtomicUsize, Ordering};
+
+    // Two unsynchronized increments: which seeds lose one to the other is up to the
+    // interleaving, and so the same every time.
+    let lost_with = |seed| {
+        let n = AtomicUsize::new(0);
+        let schedule = run(seed, |s| {
+            let increment = || {
+                let v = n.load(Ordering::Relaxed);
+                n.store(v + 1, Ordering::Relaxed);
+            };
+            s.spawn(increment);
+            increment();
+        });
+        (n.into_inner() == 1, schedule)
+    };
+    let runs: Vec<_> = (0..32).map(lost_with).collect();
+    assert!(runs.iter().any(|(lost, _)| *lost));
+    assert!(runs.iter().any(|(lost, _)| !*lost));
+    for (seed, run) in runs.iter().enumerate() {
+        assert_eq!(&lost_with(seed as u64), run);
+    }
+}
+
+#[test]
+fn sched_guided_runs_follow_their_choices() {
+    use atomic::{AtomicUsize, Ordering};
+
+    let lost_update = |choices: &[usize]| {
+        let n = AtomicUsize::new(0);
+        let schedule = run_guided(choices, |s| {
+            let increment = || {
+                let v = n.load(Ordering::Relaxed);
+                n.store(v + 1, Ordering::Relaxed);
+            };
+            s.spawn(increment);
+            increment();
+        });
+        (n.into_inner() == 1, schedule)
+    };
+    // The main thread until it's done, then the other: nothing interleaves.
+    assert_eq!(lost_update(&[0, 0]), (false, vec![0, 0, 1, 1, 1]));
+    // Both loads before either store.
+    assert!(lost_update(&[0, 1, 1, 0]).0);
+    assert_eq!(lost_update(&[0, 1, 1, 0]), lost_update(&[2, 3, 5, 4]));
+}
+
+#[test]
+fn sched_check_names_the_failing_seed() {
+    use crate::array_queue::ArrayQueue;
+
+    // The queue itself holds up under every seed...
+    check(|s| {
+        // Arcs, since the threads may outlive this closure's locals, as in thread::scope.
+        let q = Arc::new(ArrayQueue::new(2));
+        let producer = s.spawn({
+            let q = Arc::clone(&q);
+            move || {
+                for i in 0..4 {
+                    while q.push(i).is_err() {
+                        yield_now();
+                    }
+                }
+            }
+        });
+        let mut next = 0;
+        while next < 4 {
+            if let Some(v) = q.pop() {
+                assert_eq!(v, next);
+                next += 1;
+            }
+        }
+        producer.join().unwrap();
+    });
+
+    // ...while an assertion that only some interleavings break fails on one of them.
+    let failed = panic::catch_unwind(|| {
+        check(|s| {
+            let flag = Arc::new(atomic::AtomicBool::new(false));
+            s.spawn({
+                let flag = Arc::clone(&flag);
+                move || flag.store(true, atomic::Ordering::Relaxed)
+            });
+            assert!(!flag.load(atomic::Ordering::Relaxed), "saw the store");
+        })
+    });
+    assert!(failed.is_err());
+}
+
+#[test]
+fn sched_replay_takes_the_recorded_interleaving() {
+    use atomic::{AtomicUsize, Ordering};
+
+    let lost_update = |s: &Scope<'_, '_>| {
+        let n = Arc::new(AtomicUsize::new(0));
+        let increment = {
+            let n = Arc::clone(&n);
+            move || {
+                let v = n.load(Ordering::Relaxed);
+                n.store(v + 1, Ordering::Relaxed);
+            }
+        };
+        let t = s.spawn(increment.clone());
+        increment();
+        t.join().unwrap();
+        assert_eq!(n.load(Ordering::Relaxed), 2, "lost an update");
+    };
+    let failed = (0..64)
+        .map(|seed| record(seed, 100, lost_update))
+        .find(|r| r.panic.is_some())
+        .expect("no seed lost an update");
+    // Each thread's load and store, then the final load.
+    assert_eq!(failed.trace.len(), 5);
+    assert!(failed.trace[..4].iter().all(|e| e.location == Some(0)));
+    assert!(failed.to_string().contains("t1 load a0 Relaxed: 0"));
+    assert_eq!(
+        decode(&encode(&failed.schedule)),
+        Some(failed.schedule.clone())
+    );
+
+    let schedule = &failed.schedule;
+    let replayed = panic::catch_unwind(|| replay(schedule, lost_update));
+    assert!(replayed.is_err());
+    // A schedule that runs out before the run does can't be followed.
+    let diverged = panic::catch_unwind(|| {
+        replay(&[], |s| s.spawn(|| ()).join().unwrap());
+    })
+    .unwrap_err();
+    assert!(diverged
+        .downcast_ref::<String>()
+        .is_some_and(|m| m.contains("left the replayed schedule")));
+}
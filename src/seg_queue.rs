@@ -0,0 +1,362 @@
+use crate::cache_padded::CachePadded;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
+use std::thread;
+
+// Indices advance by 1 << SHIFT per slot; the low bit of the head index records whether the
+// head block is known to have a successor, which lets pop skip the tail check.
+const SHIFT: usize = 1;
+const HAS_NEXT: usize = 1;
+// Each lap of LAP indices maps onto one block. The last index of a lap has no slot and marks
+// "the next block is being installed".
+const LAP: usize = 32;
+const BLOCK_CAP: usize = LAP - 1;
+
+// Slot states.
+const WRITE: usize = 1;
+const READ: usize = 2;
+const DESTROY: usize = 4;
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    fn wait_write(&self) {
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            thread::yield_now();
+        }
+    }
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Self> {
+        // Safety: null pointers, zero states and uninit values are all valid when zeroed.
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+
+    fn wait_next(&self) -> *mut Self {
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            thread::yield_now();
+        }
+    }
+
+    // Frees the block once every reader of slots start.. is done with it. A reader that is
+    // still busy gets the DESTROY bit and takes over the job when it finishes.
+    unsafe fn destroy(this: *mut Self, start: usize) {
+        // The last slot's reader is the one that calls destroy(.., 0), so skip it.
+        for i in start..BLOCK_CAP - 1 {
+            let slot = &(*this).slots[i];
+            if slot.state.load(Ordering::Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0
+            {
+                return;
+            }
+        }
+        drop(Box::from_raw(this));
+    }
+}
+
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+/// An unbounded multi-producer multi-consumer queue built from linked blocks of slots.
+///
+/// Allocation is amortized over `BLOCK_CAP` pushes, and a block is freed by whichever
+/// consumer finishes with it last.
+pub struct SegQueue<T> {
+    // Padded so producers and consumers don't false-share.
+    head: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T>>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for SegQueue<T> {}
+unsafe impl<T: Send> Sync for SegQueue<T> {}
+
+impl<T> SegQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: CachePadded::new(Position {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(ptr::null_mut()),
+            }),
+            tail: CachePadded::new(Position {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(ptr::null_mut()),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+        loop {
+            let offset = (tail >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Someone else is installing the next block.
+                thread::yield_now();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+            // Allocate the next block before claiming the last slot, so the window in which
+            // others wait for it stays short.
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+            if block.is_null() {
+                // First push ever: install the first block.
+                let new = Box::into_raw(Block::new());
+                if self
+                    .tail
+                    .block
+                    .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                } else {
+                    next_block = Some(unsafe { Box::from_raw(new) });
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+            let new_tail = tail + (1 << SHIFT);
+            // SeqCst: pairs with the fence in pop so an empty check can't miss this push.
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                new_tail,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next_block = Box::into_raw(next_block.take().unwrap());
+                        self.tail.block.store(next_block, Ordering::Release);
+                        self.tail
+                            .index
+                            .store(new_tail.wrapping_add(1 << SHIFT), Ordering::Release);
+                        (*block).next.store(next_block, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    slot.value.get().write(MaybeUninit::new(value));
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+                    return;
+                },
+                Err(t) => {
+                    tail = t;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    std::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+        loop {
+            let offset = (head >> SHIFT) % LAP;
+            if offset == BLOCK_CAP {
+                // Someone else is moving head to the next block.
+                thread::yield_now();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+            let mut new_head = head + (1 << SHIFT);
+            if new_head & HAS_NEXT == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+                if head >> SHIFT == tail >> SHIFT {
+                    return None;
+                }
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    new_head |= HAS_NEXT;
+                }
+            }
+            if block.is_null() {
+                // The first push has claimed a slot but not installed the block yet.
+                thread::yield_now();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+            match self.head.index.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !HAS_NEXT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= HAS_NEXT;
+                        }
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    slot.wait_write();
+                    let value = slot.value.get().read().assume_init();
+                    if offset + 1 == BLOCK_CAP {
+                        Block::destroy(block, 0);
+                    } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                        Block::destroy(block, offset + 1);
+                    }
+                    return Some(value);
+                },
+                Err(h) => {
+                    head = h;
+                    block = self.head.block.load(Ordering::Acquire);
+                    std::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+impl<T> SegQueue<T> {
+    /// The number of items, which may be out of date by the time it's returned.
+    pub fn len(&self) -> usize {
+        loop {
+            let mut tail = self.tail.index.load(Ordering::SeqCst);
+            let mut head = self.head.index.load(Ordering::SeqCst);
+            // Both from one moment, or we try again.
+            if self.tail.index.load(Ordering::SeqCst) != tail {
+                continue;
+            }
+            tail &= !HAS_NEXT;
+            head &= !HAS_NEXT;
+            // An index parked on the end of a block is at the start of the next one.
+            if (tail >> SHIFT) % LAP == BLOCK_CAP {
+                tail = tail.wrapping_add(1 << SHIFT);
+            }
+            if (head >> SHIFT) % LAP == BLOCK_CAP {
+                head = head.wrapping_add(1 << SHIFT);
+            }
+            // Counted from the start of head's block, less the one index per block that
+            // isn't a slot.
+            let lap = (head >> SHIFT) / LAP;
+            let tail = tail.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+            let head = head.wrapping_sub((lap * LAP) << SHIFT) >> SHIFT;
+            return tail - head - tail / LAP;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let head = self.head.index.load(Ordering::SeqCst);
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        head >> SHIFT == tail >> SHIFT
+    }
+
+    /// Pops items until it finds the queue empty, for flushing what's left at shutdown.
+    /// Pushes made meanwhile are drained too, so it ends once producers stop or fall behind.
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+impl<T> Default for SegQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SegQueue<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut() & !HAS_NEXT;
+        let tail = *self.tail.index.get_mut() & !HAS_NEXT;
+        let mut block = *self.head.block.get_mut();
+        unsafe {
+            while head != tail {
+                let offset = (head >> SHIFT) % LAP;
+                if offset < BLOCK_CAP {
+                    let slot = &(*block).slots[offset];
+                    ptr::drop_in_place((*slot.value.get()).as_mut_ptr());
+                } else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head = head.wrapping_add(1 << SHIFT);
+            }
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+        }
+    }
+}
+
+#[test]
+fn seg_queue_mpmc() {
+    use std::sync::atomic::AtomicUsize;
+    let n = if cfg!(miri) { 200 } else { 5000 };
+    let q = &SegQueue::new();
+    let popped = &AtomicUsize::new(0);
+    let sum = &AtomicUsize::new(0);
+    thread::scope(|s| {
+        for p in 0..2 {
+            s.spawn(move || {
+                for i in 0..n {
+                    q.push(p * n + i);
+                }
+            });
+        }
+        for _ in 0..2 {
+            s.spawn(move || {
+                while popped.load(Ordering::Relaxed) < 2 * n {
+                    match q.pop() {
+                        Some(v) => {
+                            sum.fetch_add(v, Ordering::Relaxed);
+                            popped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(sum.load(Ordering::Relaxed), (0..2 * n).sum::<usize>());
+    assert!(q.pop().is_none());
+    assert_eq!((q.len(), q.is_empty()), (0, true));
+}
+
+#[test]
+fn seg_queue_drops_leftovers() {
+    let item = std::sync::Arc::new(());
+    let q = SegQueue::new();
+    for _ in 0..100 {
+        q.push(std::sync::Arc::clone(&item));
+    }
+    for _ in 0..40 {
+        q.pop().unwrap();
+    }
+    assert_eq!(std::sync::Arc::strong_count(&item), 61);
+    assert_eq!(q.len(), 60);
+    q.drain().take(30).for_each(drop);
+    assert_eq!((q.len(), q.is_empty()), (30, false));
+    drop(q);
+    assert_eq!(std::sync::Arc::strong_count(&item), 1);
+}
@@ -0,0 +1,244 @@
+use crate::event_count::EventCount;
+use crate::mpmc::TrySendError;
+use crate::mpsc::{RecvError, SendError, TryRecvError};
+use crate::parker::Parker;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A channel end that [`Select`] can receive from.
+pub trait SelectRecv {
+    type Msg;
+
+    fn try_recv_select(&self) -> Result<Self::Msg, TryRecvError>;
+
+    /// Notified whenever `try_recv_select` may have stopped returning `Empty`.
+    fn recv_events(&self) -> &EventCount;
+}
+
+/// A channel end that [`Select`] can send on.
+pub trait SelectSend {
+    type Msg;
+
+    fn try_send_select(&self, msg: Self::Msg) -> Result<(), TrySendError<Self::Msg>>;
+
+    /// Notified whenever `try_send_select` may have stopped returning `Full`.
+    fn send_events(&self) -> &EventCount;
+}
+
+// Each operation makes one non-blocking attempt, running its handler if it gets anywhere.
+type Attempt<'a, R> = Box<dyn FnMut() -> Option<R> + 'a>;
+
+// Which operation gets tried first, rotated so an always-ready one can't starve the rest.
+static NEXT_START: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits on several channel operations and completes exactly one of them.
+///
+/// Each operation comes with a handler, and the handler of the one that completes produces
+/// the result; a disconnected channel counts as ready and hands its handler the error.
+/// While nothing is ready the thread sleeps on a [`Parker`] whose waker every channel's
+/// [`EventCount`] has been told to [`watch`](EventCount::watch).
+///
+/// Messages of send operations that didn't complete are dropped with the `Select`. Two
+/// selects on opposite ends of a zero-capacity channel can't complete each other, since
+/// neither is ever blocked in the channel itself.
+pub struct Select<'a, R> {
+    attempts: Vec<Attempt<'a, R>>,
+    events: Vec<&'a EventCount>,
+}
+
+impl<'a, R> Select<'a, R> {
+    pub fn new() -> Self {
+        Self {
+            attempts: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Adds a receive on `rx`, handled by `f`.
+    pub fn recv<C: SelectRecv>(
+        mut self,
+        rx: &'a C,
+        f: impl FnOnce(Result<C::Msg, RecvError>) -> R + 'a,
+    ) -> Self {
+        let mut f = Some(f);
+        self.attempts.push(Box::new(move || {
+            let result = match rx.try_recv_select() {
+                Ok(msg) => Ok(msg),
+                Err(TryRecvError::Disconnected) => Err(RecvError),
+                Err(TryRecvError::Empty) => return None,
+            };
+            Some(f.take().expect("an operation completes at most once")(
+                result,
+            ))
+        }));
+        self.events.push(rx.recv_events());
+        self
+    }
+
+    /// Adds a send of `msg` on `tx`, handled by `f`.
+    pub fn send<C: SelectSend>(
+        mut self,
+        tx: &'a C,
+        msg: C::Msg,
+        f: impl FnOnce(Result<(), SendError<C::Msg>>) -> R + 'a,
+    ) -> Self
+    where
+        C::Msg: 'a,
+    {
+        let mut pending = Some((msg, f));
+        self.attempts.push(Box::new(move || {
+            let (msg, f) = pending.take().expect("an operation completes at most once");
+            match tx.try_send_select(msg) {
+                Ok(()) => Some(f(Ok(()))),
+                Err(TrySendError::Disconnected(msg)) => Some(f(Err(SendError(msg)))),
+                Err(TrySendError::Full(msg)) => {
+                    pending = Some((msg, f));
+                    None
+                }
+            }
+        }));
+        self.events.push(tx.send_events());
+        self
+    }
+
+    fn try_once(&mut self) -> Option<R> {
+        let n = self.attempts.len();
+        if n == 0 {
+            return None;
+        }
+        let start = NEXT_START.fetch_add(1, Ordering::Relaxed) % n;
+        (0..n).find_map(|i| (self.attempts[(start + i) % n])())
+    }
+
+    /// Completes an operation that's ready right now, or runs `f` if none is.
+    pub fn default(mut self, f: impl FnOnce() -> R) -> R {
+        self.try_once().unwrap_or_else(f)
+    }
+
+    /// Waits until an operation completes.
+    pub fn wait(self) -> R {
+        assert!(!self.attempts.is_empty(), "waiting on no operations");
+        self.run(None).expect("only a deadline gives up")
+    }
+
+    /// Waits until an operation completes, or runs `f` once `timeout` has passed.
+    pub fn timeout(self, timeout: Duration, f: impl FnOnce() -> R) -> R {
+        self.run(Instant::now().checked_add(timeout))
+            .unwrap_or_else(f)
+    }
+
+    /// Waits until an operation completes, or runs `f` at `deadline`.
+    pub fn deadline(self, deadline: Instant, f: impl FnOnce() -> R) -> R {
+        self.run(Some(deadline)).unwrap_or_else(f)
+    }
+
+    // No deadline means waiting forever.
+    fn run(mut self, deadline: Option<Instant>) -> Option<R> {
+        let parker = Parker::new();
+        let waker = parker.unparker().waker();
+        loop {
+            if let Some(result) = self.try_once() {
+                return Some(result);
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return None;
+            }
+            let keys: Vec<_> = self.events.iter().map(|e| e.watch(&waker)).collect();
+            let result = self.try_once();
+            if result.is_none() {
+                match deadline {
+                    None => parker.park(),
+                    Some(deadline) => parker.park_deadline(deadline),
+                }
+            }
+            for (events, key) in self.events.iter().zip(keys) {
+                events.unwatch(key);
+            }
+            if result.is_some() {
+                return result;
+            }
+        }
+    }
+}
+
+impl<R> Default for Select<'_, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn select_completes_one_operation() {
+    use crate::{mpmc, mpsc};
+    let (tx1, rx1) = mpsc::channel::<u32>();
+    let (tx2, rx2) = mpmc::bounded::<&str>(1);
+    let sender = std::thread::spawn(move || {
+        std::thread::yield_now();
+        tx2.send("two").unwrap();
+        tx2
+    });
+    let got = Select::new()
+        .recv(&rx1, |m| format!("{:?}", m))
+        .recv(&rx2, |m| m.unwrap().to_string())
+        .wait();
+    assert_eq!(got, "two");
+    let tx2 = sender.join().unwrap();
+
+    // Only one of two ready operations completes.
+    tx1.send(1).unwrap();
+    tx2.send("again").unwrap();
+    let got = Select::new().recv(&rx1, |_| 1).recv(&rx2, |_| 2).wait();
+    let left = if got == 1 {
+        rx2.try_recv().is_ok()
+    } else {
+        rx1.try_recv().is_ok()
+    };
+    assert!(left);
+
+    // A full channel isn't ready to send on; the default runs instead.
+    tx2.send("fill").unwrap();
+    let got = Select::new()
+        .send(&tx2, "more", |r| r.is_ok())
+        .default(|| false);
+    assert!(!got);
+    let got = Select::new()
+        .recv(&rx1, |_| "recv")
+        .timeout(Duration::from_millis(10), || "timeout");
+    assert_eq!(got, "timeout");
+
+    // A disconnected channel is ready, with the error.
+    drop(tx1);
+    let got = Select::new()
+        .recv(&rx1, |m| m)
+        .timeout(Duration::from_secs(10), || Ok(0));
+    assert_eq!(got, Err(RecvError));
+}
+
+#[test]
+fn select_send_wakes_when_room_frees_up() {
+    use crate::mpmc;
+    let (tx, rx) = mpmc::bounded(1);
+    tx.send(0).unwrap();
+    let receiver = std::thread::spawn(move || {
+        std::thread::yield_now();
+        (rx.recv(), rx.recv())
+    });
+    let sent = Select::new().send(&tx, 1, |r| r.is_ok()).wait();
+    assert!(sent);
+    assert_eq!(receiver.join().unwrap(), (Ok(0), Ok(1)));
+}
+
+#[test]
+fn select_on_zero_capacity_channel() {
+    use crate::mpmc;
+    let (tx, rx) = mpmc::bounded(0);
+    let sender = std::thread::spawn(move || tx.send(7));
+    let got = Select::new().recv(&rx, |m| m).wait();
+    assert_eq!(got, Ok(7));
+    assert_eq!(sender.join().unwrap(), Ok(()));
+
+    let (tx, rx) = mpmc::bounded(0);
+    let receiver = std::thread::spawn(move || rx.recv());
+    assert!(Select::new().send(&tx, 8, |r| r.is_ok()).wait());
+    assert_eq!(receiver.join().unwrap(), Ok(8));
+}
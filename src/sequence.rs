@@ -0,0 +1,111 @@
+use crate::cache_padded::CachePadded;
+use crate::thread_id;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const DEFAULT_BLOCK: u64 = 1024;
+
+/// Hands out unique `u64` IDs, each thread's in increasing order.
+///
+/// Rather than every thread hitting one counter, each takes a block of `block_size` IDs from
+/// it with one `fetch_add` and hands those out from a slot of its own. So IDs from different
+/// threads interleave in no particular order, and a thread that exits or moves to a newer
+/// block leaves the rest of its block unused. [`dense`](Self::dense) makes a generator with
+/// blocks of one: a single shared counter, where the IDs come out in order with no gaps.
+pub struct SequenceGenerator {
+    next_block: CachePadded<AtomicU64>,
+    block: u64,
+    // The next ID to hand out from each slot's block; a multiple of the block size once the
+    // block is used up, or before it has one. Threads pick a slot by their thread_id.
+    slots: Box<[CachePadded<AtomicU64>]>,
+}
+
+impl SequenceGenerator {
+    pub fn new() -> Self {
+        Self::with_block_size(DEFAULT_BLOCK)
+    }
+
+    /// A generator that hands out blocks of `block_size` IDs.
+    ///
+    /// # Panics
+    ///
+    /// If `block_size` is zero.
+    pub fn with_block_size(block_size: u64) -> Self {
+        assert!(block_size > 0, "zero block size");
+        let slots = if block_size == 1 {
+            0
+        } else {
+            std::thread::available_parallelism().map_or(4, |n| n.get()) * 4
+        };
+        Self {
+            next_block: CachePadded::new(AtomicU64::new(0)),
+            block: block_size,
+            slots: (0..slots)
+                .map(|_| CachePadded::new(AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// A generator whose IDs are 0, 1, 2, ... in the order `next` is called.
+    pub fn dense() -> Self {
+        Self::with_block_size(1)
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block
+    }
+
+    pub fn next(&self) -> u64 {
+        // Relaxed throughout: an ID only has to be unique, and every RMW on one word sees the
+        // ones before it.
+        if self.slots.is_empty() {
+            return self.next_block.fetch_add(1, Ordering::Relaxed);
+        }
+        let index = thread_id::current().unwrap_or(0) % self.slots.len();
+        let slot = &self.slots[index];
+        let mut next = slot.load(Ordering::Relaxed);
+        while !next.is_multiple_of(self.block) {
+            match slot.compare_exchange_weak(next, next + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return next,
+                Err(n) => next = n,
+            }
+        }
+        let base = self.next_block.fetch_add(self.block, Ordering::Relaxed);
+        // Another thread on this slot may have stored a block meanwhile. Keeping the later
+        // one means the slot only ever moves forward, so no ID comes out of it twice and a
+        // thread's IDs keep increasing.
+        slot.fetch_max(base + 1, Ordering::Relaxed);
+        base
+    }
+}
+
+impl Default for SequenceGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn sequence_ids_are_unique_and_increasing() {
+    use std::collections::HashSet;
+
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    for ids in [
+        SequenceGenerator::with_block_size(16),
+        SequenceGenerator::dense(),
+    ] {
+        let per_thread: Vec<Vec<u64>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| (0..n).map(|_| ids.next()).collect()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for mine in &per_thread {
+            assert!(mine.windows(2).all(|w| w[0] < w[1]));
+        }
+        let all: HashSet<_> = per_thread.iter().flatten().copied().collect();
+        assert_eq!(all.len(), 4 * n);
+        if ids.block_size() == 1 {
+            assert_eq!(all, (0..4 * n as u64).collect());
+        }
+    }
+}
@@ -0,0 +1,728 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::{align_of, size_of};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backoff::Backoff;
+
+// Primitives for processes sharing memory, for pipelines split across processes. An `Arena`
+// is a mapping of a named shared-memory object (a file under /dev/shm, as shm_open makes) or
+// an anonymous one that a fork shares; a `Semaphore` and an SPSC `Ring` are laid out in it.
+// Processes map the arena at different addresses, so everything in it is found by its offset
+// from the start, and nothing in it can hold a pointer.
+//
+// Everything here is laid out with repr(C), and every header starts with a magic number and
+// a version, so a process built from a different version of this module fails to open what
+// another made instead of misreading it. The layout is still the target's: processes sharing
+// an arena have to be built for the same architecture.
+//
+// The handshake, for the arena and for each object in it:
+//
+// - The memory starts zeroed, as a new shared-memory object or a fresh allocation is.
+// - The creator fills in the header with its state word left at 0, then stores READY to the
+//   state word with Release.
+// - An opener waits, up to OPEN_TIMEOUT, for an Acquire load of the state word to read
+//   READY, and only then reads the rest of the header and checks the magic number, version
+//   and sizes against what it expects.
+//
+// So an opener never sees a half-written header, and opening an offset that isn't what the
+// opener thinks it is fails with InvalidData rather than handing back garbage. The offsets of
+// what the creator allocates are its to pass on, on a command line or through a ring.
+//
+// The atomics are std's rather than sync_shim's: loom and shuttle can't model memory shared
+// with another process. A wait that outlasts its spinning sleeps in a futex on Linux, which
+// other processes can wake, and elsewhere polls with a short sleep.
+
+const VERSION: u32 = 1;
+const READY: u32 = 1;
+
+const ARENA_MAGIC: u64 = u64::from_le_bytes(*b"atomshm\0");
+const SEMAPHORE_MAGIC: u64 = u64::from_le_bytes(*b"atomsem\0");
+const RING_MAGIC: u64 = u64::from_le_bytes(*b"atomring");
+
+/// How long opening something waits for its creator to finish the handshake.
+pub const OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What every header starts with.
+#[repr(C)]
+struct Init {
+    state: AtomicU32,
+    version: u32,
+    magic: u64,
+}
+
+impl Init {
+    // For the creator, once the rest of the header is written. Safety: `init` is the header
+    // of something no opener has seen READY in yet.
+    unsafe fn publish(init: *mut Self, magic: u64) {
+        // Written before the Release store, so an opener sees them once it sees READY.
+        (*init).version = VERSION;
+        (*init).magic = magic;
+        (*init).state.store(READY, Ordering::Release);
+    }
+
+    // For an opener, before it reads anything else.
+    fn wait(&self, magic: u64, what: &str) -> io::Result<()> {
+        let deadline = Instant::now() + OPEN_TIMEOUT;
+        let backoff = Backoff::new();
+        while self.state.load(Ordering::Acquire) != READY {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("shm: the {} was never initialized", what),
+                ));
+            }
+            if backoff.is_completed() {
+                thread::sleep(Duration::from_millis(1));
+            } else {
+                backoff.snooze();
+            }
+        }
+        if self.magic != magic {
+            return Err(invalid(format!("shm: no {} there", what)));
+        }
+        if self.version != VERSION {
+            return Err(invalid(format!(
+                "shm: the {} is version {}, and this is version {}",
+                what, self.version, VERSION
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(why: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+#[repr(C)]
+struct ArenaHeader {
+    init: Init,
+    size: u64,
+    // The offset of the first byte not yet allocated.
+    next: AtomicU64,
+}
+
+/// A region of memory shared between processes, allocated from front to back.
+pub struct Arena {
+    base: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    /// Creates the shared-memory object `name`, of `size` bytes including the arena's header,
+    /// and maps it. Fails if it already exists.
+    pub fn create(name: &str, size: usize) -> io::Result<Self> {
+        if size < size_of::<ArenaHeader>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shm: an arena too small for its header",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path(name)?)?;
+        file.set_len(size as u64)?;
+        let arena = Self::map(Some(&file), size)?;
+        arena.init();
+        Ok(arena)
+    }
+
+    /// Maps the shared-memory object `name` another process created, once it's initialized.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path(name)?)?;
+        // The creator may not have sized it yet.
+        let deadline = Instant::now() + OPEN_TIMEOUT;
+        let len = loop {
+            let len = file.metadata()?.len() as usize;
+            if len >= size_of::<ArenaHeader>() {
+                break len;
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "shm: the arena was never sized",
+                ));
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        let arena = Self::map(Some(&file), len)?;
+        arena.header().init.wait(ARENA_MAGIC, "arena")?;
+        if arena.header().size != len as u64 {
+            return Err(invalid(format!(
+                "shm: the arena says it's {} bytes, and it's {}",
+                arena.header().size,
+                len
+            )));
+        }
+        Ok(arena)
+    }
+
+    /// An arena of `size` bytes that isn't named, shared with the processes this one forks
+    /// from now on.
+    pub fn anonymous(size: usize) -> io::Result<Self> {
+        if size < size_of::<ArenaHeader>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shm: an arena too small for its header",
+            ));
+        }
+        let arena = Self::map(None, size)?;
+        arena.init();
+        Ok(arena)
+    }
+
+    /// Removes the name `name`; the memory goes once every process has unmapped it.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        std::fs::remove_file(path(name)?)
+    }
+
+    fn map(file: Option<&File>, len: usize) -> io::Result<Self> {
+        let base = sys::map(file, len)?;
+        Ok(Self { base, len })
+    }
+
+    fn init(&self) {
+        // Safety: nothing else has the header yet, or can read it until it's published.
+        unsafe {
+            let h = self.base as *mut ArenaHeader;
+            (*h).size = self.len as u64;
+            (*h).next = AtomicU64::new(size_of::<ArenaHeader>() as u64);
+            Init::publish(ptr::addr_of_mut!((*h).init), ARENA_MAGIC);
+        }
+    }
+
+    fn header(&self) -> &ArenaHeader {
+        // Safety: every arena's mapped with room for its header.
+        unsafe { &*(self.base as *const ArenaHeader) }
+    }
+
+    /// The arena's size, with its header.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Allocates `size` bytes aligned to `align`, a power of two, from any of the processes
+    /// sharing the arena, and gives the offset of the zeroed memory. Nothing's ever freed.
+    pub fn alloc(&self, size: usize, align: usize) -> io::Result<usize> {
+        assert!(
+            align.is_power_of_two(),
+            "shm: alignment isn't a power of two"
+        );
+        let align = align as u64;
+        let mut offset = 0;
+        self.header()
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                offset = (next + align - 1) & !(align - 1);
+                let end = offset.checked_add(size as u64)?;
+                (end <= self.len as u64).then_some(end)
+            })
+            .map_err(|_| io::Error::new(io::ErrorKind::OutOfMemory, "shm: the arena is full"))?;
+        Ok(offset as usize)
+    }
+
+    // A T at `offset`, if there's room for one there.
+    fn at<T>(&self, offset: usize) -> io::Result<*mut T> {
+        if !offset.is_multiple_of(align_of::<T>())
+            || offset.saturating_add(size_of::<T>()) > self.len
+        {
+            return Err(invalid(format!("shm: no room for an object at {}", offset)));
+        }
+        // Safety: in bounds.
+        Ok(unsafe { self.base.add(offset) } as *mut T)
+    }
+
+    /// A semaphore counting `count`, and its offset, for [`semaphore`](Self::semaphore).
+    pub fn new_semaphore(&self, count: u32) -> io::Result<(usize, &Semaphore)> {
+        let offset = self.alloc(size_of::<Semaphore>(), align_of::<Semaphore>())?;
+        let s: *mut Semaphore = self.at(offset)?;
+        // Safety: just allocated for it, and not published yet.
+        unsafe {
+            (*s).count = AtomicU32::new(count);
+            Init::publish(ptr::addr_of_mut!((*s).init), SEMAPHORE_MAGIC);
+            Ok((offset, &*s))
+        }
+    }
+
+    /// The semaphore at `offset`, waiting for its creator to finish making it.
+    ///
+    /// # Safety
+    ///
+    /// Nothing but an arena's own allocations may have been written at `offset`, as with a
+    /// wrong offset into another object's memory; checks catch most of the rest.
+    pub unsafe fn semaphore(&self, offset: usize) -> io::Result<&Semaphore> {
+        let s = &*self.at::<Semaphore>(offset)?;
+        s.init.wait(SEMAPHORE_MAGIC, "semaphore")?;
+        Ok(s)
+    }
+
+    /// A ring of `capacity` Ts, and its offset, for [`producer`](Self::producer) and
+    /// [`consumer`](Self::consumer).
+    pub fn new_ring<T: Copy>(&self, capacity: usize) -> io::Result<usize> {
+        assert!(capacity > 0, "shm: a ring with no room");
+        let slots = size_of::<T>()
+            .checked_mul(capacity)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::OutOfMemory, "shm: ring too big"))?;
+        let offset = self.alloc(
+            ring_slots::<T>() + slots,
+            align_of::<RingHeader>().max(align_of::<T>()),
+        )?;
+        let h: *mut RingHeader = self.at(offset)?;
+        // Safety: just allocated for it, and not published yet.
+        unsafe {
+            (*h).capacity = capacity as u64;
+            (*h).item_size = size_of::<T>() as u32;
+            (*h).item_align = align_of::<T>() as u32;
+            Init::publish(ptr::addr_of_mut!((*h).init), RING_MAGIC);
+        }
+        Ok(offset)
+    }
+
+    /// The pushing end of the ring at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`semaphore`](Self::semaphore), and across every process, only one producer
+    /// of a ring may be in use at a time. A T has to mean the same in every process: plain
+    /// data, with no pointers, references or handles.
+    pub unsafe fn producer<T: Copy>(&self, offset: usize) -> io::Result<Producer<'_, T>> {
+        Ok(Producer {
+            ring: self.ring(offset)?,
+        })
+    }
+
+    /// The popping end of the ring at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`producer`](Self::producer), with one consumer.
+    pub unsafe fn consumer<T: Copy>(&self, offset: usize) -> io::Result<Consumer<'_, T>> {
+        Ok(Consumer {
+            ring: self.ring(offset)?,
+        })
+    }
+
+    unsafe fn ring<T: Copy>(&self, offset: usize) -> io::Result<Ring<'_, T>> {
+        let header = &*self.at::<RingHeader>(offset)?;
+        header.init.wait(RING_MAGIC, "ring")?;
+        if header.item_size as usize != size_of::<T>()
+            || header.item_align as usize != align_of::<T>()
+        {
+            return Err(invalid(format!(
+                "shm: the ring holds items of {} bytes aligned to {}, not {} aligned to {}",
+                header.item_size,
+                header.item_align,
+                size_of::<T>(),
+                align_of::<T>()
+            )));
+        }
+        let capacity = header.capacity as usize;
+        let start = offset + ring_slots::<T>();
+        if start.saturating_add(capacity.saturating_mul(size_of::<T>())) > self.len {
+            return Err(invalid("shm: the ring runs past the arena".to_string()));
+        }
+        Ok(Ring {
+            header,
+            slots: self.base.add(start) as *mut T,
+            capacity,
+        })
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        sys::unmap(self.base, self.len);
+    }
+}
+
+fn path(name: &str) -> io::Result<String> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    if name.is_empty() || name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "shm: a name is one path component",
+        ));
+    }
+    Ok(format!("/dev/shm/{}", name))
+}
+
+/// A counting semaphore that processes sharing its arena can wait on and post to.
+#[repr(C)]
+pub struct Semaphore {
+    init: Init,
+    count: AtomicU32,
+    // How many are asleep in the futex, so a post without any doesn't make a system call.
+    sleepers: AtomicU32,
+}
+
+impl Semaphore {
+    /// Takes one from the count, waiting while it's zero.
+    pub fn wait(&self) {
+        let backoff = Backoff::new();
+        loop {
+            if self.try_wait() {
+                return;
+            }
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+            // SeqCst, with post's increment and load: either it sees us asleep or we see
+            // its increment, as the futex's comparison does.
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            sys::wait(&self.count, 0);
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Takes one from the count if it isn't zero.
+    pub fn try_wait(&self) -> bool {
+        // Acquire: what the poster did before posting happens before what we do after.
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |c| c.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Adds one to the count, waking a waiter.
+    pub fn post(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            sys::wake(&self.count, 1);
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+// Each index on a line of its own, so the producer and consumer don't share one.
+#[repr(C, align(128))]
+struct Line(AtomicU64);
+
+#[repr(C)]
+struct RingHeader {
+    init: Init,
+    capacity: u64,
+    item_size: u32,
+    item_align: u32,
+    // Pushes and pops so far: the consumer's and the producer's.
+    head: Line,
+    tail: Line,
+}
+
+// Where a ring's slots start, from the start of its header.
+fn ring_slots<T>() -> usize {
+    let align = align_of::<T>();
+    (size_of::<RingHeader>() + align - 1) & !(align - 1)
+}
+
+struct Ring<'a, T> {
+    header: &'a RingHeader,
+    slots: *mut T,
+    capacity: usize,
+}
+
+impl<T> Ring<'_, T> {
+    fn slot(&self, i: u64) -> *mut T {
+        // Safety: in bounds, as `ring` checked.
+        unsafe { self.slots.add((i % self.capacity as u64) as usize) }
+    }
+}
+
+/// The pushing end of a ring in an [`Arena`].
+pub struct Producer<'a, T> {
+    ring: Ring<'a, T>,
+}
+
+unsafe impl<T: Send> Send for Producer<'_, T> {}
+
+impl<T: Copy> Producer<'_, T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity
+    }
+
+    /// Pushes `t`, or gives it back if the ring is full.
+    pub fn push(&mut self, t: T) -> Result<(), T> {
+        let h = self.ring.header;
+        let tail = h.tail.0.load(Ordering::Relaxed);
+        // Acquire: the consumer's done reading the slot it freed.
+        if tail - h.head.0.load(Ordering::Acquire) == self.ring.capacity as u64 {
+            return Err(t);
+        }
+        unsafe { self.ring.slot(tail).write(t) };
+        // Release: the write above happens before the consumer reads it.
+        h.tail.0.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// The popping end of a ring in an [`Arena`].
+pub struct Consumer<'a, T> {
+    ring: Ring<'a, T>,
+}
+
+unsafe impl<T: Send> Send for Consumer<'_, T> {}
+
+impl<T: Copy> Consumer<'_, T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let h = self.ring.header;
+        let head = h.head.0.load(Ordering::Relaxed);
+        // Acquire: pairs with the producer's Release of the push.
+        if head == h.tail.0.load(Ordering::Acquire) {
+            return None;
+        }
+        let t = unsafe { self.ring.slot(head).read() };
+        h.head.0.store(head + 1, Ordering::Release);
+        Some(t)
+    }
+
+    pub fn len(&self) -> usize {
+        let h = self.ring.header;
+        (h.tail.0.load(Ordering::Acquire) - h.head.0.load(Ordering::Relaxed)) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::raw::{c_int, c_long, c_void};
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::AtomicU32;
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_FUTEX: c_long = 202;
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const SYS_FUTEX: c_long = 98;
+
+    // Without FUTEX_PRIVATE_FLAG, so that other processes' waits and wakes meet ours.
+    const FUTEX_WAIT: c_int = 0;
+    const FUTEX_WAKE: c_int = 1;
+
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    const MAP_SHARED: c_int = 1;
+    const MAP_ANONYMOUS: c_int = 0x20;
+    const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    pub fn map(file: Option<&File>, len: usize) -> io::Result<*mut u8> {
+        let (flags, fd) = match file {
+            Some(f) => (MAP_SHARED, f.as_raw_fd()),
+            None => (MAP_SHARED | MAP_ANONYMOUS, -1),
+        };
+        let p = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                flags,
+                fd,
+                0,
+            )
+        };
+        if p == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(p as *mut u8)
+    }
+
+    pub fn unmap(base: *mut u8, len: usize) {
+        unsafe { munmap(base as *mut c_void, len) };
+    }
+
+    // Sleeps while `word` is `expected`, or until woken; it may wake spuriously.
+    pub fn wait(word: &AtomicU32, expected: u32) {
+        unsafe {
+            syscall(
+                SYS_FUTEX,
+                word as *const AtomicU32,
+                FUTEX_WAIT,
+                expected,
+                std::ptr::null::<c_void>(),
+            )
+        };
+    }
+
+    pub fn wake(word: &AtomicU32, n: c_int) {
+        unsafe { syscall(SYS_FUTEX, word as *const AtomicU32, FUTEX_WAKE, n) };
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )
+)))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    pub fn map(_: Option<&File>, _: usize) -> io::Result<*mut u8> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "shared memory is only mapped on Linux",
+        ))
+    }
+
+    pub fn unmap(_: *mut u8, _: usize) {
+        unreachable!()
+    }
+
+    pub fn wait(_: &AtomicU32, _: u32) {
+        std::thread::sleep(Duration::from_micros(100));
+    }
+
+    pub fn wake(_: &AtomicU32, _: i32) {}
+}
+
+// The other process of shm_ring_and_semaphore_cross_processes, when it's run as that.
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "maps memory and starts processes")]
+fn shm_child() {
+    let (name, ring, done) = match std::env::var("ATOMICS_SHM_CHILD") {
+        Ok(v) => {
+            let mut v = v.split(',');
+            let mut next = || v.next().unwrap().to_string();
+            (next(), next().parse().unwrap(), next().parse().unwrap())
+        }
+        Err(_) => return,
+    };
+    let arena = Arena::open(&name).unwrap();
+    let mut tx = unsafe { arena.producer::<u64>(ring).unwrap() };
+    let done = unsafe { arena.semaphore(done).unwrap() };
+    for i in 1..=10_000u64 {
+        while tx.push(i).is_err() {
+            thread::yield_now();
+        }
+    }
+    done.post();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "maps memory and starts processes")]
+fn shm_ring_and_semaphore_cross_processes() {
+    if !cfg!(target_os = "linux") {
+        assert!(Arena::anonymous(4096).is_err());
+        return;
+    }
+    let name = format!("atomics-test-{}", std::process::id());
+    let arena = Arena::create(&name, 1 << 16).unwrap();
+    let ring = arena.new_ring::<u64>(64).unwrap();
+    let (done, sem) = arena.new_semaphore(0).unwrap();
+    let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "shm::shm_child", "--quiet"])
+        .env("ATOMICS_SHM_CHILD", format!("{},{},{}", name, ring, done))
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut rx = unsafe { arena.consumer::<u64>(ring).unwrap() };
+    let mut expected = 1;
+    while expected <= 10_000 {
+        match rx.pop() {
+            Some(i) => {
+                assert_eq!(i, expected);
+                expected += 1;
+            }
+            None => thread::yield_now(),
+        }
+    }
+    sem.wait();
+    assert!(child.wait().unwrap().success());
+    assert!(rx.is_empty());
+    Arena::unlink(&name).unwrap();
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[cfg_attr(miri, ignore = "maps memory")]
+fn shm_opening_checks_what_is_there() {
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    let arena = Arena::anonymous(4096).unwrap();
+    let ring = arena.new_ring::<u32>(4).unwrap();
+    let (sem, s) = arena.new_semaphore(1).unwrap();
+    let e = |r: io::Result<()>| r.unwrap_err().to_string();
+
+    assert_eq!(
+        e(unsafe { arena.semaphore(ring).map(drop) }),
+        "shm: no semaphore there"
+    );
+    assert!(e(unsafe { arena.consumer::<u64>(ring).map(drop) })
+        .starts_with("shm: the ring holds items of 4 bytes"));
+    assert_eq!(
+        unsafe { arena.producer::<u32>(sem).map(drop) }
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert_eq!(
+        arena.alloc(1 << 20, 8).unwrap_err().kind(),
+        io::ErrorKind::OutOfMemory
+    );
+
+    // The semaphore wakes a waiter another thread posts to.
+    assert!(s.try_wait());
+    assert!(!s.try_wait());
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(20));
+            s.post();
+        });
+        s.wait();
+    });
+    assert_eq!(s.count(), 0);
+}
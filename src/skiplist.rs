@@ -0,0 +1,690 @@
+use crate::epoch::{self, Guard};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+const MAX_HEIGHT: usize = 16;
+
+// Same trick as the list set: a node is deleted at a level once the low bit of its next
+// pointer at that level is set, and it's removed from the map once level 0 is marked.
+const MARK: usize = 1;
+
+fn is_marked<T>(p: *mut T) -> bool {
+    p.addr() & MARK != 0
+}
+
+fn marked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a | MARK)
+}
+
+fn unmarked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a & !MARK)
+}
+
+fn random_height() -> usize {
+    thread_local! {
+        static SEED: Cell<u64> = Cell::new({
+            use std::collections::hash_map::RandomState;
+            use std::hash::{BuildHasher, Hasher};
+            RandomState::new().build_hasher().finish() | 1
+        });
+    }
+    SEED.with(|seed| {
+        // xorshift64; each extra level with probability 1/2.
+        let mut x = seed.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        seed.set(x);
+        std::cmp::min(x.trailing_ones() as usize + 1, MAX_HEIGHT)
+    })
+}
+
+struct Node<K, V> {
+    key: K,
+    // Boxed separately so insert can replace it atomically.
+    value: AtomicPtr<V>,
+    // One reference per level the node is linked at, plus one held by the inserting thread
+    // while it builds the tower. The node is retired when this hits zero.
+    refs: AtomicUsize,
+    next: Box<[AtomicPtr<Node<K, V>>]>,
+}
+
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        let value = *self.value.get_mut();
+        if !value.is_null() {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
+
+impl<K: Send + 'static, V: Send + 'static> Node<K, V> {
+    fn height(&self) -> usize {
+        self.next.len()
+    }
+
+    unsafe fn release(node: *mut Self, guard: &Guard) {
+        if (*node).refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            guard.defer_destroy(node);
+        }
+    }
+}
+
+struct Search<K, V> {
+    preds: [*const AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+    succs: [*mut Node<K, V>; MAX_HEIGHT],
+    found: bool,
+}
+
+/// A lock-free ordered map built on a skiplist, reclaiming nodes through [`epoch`].
+pub struct SkipMap<K, V> {
+    head: [AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+    len: AtomicUsize,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for SkipMap<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for SkipMap<K, V> {}
+
+impl<K, V> SkipMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            head: Default::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> SkipMap<K, V>
+where
+    K: Ord + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    // Finds the predecessors and successors of `key` at every level, unlinking marked nodes on
+    // the way. succs[0] is the first node with a key >= `key`.
+    fn find(&self, key: &K, guard: &Guard) -> Search<K, V> {
+        'retry: loop {
+            let mut search = Search {
+                preds: [ptr::null(); MAX_HEIGHT],
+                succs: [ptr::null_mut(); MAX_HEIGHT],
+                found: false,
+            };
+            let mut links: &[AtomicPtr<Node<K, V>>] = &self.head;
+            for level in (0..MAX_HEIGHT).rev() {
+                let mut curr = links[level].load(Ordering::Acquire);
+                if is_marked(curr) {
+                    // Our predecessor got deleted under us.
+                    continue 'retry;
+                }
+                while !curr.is_null() {
+                    let node = unsafe { &*curr };
+                    let succ = node.next[level].load(Ordering::Acquire);
+                    if is_marked(succ) {
+                        if links[level]
+                            .compare_exchange(
+                                curr,
+                                unmarked(succ),
+                                Ordering::AcqRel,
+                                Ordering::Acquire,
+                            )
+                            .is_err()
+                        {
+                            continue 'retry;
+                        }
+                        unsafe { Node::release(curr, guard) };
+                        curr = unmarked(succ);
+                        continue;
+                    }
+                    if node.key >= *key {
+                        break;
+                    }
+                    links = &node.next;
+                    curr = succ;
+                }
+                search.preds[level] = &links[level];
+                search.succs[level] = curr;
+            }
+            let first = search.succs[0];
+            search.found = !first.is_null() && unsafe { (*first).key == *key };
+            return search;
+        }
+    }
+
+    // A read-only walk to the first live node whose key is not below `bound`; never writes.
+    fn seek_lower(&self, bound: Bound<&K>, _guard: &Guard) -> *mut Node<K, V> {
+        let below = |key: &K| match bound {
+            Bound::Included(b) => key < b,
+            Bound::Excluded(b) => key <= b,
+            Bound::Unbounded => false,
+        };
+        let mut links: &[AtomicPtr<Node<K, V>>] = &self.head;
+        let mut result = ptr::null_mut();
+        for level in (0..MAX_HEIGHT).rev() {
+            let mut curr = unmarked(links[level].load(Ordering::Acquire));
+            while !curr.is_null() {
+                let node = unsafe { &*curr };
+                let succ = node.next[level].load(Ordering::Acquire);
+                if is_marked(succ) {
+                    // Deleted nodes keep valid next pointers, so step over them.
+                    curr = unmarked(succ);
+                    continue;
+                }
+                if !below(&node.key) {
+                    break;
+                }
+                links = &node.next;
+                curr = succ;
+            }
+            result = curr;
+        }
+        result
+    }
+
+    // The last live node whose key is not above `bound`, or null.
+    fn seek_upper(&self, bound: Bound<&K>, _guard: &Guard) -> *mut Node<K, V> {
+        let within = |key: &K| match bound {
+            Bound::Included(b) => key <= b,
+            Bound::Excluded(b) => key < b,
+            Bound::Unbounded => true,
+        };
+        'retry: loop {
+            let mut links: &[AtomicPtr<Node<K, V>>] = &self.head;
+            let mut pred = ptr::null_mut();
+            for level in (0..MAX_HEIGHT).rev() {
+                let mut curr = unmarked(links[level].load(Ordering::Acquire));
+                while !curr.is_null() {
+                    let node = unsafe { &*curr };
+                    let succ = node.next[level].load(Ordering::Acquire);
+                    if is_marked(succ) {
+                        curr = unmarked(succ);
+                        continue;
+                    }
+                    if !within(&node.key) {
+                        break;
+                    }
+                    pred = curr;
+                    links = &node.next;
+                    curr = succ;
+                }
+            }
+            if !pred.is_null() && is_marked(unsafe { (*pred).next[0].load(Ordering::Acquire) }) {
+                // Removed after we stepped onto it.
+                continue 'retry;
+            }
+            return pred;
+        }
+    }
+
+    // The first live node after `node`, which may itself have been removed meanwhile.
+    fn successor(&self, node: *mut Node<K, V>, guard: &Guard) -> *mut Node<K, V> {
+        let node = unsafe { &*node };
+        let next = node.next[0].load(Ordering::Acquire);
+        if is_marked(next) {
+            // Nodes inserted after our removal aren't reachable from us; search instead.
+            return self.seek_lower(Bound::Excluded(&node.key), guard);
+        }
+        let mut curr = next;
+        while !curr.is_null() {
+            let next = unsafe { (*curr).next[0].load(Ordering::Acquire) };
+            if !is_marked(next) {
+                break;
+            }
+            curr = unmarked(next);
+        }
+        curr
+    }
+
+    fn entry(&self, node: *mut Node<K, V>, guard: Guard) -> Option<Entry<'_, K, V>> {
+        if node.is_null() {
+            return None;
+        }
+        Some(Entry {
+            map: self,
+            node,
+            guard,
+        })
+    }
+
+    /// The first entry whose key is not below `bound`.
+    pub fn lower_bound(&self, bound: Bound<&K>) -> Option<Entry<'_, K, V>> {
+        let guard = epoch::pin();
+        let node = self.seek_lower(bound, &guard);
+        self.entry(node, guard)
+    }
+
+    /// The last entry whose key is not above `bound`.
+    pub fn upper_bound(&self, bound: Bound<&K>) -> Option<Entry<'_, K, V>> {
+        let guard = epoch::pin();
+        let node = self.seek_upper(bound, &guard);
+        self.entry(node, guard)
+    }
+
+    pub fn front(&self) -> Option<Entry<'_, K, V>> {
+        self.lower_bound(Bound::Unbounded)
+    }
+
+    pub fn back(&self) -> Option<Entry<'_, K, V>> {
+        self.upper_bound(Bound::Unbounded)
+    }
+
+    /// Iterates in key order over the entries in `range`, as insertions and removals allow.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V, R> {
+        Range {
+            map: self,
+            range,
+            guard: epoch::pin(),
+            last: ptr::null_mut(),
+            done: false,
+        }
+    }
+
+    unsafe fn replace_value(node: *mut Node<K, V>, value: *mut V, guard: &Guard) {
+        let old = (*node).value.swap(value, Ordering::AcqRel);
+        guard.defer_destroy(old);
+    }
+
+    /// Inserts or replaces the value for `key`. Returns true if the key was new.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = epoch::pin();
+        let value = Box::into_raw(Box::new(value));
+        let mut search = self.find(&key, &guard);
+        if search.found {
+            unsafe { Self::replace_value(search.succs[0], value, &guard) };
+            return false;
+        }
+
+        let height = random_height();
+        let new = Box::into_raw(Box::new(Node {
+            key,
+            value: AtomicPtr::new(value),
+            // Level 0 plus our own reference while building.
+            refs: AtomicUsize::new(2),
+            next: (0..height)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+        }));
+        let node = unsafe { &*new };
+
+        // Level 0: once this CAS succeeds the key is in the map.
+        loop {
+            node.next[0].store(search.succs[0], Ordering::Relaxed);
+            if unsafe { &*search.preds[0] }
+                .compare_exchange(search.succs[0], new, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+            search = self.find(&node.key, &guard);
+            if search.found {
+                // Someone else inserted the key first; hand them our value instead.
+                let value = node.value.swap(ptr::null_mut(), Ordering::Relaxed);
+                unsafe {
+                    Self::replace_value(search.succs[0], value, &guard);
+                    // Never published, so no one else can see it.
+                    drop(Box::from_raw(new));
+                }
+                return false;
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        // Upper levels are only hints for searching; give up if the node gets deleted.
+        'build: for level in 1..height {
+            loop {
+                let next = node.next[level].load(Ordering::Acquire);
+                if is_marked(next) {
+                    break 'build;
+                }
+                let succ = search.succs[level];
+                if next != succ
+                    && node.next[level]
+                        .compare_exchange(next, succ, Ordering::AcqRel, Ordering::Acquire)
+                        .is_err()
+                {
+                    // Only a remover marking us changes our links.
+                    break 'build;
+                }
+                if unsafe { &*search.preds[level] }
+                    .compare_exchange(succ, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    node.refs.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                search = self.find(&node.key, &guard);
+                if search.succs[0] != new {
+                    break 'build;
+                }
+            }
+        }
+        if is_marked(node.next[0].load(Ordering::Acquire)) {
+            // Deleted while we were building: make sure none of the levels we linked linger.
+            self.find(&node.key, &guard);
+        }
+        unsafe { Node::release(new, &guard) };
+        true
+    }
+
+    /// Returns a clone of the value for `key`.
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let guard = epoch::pin();
+        let node = self.seek_lower(Bound::Included(key), &guard);
+        if node.is_null() {
+            return None;
+        }
+        let node = unsafe { &*node };
+        if node.key != *key {
+            return None;
+        }
+        Some(unsafe { (*node.value.load(Ordering::Acquire)).clone() })
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        let guard = epoch::pin();
+        let node = self.seek_lower(Bound::Included(key), &guard);
+        !node.is_null() && unsafe { (*node).key == *key }
+    }
+
+    /// Removes `key`, returning false if it wasn't present.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = epoch::pin();
+        let search = self.find(key, &guard);
+        if !search.found {
+            return false;
+        }
+        let node = unsafe { &*search.succs[0] };
+        // Mark top-down so searches stop using the node as a shortcut before it disappears.
+        for level in (1..node.height()).rev() {
+            let mut next = node.next[level].load(Ordering::Acquire);
+            while !is_marked(next) {
+                match node.next[level].compare_exchange(
+                    next,
+                    marked(next),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(n) => next = n,
+                }
+            }
+        }
+        // Marking level 0 is the linearization point; only one remover can win it.
+        let mut next = node.next[0].load(Ordering::Acquire);
+        loop {
+            if is_marked(next) {
+                return false;
+            }
+            match node.next[0].compare_exchange(
+                next,
+                marked(next),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(n) => next = n,
+            }
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        // Unlink it from every level.
+        self.find(key, &guard);
+        true
+    }
+
+    /// Iterates over clones of the entries in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let guard = epoch::pin();
+        let curr = unmarked(self.head[0].load(Ordering::Acquire));
+        Iter {
+            _map: self,
+            _guard: guard,
+            curr,
+        }
+    }
+}
+
+impl<K, V> Default for SkipMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Drop for SkipMap<K, V> {
+    fn drop(&mut self) {
+        // A node can be unlinked at level 0 but still linked higher up, so collect everything
+        // reachable from any level. Fully unlinked nodes were already retired to the epoch.
+        let mut nodes = HashSet::new();
+        for level in 0..MAX_HEIGHT {
+            let mut curr = unmarked(*self.head[level].get_mut());
+            while !curr.is_null() {
+                nodes.insert(curr);
+                curr = unmarked(unsafe { (*curr).next[level].load(Ordering::Relaxed) });
+            }
+        }
+        for node in nodes {
+            drop(unsafe { Box::from_raw(node) });
+        }
+    }
+}
+
+/// Iterator over a [`SkipMap`], see [`SkipMap::iter`].
+pub struct Iter<'a, K, V> {
+    _map: &'a SkipMap<K, V>,
+    // Keeps the current node (and its successors) alive even if they get removed.
+    _guard: Guard,
+    curr: *mut Node<K, V>,
+}
+
+impl<'a, K: Clone, V: Clone> Iterator for Iter<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        while !self.curr.is_null() {
+            let node = unsafe { &*self.curr };
+            let next = node.next[0].load(Ordering::Acquire);
+            self.curr = unmarked(next);
+            if !is_marked(next) {
+                let value = unsafe { (*node.value.load(Ordering::Acquire)).clone() };
+                return Some((node.key.clone(), value));
+            }
+        }
+        None
+    }
+}
+
+/// A cursor at one entry of a [`SkipMap`].
+///
+/// The entry stays readable even if it's removed from the map, and can still step to its
+/// neighbours in the map's current contents.
+pub struct Entry<'a, K, V> {
+    map: &'a SkipMap<K, V>,
+    node: *mut Node<K, V>,
+    // Keeps the node and its current value alive.
+    guard: Guard,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Ord + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn key(&self) -> &K {
+        unsafe { &(*self.node).key }
+    }
+
+    /// The value at the time of the call; a later insert for the same key doesn't change it.
+    pub fn value(&self) -> &V {
+        unsafe { &*(*self.node).value.load(Ordering::Acquire) }
+    }
+
+    pub fn is_removed(&self) -> bool {
+        is_marked(unsafe { (*self.node).next[0].load(Ordering::Acquire) })
+    }
+
+    pub fn next(&self) -> Option<Entry<'a, K, V>> {
+        let guard = epoch::pin();
+        let node = self.map.successor(self.node, &guard);
+        self.map.entry(node, guard)
+    }
+
+    pub fn prev(&self) -> Option<Entry<'a, K, V>> {
+        let guard = epoch::pin();
+        let node = self.map.seek_upper(Bound::Excluded(self.key()), &guard);
+        self.map.entry(node, guard)
+    }
+
+    /// Moves to the next entry, staying put and returning false at the end.
+    pub fn move_next(&mut self) -> bool {
+        let node = self.map.successor(self.node, &self.guard);
+        if node.is_null() {
+            return false;
+        }
+        self.node = node;
+        true
+    }
+
+    /// Moves to the previous entry, staying put and returning false at the start.
+    pub fn move_prev(&mut self) -> bool {
+        let node = self
+            .map
+            .seek_upper(Bound::Excluded(self.key()), &self.guard);
+        if node.is_null() {
+            return false;
+        }
+        self.node = node;
+        true
+    }
+}
+
+/// Iterator over part of a [`SkipMap`], see [`SkipMap::range`].
+pub struct Range<'a, K, V, R> {
+    map: &'a SkipMap<K, V>,
+    range: R,
+    guard: Guard,
+    last: *mut Node<K, V>,
+    done: bool,
+}
+
+impl<'a, K, V, R> Iterator for Range<'a, K, V, R>
+where
+    K: Ord + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    R: RangeBounds<K>,
+{
+    type Item = Entry<'a, K, V>;
+
+    fn next(&mut self) -> Option<Entry<'a, K, V>> {
+        if self.done {
+            return None;
+        }
+        let node = if self.last.is_null() {
+            self.map.seek_lower(self.range.start_bound(), &self.guard)
+        } else {
+            self.map.successor(self.last, &self.guard)
+        };
+        let in_range = !node.is_null()
+            && match self.range.end_bound() {
+                Bound::Included(end) => unsafe { (*node).key <= *end },
+                Bound::Excluded(end) => unsafe { (*node).key < *end },
+                Bound::Unbounded => true,
+            };
+        if !in_range {
+            self.done = true;
+            return None;
+        }
+        self.last = node;
+        self.map.entry(node, epoch::pin())
+    }
+}
+
+#[test]
+fn skipmap_basic() {
+    let map = SkipMap::new();
+    for k in [5, 1, 4, 2, 3].iter() {
+        assert!(map.insert(*k, k * 10));
+    }
+    assert!(!map.insert(3, 33));
+    assert_eq!(map.get(&3), Some(33));
+    assert_eq!(map.get(&6), None);
+    assert!(map.remove(&4));
+    assert!(!map.remove(&4));
+    assert_eq!(map.len(), 4);
+    let entries: Vec<_> = map.iter().collect();
+    assert_eq!(entries, [(1, 10), (2, 20), (3, 33), (5, 50)]);
+}
+
+#[test]
+fn skipmap_concurrent() {
+    let n = if cfg!(miri) { 50 } else { 500 };
+    let map = &SkipMap::new();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    let k = i * 4 + t;
+                    map.insert(k, t);
+                    if i % 2 == 0 {
+                        assert!(map.remove(&k));
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(map.len(), 2 * n);
+    let keys: Vec<_> = map.iter().map(|(k, _)| k).collect();
+    let expected: Vec<_> = (0..4 * n).filter(|k| (k / 4) % 2 == 1).collect();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn skipmap_range_and_bounds() {
+    let map = SkipMap::new();
+    for k in (0..100).step_by(10) {
+        map.insert(k, k.to_string());
+    }
+    let keys: Vec<_> = map.range(15..=50).map(|e| *e.key()).collect();
+    assert_eq!(keys, [20, 30, 40, 50]);
+    assert_eq!(map.range(..).count(), 10);
+    assert_eq!(*map.lower_bound(Bound::Included(&30)).unwrap().key(), 30);
+    assert_eq!(*map.lower_bound(Bound::Excluded(&30)).unwrap().key(), 40);
+    assert_eq!(*map.upper_bound(Bound::Excluded(&30)).unwrap().key(), 20);
+    assert!(map.lower_bound(Bound::Excluded(&90)).is_none());
+    assert_eq!(*map.back().unwrap().key(), 90);
+}
+
+#[test]
+fn skipmap_cursor_survives_removal() {
+    let map = SkipMap::new();
+    for k in (0..50).step_by(10) {
+        map.insert(k, k);
+    }
+    let mut cursor = map.lower_bound(Bound::Included(&20)).unwrap();
+    assert!(map.remove(&20));
+    map.insert(25, 25);
+    assert!(cursor.is_removed());
+    assert_eq!((*cursor.key(), *cursor.value()), (20, 20));
+    // Stepping off a removed entry lands on its neighbours in the current map, including
+    // ones inserted after the removal.
+    assert_eq!(*cursor.next().unwrap().key(), 25);
+    assert_eq!(*cursor.prev().unwrap().key(), 10);
+    assert!(cursor.move_next());
+    assert_eq!(*cursor.key(), 25);
+    assert!(cursor.move_next());
+    assert_eq!(*cursor.key(), 30);
+    assert!(cursor.move_prev() && cursor.move_prev() && cursor.move_prev());
+    assert_eq!(*cursor.key(), 0);
+    assert!(!cursor.move_prev());
+    assert_eq!(*cursor.key(), 0);
+}
@@ -0,0 +1,260 @@
+use crate::epoch;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+// Page k holds FIRST_PAGE << k slots, so pages never move and a key is found without locks.
+const FIRST_PAGE: usize = 32;
+const PAGES: usize = 27;
+const MAX_KEYS: usize = FIRST_PAGE * ((1 << PAGES) - 1);
+// Free list links and the head's index are stored as key + 1, so 0 means "none".
+const NONE: u32 = 0;
+
+fn page_of(key: usize) -> (usize, usize) {
+    let page = (usize::BITS - 1 - (key / FIRST_PAGE + 1).leading_zeros()) as usize;
+    (page, key - FIRST_PAGE * ((1 << page) - 1))
+}
+
+fn page_len(page: usize) -> usize {
+    FIRST_PAGE << page
+}
+
+struct Slot<T> {
+    // Boxed so get can read it under an epoch guard while remove retires it.
+    value: AtomicPtr<T>,
+    next_free: AtomicU32,
+}
+
+/// A concurrent slab: `insert` stores a value and returns a small integer key for it.
+///
+/// Lookups are lock-free, and removed keys are recycled through a lock-free free list, so a
+/// key may be handed out again once its value has been removed.
+pub struct Slab<T> {
+    pages: [AtomicPtr<Slot<T>>; PAGES],
+    // The top 32 bits count pops, so a stale head can't be CASed back in (ABA).
+    free: AtomicU64,
+    // Keys below this have been handed out at least once.
+    next_key: AtomicUsize,
+    len: AtomicUsize,
+}
+
+unsafe impl<T: Send + Sync> Send for Slab<T> {}
+unsafe impl<T: Send + Sync> Sync for Slab<T> {}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self {
+            pages: Default::default(),
+            free: AtomicU64::new(0),
+            next_key: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The slot for `key`, or None if its page was never allocated.
+    fn slot(&self, key: usize) -> Option<&Slot<T>> {
+        if key >= MAX_KEYS {
+            return None;
+        }
+        let (page, index) = page_of(key);
+        let slots = self.pages[page].load(Ordering::Acquire);
+        if slots.is_null() {
+            return None;
+        }
+        Some(unsafe { &*slots.add(index) })
+    }
+
+    fn slot_or_alloc(&self, key: usize) -> &Slot<T> {
+        let (page, index) = page_of(key);
+        let mut slots = self.pages[page].load(Ordering::Acquire);
+        if slots.is_null() {
+            let new: Box<[Slot<T>]> = (0..page_len(page))
+                .map(|_| Slot {
+                    value: AtomicPtr::new(ptr::null_mut()),
+                    next_free: AtomicU32::new(NONE),
+                })
+                .collect();
+            let new = Box::into_raw(new).cast::<Slot<T>>();
+            // AcqRel: publishes our empty page, or acquires the one that beat us.
+            match self.pages[page].compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => slots = new,
+                Err(current) => {
+                    drop(unsafe {
+                        Box::from_raw(ptr::slice_from_raw_parts_mut(new, page_len(page)))
+                    });
+                    slots = current;
+                }
+            }
+        }
+        unsafe { &*slots.add(index) }
+    }
+
+    fn pop_free(&self) -> Option<usize> {
+        let mut head = self.free.load(Ordering::Acquire);
+        loop {
+            let key = (head as u32).checked_sub(1)? as usize;
+            // May be stale if someone pops `key` first, but then the tag has moved on and
+            // our CAS fails.
+            let next = self.slot(key).unwrap().next_free.load(Ordering::Relaxed);
+            let new = (head >> 32).wrapping_add(1) << 32 | u64::from(next);
+            match self
+                .free
+                .compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => return Some(key),
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    fn push_free(&self, key: usize) {
+        let slot = self.slot(key).unwrap();
+        let mut head = self.free.load(Ordering::Relaxed);
+        loop {
+            slot.next_free.store(head as u32, Ordering::Relaxed);
+            let new = head & !u64::from(u32::MAX) | (key as u64 + 1);
+            // Release: the popper reads our next_free link.
+            match self
+                .free
+                .compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Slab<T> {
+    /// Stores `value` and returns its key.
+    ///
+    /// # Panics
+    ///
+    /// If the slab already holds about 4 billion values.
+    pub fn insert(&self, value: T) -> usize {
+        let key = self.pop_free().unwrap_or_else(|| {
+            let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+            assert!(key < MAX_KEYS, "slab is full");
+            key
+        });
+        let value = Box::into_raw(Box::new(value));
+        // Release: publishes the value to get.
+        self.slot_or_alloc(key)
+            .value
+            .store(value, Ordering::Release);
+        self.len.fetch_add(1, Ordering::Relaxed);
+        key
+    }
+
+    /// Runs `f` on the value for `key`, without taking any locks.
+    pub fn get<R>(&self, key: usize, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let _guard = epoch::pin();
+        let value = self.slot(key)?.value.load(Ordering::Acquire);
+        if value.is_null() {
+            return None;
+        }
+        Some(f(unsafe { &*value }))
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        self.slot(key)
+            .is_some_and(|slot| !slot.value.load(Ordering::Relaxed).is_null())
+    }
+
+    /// Removes the value for `key`, returning false if there was none. The value is dropped
+    /// once no concurrent `get` can still be looking at it, and the key becomes free for reuse.
+    pub fn remove(&self, key: usize) -> bool {
+        let Some(slot) = self.slot(key) else {
+            return false;
+        };
+        let guard = epoch::pin();
+        let value = slot.value.swap(ptr::null_mut(), Ordering::AcqRel);
+        if value.is_null() {
+            return false;
+        }
+        unsafe { guard.defer_destroy(value) };
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        self.push_free(key);
+        true
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Slab<T> {
+    fn drop(&mut self) {
+        for (page, slots) in self.pages.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if slots.is_null() {
+                continue;
+            }
+            let mut slots =
+                unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(slots, page_len(page))) };
+            for slot in slots.iter_mut() {
+                let value = *slot.value.get_mut();
+                if !value.is_null() {
+                    drop(unsafe { Box::from_raw(value) });
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn slab_recycles_keys() {
+    let slab = Slab::new();
+    let a = slab.insert("a".to_string());
+    let b = slab.insert("b".to_string());
+    assert_ne!(a, b);
+    assert_eq!(slab.get(a, |s| s.clone()), Some("a".to_string()));
+    assert!(slab.remove(a));
+    assert!(!slab.remove(a));
+    assert_eq!(slab.get(a, |s| s.len()), None);
+    assert_eq!(slab.insert("c".to_string()), a);
+    assert_eq!(slab.len(), 2);
+    assert!(!slab.contains(1_000_000));
+    // Keys past the first page still land somewhere stable.
+    let keys: Vec<_> = (0..100).map(|i| slab.insert(i.to_string())).collect();
+    for (i, &k) in keys.iter().enumerate() {
+        assert_eq!(slab.get(k, |s| s.parse::<usize>().unwrap()), Some(i));
+    }
+}
+
+#[test]
+fn slab_concurrent() {
+    let slab = &Slab::new();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                let mut mine = Vec::new();
+                for i in 0..500 {
+                    mine.push((slab.insert((t, i)), i));
+                    if i % 3 == 0 {
+                        let (k, _) = mine.swap_remove(0);
+                        assert!(slab.remove(k));
+                    }
+                }
+                for (k, i) in mine {
+                    assert_eq!(slab.get(k, |v| *v), Some((t, i)));
+                }
+            });
+        }
+    });
+    assert_eq!(slab.len(), 4 * (500 - 167));
+}
@@ -0,0 +1,477 @@
+use crate::epoch::{self, Guard};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+// Average entries per bucket before the table doubles.
+const MAX_LOAD: usize = 2;
+// Segment s holds buckets [2^(s-1), 2^s), segment 0 just bucket 0, so the table can double
+// without ever moving a bucket.
+const SEGMENTS: usize = usize::BITS as usize;
+const MAX_BUCKETS: usize = 1 << (usize::BITS - 1);
+
+// Same trick as the list set: a node is deleted once the low bit of its next pointer is set.
+const MARK: usize = 1;
+
+fn is_marked<T>(p: *mut T) -> bool {
+    p.addr() & MARK != 0
+}
+
+fn marked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a | MARK)
+}
+
+fn unmarked<T>(p: *mut T) -> *mut T {
+    p.map_addr(|a| a & !MARK)
+}
+
+// The list is sorted by bit-reversed hash, so the entries of bucket b (hash mod 2^i == b) sit
+// in one run right after b's sentinel, and doubling the table splits that run in two at the
+// new sentinel for b + 2^i. Regular keys get the top bit set before reversing so they always
+// sort after the sentinel with the same bits.
+fn regular_key(hash: u64) -> u64 {
+    (hash | 1 << 63).reverse_bits()
+}
+
+fn sentinel_key(bucket: usize) -> u64 {
+    (bucket as u64).reverse_bits()
+}
+
+fn segment_of(bucket: usize) -> (usize, usize) {
+    let segment = (usize::BITS - bucket.leading_zeros()) as usize;
+    let start = if segment == 0 { 0 } else { 1 << (segment - 1) };
+    (segment, bucket - start)
+}
+
+fn segment_len(segment: usize) -> usize {
+    if segment == 0 {
+        1
+    } else {
+        1 << (segment - 1)
+    }
+}
+
+struct Node<K, V> {
+    so_key: u64,
+    // None (and a null value) for bucket sentinels, which are never removed.
+    key: Option<K>,
+    // Boxed separately so insert can replace it atomically.
+    value: AtomicPtr<V>,
+    next: AtomicPtr<Node<K, V>>,
+}
+
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        let value = *self.value.get_mut();
+        if !value.is_null() {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
+
+/// Shalev and Shavit's split-ordered list: a lock-free hash map that keeps every entry in one
+/// Harris list and uses the bucket table only as shortcuts into it.
+///
+/// Resizing never moves a node. The table just doubles its bucket count, and each new bucket
+/// gets its sentinel spliced into the list the first time someone hashes to it. Removed nodes
+/// are retired through [`epoch`].
+pub struct SplitOrderedMap<K, V, S = RandomState> {
+    segments: [AtomicPtr<AtomicPtr<Node<K, V>>>; SEGMENTS],
+    buckets: AtomicUsize,
+    len: AtomicUsize,
+    hasher: S,
+}
+
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Send> Send for SplitOrderedMap<K, V, S> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Sync> Sync for SplitOrderedMap<K, V, S> {}
+
+impl<K, V> SplitOrderedMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> SplitOrderedMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        let map = Self {
+            segments: std::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            buckets: AtomicUsize::new(2),
+            len: AtomicUsize::new(0),
+            hasher,
+        };
+        // Bucket 0's sentinel heads the list and is every other sentinel's eventual parent.
+        let head = Box::into_raw(Box::new(Node {
+            so_key: sentinel_key(0),
+            key: None,
+            value: AtomicPtr::new(ptr::null_mut()),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }));
+        map.slot(0).store(head, Ordering::Relaxed);
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The table entry for `bucket`, allocating its segment if this is the first use.
+    fn slot(&self, bucket: usize) -> &AtomicPtr<Node<K, V>> {
+        let (segment, index) = segment_of(bucket);
+        let mut table = self.segments[segment].load(Ordering::Acquire);
+        if table.is_null() {
+            let new: Box<[AtomicPtr<Node<K, V>>]> = (0..segment_len(segment))
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect();
+            let new = Box::into_raw(new).cast::<AtomicPtr<Node<K, V>>>();
+            // AcqRel: publishes our empty segment, or acquires the one that beat us.
+            match self.segments[segment].compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => table = new,
+                Err(current) => {
+                    drop(unsafe {
+                        Box::from_raw(ptr::slice_from_raw_parts_mut(new, segment_len(segment)))
+                    });
+                    table = current;
+                }
+            }
+        }
+        unsafe { &*table.add(index) }
+    }
+}
+
+impl<K, V, S> SplitOrderedMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    // Returns the link that points at the first node at or past (so_key, key), that node (or
+    // null), and whether it is the node being looked for. Nodes with the same so_key but a
+    // different key are skipped over, so colliding hashes keep insertion order. Marked nodes
+    // found on the way are unlinked and retired.
+    fn find<'g, Q>(
+        &'g self,
+        start: &'g AtomicPtr<Node<K, V>>,
+        so_key: u64,
+        key: Option<&Q>,
+        guard: &'g Guard,
+    ) -> (&'g AtomicPtr<Node<K, V>>, *mut Node<K, V>, bool)
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        'retry: loop {
+            let mut prev = start;
+            let mut curr = prev.load(Ordering::Acquire);
+            loop {
+                if curr.is_null() {
+                    return (prev, curr, false);
+                }
+                let node = unsafe { &*curr };
+                let next = node.next.load(Ordering::Acquire);
+                if is_marked(next) {
+                    if prev
+                        .compare_exchange(curr, unmarked(next), Ordering::AcqRel, Ordering::Acquire)
+                        .is_err()
+                    {
+                        continue 'retry;
+                    }
+                    unsafe { guard.defer_destroy(curr) };
+                    curr = unmarked(next);
+                    continue;
+                }
+                if node.so_key > so_key {
+                    return (prev, curr, false);
+                }
+                if node.so_key == so_key && node.key.as_ref().map(Borrow::borrow) == key {
+                    return (prev, curr, true);
+                }
+                prev = &node.next;
+                curr = next;
+            }
+        }
+    }
+
+    // The sentinel for the bucket `hash` falls in under the current table size.
+    fn bucket(&self, hash: u64, guard: &Guard) -> &Node<K, V> {
+        let buckets = self.buckets.load(Ordering::Relaxed);
+        self.sentinel(hash as usize & (buckets - 1), guard)
+    }
+
+    fn sentinel(&self, bucket: usize, guard: &Guard) -> &Node<K, V> {
+        let slot = self.slot(bucket);
+        let sentinel = slot.load(Ordering::Acquire);
+        if !sentinel.is_null() {
+            return unsafe { &*sentinel };
+        }
+
+        // Splice the sentinel in after its parent's, the bucket it was split off from. The
+        // parent may be uninitialized too; bucket 0 always exists, so this bottoms out.
+        let parent = bucket & !(1 << (usize::BITS - 1 - bucket.leading_zeros()));
+        let parent = self.sentinel(parent, guard);
+        let so_key = sentinel_key(bucket);
+        let new = Box::into_raw(Box::new(Node {
+            so_key,
+            key: None,
+            value: AtomicPtr::new(ptr::null_mut()),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }));
+        let sentinel = loop {
+            let (prev, curr, found) = self.find::<K>(&parent.next, so_key, None, guard);
+            if found {
+                // Never published, so no one else can see it.
+                drop(unsafe { Box::from_raw(new) });
+                break curr;
+            }
+            unsafe { (*new).next.store(curr, Ordering::Relaxed) };
+            if prev
+                .compare_exchange(curr, new, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                break new;
+            }
+        };
+        // Everyone racing here ends up with the same sentinel, so a plain store is enough.
+        slot.store(sentinel, Ordering::Release);
+        unsafe { &*sentinel }
+    }
+
+    // Read-only lookup that skips marked nodes instead of unlinking them.
+    fn lookup<'g, Q>(&'g self, key: &Q, guard: &'g Guard) -> Option<&'g Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let so_key = regular_key(hash);
+        let mut curr = self.bucket(hash, guard).next.load(Ordering::Acquire);
+        while !curr.is_null() {
+            let node = unsafe { &*curr };
+            let next = node.next.load(Ordering::Acquire);
+            if node.so_key > so_key {
+                break;
+            }
+            if node.so_key == so_key
+                && !is_marked(next)
+                && node.key.as_ref().map(Borrow::borrow) == Some(key)
+            {
+                return Some(node);
+            }
+            curr = unmarked(next);
+        }
+        None
+    }
+
+    /// Inserts or replaces the value for `key`, returning true if the key was new.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = epoch::pin();
+        let hash = self.hasher.hash_one(&key);
+        let so_key = regular_key(hash);
+        let start = &self.bucket(hash, &guard).next;
+        let new = Box::into_raw(Box::new(Node {
+            so_key,
+            key: Some(key),
+            value: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }));
+        loop {
+            let key = unsafe { (*new).key.as_ref() };
+            let (prev, curr, found) = self.find(start, so_key, key, &guard);
+            if found {
+                unsafe {
+                    let value = (*new).value.swap(ptr::null_mut(), Ordering::Relaxed);
+                    let old = (*curr).value.swap(value, Ordering::AcqRel);
+                    guard.defer_destroy(old);
+                    drop(Box::from_raw(new));
+                }
+                return false;
+            }
+            unsafe { (*new).next.store(curr, Ordering::Relaxed) };
+            // Release: publishes the new node's key, value and next pointer.
+            if prev
+                .compare_exchange(curr, new, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+
+        let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+        let buckets = self.buckets.load(Ordering::Relaxed);
+        if len > buckets * MAX_LOAD && buckets < MAX_BUCKETS {
+            // Losing this race is fine: someone else already doubled it. Any table size is
+            // correct to search with, so Relaxed is enough.
+            let _ = self.buckets.compare_exchange(
+                buckets,
+                buckets * 2,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        true
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let guard = epoch::pin();
+        let node = self.lookup(key, &guard)?;
+        Some(unsafe { (*node.value.load(Ordering::Acquire)).clone() })
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = epoch::pin();
+        self.lookup(key, &guard).is_some()
+    }
+
+    /// Removes `key`, returning false if it wasn't present.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = epoch::pin();
+        let hash = self.hasher.hash_one(key);
+        let so_key = regular_key(hash);
+        let start = &self.bucket(hash, &guard).next;
+        loop {
+            let (prev, curr, found) = self.find(start, so_key, Some(key), &guard);
+            if !found {
+                return false;
+            }
+            let node = unsafe { &*curr };
+            let next = node.next.load(Ordering::Acquire);
+            if is_marked(next) {
+                continue;
+            }
+            // Logical deletion: the linearization point of remove.
+            if node
+                .next
+                .compare_exchange(next, marked(next), Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                continue;
+            }
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            // Physical deletion; if it fails someone else's find will do it.
+            if prev
+                .compare_exchange(curr, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                unsafe { guard.defer_destroy(curr) };
+            } else {
+                self.find(start, so_key, Some(key), &guard);
+            }
+            return true;
+        }
+    }
+}
+
+impl<K, V> Default for SplitOrderedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Drop for SplitOrderedMap<K, V, S> {
+    fn drop(&mut self) {
+        // Bucket 0's sentinel sorts first, so walking from it reaches every linked node.
+        let mut curr = unsafe { (**self.segments[0].get_mut()).load(Ordering::Relaxed) };
+        while !curr.is_null() {
+            let mut node = unsafe { Box::from_raw(curr) };
+            curr = unmarked(*node.next.get_mut());
+        }
+        for (segment, table) in self.segments.iter_mut().enumerate() {
+            let table = *table.get_mut();
+            if !table.is_null() {
+                drop(unsafe {
+                    Box::from_raw(ptr::slice_from_raw_parts_mut(table, segment_len(segment)))
+                });
+            }
+        }
+    }
+}
+
+#[test]
+fn split_ordered_grows() {
+    // Hashes everything to 0, so every key lands in the same run of the list.
+    struct Collide;
+    impl std::hash::Hasher for Collide {
+        fn finish(&self) -> u64 {
+            0
+        }
+        fn write(&mut self, _: &[u8]) {}
+    }
+    impl BuildHasher for Collide {
+        type Hasher = Collide;
+        fn build_hasher(&self) -> Collide {
+            Collide
+        }
+    }
+
+    let map = SplitOrderedMap::new();
+    for i in 0..1000 {
+        assert!(map.insert(i, i * 2));
+    }
+    assert!(!map.insert(7, 70));
+    assert_eq!(map.len(), 1000);
+    assert!(map.buckets.load(Ordering::Relaxed) >= 1000 / MAX_LOAD);
+    for i in 0..1000 {
+        assert_eq!(map.get(&i), Some(if i == 7 { 70 } else { i * 2 }));
+    }
+    assert!(map.remove(&7));
+    assert!(!map.remove(&7));
+    assert!(!map.contains_key(&7));
+    assert_eq!(map.len(), 999);
+
+    let colliding = SplitOrderedMap::with_hasher(Collide);
+    for s in ["a", "b", "c"] {
+        assert!(colliding.insert(s.to_string(), s.len()));
+    }
+    assert!(colliding.remove("b"));
+    assert_eq!(colliding.get("a"), Some(1));
+    assert_eq!(colliding.get("b"), None);
+    assert_eq!(colliding.get("c"), Some(1));
+}
+
+#[test]
+fn split_ordered_concurrent() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let map = &SplitOrderedMap::new();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..n {
+                    assert!(map.insert(i * 4 + t, t));
+                }
+                for i in (0..n).step_by(2) {
+                    assert!(map.remove(&(i * 4 + t)));
+                }
+            });
+        }
+    });
+    assert_eq!(map.len(), 2 * n);
+    for k in 0..4 * n {
+        assert_eq!(
+            map.get(&k),
+            if (k / 4) % 2 == 1 { Some(k % 4) } else { None }
+        );
+    }
+}
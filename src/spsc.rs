@@ -0,0 +1,825 @@
+use crate::cache_padded::CachePadded;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+type Slot<T> = UnsafeCell<MaybeUninit<T>>;
+
+struct Indices {
+    // head: next slot the consumer reads, tail: next slot the producer writes.
+    // Both indices run over 0..2*capacity so that a full ring (distance == capacity)
+    // and an empty ring (distance == 0) can be told apart for any capacity. Padded, since
+    // each is written by one side and polled by the other.
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+}
+
+impl Indices {
+    const fn new() -> Self {
+        Self {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+// A ring's indices and slots, wherever they're kept: on the heap behind the halves of a
+// `ring`, or inline in a StaticRingBuffer. Both sides work through this, so the two kinds of
+// ring share their code.
+struct RingRef<'a, T> {
+    indices: &'a Indices,
+    slots: &'a [Slot<T>],
+}
+
+impl<T> Clone for RingRef<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for RingRef<'_, T> {}
+
+// Safety: each slot is only ever accessed by the side that currently owns it, and
+// ownership is handed over through head/tail with Release/Acquire. The same goes for Ring
+// and StaticRingBuffer, below.
+unsafe impl<T: Send> Send for RingRef<'_, T> {}
+unsafe impl<T: Send> Sync for RingRef<'_, T> {}
+
+impl<'a, T> RingRef<'a, T> {
+    fn capacity(self) -> usize {
+        self.slots.len()
+    }
+
+    fn distance(self, from: usize, to: usize) -> usize {
+        if to >= from {
+            to - from
+        } else {
+            2 * self.capacity() - from + to
+        }
+    }
+
+    fn advance(self, idx: usize, n: usize) -> usize {
+        let next = idx + n;
+        if next >= 2 * self.capacity() {
+            next - 2 * self.capacity()
+        } else {
+            next
+        }
+    }
+
+    // UnsafeCell is repr(transparent), so the slot array is also an array of MaybeUninit<T>.
+    fn slot(self, idx: usize) -> *mut MaybeUninit<T> {
+        let base = self.slots.as_ptr() as *mut MaybeUninit<T>;
+        unsafe { base.add(idx % self.capacity()) }
+    }
+
+    // The (up to) two contiguous regions covering `len` slots starting at `idx`.
+    fn regions(self, idx: usize, len: usize) -> ((usize, usize), usize) {
+        let start = idx % self.capacity();
+        let first = cmp::min(len, self.capacity() - start);
+        ((start, first), len - first)
+    }
+
+    // Drops the items still in the ring, once both halves are gone.
+    fn drop_items(self) {
+        let mut head = self.indices.head.load(Ordering::Relaxed);
+        let tail = self.indices.tail.load(Ordering::Relaxed);
+        while head != tail {
+            unsafe { ptr::drop_in_place((*self.slot(head)).as_mut_ptr()) };
+            head = self.advance(head, 1);
+        }
+    }
+}
+
+struct Ring<T> {
+    indices: Indices,
+    slots: Box<[Slot<T>]>,
+}
+
+unsafe impl<T> Sync for Ring<T> where T: Send {}
+
+impl<T> Ring<T> {
+    fn view(&self) -> RingRef<'_, T> {
+        RingRef {
+            indices: &self.indices,
+            slots: &self.slots,
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        self.view().drop_items();
+    }
+}
+
+/// Creates a single-producer single-consumer ring buffer holding up to `capacity` items.
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    ring_at(capacity, 0)
+}
+
+/// A [`ring`] whose indices start at `index`, wrapped into their range, rather than 0, so a
+/// test or fuzz target can put the wrap of the indices wherever it likes.
+#[cfg(any(test, fuzzing))]
+#[doc(hidden)]
+pub fn ring_starting_at<T>(capacity: usize, index: usize) -> (Producer<T>, Consumer<T>) {
+    ring_at(capacity, index)
+}
+
+fn ring_at<T>(capacity: usize, index: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "ring capacity must be non-zero");
+    assert!(capacity <= usize::MAX / 4, "ring capacity too large");
+    let index = index % (2 * capacity);
+    let ring = Arc::new(Ring {
+        indices: Indices {
+            head: CachePadded::new(AtomicUsize::new(index)),
+            tail: CachePadded::new(AtomicUsize::new(index)),
+        },
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+            writer: Writer {
+                head: index,
+                tail: index,
+            },
+        },
+        Consumer {
+            ring,
+            reader: Reader {
+                head: index,
+                tail: index,
+            },
+        },
+    )
+}
+
+// The producer's side of the algorithm, with its own copies of the indices.
+struct Writer {
+    // Last observed consumer head; only refreshed when it looks like we're out of room.
+    head: usize,
+    tail: usize,
+}
+
+impl Writer {
+    fn free_slots<T>(&mut self, ring: RingRef<'_, T>, wanted: usize) -> usize {
+        let mut free = ring.capacity() - ring.distance(self.head, self.tail);
+        if free < wanted {
+            // Acquire: the consumer must be done reading a slot before we overwrite it.
+            self.head = ring.indices.head.load(Ordering::Acquire);
+            free = ring.capacity() - ring.distance(self.head, self.tail);
+        }
+        free
+    }
+
+    fn publish<T>(&mut self, ring: RingRef<'_, T>, n: usize) {
+        self.tail = ring.advance(self.tail, n);
+        // Release: makes the slot writes visible to the consumer that acquires tail.
+        ring.indices.tail.store(self.tail, Ordering::Release);
+    }
+
+    fn push<T>(&mut self, ring: RingRef<'_, T>, t: T) -> Result<(), T> {
+        if self.free_slots(ring, 1) == 0 {
+            return Err(t);
+        }
+        unsafe { (*ring.slot(self.tail)).as_mut_ptr().write(t) };
+        self.publish(ring, 1);
+        Ok(())
+    }
+
+    fn push_slice<T: Copy>(&mut self, ring: RingRef<'_, T>, items: &[T]) -> usize {
+        let mut chunk = self.write_chunk(ring, items.len());
+        let n = chunk.len();
+        let (first, second) = chunk.as_mut_slices();
+        for (slot, item) in first.iter_mut().chain(second).zip(items) {
+            *slot = MaybeUninit::new(*item);
+        }
+        // Safety: all n slots were just initialized.
+        unsafe { chunk.commit(n) };
+        n
+    }
+
+    fn write_chunk<'a, T>(&'a mut self, ring: RingRef<'a, T>, n: usize) -> WriteChunk<'a, T> {
+        let len = cmp::min(n, self.free_slots(ring, n));
+        WriteChunk {
+            ring,
+            writer: self,
+            len,
+        }
+    }
+}
+
+/// The writing half of a [`ring`].
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    writer: Writer,
+}
+
+impl<T> Producer<T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    pub fn push(&mut self, t: T) -> Result<(), T> {
+        self.writer.push(self.ring.view(), t)
+    }
+
+    /// Copies as many items from `items` as fit, with a single publish for the whole batch.
+    pub fn push_slice(&mut self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        self.writer.push_slice(self.ring.view(), items)
+    }
+
+    /// Reserves up to `n` free slots for writing in place.
+    pub fn write_chunk(&mut self, n: usize) -> WriteChunk<'_, T> {
+        self.writer.write_chunk(self.ring.view(), n)
+    }
+}
+
+/// A reserved region of free slots, split in two where it wraps around the end of the buffer.
+pub struct WriteChunk<'a, T> {
+    ring: RingRef<'a, T>,
+    writer: &'a mut Writer,
+    len: usize,
+}
+
+impl<'a, T> WriteChunk<'a, T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_mut_slices(&mut self) -> (&mut [MaybeUninit<T>], &mut [MaybeUninit<T>]) {
+        let ring = self.ring;
+        let ((start, first), second) = ring.regions(self.writer.tail, self.len);
+        // Safety: the producer owns these free slots until it publishes them, and we hold
+        // the producer mutably for the lifetime of the returned slices.
+        unsafe {
+            (
+                std::slice::from_raw_parts_mut(ring.slot(start), first),
+                std::slice::from_raw_parts_mut(ring.slot(0), second),
+            )
+        }
+    }
+
+    /// Publishes the first `n` slots of the chunk to the consumer.
+    ///
+    /// # Safety
+    ///
+    /// The first `n` slots (in `as_mut_slices` order) must have been initialized.
+    pub unsafe fn commit(self, n: usize) {
+        assert!(n <= self.len, "committed more slots than were reserved");
+        self.writer.publish(self.ring, n);
+    }
+}
+
+// The consumer's side of the algorithm, with its own copies of the indices.
+struct Reader {
+    head: usize,
+    // Last observed producer tail; only refreshed when it looks like we've run dry.
+    tail: usize,
+}
+
+impl Reader {
+    fn available<T>(&mut self, ring: RingRef<'_, T>, wanted: usize) -> usize {
+        let mut available = ring.distance(self.head, self.tail);
+        if available < wanted {
+            // Acquire: pairs with the producer's Release so we see the slot contents.
+            self.tail = ring.indices.tail.load(Ordering::Acquire);
+            available = ring.distance(self.head, self.tail);
+        }
+        available
+    }
+
+    fn release<T>(&mut self, ring: RingRef<'_, T>, n: usize) {
+        self.head = ring.advance(self.head, n);
+        // Release: our reads of the slots must be done before the producer reuses them.
+        ring.indices.head.store(self.head, Ordering::Release);
+    }
+
+    fn pop<T>(&mut self, ring: RingRef<'_, T>) -> Option<T> {
+        if self.available(ring, 1) == 0 {
+            return None;
+        }
+        let t = unsafe { (*ring.slot(self.head)).as_ptr().read() };
+        self.release(ring, 1);
+        Some(t)
+    }
+
+    fn pop_slice<T: Copy>(&mut self, ring: RingRef<'_, T>, out: &mut [T]) -> usize {
+        let chunk = self.read_chunk(ring, out.len());
+        let n = chunk.len();
+        let (first, second) = chunk.as_slices();
+        for (item, slot) in first.iter().chain(second).zip(out.iter_mut()) {
+            *slot = *item;
+        }
+        chunk.commit(n);
+        n
+    }
+
+    fn read_chunk<'a, T>(&'a mut self, ring: RingRef<'a, T>, n: usize) -> ReadChunk<'a, T> {
+        let len = cmp::min(n, self.available(ring, n));
+        ReadChunk {
+            ring,
+            reader: self,
+            len,
+        }
+    }
+}
+
+/// The reading half of a [`ring`].
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    reader: Reader,
+}
+
+impl<T> Consumer<T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.reader.pop(self.ring.view())
+    }
+
+    /// Copies as many items as are available into `out`, with a single release for the batch.
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        self.reader.pop_slice(self.ring.view(), out)
+    }
+
+    /// Borrows up to `n` readable items in place.
+    pub fn read_chunk(&mut self, n: usize) -> ReadChunk<'_, T> {
+        self.reader.read_chunk(self.ring.view(), n)
+    }
+}
+
+/// A region of readable items, split in two where it wraps around the end of the buffer.
+pub struct ReadChunk<'a, T> {
+    ring: RingRef<'a, T>,
+    reader: &'a mut Reader,
+    len: usize,
+}
+
+impl<'a, T> ReadChunk<'a, T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let ring = self.ring;
+        let ((start, first), second) = ring.regions(self.reader.head, self.len);
+        // Safety: these slots were published by the producer and won't be touched by it
+        // until we release them.
+        unsafe {
+            (
+                std::slice::from_raw_parts(ring.slot(start) as *const T, first),
+                std::slice::from_raw_parts(ring.slot(0) as *const T, second),
+            )
+        }
+    }
+
+    /// Drops the first `n` items of the chunk and hands their slots back to the producer.
+    pub fn commit(self, n: usize) {
+        assert!(n <= self.len, "committed more items than were read");
+        let ring = self.ring;
+        let mut idx = self.reader.head;
+        for _ in 0..n {
+            unsafe { ptr::drop_in_place((*ring.slot(idx)).as_mut_ptr()) };
+            idx = ring.advance(idx, 1);
+        }
+        self.reader.release(ring, n);
+    }
+}
+
+/// A [`ring`] with its `N` slots inline rather than on the heap, so it can be made in a const
+/// and kept in a `static`. It's split into its two halves once, by [`split`](Self::split),
+/// which borrow it.
+pub struct StaticRingBuffer<T, const N: usize> {
+    indices: Indices,
+    slots: [Slot<T>; N],
+    split: AtomicBool,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for StaticRingBuffer<T, N> {}
+
+impl<T, const N: usize> StaticRingBuffer<T, N> {
+    /// # Panics
+    ///
+    /// If `N` is zero or more than a quarter of `usize::MAX`; at compile time, in a const or a
+    /// static.
+    pub const fn new() -> Self {
+        assert!(N > 0, "ring capacity must be non-zero");
+        assert!(N <= usize::MAX / 4, "ring capacity too large");
+        Self {
+            indices: Indices::new(),
+            // Safety: an array of MaybeUninit needs no initializing.
+            slots: unsafe { MaybeUninit::uninit().assume_init() },
+            split: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn view(&self) -> RingRef<'_, T> {
+        RingRef {
+            indices: &self.indices,
+            slots: &self.slots,
+        }
+    }
+
+    /// The producer and the consumer, the first time it's called; None after that, even once
+    /// they're dropped, since they don't hand back where they'd got to.
+    pub fn split(&self) -> Option<(StaticProducer<'_, T>, StaticConsumer<'_, T>)> {
+        if self.split.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some((
+            StaticProducer {
+                ring: self.view(),
+                writer: Writer { head: 0, tail: 0 },
+            },
+            StaticConsumer {
+                ring: self.view(),
+                reader: Reader { head: 0, tail: 0 },
+            },
+        ))
+    }
+}
+
+impl<T, const N: usize> Default for StaticRingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for StaticRingBuffer<T, N> {
+    fn drop(&mut self) {
+        self.view().drop_items();
+    }
+}
+
+/// The writing half of a [`StaticRingBuffer`], with [`Producer`]'s methods.
+pub struct StaticProducer<'a, T> {
+    ring: RingRef<'a, T>,
+    writer: Writer,
+}
+
+impl<'a, T> StaticProducer<'a, T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    pub fn push(&mut self, t: T) -> Result<(), T> {
+        self.writer.push(self.ring, t)
+    }
+
+    /// Copies as many items from `items` as fit, with a single publish for the whole batch.
+    pub fn push_slice(&mut self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        self.writer.push_slice(self.ring, items)
+    }
+
+    /// Reserves up to `n` free slots for writing in place.
+    pub fn write_chunk(&mut self, n: usize) -> WriteChunk<'_, T> {
+        self.writer.write_chunk(self.ring, n)
+    }
+}
+
+/// The reading half of a [`StaticRingBuffer`], with [`Consumer`]'s methods.
+pub struct StaticConsumer<'a, T> {
+    ring: RingRef<'a, T>,
+    reader: Reader,
+}
+
+impl<'a, T> StaticConsumer<'a, T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.reader.pop(self.ring)
+    }
+
+    /// Copies as many items as are available into `out`, with a single release for the batch.
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        self.reader.pop_slice(self.ring, out)
+    }
+
+    /// Borrows up to `n` readable items in place.
+    pub fn read_chunk(&mut self, n: usize) -> ReadChunk<'_, T> {
+        self.reader.read_chunk(self.ring, n)
+    }
+}
+
+struct OverwriteSlot<T> {
+    // stamp == idx: free for the write of idx; stamp == idx + 1: holds the value of idx.
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct OverwriteRing<T> {
+    // Unlike Ring, head is contended: the producer advances it to evict when full, so the
+    // consumer claims an entry with a CAS on head before reading its slot.
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    dropped: AtomicUsize,
+    slots: Box<[OverwriteSlot<T>]>,
+}
+
+unsafe impl<T> Sync for OverwriteRing<T> where T: Send {}
+
+impl<T> OverwriteRing<T> {
+    fn slot(&self, idx: usize) -> &OverwriteSlot<T> {
+        &self.slots[idx % self.slots.len()]
+    }
+}
+
+impl<T> Drop for OverwriteRing<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        for idx in *self.head.get_mut()..tail {
+            unsafe { ptr::drop_in_place((*self.slot(idx).value.get()).as_mut_ptr()) };
+        }
+    }
+}
+
+/// Creates an SPSC ring that keeps the latest `capacity` items, overwriting the oldest
+/// entry when a push finds it full.
+pub fn overwriting_ring<T>(capacity: usize) -> (OverwriteProducer<T>, OverwriteConsumer<T>) {
+    assert!(capacity > 0, "ring capacity must be non-zero");
+    let ring = Arc::new(OverwriteRing {
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        dropped: AtomicUsize::new(0),
+        slots: (0..capacity)
+            .map(|i| OverwriteSlot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect(),
+    });
+    (
+        OverwriteProducer {
+            ring: Arc::clone(&ring),
+            tail: 0,
+        },
+        OverwriteConsumer { ring },
+    )
+}
+
+/// The writing half of an [`overwriting_ring`].
+pub struct OverwriteProducer<T> {
+    ring: Arc<OverwriteRing<T>>,
+    tail: usize,
+}
+
+impl<T> OverwriteProducer<T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    /// Pushes `t`, returning the evicted oldest entry if the ring was full.
+    pub fn push(&mut self, t: T) -> Option<T> {
+        let ring = &*self.ring;
+        let tail = self.tail;
+        let slot = ring.slot(tail);
+        let mut evicted = None;
+        loop {
+            let head = ring.head.load(Ordering::Acquire);
+            if tail - head < self.capacity() {
+                // The consumer may still be copying the previous lap's value out of this
+                // slot; it marks the slot free once it's done.
+                while slot.stamp.load(Ordering::Acquire) != tail {
+                    std::hint::spin_loop();
+                }
+                break;
+            }
+            // Full, so head == tail - capacity lives in our slot. Winning the CAS takes that
+            // entry away from the consumer, which only reads a slot after claiming it.
+            if ring
+                .head
+                .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                evicted = Some(unsafe { (*slot.value.get()).as_ptr().read() });
+                ring.dropped.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+        }
+        unsafe { (*slot.value.get()).as_mut_ptr().write(t) };
+        slot.stamp.store(tail + 1, Ordering::Release);
+        self.tail = tail + 1;
+        ring.tail.store(self.tail, Ordering::Release);
+        evicted
+    }
+}
+
+/// The reading half of an [`overwriting_ring`].
+pub struct OverwriteConsumer<T> {
+    ring: Arc<OverwriteRing<T>>,
+}
+
+impl<T> OverwriteConsumer<T> {
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        loop {
+            let head = ring.head.load(Ordering::Acquire);
+            let slot = ring.slot(head);
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp != head + 1 {
+                if (stamp.wrapping_sub(head + 1) as isize) < 0 {
+                    // Not written yet: the ring is empty.
+                    return None;
+                }
+                // The producer evicted `head` and wrote a newer lap; reload head.
+                continue;
+            }
+            if ring
+                .head
+                .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                let t = unsafe { (*slot.value.get()).as_ptr().read() };
+                // Release: the producer must not overwrite the slot until we're done reading.
+                slot.stamp.store(head + self.capacity(), Ordering::Release);
+                return Some(t);
+            }
+        }
+    }
+
+    /// Returns how many entries were overwritten before they could be popped since the last call.
+    pub fn take_dropped(&mut self) -> usize {
+        self.ring.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn batch_roundtrip() {
+    let total = if cfg!(miri) { 2000 } else { 100_000 };
+    let (mut tx, mut rx) = ring::<usize>(64);
+    let producer = std::thread::spawn(move || {
+        let items: Vec<usize> = (0..total).collect();
+        let mut sent = 0;
+        while sent < items.len() {
+            let n = tx.push_slice(&items[sent..std::cmp::min(sent + 48, items.len())]);
+            if n == 0 {
+                std::thread::yield_now();
+            }
+            sent += n;
+        }
+    });
+    let mut buf = [0; 40];
+    let mut expected = 0;
+    while expected < total {
+        let n = rx.pop_slice(&mut buf);
+        if n == 0 {
+            std::thread::yield_now();
+        }
+        for &v in &buf[..n] {
+            assert_eq!(v, expected);
+            expected += 1;
+        }
+    }
+    producer.join().unwrap();
+    assert_eq!(rx.pop(), None);
+}
+
+#[test]
+fn chunks_wrap_around() {
+    let (mut tx, mut rx) = ring::<String>(4);
+    for s in ["a", "b", "c"].iter() {
+        tx.push(s.to_string()).unwrap();
+    }
+    assert_eq!(rx.pop().as_deref(), Some("a"));
+    assert_eq!(rx.pop().as_deref(), Some("b"));
+
+    let mut chunk = tx.write_chunk(10);
+    assert_eq!(chunk.len(), 3);
+    let (first, second) = chunk.as_mut_slices();
+    assert_eq!((first.len(), second.len()), (1, 2));
+    for (i, slot) in first.iter_mut().chain(second).enumerate() {
+        *slot = MaybeUninit::new(i.to_string());
+    }
+    unsafe { chunk.commit(3) };
+    assert!(tx.push("full".to_string()).is_err());
+
+    let chunk = rx.read_chunk(10);
+    let (first, second) = chunk.as_slices();
+    assert_eq!(first, ["c", "0"]);
+    assert_eq!(second, ["1", "2"]);
+    chunk.commit(3);
+    assert_eq!(rx.pop().as_deref(), Some("2"));
+    assert_eq!(rx.pop(), None);
+}
+
+#[test]
+fn static_ring_buffer_in_a_static() {
+    static RING: StaticRingBuffer<usize, 8> = StaticRingBuffer::new();
+    let total = if cfg!(miri) { 500 } else { 50_000 };
+    let (mut tx, mut rx) = RING.split().unwrap();
+    assert!(RING.split().is_none());
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..total {
+                while tx.push(i).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut buf = [0; 5];
+        let mut expected = 0;
+        while expected < total {
+            let n = rx.pop_slice(&mut buf);
+            if n == 0 {
+                std::thread::yield_now();
+            }
+            for &v in &buf[..n] {
+                assert_eq!(v, expected);
+                expected += 1;
+            }
+        }
+        assert_eq!(rx.pop(), None);
+    });
+
+    let ring = StaticRingBuffer::<String, 2>::new();
+    let (mut tx, _rx) = ring.split().unwrap();
+    tx.push(String::from("dropped with the ring")).unwrap();
+}
+
+#[test]
+fn overwrite_keeps_latest() {
+    let (mut tx, mut rx) = overwriting_ring(4);
+    for i in 0..10 {
+        let evicted = tx.push(i);
+        assert_eq!(evicted, if i >= 4 { Some(i - 4) } else { None });
+    }
+    assert_eq!(rx.take_dropped(), 6);
+    assert_eq!(rx.take_dropped(), 0);
+    let drained: Vec<_> = std::iter::from_fn(|| rx.pop()).collect();
+    assert_eq!(drained, [6, 7, 8, 9]);
+}
+
+#[test]
+fn overwrite_concurrent_accounting() {
+    let total = if cfg!(miri) { 1000 } else { 50_000 };
+    let (mut tx, mut rx) = overwriting_ring::<usize>(8);
+    let producer = std::thread::spawn(move || {
+        for i in 0..total {
+            tx.push(i);
+            if i % 64 == 0 {
+                std::thread::yield_now();
+            }
+        }
+    });
+    let mut popped = 0;
+    let mut last = None;
+    loop {
+        match rx.pop() {
+            Some(v) => {
+                assert!(last.is_none_or(|l| v > l), "entries must stay in order");
+                last = Some(v);
+                popped += 1;
+            }
+            None if producer.is_finished() => break,
+            None => std::thread::yield_now(),
+        }
+    }
+    // is_finished alone doesn't synchronize with the producer's last pushes; join does.
+    producer.join().unwrap();
+    while let Some(v) = rx.pop() {
+        assert!(last.is_none_or(|l| v > l));
+        last = Some(v);
+        popped += 1;
+    }
+    assert_eq!(last, Some(total - 1));
+    assert_eq!(popped + rx.take_dropped(), total);
+}
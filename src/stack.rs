@@ -0,0 +1,295 @@
+use crate::epoch;
+use std::cell::UnsafeCell;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use std::thread;
+
+struct Node<T> {
+    // Moved out by whoever pops the node; the node itself is freed later by the collector.
+    value: ManuallyDrop<T>,
+    next: *mut Node<T>,
+}
+
+// Only so it can be retired: next is written once, before the node is published.
+unsafe impl<T: Send> Send for Node<T> {}
+
+/// Treiber's lock-free stack.
+///
+/// Popped nodes are retired through [`epoch`], which also rules out ABA: a node's address
+/// can't be reused while a thread that loaded it is still pinned.
+pub struct TreiberStack<T> {
+    head: AtomicPtr<Node<T>>,
+    // Counted up before a push links its node and down after a pop unlinks one, so it never
+    // goes below the number of nodes a pop could find.
+    len: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for TreiberStack<T> {}
+unsafe impl<T: Send> Sync for TreiberStack<T> {}
+
+impl<T> TreiberStack<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of items, which may be out of date by the time it's returned.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T: Send + 'static> TreiberStack<T> {
+    pub fn push(&self, value: T) {
+        let new = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+        }));
+        self.len.fetch_add(1, Ordering::Relaxed);
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*new).next = head };
+            // Release: publishes the node's value and next pointer to the popper.
+            match self
+                .head
+                .compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = epoch::pin();
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if head.is_null() {
+                return None;
+            }
+            // Safe to read even if someone else pops it first: we're pinned.
+            let next = unsafe { (*head).next };
+            match self
+                .head
+                .compare_exchange_weak(head, next, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => unsafe {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    let value = ptr::read(&*(*head).value);
+                    guard.defer_destroy(head);
+                    return Some(value);
+                },
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    /// Pops items until it finds the stack empty, for flushing what's left at shutdown.
+    /// Pushes made meanwhile are drained too, so it ends once pushers stop or fall behind.
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+impl<T> Default for TreiberStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        let mut curr = *self.head.get_mut();
+        while !curr.is_null() {
+            let mut node = unsafe { Box::from_raw(curr) };
+            unsafe { ManuallyDrop::drop(&mut node.value) };
+            curr = node.next;
+        }
+    }
+}
+
+// Slot states of the bounded stack.
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const FULL: u8 = 2;
+const READING: u8 = 3;
+
+struct Slot<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// An array-backed stack that holds at most a fixed number of items, for free lists that
+/// mustn't grow without bound.
+///
+/// Pushes and pops reserve a slot by moving `top` with a CAS, then hand the value over through
+/// the slot's state. If the slot's previous occupant is still being written or read, the new
+/// owner waits for it, so a thread stalled mid-operation can hold up others on the same slot.
+pub struct BoundedStack<T> {
+    top: AtomicUsize,
+    slots: Box<[Slot<T>]>,
+}
+
+unsafe impl<T: Send> Send for BoundedStack<T> {}
+unsafe impl<T: Send> Sync for BoundedStack<T> {}
+
+impl<T> BoundedStack<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            top: AtomicUsize::new(0),
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    state: AtomicU8::new(EMPTY),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.top.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Moves the slot from `from` to `to` once whoever had it before is done with it.
+    fn acquire_slot(slot: &Slot<T>, from: u8, to: u8) {
+        // Acquire: pairs with the Release store that handed the slot over.
+        while slot
+            .state
+            .compare_exchange_weak(from, to, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            thread::yield_now();
+        }
+    }
+
+    /// Pushes `value`, or hands it back if the stack is full.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut top = self.top.load(Ordering::Relaxed);
+        loop {
+            if top == self.slots.len() {
+                return Err(value);
+            }
+            match self
+                .top
+                .compare_exchange_weak(top, top + 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(t) => top = t,
+            }
+        }
+        let slot = &self.slots[top];
+        Self::acquire_slot(slot, EMPTY, WRITING);
+        unsafe { (*slot.value.get()).write(value) };
+        // Release: publishes the value to the popper.
+        slot.state.store(FULL, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut top = self.top.load(Ordering::Relaxed);
+        loop {
+            if top == 0 {
+                return None;
+            }
+            match self
+                .top
+                .compare_exchange_weak(top, top - 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(t) => top = t,
+            }
+        }
+        let slot = &self.slots[top - 1];
+        Self::acquire_slot(slot, FULL, READING);
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        // Release: we're done reading before the next pusher overwrites the slot.
+        slot.state.store(EMPTY, Ordering::Release);
+        Some(value)
+    }
+
+    /// Pops items until it finds the stack empty, like [`TreiberStack::drain`].
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.pop())
+    }
+}
+
+impl<T> Drop for BoundedStack<T> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if *slot.state.get_mut() == FULL {
+                unsafe { slot.value.get_mut().assume_init_drop() };
+            }
+        }
+    }
+}
+
+#[test]
+fn stack_concurrent() {
+    use std::sync::atomic::AtomicUsize;
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let stack = &TreiberStack::new();
+    let popped = &AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                let mut sum = 0;
+                for i in 0..n {
+                    stack.push(t * n + i);
+                    if i % 2 == 0 {
+                        sum += stack.pop().unwrap();
+                    }
+                }
+                popped.fetch_add(sum, Ordering::Relaxed);
+            });
+        }
+    });
+    assert_eq!(stack.len(), 2 * n);
+    let rest: usize = stack.drain().sum();
+    assert_eq!((stack.len(), stack.is_empty()), (0, true));
+    assert_eq!(
+        popped.load(Ordering::Relaxed) + rest,
+        (0..4 * n).sum::<usize>()
+    );
+}
+
+#[test]
+fn bounded_stack_capacity() {
+    let n = if cfg!(miri) { 100 } else { 1000 };
+    let stack = &BoundedStack::new(8);
+    for i in 0..8 {
+        assert_eq!(stack.try_push(i), Ok(()));
+    }
+    assert_eq!(stack.try_push(8), Err(8));
+    // Used as a free list of 8 tokens: every token taken goes back, and none is ever lost or
+    // handed to two threads.
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(move || {
+                for _ in 0..n {
+                    if let Some(token) = stack.pop() {
+                        std::thread::yield_now();
+                        assert!(stack.try_push(token).is_ok());
+                    }
+                }
+            });
+        }
+    });
+    let mut tokens: Vec<_> = std::iter::from_fn(|| stack.pop()).collect();
+    tokens.sort_unstable();
+    assert_eq!(tokens, (0..8).collect::<Vec<_>>());
+}
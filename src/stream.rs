@@ -0,0 +1,229 @@
+use crate::atomic_waker::AtomicWaker;
+use crate::event_count::{EventCount, WatchKey};
+use crate::mpmc::TrySendError;
+use crate::mpsc::{SendError, TryRecvError};
+use crate::select::{SelectRecv, SelectSend};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+// Forwards an event count's notifies to whichever task polled last.
+struct TaskWaker(AtomicWaker);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.wake();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.wake();
+    }
+}
+
+// Watches a channel's event count from the first Pending until the adapter makes progress.
+// The watch stays put across polls and only the task's waker is swapped, so a pending
+// adapter costs the channel's notifiers a lock and nothing more.
+struct Registration {
+    task: Arc<TaskWaker>,
+    waker: Waker,
+    key: Option<WatchKey>,
+}
+
+impl Registration {
+    fn new() -> Self {
+        let task = Arc::new(TaskWaker(AtomicWaker::new()));
+        Self {
+            waker: Waker::from(Arc::clone(&task)),
+            task,
+            key: None,
+        }
+    }
+
+    // The caller checks its condition again afterwards, as with EventCount::watch.
+    fn register(&mut self, events: &EventCount, waker: &Waker) {
+        self.task.0.register(waker);
+        if self.key.is_none() {
+            self.key = Some(events.watch(&self.waker));
+        }
+    }
+
+    fn unregister(&mut self, events: &EventCount) {
+        if let Some(key) = self.key.take() {
+            events.unwatch(key);
+        }
+    }
+}
+
+/// A [`Stream`] of the messages on a receiver, ending once its channel is disconnected.
+///
+/// Works with any receiver [`Select`](crate::select::Select) can use, which covers both
+/// [`mpsc`](crate::mpsc) and [`bounded`](crate::mpmc::bounded) channels.
+pub struct RecvStream<C: SelectRecv> {
+    rx: C,
+    registration: Registration,
+}
+
+/// A [`Sink`] that sends on a sender, failing once its channel is disconnected.
+///
+/// A message that finds the channel full is held by the sink until there's room, so
+/// [`poll_ready`](Sink::poll_ready) and [`poll_flush`](Sink::poll_flush) are what wait.
+pub struct SendSink<C: SelectSend> {
+    tx: C,
+    pending: Option<C::Msg>,
+    registration: Registration,
+}
+
+impl<C: SelectRecv> RecvStream<C> {
+    pub fn new(rx: C) -> Self {
+        Self {
+            rx,
+            registration: Registration::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.rx
+    }
+}
+
+// Neither adapter relies on being pinned.
+impl<C: SelectRecv> Unpin for RecvStream<C> {}
+
+impl<C: SelectRecv> Stream for RecvStream<C> {
+    type Item = C::Msg;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<C::Msg>> {
+        let this = self.get_mut();
+        let events = this.rx.recv_events();
+        for registered in [false, true] {
+            match this.rx.try_recv_select() {
+                Ok(msg) => {
+                    this.registration.unregister(events);
+                    return Poll::Ready(Some(msg));
+                }
+                Err(TryRecvError::Disconnected) => {
+                    this.registration.unregister(events);
+                    return Poll::Ready(None);
+                }
+                Err(TryRecvError::Empty) if !registered => {
+                    this.registration.register(events, cx.waker());
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<C: SelectRecv> Drop for RecvStream<C> {
+    fn drop(&mut self) {
+        self.registration.unregister(self.rx.recv_events());
+    }
+}
+
+impl<C: SelectSend> SendSink<C> {
+    pub fn new(tx: C) -> Self {
+        Self {
+            tx,
+            pending: None,
+            registration: Registration::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.tx
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SendError<C::Msg>>> {
+        let events = self.tx.send_events();
+        let mut registered = false;
+        while let Some(msg) = self.pending.take() {
+            match self.tx.try_send_select(msg) {
+                Ok(()) => {}
+                Err(TrySendError::Disconnected(msg)) => {
+                    self.registration.unregister(events);
+                    return Poll::Ready(Err(SendError(msg)));
+                }
+                Err(TrySendError::Full(msg)) => {
+                    self.pending = Some(msg);
+                    if registered {
+                        return Poll::Pending;
+                    }
+                    self.registration.register(events, cx.waker());
+                    registered = true;
+                }
+            }
+        }
+        self.registration.unregister(events);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: SelectSend> Unpin for SendSink<C> {}
+
+impl<C: SelectSend> Sink<C::Msg> for SendSink<C> {
+    type Error = SendError<C::Msg>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: C::Msg) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "start_send without poll_ready");
+        match this.tx.try_send_select(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Disconnected(msg)) => Err(SendError(msg)),
+            Err(TrySendError::Full(msg)) => {
+                this.pending = Some(msg);
+                Ok(())
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    // Only flushes: the channel disconnects once the sink and every other sender are dropped.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl<C: SelectSend> Drop for SendSink<C> {
+    fn drop(&mut self) {
+        self.registration.unregister(self.tx.send_events());
+    }
+}
+
+#[test]
+fn stream_and_sink_over_bounded_channel() {
+    use crate::async_sync::block_on;
+    use crate::mpmc;
+    use std::future::poll_fn;
+
+    let (tx, rx) = mpmc::bounded(1);
+    let sender = std::thread::spawn(move || {
+        let mut sink = SendSink::new(tx);
+        block_on(async {
+            for i in 0..20 {
+                poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx)).await?;
+                Pin::new(&mut sink).start_send(i)?;
+            }
+            poll_fn(|cx| Pin::new(&mut sink).poll_close(cx)).await
+        })
+    });
+    let mut stream = RecvStream::new(rx);
+    let got = block_on(async {
+        let mut got = Vec::new();
+        while let Some(i) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            got.push(i);
+        }
+        got
+    });
+    assert!(got.into_iter().eq(0..20));
+    assert_eq!(sender.join().unwrap(), Ok(()));
+}
@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::sync::atomic::Ordering;
+
+// Panics on orderings that are almost always bugs, as the crate's atomics use them. Under the
+// `strict-orderings` feature `sync_shim::atomic` is the wrappers in `atomic` here, and the
+// unit tests' scheduler atomics make the same checks, so a mistake in the crate's own
+// primitives shows up the first time its code runs rather than on the one interleaving in a
+// million that the weaker ordering lets through.
+//
+// Two patterns are caught:
+//
+// - A compare-exchange whose success ordering reads more weakly than its failure ordering,
+//   as with `(Release, Acquire)`: the exchange that fails synchronizes and the one that
+//   succeeds doesn't, which is backwards for anything but a retry loop that only reads.
+//
+// - A Relaxed store to an atomic the thread took with an Acquire read-modify-write and hasn't
+//   released since, by a Release store or RMW to it or a Release fence: the shape of a lock
+//   whose unlock doesn't publish what was done under it.
+//
+// The second is a heuristic, tracked per thread over the last few atomics taken.
+
+// How many atomics a thread's taken to keep track of; older ones are forgotten.
+const TRACKED: usize = 64;
+
+thread_local! {
+    static TAKEN: RefCell<Vec<(usize, Ordering)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn reads(order: Ordering) -> u8 {
+    match order {
+        Ordering::Acquire | Ordering::AcqRel => 1,
+        Ordering::SeqCst => 2,
+        _ => 0,
+    }
+}
+
+fn releases(order: Ordering) -> bool {
+    matches!(
+        order,
+        Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+    )
+}
+
+/// Checks the orderings of a compare-exchange.
+#[track_caller]
+pub fn compare_exchange(success: Ordering, failure: Ordering) {
+    if reads(success) < reads(failure) {
+        panic!(
+            "strict orderings: compare_exchange with success ordering {:?} and failure \
+             ordering {:?}. The failure ordering is only the load of an exchange that fails, \
+             so this one synchronizes when it fails and not when it succeeds. A success \
+             ordering has to read at least as strongly, and if only the successful exchange \
+             needs to synchronize, the failure can be Relaxed (see std::sync::atomic's \
+             compare_exchange, and [atomics.types.operations] in the C++ standard).",
+            success, failure
+        );
+    }
+}
+
+/// Notes a read-modify-write of the atomic at `addr` that happened, as a successful exchange
+/// does and a failed one doesn't.
+pub fn rmw(addr: usize, order: Ordering) {
+    let _ = TAKEN.try_with(|taken| {
+        let mut taken = taken.borrow_mut();
+        taken.retain(|&(a, _)| a != addr);
+        if reads(order) > 0 && !releases(order) {
+            if taken.len() == TRACKED {
+                taken.remove(0);
+            }
+            taken.push((addr, order));
+        }
+    });
+}
+
+/// Checks a store to the atomic at `addr`.
+#[track_caller]
+pub fn store(addr: usize, order: Ordering) {
+    let taken = TAKEN
+        .try_with(|taken| {
+            let mut taken = taken.borrow_mut();
+            let i = taken.iter().position(|&(a, _)| a == addr)?;
+            Some(taken.remove(i).1)
+        })
+        .ok()
+        .flatten();
+    if let (Ordering::Relaxed, Some(taken)) = (order, taken) {
+        panic!(
+            "strict orderings: a Relaxed store to the atomic at {:#x}, which this thread took \
+             with an {:?} read-modify-write and hasn't released since. A Relaxed store doesn't \
+             release, so nothing this thread wrote in between is guaranteed visible to the next \
+             thread that takes it: that needs the store to be Release, or a Release fence \
+             before it (see the Release/Acquire synchronizes-with rule in std::sync::atomic's \
+             Ordering docs, [atomics.order] in the C++ standard).",
+            addr, taken
+        );
+    }
+}
+
+/// Notes a fence, which releases everything taken before it if it's a Release one.
+pub fn fence(order: Ordering) {
+    if releases(order) {
+        let _ = TAKEN.try_with(|taken| taken.borrow_mut().clear());
+    }
+}
+
+/// The wrapped atomics `sync_shim::atomic` is under the feature: std's, with the checks.
+pub mod atomic {
+    use std::sync::atomic as std_atomic;
+    pub use std::sync::atomic::Ordering;
+
+    #[track_caller]
+    pub fn fence(order: Ordering) {
+        super::fence(order);
+        std_atomic::fence(order);
+    }
+
+    macro_rules! atomic {
+        ($name:ident $(<$g:ident>)?, $t:ty $(, $rmw:ident)*) => {
+            #[derive(Debug, Default)]
+            pub struct $name$(<$g>)?(std_atomic::$name$(<$g>)?);
+
+            impl$(<$g>)? $name$(<$g>)? {
+                pub const fn new(v: $t) -> Self {
+                    Self(std_atomic::$name::new(v))
+                }
+
+                fn addr(&self) -> usize {
+                    self as *const Self as usize
+                }
+
+                pub fn load(&self, order: Ordering) -> $t {
+                    self.0.load(order)
+                }
+
+                #[track_caller]
+                pub fn store(&self, v: $t, order: Ordering) {
+                    super::store(self.addr(), order);
+                    self.0.store(v, order);
+                }
+
+                pub fn swap(&self, v: $t, order: Ordering) -> $t {
+                    super::rmw(self.addr(), order);
+                    self.0.swap(v, order)
+                }
+
+                #[track_caller]
+                pub fn compare_exchange(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    super::compare_exchange(success, failure);
+                    let r = self.0.compare_exchange(current, new, success, failure);
+                    if r.is_ok() {
+                        super::rmw(self.addr(), success);
+                    }
+                    r
+                }
+
+                #[track_caller]
+                pub fn compare_exchange_weak(
+                    &self,
+                    current: $t,
+                    new: $t,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$t, $t> {
+                    super::compare_exchange(success, failure);
+                    let r = self.0.compare_exchange_weak(current, new, success, failure);
+                    if r.is_ok() {
+                        super::rmw(self.addr(), success);
+                    }
+                    r
+                }
+
+                pub fn get_mut(&mut self) -> &mut $t {
+                    self.0.get_mut()
+                }
+
+                pub fn into_inner(self) -> $t {
+                    self.0.into_inner()
+                }
+
+                $(
+                    pub fn $rmw(&self, v: $t, order: Ordering) -> $t {
+                        super::rmw(self.addr(), order);
+                        self.0.$rmw(v, order)
+                    }
+                )*
+            }
+        };
+    }
+
+    atomic!(AtomicBool, bool, fetch_and, fetch_or, fetch_xor);
+    atomic!(
+        AtomicUsize,
+        usize,
+        fetch_add,
+        fetch_sub,
+        fetch_and,
+        fetch_or,
+        fetch_xor,
+        fetch_max,
+        fetch_min
+    );
+    atomic!(
+        AtomicU64, u64, fetch_add, fetch_sub, fetch_and, fetch_or, fetch_xor, fetch_max, fetch_min
+    );
+    atomic!(AtomicPtr<T>, *mut T);
+}
+
+#[test]
+#[should_panic(expected = "strict orderings: a Relaxed store")]
+fn strict_orderings_catch_a_relaxed_unlock() {
+    use atomic::AtomicBool;
+
+    let locked = AtomicBool::new(false);
+    while locked
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {}
+    locked.store(false, Ordering::Relaxed);
+}
+
+#[test]
+#[should_panic(expected = "success ordering Release and failure ordering Acquire")]
+fn strict_orderings_catch_a_weaker_success() {
+    let a = atomic::AtomicUsize::new(0);
+    let _ = a.compare_exchange(0, 1, Ordering::Release, Ordering::Acquire);
+}
+
+#[test]
+fn strict_orderings_allow_releasing_unlocks() {
+    use atomic::{AtomicBool, AtomicUsize};
+
+    let locked = AtomicBool::new(false);
+    assert!(!locked.swap(true, Ordering::Acquire));
+    locked.store(false, Ordering::Release);
+    assert!(!locked.swap(true, Ordering::Acquire));
+    atomic::fence(Ordering::Release);
+    locked.store(false, Ordering::Relaxed);
+    // Taken without Acquire, so not a lock as far as this goes.
+    assert!(!locked.swap(true, Ordering::Relaxed));
+    locked.store(false, Ordering::Relaxed);
+
+    let readers = AtomicUsize::new(0);
+    readers.fetch_add(1, Ordering::Acquire);
+    readers.fetch_sub(1, Ordering::Release);
+    readers.store(0, Ordering::Relaxed);
+    let _ = readers.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire);
+    let _ = readers.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed);
+}
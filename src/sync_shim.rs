@@ -0,0 +1,118 @@
+// The atomics, locks, cell and spin-wait hooks that std provides in a normal build, and that
+// loom (https://docs.rs/loom) or shuttle (https://docs.rs/shuttle) provide when built with
+// `--cfg loom` or `--cfg shuttle`. Code written against this module can be model checked.
+//
+// Loom runs each test under every interleaving and every outcome the memory model allows, and
+// fails on a data race through an UnsafeCell as well as on a failed assertion. That only
+// scales to small tests, so the bigger structures get shuttle tests instead, which try a
+// number of random schedules. Run them with
+//
+//     RUSTFLAGS="--cfg loom" cargo test --release loom
+//     RUSTFLAGS="--cfg shuttle" cargo test --release shuttle
+//
+// Under either, the other tests of a module built on this one are compiled out, since the
+// model checker's types only work inside its runs. See shuttle_check for configuring the
+// shuttle runs.
+
+#[cfg(not(any(loom, shuttle, test)))]
+pub use std::{
+    hint::spin_loop,
+    sync::{Condvar, Mutex, MutexGuard},
+    thread::yield_now,
+};
+
+#[cfg(not(any(loom, shuttle, test, feature = "strict-orderings")))]
+pub use std::sync::atomic;
+
+// std's, checked for orderings that are almost always bugs.
+#[cfg(all(not(any(loom, shuttle, test)), feature = "strict-orderings"))]
+pub use crate::strict_orderings::atomic;
+
+// The unit tests get the deterministic scheduler's, which act as std's outside sched::run.
+#[cfg(all(test, not(any(loom, shuttle))))]
+pub use crate::sched::{atomic, spin_loop, yield_now, Condvar, Mutex, MutexGuard};
+
+#[cfg(loom)]
+pub use loom::{
+    hint::spin_loop,
+    sync::{atomic, Condvar, Mutex, MutexGuard},
+    thread::yield_now,
+};
+
+#[cfg(shuttle)]
+pub use shuttle::{
+    hint::spin_loop,
+    sync::{atomic, Condvar, Mutex, MutexGuard},
+    thread::yield_now,
+};
+
+/// Runs `f` under shuttle's random scheduler, `SHUTTLE_ITERATIONS` times (1000 by default),
+/// starting from the seed in `SHUTTLE_SEED` if that's set. A failing run prints the schedule
+/// it took; set `SHUTTLE_REPLAY` to that string to run just that schedule again.
+#[cfg(shuttle)]
+pub fn shuttle_check(f: impl Fn() + Send + Sync + 'static) {
+    let var = |name| std::env::var(name).ok();
+    if let Some(schedule) = var("SHUTTLE_REPLAY") {
+        return shuttle::replay(f, &schedule);
+    }
+    let iterations = var("SHUTTLE_ITERATIONS").map_or(1000, |n| {
+        n.parse().expect("SHUTTLE_ITERATIONS isn't a number")
+    });
+    match var("SHUTTLE_SEED") {
+        Some(seed) => {
+            let seed = seed.parse().expect("SHUTTLE_SEED isn't a number");
+            shuttle::check_random_with_seed(f, seed, iterations)
+        }
+        None => shuttle::check_random(f, iterations),
+    }
+}
+
+/// `std::cell::UnsafeCell` with access through closures, which is the only access loom can
+/// check.
+pub struct UnsafeCell<T> {
+    #[cfg(not(loom))]
+    v: std::cell::UnsafeCell<T>,
+    #[cfg(loom)]
+    v: loom::cell::UnsafeCell<T>,
+}
+
+impl<T> UnsafeCell<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> Self {
+        Self {
+            v: std::cell::UnsafeCell::new(v),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> Self {
+        Self {
+            v: loom::cell::UnsafeCell::new(v),
+        }
+    }
+
+    /// Calls `f` with a pointer to the value, to read it through.
+    pub fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        #[cfg(not(loom))]
+        return f(self.v.get());
+        #[cfg(loom)]
+        return self.v.with(f);
+    }
+
+    /// Calls `f` with a pointer to the value, to write it through.
+    pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        #[cfg(not(loom))]
+        return f(self.v.get());
+        #[cfg(loom)]
+        return self.v.with_mut(f);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // Safety: &mut self means no one else has access.
+        self.with_mut(|v| unsafe { &mut *v })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
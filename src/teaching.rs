@@ -0,0 +1,326 @@
+use crate::backoff::Backoff;
+use crate::sync_shim::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::sync_shim::{spin_loop, UnsafeCell};
+
+// The classic examples with their orderings as parameters, to weaken on purpose. Each type's
+// `new` uses the weakest orderings that are correct; `with_orderings` takes any. The loom
+// tests at the bottom run every weakening of each and show which ones break it, by loom
+// reporting two threads at the value at once:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --release teaching
+
+/// The spin lock from the `with_lock` example, with the orderings of the compare-exchange that
+/// takes it and the store that gives it back as parameters.
+///
+/// Acquire on the one and Release on the other is what makes the previous holder's writes
+/// visible to the next. Weaken either to Relaxed and two critical sections can overlap as far
+/// as their memory is concerned, even though the lock word itself is only ever held by one.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    acquire: Ordering,
+    release: Ordering,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(t: T) -> Self {
+        Self::with_orderings(t, Ordering::Acquire, Ordering::Release)
+    }
+
+    /// Panics, on the first unlock, if `release` is `Acquire` or `AcqRel`, which a store can't
+    /// take.
+    pub fn with_orderings(t: T, acquire: Ordering, release: Ordering) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            acquire,
+            release,
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let backoff = Backoff::new();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, self.acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                backoff.snooze();
+            }
+            backoff.spin();
+        }
+        // Safety: we hold the lock, if the orderings are strong enough for that to mean
+        // anything.
+        let ret = self.v.with_mut(|v| f(unsafe { &mut *v }));
+        self.locked.store(false, self.release);
+        ret
+    }
+}
+
+/// Message passing: one thread puts a value, then sets a flag; others read the value once
+/// they see the flag set. The flag's store and load orderings are parameters.
+///
+/// A Release store read by an Acquire load is the least that works; with either Relaxed a
+/// reader can see the flag set and still read the value as it was before the put.
+pub struct Mailbox<T> {
+    ready: AtomicBool,
+    store: Ordering,
+    load: Ordering,
+    value: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Mailbox<T> {}
+
+impl<T: Copy> Mailbox<T> {
+    pub fn new() -> Self {
+        Self::with_orderings(Ordering::Release, Ordering::Acquire)
+    }
+
+    /// Panics, on the first put or get, if `store` is `Acquire` or `AcqRel` or `load` is
+    /// `Release` or `AcqRel`.
+    pub fn with_orderings(store: Ordering, load: Ordering) -> Self {
+        Self {
+            ready: AtomicBool::new(false),
+            store,
+            load,
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// At most one call, ever: the write of the value isn't guarded against another.
+    pub unsafe fn put(&self, value: T) {
+        self.value.with_mut(|v| *v = Some(value));
+        self.ready.store(true, self.store);
+    }
+
+    /// The value, if the flag says it's there.
+    pub fn get(&self) -> Option<T> {
+        if self.ready.load(self.load) {
+            // Safety: the put is done and won't come again, if the orderings are strong
+            // enough for the flag to mean that.
+            self.value.with(|v| unsafe { *v })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Copy> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Peterson's lock for two threads, store buffering at its core: each sets its flag, gives
+/// way to the other, then reads the other's flag. The orderings of the flag store, of the
+/// exchange that gives way, and of the loads are parameters.
+///
+/// Both flag stores can sit unseen while both threads load, so both could read the other's
+/// flag as clear and get in. What rules that out is the exchange: the two are ordered, and if
+/// it's AcqRel the later one acquires the earlier thread's flag store along with its write
+/// to `turn`, so the later thread sees that flag set and waits. A plain Release store of
+/// `turn` doesn't do that, not even with a SeqCst fence after it, since nothing then orders
+/// the two writes to `turn` with the fences. The Acquire loads are what pass the data from
+/// one critical section to the next.
+pub struct Peterson<T> {
+    flags: [AtomicBool; 2],
+    // Who goes in when both want to: each thread gives way to the other, and whoever gave
+    // way last waits.
+    turn: AtomicUsize,
+    store: Ordering,
+    exchange: Ordering,
+    load: Ordering,
+    v: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Peterson<T> {}
+
+impl<T> Peterson<T> {
+    pub fn new(t: T) -> Self {
+        Self::with_orderings(t, Ordering::Relaxed, Ordering::AcqRel, Ordering::Acquire)
+    }
+
+    /// Panics, on the first lock, if `store` is `Acquire` or `AcqRel` or `load` is `Release`
+    /// or `AcqRel`.
+    pub fn with_orderings(t: T, store: Ordering, exchange: Ordering, load: Ordering) -> Self {
+        Self {
+            flags: [AtomicBool::new(false), AtomicBool::new(false)],
+            turn: AtomicUsize::new(0),
+            store,
+            exchange,
+            load,
+            v: UnsafeCell::new(t),
+        }
+    }
+
+    /// Runs `f` holding the lock, as thread `me`, 0 or 1. The two threads must pass
+    /// different `me`s.
+    pub fn with_lock<R>(&self, me: usize, f: impl FnOnce(&mut T) -> R) -> R {
+        let other = 1 - me;
+        self.flags[me].store(true, self.store);
+        self.turn.swap(other, self.exchange);
+        while self.flags[other].load(self.load) && self.turn.load(self.load) == other {
+            spin_loop();
+        }
+        // Safety: we hold the lock, if the orderings are strong enough for that to mean
+        // anything.
+        let ret = self.v.with_mut(|v| f(unsafe { &mut *v }));
+        self.flags[me].store(false, Ordering::Release);
+        ret
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn teaching_defaults_work() {
+    let n = if cfg!(miri) { 50 } else { 1000 };
+    let m = Mutex::new(0);
+    let p = Peterson::new(0);
+    let b = Mailbox::new();
+    std::thread::scope(|s| {
+        for me in 0..2 {
+            let (m, p, b) = (&m, &p, &b);
+            s.spawn(move || {
+                for _ in 0..n {
+                    m.with_lock(|v| *v += 1);
+                    p.with_lock(me, |v| *v += 1);
+                }
+                if me == 0 {
+                    unsafe { b.put(42) };
+                }
+            });
+        }
+    });
+    assert_eq!(m.with_lock(|v| *v), 2 * n);
+    assert_eq!(p.with_lock(0, |v| *v), 2 * n);
+    assert_eq!(b.get(), Some(42));
+}
+
+// Two threads each add one to the value under `lock`; the value must come out as 2.
+#[cfg(all(loom, test))]
+fn two_adds<L: Send + Sync + 'static>(
+    lock: impl Fn() -> L + Send + Sync + 'static,
+    add: fn(&L, usize),
+    read: fn(&L) -> usize,
+) {
+    use loom::sync::Arc;
+
+    loom::model(move || {
+        let l = Arc::new(lock());
+        let t = {
+            let l = Arc::clone(&l);
+            loom::thread::spawn(move || add(&l, 1))
+        };
+        add(&l, 0);
+        t.join().unwrap();
+        assert_eq!(read(&l), 2);
+    });
+}
+
+#[cfg(all(loom, test))]
+fn two_adds_mutex(acquire: Ordering, release: Ordering) {
+    two_adds(
+        move || Mutex::with_orderings(0, acquire, release),
+        |l, _| l.with_lock(|v| *v += 1),
+        |l| l.with_lock(|v| *v),
+    );
+}
+
+#[cfg(all(loom, test))]
+fn two_adds_peterson(store: Ordering, exchange: Ordering, load: Ordering) {
+    two_adds(
+        move || Peterson::with_orderings(0, store, exchange, load),
+        |l, me| l.with_lock(me, |v| *v += 1),
+        |l| l.with_lock(0, |v| *v),
+    );
+}
+
+// One thread puts 42 in a mailbox, the other reads it if it's there.
+#[cfg(all(loom, test))]
+fn pass_message(store: Ordering, load: Ordering) {
+    use loom::sync::Arc;
+
+    loom::model(move || {
+        let b = Arc::new(Mailbox::with_orderings(store, load));
+        let t = {
+            let b = Arc::clone(&b);
+            loom::thread::spawn(move || unsafe { b.put(42) })
+        };
+        assert!(b.get().is_none_or(|v| v == 42));
+        t.join().unwrap();
+    });
+}
+
+#[cfg(loom)]
+#[test]
+fn teaching_mutex_needs_acquire_and_release() {
+    two_adds_mutex(Ordering::Acquire, Ordering::Release);
+    two_adds_mutex(Ordering::SeqCst, Ordering::SeqCst);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_mutex_relaxed_acquire_races() {
+    two_adds_mutex(Ordering::Relaxed, Ordering::Release);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_mutex_relaxed_release_races() {
+    two_adds_mutex(Ordering::Acquire, Ordering::Relaxed);
+}
+
+#[cfg(loom)]
+#[test]
+fn teaching_mailbox_needs_release_and_acquire() {
+    pass_message(Ordering::Release, Ordering::Acquire);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_mailbox_relaxed_store_races() {
+    pass_message(Ordering::Relaxed, Ordering::Acquire);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_mailbox_relaxed_load_races() {
+    pass_message(Ordering::Release, Ordering::Relaxed);
+}
+
+#[cfg(loom)]
+#[test]
+fn teaching_peterson_needs_an_acq_rel_exchange() {
+    two_adds_peterson(Ordering::Relaxed, Ordering::AcqRel, Ordering::Acquire);
+    two_adds_peterson(Ordering::SeqCst, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_peterson_relaxed_exchange_races() {
+    two_adds_peterson(Ordering::Release, Ordering::Relaxed, Ordering::Acquire);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_peterson_release_exchange_races() {
+    two_adds_peterson(Ordering::Release, Ordering::Release, Ordering::Acquire);
+}
+
+#[cfg(loom)]
+#[test]
+#[should_panic(expected = "Causality violation")]
+fn teaching_peterson_relaxed_loads_race() {
+    two_adds_peterson(Ordering::Relaxed, Ordering::AcqRel, Ordering::Relaxed);
+}
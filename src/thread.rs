@@ -0,0 +1,189 @@
+use crate::parker::{Parker, Unparker};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, Thread};
+
+struct ScopeData {
+    // Threads whose packet hasn't been dropped yet.
+    running: AtomicUsize,
+    // Set when a thread panics and nobody joins it to find out.
+    panicked: AtomicBool,
+    owner: Unparker,
+}
+
+// Where a scoped thread leaves its result. Dropping it is what counts the thread as done, so
+// a result that borrows from the scope is gone before the scope returns.
+struct Packet<T> {
+    scope: Arc<ScopeData>,
+    // Written by the thread before it lets go of its reference, read by the handle after the
+    // thread has been joined.
+    result: UnsafeCell<Option<thread::Result<T>>>,
+}
+
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// Spawns threads that may borrow from the stack of the thread that called [`scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    data: Arc<ScopeData>,
+    // Invariant, so the borrow checker can't shrink either lifetime to fit a spawn.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// An owned permission to join a thread spawned on a [`Scope`].
+pub struct ScopedJoinHandle<'scope, T> {
+    thread: JoinHandle<()>,
+    packet: Arc<Packet<T>>,
+    scope: PhantomData<&'scope ()>,
+}
+
+/// Runs `f` with a [`Scope`] for spawning threads that borrow local data, and waits for all of
+/// them before returning.
+///
+/// A borrowed `&AtomicUsize` can go straight into the threads, rather than being leaked to get
+/// a `&'static` or wrapped in an `Arc`. If `f` panics, the panic is resumed once every thread
+/// has finished. Otherwise, if a thread panicked and wasn't joined, `scope` panics too; a
+/// thread that was joined has its panic handed to whoever joined it.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let parker = Parker::new();
+    let scope = Scope {
+        data: Arc::new(ScopeData {
+            running: AtomicUsize::new(0),
+            panicked: AtomicBool::new(false),
+            owner: parker.unparker(),
+        }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    // Acquire: pairs with the Release in Packet::drop, so everything the threads did is
+    // visible once they're all gone.
+    while scope.data.running.load(Ordering::Acquire) != 0 {
+        parker.park();
+    }
+    match result {
+        Err(payload) => panic::resume_unwind(payload),
+        Ok(_) if scope.data.panicked.load(Ordering::Relaxed) => {
+            panic!("a scoped thread panicked")
+        }
+        Ok(value) => value,
+    }
+}
+
+impl<'scope, 'env: 'scope> Scope<'scope, 'env> {
+    /// Spawns a thread running `f`, which may borrow anything that outlives the scope.
+    ///
+    /// Panics if the OS fails to create the thread, like [`std::thread::spawn`].
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let packet = Arc::new(Packet {
+            scope: Arc::clone(&self.data),
+            result: UnsafeCell::new(None),
+        });
+        let theirs = Arc::clone(&packet);
+        self.data.running.fetch_add(1, Ordering::Relaxed);
+        let main = move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            // Safety: nothing reads the result until the thread has been joined.
+            unsafe { *theirs.result.get() = Some(result) };
+            drop(theirs);
+        };
+        // Safety: scope doesn't return until every packet, and so every closure, is gone;
+        // the closure can't outlive what it borrows.
+        let thread = unsafe { thread::Builder::new().spawn_unchecked(main) }
+            .expect("failed to spawn thread");
+        ScopedJoinHandle {
+            thread,
+            packet,
+            scope: PhantomData,
+        }
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Waits for the thread to finish, returning its result or the payload it panicked with.
+    pub fn join(self) -> thread::Result<T> {
+        // The closure catches panics, so this only fails if storing the result did.
+        self.thread.join()?;
+        let mut packet = self.packet;
+        // The thread has dropped its reference, so the result is ours alone.
+        Arc::get_mut(&mut packet)
+            .and_then(|p| p.result.get_mut().take())
+            .expect("scoped thread left no result")
+    }
+
+    pub fn thread(&self) -> &Thread {
+        self.thread.thread()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+impl<T> Drop for Packet<T> {
+    fn drop(&mut self) {
+        let unhandled = matches!(self.result.get_mut(), Some(Err(_)));
+        // The result may borrow from the scope, so it goes before we're counted as done. A
+        // panic while dropping it counts as the thread panicking.
+        let dropped = panic::catch_unwind(AssertUnwindSafe(|| *self.result.get_mut() = None));
+        if unhandled || dropped.is_err() {
+            self.scope.panicked.store(true, Ordering::Relaxed);
+        }
+        // Release: pairs with the Acquire in scope.
+        if self.scope.running.fetch_sub(1, Ordering::Release) == 1 {
+            self.scope.owner.unpark();
+        }
+    }
+}
+
+#[test]
+fn scoped_threads_borrow_the_stack() {
+    let counter = AtomicUsize::new(0);
+    let mut names = vec![String::from("a"), String::from("b")];
+    let lens = scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+        let handles: Vec<_> = names.iter().map(|n| s.spawn(move || n.len())).collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .sum::<usize>()
+    });
+    // Unjoined threads are finished by the time scope returns.
+    assert_eq!(counter.into_inner(), 4000);
+    assert_eq!(lens, 2);
+    names.push(String::from("c"));
+}
+
+#[test]
+fn scoped_thread_panics_propagate() {
+    // A joined panic goes to the joiner.
+    let joined = scope(|s| s.spawn(|| panic!("joined")).join().is_err());
+    assert!(joined);
+
+    // An unjoined one fails the whole scope, after the other threads are done.
+    let done = AtomicBool::new(false);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        scope(|s| {
+            s.spawn(|| panic!("unjoined"));
+            s.spawn(|| done.store(true, Ordering::Relaxed));
+        })
+    }));
+    assert!(result.is_err());
+    assert!(done.load(Ordering::Relaxed));
+}
@@ -0,0 +1,103 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// IDs of exited threads, smallest first, and the next never-used one.
+struct Registry {
+    free: BinaryHeap<Reverse<usize>>,
+    next: usize,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    free: BinaryHeap::new(),
+    next: 0,
+});
+
+// One more than the largest ID ever handed out.
+static HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+// Gives the ID back when the thread exits.
+struct ThreadId {
+    id: usize,
+}
+
+impl ThreadId {
+    fn acquire() -> Self {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let id = match registry.free.pop() {
+            Some(Reverse(id)) => id,
+            None => {
+                registry.next += 1;
+                HIGH_WATER.store(registry.next, Ordering::Relaxed);
+                registry.next - 1
+            }
+        };
+        Self { id }
+    }
+}
+
+impl Drop for ThreadId {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.free.push(Reverse(self.id));
+    }
+}
+
+thread_local! {
+    static ID: ThreadId = ThreadId::acquire();
+}
+
+/// A small integer identifying the current thread among the threads alive right now.
+///
+/// IDs start at zero and an exiting thread's ID goes to the next thread that asks, smallest
+/// first, so they stay about as dense as the number of threads using them. That makes them
+/// good indices into per-thread slots, unlike [`std::thread::ThreadId`], which never repeats.
+///
+/// A thread gets its ID the first time it asks. Returns None once the thread's thread-locals
+/// are being destroyed, since by then the ID may have been given back.
+pub fn current() -> Option<usize> {
+    ID.try_with(|id| id.id).ok()
+}
+
+/// One more than the largest ID handed out so far: a table with this many slots has one for
+/// every thread that has asked. Grows, never shrinks.
+pub fn high_water() -> usize {
+    HIGH_WATER.load(Ordering::Relaxed)
+}
+
+#[test]
+fn thread_id_recycles_ids() {
+    use std::sync::Barrier;
+    use std::thread;
+
+    let mine = current().unwrap();
+    assert_eq!(current(), Some(mine));
+    // Other tests' threads take and give back IDs at the same time, so only check that the
+    // live ones are distinct and that a finished thread's ID gets reused.
+    let barrier = Barrier::new(4);
+    let mut ids: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let id = current().unwrap();
+                    barrier.wait();
+                    id
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    ids.push(mine);
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), 5);
+    assert!(high_water() > *ids.last().unwrap());
+
+    let first = thread::spawn(|| current().unwrap()).join().unwrap();
+    let reused = (0..100).any(|_| {
+        let id = thread::spawn(|| current().unwrap()).join().unwrap();
+        id <= first
+    });
+    assert!(reused);
+}
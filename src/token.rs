@@ -0,0 +1,186 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+
+// Cells that share one key. Every `TokenCell<'brand, _>` is opened with the one
+// `Token<'brand>`: `&Token` reads any of them and `&mut Token` writes any of them, so a graph
+// or a set of nodes that's only ever touched by whoever holds the token needs no lock and no
+// atomic per node, and the borrow checker proves no two writers overlap. It's GhostCell's
+// design: what the locks elsewhere in this crate do at run time, done at compile time, for
+// when exclusivity is already there, one level up.
+//
+// The brand is an invariant lifetime that only `Token::with` makes up, a fresh one each call,
+// so cells from one token can't be opened with another. To share the cells between threads,
+// share the token the way anything else is shared: behind a lock, or by moving it.
+
+// Invariant in 'brand, so one brand can't be stretched or shrunk into another.
+type Brand<'brand> = PhantomData<fn(&'brand ()) -> &'brand ()>;
+
+/// The key to every [`TokenCell`] of its brand.
+pub struct Token<'brand> {
+    _brand: Brand<'brand>,
+}
+
+impl Token<'_> {
+    /// Runs `f` with a token of a brand no other token has.
+    pub fn with<R>(f: impl for<'brand> FnOnce(Token<'brand>) -> R) -> R {
+        f(Token {
+            _brand: PhantomData,
+        })
+    }
+}
+
+impl fmt::Debug for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Token { .. }")
+    }
+}
+
+/// A value read through `&Token<'brand>` and written through `&mut Token<'brand>`.
+#[repr(transparent)]
+pub struct TokenCell<'brand, T: ?Sized> {
+    _brand: Brand<'brand>,
+    value: UnsafeCell<T>,
+}
+
+// Safety: shared, a cell is only read through a shared token and written through the one
+// unique one, so it's as safe to share as `T` is to share and to send.
+unsafe impl<T: ?Sized + Send> Send for TokenCell<'_, T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for TokenCell<'_, T> {}
+
+impl<'brand, T> TokenCell<'brand, T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            _brand: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Puts `value` in and returns what was there.
+    pub fn replace(&self, value: T, token: &mut Token<'brand>) -> T {
+        std::mem::replace(self.borrow_mut(token), value)
+    }
+}
+
+impl<'brand, T: ?Sized> TokenCell<'brand, T> {
+    /// A cell over what `value` borrows, for as long as it's borrowed.
+    pub fn from_mut(value: &mut T) -> &mut Self {
+        // Safety: repr(transparent) over UnsafeCell<T>, which is repr(transparent) over T.
+        unsafe { &mut *(value as *mut T as *mut Self) }
+    }
+
+    pub fn borrow<'a>(&'a self, _token: &'a Token<'brand>) -> &'a T {
+        // Safety: a writer would need the token mutably, which our borrow of it rules out.
+        unsafe { &*self.value.get() }
+    }
+
+    pub fn borrow_mut<'a>(&'a self, _token: &'a mut Token<'brand>) -> &'a mut T {
+        // Safety: the token is borrowed uniquely for as long as the result, so no one else
+        // can read or write any cell of its brand meanwhile.
+        unsafe { &mut *self.value.get() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<'brand, T> TokenCell<'brand, T> {
+    /// Two cells written at once, with the one token.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are the same cell.
+    pub fn borrow_mut2<'a, U>(
+        &'a self,
+        other: &'a TokenCell<'brand, U>,
+        _token: &'a mut Token<'brand>,
+    ) -> (&'a mut T, &'a mut U) {
+        let (a, b) = (self.value.get(), other.value.get());
+        // Only zero-sized values can have distinct cells at the same address, and two
+        // references to nothing don't alias.
+        assert!(
+            a as *const u8 != b as *const u8
+                || std::mem::size_of::<T>() == 0
+                || std::mem::size_of::<U>() == 0,
+            "borrow_mut2 of a cell with itself"
+        );
+        // Safety: distinct cells, and the token is borrowed uniquely for as long as both.
+        unsafe { (&mut *a, &mut *b) }
+    }
+}
+
+impl<T: Default> Default for TokenCell<'_, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for TokenCell<'_, T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for TokenCell<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Reading it would need the token.
+        f.pad("TokenCell { .. }")
+    }
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+fn token_cells_share_one_key() {
+    use std::sync::Mutex;
+
+    Token::with(|mut token| {
+        let cells: Vec<TokenCell<'_, u32>> = (0..4).map(TokenCell::new).collect();
+        for c in &cells {
+            *c.borrow_mut(&mut token) += 10;
+        }
+        let (a, b) = cells[0].borrow_mut2(&cells[1], &mut token);
+        std::mem::swap(a, b);
+        assert_eq!(cells[3].replace(0, &mut token), 13);
+        let seen: Vec<u32> = cells.iter().map(|c| *c.borrow(&token)).collect();
+        assert_eq!(seen, [11, 10, 12, 0]);
+
+        let mut n = 5;
+        *TokenCell::from_mut(&mut n).borrow_mut(&mut token) += 1;
+        assert_eq!(n, 6);
+    });
+
+    // One lock for the token, instead of one per cell.
+    let n = if cfg!(miri) { 20 } else { 1000 };
+    Token::with(|token| {
+        let token = Mutex::new(token);
+        let cells: Vec<TokenCell<'_, u64>> = (0..8).map(|_| TokenCell::new(0)).collect();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..n {
+                        let mut token = token.lock().unwrap();
+                        *cells[i % 8].borrow_mut(&mut token) += 1;
+                    }
+                });
+            }
+        });
+        let token = token.into_inner().unwrap();
+        let total: u64 = cells.iter().map(|c| *c.borrow(&token)).sum();
+        assert_eq!(total, 4 * n as u64);
+    });
+}
+
+#[cfg(not(any(loom, shuttle)))]
+#[test]
+#[should_panic(expected = "with itself")]
+fn token_cell_borrow_mut2_of_one_cell_panics() {
+    Token::with(|mut token| {
+        let c = TokenCell::new(1);
+        let _ = c.borrow_mut2(&c, &mut token);
+    });
+}
@@ -0,0 +1,183 @@
+/// The machine's NUMA nodes and the cores on each, so per-node structures can keep a thread's
+/// traffic on its own socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    nodes: Vec<Node>,
+}
+
+/// A NUMA node: a set of cores with the same local memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    id: usize,
+    cores: Vec<usize>,
+}
+
+impl Topology {
+    /// Reads the topology from the OS. On Linux that's `/sys/devices/system/node`; where it
+    /// can't be read, or elsewhere, the machine is taken to be one node with every core.
+    pub fn detect() -> Self {
+        sys::detect()
+            .ok()
+            .filter(|nodes| !nodes.is_empty())
+            .map(|nodes| Self { nodes })
+            .unwrap_or_else(|| {
+                let n = std::thread::available_parallelism().map_or(1, |n| n.get());
+                Self::from_nodes(vec![(0..n).collect()])
+            })
+    }
+
+    /// A topology with the given cores on each node, numbered in order, as for testing a
+    /// layout made for a bigger machine.
+    pub fn from_nodes(nodes: Vec<Vec<usize>>) -> Self {
+        assert!(!nodes.is_empty(), "a topology needs a node");
+        Self {
+            nodes: nodes
+                .into_iter()
+                .enumerate()
+                .map(|(id, cores)| Node { id, cores })
+                .collect(),
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The position in [`nodes`](Self::nodes) of the node holding `core`.
+    pub fn node_of(&self, core: usize) -> Option<usize> {
+        self.nodes.iter().position(|n| n.cores.contains(&core))
+    }
+
+    /// The position of the node the current thread is running on right now, or 0 if that
+    /// can't be told. Unless the thread is pinned, it may have moved by the time this returns.
+    pub fn current_node(&self) -> usize {
+        current_cpu()
+            .and_then(|core| self.node_of(core))
+            .unwrap_or(0)
+    }
+}
+
+impl Node {
+    /// The OS's number for the node.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn cores(&self) -> &[usize] {
+        &self.cores
+    }
+}
+
+/// The core the current thread is running on, where the OS can say.
+pub fn current_cpu() -> Option<usize> {
+    sys::current_cpu()
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+mod sys {
+    use super::Node;
+    use std::convert::TryFrom;
+    use std::fs;
+    use std::io;
+    use std::os::raw::c_int;
+
+    extern "C" {
+        fn sched_getcpu() -> c_int;
+    }
+
+    pub fn detect() -> io::Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        for entry in fs::read_dir("/sys/devices/system/node")? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let id = match name.to_str().and_then(|n| n.strip_prefix("node")) {
+                Some(id) => id.parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "malformed node name")
+                })?,
+                None => continue,
+            };
+            let cores = parse_cpu_list(&fs::read_to_string(entry.path().join("cpulist"))?)?;
+            // Memory-only nodes have no cores to place anything on.
+            if !cores.is_empty() {
+                nodes.push(Node { id, cores });
+            }
+        }
+        nodes.sort_by_key(|n| n.id);
+        Ok(nodes)
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        usize::try_from(unsafe { sched_getcpu() }).ok()
+    }
+
+    // Parses a kernel cpu list, like "0-3,8,10-11".
+    pub fn parse_cpu_list(list: &str) -> io::Result<Vec<usize>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed cpu list");
+        let mut cores = Vec::new();
+        for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+            let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+            let lo: usize = lo.parse().map_err(|_| invalid())?;
+            let hi: usize = hi.parse().map_err(|_| invalid())?;
+            cores.extend(lo..=hi);
+        }
+        Ok(cores)
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod sys {
+    use super::Node;
+    use std::io;
+
+    extern "system" {
+        fn GetCurrentProcessorNumber() -> u32;
+    }
+
+    pub fn detect() -> io::Result<Vec<Node>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        Some(unsafe { GetCurrentProcessorNumber() } as usize)
+    }
+}
+
+// Also Miri's: it can't call sched_getcpu, or read /sys while isolated.
+#[cfg(any(miri, not(any(target_os = "linux", windows))))]
+mod sys {
+    use super::Node;
+    use std::io;
+
+    pub fn detect() -> io::Result<Vec<Node>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        None
+    }
+}
+
+#[test]
+fn topology_parses_and_detects() {
+    #[cfg(all(target_os = "linux", not(miri)))]
+    {
+        use sys::parse_cpu_list;
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpu_list("\n").unwrap(), [] as [usize; 0]);
+        assert!(parse_cpu_list("0-x").is_err());
+    }
+
+    let topology = Topology::from_nodes(vec![vec![0, 1], vec![2, 3]]);
+    assert_eq!(topology.node_of(3), Some(1));
+    assert_eq!(topology.node_of(4), None);
+
+    // Whatever the machine, every core we may run on is on some node.
+    let detected = Topology::detect();
+    for core in crate::affinity::cores() {
+        assert!(detected.node_of(core).is_some());
+    }
+    assert!(detected.current_node() < detected.nodes().len());
+}
@@ -0,0 +1,124 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// The shared "middle" index, with this bit set while it holds a value the reader hasn't taken.
+const FRESH: u8 = 4;
+
+/// Three buffers shared by one [`Writer`] and one [`Reader`]: the writer always has a back
+/// buffer to itself, the reader always has a front buffer to itself, and they trade through
+/// the third with a single swap. Both sides are wait-free.
+pub struct TripleBuffer<T> {
+    buffers: [UnsafeCell<T>; 3],
+    middle: AtomicU8,
+}
+
+unsafe impl<T: Send> Sync for TripleBuffer<T> {}
+
+impl<T: Clone> TripleBuffer<T> {
+    /// Returns the two ends of a triple buffer, with the reader starting out at `initial`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(initial: T) -> (Writer<T>, Reader<T>) {
+        let shared = Arc::new(TripleBuffer {
+            buffers: [
+                UnsafeCell::new(initial.clone()),
+                UnsafeCell::new(initial.clone()),
+                UnsafeCell::new(initial),
+            ],
+            middle: AtomicU8::new(1),
+        });
+        (
+            Writer {
+                shared: Arc::clone(&shared),
+                back: 0,
+            },
+            Reader { shared, front: 2 },
+        )
+    }
+}
+
+pub struct Writer<T> {
+    shared: Arc<TripleBuffer<T>>,
+    back: u8,
+}
+
+impl<T> Writer<T> {
+    /// Publishes `value` as the latest one.
+    pub fn write(&mut self, value: T) {
+        *self.buffer() = value;
+        self.publish();
+    }
+
+    /// The back buffer, to update in place before calling [`publish`](Self::publish). It
+    /// holds whatever was written to it last, which is not necessarily the latest value.
+    pub fn buffer(&mut self) -> &mut T {
+        // Safety: the back buffer is never reachable from the reader's side.
+        unsafe { &mut *self.shared.buffers[self.back as usize].get() }
+    }
+
+    /// Makes the back buffer the latest value, taking whichever buffer was in the middle
+    /// (possibly a value the reader never saw) as the new back buffer.
+    pub fn publish(&mut self) {
+        // AcqRel: releases our writes to the buffer, and acquires the reader's last use of the
+        // one we get back.
+        let old = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = old & !FRESH;
+    }
+}
+
+pub struct Reader<T> {
+    shared: Arc<TripleBuffer<T>>,
+    front: u8,
+}
+
+impl<T> Reader<T> {
+    /// Whether a value newer than the one [`read`](Self::read) last returned is waiting.
+    pub fn updated(&self) -> bool {
+        self.shared.middle.load(Ordering::Relaxed) & FRESH != 0
+    }
+
+    /// The most recently published value.
+    pub fn read(&mut self) -> &T {
+        if self.updated() {
+            let old = self.shared.middle.swap(self.front, Ordering::AcqRel);
+            self.front = old & !FRESH;
+        }
+        // Safety: the front buffer is never reachable from the writer's side.
+        unsafe { &*self.shared.buffers[self.front as usize].get() }
+    }
+}
+
+#[test]
+fn triple_buffer_latest_value() {
+    let (mut w, mut r) = TripleBuffer::new(0);
+    assert_eq!(*r.read(), 0);
+    assert!(!r.updated());
+    w.write(1);
+    w.write(2);
+    assert!(r.updated());
+    assert_eq!(*r.read(), 2);
+    assert_eq!(*r.read(), 2);
+    *w.buffer() = 3;
+    w.publish();
+    assert_eq!(*r.read(), 3);
+}
+
+#[test]
+fn triple_buffer_concurrent() {
+    let (mut w, mut r) = TripleBuffer::new([0u64; 8]);
+    let writer = std::thread::spawn(move || {
+        for i in 1..=10_000 {
+            w.write([i; 8]);
+        }
+    });
+    let mut last = 0;
+    while last < 10_000 {
+        let v = *r.read();
+        // Never torn, and never goes back in time.
+        assert!(v.iter().all(|&x| x == v[0]));
+        assert!(v[0] >= last);
+        last = v[0];
+        std::thread::yield_now();
+    }
+    writer.join().unwrap();
+}
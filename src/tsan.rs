@@ -0,0 +1,43 @@
+// Annotations that tell ThreadSanitizer about synchronization it can't see for itself. TSan
+// understands atomic accesses with their orderings, but not fences: a Relaxed load followed
+// by an Acquire fence synchronizes with the store it read, and TSan, seeing only the Relaxed
+// load, reports a race on every UnsafeCell access the fence was there to order. So each fence
+// that synchronizes says which atomic it's synchronizing through, with `release` before a
+// Release fence and `acquire` after an Acquire one, and under `--cfg tsan` those are TSan's
+// own `__tsan_release` and `__tsan_acquire`. Otherwise they compile to nothing.
+//
+// TSan needs a nightly compiler and std rebuilt with it:
+//
+//     RUSTFLAGS="--cfg tsan -Zsanitizer=thread" cargo +nightly test -Zbuild-std \
+//         --target x86_64-unknown-linux-gnu
+//
+// The SeqCst fences that only order a store before a load, as in a Dekker handshake, don't
+// need annotating: the data they guard is handed over through atomics TSan already sees.
+
+#[cfg(tsan)]
+extern "C" {
+    fn __tsan_acquire(addr: *mut std::ffi::c_void);
+    fn __tsan_release(addr: *mut std::ffi::c_void);
+}
+
+/// Says whatever was released through `a` happens before what follows.
+#[inline(always)]
+#[allow(unused_variables)]
+pub fn acquire<T>(a: &T) {
+    // Safety: TSan only uses the address as a key.
+    #[cfg(tsan)]
+    unsafe {
+        __tsan_acquire(a as *const T as *mut std::ffi::c_void)
+    }
+}
+
+/// Says what came before happens before whatever later acquires through `a`.
+#[inline(always)]
+#[allow(unused_variables)]
+pub fn release<T>(a: &T) {
+    // Safety: as for acquire.
+    #[cfg(tsan)]
+    unsafe {
+        __tsan_release(a as *const T as *mut std::ffi::c_void)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::event_count::EventCount;
+use crate::rwlock::{RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::mpsc::{RecvError, RecvTimeoutError, SendError};
+
+struct Shared<T> {
+    value: RwLock<T>,
+    // Bumped under the write lock on every send, so it's consistent with `value` for anyone
+    // holding the read lock.
+    version: AtomicU64,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    changed: EventCount,
+}
+
+/// The sending half of a watch [`channel`]; clone it for more producers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// A view of the latest value in a watch [`channel`], remembering which version it last saw.
+///
+/// Cloning it gives a receiver that has seen the same version.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    seen: u64,
+}
+
+/// A channel holding only the latest value: each send overwrites the last, and receivers
+/// read whatever is current when they look.
+///
+/// Receivers can [`borrow`](Receiver::borrow) the value in place. The borrow holds the
+/// slot's [`RwLock`] for reading, which keeps senders waiting, so keep it short.
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(initial),
+        version: AtomicU64::new(0),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        changed: EventCount::new(),
+    });
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    (sender, Receiver { shared, seen: 0 })
+}
+
+impl<T> Sender<T> {
+    /// Replaces the current value, or hands `value` back if there are no receivers.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::Relaxed) == 0 {
+            return Err(SendError(value));
+        }
+        let mut slot = self.shared.value.write();
+        *slot = value;
+        self.shared.version.fetch_add(1, Ordering::Relaxed);
+        drop(slot);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read()
+    }
+
+    /// A new receiver that counts the current value as seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        let slot = self.shared.value.read();
+        let seen = self.shared.version.load(Ordering::Relaxed);
+        drop(slot);
+        Receiver {
+            shared: Arc::clone(&self.shared),
+            seen,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it seen.
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.shared.value.read()
+    }
+
+    /// The current value, marking it seen.
+    pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+        let slot = self.shared.value.read();
+        self.seen = self.shared.version.load(Ordering::Relaxed);
+        slot
+    }
+
+    /// Whether there's a value this receiver hasn't seen, failing once every sender is gone.
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        self.shared.has_changed(self.seen)
+    }
+
+    /// Waits for a value this receiver hasn't seen and marks it seen.
+    ///
+    /// Updates in between are coalesced: only the latest counts. Fails once every sender is
+    /// gone without sending anything new.
+    pub fn changed(&mut self) -> Result<(), RecvError> {
+        self.shared
+            .changed(&mut self.seen, None)
+            .map_err(|_| RecvError)
+    }
+
+    /// Like [`changed`](Self::changed), but gives up after `timeout`.
+    pub fn changed_timeout(&mut self, timeout: Duration) -> Result<(), RecvTimeoutError> {
+        self.shared
+            .changed(&mut self.seen, Instant::now().checked_add(timeout))
+    }
+
+    /// Like [`changed`](Self::changed), but gives up at `deadline`.
+    pub fn changed_deadline(&mut self, deadline: Instant) -> Result<(), RecvTimeoutError> {
+        self.shared.changed(&mut self.seen, Some(deadline))
+    }
+
+    /// Waits until the current value satisfies `pred` (which may be right away), marks it
+    /// seen and returns it.
+    pub fn wait_for(
+        &mut self,
+        pred: impl FnMut(&T) -> bool,
+    ) -> Result<RwLockReadGuard<'_, T>, RecvError> {
+        self.wait_for_until(pred, None).map_err(|_| RecvError)
+    }
+
+    /// Like [`wait_for`](Self::wait_for), but gives up after `timeout`.
+    pub fn wait_for_timeout(
+        &mut self,
+        pred: impl FnMut(&T) -> bool,
+        timeout: Duration,
+    ) -> Result<RwLockReadGuard<'_, T>, RecvTimeoutError> {
+        self.wait_for_until(pred, Instant::now().checked_add(timeout))
+    }
+
+    // No deadline means waiting forever.
+    fn wait_for_until(
+        &mut self,
+        mut pred: impl FnMut(&T) -> bool,
+        deadline: Option<Instant>,
+    ) -> Result<RwLockReadGuard<'_, T>, RecvTimeoutError> {
+        let shared = &*self.shared;
+        loop {
+            let slot = shared.value.read();
+            self.seen = shared.version.load(Ordering::Relaxed);
+            if pred(&slot) {
+                return Ok(slot);
+            }
+            drop(slot);
+            shared.changed(&mut self.seen, deadline)?;
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    fn has_changed(&self, seen: u64) -> Result<bool, RecvError> {
+        if self.version.load(Ordering::Acquire) != seen {
+            return Ok(true);
+        }
+        if self.senders.load(Ordering::SeqCst) == 0 {
+            // The last sender may have sent just before it dropped.
+            return match self.version.load(Ordering::Acquire) != seen {
+                true => Ok(true),
+                false => Err(RecvError),
+            };
+        }
+        Ok(false)
+    }
+
+    // No deadline means waiting forever.
+    fn changed(&self, seen: &mut u64, deadline: Option<Instant>) -> Result<(), RecvTimeoutError> {
+        let disconnected = |_| RecvTimeoutError::Disconnected;
+        loop {
+            if self.has_changed(*seen).map_err(disconnected)? {
+                break;
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let key = self.changed.prepare_wait();
+            match self.has_changed(*seen) {
+                Ok(false) => match deadline {
+                    None => self.changed.wait(key),
+                    Some(deadline) => {
+                        self.changed.wait_deadline(key, deadline);
+                    }
+                },
+                result => {
+                    self.changed.cancel_wait(key);
+                    result.map_err(disconnected)?;
+                    break;
+                }
+            }
+        }
+        *seen = self.version.load(Ordering::Acquire);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // SeqCst: our sends happen before a receiver that sees the count hit zero, and the
+        // count is re-checked after registering to wait.
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn watch_coalesces_updates() {
+    let (tx, mut rx) = channel(0);
+    assert_eq!(rx.has_changed(), Ok(false));
+    tx.send(1).unwrap();
+    tx.send(2).unwrap();
+    assert_eq!(rx.has_changed(), Ok(true));
+    rx.changed().unwrap();
+    assert_eq!(*rx.borrow(), 2);
+    assert_eq!(rx.has_changed(), Ok(false));
+
+    let waiter = std::thread::spawn(move || {
+        let ten = *rx.wait_for(|&v| v >= 10).unwrap();
+        (ten, rx.changed())
+    });
+    for i in 3..=10 {
+        tx.send(i).unwrap();
+    }
+    drop(tx);
+    assert_eq!(waiter.join().unwrap(), (10, Err(RecvError)));
+}
+
+#[test]
+fn watch_changed_timeout() {
+    let (tx, mut rx) = channel(0);
+    let timeout = Duration::from_millis(10);
+    assert_eq!(rx.changed_timeout(timeout), Err(RecvTimeoutError::Timeout));
+    tx.send(1).unwrap();
+    assert_eq!(rx.changed_deadline(Instant::now() + timeout), Ok(()));
+    assert_eq!(
+        rx.wait_for_timeout(|&v| v == 2, timeout).err(),
+        Some(RecvTimeoutError::Timeout)
+    );
+    assert_eq!(*rx.wait_for_timeout(|&v| v == 1, timeout).unwrap(), 1);
+    drop(tx);
+    assert_eq!(
+        rx.changed_timeout(timeout),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
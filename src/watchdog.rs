@@ -0,0 +1,152 @@
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Warns of locks held too long, while they're still held: the way to find blocking I/O, or
+// anything else slow, done under a lock. Every acquisition is put down here with where it was
+// made and a backtrace, and a thread started with the first one looks them over a few times
+// per threshold, warning once of each hold that's gone past it, with the backtrace of the
+// acquisition.
+//
+// `rwlock::RwLock` reports to this module under the `watchdog` feature; other locks can do the
+// same through `acquired` and `released`. Capturing a backtrace for every acquisition makes
+// this slow.
+
+static THRESHOLD: AtomicU64 = AtomicU64::new(100_000_000);
+static NEXT: AtomicU64 = AtomicU64::new(0);
+static START: Once = Once::new();
+
+struct Held {
+    lock: usize,
+    thread: String,
+    site: &'static Location<'static>,
+    backtrace: Backtrace,
+    since: Instant,
+    warned: bool,
+}
+
+static HELD: Mutex<BTreeMap<u64, Held>> = Mutex::new(BTreeMap::new());
+static WARN: Mutex<fn(&str)> = Mutex::new(stderr);
+
+fn stderr(warning: &str) {
+    eprintln!("{}", warning);
+}
+
+fn held() -> std::sync::MutexGuard<'static, BTreeMap<u64, Held>> {
+    HELD.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn threshold() -> Duration {
+    Duration::from_nanos(THRESHOLD.load(Ordering::Relaxed))
+}
+
+/// Sets how long a lock can be held before the watchdog warns; 100ms by default.
+pub fn set_threshold(threshold: Duration) {
+    THRESHOLD.store(
+        threshold.as_nanos().min(u128::from(u64::MAX)) as u64,
+        Ordering::Relaxed,
+    );
+}
+
+/// Sets where warnings go; stderr by default.
+pub fn set_warn(warn: fn(&str)) {
+    *WARN.lock().unwrap_or_else(|e| e.into_inner()) = warn;
+}
+
+/// A hold the watchdog knows of, to hand back to [`released`].
+pub struct Watch(u64);
+
+/// Says the current thread took `lock` at `site`.
+pub fn acquired(lock: usize, site: &'static Location<'static>) -> Watch {
+    START.call_once(|| {
+        thread::Builder::new()
+            .name("lock watchdog".to_string())
+            .spawn(watch)
+            .expect("couldn't start the lock watchdog");
+    });
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    let t = thread::current();
+    let thread = match t.name() {
+        Some(name) => format!("'{}'", name),
+        None => format!("{:?}", t.id()),
+    };
+    held().insert(
+        id,
+        Held {
+            lock,
+            thread,
+            site,
+            backtrace: Backtrace::force_capture(),
+            since: Instant::now(),
+            warned: false,
+        },
+    );
+    Watch(id)
+}
+
+/// Says the hold is over.
+pub fn released(watch: &Watch) {
+    held().remove(&watch.0);
+}
+
+fn watch() {
+    loop {
+        let threshold = threshold();
+        thread::sleep((threshold / 4).clamp(Duration::from_millis(1), Duration::from_secs(1)));
+        let mut warnings = Vec::new();
+        for h in held().values_mut() {
+            let held_for = h.since.elapsed();
+            if !h.warned && held_for >= threshold {
+                h.warned = true;
+                warnings.push(format!(
+                    "watchdog: lock {:#x} held for {:?} and counting by thread {}, taken at {}\n{}",
+                    h.lock, held_for, h.thread, h.site, h.backtrace
+                ));
+            }
+        }
+        // Outside the lock on the holds, so the warning can take locks of its own.
+        let warn = *WARN.lock().unwrap_or_else(|e| e.into_inner());
+        for w in warnings {
+            warn(&w);
+        }
+    }
+}
+
+#[test]
+fn watchdog_warns_of_a_long_hold_while_it_lasts() {
+    use crate::rwlock::RwLock;
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    fn collect(w: &str) {
+        WARNINGS.lock().unwrap().push(w.to_string());
+    }
+    let ours = || {
+        WARNINGS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|w| w.contains(&format!("taken at {}:", file!())))
+            .count()
+    };
+    set_warn(collect);
+    set_threshold(Duration::from_millis(10));
+
+    let lock = RwLock::new(0);
+    let guard = lock.read();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while ours() == 0 {
+        assert!(Instant::now() < deadline, "no warning of the hold");
+        thread::sleep(Duration::from_millis(1));
+    }
+    // Once a hold, however long.
+    thread::sleep(Duration::from_millis(50));
+    drop(guard);
+    assert_eq!(ours(), 1);
+    let w = WARNINGS.lock().unwrap().join("\n");
+    assert!(w.contains("and counting by thread 'watchdog::watchdog_warns"));
+    assert!(w.contains("watchdog_warns_of_a_long_hold_while_it_lasts"));
+}
@@ -0,0 +1,607 @@
+use crate::atomic_waker::AtomicWaker;
+use crate::deque::{Steal, Stealer, Worker};
+use crate::event_count::EventCount;
+use crate::parker::Parker;
+use crate::seg_queue::SegQueue;
+use std::cell::{Cell, UnsafeCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Waker;
+use std::thread::{self, JoinHandle as ThreadHandle};
+
+// A type-erased pointer to a job and the function that runs it. Stack jobs are kept alive by
+// whoever is waiting on their latch; heap jobs own themselves and are freed by running.
+struct JobRef {
+    data: *const (),
+    execute: unsafe fn(*const ()),
+}
+
+unsafe impl Send for JobRef {}
+
+impl JobRef {
+    unsafe fn execute(self) {
+        (self.execute)(self.data)
+    }
+}
+
+// Set once a stack job has run. The waker is fixed when the job is made, so setting never
+// has to look at anything the waiting thread might free as soon as it sees the flag.
+struct Latch {
+    done: AtomicBool,
+    waker: Waker,
+}
+
+impl Latch {
+    fn new(waker: Waker) -> Self {
+        Self {
+            done: AtomicBool::new(false),
+            waker,
+        }
+    }
+
+    fn probe(&self) -> bool {
+        // Acquire: pairs with the Release in set, making the job's result visible.
+        self.done.load(Ordering::Acquire)
+    }
+
+    // A raw pointer rather than &self, which would claim the latch stays alive for the whole
+    // call.
+    unsafe fn set(this: *const Self) {
+        let waker = (*this).waker.clone();
+        (*this).done.store(true, Ordering::Release);
+        // The latch may be gone by now.
+        waker.wake();
+    }
+}
+
+// A job that borrows from the stack of the thread waiting on it.
+struct StackJob<F, R> {
+    func: UnsafeCell<Option<F>>,
+    result: UnsafeCell<Option<thread::Result<R>>>,
+    latch: Latch,
+}
+
+impl<F: FnOnce() -> R, R> StackJob<F, R> {
+    fn new(func: F, waker: Waker) -> Self {
+        Self {
+            func: UnsafeCell::new(Some(func)),
+            result: UnsafeCell::new(None),
+            latch: Latch::new(waker),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The job must outlive its latch being set.
+    unsafe fn as_job_ref(&self) -> JobRef {
+        JobRef {
+            data: self as *const Self as *const (),
+            execute: Self::execute,
+        }
+    }
+
+    unsafe fn execute(this: *const ()) {
+        let this = &*(this as *const Self);
+        let func = (*this.func.get()).take().expect("job ran twice");
+        *this.result.get() = Some(panic::catch_unwind(AssertUnwindSafe(func)));
+        Latch::set(&this.latch);
+    }
+
+    // For a job taken back before anyone else ran it.
+    fn run_inline(&self) -> thread::Result<R> {
+        let func = unsafe { (*self.func.get()).take() }.expect("job ran twice");
+        panic::catch_unwind(AssertUnwindSafe(func))
+    }
+
+    fn take_result(&self) -> thread::Result<R> {
+        debug_assert!(self.latch.probe());
+        unsafe { (*self.result.get()).take() }.expect("job has no result")
+    }
+}
+
+unsafe fn execute_heap<F: FnOnce()>(data: *const ()) {
+    Box::from_raw(data as *mut F)()
+}
+
+fn heap_job<F: FnOnce() + Send + 'static>(func: F) -> JobRef {
+    JobRef {
+        data: Box::into_raw(Box::new(func)) as *const (),
+        execute: execute_heap::<F>,
+    }
+}
+
+struct Registry {
+    injector: SegQueue<JobRef>,
+    stealers: Vec<Stealer<JobRef>>,
+    // Idle workers sleep here; workers blocked on a job watch it so new work wakes them.
+    sleep: EventCount,
+    shutdown: AtomicBool,
+}
+
+impl Registry {
+    fn inject(&self, job: JobRef) {
+        self.injector.push(job);
+        self.sleep.notify_one();
+    }
+}
+
+// A pool thread's own state, reachable from the jobs it runs through CURRENT.
+struct WorkerThread {
+    index: usize,
+    deque: Worker<JobRef>,
+    registry: Arc<Registry>,
+    parker: Parker,
+    waker: Waker,
+    // Xorshift state for picking where to start stealing.
+    seed: Cell<u64>,
+}
+
+thread_local! {
+    static CURRENT: Cell<*const WorkerThread> = const { Cell::new(ptr::null()) };
+}
+
+impl WorkerThread {
+    fn current<'a>() -> Option<&'a WorkerThread> {
+        // Safety: CURRENT is only set while the worker's main loop has it borrowed.
+        unsafe { CURRENT.with(Cell::get).as_ref() }
+    }
+
+    fn push(&self, job: JobRef) {
+        self.deque.push(job);
+        self.registry.sleep.notify_one();
+    }
+
+    fn find_work(&self) -> Option<JobRef> {
+        self.deque.pop().or_else(|| self.steal())
+    }
+
+    fn steal(&self) -> Option<JobRef> {
+        let stealers = &self.registry.stealers;
+        loop {
+            if let Some(job) = self.registry.injector.pop() {
+                return Some(job);
+            }
+            let mut retry = false;
+            let start = self.next_victim();
+            for i in 0..stealers.len() {
+                let victim = (start + i) % stealers.len();
+                if victim == self.index {
+                    continue;
+                }
+                // Taking half of the victim's jobs leaves the rest for other thieves and saves
+                // coming back for each one.
+                match stealers[victim].steal_batch(&self.deque) {
+                    Steal::Success(_) => return self.deque.pop(),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+
+    fn next_victim(&self) -> usize {
+        let mut x = self.seed.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed.set(x);
+        x as usize % self.registry.stealers.len()
+    }
+
+    fn main_loop(&self) {
+        loop {
+            if let Some(job) = self.find_work() {
+                unsafe { job.execute() };
+                continue;
+            }
+            let key = self.registry.sleep.prepare_wait();
+            if let Some(job) = self.find_work() {
+                self.registry.sleep.cancel_wait(key);
+                unsafe { job.execute() };
+            } else if self.registry.shutdown.load(Ordering::SeqCst) {
+                // Only once there's nothing left to run, so dropping the pool finishes the
+                // jobs already spawned on it.
+                self.registry.sleep.cancel_wait(key);
+                return;
+            } else {
+                self.registry.sleep.wait(key);
+            }
+        }
+    }
+
+    // Runs other jobs until `done`, which must turn true with a wake of self.waker.
+    fn wait_until(&self, done: &dyn Fn() -> bool) {
+        if done() {
+            return;
+        }
+        let key = self.registry.sleep.watch(&self.waker);
+        while !done() {
+            match self.find_work() {
+                Some(job) => unsafe { job.execute() },
+                None => self.parker.park(),
+            }
+        }
+        self.registry.sleep.unwatch(key);
+    }
+
+    fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA,
+        B: FnOnce() -> RB + Send,
+        RB: Send,
+    {
+        let job_b = StackJob::new(b, self.waker.clone());
+        let job_b_ref = unsafe { job_b.as_job_ref() };
+        let job_b_id = job_b_ref.data;
+        self.push(job_b_ref);
+        let ra = panic::catch_unwind(AssertUnwindSafe(a));
+        // Everything a pushed has been joined, so b is on top unless someone stole it.
+        let rb = loop {
+            if job_b.latch.probe() {
+                break job_b.take_result();
+            }
+            match self.deque.pop() {
+                Some(job) if job.data == job_b_id => break job_b.run_inline(),
+                Some(job) => unsafe { job.execute() },
+                None => {
+                    self.wait_until(&|| job_b.latch.probe());
+                    break job_b.take_result();
+                }
+            }
+        };
+        // b may borrow what a does, so a's panic waits until b is finished too.
+        match (ra, rb) {
+            (Ok(ra), Ok(rb)) => (ra, rb),
+            (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// A fixed set of threads, each with a Chase–Lev [`Worker`] deque, and a [`SegQueue`] for jobs
+/// that come from outside the pool.
+///
+/// A worker pops its own deque LIFO, then takes from the shared queue, then steals about half
+/// of another worker's deque from the other end. [`join`] runs one closure and offers the
+/// other for stealing, so recursive divide-and-conquer spreads over the pool on its own.
+///
+/// Dropping the pool waits for every job spawned on it to finish, so it mustn't be dropped
+/// from one of its own threads.
+pub struct ThreadPool {
+    registry: Arc<Registry>,
+    threads: Vec<ThreadHandle<()>>,
+}
+
+struct Slot<T> {
+    done: AtomicBool,
+    result: UnsafeCell<Option<thread::Result<T>>>,
+    waiter: AtomicWaker,
+}
+
+unsafe impl<T: Send> Sync for Slot<T> {}
+
+/// Waits for a job from [`ThreadPool::spawn`].
+pub struct JoinHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "a pool needs at least one thread");
+        let deques: Vec<_> = (0..threads).map(|_| Worker::new()).collect();
+        let registry = Arc::new(Registry {
+            injector: SegQueue::new(),
+            stealers: deques.iter().map(Worker::stealer).collect(),
+            sleep: EventCount::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = deques
+            .into_iter()
+            .enumerate()
+            .map(|(index, deque)| {
+                let registry = Arc::clone(&registry);
+                thread::Builder::new()
+                    .name(format!("work-stealing-{}", index))
+                    .spawn(move || {
+                        let parker = Parker::new();
+                        let worker = WorkerThread {
+                            index,
+                            deque,
+                            registry,
+                            waker: parker.unparker().waker(),
+                            parker,
+                            seed: Cell::new(index as u64 + 1),
+                        };
+                        CURRENT.with(|c| c.set(&worker));
+                        worker.main_loop();
+                        CURRENT.with(|c| c.set(ptr::null()));
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { registry, threads }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs `f` on the pool and returns a handle to its result. From one of the pool's own
+    /// threads, the job goes on that thread's deque; otherwise it goes on the shared queue.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = Arc::new(Slot {
+            done: AtomicBool::new(false),
+            result: UnsafeCell::new(None),
+            waiter: AtomicWaker::new(),
+        });
+        let theirs = Arc::clone(&slot);
+        let job = heap_job(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            unsafe { *theirs.result.get() = Some(result) };
+            // Release: the result is visible to a joiner that sees the flag.
+            theirs.done.store(true, Ordering::Release);
+            theirs.waiter.wake();
+        });
+        match self.current_worker() {
+            Some(worker) => worker.push(job),
+            None => self.registry.inject(job),
+        }
+        JoinHandle { slot }
+    }
+
+    /// Runs `f` on one of the pool's threads and waits for it.
+    ///
+    /// Called from one of this pool's threads, it just calls `f`. A thread of another pool
+    /// keeps running its own pool's jobs while it waits.
+    pub fn install<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        if self.current_worker().is_some() {
+            return f();
+        }
+        let result = match WorkerThread::current() {
+            Some(other) => {
+                let job = StackJob::new(f, other.waker.clone());
+                self.registry.inject(unsafe { job.as_job_ref() });
+                other.wait_until(&|| job.latch.probe());
+                job.take_result()
+            }
+            None => {
+                let parker = Parker::new();
+                let job = StackJob::new(f, parker.unparker().waker());
+                self.registry.inject(unsafe { job.as_job_ref() });
+                while !job.latch.probe() {
+                    parker.park();
+                }
+                job.take_result()
+            }
+        };
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Runs `a` and `b`, potentially in parallel on the pool, and returns both results. See
+    /// [`join`].
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.install(|| join(a, b))
+    }
+
+    /// Calls `f` on every item of `items` in parallel, returning once all of them are done.
+    ///
+    /// The slice is halved with [`join`] until the pieces are about a quarter of an even
+    /// share per thread, so idle threads steal large pieces first and split them further.
+    pub fn parallel_for_each<T, F>(&self, items: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        let min_len = chunk_len(items.len(), self.num_threads());
+        self.install(|| for_each_chunk(items, min_len, &|chunk: &[T]| chunk.iter().for_each(&f)));
+    }
+
+    /// Like [`parallel_for_each`](Self::parallel_for_each), with mutable access to each item.
+    pub fn parallel_for_each_mut<T, F>(&self, items: &mut [T], f: F)
+    where
+        T: Send,
+        F: Fn(&mut T) + Sync,
+    {
+        let min_len = chunk_len(items.len(), self.num_threads());
+        self.install(|| {
+            for_each_chunk(items, min_len, &|chunk: &mut [T]| {
+                chunk.iter_mut().for_each(&f)
+            })
+        });
+    }
+
+    fn current_worker(&self) -> Option<&WorkerThread> {
+        WorkerThread::current().filter(|w| Arc::ptr_eq(&w.registry, &self.registry))
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.registry.shutdown.store(true, Ordering::SeqCst);
+        self.registry.sleep.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// A slice that can be halved for parallel_for_each, shared or not.
+trait Chunk: Sized + Send {
+    fn len(&self) -> usize;
+    fn split(self, mid: usize) -> (Self, Self);
+}
+
+impl<T: Sync> Chunk for &[T] {
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn split(self, mid: usize) -> (Self, Self) {
+        self.split_at(mid)
+    }
+}
+
+impl<T: Send> Chunk for &mut [T] {
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn split(self, mid: usize) -> (Self, Self) {
+        self.split_at_mut(mid)
+    }
+}
+
+fn chunk_len(len: usize, threads: usize) -> usize {
+    (len / (threads * 4)).max(1)
+}
+
+fn for_each_chunk<C: Chunk>(chunk: C, min_len: usize, f: &(dyn Fn(C) + Sync)) {
+    if chunk.len() <= min_len {
+        return f(chunk);
+    }
+    let mid = chunk.len() / 2;
+    let (left, right) = chunk.split(mid);
+    join(
+        || for_each_chunk(left, min_len, f),
+        || for_each_chunk(right, min_len, f),
+    );
+}
+
+/// Runs `a` on the current thread while offering `b` to the rest of the pool, and returns
+/// both results once both have finished.
+///
+/// If nobody steals `b` by the time `a` is done, the current thread runs it too. If `b` was
+/// stolen, the current thread runs other jobs while it waits. Outside a pool, `a` and `b`
+/// just run one after the other. A panic in either is resumed once both have finished.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB + Send,
+    RB: Send,
+{
+    match WorkerThread::current() {
+        Some(worker) => worker.join(a, b),
+        None => (a(), b()),
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the job, returning its result or the payload it panicked with. On a pool
+    /// thread, runs other jobs while waiting.
+    pub fn join(self) -> thread::Result<T> {
+        let done = || self.slot.done.load(Ordering::Acquire);
+        match WorkerThread::current() {
+            Some(worker) => {
+                self.slot.waiter.register(&worker.waker);
+                worker.wait_until(&done);
+            }
+            None => {
+                let parker = Parker::new();
+                self.slot.waiter.register(&parker.unparker().waker());
+                while !done() {
+                    parker.park();
+                }
+            }
+        }
+        unsafe { (*self.slot.result.get()).take() }.expect("job has no result")
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.slot.done.load(Ordering::Acquire)
+    }
+}
+
+#[test]
+fn work_stealing_join_splits_recursion() {
+    fn fib(n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    let pool = ThreadPool::new(4);
+    assert_eq!(pool.install(|| fib(18)), 2584);
+
+    // Borrowed data is fine, since join doesn't return until both halves are done.
+    let mut data: Vec<u64> = (0..1000).collect();
+    let (left, right) = data.split_at_mut(500);
+    pool.join(
+        || left.iter_mut().for_each(|x| *x *= 2),
+        || right.iter_mut().for_each(|x| *x += 1),
+    );
+    assert_eq!(data[499], 998);
+    assert_eq!(data[500], 501);
+
+    // A panicking half reaches the caller after the other half has finished.
+    let other = AtomicBool::new(false);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.join(
+            || panic!("left half"),
+            || other.store(true, Ordering::Relaxed),
+        )
+    }));
+    assert!(result.is_err() && other.load(Ordering::Relaxed));
+}
+
+#[test]
+fn work_stealing_spawn_and_drop() {
+    use std::sync::atomic::AtomicUsize;
+
+    let pool = Arc::new(ThreadPool::new(2));
+    let handles: Vec<_> = (0..16u64)
+        .map(|i| {
+            let inner = Arc::clone(&pool);
+            // Joining from a pool thread runs other jobs instead of blocking the worker.
+            pool.spawn(move || inner.spawn(move || i * i).join().unwrap())
+        })
+        .collect();
+    let squares: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert!(squares.into_iter().eq((0..16).map(|i| i * i)));
+    assert!(pool.spawn(|| panic!("spawned")).join().is_err());
+
+    let ran = Arc::new(AtomicUsize::new(0));
+    for _ in 0..100 {
+        let ran = Arc::clone(&ran);
+        pool.spawn(move || ran.fetch_add(1, Ordering::Relaxed));
+    }
+    drop(Arc::try_unwrap(pool).ok().expect("no other references"));
+    assert_eq!(ran.load(Ordering::Relaxed), 100);
+}
+
+#[test]
+fn parallel_for_each_visits_every_item() {
+    use std::sync::atomic::AtomicU64;
+
+    let pool = ThreadPool::new(3);
+    let items: Vec<u64> = (1..=1000).collect();
+    let sum = AtomicU64::new(0);
+    pool.parallel_for_each(&items, |x| {
+        sum.fetch_add(*x, Ordering::Relaxed);
+    });
+    assert_eq!(sum.into_inner(), 500_500);
+
+    let mut items = items;
+    pool.parallel_for_each_mut(&mut items, |x| *x *= 3);
+    assert!(items.iter().copied().eq((1..=1000).map(|x| x * 3)));
+    pool.parallel_for_each_mut(&mut [] as &mut [u64], |_| unreachable!());
+}